@@ -1,7 +1,8 @@
 use std::time::{Duration, Instant};
 use std::net::UdpSocket;
-use udcn_common::{serialize_interest, serialize_data, hash_name};
-use rand;
+use std::collections::HashMap;
+use udcn_common::pit::{self, Probe, PIT_PROBE_LIMIT, PIT_SLOTS};
+use udcn_common::{hash_name, Data, Interest, PitEntry, PitRecord, PIT_CAPACITY};
 
 fn main() -> anyhow::Result<()> {
     println!("µDCN Performance Benchmark");
@@ -10,6 +11,7 @@ fn main() -> anyhow::Result<()> {
     benchmark_serialization()?;
     benchmark_name_hashing()?;
     benchmark_udp_throughput()?;
+    benchmark_pit_layouts()?;
     
     Ok(())
 }
@@ -25,14 +27,14 @@ fn benchmark_serialization() -> anyhow::Result<()> {
     // Interest serialization
     let start = Instant::now();
     for i in 0..iterations {
-        let _packet = serialize_interest(name, i);
+        let _packet = Interest::builder().name(name).nonce(i).build().encode();
     }
     let interest_duration = start.elapsed();
     
     // Data serialization  
     let start = Instant::now();
-    for i in 0..iterations {
-        let _packet = serialize_data(name, content, i);
+    for _ in 0..iterations {
+        let _packet = Data::builder().name(name).content(&content[..]).build().encode();
     }
     let data_duration = start.elapsed();
     
@@ -88,7 +90,7 @@ fn benchmark_udp_throughput() -> anyhow::Result<()> {
     // Send interests
     let start = Instant::now();
     for i in 0..iterations {
-        let packet = serialize_interest(name, i);
+        let packet = Interest::builder().name(name).nonce(i).build().encode();
         client_socket.send_to(&packet, server_addr)?;
     }
     let send_duration = start.elapsed();
@@ -104,7 +106,7 @@ fn benchmark_udp_throughput() -> anyhow::Result<()> {
     
     let start = Instant::now();
     while received < iterations && start.elapsed() < Duration::from_secs(5) {
-        if let Ok(_) = server_socket.recv_from(&mut buf) {
+        if server_socket.recv_from(&mut buf).is_ok() {
             received += 1;
         }
     }
@@ -119,4 +121,94 @@ fn benchmark_udp_throughput() -> anyhow::Result<()> {
     println!("Received {} out of {} packets", received, iterations);
     
     Ok(())
-}
\ No newline at end of file
+}
+/// Fill each PIT layout with `offered` names, then look every one of them up, as the datapath
+/// does for an Interest and then its Data. The hash map is std's, bounded the way the BPF map
+/// is, so it shows when that one overflows but not what its lookups cost in the kernel.
+fn benchmark_pit_layouts() -> anyhow::Result<()> {
+    println!("\n4. PIT Layout Benchmark");
+    println!("-----------------------");
+    println!("Hash map: {} entries. Open addressing: {} slots, {} probes at most.",
+        PIT_CAPACITY, PIT_SLOTS, PIT_PROBE_LIMIT);
+    println!("{:>8} {:>12} {:>12} {:>10} {:>12} {:>12} {:>10} {:>10}",
+        "NAMES", "MAP INSERT", "MAP LOOKUP", "MAP FULL", "OA INSERT", "OA LOOKUP", "OA PROBES", "OA FULL");
+
+    let rounds = 100;
+    for offered in [256, 512, 768, 1024, 1280, 1536, 2048] {
+        let hashes: Vec<u32> = (0..offered)
+            .map(|i| hash_name(format!("/benchmark/pit/{i}").as_bytes()))
+            .collect();
+        let entry = |name_hash| PitEntry::new(name_hash, PitRecord::default(), 0);
+
+        let (mut map_insert, mut map_lookup, mut map_full) = (Duration::ZERO, Duration::ZERO, 0);
+        let (mut oa_insert, mut oa_lookup, mut oa_full, mut oa_probes) =
+            (Duration::ZERO, Duration::ZERO, 0, 0);
+        for _ in 0..rounds {
+            let mut map = HashMap::with_capacity(PIT_CAPACITY as usize);
+            let start = Instant::now();
+            map_full = 0;
+            for &name_hash in &hashes {
+                if map.len() < PIT_CAPACITY as usize {
+                    map.insert(name_hash, entry(name_hash));
+                } else {
+                    map_full += 1;
+                }
+            }
+            map_insert += start.elapsed();
+            let start = Instant::now();
+            for name_hash in &hashes {
+                std::hint::black_box(map.get(name_hash));
+            }
+            map_lookup += start.elapsed();
+
+            let mut fingerprints = vec![pit::EMPTY; PIT_SLOTS as usize];
+            let mut entries = vec![entry(0); PIT_SLOTS as usize];
+            let start = Instant::now();
+            oa_full = 0;
+            for &name_hash in &hashes {
+                let probe = pit::probe(
+                    name_hash,
+                    |slot| fingerprints[slot as usize],
+                    |slot| entries[slot as usize].name_hash,
+                );
+                match probe {
+                    Probe::Vacant(slot) => {
+                        entries[slot as usize] = entry(name_hash);
+                        fingerprints[slot as usize] = pit::fingerprint(name_hash);
+                    }
+                    Probe::Found(_) => {}
+                    Probe::Full => oa_full += 1,
+                }
+            }
+            oa_insert += start.elapsed();
+            let start = Instant::now();
+            oa_probes = 0;
+            for &name_hash in &hashes {
+                let probe = pit::probe(
+                    name_hash,
+                    |slot| {
+                        oa_probes += 1;
+                        fingerprints[slot as usize]
+                    },
+                    |slot| entries[slot as usize].name_hash,
+                );
+                std::hint::black_box(probe);
+            }
+            oa_lookup += start.elapsed();
+        }
+
+        let per_op = |total: Duration| total.as_nanos() as f64 / (rounds * offered) as f64;
+        println!("{:>8} {:>9.1} ns {:>9.1} ns {:>10} {:>9.1} ns {:>9.1} ns {:>10.2} {:>10}",
+            offered,
+            per_op(map_insert),
+            per_op(map_lookup),
+            map_full,
+            per_op(oa_insert),
+            per_op(oa_lookup),
+            oa_probes as f64 / offered as f64,
+            oa_full);
+    }
+    println!("MAP FULL and OA FULL count names with no room; OA PROBES is fingerprints read per lookup.");
+
+    Ok(())
+}
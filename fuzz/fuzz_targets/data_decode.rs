@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udcn_common::Data;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(packet) = Data::decode(data) {
+        // Anything we accept must survive a re-encode unchanged
+        assert_eq!(Data::decode(&packet.encode()), Some(packet));
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udcn_common::Interest;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(interest) = Interest::decode(data) {
+        // Anything we accept must survive a re-encode unchanged
+        assert_eq!(Interest::decode(&interest.encode()), Some(interest));
+    }
+});
@@ -0,0 +1,55 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use udcn_common::lp::{self, Reassembler};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    /// Arbitrary LpPackets, malformed ones included.
+    noise: Vec<Vec<u8>>,
+    packet: Vec<u8>,
+    max_size: u16,
+    sequence: u64,
+    /// Picks the order the fragments arrive in.
+    order: Vec<usize>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut reassembler = Reassembler::new();
+    for lp_packet in &input.noise {
+        let _ = reassembler.receive(lp_packet);
+    }
+
+    // Fragments of the noise could share the packet's key, so it goes to a reassembler of its own
+    let mut reassembler = Reassembler::new();
+    // A packet that is an LpPacket itself is unwrapped on arrival, so would not come out as sent
+    if lp::is_lp_packet(&input.packet) {
+        return;
+    }
+    let Some(fragments) = lp::fragment(&input.packet, input.max_size.into(), input.sequence)
+    else {
+        return;
+    };
+    if fragments.is_empty() {
+        return;
+    }
+    assert!(fragments.iter().all(|f| f.len() <= input.max_size.into()));
+    let mut fragments: Vec<Option<Vec<u8>>> = fragments.into_iter().map(Some).collect();
+    let mut pending = fragments.len();
+    let mut order = input.order.iter().chain(std::iter::repeat(&0));
+    let mut reassembled = None;
+    while pending > 0 {
+        let mut slot = order.next().unwrap() % fragments.len();
+        while fragments[slot].is_none() {
+            slot = (slot + 1) % fragments.len();
+        }
+        let fragment = fragments[slot].take().unwrap();
+        pending -= 1;
+        let received = reassembler.receive(&fragment);
+        // Only the last fragment to arrive completes the packet
+        assert_eq!(received.is_some(), pending == 0);
+        reassembled = received;
+    }
+    assert_eq!(reassembled, Some(input.packet));
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udcn_common::{
+    is_ndn_packet, parse_data_packet, parse_interest_packet, parse_nack_packet, TlvHeader,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TlvHeader::parse(data);
+    let _ = is_ndn_packet(data);
+    let _ = parse_interest_packet(data);
+    let _ = parse_data_packet(data);
+    let _ = parse_nack_packet(data);
+
+    // libFuzzer hands out aligned buffers, so also try every misaligned start
+    for offset in 1..data.len().min(8) {
+        let _ = parse_interest_packet(&data[offset..]);
+        let _ = parse_data_packet(&data[offset..]);
+        let _ = parse_nack_packet(&data[offset..]);
+    }
+});
@@ -0,0 +1,53 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use udcn_common::{Component, Data, Interest, MetaInfo, Name};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    components: Vec<(u64, Vec<u8>)>,
+    nonce: u32,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: Option<u64>,
+    application_parameters: Option<Vec<u8>>,
+    content_type: Option<u64>,
+    freshness_period_ms: Option<u64>,
+    content: Vec<u8>,
+    signature_value: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut name = Name::new();
+    for (typ, value) in input.components {
+        name.push(Component { typ, value });
+    }
+
+    let interest = Interest {
+        name: name.clone(),
+        nonce: input.nonce,
+        can_be_prefix: input.can_be_prefix,
+        must_be_fresh: input.must_be_fresh,
+        lifetime_ms: input.lifetime_ms,
+        forwarding_hint: Vec::new(),
+        application_parameters: input.application_parameters,
+    };
+    assert_eq!(Interest::decode(&interest.encode()).as_ref(), Some(&interest));
+
+    // The fixed header can only describe 64 KiB of content
+    if input.content.len() > u16::MAX as usize {
+        return;
+    }
+    let data = Data {
+        name,
+        meta: MetaInfo {
+            content_type: input.content_type,
+            freshness_period_ms: input.freshness_period_ms,
+        },
+        content: input.content,
+        signature_info: None,
+        signature_value: input.signature_value,
+    };
+    assert_eq!(Data::decode(&data.encode()).as_ref(), Some(&data));
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udcn_common::tlv;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tlv::read_var_number(data);
+
+    // Walk the buffer as a sequence of elements, the way every decoder does
+    let mut rest = data;
+    while let Some((element, next)) = tlv::read_element(rest) {
+        assert!(next.len() < rest.len());
+        if let Some(value) = tlv::read_nonneg_int(element.value) {
+            assert!(tlv::nonneg_int_len(value) <= element.value.len());
+        }
+        rest = next;
+    }
+});
@@ -0,0 +1,376 @@
+//! Expressing many Interests at once, for workloads such as polling thousands of sensors a
+//! second, where a syscall and an allocation per Interest would dominate.
+//!
+//! [`Consumer::express_batch`] copies a template Interest once per name, with a fresh nonce each,
+//! and encodes them back to back into one buffer sized for them all. [`Batch::next`] sends them
+//! [`BATCH_SEND_SIZE`] datagrams per `sendmmsg` while it listens for replies, so replies to the
+//! first Interests are read as they arrive rather than piling up in the socket's receive buffer
+//! behind the last ones sent. At most a window of Interests, [`DEFAULT_BATCH_WINDOW`] unless set,
+//! are outstanding at once, so a batch does not overrun the upstream's receive buffer either.
+//! Each Interest's lifetime starts when it is sent. Each name comes back once, in the order its
+//! Data, Nack or timeout arrives.
+
+use std::{
+    collections::HashMap, io, ops::Range, os::fd::AsRawFd as _, sync::atomic::Ordering,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use log::debug;
+use tokio::{
+    io::Interest as Readiness,
+    time::{self, Instant},
+};
+use udcn_common::{lp, Interest, Name};
+
+use crate::consumer::{answer, Consumer, Reply, DEFAULT_INTEREST_LIFETIME, MAX_PACKET_SIZE};
+
+/// Datagrams handed to the kernel per `sendmmsg`.
+pub const BATCH_SEND_SIZE: usize = 64;
+
+/// Interests sent and not yet answered or timed out, at most, unless set.
+pub const DEFAULT_BATCH_WINDOW: usize = 128;
+
+impl Consumer {
+    /// Express an Interest for each of `names`, with the default lifetime and no selectors.
+    pub fn express_batch(&self, names: impl IntoIterator<Item = Name>) -> Batch<'_> {
+        self.express_batch_like(&Interest::builder().build(), names)
+    }
+
+    /// Express an Interest for each of `names`, otherwise as `template`.
+    pub fn express_batch_like(
+        &self,
+        template: &Interest,
+        names: impl IntoIterator<Item = Name>,
+    ) -> Batch<'_> {
+        let interests: Vec<Interest> = names
+            .into_iter()
+            .map(|name| {
+                let mut interest = Interest::builder()
+                    .name(name)
+                    .can_be_prefix(template.can_be_prefix)
+                    .must_be_fresh(template.must_be_fresh)
+                    .build();
+                interest.lifetime_ms = template.lifetime_ms;
+                interest.forwarding_hint = template.forwarding_hint.clone();
+                interest
+            })
+            .collect();
+
+        // Sized from the first with some room for longer names, so the pool is rarely regrown
+        let estimate = interests
+            .first()
+            .map_or(0, |interest| interest.encode().len());
+        let mut pool = Vec::with_capacity(interests.len() * (estimate + estimate / 4));
+        let mut encoded = Vec::with_capacity(interests.len());
+        let mut by_hash: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, interest) in interests.iter().enumerate() {
+            let start = pool.len();
+            interest.encode_to(&mut pool);
+            encoded.push(start..pool.len());
+            by_hash
+                .entry(interest.name.name_hash())
+                .or_default()
+                .push(i);
+        }
+
+        Batch {
+            consumer: self,
+            lifetime: template
+                .lifetime_ms
+                .map_or(DEFAULT_INTEREST_LIFETIME, Duration::from_millis),
+            settled: vec![false; interests.len()],
+            unsettled: interests.len(),
+            interests,
+            pool,
+            encoded,
+            by_hash,
+            window: DEFAULT_BATCH_WINDOW,
+            sent: 0,
+            deadlines: Vec::new(),
+            expired: 0,
+            send_error: None,
+            buf: vec![0u8; MAX_PACKET_SIZE],
+            reassembler: lp::Reassembler::new(),
+        }
+    }
+}
+
+/// Interests expressed by [`Consumer::express_batch`], whose replies are read with
+/// [`Batch::next`]. Interests not yet sent when it is dropped never are.
+pub struct Batch<'a> {
+    consumer: &'a Consumer,
+    lifetime: Duration,
+    interests: Vec<Interest>,
+    /// Every Interest's encoding, back to back.
+    pool: Vec<u8>,
+    /// Where each Interest's encoding is in the pool.
+    encoded: Vec<Range<usize>>,
+    /// Interests by the hash of their name, which Nacks and exactly named Data carry.
+    by_hash: HashMap<u32, Vec<usize>>,
+    window: usize,
+    /// Interests before this one have been sent.
+    sent: usize,
+    /// When each sent Interest's lifetime runs out, in the order they were sent.
+    deadlines: Vec<Instant>,
+    /// Sent Interests before this one have been checked for expiry.
+    expired: usize,
+    /// Whether each Interest has been answered, timed out or failed to send.
+    settled: Vec<bool>,
+    unsettled: usize,
+    /// Why sending stopped; Interests not sent by then fail with it.
+    send_error: Option<String>,
+    buf: Vec<u8>,
+    reassembler: lp::Reassembler,
+}
+
+impl Batch<'_> {
+    /// Interests in the batch.
+    pub fn len(&self) -> usize {
+        self.interests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interests.is_empty()
+    }
+
+    /// Keep at most `window` Interests outstanding, rather than [`DEFAULT_BATCH_WINDOW`].
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+    }
+
+    /// Interests handed to the kernel so far.
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Interests sent and not yet answered or timed out.
+    fn outstanding(&self) -> usize {
+        self.sent - (self.interests.len() - self.unsettled)
+    }
+
+
+    /// The next name whose Interest was answered, timed out or could not be sent, with what
+    /// became of it, or `None` once every name has come back.
+    pub async fn next(&mut self) -> Option<(Name, anyhow::Result<Reply>)> {
+        loop {
+            if self.unsettled == 0 {
+                return None;
+            }
+            if let Some(settled) = self.settle_expired() {
+                return Some(settled);
+            }
+            if let Some(error) = &self.send_error {
+                if self.sent < self.interests.len() {
+                    let error = anyhow!("failed to send Interest: {error}");
+                    let i = self.sent;
+                    self.sent += 1;
+                    return Some(self.settle(i, Err(error)));
+                }
+            }
+
+            let unsent = self.send_error.is_none()
+                && self.sent < self.interests.len()
+                && self.outstanding() < self.window;
+            let deadline = self.deadlines.get(self.expired).copied();
+            let socket = &self.consumer.socket;
+            tokio::select! {
+                ready = socket.writable(), if unsent => match ready {
+                    Ok(()) => self.send_some(),
+                    Err(e) => self.send_error = Some(e.to_string()),
+                },
+                received = socket.recv(&mut self.buf) => match received {
+                    Ok(len) => {
+                        if let Some((i, reply)) = self.receive(len) {
+                            return Some(self.settle(i, Ok(reply)));
+                        }
+                    }
+                    // Such as an ICMP port unreachable for an earlier datagram
+                    Err(e) => debug!("batch receive failed: {e}"),
+                },
+                () = time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() => {}
+            }
+        }
+    }
+
+    fn settle(&mut self, i: usize, result: anyhow::Result<Reply>) -> (Name, anyhow::Result<Reply>) {
+        self.settled[i] = true;
+        self.unsettled -= 1;
+        (self.interests[i].name.clone(), result)
+    }
+
+    /// The first sent Interest whose lifetime ran out unanswered, if any.
+    fn settle_expired(&mut self) -> Option<(Name, anyhow::Result<Reply>)> {
+        let now = Instant::now();
+        while let Some(&deadline) = self.deadlines.get(self.expired) {
+            if deadline > now {
+                break;
+            }
+            let i = self.expired;
+            self.expired += 1;
+            if !self.settled[i] {
+                let error = anyhow!("Interest for '{}' timed out", self.interests[i].name);
+                return Some(self.settle(i, Err(error)));
+            }
+        }
+        None
+    }
+
+    /// Hand the kernel as many of the unsent Interests as it takes in one `sendmmsg`.
+    fn send_some(&mut self) {
+        let room = BATCH_SEND_SIZE.min(self.window - self.outstanding());
+        let end = self.interests.len().min(self.sent + room);
+        let mut iovecs: Vec<libc::iovec> = self.encoded[self.sent..end]
+            .iter()
+            .map(|range| libc::iovec {
+                iov_base: self.pool[range.clone()].as_ptr() as *mut libc::c_void,
+                iov_len: range.len(),
+            })
+            .collect();
+        let mut messages: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                // No address, as the socket is connected
+                let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+                header.msg_iov = iovec;
+                header.msg_iovlen = 1;
+                libc::mmsghdr {
+                    msg_hdr: header,
+                    msg_len: 0,
+                }
+            })
+            .collect();
+
+        let socket = &self.consumer.socket;
+        let sent = socket.try_io(Readiness::WRITABLE, || {
+            let ret = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    messages.as_mut_ptr(),
+                    messages.len() as libc::c_uint,
+                    0,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(ret as usize)
+        });
+        match sent {
+            Ok(sent) => {
+                let deadline = Instant::now() + self.lifetime;
+                self.deadlines.extend(std::iter::repeat_n(deadline, sent));
+                self.sent += sent;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => self.send_error = Some(e.to_string()),
+        }
+    }
+
+    /// The unsettled Interest the `len` bytes just received answer, and how.
+    fn receive(&mut self, len: usize) -> Option<(usize, Reply)> {
+        let reassembled;
+        let packet = if lp::is_lp_packet(&self.buf[..len]) {
+            reassembled = self.reassembler.receive(&self.buf[..len])?;
+            &reassembled[..]
+        } else {
+            &self.buf[..len]
+        };
+
+        let hash = match udcn_common::parse_nack_packet(packet) {
+            Some(nack) => Some(nack.name_hash),
+            None => udcn_common::Data::decode(packet).map(|data| data.name.name_hash()),
+        };
+        let unsettled = |i: &usize| *i < self.sent && !self.settled[*i];
+        // Data named exactly as the Interest carries its hash; Data under a prefix is searched for
+        let candidates: Vec<usize> = match hash.and_then(|hash| self.by_hash.get(&hash)) {
+            Some(same_name) => same_name.iter().copied().filter(unsettled).collect(),
+            None => Vec::new(),
+        };
+        let found = candidates
+            .into_iter()
+            .chain((0..self.sent).filter(|i| self.interests[*i].can_be_prefix && unsettled(i)))
+            .find_map(|i| answer(&self.interests[i], packet).map(|reply| (i, reply)));
+        match &found {
+            Some(_) => {
+                self.consumer
+                    .congestion_marks
+                    .fetch_add(self.reassembler.congestion_marks(), Ordering::Relaxed);
+                self.reassembler = lp::Reassembler::new();
+            }
+            None => debug!("ignoring unrelated {}-byte packet", packet.len()),
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{Data, NackReason};
+
+    use super::*;
+    use crate::Producer;
+
+    #[tokio::test]
+    async fn test_batches_stream_every_reply() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let prefix = Name::from("/sensors");
+        let serve = producer.serve(&prefix, |interest| {
+            let sensor = interest.name.to_string();
+            // Every tenth sensor is offline
+            (!sensor.ends_with('0')).then(|| {
+                Data::builder()
+                    .name(interest.name.clone())
+                    .content(sensor.into_bytes())
+                    .build()
+            })
+        });
+        let names = (0..300).map(|i| Name::from(format!("/sensors/{i}").as_str()));
+        let mut batch = consumer.express_batch(names);
+        assert_eq!(batch.len(), 300);
+
+        let collect = async {
+            let mut replies = HashMap::new();
+            while let Some((name, reply)) = batch.next().await {
+                assert!(replies.insert(name, reply.unwrap()).is_none());
+            }
+            replies
+        };
+        let replies = tokio::select! {
+            result = serve => panic!("producer stopped: {result:?}"),
+            replies = collect => replies,
+        };
+        assert_eq!(batch.sent(), 300);
+        assert_eq!(replies.len(), 300);
+        for (name, reply) in replies {
+            match reply {
+                Reply::Data(data) => assert_eq!(data.content, name.to_string().as_bytes()),
+                Reply::Nack(reason) => {
+                    assert!(name.to_string().ends_with('0'));
+                    assert_eq!(reason, NackReason::NoData as u8);
+                }
+            }
+        }
+
+        // Nobody answers here, so every Interest times out
+        let silent = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(silent.local_addr().unwrap())
+            .await
+            .unwrap();
+        let template = Interest::builder().lifetime_ms(50).build();
+        let mut batch = consumer.express_batch_like(
+            &template,
+            (0..3).map(|i| Name::from(format!("/x/{i}").as_str())),
+        );
+        let mut timed_out = 0;
+        while let Some((_, reply)) = batch.next().await {
+            assert!(reply.unwrap_err().to_string().contains("timed out"));
+            timed_out += 1;
+        }
+        assert_eq!(timed_out, 3);
+    }
+}
@@ -0,0 +1,211 @@
+//! Expressing Interests and waiting for the matching Data or Nack.
+
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use log::{debug, warn};
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant},
+};
+use udcn_common::{
+    lp::{self, HopReport},
+    parse_nack_packet, Data, Interest,
+};
+
+use crate::segments::implicit_digest;
+
+/// Used when an Interest carries no InterestLifetime, matching the NDN default.
+pub const DEFAULT_INTEREST_LIFETIME: Duration = Duration::from_secs(4);
+
+pub(crate) const MAX_PACKET_SIZE: usize = 65_536;
+
+/// What came back for an Interest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    Data(Data),
+    /// The raw Nack reason; see `NackReason::from_u8`.
+    Nack(u8),
+}
+
+/// A UDP face to one forwarder or producer.
+pub struct Consumer {
+    pub(crate) socket: UdpSocket,
+    pub(crate) congestion_marks: AtomicU64,
+}
+
+impl Consumer {
+    pub async fn connect(target: SocketAddr) -> anyhow::Result<Self> {
+        let local: SocketAddr = if target.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(target).await?;
+        Ok(Self {
+            socket,
+            congestion_marks: AtomicU64::new(0),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Replies that came back with a CongestionMark, a sign to express Interests more slowly.
+    pub fn congestion_marks(&self) -> u64 {
+        self.congestion_marks.load(Ordering::Relaxed)
+    }
+
+    /// Send `interest` and wait until its lifetime runs out for the Data or Nack answering it.
+    /// Replies fragmented with NDNLPv2 are reassembled, and congestion marks counted. Unrelated
+    /// packets arriving in the meantime are discarded.
+    pub async fn express(&self, interest: &Interest) -> anyhow::Result<Reply> {
+        let lifetime = interest
+            .lifetime_ms
+            .map_or(DEFAULT_INTEREST_LIFETIME, Duration::from_millis);
+        self.socket.send(&interest.encode()).await?;
+
+        time::timeout(lifetime, self.wait_for(interest))
+            .await
+            .with_context(|| format!("Interest for '{}' timed out", interest.name))?
+    }
+
+    async fn wait_for(&self, interest: &Interest) -> anyhow::Result<Reply> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let mut reassembler = lp::Reassembler::new();
+        loop {
+            let len = self.socket.recv(&mut buf).await?;
+            let reassembled;
+            let packet = if lp::is_lp_packet(&buf[..len]) {
+                match reassembler.receive(&buf[..len]) {
+                    Some(packet) => {
+                        reassembled = packet;
+                        &reassembled[..]
+                    }
+                    None => continue,
+                }
+            } else {
+                &buf[..len]
+            };
+
+            match answer(interest, packet) {
+                Some(reply) => {
+                    self.congestion_marks
+                        .fetch_add(reassembler.congestion_marks(), Ordering::Relaxed);
+                    return Ok(reply);
+                }
+                None => debug!("ignoring unrelated {}-byte packet", packet.len()),
+            }
+        }
+    }
+}
+
+/// The Data or Nack answering `interest`, if `packet` is one.
+pub(crate) fn answer(interest: &Interest, packet: &[u8]) -> Option<Reply> {
+    if let Some(nack) = parse_nack_packet(packet) {
+        if nack.name_hash == interest.name.name_hash() && nack.nonce == interest.nonce {
+            return Some(Reply::Nack(nack.reason));
+        }
+    } else if let Some(data) = Data::decode(packet) {
+        if satisfies(interest, &data, packet) {
+            return Some(Reply::Data(data));
+        }
+    }
+    None
+}
+
+/// How long [`trace`] keeps listening once the reply is in, for reports from hops behind it.
+pub const TRACE_LINGER: Duration = Duration::from_millis(200);
+
+/// What came back for a traced Interest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    /// `None` if the Interest's lifetime ran out first.
+    pub reply: Option<Reply>,
+    /// Each forwarder's report, with how long after sending it arrived, in order of arrival.
+    pub hops: Vec<(Duration, HopReport)>,
+}
+
+/// Send `interest` to `target` carrying `trace_id`, and collect the hop reports forwarders on
+/// the path send back along with the reply.
+pub async fn trace(
+    target: SocketAddr,
+    interest: &Interest,
+    trace_id: u64,
+) -> anyhow::Result<Trace> {
+    let local: SocketAddr = if target.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    // Not connected, as hop reports come from every forwarder on the path
+    let socket = UdpSocket::bind(local).await?;
+    socket
+        .send_to(&lp::trace(&interest.encode(), trace_id), target)
+        .await?;
+    let sent = Instant::now();
+    let lifetime = interest
+        .lifetime_ms
+        .map_or(DEFAULT_INTEREST_LIFETIME, Duration::from_millis);
+    let mut deadline = sent + lifetime;
+
+    let mut trace = Trace {
+        reply: None,
+        hops: Vec::new(),
+    };
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut reassembler = lp::Reassembler::new();
+    while let Ok(received) = time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, _) = received?;
+        if let Some(hop) = HopReport::decode(&buf[..len]) {
+            if hop.trace_id == trace_id {
+                trace.hops.push((sent.elapsed(), hop));
+            }
+            continue;
+        }
+        if trace.reply.is_some() {
+            continue;
+        }
+        let packet = if lp::is_lp_packet(&buf[..len]) {
+            match reassembler.receive(&buf[..len]) {
+                Some(packet) => packet,
+                None => continue,
+            }
+        } else {
+            buf[..len].to_vec()
+        };
+        if let Some(reply) = answer(interest, &packet) {
+            trace.reply = Some(reply);
+            deadline = Instant::now() + TRACE_LINGER;
+        }
+    }
+    Ok(trace)
+}
+
+/// Whether `data`, received as `packet`, answers `interest`. An Interest naming an exact packet
+/// by its implicit digest only takes Data whose digest matches, so altered copies are ignored.
+fn satisfies(interest: &Interest, data: &Data, packet: &[u8]) -> bool {
+    let name = &interest.name;
+    match name.implicit_digest() {
+        Some(digest) => {
+            if data.name != name.prefix(name.len() - 1) {
+                return false;
+            }
+            let matches = implicit_digest(packet)[..] == *digest;
+            if !matches {
+                warn!(
+                    "Data for '{}' does not match the requested digest",
+                    data.name
+                );
+            }
+            matches
+        }
+        None => data.name == *name || (interest.can_be_prefix && name.is_prefix_of(&data.name)),
+    }
+}
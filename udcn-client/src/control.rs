@@ -0,0 +1,456 @@
+//! Client side of the daemon's Unix control socket.
+//!
+//! A client sends a single request line (`<command> [argument]`) and reads the response until the
+//! daemon closes the connection. Failed requests are answered with a line starting with
+//! [`ERROR_PREFIX`]. The `events` and `events-raw` requests are the exception: their response is a
+//! line per datapath event, for as long as the client stays connected. `cs-preload` and
+//! `state-import` requests are followed by a body, which the daemon reads until the client shuts
+//! down its side of the stream.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader, Lines},
+    net::UnixStream,
+};
+use udcn_common::{Name, PacketStats};
+
+pub const SOCKET_PATH: &str = "/run/udcn/control.sock";
+
+/// Overrides [`SOCKET_PATH`] for both the daemon and its clients, so several daemons can run side
+/// by side on one host.
+pub const SOCKET_PATH_ENV: &str = "UDCN_CONTROL_SOCKET";
+
+pub const ERROR_PREFIX: &str = "error: ";
+
+/// The control socket in effect: `$UDCN_CONTROL_SOCKET`, or [`SOCKET_PATH`].
+pub fn socket_path() -> PathBuf {
+    env::var_os(SOCKET_PATH_ENV).map_or_else(|| SOCKET_PATH.into(), PathBuf::from)
+}
+
+/// Send `request` to the daemon and return its response.
+pub async fn request(request: &str) -> anyhow::Result<String> {
+    request_at(&socket_path(), request).await
+}
+
+/// Send `request` to the daemon listening on `socket`.
+pub async fn request_at(socket: &Path, request: &str) -> anyhow::Result<String> {
+    let stream = send_request(socket, request).await?;
+    read_response(stream).await
+}
+
+/// Send `request` followed by `body`, which the daemon reads up to the end of the stream, and
+/// return its response.
+pub async fn request_with_body(request: &str, body: &[u8]) -> anyhow::Result<String> {
+    let mut stream = send_request(&socket_path(), request).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await?;
+    read_response(stream).await
+}
+
+async fn read_response(mut stream: UnixStream) -> anyhow::Result<String> {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    match response.strip_prefix(ERROR_PREFIX) {
+        Some(error) => Err(anyhow::anyhow!("{}", error.trim_end())),
+        None => Ok(response),
+    }
+}
+
+async fn send_request(socket: &Path, request: &str) -> anyhow::Result<UnixStream> {
+    let mut stream = UnixStream::connect(socket).await.with_context(|| {
+        format!(
+            "failed to connect to {} - is `udcn run` running?",
+            socket.display()
+        )
+    })?;
+    stream.write_all(format!("{request}\n").as_bytes()).await?;
+    Ok(stream)
+}
+
+/// The datapath counters.
+pub async fn stats() -> anyhow::Result<PacketStats> {
+    stats_at(&socket_path()).await
+}
+
+/// The datapath counters of the daemon listening on `socket`.
+pub async fn stats_at(socket: &Path) -> anyhow::Result<PacketStats> {
+    let response = request_at(socket, "stats").await?;
+    serde_json::from_str(&response).context("invalid stats response")
+}
+
+/// How full one of the datapath's tables is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableOccupancy {
+    /// The map name, e.g. `PIT`.
+    pub table: String,
+    pub entries: u32,
+    pub capacity: u32,
+}
+
+/// Entry counts and capacities of the datapath tables.
+pub async fn tables() -> anyhow::Result<Vec<TableOccupancy>> {
+    tables_at(&socket_path()).await
+}
+
+/// Entry counts and capacities of the tables of the daemon listening on `socket`.
+pub async fn tables_at(socket: &Path) -> anyhow::Result<Vec<TableOccupancy>> {
+    let response = request_at(socket, "tables").await?;
+    serde_json::from_str(&response).context("invalid tables response")
+}
+
+/// One of the alarm thresholds the daemon was configured with, and whether it is crossed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlarmStatus {
+    pub name: String,
+    /// What the alarm fires on, e.g. `hit_ratio below 20% over 60s`.
+    pub condition: String,
+    /// The latest value, in percent, or `None` while there is nothing to take a ratio of.
+    pub value: Option<f64>,
+    pub firing: bool,
+    /// When the alarm last started or stopped firing, in Unix milliseconds.
+    pub since_unix_ms: Option<u64>,
+}
+
+/// Where each configured alarm stands.
+pub async fn alarms() -> anyhow::Result<Vec<AlarmStatus>> {
+    let response = request("alarms").await?;
+    serde_json::from_str(&response).context("invalid alarms response")
+}
+
+/// What one kind of table management, such as sweeping the PIT, has cost the daemon.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OperationTiming {
+    /// e.g. `pit_gc`, `cs_flush` or `table_scrape`.
+    pub operation: String,
+    pub calls: u64,
+    /// Entries read or written, over every call.
+    pub entries: u64,
+    /// Whether the latest call used batch map operations, as opposed to one syscall per key.
+    pub batched: bool,
+    pub last_us: u64,
+    pub total_us: u64,
+}
+
+/// How long the daemon's table management has taken, by operation.
+pub async fn timings() -> anyhow::Result<Vec<OperationTiming>> {
+    let response = request("timings").await?;
+    serde_json::from_str(&response).context("invalid timings response")
+}
+
+/// One of the daemon's own faces, such as a tunnel to another site, and how sending on it goes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FaceStatus {
+    /// e.g. `tunnel site-b.example.net:6363`.
+    pub face: String,
+    /// Where the face takes in packets.
+    pub local: String,
+    /// The address it sends to, or `None` while it is down.
+    pub remote: Option<String>,
+    /// Bytes waiting in the send buffer of the socket towards the remote, and how many it holds.
+    pub send_queue_bytes: u64,
+    pub send_buffer_bytes: u64,
+    /// Packets handed to the face's sockets.
+    pub sent: u64,
+    /// Packets dropped as a socket's send buffer was full (EWOULDBLOCK).
+    pub would_block_drops: u64,
+    /// Packets a socket failed to send for any other reason.
+    pub send_errors: u64,
+    /// Copies of Data not sent to consumers on the face's multicast segment, as one multicast
+    /// packet reached them all.
+    #[serde(default)]
+    pub suppressed_duplicates: u64,
+    /// Smoothed time from a packet arriving to a socket taking it, in microseconds.
+    pub queuing_delay_us: u64,
+    /// Nacks received on the face, by reason.
+    #[serde(default)]
+    pub nacks: BTreeMap<String, u64>,
+    /// Bytes held back to keep within the face's egress rate, and bytes dropped as too many
+    /// were held back already.
+    #[serde(default)]
+    pub shaped_bytes: u64,
+    #[serde(default)]
+    pub shaping_dropped_bytes: u64,
+}
+
+/// The daemon's own faces.
+pub async fn faces() -> anyhow::Result<Vec<FaceStatus>> {
+    let response = request("faces").await?;
+    serde_json::from_str(&response).context("invalid faces response")
+}
+
+/// A tenant's usage against its quotas, and what the quotas turned away. A quota of 0 is none.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TenantStatus {
+    pub tenant: String,
+    pub prefix: String,
+    pub pit_entries: u32,
+    pub pit_quota: u32,
+    /// Content bytes of the tenant's Data in the content store.
+    pub cs_bytes: u32,
+    pub cs_quota: u32,
+    /// Interests admitted in the current one-second window.
+    pub interest_rate: u32,
+    pub interest_rate_quota: u32,
+    /// Interests seen in total, and those dropped over the rate and PIT quotas.
+    pub interests: u32,
+    pub rate_limited: u32,
+    pub pit_rejects: u32,
+    /// Data kept out of the content store over the byte quota.
+    pub cs_rejects: u32,
+}
+
+/// Each configured tenant's usage.
+pub async fn tenants() -> anyhow::Result<Vec<TenantStatus>> {
+    let response = request("tenants").await?;
+    serde_json::from_str(&response).context("invalid tenants response")
+}
+
+/// Tell the daemon about `name` so its listings can show it instead of its hash.
+pub async fn register(name: &Name) -> anyhow::Result<u32> {
+    let response = request(&format!("register {name}")).await?;
+    let hash = response.trim().trim_start_matches("0x");
+    u32::from_str_radix(hash, 16).with_context(|| format!("invalid register response {response:?}"))
+}
+
+/// The `k` names the datapath handles the most Interests for over the next `secs` seconds, a
+/// `count name` line each.
+pub async fn top_names(k: usize, secs: u64) -> anyhow::Result<String> {
+    request(&format!("names-top {k} {secs}")).await
+}
+
+/// Route Interests under `prefix` upstream, until the daemon stops if `ephemeral`. Returns the
+/// daemon's confirmation.
+pub async fn route_add(prefix: &Name, ephemeral: bool) -> anyhow::Result<String> {
+    let flag = if ephemeral { "--ephemeral " } else { "" };
+    request(&format!("route-add {flag}{prefix}")).await
+}
+
+/// Stop routing Interests under `prefix`. Returns the daemon's confirmation.
+pub async fn route_remove(prefix: &Name) -> anyhow::Result<String> {
+    request(&format!("route-remove {prefix}")).await
+}
+
+/// The routed prefixes, each with where its route comes from: `config`, `saved` or `ephemeral`.
+pub async fn routes() -> anyhow::Result<Vec<(Name, Vec<String>)>> {
+    parse_routes(&request("route-list").await?)
+}
+
+/// Parse the table `route-list` answers with.
+fn parse_routes(table: &str) -> anyhow::Result<Vec<(Name, Vec<String>)>> {
+    table
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let (prefix, sources) = line
+                .split_once(' ')
+                .with_context(|| format!("invalid route line {line:?}"))?;
+            let prefix = Name::from_uri(prefix)
+                .map_err(|e| anyhow::anyhow!("invalid route prefix {prefix:?}: {e}"))?;
+            let sources = sources.trim().split(", ").map(str::to_string).collect();
+            Ok((prefix, sources))
+        })
+        .collect()
+}
+
+/// The strategy the daemon has in effect for `name`.
+pub async fn strategy(name: &Name) -> anyhow::Result<crate::strategy::StrategyChoice> {
+    let response = request(&format!("strategy {name}")).await?;
+    serde_json::from_str(&response).context("invalid strategy response")
+}
+
+/// A live stream of datapath events, one formatted line each.
+pub struct Events {
+    lines: Lines<BufReader<UnixStream>>,
+}
+
+impl Events {
+    /// The next event line, or `None` once the daemon goes away.
+    pub async fn next_line(&mut self) -> anyhow::Result<Option<String>> {
+        let line = self.lines.next_line().await?;
+        match line
+            .as_deref()
+            .and_then(|line| line.strip_prefix(ERROR_PREFIX))
+        {
+            Some(error) => Err(anyhow::anyhow!("{error}")),
+            None => Ok(line),
+        }
+    }
+}
+
+/// Subscribe to datapath events; `filter` is a filter expression such as
+/// `type == interest, name ~ /video`, or `None` for all of them.
+pub async fn events(filter: Option<&str>) -> anyhow::Result<Events> {
+    events_at(&socket_path(), filter).await
+}
+
+/// Subscribe to the datapath events of the daemon listening on `socket`.
+pub async fn events_at(socket: &Path, filter: Option<&str>) -> anyhow::Result<Events> {
+    subscribe(socket, "events", filter).await
+}
+
+/// Subscribe to datapath events as [`RawEvent`] lines, which keep the exact time of each.
+pub async fn raw_events(filter: Option<&str>) -> anyhow::Result<Events> {
+    subscribe(&socket_path(), "events-raw", filter).await
+}
+
+/// Sample packets from the datapath as [`CapturedPacket`] lines, one in `rate` truncated to
+/// `snaplen` bytes, or with the daemon's current sampling settings if `None`. Only packets
+/// matching `filter`, a filter expression as for [`events`], are sampled and sent.
+pub async fn capture(settings: Option<(u32, u32)>, filter: Option<&str>) -> anyhow::Result<Events> {
+    let settings = settings.map(|(rate, snaplen)| format!("{rate} {snaplen}"));
+    let arguments = match (settings, filter) {
+        (Some(settings), Some(filter)) => Some(format!("{settings} {filter}")),
+        (settings, filter) => settings.or(filter.map(str::to_string)),
+    };
+    subscribe(&socket_path(), "capture", arguments.as_deref()).await
+}
+
+/// Change how often the datapath samples packets for captures, and how much of each it keeps.
+/// A `rate` of 0 pauses every capture. With a `filter`, only packets of the types and name it
+/// allows are sampled.
+pub async fn set_sampling(rate: u32, snaplen: u32, filter: Option<&str>) -> anyhow::Result<String> {
+    match filter {
+        Some(filter) => request(&format!("sample {rate} {snaplen} {filter}")).await,
+        None => request(&format!("sample {rate} {snaplen}")).await,
+    }
+}
+
+async fn subscribe(socket: &Path, command: &str, filter: Option<&str>) -> anyhow::Result<Events> {
+    let request = match filter {
+        Some(filter) => format!("{command} {filter}"),
+        None => command.to_string(),
+    };
+    let stream = send_request(socket, &request).await?;
+    Ok(Events {
+        lines: BufReader::new(stream).lines(),
+    })
+}
+
+/// One line of `events-raw`: `<unix time ns> <name hash> <packet type> <outcome> <reason>`, the
+/// last three as the datapath's numeric codes. A subscriber that falls behind gets a
+/// `missed <count>` line instead of the events it missed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawEvent {
+    /// When the datapath made its decision, in nanoseconds since the Unix epoch.
+    pub timestamp_ns: u64,
+    pub name_hash: u32,
+    /// A `TlvType`.
+    pub packet_type: u8,
+    /// An `EventOutcome`.
+    pub outcome: u8,
+    pub reason: u8,
+}
+
+impl FromStr for RawEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let &[timestamp_ns, name_hash, packet_type, outcome, reason] = fields.as_slice() else {
+            anyhow::bail!("malformed event line '{s}'");
+        };
+        Ok(Self {
+            timestamp_ns: timestamp_ns.parse()?,
+            name_hash: u32::from_str_radix(name_hash.trim_start_matches("0x"), 16)?,
+            packet_type: packet_type.parse()?,
+            outcome: outcome.parse()?,
+            reason: reason.parse()?,
+        })
+    }
+}
+
+impl std::fmt::Display for RawEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:#010x} {} {} {}",
+            self.timestamp_ns, self.name_hash, self.packet_type, self.outcome, self.reason
+        )
+    }
+}
+
+/// One line of `capture`: `<unix time ns> <ifindex> <length on the wire> <captured bytes in hex>`.
+/// A capture that falls behind gets a `missed <count>` line instead of the packets it missed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// When the packet arrived, in nanoseconds since the Unix epoch.
+    pub timestamp_ns: u64,
+    /// The interface it arrived on.
+    pub ifindex: u32,
+    /// Its length on the wire; `data` is shorter if it was truncated.
+    pub packet_len: u32,
+    /// The packet from the Ethernet header on.
+    pub data: Vec<u8>,
+}
+
+impl FromStr for CapturedPacket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let &[timestamp_ns, ifindex, packet_len, data] = fields.as_slice() else {
+            anyhow::bail!("malformed capture line '{s}'");
+        };
+        anyhow::ensure!(data.len() % 2 == 0, "odd number of hex digits in '{s}'");
+        let data = (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&data[i..i + 2], 16))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            timestamp_ns: timestamp_ns.parse()?,
+            ifindex: ifindex.parse()?,
+            packet_len: packet_len.parse()?,
+            data,
+        })
+    }
+}
+
+impl std::fmt::Display for CapturedPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} ",
+            self.timestamp_ns, self.ifindex, self.packet_len
+        )?;
+        for byte in &self.data {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_routes() {
+        let table = "PREFIX                           SOURCE\n\
+                     /video                           config, saved\n\
+                     /a/very/long/prefix/that/overflows/the/column ephemeral\n";
+        let routes = parse_routes(table).unwrap();
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    Name::from("/video"),
+                    vec!["config".to_string(), "saved".to_string()]
+                ),
+                (
+                    Name::from("/a/very/long/prefix/that/overflows/the/column"),
+                    vec!["ephemeral".to_string()]
+                ),
+            ]
+        );
+        assert!(parse_routes("PREFIX SOURCE\n").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+//! Segments served from the file [`SegmentStore::save`] writes rather than from memory, so that an
+//! object of many gigabytes is served with only an index of its packets held.
+//!
+//! Opening the file reads it through once to index each packet's name, place in the file,
+//! implicit digest and freshness. Each lookup then reads the one packet it finds with a
+//! positional read on tokio's blocking thread pool, so that concurrent Interests for different
+//! segments are read in parallel rather than one after another. Packets answer Interests as those
+//! of a [`SegmentStore`] do, and stay fresh for their FreshnessPeriod from when the file was
+//! opened or written.
+//!
+//! [`DiskSegmentStore::publish`] writes such a file from content read a segment at a time, which
+//! then never has to fit in memory either.
+//!
+//! [`SegmentStore::save`]: crate::SegmentStore::save
+//! [`SegmentStore`]: crate::SegmentStore
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io,
+    os::unix::fs::FileExt as _,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWriteExt as _, BufReader, BufWriter};
+use udcn_common::{Data, Interest, Name};
+
+use crate::segments::{self, build_segment, implicit_digest, Indexed};
+
+/// Where a packet is in the file, its implicit digest and when it goes stale.
+#[derive(Clone, Debug)]
+struct Location {
+    offset: u64,
+    len: usize,
+    digest: [u8; 32],
+    fresh_until: Instant,
+}
+
+impl Indexed for Location {
+    fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+
+    fn fresh_until(&self) -> Instant {
+        self.fresh_until
+    }
+}
+
+/// Encoded Data packets in a file, indexed by name.
+#[derive(Debug)]
+pub struct DiskSegmentStore {
+    file: Arc<File>,
+    index: BTreeMap<Name, Location>,
+}
+
+impl DiskSegmentStore {
+    /// Index the packets in `path`, a file [`SegmentStore::save`] or [`Self::publish`] wrote.
+    ///
+    /// [`SegmentStore::save`]: crate::SegmentStore::save
+    pub async fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let truncated = || format!("{} is truncated", path.display());
+        let now = Instant::now();
+        let mut index = BTreeMap::new();
+        let mut offset = 0;
+        let mut packet = Vec::new();
+        loop {
+            let mut len = [0; 4];
+            if reader.read(&mut len[..1]).await? == 0 {
+                break;
+            }
+            reader
+                .read_exact(&mut len[1..])
+                .await
+                .with_context(truncated)?;
+            packet.resize(u32::from_be_bytes(len) as usize, 0);
+            reader
+                .read_exact(&mut packet)
+                .await
+                .with_context(truncated)?;
+            let data = Data::decode(&packet)
+                .with_context(|| format!("{} holds a packet that is not Data", path.display()))?;
+            let freshness = Duration::from_millis(data.meta.freshness_period_ms.unwrap_or(0));
+            let location = Location {
+                offset: offset + 4,
+                len: packet.len(),
+                digest: implicit_digest(&packet),
+                fresh_until: now + freshness,
+            };
+            offset += 4 + packet.len() as u64;
+            index.insert(data.name, location);
+        }
+        Ok(Self {
+            file: Arc::new(reader.into_inner().into_std().await),
+            index,
+        })
+    }
+
+    /// Split `content` into `segment_size`-byte segments named `<prefix>/seg=<n>`, sign each
+    /// with `signer` and write the encoded packets to `path`, as [`SegmentStore::publish`] and
+    /// [`SegmentStore::save`] would, holding one segment in memory at a time.
+    ///
+    /// [`SegmentStore::publish`]: crate::SegmentStore::publish
+    /// [`SegmentStore::save`]: crate::SegmentStore::save
+    pub async fn publish(
+        path: &Path,
+        prefix: &Name,
+        mut content: impl AsyncRead + Unpin,
+        segment_size: usize,
+        freshness_period_ms: Option<u64>,
+        signer: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> anyhow::Result<Self> {
+        assert!(segment_size > 0, "segment size must be positive");
+        let file = tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let fresh_until = Instant::now() + Duration::from_millis(freshness_period_ms.unwrap_or(0));
+        let mut index = BTreeMap::new();
+        let mut offset = 0;
+        let mut chunk = vec![0; segment_size];
+        for segment in 0.. {
+            let filled = fill(&mut content, &mut chunk).await?;
+            // Empty content still gets one empty segment
+            if filled == 0 && segment > 0 {
+                break;
+            }
+            let name = prefix.clone().append_segment(segment);
+            let packet =
+                build_segment(name.clone(), &chunk[..filled], freshness_period_ms, &signer)
+                    .encode();
+            writer
+                .write_all(&(packet.len() as u32).to_be_bytes())
+                .await?;
+            writer.write_all(&packet).await?;
+            let location = Location {
+                offset: offset + 4,
+                len: packet.len(),
+                digest: implicit_digest(&packet),
+                fresh_until,
+            };
+            offset += 4 + packet.len() as u64;
+            index.insert(name, location);
+            if filled < segment_size {
+                break;
+            }
+        }
+        writer.flush().await?;
+        writer.into_inner().sync_all().await?;
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Self {
+            file: Arc::new(file.into_std().await),
+            index,
+        })
+    }
+
+    /// The encoded packet named `name`, read from the file. A trailing implicit digest
+    /// component must match the packet's digest.
+    pub async fn get(&self, name: &Name) -> io::Result<Option<Vec<u8>>> {
+        match segments::lookup(&self.index, name) {
+            Some(location) => self.read(location).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The packet answering `interest`, as [`SegmentStore::find`] picks it, read from the file.
+    ///
+    /// [`SegmentStore::find`]: crate::SegmentStore::find
+    pub async fn find(&self, interest: &Interest) -> io::Result<Option<Vec<u8>>> {
+        match segments::find(&self.index, interest) {
+            Some(location) => self.read(location).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn read(&self, location: &Location) -> io::Result<Vec<u8>> {
+        let file = self.file.clone();
+        let (offset, len) = (location.offset, location.len);
+        tokio::task::spawn_blocking(move || {
+            let mut packet = vec![0; len];
+            file.read_exact_at(&mut packet, offset)?;
+            Ok(packet)
+        })
+        .await?
+    }
+
+    /// The implicit digest of the packet named `name`.
+    pub fn digest(&self, name: &Name) -> Option<[u8; 32]> {
+        self.index.get(name).map(|location| location.digest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+/// Read from `content` until `buf` is full or the content ends. Returns how much was read.
+async fn fill(content: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match content.read(&mut buf[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{segments::sha256_signer, SegmentStore};
+
+    #[tokio::test]
+    async fn test_disk_store_serves_what_memory_would() {
+        let prefix = Name::from("/file");
+        let content = b"abcdefghij".repeat(10);
+        let mut memory = SegmentStore::new();
+        memory.publish(&prefix, &content, 30, Some(60_000), sha256_signer);
+        let dir = std::env::temp_dir();
+        let saved = dir.join(format!("udcn-disk-saved-{}", std::process::id()));
+        let published = dir.join(format!("udcn-disk-published-{}", std::process::id()));
+        memory.save(&saved).unwrap();
+
+        let opened = DiskSegmentStore::open(&saved).await.unwrap();
+        let written = DiskSegmentStore::publish(
+            &published,
+            &prefix,
+            &content[..],
+            30,
+            Some(60_000),
+            sha256_signer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&published).unwrap(), fs::read(&saved).unwrap());
+        for store in [&opened, &written] {
+            assert_eq!(store.len(), 4);
+            for segment in 0..4 {
+                let name = prefix.clone().append_segment(segment);
+                let packet = store.get(&name).await.unwrap();
+                assert_eq!(packet.as_deref(), memory.get(&name).as_deref());
+                assert_eq!(store.digest(&name), memory.digest(&name));
+            }
+            let discover = Interest::builder()
+                .name(prefix.clone())
+                .can_be_prefix(true)
+                .must_be_fresh(true)
+                .build();
+            let first = store.find(&discover).await.unwrap().unwrap();
+            assert_eq!(
+                Data::decode(&first).unwrap().name,
+                prefix.clone().append_segment(0)
+            );
+            assert!(store.get(&Name::from("/other")).await.unwrap().is_none());
+        }
+
+        let empty =
+            DiskSegmentStore::publish(&published, &prefix, &b""[..], 30, None, sha256_signer)
+                .await
+                .unwrap();
+        assert_eq!(empty.len(), 1);
+        fs::write(&saved, [0, 0, 0, 9, 1]).unwrap();
+        assert!(DiskSegmentStore::open(&saved).await.is_err());
+        fs::remove_file(&saved).unwrap();
+        fs::remove_file(&published).unwrap();
+    }
+}
@@ -0,0 +1,923 @@
+//! Upstream faces with liveness monitoring and failover.
+//!
+//! A [`FaceTable`] holds nexthops in order of preference. Each is probed with an Interest under
+//! [`KEEPALIVE_PREFIX`]; any Data or Nack in return shows the peer is there, as producers answer
+//! names they do not serve with a NoData Nack. A face whose probes go unanswered
+//! `failures_until_down` times in a row is marked down, and the first answered probe brings it
+//! back up. Interests go to the most preferred face that is up and fail over to the next one on a
+//! timeout or socket error. Subscribers are told about every change of state. Interests that fail
+//! are logged through [`Warnings`], so that failing faces do not flood the log.
+//!
+//! With [`Retransmission`] set, an Interest no longer waits out its whole lifetime on one face.
+//! Once a face leaves it unanswered for the retransmission timeout, it is sent again on the next
+//! face that is up, with a fresh nonce so the upstream forwarders do not take it for a loop. The
+//! retries are bounded and all fit within the Interest's lifetime, so a consumer only sees a
+//! timeout once every retry has gone unanswered. Each face can also be paced, see
+//! [`pacing`](crate::pacing).
+//!
+//! A Nack from a face is handed to the consumer straight away by default. Under another
+//! [`NackPolicy`] the other faces that are up are tried first, and the Nack only counts once they
+//! all Nack or fail too. The table counts the Nacks each face returns, by reason.
+//!
+//! The table keeps a smoothed round-trip time for each face, from the replies to its Interests
+//! and probes. When [deadline-aware](FaceTable::set_deadline_aware), an Interest's lifetime is the
+//! consumer's whole budget rather than each face's: every face it is sent to gets what is left of
+//! it, and a face whose RTT exceeds what is left is skipped. An Interest no face can answer in time
+//! is Nacked at once with reason `TooLate`, rather than spent on a reply that would come too late.
+//!
+//! With [`LoadBalance`] set, Interests are spread over the faces that are up instead of all going
+//! to the most preferred one: each goes first to the face picked by smooth weighted round-robin,
+//! as nginx balances upstreams, and fails over to the others in order of preference. A face
+//! weighing 3 against another's 1 is picked for three Interests in every four. Faces that are
+//! down are left out, and their share goes to the rest until they are back up.
+//!
+//! Faces can be tagged with the [`Tier`] of the hierarchy they reach, from a local cache through a
+//! regional one to the origin. Interests then go to the faces of the nearest tier first, and only
+//! escalate to the next tier once every face of the nearer one has failed, Nacked or is down;
+//! within a tier faces keep their order of preference, or are load balanced. The table counts the
+//! Interests each tier was sent and the Data it returned, see [`FaceTable::tier_stats`].
+//!
+//! Faces are reached through a [`Transport`]: [`UdpTransport`] on the network, or a
+//! [`SimNetwork`](crate::sim::SimNetwork) to test this logic on a virtual clock.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, task::JoinHandle, time};
+use udcn_common::{Interest, NackReason, Name};
+
+use crate::{
+    consumer::{Consumer, Reply, DEFAULT_INTEREST_LIFETIME},
+    pacing::{Pacer, Pacing, PacingStats},
+    registry::fresh_nonce,
+    warnings::Warnings,
+};
+
+/// Names keepalive probes are expressed under, each followed by a sequence number so no cache
+/// answers them.
+pub const KEEPALIVE_PREFIX: &str = "/localhop/udcn/keepalive";
+
+/// State changes a slow subscriber may fall behind by before it starts missing them.
+const SUBSCRIBER_BACKLOG: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaceState {
+    Up,
+    Down,
+}
+
+/// A face going up or down.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaceEvent {
+    pub addr: SocketAddr,
+    pub state: FaceState,
+}
+
+/// How faces are probed, and how many probes in a row have to go unanswered before a face is
+/// marked down.
+#[derive(Clone, Copy, Debug)]
+pub struct Liveness {
+    pub probe_interval: Duration,
+    pub probe_lifetime: Duration,
+    pub failures_until_down: u32,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(5),
+            probe_lifetime: Duration::from_secs(1),
+            failures_until_down: 3,
+        }
+    }
+}
+
+/// When an unanswered Interest is sent again on another face.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retransmission {
+    /// How long a face may leave an Interest unanswered before it is sent on the next one.
+    pub timeout: Duration,
+    /// Times an Interest is sent again; the last attempt waits for what is left of its lifetime.
+    pub max_retries: u32,
+}
+
+impl Default for Retransmission {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(500),
+            max_retries: 2,
+        }
+    }
+}
+
+/// How far up a caching hierarchy a face reaches. Nearer tiers are tried first.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Tier {
+    LocalCache,
+    Regional,
+    /// Where faces not tagged with a tier are, so tagging a cache puts it ahead of them.
+    #[default]
+    Origin,
+}
+
+impl std::fmt::Display for Tier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LocalCache => "local-cache",
+            Self::Regional => "regional",
+            Self::Origin => "origin",
+        })
+    }
+}
+
+impl std::str::FromStr for Tier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "local-cache" => Ok(Self::LocalCache),
+            "regional" => Ok(Self::Regional),
+            "origin" => Ok(Self::Origin),
+            _ => anyhow::bail!("unknown tier '{s}', expected local-cache, regional or origin"),
+        }
+    }
+}
+
+/// What the faces of a tier were sent and returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TierStats {
+    /// Interests sent to the tier's faces, counting each retransmission.
+    pub sent: u64,
+    /// Those answered with Data.
+    pub hits: u64,
+}
+
+/// How Interests are spread over the faces that are up, by the weight of each. Faces without one
+/// weigh 1, so without any weights Interests go round-robin.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadBalance {
+    pub weights: BTreeMap<SocketAddr, u32>,
+}
+
+impl LoadBalance {
+    pub fn weight(&self, addr: SocketAddr) -> u32 {
+        self.weights.get(&addr).copied().unwrap_or(1)
+    }
+}
+
+/// Smooth weighted round-robin over faces by their index in the table.
+struct Balancer {
+    weights: Vec<i64>,
+    /// Each face's standing; the one with the most is picked next.
+    current: Vec<i64>,
+    /// Interests each face was picked for first.
+    picked: Vec<u64>,
+}
+
+impl Balancer {
+    fn new(balance: &LoadBalance, faces: &[Face]) -> Self {
+        Self {
+            weights: faces
+                .iter()
+                .map(|face| balance.weight(face.addr).into())
+                .collect(),
+            current: vec![0; faces.len()],
+            picked: vec![0; faces.len()],
+        }
+    }
+
+    /// Pick one of the faces at indices `up`: each gains its weight, and the one standing highest
+    /// gives back the total of their weights.
+    fn pick(&mut self, up: &[usize]) -> Option<usize> {
+        let total: i64 = up.iter().map(|&i| self.weights[i]).sum();
+        for &i in up {
+            self.current[i] += self.weights[i];
+        }
+        // The first of equals, so ties go to the more preferred face
+        let picked = up.iter().copied().rev().max_by_key(|&i| self.current[i])?;
+        self.current[picked] -= total;
+        self.picked[picked] += 1;
+        Some(picked)
+    }
+}
+
+/// What [`FaceTable::express`] does with a Nack from a face.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NackPolicy {
+    /// Hand it to the consumer straight away.
+    #[default]
+    Propagate,
+    /// Try the other faces first, and hand over the least severe Nack, as NFD picks it, only if
+    /// none of them returns Data.
+    Wait,
+    /// Try the other faces first, and fail as if the Interest timed out if none of them returns
+    /// Data, so the consumer never sees a Nack.
+    Suppress,
+}
+
+impl std::fmt::Display for NackPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Propagate => "propagate",
+            Self::Wait => "wait",
+            Self::Suppress => "suppress",
+        })
+    }
+}
+
+impl std::str::FromStr for NackPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "propagate" => Ok(Self::Propagate),
+            "wait" => Ok(Self::Wait),
+            "suppress" => Ok(Self::Suppress),
+            _ => anyhow::bail!("unknown Nack policy '{s}', expected propagate, wait or suppress"),
+        }
+    }
+}
+
+/// The Nack to go by out of those got from several faces: the least severe, with Congestion
+/// below Duplicate below NoRoute, which is also the order of their codes.
+fn least_severe(
+    nacked: Option<(SocketAddr, u8)>,
+    addr: SocketAddr,
+    reason: u8,
+) -> Option<(SocketAddr, u8)> {
+    match nacked {
+        Some((_, least)) if least <= reason => nacked,
+        _ => Some((addr, reason)),
+    }
+}
+
+struct Face {
+    addr: SocketAddr,
+    tier: Mutex<Tier>,
+    health: Mutex<Health>,
+    pacer: Mutex<Pacer>,
+    /// Nacks received, by reason.
+    nacks: Mutex<BTreeMap<u8, u64>>,
+    /// Smoothed time to a reply, once one has come back.
+    rtt: Mutex<Option<Duration>>,
+}
+
+impl Face {
+    /// Fold the time a reply took into the smoothed RTT, weighting it by 1/8 as TCP does.
+    fn sample_rtt(&self, rtt: Duration) {
+        let mut smoothed = self.rtt.lock().unwrap();
+        *smoothed = Some(smoothed.map_or(rtt, |smoothed| (smoothed * 7 + rtt) / 8));
+    }
+
+    fn count(&self, reply: &Reply) {
+        if let Reply::Nack(reason) = reply {
+            *self.nacks.lock().unwrap().entry(*reason).or_default() += 1;
+        }
+    }
+}
+
+/// Keeps an Interest in its face's pacing queue while it waits for its turn.
+struct Queued<'a>(&'a Mutex<Pacer>);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().dequeue();
+    }
+}
+
+struct Health {
+    state: FaceState,
+    failures: u32,
+}
+
+/// How Interests reach a face: each one sent to the face's address and answered with Data, a
+/// Nack, or an error once it times out or cannot be sent.
+pub trait Transport: Send + Sync + 'static {
+    fn attempt(
+        &self,
+        addr: SocketAddr,
+        interest: &Interest,
+    ) -> impl Future<Output = anyhow::Result<Reply>> + Send;
+
+    /// Like [`Self::attempt`], also telling whether the reply carried an NDNLPv2 CongestionMark.
+    /// Transports that cannot tell report none.
+    fn attempt_marked(
+        &self,
+        addr: SocketAddr,
+        interest: &Interest,
+    ) -> impl Future<Output = anyhow::Result<(Reply, bool)>> + Send {
+        async move { Ok((self.attempt(addr, interest).await?, false)) }
+    }
+}
+
+/// Expresses each Interest on a UDP socket of its own, so probes and Interests in flight at the
+/// same time do not read each other's replies.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdpTransport;
+
+impl Transport for UdpTransport {
+    async fn attempt(&self, addr: SocketAddr, interest: &Interest) -> anyhow::Result<Reply> {
+        Ok(self.attempt_marked(addr, interest).await?.0)
+    }
+
+    async fn attempt_marked(
+        &self,
+        addr: SocketAddr,
+        interest: &Interest,
+    ) -> anyhow::Result<(Reply, bool)> {
+        let consumer = Consumer::connect(addr)
+            .await
+            .with_context(|| format!("failed to connect to {addr}"))?;
+        let reply = consumer.express(interest).await?;
+        Ok((reply, consumer.congestion_marks() > 0))
+    }
+}
+
+/// Nexthops in order of preference. Faces start out up.
+pub struct FaceTable<T = UdpTransport> {
+    faces: Vec<Face>,
+    liveness: Liveness,
+    sequence: AtomicU64,
+    events: broadcast::Sender<FaceEvent>,
+    retransmission: Mutex<Option<Retransmission>>,
+    retransmissions: AtomicU64,
+    pacing: Mutex<Option<Pacing>>,
+    nack_policy: Mutex<NackPolicy>,
+    deadline_aware: Mutex<bool>,
+    balancer: Mutex<Option<Balancer>>,
+    tier_stats: Mutex<BTreeMap<Tier, TierStats>>,
+    too_late: AtomicU64,
+    token: Mutex<Option<Vec<u8>>>,
+    warnings: Warnings,
+    transport: T,
+}
+
+impl FaceTable {
+    pub fn new(addrs: &[SocketAddr], liveness: Liveness) -> Arc<Self> {
+        Self::with_transport(addrs, liveness, UdpTransport)
+    }
+}
+
+impl<T: Transport> FaceTable<T> {
+    pub fn with_transport(addrs: &[SocketAddr], liveness: Liveness, transport: T) -> Arc<Self> {
+        let faces = addrs
+            .iter()
+            .map(|&addr| Face {
+                addr,
+                tier: Mutex::new(Tier::default()),
+                health: Mutex::new(Health {
+                    state: FaceState::Up,
+                    failures: 0,
+                }),
+                pacer: Mutex::new(Pacer::new(&Pacing::default(), time::Instant::now())),
+                nacks: Mutex::new(BTreeMap::new()),
+                rtt: Mutex::new(None),
+            })
+            .collect();
+        let (events, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        Arc::new(Self {
+            faces,
+            liveness,
+            sequence: AtomicU64::new(0),
+            events,
+            retransmission: Mutex::new(None),
+            retransmissions: AtomicU64::new(0),
+            pacing: Mutex::new(None),
+            nack_policy: Mutex::new(NackPolicy::default()),
+            deadline_aware: Mutex::new(false),
+            balancer: Mutex::new(None),
+            tier_stats: Mutex::new(BTreeMap::new()),
+            too_late: AtomicU64::new(0),
+            token: Mutex::new(None),
+            warnings: Warnings::default(),
+            transport,
+        })
+    }
+
+    /// Carry `token` as the ApplicationParameters of every Interest without any of its own, e.g.
+    /// an access token for a gated namespace, or with `None` send Interests as they are.
+    pub fn set_token(&self, token: Option<Vec<u8>>) {
+        *self.token.lock().unwrap() = token;
+    }
+
+    /// Retransmit unanswered Interests on another face, or with `None` only fail over once an
+    /// Interest's lifetime runs out on a face.
+    pub fn set_retransmission(&self, retransmission: Option<Retransmission>) {
+        *self.retransmission.lock().unwrap() = retransmission;
+    }
+
+    /// Pace the Interests sent on each face, starting over from `initial_rate`, or with `None`
+    /// send them as they come.
+    pub fn set_pacing(&self, pacing: Option<Pacing>) {
+        let now = time::Instant::now();
+        for face in &self.faces {
+            *face.pacer.lock().unwrap() = Pacer::new(&pacing.unwrap_or_default(), now);
+        }
+        *self.pacing.lock().unwrap() = pacing;
+    }
+
+    /// How each face has been paced, most preferred first.
+    pub fn pacing_stats(&self) -> Vec<(SocketAddr, PacingStats)> {
+        self.faces
+            .iter()
+            .map(|face| (face.addr, face.pacer.lock().unwrap().stats()))
+            .collect()
+    }
+
+    /// Whether a Nack from one face goes to the consumer or the other faces are tried first.
+    pub fn set_nack_policy(&self, policy: NackPolicy) {
+        *self.nack_policy.lock().unwrap() = policy;
+    }
+
+    /// The Nacks each face has returned, by reason, most preferred face first.
+    pub fn nacks(&self) -> Vec<(SocketAddr, BTreeMap<u8, u64>)> {
+        self.faces
+            .iter()
+            .map(|face| (face.addr, face.nacks.lock().unwrap().clone()))
+            .collect()
+    }
+
+    /// Skip faces too slow to answer within what is left of an Interest's lifetime, and Nack it
+    /// if every face is, or with `false` try each face for its whole lifetime.
+    pub fn set_deadline_aware(&self, deadline_aware: bool) {
+        *self.deadline_aware.lock().unwrap() = deadline_aware;
+    }
+
+    /// Spread Interests over the faces that are up by their weights, starting the round over, or
+    /// with `None` send each to the most preferred face that is up.
+    pub fn set_load_balance(&self, balance: Option<LoadBalance>) {
+        *self.balancer.lock().unwrap() =
+            balance.map(|balance| Balancer::new(&balance, &self.faces));
+    }
+
+    /// How many Interests each face was picked for first while load balancing, most preferred
+    /// face first.
+    pub fn load_balanced(&self) -> Vec<(SocketAddr, u64)> {
+        let balancer = self.balancer.lock().unwrap();
+        self.faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| (face.addr, balancer.as_ref().map_or(0, |b| b.picked[i])))
+            .collect()
+    }
+
+    /// Tag the faces in `tiers` with their tier, and the others as [`Tier::Origin`].
+    pub fn set_tiers(&self, tiers: &BTreeMap<SocketAddr, Tier>) {
+        for face in &self.faces {
+            *face.tier.lock().unwrap() = tiers.get(&face.addr).copied().unwrap_or_default();
+        }
+    }
+
+    /// What each tier that was sent Interests returned, nearest first.
+    pub fn tier_stats(&self) -> BTreeMap<Tier, TierStats> {
+        self.tier_stats.lock().unwrap().clone()
+    }
+
+    /// Every face's smoothed RTT, if it has answered yet, most preferred first.
+    pub fn rtts(&self) -> Vec<(SocketAddr, Option<Duration>)> {
+        self.faces
+            .iter()
+            .map(|face| (face.addr, *face.rtt.lock().unwrap()))
+            .collect()
+    }
+
+    /// Interests Nacked as too late for every face so far, without being sent.
+    pub fn too_late(&self) -> u64 {
+        self.too_late.load(Ordering::Relaxed)
+    }
+
+    /// Interests sent again on another face so far.
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FaceEvent> {
+        self.events.subscribe()
+    }
+
+    /// Every face and its current state, most preferred first.
+    pub fn states(&self) -> Vec<(SocketAddr, FaceState)> {
+        self.faces
+            .iter()
+            .map(|face| (face.addr, face.health.lock().unwrap().state))
+            .collect()
+    }
+
+    /// Probe every face each `probe_interval` until the task is aborted.
+    pub fn start_probing(self: &Arc<Self>) -> JoinHandle<()> {
+        let table = self.clone();
+        tokio::spawn(async move {
+            loop {
+                table.probe_all().await;
+                time::sleep(table.liveness.probe_interval).await;
+            }
+        })
+    }
+
+    /// Probe every face once and update their states.
+    pub async fn probe_all(&self) {
+        for face in &self.faces {
+            let answered = self.probe(face).await;
+            self.record_probe(face, answered);
+        }
+    }
+
+    async fn probe(&self, face: &Face) -> bool {
+        let addr = face.addr;
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let interest = Interest::builder()
+            .name(Name::from(KEEPALIVE_PREFIX).append_sequence_num(sequence))
+            .lifetime_ms(self.liveness.probe_lifetime.as_millis() as u64)
+            .build();
+        let sent = time::Instant::now();
+        match self.transport.attempt(addr, &interest).await {
+            Ok(_) => {
+                face.sample_rtt(sent.elapsed());
+                true
+            }
+            Err(e) => {
+                debug!("keepalive probe to {addr} failed: {e:#}");
+                false
+            }
+        }
+    }
+
+    fn record_probe(&self, face: &Face, answered: bool) {
+        let mut health = face.health.lock().unwrap();
+        let state = if answered {
+            health.failures = 0;
+            FaceState::Up
+        } else {
+            health.failures += 1;
+            if health.failures >= self.liveness.failures_until_down {
+                FaceState::Down
+            } else {
+                health.state
+            }
+        };
+        if state == health.state {
+            return;
+        }
+        health.state = state;
+        match state {
+            FaceState::Up => info!("Face {} is up again", face.addr),
+            FaceState::Down => warn!(
+                "Face {} is down after {} unanswered keepalive probes",
+                face.addr, health.failures
+            ),
+        }
+        // Nobody listening is not an error
+        let _ = self.events.send(FaceEvent {
+            addr: face.addr,
+            state,
+        });
+    }
+
+    /// Send `interest` to the most preferred face that is up, or the one load balancing picks,
+    /// failing over to the next on a timeout or error, or on a Nack unless it is to be
+    /// propagated. Returns the reply and the face it came from, or the face it would have gone to
+    /// if it was too late for every one.
+    pub async fn express(&self, interest: &Interest) -> anyhow::Result<(SocketAddr, Reply)> {
+        let token = self.token.lock().unwrap().clone();
+        let with_token;
+        let interest = match token {
+            Some(token) if interest.application_parameters.is_none() => {
+                with_token = Interest {
+                    application_parameters: Some(token),
+                    ..interest.clone()
+                };
+                &with_token
+            }
+            _ => interest,
+        };
+        let up = self.up();
+        // Retransmitting takes another face to retransmit on
+        let retransmission = *self.retransmission.lock().unwrap();
+        if let Some(retransmission) = retransmission.filter(|_| up.len() > 1) {
+            return self.retransmit(interest, &up, retransmission).await;
+        }
+
+        let policy = *self.nack_policy.lock().unwrap();
+        let deadline_aware = *self.deadline_aware.lock().unwrap();
+        let deadline = time::Instant::now() + lifetime(interest);
+        let mut wire = interest.clone();
+        let (mut last_error, mut nacked, mut late) = (None, None, None);
+        for face in up {
+            if deadline_aware {
+                let remaining = deadline.saturating_duration_since(time::Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                if self.too_slow(face, remaining) {
+                    late.get_or_insert(face.addr);
+                    continue;
+                }
+                wire.lifetime_ms = Some((remaining.as_millis() as u64).max(1));
+            }
+            match self.send(face, &wire).await {
+                Ok(Reply::Nack(reason)) if policy != NackPolicy::Propagate => {
+                    debug!(
+                        "Interest for '{}' Nacked by {} with reason {reason}",
+                        interest.name, face.addr
+                    );
+                    nacked = least_severe(nacked, face.addr, reason);
+                }
+                Ok(reply) => return Ok((face.addr, reply)),
+                Err(e) => {
+                    self.warnings.warn("express", e.root_cause(), || {
+                        format!(
+                            "Interest for '{}' via {} failed: {e:#}",
+                            interest.name, face.addr
+                        )
+                    });
+                    last_error = Some(e);
+                }
+            }
+        }
+        if let Some(nacked) = nacked {
+            return settle(interest, nacked, policy);
+        }
+        if let (None, Some(addr)) = (&last_error, late) {
+            return Ok(self.nack_too_late(interest, addr));
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no upstream face is up")))
+    }
+
+    /// The faces that are up, nearest tier first and then most preferred first. When load
+    /// balancing, the one picked for this Interest out of the nearest tier goes ahead of the
+    /// others.
+    fn up(&self) -> Vec<&Face> {
+        let mut up: Vec<usize> = (0..self.faces.len())
+            .filter(|&i| self.faces[i].health.lock().unwrap().state == FaceState::Up)
+            .collect();
+        let tier = |i: usize| *self.faces[i].tier.lock().unwrap();
+        up.sort_by_key(|&i| tier(i));
+        if let Some(balancer) = self.balancer.lock().unwrap().as_mut() {
+            let nearest = up.iter().take_while(|&&i| tier(i) == tier(up[0])).count();
+            if let Some(picked) = balancer.pick(&up[..nearest]) {
+                up.retain(|&i| i != picked);
+                up.insert(0, picked);
+            }
+        }
+        up.into_iter().map(|i| &self.faces[i]).collect()
+    }
+
+    /// Count an Interest sent on `face`, and the reply to it if one came, towards its tier.
+    fn count_tier(&self, face: &Face, reply: Option<&Reply>) {
+        let tier = *face.tier.lock().unwrap();
+        let mut stats = self.tier_stats.lock().unwrap();
+        let stats = stats.entry(tier).or_default();
+        stats.sent += 1;
+        stats.hits += matches!(reply, Some(Reply::Data(_))) as u64;
+    }
+
+    /// Whether `face` is expected to take longer than `remaining` to answer, when Interests are
+    /// only sent where they can be answered in time. Faces that have not answered yet are given
+    /// the benefit of the doubt.
+    fn too_slow(&self, face: &Face, remaining: Duration) -> bool {
+        *self.deadline_aware.lock().unwrap()
+            && face.rtt.lock().unwrap().is_some_and(|rtt| rtt > remaining)
+    }
+
+    /// Nack `interest` on behalf of `addr`, the face it would have gone to, as no face could
+    /// answer it in time.
+    fn nack_too_late(&self, interest: &Interest, addr: SocketAddr) -> (SocketAddr, Reply) {
+        debug!(
+            "no face can answer the Interest for '{}' within its lifetime, Nacking it",
+            interest.name
+        );
+        self.too_late.fetch_add(1, Ordering::Relaxed);
+        (addr, Reply::Nack(NackReason::TooLate as u8))
+    }
+
+    /// Send `interest` to the faces in `up` in turn, each time with a fresh nonce, until one
+    /// answers, the retries run out or so does its lifetime. Faces too slow for its lifetime are
+    /// left out of the turn.
+    async fn retransmit(
+        &self,
+        interest: &Interest,
+        up: &[&Face],
+        retransmission: Retransmission,
+    ) -> anyhow::Result<(SocketAddr, Reply)> {
+        let lifetime = lifetime(interest);
+        let deadline = time::Instant::now() + lifetime;
+        let fast: Vec<&Face> = up
+            .iter()
+            .copied()
+            .filter(|face| !self.too_slow(face, lifetime))
+            .collect();
+        let Some(first) = fast.first() else {
+            return Ok(self.nack_too_late(interest, up[0].addr));
+        };
+        let mut wire = interest.clone();
+        let policy = *self.nack_policy.lock().unwrap();
+        let (mut last_error, mut nacked, mut late, mut retries) = (None, None, None, 0);
+        for (attempt, face) in fast
+            .iter()
+            .cycle()
+            .take(retransmission.max_retries as usize + 1)
+            .enumerate()
+        {
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            if self.too_slow(face, remaining) {
+                late.get_or_insert(face.addr);
+                continue;
+            }
+            let wait = if attempt == retransmission.max_retries as usize {
+                remaining
+            } else {
+                remaining.min(retransmission.timeout)
+            };
+            let addr = face.addr;
+            wire.lifetime_ms = Some((wait.as_millis() as u64).max(1));
+            if attempt > 0 {
+                wire.nonce = fresh_nonce(wire.nonce);
+                self.retransmissions.fetch_add(1, Ordering::Relaxed);
+                retries += 1;
+                debug!(
+                    "retransmitting the Interest for '{}' via {addr} ({attempt} of {})",
+                    interest.name, retransmission.max_retries
+                );
+            }
+            match self.send(face, &wire).await {
+                Ok(Reply::Nack(reason)) if policy != NackPolicy::Propagate => {
+                    debug!(
+                        "Interest for '{}' Nacked by {addr} with reason {reason}",
+                        interest.name
+                    );
+                    nacked = least_severe(nacked, addr, reason);
+                }
+                Ok(reply) => return Ok((addr, reply)),
+                Err(e) => {
+                    debug!("Interest for '{}' via {addr} failed: {e:#}", interest.name);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if let Some(nacked) = nacked {
+            return settle(interest, nacked, policy);
+        }
+        if let (None, Some(_)) = (&last_error, late) {
+            return Ok(self.nack_too_late(interest, first.addr));
+        }
+        let e = last_error.unwrap_or_else(|| anyhow::anyhow!("its lifetime ran out"));
+        self.warnings.warn("retransmit", e.root_cause(), || {
+            format!(
+                "Interest for '{}' failed after {retries} retransmissions: {e:#}",
+                interest.name
+            )
+        });
+        Err(e)
+    }
+
+    /// Send `interest` on `face` and count it towards the face's tier.
+    async fn send(&self, face: &Face, interest: &Interest) -> anyhow::Result<Reply> {
+        let reply = self.send_paced(face, interest).await;
+        self.count_tier(face, reply.as_ref().ok());
+        reply
+    }
+
+    /// Send `interest` on `face`, in its turn if Interests are paced. An Interest the face has no
+    /// room to queue is answered with a Congestion Nack.
+    async fn send_paced(&self, face: &Face, interest: &Interest) -> anyhow::Result<Reply> {
+        let Some(pacing) = *self.pacing.lock().unwrap() else {
+            let sent = time::Instant::now();
+            let reply = self.transport.attempt(face.addr, interest).await?;
+            face.sample_rtt(sent.elapsed());
+            face.count(&reply);
+            return Ok(reply);
+        };
+        let now = time::Instant::now();
+        let Some(at) = face.pacer.lock().unwrap().reserve(&pacing, now) else {
+            debug!(
+                "pacing queue to {} is full, Nacking '{}'",
+                face.addr, interest.name
+            );
+            return Ok(Reply::Nack(NackReason::Congestion as u8));
+        };
+        if at > now {
+            let _queued = Queued(&face.pacer);
+            time::sleep_until(at).await;
+        }
+
+        let sent = time::Instant::now();
+        let (reply, marked) = self.transport.attempt_marked(face.addr, interest).await?;
+        face.sample_rtt(sent.elapsed());
+        face.count(&reply);
+        let congested = marked || reply == Reply::Nack(NackReason::Congestion as u8);
+        let data = matches!(reply, Reply::Data(_));
+        let now = time::Instant::now();
+        face.pacer
+            .lock()
+            .unwrap()
+            .replied(&pacing, sent, now, data, congested);
+        Ok(reply)
+    }
+}
+
+fn lifetime(interest: &Interest) -> Duration {
+    interest
+        .lifetime_ms
+        .map_or(DEFAULT_INTEREST_LIFETIME, Duration::from_millis)
+}
+
+/// Answer `interest` with the Nack every face that was tried ended up at, or fail if Nacks are
+/// suppressed.
+fn settle(
+    interest: &Interest,
+    (addr, reason): (SocketAddr, u8),
+    policy: NackPolicy,
+) -> anyhow::Result<(SocketAddr, Reply)> {
+    match policy {
+        NackPolicy::Suppress => anyhow::bail!(
+            "no face returned Data for '{}', suppressed Nack with reason {reason} from {addr}",
+            interest.name
+        ),
+        NackPolicy::Propagate | NackPolicy::Wait => Ok((addr, Reply::Nack(reason))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UdpSocket;
+    use udcn_common::NackReason;
+
+    use super::*;
+    use crate::Producer;
+
+    /// An address nothing listens on, so datagrams sent to it are refused.
+    async fn dead_addr() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_faces_go_down_and_fail_over() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let dead = dead_addr().await;
+        let live = producer.local_addr().unwrap();
+        let liveness = Liveness {
+            probe_lifetime: Duration::from_millis(200),
+            failures_until_down: 2,
+            ..Liveness::default()
+        };
+        let table = FaceTable::new(&[dead, live], liveness);
+        let prefix = Name::from("/test");
+        let mut events = table.subscribe();
+
+        let check = async {
+            let interest = Interest::builder()
+                .name("/test/data")
+                .lifetime_ms(200)
+                .build();
+            // The dead face is still presumed up and fails over
+            let (from, reply) = table.express(&interest).await.unwrap();
+            assert_eq!(from, live);
+            assert_eq!(reply, Reply::Nack(NackReason::NoData as u8));
+
+            table.probe_all().await;
+            assert_eq!(table.states()[0], (dead, FaceState::Up));
+            table.probe_all().await;
+            assert_eq!(
+                table.states(),
+                [(dead, FaceState::Down), (live, FaceState::Up)]
+            );
+            assert_eq!(
+                events.try_recv().unwrap(),
+                FaceEvent {
+                    addr: dead,
+                    state: FaceState::Down,
+                }
+            );
+            assert!(events.try_recv().is_err());
+
+            let (from, _) = table.express(&interest).await.unwrap();
+            assert_eq!(from, live);
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |_| None) => {
+                panic!("producer stopped: {result:?}")
+            }
+            () = check => {}
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Fair scheduling of Interests between the clients of a busy producer.
+//!
+//! With fair scheduling on, [`Producer::serve_filters`](crate::Producer::serve_filters) does not
+//! hand each Interest to its handler as soon as it arrives. Interests wait in a queue per client,
+//! told apart by source address, and a fixed number of workers take them from the clients in
+//! turn, so a client with a hundred Interests pending gets no more turns than one with a single
+//! Interest. Each client may also be capped at a rate: once it has used up its burst, its
+//! Interests wait until the cap allows them, even while workers are idle. Waiting Interests keep
+//! their place in the reply queue, so a client that goes on sending past its cap ends up refused
+//! with Congestion Nacks.
+//!
+//! Past [`MAX_CLIENTS`], clients with nothing pending and their burst restored are forgotten,
+//! stats and all, to make room for new ones.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    pin::pin,
+    sync::Mutex,
+    time::Duration,
+};
+
+use tokio::{
+    sync::Notify,
+    time::{sleep, Instant},
+};
+
+/// Clients kept before idle ones are forgotten.
+pub const MAX_CLIENTS: usize = 4096;
+
+/// How many Interests are handled at once, and how fast each client may have them handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FairLimits {
+    /// Interests handled at once, across every client.
+    pub workers: usize,
+    /// Interests per second each client may have handled, or `None` for no cap.
+    pub client_rate: Option<f64>,
+    /// Interests a client may have handled back to back before its rate cap applies.
+    pub client_burst: u32,
+}
+
+impl Default for FairLimits {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            client_rate: None,
+            client_burst: 16,
+        }
+    }
+}
+
+/// How a client has been served.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    /// Interests waiting for a worker right now.
+    pub pending: usize,
+    /// Interests handed to a handler.
+    pub served: u64,
+    /// Interests that had to wait for the client's rate cap.
+    pub throttled: u64,
+    /// How long the Interests served waited for a worker, in total.
+    pub waited: Duration,
+}
+
+struct Client<J> {
+    pending: VecDeque<(Instant, J)>,
+    tokens: f64,
+    refilled: Instant,
+    /// Whether the Interest at the head of the queue has been held back by the rate cap.
+    throttled: bool,
+    stats: ClientStats,
+}
+
+struct State<J> {
+    limits: Option<FairLimits>,
+    clients: HashMap<SocketAddr, Client<J>>,
+    /// Clients with Interests pending, the one whose turn it is first.
+    turns: VecDeque<SocketAddr>,
+}
+
+impl<J> State<J> {
+    /// Forget the clients with nothing pending whose cap would let a full burst through at `now`,
+    /// as a new client would.
+    fn evict_idle(&mut self, now: Instant) {
+        let (rate, burst) = self.limits.map_or((None, 0.0), |limits| {
+            (limits.client_rate, f64::from(limits.client_burst.max(1)))
+        });
+        self.clients.retain(|_, client| {
+            let refilled = match rate {
+                Some(rate) => {
+                    let elapsed = now.duration_since(client.refilled).as_secs_f64();
+                    client.tokens + elapsed * rate >= burst
+                }
+                None => true,
+            };
+            !client.pending.is_empty() || !refilled
+        });
+    }
+
+    /// The next job, from the first client in turn whose cap allows it, or failing that how long
+    /// until one's does, if any client has Interests pending.
+    fn take(&mut self, now: Instant) -> Result<J, Option<Duration>> {
+        let (rate, burst) = self.limits.map_or((None, 0.0), |limits| {
+            (limits.client_rate, f64::from(limits.client_burst.max(1)))
+        });
+        let mut wait: Option<Duration> = None;
+        for _ in 0..self.turns.len() {
+            let addr = self.turns.pop_front().unwrap();
+            let client = self.clients.get_mut(&addr).unwrap();
+            if let Some(rate) = rate {
+                let elapsed = now.duration_since(client.refilled).as_secs_f64();
+                client.tokens = (client.tokens + elapsed * rate).min(burst);
+                client.refilled = now;
+                if client.tokens < 1.0 {
+                    client.throttled = true;
+                    let until = Duration::from_secs_f64((1.0 - client.tokens) / rate);
+                    wait = Some(wait.map_or(until, |wait| wait.min(until)));
+                    self.turns.push_back(addr);
+                    continue;
+                }
+                client.tokens -= 1.0;
+            }
+            let (queued, job) = client.pending.pop_front().unwrap();
+            client.stats.pending -= 1;
+            client.stats.served += 1;
+            client.stats.waited += now.duration_since(queued);
+            if std::mem::take(&mut client.throttled) {
+                client.stats.throttled += 1;
+            }
+            if !client.pending.is_empty() {
+                self.turns.push_back(addr);
+            }
+            return Ok(job);
+        }
+        Err(wait)
+    }
+}
+
+/// Interests waiting for a worker, by client.
+pub(crate) struct FairScheduler<J> {
+    state: Mutex<State<J>>,
+    pushed: Notify,
+}
+
+impl<J> Default for FairScheduler<J> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                limits: None,
+                clients: HashMap::new(),
+                turns: VecDeque::new(),
+            }),
+            pushed: Notify::new(),
+        }
+    }
+}
+
+impl<J> FairScheduler<J> {
+    pub fn set_limits(&self, limits: Option<FairLimits>) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    pub fn limits(&self) -> Option<FairLimits> {
+        self.state.lock().unwrap().limits
+    }
+
+    /// Every client heard from and not forgotten, in no particular order.
+    pub fn stats(&self) -> Vec<(SocketAddr, ClientStats)> {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter()
+            .map(|(&addr, client)| (addr, client.stats))
+            .collect()
+    }
+
+    /// Queue `job` behind the other Interests from `addr`.
+    pub fn push(&self, addr: SocketAddr, job: J) {
+        let now = Instant::now();
+        let state = &mut *self.state.lock().unwrap();
+        if state.clients.len() >= MAX_CLIENTS && !state.clients.contains_key(&addr) {
+            state.evict_idle(now);
+        }
+        let burst = state
+            .limits
+            .map_or(0.0, |limits| f64::from(limits.client_burst.max(1)));
+        let client = state.clients.entry(addr).or_insert_with(|| Client {
+            pending: VecDeque::new(),
+            tokens: burst,
+            refilled: now,
+            throttled: false,
+            stats: ClientStats::default(),
+        });
+        if client.pending.is_empty() {
+            state.turns.push_back(addr);
+        }
+        client.pending.push_back((now, job));
+        client.stats.pending += 1;
+        self.pushed.notify_one();
+    }
+
+    /// Wait for the next job to hand to a worker. Cancelling this loses no job.
+    pub async fn next(&self) -> J {
+        loop {
+            let mut pushed = pin!(self.pushed.notified());
+            // Register for a push before looking, so one in between is not missed
+            pushed.as_mut().enable();
+            let wait = match self.state.lock().unwrap().take(Instant::now()) {
+                Ok(job) => return job,
+                Err(wait) => wait,
+            };
+            match wait {
+                Some(wait) => tokio::select! {
+                    () = sleep(wait) => {}
+                    () = pushed => {}
+                },
+                None => pushed.await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clients_take_turns_within_their_rate() {
+        let scheduler = FairScheduler::default();
+        scheduler.set_limits(Some(FairLimits::default()));
+        for job in ["a1", "a2", "a3"] {
+            scheduler.push(addr(1), job);
+        }
+        scheduler.push(addr(2), "b1");
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(scheduler.next().await);
+        }
+        assert_eq!(order, ["a1", "b1", "a2", "a3"]);
+
+        // Ten a second with a burst of two: the third Interest waits 100 ms
+        scheduler.set_limits(Some(FairLimits {
+            client_rate: Some(10.0),
+            client_burst: 2,
+            ..FairLimits::default()
+        }));
+        let start = Instant::now();
+        for job in ["c1", "c2", "c3"] {
+            scheduler.push(addr(3), job);
+        }
+        let mut times = Vec::new();
+        for _ in 0..3 {
+            scheduler.next().await;
+            times.push(start.elapsed().as_millis());
+        }
+        assert_eq!(times, [0, 0, 100]);
+
+        let mut stats = scheduler.stats();
+        stats.sort_by_key(|(addr, _)| addr.port());
+        assert_eq!(
+            stats[2].1,
+            ClientStats {
+                pending: 0,
+                served: 3,
+                throttled: 1,
+                waited: Duration::from_millis(100),
+            }
+        );
+        assert_eq!(stats[0].1.served, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_clients_are_forgotten_past_the_limit() {
+        let scheduler = FairScheduler::default();
+        scheduler.set_limits(Some(FairLimits {
+            client_rate: Some(10.0),
+            client_burst: 1,
+            ..FairLimits::default()
+        }));
+        for port in 0..MAX_CLIENTS as u16 {
+            scheduler.push(addr(port), port);
+        }
+        // Every client uses its burst, then client 0 asks again
+        for _ in 0..MAX_CLIENTS {
+            scheduler.next().await;
+        }
+        scheduler.push(addr(0), 0);
+
+        // Nobody's burst is back yet, so nobody is forgotten
+        scheduler.push(addr(u16::MAX), u16::MAX);
+        assert_eq!(scheduler.stats().len(), MAX_CLIENTS + 1);
+
+        // Once it is, only the clients with nothing pending go
+        sleep(Duration::from_millis(100)).await;
+        scheduler.push(addr(u16::MAX - 1), u16::MAX - 1);
+        let mut clients: Vec<u16> = scheduler
+            .stats()
+            .into_iter()
+            .map(|(addr, _)| addr.port())
+            .collect();
+        clients.sort();
+        assert_eq!(clients, [0, u16::MAX - 1, u16::MAX]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Fetching versioned, segmented content such as `serve --version` publishes: names of the form
+//! `<prefix>/v=<version>/seg=<n>`.
+//!
+//! The latest version is found by asking for the prefix with CanBePrefix and MustBeFresh: any
+//! packet under it that is still fresh names the version it belongs to. Producers give their
+//! content a FreshnessPeriod for this, so that once a newer version is out the older one drops
+//! out of answers as it goes stale, in caches too. Segments are then fetched in order until the
+//! producer answers with a NoData Nack.
+
+use anyhow::Context as _;
+use udcn_common::{Data, Interest, NackReason, Name};
+
+use crate::{
+    consumer::Reply,
+    faces::{FaceTable, Transport},
+    poison::PoisonGuard,
+};
+
+/// The versioned name of the latest version under `prefix`, e.g. `/file/v=3` for `/file`.
+pub async fn discover_latest<T: Transport>(
+    faces: &FaceTable<T>,
+    prefix: &Name,
+) -> anyhow::Result<Name> {
+    let interest = Interest::builder()
+        .name(prefix.clone())
+        .can_be_prefix(true)
+        .must_be_fresh(true)
+        .build();
+    let (_, reply) = faces
+        .express(&interest)
+        .await
+        .with_context(|| format!("failed to discover the latest version of '{prefix}'"))?;
+    let data = match reply {
+        Reply::Data(data) => data,
+        Reply::Nack(reason) => {
+            anyhow::bail!("no fresh version of '{prefix}' (Nack reason {reason})")
+        }
+    };
+    data.name
+        .get(prefix.len())
+        .and_then(|component| component.to_version())
+        .map(|version| prefix.clone().append_version(version))
+        .with_context(|| format!("'{}' answered for '{prefix}' is not versioned", data.name))
+}
+
+/// The content of every segment of `name`, from segment 0 until the first the producer has no
+/// Data for.
+pub async fn fetch_segments<T: Transport>(
+    faces: &FaceTable<T>,
+    name: &Name,
+) -> anyhow::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    fetch_segments_from(faces, name, 0, |_, segment| {
+        content.extend_from_slice(segment);
+        Ok(())
+    })
+    .await?;
+    Ok(content)
+}
+
+/// Fetch the segments of `name` from segment `first` on, as a fetch resumed after the ones
+/// before it arrived, handing each one's number and content to `received` in order until the
+/// first the producer has no Data for. Returns the number of segments `name` has.
+pub async fn fetch_segments_from<T: Transport>(
+    faces: &FaceTable<T>,
+    name: &Name,
+    first: u64,
+    received: impl FnMut(u64, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    let mut guard = PoisonGuard::new(None);
+    guard.set_max_refetches(0);
+    fetch_guarded_segments_from(faces, &guard, name, first, |_| Ok(()), received).await
+}
+
+/// Like [`fetch_segments_from`], checking each segment with `validate` through `guard`, so a
+/// poisoned copy is excluded and purged and the segment fetched again.
+pub async fn fetch_guarded_segments_from<T: Transport>(
+    faces: &FaceTable<T>,
+    guard: &PoisonGuard,
+    name: &Name,
+    first: u64,
+    validate: impl Fn(&Data) -> anyhow::Result<()>,
+    mut received: impl FnMut(u64, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    for segment in first.. {
+        let interest = Interest::builder()
+            .name(name.clone().append_segment(segment))
+            .build();
+        match guard.express(faces, &interest, &validate).await? {
+            Reply::Data(data) => received(segment, &data.content)?,
+            Reply::Nack(reason) if reason == NackReason::NoData as u8 && segment > 0 => {
+                return Ok(segment)
+            }
+            Reply::Nack(reason) => {
+                anyhow::bail!("segment {segment} of '{name}' was Nacked with reason {reason}")
+            }
+        }
+    }
+    unreachable!("segment numbers ran out")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{segments::sha256_signer, Liveness, Producer, SegmentStore};
+
+    #[tokio::test]
+    async fn test_discover_and_fetch_latest_version() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let faces = FaceTable::new(&[producer.local_addr().unwrap()], Liveness::default());
+        let prefix = Name::from("/file");
+        let mut store = SegmentStore::new();
+        // The old version is stale already, so only the new one answers MustBeFresh
+        store.publish(
+            &prefix.clone().append_version(1),
+            b"old",
+            2,
+            None,
+            sha256_signer,
+        );
+        store.publish(
+            &prefix.clone().append_version(2),
+            b"hello world",
+            4,
+            Some(60_000),
+            sha256_signer,
+        );
+
+        let check = async {
+            let latest = discover_latest(&faces, &prefix).await.unwrap();
+            assert_eq!(latest, Name::from("/file/v=2"));
+            assert_eq!(
+                fetch_segments(&faces, &latest).await.unwrap(),
+                b"hello world"
+            );
+
+            let e = discover_latest(&faces, &Name::from("/missing"))
+                .await
+                .unwrap_err();
+            assert!(e.to_string().contains("no fresh version"));
+            let unversioned = prefix.clone().append_version(2).append_segment(0);
+            let e = discover_latest(&faces, &unversioned).await.unwrap_err();
+            assert!(e.to_string().contains("is not versioned"));
+        };
+
+        tokio::select! {
+            result = producer.serve_store(&store) => panic!("producer stopped: {result:?}"),
+            () = check => {}
+        }
+    }
+}
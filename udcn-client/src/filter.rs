@@ -0,0 +1,120 @@
+//! Interest filters: routing Interests to the handler registered for their sub-namespace.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use regex::Regex;
+use udcn_common::{Data, Interest, Name};
+
+/// Selects Interests by name: everything under `prefix`, optionally narrowed by a regex that has
+/// to match the URI of the remaining components in full (`/seg=3`, or `/` when nothing remains).
+#[derive(Clone, Debug)]
+pub struct InterestFilter {
+    prefix: Name,
+    regex: Option<Regex>,
+}
+
+impl InterestFilter {
+    pub fn new(prefix: impl Into<Name>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            regex: None,
+        }
+    }
+
+    /// Only match Interests whose components after the prefix match `pattern`.
+    pub fn regex(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.regex = Some(Regex::new(&format!("^(?:{pattern})$"))?);
+        Ok(self)
+    }
+
+    pub fn prefix(&self) -> &Name {
+        &self.prefix
+    }
+
+    pub fn matches(&self, name: &Name) -> bool {
+        if !self.prefix.is_prefix_of(name) {
+            return false;
+        }
+        match &self.regex {
+            Some(regex) => {
+                let mut rest = Name::new();
+                for component in &name.components()[self.prefix.len()..] {
+                    rest.push(component.clone());
+                }
+                regex.is_match(&rest.to_string())
+            }
+            None => true,
+        }
+    }
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Option<Data>> + Send>>;
+pub(crate) type Handler = Arc<dyn Fn(Interest) -> HandlerFuture + Send + Sync>;
+
+/// Filters and the async handlers answering the Interests they match.
+#[derive(Clone, Default)]
+pub struct InterestFilters {
+    filters: Vec<(InterestFilter, Handler)>,
+}
+
+impl InterestFilters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer Interests matching `filter` with the Data `handler` resolves to; `None` is sent as
+    /// a NoData Nack.
+    pub fn register<F, Fut>(&mut self, filter: InterestFilter, handler: F) -> &mut Self
+    where
+        F: Fn(Interest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Data>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |interest| Box::pin(handler(interest)));
+        self.filters.push((filter, handler));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The registered filters, in registration order.
+    pub fn iter(&self) -> impl Iterator<Item = &InterestFilter> {
+        self.filters.iter().map(|(filter, _)| filter)
+    }
+
+    /// The handler for `name`: the matching filter with the longest prefix, and among those the
+    /// one registered first.
+    pub(crate) fn handler(&self, name: &Name) -> Option<&Handler> {
+        let mut best: Option<&(InterestFilter, Handler)> = None;
+        for entry in self
+            .filters
+            .iter()
+            .filter(|(filter, _)| filter.matches(name))
+        {
+            if best.is_none_or(|(filter, _)| entry.0.prefix.len() > filter.prefix.len()) {
+                best = Some(entry);
+            }
+        }
+        best.map(|(_, handler)| handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matches_remaining_components() {
+        let filter = InterestFilter::new("/video").regex(r"/seg=\d+").unwrap();
+        assert!(filter.matches(&Name::from("/video/seg=3")));
+        assert!(!filter.matches(&Name::from("/video/meta")));
+        assert!(!filter.matches(&Name::from("/video/seg=3/extra")));
+        assert!(!filter.matches(&Name::from("/audio/seg=3")));
+
+        let exact = InterestFilter::new("/video").regex("/").unwrap();
+        assert!(exact.matches(&Name::from("/video")));
+        assert!(!exact.matches(&Name::from("/video/seg=3")));
+        assert!(InterestFilter::new("/").matches(&Name::from("/anything")));
+    }
+}
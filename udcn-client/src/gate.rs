@@ -0,0 +1,207 @@
+//! Gated namespaces: prefixes whose Interests are only answered if they carry an access token, to
+//! keep pay-walled or private content from consumers without one.
+//!
+//! Whoever holds a prefix's secret issues tokens for it, each valid until the Unix time it
+//! carries. A token travels in the Interest's ApplicationParameters as that time, 8 bytes
+//! big-endian, followed by the HMAC-SHA256 under the secret of the prefix's Name element and the
+//! time, so a token for one prefix grants nothing under another. Producers and forwarders given
+//! the secret check every Interest under the prefix, the longest gated prefix of a name deciding,
+//! and drop those without a valid token before anything else is done for them. Dropped Interests
+//! are counted for their prefix but not answered, not even with a Nack, so a consumer without a
+//! token learns nothing of what the namespace holds.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use udcn_common::{Interest, Name};
+
+use crate::keychain::hmac_sha256;
+
+/// The length of an encoded token: its expiry and MAC.
+pub const TOKEN_LEN: usize = 8 + 32;
+
+/// An access token for a gated prefix, as carried in ApplicationParameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token(pub Vec<u8>);
+
+impl Token {
+    /// A token for `prefix`, valid until `expires_unix_secs`.
+    pub fn issue(secret: &[u8], prefix: &Name, expires_unix_secs: u64) -> Self {
+        let mut token = expires_unix_secs.to_be_bytes().to_vec();
+        token.extend_from_slice(&mac(secret, prefix, expires_unix_secs));
+        Self(token)
+    }
+
+    /// The Unix time the token expires at, if it is laid out as one.
+    pub fn expires_unix_secs(&self) -> Option<u64> {
+        (self.0.len() == TOKEN_LEN).then(|| u64::from_be_bytes(self.0[..8].try_into().unwrap()))
+    }
+}
+
+impl FromStr for Token {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            s.len() == 2 * TOKEN_LEN,
+            "a token is {TOKEN_LEN} bytes, {} hex digits",
+            2 * TOKEN_LEN
+        );
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .map(Self)
+            .context("a token is written in hex digits")
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+fn mac(secret: &[u8], prefix: &Name, expires_unix_secs: u64) -> [u8; 32] {
+    let mut message = Vec::new();
+    prefix.encode(&mut message);
+    message.extend_from_slice(&expires_unix_secs.to_be_bytes());
+    hmac_sha256(secret, &message)
+}
+
+/// A gated prefix, its secret and the Interests it turned away.
+#[derive(Debug)]
+struct Gate {
+    prefix: Name,
+    secret: Vec<u8>,
+    unauthorized: AtomicU64,
+}
+
+impl Gate {
+    fn admits(&self, token: Option<&[u8]>, now_unix_secs: u64) -> bool {
+        let Some(token) = token.filter(|token| token.len() == TOKEN_LEN) else {
+            return false;
+        };
+        let expires = u64::from_be_bytes(token[..8].try_into().unwrap());
+        let expected = mac(&self.secret, &self.prefix, expires);
+        // Compared in constant time, so the time taken tells nothing of the right MAC
+        let difference = expected
+            .iter()
+            .zip(&token[8..])
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        difference == 0 && now_unix_secs < expires
+    }
+}
+
+/// The gated prefixes a producer or forwarder checks Interests against.
+#[derive(Debug, Default)]
+pub struct Gates {
+    gates: RwLock<Vec<Gate>>,
+}
+
+impl Gates {
+    /// Gate `prefix` with `secret`, replacing its secret if it is gated already.
+    pub fn add(&self, prefix: Name, secret: Vec<u8>) {
+        let mut gates = self.gates.write().unwrap();
+        gates.retain(|gate| gate.prefix != prefix);
+        gates.push(Gate {
+            prefix,
+            secret,
+            unauthorized: AtomicU64::new(0),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gates.read().unwrap().is_empty()
+    }
+
+    /// Whether `interest` may be answered: it is under no gated prefix, or carries a token for
+    /// the longest one it is under. Interests turned away are counted.
+    pub fn admits(&self, interest: &Interest) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.admits_at(interest, now)
+    }
+
+    /// [`Gates::admits`] at `now_unix_secs`.
+    fn admits_at(&self, interest: &Interest, now_unix_secs: u64) -> bool {
+        let gates = self.gates.read().unwrap();
+        let Some(gate) = gates
+            .iter()
+            .filter(|gate| gate.prefix.is_prefix_of(&interest.name))
+            .max_by_key(|gate| gate.prefix.len())
+        else {
+            return true;
+        };
+        let admitted = gate.admits(interest.application_parameters.as_deref(), now_unix_secs);
+        if !admitted {
+            gate.unauthorized.fetch_add(1, Ordering::Relaxed);
+        }
+        admitted
+    }
+
+    /// Each gated prefix and the Interests it turned away, in the order they were gated.
+    pub fn unauthorized(&self) -> Vec<(Name, u64)> {
+        self.gates
+            .read()
+            .unwrap()
+            .iter()
+            .map(|gate| {
+                (
+                    gate.prefix.clone(),
+                    gate.unauthorized.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gates_admit_unexpired_tokens_for_their_prefix() {
+        let gates = Gates::default();
+        gates.add(Name::from("/paid"), b"paid secret".to_vec());
+        gates.add(Name::from("/paid/premium"), b"premium secret".to_vec());
+        let interest = |uri: &str, token: Option<&Token>| {
+            let builder = Interest::builder().name(uri);
+            match token {
+                Some(token) => builder.application_parameters(token.0.clone()),
+                None => builder,
+            }
+            .build()
+        };
+        let paid = Token::issue(b"paid secret", &Name::from("/paid"), 1_000);
+        let premium = Token::issue(b"premium secret", &Name::from("/paid/premium"), 1_000);
+
+        assert!(gates.admits_at(&interest("/free/a", None), 500));
+        assert!(gates.admits_at(&interest("/paid/a/seg=0", Some(&paid)), 500));
+        assert!(!gates.admits_at(&interest("/paid/a/seg=0", None), 500));
+        // Expired, or for another prefix
+        assert!(!gates.admits_at(&interest("/paid/a/seg=0", Some(&paid)), 1_000));
+        assert!(!gates.admits_at(&interest("/paid/premium/a", Some(&paid)), 500));
+        assert!(gates.admits_at(&interest("/paid/premium/a", Some(&premium)), 500));
+        // A token made with another secret
+        let forged = Token::issue(b"guess", &Name::from("/paid"), 1_000);
+        assert!(!gates.admits_at(&interest("/paid/a", Some(&forged)), 500));
+        assert_eq!(
+            gates.unauthorized(),
+            [(Name::from("/paid"), 3), (Name::from("/paid/premium"), 1)]
+        );
+
+        assert_eq!(paid.to_string().parse::<Token>().unwrap(), paid);
+        assert_eq!(paid.expires_unix_secs(), Some(1_000));
+        assert!("00ff".parse::<Token>().is_err());
+    }
+}
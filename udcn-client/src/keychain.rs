@@ -0,0 +1,546 @@
+//! Verifying Data signatures, fetching the certificates of signers not seen before.
+//!
+//! A certificate is a Data whose ContentType is [`CONTENT_TYPE_KEY`], whose content is a key, and
+//! whose KeyLocator names the certificate of the key that signed it. A Data is valid when
+//! following KeyLocators from it reaches a trust anchor, with each signature checking out against
+//! the key above it. Certificates missing along the way are fetched with an Interest for the
+//! KeyLocator name, and kept in the key chain for their FreshnessPeriod, or [`DEFAULT_CERT_TTL`]
+//! without one, so the next Data from the same signer needs no round trip.
+//!
+//! Data that validated is remembered by its implicit digest, with the certificates its chain went
+//! through, up to [`DEFAULT_VERIFY_CACHE_CAPACITY`] packets, oldest forgotten first. The same
+//! packet fetched again skips its signature checks as long as each of those certificates is
+//! still a trust anchor or in the key chain; public-key verification is most of what validating
+//! costs a consumer, and popular content is fetched over and over.
+//!
+//! Which signature types can be checked is up to a [`Verifier`]. The built-in one handles
+//! DigestSha256 and HMAC-SHA256; applications with public-key signatures plug in their own.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use log::debug;
+use sha2::{Digest as _, Sha256};
+use udcn_common::{
+    Data, Interest, Name, CONTENT_TYPE_KEY, SIGNATURE_DIGEST_SHA256, SIGNATURE_HMAC_WITH_SHA256,
+};
+
+use crate::{
+    consumer::{Consumer, Reply},
+    segments::implicit_digest,
+};
+
+/// How long a fetched certificate without a FreshnessPeriod stays in the key chain.
+pub const DEFAULT_CERT_TTL: Duration = Duration::from_secs(3600);
+
+/// Certificates followed from one Data before giving up on reaching a trust anchor.
+pub const DEFAULT_MAX_CHAIN_DEPTH: usize = 8;
+
+/// Validated packets remembered so that fetching them again skips their signature checks.
+pub const DEFAULT_VERIFY_CACHE_CAPACITY: usize = 4096;
+
+/// Checks signatures of the types it knows.
+pub trait Verifier: Send + Sync {
+    /// Whether `signature` over `signed_portion` is valid for `signature_type`. `key` is the
+    /// content of the signer's certificate, or `None` for signatures that need no key.
+    fn verify(
+        &self,
+        signature_type: u64,
+        key: Option<&[u8]>,
+        signed_portion: &[u8],
+        signature: &[u8],
+    ) -> bool;
+}
+
+/// DigestSha256, and HMAC-SHA256 with the certificate's content as the shared key.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256Verifier;
+
+impl Verifier for Sha256Verifier {
+    fn verify(
+        &self,
+        signature_type: u64,
+        key: Option<&[u8]>,
+        signed_portion: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        match (signature_type, key) {
+            (SIGNATURE_DIGEST_SHA256, None) => Sha256::digest(signed_portion)[..] == *signature,
+            (SIGNATURE_HMAC_WITH_SHA256, Some(key)) => {
+                hmac_sha256(key, signed_portion)[..] == *signature
+            }
+            _ => false,
+        }
+    }
+}
+
+/// HMAC (RFC 2104) over SHA-256.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// A signer for [`udcn_common::DataBuilder::sign_with`] making HMAC-SHA256 signatures.
+pub fn hmac_sha256_signer(key: Vec<u8>) -> impl Fn(&[u8]) -> Vec<u8> {
+    move |signed_portion| hmac_sha256(&key, signed_portion).to_vec()
+}
+
+/// A certificate fetched on the way to a trust anchor, and when to fetch it again.
+#[derive(Clone, Debug)]
+struct Cached {
+    certificate: Data,
+    expires: Instant,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VerifyStats {
+    /// Data validated again without checking its signatures.
+    pub hits: u64,
+    /// Data whose signatures were checked.
+    pub misses: u64,
+    /// Validated packets forgotten to make room for newer ones.
+    pub evicted: u64,
+}
+
+/// Validated packets by implicit digest, with the certificates their chains ended in or went
+/// through.
+#[derive(Default)]
+struct Verified {
+    chains: HashMap<[u8; 32], Vec<Name>>,
+    /// Digests, oldest first.
+    order: VecDeque<[u8; 32]>,
+    stats: VerifyStats,
+}
+
+/// Trust anchors, and the certificates fetched and validated against them.
+pub struct KeyChain {
+    anchors: BTreeMap<Name, Data>,
+    cache: Mutex<BTreeMap<Name, Cached>>,
+    verified: Mutex<Verified>,
+    verify_cache_capacity: usize,
+    verifier: Box<dyn Verifier>,
+    default_ttl: Duration,
+    max_depth: usize,
+}
+
+impl Default for KeyChain {
+    fn default() -> Self {
+        Self::new(Sha256Verifier)
+    }
+}
+
+impl KeyChain {
+    pub fn new(verifier: impl Verifier + 'static) -> Self {
+        Self {
+            anchors: BTreeMap::new(),
+            cache: Mutex::new(BTreeMap::new()),
+            verified: Mutex::new(Verified::default()),
+            verify_cache_capacity: DEFAULT_VERIFY_CACHE_CAPACITY,
+            verifier: Box::new(verifier),
+            default_ttl: DEFAULT_CERT_TTL,
+            max_depth: DEFAULT_MAX_CHAIN_DEPTH,
+        }
+    }
+
+    /// Trust `certificate` without checking its signature. Chains end at one of these.
+    pub fn add_anchor(&mut self, certificate: Data) {
+        self.anchors.insert(certificate.name.clone(), certificate);
+    }
+
+    /// How long to keep fetched certificates that have no FreshnessPeriod.
+    pub fn set_default_ttl(&mut self, ttl: Duration) {
+        self.default_ttl = ttl;
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// How many validated packets to remember; 0 checks every packet's signatures.
+    pub fn set_verify_cache_capacity(&mut self, capacity: usize) {
+        self.verify_cache_capacity = capacity;
+    }
+
+    pub fn verify_stats(&self) -> VerifyStats {
+        self.verified.lock().unwrap().stats
+    }
+
+    /// Fetched certificates still in the key chain.
+    pub fn cached(&self) -> Vec<Name> {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, cached| cached.expires > now);
+        cache.keys().cloned().collect()
+    }
+
+    /// A trusted or cached certificate `locator` names: the one called that, or the first one
+    /// under it, as a KeyLocator may name a key rather than one of its certificates.
+    fn find(&self, locator: &Name) -> Option<Data> {
+        let under = |name: &Name| locator.is_prefix_of(name);
+        if let Some((_, anchor)) = self
+            .anchors
+            .range(locator..)
+            .next()
+            .filter(|(n, _)| under(n))
+        {
+            return Some(anchor.clone());
+        }
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, cached| cached.expires > now);
+        cache
+            .range(locator..)
+            .next()
+            .filter(|(name, _)| under(name))
+            .map(|(_, cached)| cached.certificate.clone())
+    }
+
+    fn check(&self, data: &Data, key: Option<&[u8]>) -> anyhow::Result<()> {
+        let signature_type = data
+            .signature_info
+            .as_ref()
+            .map_or(SIGNATURE_DIGEST_SHA256, |info| info.signature_type);
+        anyhow::ensure!(
+            self.verifier.verify(
+                signature_type,
+                key,
+                &data.signed_portion(),
+                &data.signature_value
+            ),
+            "bad signature of type {signature_type} on '{}'",
+            data.name
+        );
+        Ok(())
+    }
+
+    /// Whether `certificate` is a trust anchor or a certificate still in the key chain.
+    fn holds(&self, certificate: &Name, now: Instant) -> bool {
+        self.anchors.contains_key(certificate)
+            || self
+                .cache
+                .lock()
+                .unwrap()
+                .get(certificate)
+                .is_some_and(|cached| cached.expires > now)
+    }
+
+    /// Whether the packet with `digest` validated before, through certificates all still held.
+    fn verified(&self, digest: &[u8; 32]) -> bool {
+        if self.verify_cache_capacity == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        let mut verified = self.verified.lock().unwrap();
+        let hit = verified
+            .chains
+            .get(digest)
+            .is_some_and(|chain| chain.iter().all(|name| self.holds(name, now)));
+        match hit {
+            true => verified.stats.hits += 1,
+            false => verified.stats.misses += 1,
+        }
+        hit
+    }
+
+    /// Remember that the packet with `digest` validated through `chain`.
+    fn remember(&self, digest: [u8; 32], chain: Vec<Name>) {
+        if self.verify_cache_capacity == 0 {
+            return;
+        }
+        let mut verified = self.verified.lock().unwrap();
+        if verified.chains.insert(digest, chain).is_some() {
+            return;
+        }
+        verified.order.push_back(digest);
+        while verified.order.len() > self.verify_cache_capacity {
+            let oldest = verified.order.pop_front().unwrap();
+            verified.chains.remove(&oldest);
+            verified.stats.evicted += 1;
+        }
+    }
+
+    /// Check `data`'s signature, following KeyLocators up to a trust anchor and fetching the
+    /// certificates not in the key chain through `consumer`. Certificates fetched are kept for
+    /// later Data once the whole chain checks out.
+    pub async fn validate(&self, consumer: &Consumer, data: &Data) -> anyhow::Result<()> {
+        let digest = implicit_digest(&data.encode());
+        if self.verified(&digest) {
+            return Ok(());
+        }
+        // Data from the signed one up, each checked with the key of the next
+        let mut chain = vec![data.clone()];
+        let trusted = loop {
+            let current = chain.last().unwrap();
+            let Some(locator) = current.key_locator().cloned() else {
+                anyhow::ensure!(
+                    chain.len() == 1,
+                    "certificate '{}' names no issuer and is not a trust anchor",
+                    current.name
+                );
+                self.check(data, None)?;
+                self.remember(digest, Vec::new());
+                return Ok(());
+            };
+            if let Some(certificate) = self.find(&locator) {
+                break certificate;
+            }
+            anyhow::ensure!(
+                chain.len() <= self.max_depth,
+                "no trust anchor within {} certificates of '{}'",
+                self.max_depth,
+                data.name
+            );
+            let certificate = fetch(consumer, &locator).await?;
+            debug!("fetched certificate '{}'", certificate.name);
+            chain.push(certificate);
+        };
+
+        let mut key = trusted.content;
+        for signed in chain.iter().rev() {
+            self.check(signed, Some(&key))?;
+            key.clone_from(&signed.content);
+        }
+
+        let mut certificates: Vec<Name> = chain.iter().skip(1).map(|c| c.name.clone()).collect();
+        certificates.push(trusted.name);
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        for certificate in chain.into_iter().skip(1) {
+            let ttl = certificate
+                .meta
+                .freshness_period_ms
+                .map_or(self.default_ttl, Duration::from_millis);
+            cache.insert(
+                certificate.name.clone(),
+                Cached {
+                    certificate,
+                    expires: now + ttl,
+                },
+            );
+        }
+        drop(cache);
+        self.remember(digest, certificates);
+        Ok(())
+    }
+}
+
+/// Express an Interest for the certificate `locator` names.
+async fn fetch(consumer: &Consumer, locator: &Name) -> anyhow::Result<Data> {
+    let interest = Interest::builder()
+        .name(locator.clone())
+        .can_be_prefix(true)
+        .must_be_fresh(true)
+        .build();
+    let reply = consumer
+        .express(&interest)
+        .await
+        .with_context(|| format!("failed to fetch certificate '{locator}'"))?;
+    let certificate = match reply {
+        Reply::Data(data) => data,
+        Reply::Nack(reason) => {
+            anyhow::bail!("certificate '{locator}' was Nacked with reason {reason}")
+        }
+    };
+    anyhow::ensure!(
+        certificate.meta.content_type == Some(CONTENT_TYPE_KEY),
+        "'{}' fetched for '{locator}' is not a certificate",
+        certificate.name
+    );
+    Ok(certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::Producer;
+
+    fn certificate(
+        name: &str,
+        key: &[u8],
+        issuer: Option<(&str, &[u8])>,
+        ttl_ms: Option<u64>,
+    ) -> Data {
+        let mut builder = Data::builder()
+            .name(name)
+            .content(key.to_vec())
+            .content_type(CONTENT_TYPE_KEY);
+        if let Some(ttl_ms) = ttl_ms {
+            builder = builder.freshness_period_ms(ttl_ms);
+        }
+        match issuer {
+            Some((issuer, issuer_key)) => builder
+                .signature_info(SIGNATURE_HMAC_WITH_SHA256, Some(Name::from(issuer)))
+                .sign_with(hmac_sha256_signer(issuer_key.to_vec()))
+                .build(),
+            None => builder.build(),
+        }
+    }
+
+    #[test]
+    fn test_hmac_sha256_matches_rfc_4231() {
+        // Test case 2
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(mac[..8], [0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e]);
+        assert_eq!(hmac_sha256(&[0xaa; 131], b"x").len(), 32);
+    }
+
+    #[tokio::test]
+    async fn test_validate_fetches_and_caches_chain() {
+        let root_key = b"root secret";
+        let site_key = b"site secret";
+        let user_key = b"user secret";
+        let root = certificate("/org/KEY/root", root_key, None, None);
+        let site = certificate(
+            "/org/site/KEY/1",
+            site_key,
+            Some(("/org/KEY", root_key)),
+            Some(60_000),
+        );
+        let user = certificate(
+            "/org/site/alice/KEY/1",
+            user_key,
+            Some(("/org/site/KEY/1", site_key)),
+            None,
+        );
+        let signed = |content: &[u8], key: &[u8]| {
+            Data::builder()
+                .name("/org/site/alice/note")
+                .content(content.to_vec())
+                .signature_info(
+                    SIGNATURE_HMAC_WITH_SHA256,
+                    Some(Name::from("/org/site/alice/KEY")),
+                )
+                .sign_with(hmac_sha256_signer(key.to_vec()))
+                .build()
+        };
+
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let prefix = Name::from("/org/site");
+        let fetched = AtomicU32::new(0);
+        let mut keychain = KeyChain::default();
+        keychain.add_anchor(root);
+
+        let check = async {
+            keychain
+                .validate(&consumer, &signed(b"hi", user_key))
+                .await
+                .unwrap();
+            assert_eq!(fetched.load(Ordering::Relaxed), 2);
+            // Without a FreshnessPeriod the user's certificate is cached for the default TTL
+            assert_eq!(
+                keychain.cached(),
+                [
+                    Name::from("/org/site/KEY/1"),
+                    Name::from("/org/site/alice/KEY/1")
+                ]
+            );
+
+            keychain
+                .validate(&consumer, &signed(b"again", user_key))
+                .await
+                .unwrap();
+            assert_eq!(fetched.load(Ordering::Relaxed), 2);
+            let forged = keychain
+                .validate(&consumer, &signed(b"forged", b"guess"))
+                .await;
+            assert!(forged.unwrap_err().to_string().contains("bad signature"));
+
+            // The same packet again is known valid through the same chain
+            keychain
+                .validate(&consumer, &signed(b"hi", user_key))
+                .await
+                .unwrap();
+            assert_eq!(
+                keychain.verify_stats(),
+                VerifyStats {
+                    hits: 1,
+                    misses: 3,
+                    evicted: 0
+                }
+            );
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |interest| {
+                fetched.fetch_add(1, Ordering::Relaxed);
+                [&site, &user]
+                    .into_iter()
+                    .find(|cert| interest.name.is_prefix_of(&cert.name))
+                    .cloned()
+            }) => panic!("producer stopped: {result:?}"),
+            () = check => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_gives_up_without_anchor() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut keychain = KeyChain::default();
+        keychain.set_max_depth(2);
+        keychain.set_verify_cache_capacity(1);
+        let prefix = Name::from("/loop");
+        // Signed with its own key, so following KeyLocators never ends
+        let looping = certificate("/loop/KEY/1", b"k", Some(("/loop/KEY", b"k")), None);
+
+        let check = async {
+            let e = keychain.validate(&consumer, &looping).await.unwrap_err();
+            assert!(e.to_string().contains("no trust anchor within 2"));
+            assert!(keychain.cached().is_empty());
+
+            let digest = |name: &str| {
+                Data::builder()
+                    .name(name)
+                    .sign_with(crate::segments::sha256_signer)
+                    .build()
+            };
+            keychain
+                .validate(&consumer, &digest("/plain"))
+                .await
+                .unwrap();
+            keychain
+                .validate(&consumer, &digest("/plain"))
+                .await
+                .unwrap();
+            keychain
+                .validate(&consumer, &digest("/other"))
+                .await
+                .unwrap();
+            let stats = keychain.verify_stats();
+            assert_eq!((stats.hits, stats.evicted), (1, 1));
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |_| Some(looping.clone())) => {
+                panic!("producer stopped: {result:?}")
+            }
+            () = check => {}
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Client library for applications talking to µDCN: a consumer that expresses Interests, one at a
+//! time or in batches, over monitored and paced upstream faces with failover and retransmission as the strategy chosen for
+//! the prefix says and coalescing concurrent requests for the same name, and fetches the latest version of segmented content,
+//! checking it against a signed manifest if it has one; a producer that answers them, optionally
+//! through several Interest filters or from segments published ahead of time, kept in memory or
+//! read from disk as they are asked for, fragmenting replies larger than a face's MTU, pushing
+//! back on consumers that outpace it and taking clients in turn;
+//! a key chain that validates signatures and fetches the certificates behind them, purging and
+//! refetching cached copies that fail; a client for the daemon's control socket; and a simulated
+//! network to test face failover and Interest aggregation on a virtual clock.
+
+pub mod batch;
+pub mod consumer;
+pub mod control;
+pub mod disk_store;
+pub mod faces;
+pub mod fair;
+pub mod fetch;
+pub mod filter;
+pub mod gate;
+pub mod keychain;
+pub mod manifest;
+pub mod mtu;
+pub mod pacing;
+pub mod poison;
+pub mod producer;
+pub mod queue;
+pub mod registry;
+pub mod segments;
+pub mod sim;
+pub mod strategy;
+pub mod warnings;
+
+pub use batch::Batch;
+pub use consumer::{Consumer, Reply, Trace};
+pub use disk_store::DiskSegmentStore;
+pub use faces::{
+    FaceTable, Liveness, LoadBalance, NackPolicy, Retransmission, Tier, TierStats, Transport,
+    UdpTransport,
+};
+pub use fair::{ClientStats, FairLimits};
+pub use filter::{InterestFilter, InterestFilters};
+pub use gate::{Gates, Token};
+pub use keychain::{KeyChain, VerifyStats};
+pub use manifest::Manifest;
+pub use mtu::{FaceCounters, MtuPolicy};
+pub use pacing::Pacing;
+pub use poison::PoisonGuard;
+pub use producer::Producer;
+pub use queue::{QueueCounters, QueueLimits};
+pub use registry::InterestRegistry;
+pub use segments::{Codec, Compression, CompressionStats, SegmentSize, SegmentStore};
+pub use strategy::{StrategyChoice, StrategyTable};
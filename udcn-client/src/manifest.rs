@@ -0,0 +1,310 @@
+//! Manifests of segmented content, after FLIC: one signature per object instead of per segment.
+//!
+//! With a manifest, the segments of `<name>` are published unsigned and a manifest lists their
+//! implicit digests, in order. A consumer validates the manifest's signature once, then checks
+//! each segment it fetches against its digest. The manifest is segmented itself, as
+//! `<name>/manifest/seg=<k>`, each segment listing up to [`DIGESTS_PER_MANIFEST`] digests. Every
+//! one but the last starts with the digest of the next, so only the first needs a signature.
+//!
+//! Each manifest segment's content is a byte telling whether a next manifest segment follows,
+//! that segment's digest if so, then the digests of the content segments it lists.
+
+use anyhow::Context as _;
+use udcn_common::{Component, Data, Interest, NackReason, Name};
+
+use crate::{
+    consumer::Reply,
+    faces::{FaceTable, Transport},
+    poison::PoisonGuard,
+    segments::{implicit_digest, SegmentStore},
+};
+
+/// The component naming the manifest under the content's name.
+pub const MANIFEST_COMPONENT: &[u8] = b"manifest";
+
+/// Digests listed in one manifest segment: 32 KiB of them, well within one Data.
+pub const DIGESTS_PER_MANIFEST: usize = 1024;
+
+/// The name of the manifest of `name`'s segments, `<name>/manifest`.
+pub fn manifest_name(name: &Name) -> Name {
+    name.clone().append(Component::generic(MANIFEST_COMPONENT))
+}
+
+/// Publish `content` into `store` like [`SegmentStore::publish`], but with the segments unsigned
+/// and a manifest of their digests signed by `signer` instead. Returns the number of segments.
+pub fn publish(
+    store: &mut SegmentStore,
+    prefix: &Name,
+    content: &[u8],
+    segment_size: usize,
+    freshness_period_ms: Option<u64>,
+    signer: impl Fn(&[u8]) -> Vec<u8>,
+) -> usize {
+    let segments = store.publish(prefix, content, segment_size, freshness_period_ms, |_| {
+        Vec::new()
+    });
+    let digests: Vec<[u8; 32]> = (0..segments as u64)
+        .map(|segment| {
+            store
+                .digest(&prefix.clone().append_segment(segment))
+                .expect("segment just published")
+        })
+        .collect();
+
+    // Last to first, each manifest segment taking the digest of the one after it
+    let manifest = manifest_name(prefix);
+    let chunks: Vec<&[[u8; 32]]> = digests.chunks(DIGESTS_PER_MANIFEST).collect();
+    let mut next: Option<[u8; 32]> = None;
+    for (k, chunk) in chunks.iter().enumerate().rev() {
+        let mut listed = Vec::with_capacity(33 + chunk.len() * 32);
+        listed.push(next.is_some() as u8);
+        listed.extend(next.iter().flatten());
+        listed.extend(chunk.iter().flatten());
+        let mut builder = Data::builder()
+            .name(manifest.clone().append_segment(k as u64))
+            .content(listed);
+        if let Some(freshness_period_ms) = freshness_period_ms {
+            builder = builder.freshness_period_ms(freshness_period_ms);
+        }
+        let mut data = builder.build();
+        if k == 0 {
+            data.signature_value = signer(&data.signed_portion());
+        }
+        next = Some(store.insert(&data));
+    }
+    segments
+}
+
+/// The digests of the segments of one object, from its validated manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    name: Name,
+    digests: Vec<[u8; 32]>,
+}
+
+impl Manifest {
+    /// The name whose segments the manifest lists.
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// How many segments the content has.
+    pub fn segments(&self) -> u64 {
+        self.digests.len() as u64
+    }
+
+    /// The implicit digest of `segment`.
+    pub fn digest(&self, segment: u64) -> Option<&[u8; 32]> {
+        self.digests.get(usize::try_from(segment).ok()?)
+    }
+
+    /// Fetch the manifest of `name`'s segments, checking its first segment with `validate` and
+    /// every later one against the digest before it. `None` if the producer has no manifest.
+    pub async fn fetch<T: Transport>(
+        faces: &FaceTable<T>,
+        name: &Name,
+        validate: impl Fn(&Data) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut guard = PoisonGuard::new(None);
+        guard.set_max_refetches(0);
+        Self::fetch_guarded(faces, &guard, name, validate).await
+    }
+
+    /// Like [`Self::fetch`], through `guard`, so a manifest segment that fails its check is
+    /// excluded and purged and fetched again.
+    pub async fn fetch_guarded<T: Transport>(
+        faces: &FaceTable<T>,
+        guard: &PoisonGuard,
+        name: &Name,
+        validate: impl Fn(&Data) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Option<Self>> {
+        let manifest = manifest_name(name);
+        let mut digests = Vec::new();
+        let mut expected: Option<[u8; 32]> = None;
+        for k in 0.. {
+            let segment = manifest.clone().append_segment(k);
+            let interest = Interest::builder().name(segment.clone()).build();
+            let check = |data: &Data| match expected {
+                Some(expected) => {
+                    anyhow::ensure!(
+                        implicit_digest(&data.encode()) == expected,
+                        "'{segment}' does not match the digest listed before it"
+                    );
+                    Ok(())
+                }
+                None => validate(data).with_context(|| format!("'{segment}' is not valid")),
+            };
+            let data = match guard.express(faces, &interest, check).await? {
+                Reply::Data(data) => data,
+                Reply::Nack(reason) if reason == NackReason::NoData as u8 && k == 0 => {
+                    return Ok(None)
+                }
+                Reply::Nack(reason) => {
+                    anyhow::bail!("'{segment}' was Nacked with reason {reason}")
+                }
+            };
+            let (more, listed) = match data.content.split_first() {
+                Some((0, listed)) => (false, listed),
+                Some((1, listed)) if listed.len() >= 32 => {
+                    let (next, listed) = listed.split_at(32);
+                    expected = Some(next.try_into().unwrap());
+                    (true, listed)
+                }
+                _ => anyhow::bail!("'{segment}' is not a manifest"),
+            };
+            anyhow::ensure!(listed.len() % 32 == 0, "'{segment}' is not a manifest");
+            digests.extend(
+                listed
+                    .chunks_exact(32)
+                    .map(|digest| <[u8; 32]>::try_from(digest).unwrap()),
+            );
+            if !more {
+                break;
+            }
+        }
+        Ok(Some(Self {
+            name: name.clone(),
+            digests,
+        }))
+    }
+
+    /// Fetch the segments the manifest lists from segment `first` on, checking each against its
+    /// digest, and hand each one's number and content to `received` in order. Returns the number
+    /// of segments.
+    pub async fn fetch_segments_from<T: Transport>(
+        &self,
+        faces: &FaceTable<T>,
+        first: u64,
+        received: impl FnMut(u64, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<u64> {
+        let mut guard = PoisonGuard::new(None);
+        guard.set_max_refetches(0);
+        self.fetch_guarded_segments_from(faces, &guard, first, received)
+            .await
+    }
+
+    /// Like [`Self::fetch_segments_from`], through `guard`, so a segment that does not match its
+    /// digest is excluded and purged and fetched again.
+    pub async fn fetch_guarded_segments_from<T: Transport>(
+        &self,
+        faces: &FaceTable<T>,
+        guard: &PoisonGuard,
+        first: u64,
+        mut received: impl FnMut(u64, &[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<u64> {
+        for (segment, digest) in (first..).zip(self.digests.iter().skip(first as usize)) {
+            let interest = Interest::builder()
+                .name(self.name.clone().append_segment(segment))
+                .build();
+            let check = |data: &Data| {
+                anyhow::ensure!(
+                    implicit_digest(&data.encode()) == *digest,
+                    "segment {segment} of '{}' does not match its digest in the manifest",
+                    self.name
+                );
+                Ok(())
+            };
+            let data = match guard.express(faces, &interest, check).await? {
+                Reply::Data(data) => data,
+                Reply::Nack(reason) => anyhow::bail!(
+                    "segment {segment} of '{}' was Nacked with reason {reason}",
+                    self.name
+                ),
+            };
+            received(segment, &data.content)?;
+        }
+        Ok(self.segments())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keychain::Sha256Verifier, segments::sha256_signer, Liveness, Producer};
+
+    fn validate(data: &Data) -> anyhow::Result<()> {
+        use crate::keychain::Verifier as _;
+        let signature_type = data
+            .signature_info
+            .as_ref()
+            .map_or(udcn_common::SIGNATURE_DIGEST_SHA256, |info| {
+                info.signature_type
+            });
+        anyhow::ensure!(
+            Sha256Verifier.verify(
+                signature_type,
+                None,
+                &data.signed_portion(),
+                &data.signature_value
+            ),
+            "bad signature"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_segments_check_out_against_a_chained_manifest() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let faces = FaceTable::new(&[producer.local_addr().unwrap()], Liveness::default());
+        let name = Name::from("/file/v=1");
+        // Two manifest segments' worth of digests
+        let content: Vec<u8> = (0..DIGESTS_PER_MANIFEST as u32 + 5)
+            .map(|i| i as u8)
+            .collect();
+        let mut store = SegmentStore::new();
+        let segments = publish(&mut store, &name, &content, 1, Some(60_000), sha256_signer);
+        assert_eq!(segments, content.len());
+        assert_eq!(store.len(), segments + 2);
+        let mut tampered = store.clone();
+        let evil = Data::builder()
+            .name(name.clone().append_segment(3))
+            .content(b"x".to_vec())
+            .build();
+        tampered.insert(&evil);
+
+        let check = async {
+            let manifest = Manifest::fetch(&faces, &name, validate)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(manifest.segments(), content.len() as u64);
+            let mut fetched = Vec::new();
+            manifest
+                .fetch_segments_from(&faces, 0, |_, segment| {
+                    fetched.extend_from_slice(segment);
+                    Ok(())
+                })
+                .await
+                .unwrap();
+            assert_eq!(fetched, content);
+
+            let missing = Manifest::fetch(&faces, &Name::from("/other"), validate).await;
+            assert_eq!(missing.unwrap(), None);
+            manifest
+        };
+        let manifest = tokio::select! {
+            result = producer.serve_store(&store) => panic!("producer stopped: {result:?}"),
+            manifest = check => manifest,
+        };
+
+        let check = async {
+            let e = manifest
+                .fetch_segments_from(&faces, 2, |_, _| Ok(()))
+                .await
+                .unwrap_err();
+            assert!(format!("{e:#}").contains("segment 3"), "{e:#}");
+
+            // A guard fetches the tampered segment again before giving up
+            let guard = PoisonGuard::new(None);
+            manifest
+                .fetch_guarded_segments_from(&faces, &guard, 2, |_, _| Ok(()))
+                .await
+                .unwrap_err();
+            assert_eq!(guard.stats().poisoned, 3);
+        };
+        tokio::select! {
+            result = producer.serve_store(&tampered) => panic!("producer stopped: {result:?}"),
+            () = check => {}
+        }
+    }
+}
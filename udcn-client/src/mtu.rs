@@ -0,0 +1,278 @@
+//! Per-face MTUs for the packets a producer sends.
+//!
+//! A face's MTU is either configured or learnt from the kernel: producer sockets ask for path MTU
+//! discovery with the Don't Fragment bit set, so a datagram too large for the path fails with
+//! EMSGSIZE instead of being fragmented by the kernel or silently dropped further on, and the MTU
+//! the route then reports is remembered for the face. Packets larger than a face's MTU are split
+//! into NDNLPv2 fragments, or dropped if fragmenting is turned off, and counted either way.
+//!
+//! Segments published ahead of time can be sized to fit a datagram whole instead, with
+//! [`packet_limit`] for an MTU and [`smallest_interface_mtu`] when none is configured.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{SocketAddr, UdpSocket as StdUdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use log::{debug, info, warn};
+use tokio::net::UdpSocket;
+use udcn_common::lp;
+
+/// Smallest MTU IPv6 links have to carry, assumed when the kernel cannot tell a path's MTU.
+pub const MIN_MTU: usize = 1280;
+
+/// Largest UDP payload an IPv4 datagram can carry.
+const MAX_UDP_PAYLOAD: usize = 65_507;
+
+/// What to do with a packet larger than its face's MTU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MtuPolicy {
+    /// Split it into NDNLPv2 fragments.
+    #[default]
+    Fragment,
+    /// Drop it.
+    Refuse,
+}
+
+/// A face's MTU, if known, and what happened to packets that exceeded it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaceCounters {
+    pub mtu: Option<usize>,
+    /// Packets sent as NDNLPv2 fragments.
+    pub fragmented_out: u64,
+    /// Packets dropped for exceeding the MTU.
+    pub oversized_dropped: u64,
+}
+
+#[derive(Default)]
+struct State {
+    default_mtu: Option<usize>,
+    policy: MtuPolicy,
+    faces: HashMap<SocketAddr, FaceCounters>,
+}
+
+/// The MTU of every face a producer has sent to, or been told about.
+#[derive(Default)]
+pub(crate) struct FaceMtus {
+    state: Mutex<State>,
+    /// Sequence numbers for NDNLPv2 fragments, shared by all faces.
+    sequence: AtomicU64,
+}
+
+impl FaceMtus {
+    pub fn set_default_mtu(&self, mtu: Option<usize>) {
+        self.state.lock().unwrap().default_mtu = mtu;
+    }
+
+    pub fn set_face_mtu(&self, addr: SocketAddr, mtu: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .faces
+            .entry(addr)
+            .or_default()
+            .mtu = Some(mtu);
+    }
+
+    pub fn set_policy(&self, policy: MtuPolicy) {
+        self.state.lock().unwrap().policy = policy;
+    }
+
+    /// Every face with a known MTU or oversized packets, in no particular order.
+    pub fn counters(&self) -> Vec<(SocketAddr, FaceCounters)> {
+        let state = self.state.lock().unwrap();
+        state
+            .faces
+            .iter()
+            .map(|(&addr, &counters)| {
+                let mtu = counters.mtu.or(state.default_mtu);
+                (addr, FaceCounters { mtu, ..counters })
+            })
+            .collect()
+    }
+
+    /// Largest datagram `addr` takes, if its MTU is known.
+    fn payload_limit(&self, addr: SocketAddr) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        let mtu = state
+            .faces
+            .get(&addr)
+            .and_then(|face| face.mtu)
+            .or(state.default_mtu)?;
+        Some(mtu.saturating_sub(header_len(addr)))
+    }
+
+    /// Send `packet` to `addr`, fragmenting or refusing it if it exceeds the face's MTU.
+    pub async fn send(
+        &self,
+        socket: &UdpSocket,
+        packet: &[u8],
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        if let Some(limit) = self.payload_limit(addr) {
+            if packet.len() > limit {
+                return self.send_oversized(socket, packet, addr, limit).await;
+            }
+        }
+        match socket.send_to(packet, addr).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => {
+                let mtu = path_mtu(addr).unwrap_or_else(|e| {
+                    warn!("failed to look up the path MTU to {addr}, assuming {MIN_MTU}: {e}");
+                    MIN_MTU
+                });
+                info!("Path MTU to {addr} is {mtu}");
+                self.set_face_mtu(addr, mtu);
+                let limit = mtu.saturating_sub(header_len(addr));
+                self.send_oversized(socket, packet, addr, limit).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn send_oversized(
+        &self,
+        socket: &UdpSocket,
+        packet: &[u8],
+        addr: SocketAddr,
+        limit: usize,
+    ) -> io::Result<()> {
+        let fragments = match self.state.lock().unwrap().policy {
+            MtuPolicy::Fragment => {
+                // Reserve a sequence number for each fragment
+                let chunk_size = limit.saturating_sub(lp::LP_FRAGMENT_OVERHEAD).max(1);
+                let count = packet.len().div_ceil(chunk_size) as u64;
+                let sequence = self.sequence.fetch_add(count, Ordering::Relaxed);
+                lp::fragment(packet, limit, sequence)
+            }
+            MtuPolicy::Refuse => None,
+        };
+        let Some(fragments) = fragments else {
+            self.count(addr, |face| face.oversized_dropped += 1);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}-byte packet exceeds the {limit}-byte limit of {addr}",
+                    packet.len()
+                ),
+            ));
+        };
+        debug!(
+            "sending {}-byte packet to {addr} as {} fragments",
+            packet.len(),
+            fragments.len()
+        );
+        for fragment in &fragments {
+            socket.send_to(fragment, addr).await?;
+        }
+        self.count(addr, |face| face.fragmented_out += 1);
+        Ok(())
+    }
+
+    fn count(&self, addr: SocketAddr, update: impl FnOnce(&mut FaceCounters)) {
+        update(self.state.lock().unwrap().faces.entry(addr).or_default());
+    }
+}
+
+/// The largest packet a producer can send over a link with an `mtu`-byte MTU without fragmenting
+/// it: what is left after the IP and UDP headers, IPv6's as they are the larger, and the LpPacket
+/// a traced or congestion-marked reply is wrapped in. Link MTUs count from the IP header, so the
+/// Ethernet header is already outside them.
+pub fn packet_limit(mtu: usize) -> usize {
+    mtu.saturating_sub(40 + 8 + lp::LP_REPLY_OVERHEAD).min(MAX_UDP_PAYLOAD)
+}
+
+/// The smallest MTU of this host's interfaces that are up, leaving out loopback, as replies may
+/// leave through the local forwarder on any of them. `None` if there is no such interface.
+pub fn smallest_interface_mtu() -> io::Result<Option<usize>> {
+    let read = |path: std::path::PathBuf| -> io::Result<String> {
+        Ok(fs::read_to_string(path)?.trim().to_string())
+    };
+    let mut smallest = None;
+    for interface in fs::read_dir("/sys/class/net")? {
+        let path = interface?.path();
+        let flags = read(path.join("flags"))?;
+        let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if flags & libc::IFF_UP as u32 == 0 || flags & libc::IFF_LOOPBACK as u32 != 0 {
+            continue;
+        }
+        let mtu: usize = read(path.join("mtu"))?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        smallest = Some(smallest.map_or(mtu, |smallest: usize| smallest.min(mtu)));
+    }
+    Ok(smallest)
+}
+
+/// IP and UDP header bytes in front of each datagram.
+fn header_len(addr: SocketAddr) -> usize {
+    match addr {
+        SocketAddr::V4(_) => 20 + 8,
+        SocketAddr::V6(_) => 40 + 8,
+    }
+}
+
+/// Have the kernel set Don't Fragment and report datagrams too large for the path, rather than
+/// fragmenting them.
+pub(crate) fn forbid_fragmentation(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    let (level, name, value) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+        SocketAddr::V6(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The MTU the kernel knows for the path to `addr`, which only a connected socket reports.
+fn path_mtu(addr: SocketAddr) -> io::Result<usize> {
+    use std::os::fd::AsRawFd as _;
+
+    let (bind, level, name) = match addr {
+        SocketAddr::V4(_) => ("0.0.0.0:0", libc::IPPROTO_IP, libc::IP_MTU),
+        SocketAddr::V6(_) => ("[::]:0", libc::IPPROTO_IPV6, libc::IPV6_MTU),
+    };
+    let socket = StdUdpSocket::bind(bind)?;
+    socket.connect(addr)?;
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut mtu as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(mtu as usize)
+}
@@ -0,0 +1,151 @@
+//! Pacing of the Interests sent on each upstream face.
+//!
+//! A consumer on a fast link can send Interests far quicker than a slow upstream link returns
+//! Data, filling the queues along the path until they drop or Nack. With pacing on, a
+//! [`FaceTable`](crate::FaceTable) lets each face send Interests no faster than a rate, in bursts
+//! of at most `burst`, and holds the rest back in turn. An Interest that would have to wait behind
+//! `queue` others is answered at once with a Congestion Nack instead.
+//!
+//! The rate adapts to the face. Each Data returned without a CongestionMark raises it by one
+//! Interest a second, up to twice the rate Data has been returning at, so a face is not allowed
+//! ever more Interests while it returns few. A CongestionMark or a Congestion Nack halves it,
+//! once for the Interests sent at the old rate.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// How Interests are paced on each face.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pacing {
+    /// Interests per second a face may send to begin with.
+    pub initial_rate: f64,
+    /// Bounds the rate adapts within.
+    pub min_rate: f64,
+    pub max_rate: f64,
+    /// Interests a face may send back to back.
+    pub burst: u32,
+    /// Interests that may wait for their turn on a face.
+    pub queue: usize,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            initial_rate: 100.0,
+            min_rate: 1.0,
+            max_rate: 10_000.0,
+            burst: 10,
+            queue: 64,
+        }
+    }
+}
+
+/// How a face has been paced.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PacingStats {
+    /// Interests per second the face may send now.
+    pub rate: f64,
+    /// Interests waiting for their turn right now.
+    pub queued: usize,
+    /// Interests that had to wait for their turn.
+    pub delayed: u64,
+    /// Interests Nacked because the queue was full.
+    pub refused: u64,
+    /// Replies that came back with a CongestionMark or as a Congestion Nack.
+    pub congested: u64,
+}
+
+/// How long Data is counted over to tell the rate it returns at.
+const RETURN_WINDOW: Duration = Duration::from_secs(1);
+
+/// The pacing state of one face.
+pub(crate) struct Pacer {
+    /// When the next Interest could go out were there no burst allowance, as in GCRA.
+    next: Instant,
+    /// When the rate was last halved; replies to Interests sent before then do not halve it again.
+    decreased: Option<Instant>,
+    /// Data returned since `window_start`, and the rate it returned at over the last window.
+    returned: u32,
+    window_start: Instant,
+    return_rate: Option<f64>,
+    stats: PacingStats,
+}
+
+impl Pacer {
+    pub fn new(pacing: &Pacing, now: Instant) -> Self {
+        Self {
+            next: now,
+            decreased: None,
+            returned: 0,
+            window_start: now,
+            return_rate: None,
+            stats: PacingStats {
+                rate: pacing.initial_rate,
+                ..PacingStats::default()
+            },
+        }
+    }
+
+    pub fn stats(&self) -> PacingStats {
+        self.stats
+    }
+
+    /// When an Interest arriving `now` may be sent, or `None` if the queue is full. An Interest
+    /// that has to wait is queued until [`Self::dequeue`].
+    pub fn reserve(&mut self, pacing: &Pacing, now: Instant) -> Option<Instant> {
+        let interval = Duration::from_secs_f64(1.0 / self.stats.rate);
+        let tolerance = interval * pacing.burst.max(1).saturating_sub(1);
+        let next = self.next.max(now);
+        let at = next.checked_sub(tolerance).unwrap_or(now).max(now);
+        if at > now {
+            if self.stats.queued >= pacing.queue {
+                self.stats.refused += 1;
+                return None;
+            }
+            self.stats.queued += 1;
+            self.stats.delayed += 1;
+        }
+        self.next = next + interval;
+        Some(at)
+    }
+
+    /// A queued Interest was sent, or given up on.
+    pub fn dequeue(&mut self) {
+        self.stats.queued -= 1;
+    }
+
+    /// A reply to an Interest sent at `sent` came back at `now`, as Data if `data` and with a
+    /// congestion signal if `congested`.
+    pub fn replied(
+        &mut self,
+        pacing: &Pacing,
+        sent: Instant,
+        now: Instant,
+        data: bool,
+        congested: bool,
+    ) {
+        if now.duration_since(self.window_start) >= RETURN_WINDOW {
+            let elapsed = now.duration_since(self.window_start).as_secs_f64();
+            self.return_rate = Some(f64::from(self.returned) / elapsed);
+            (self.returned, self.window_start) = (0, now);
+        }
+        if data {
+            self.returned += 1;
+        }
+        if congested {
+            self.stats.congested += 1;
+            if self.decreased.is_none_or(|decreased| sent > decreased) {
+                self.stats.rate = (self.stats.rate / 2.0).max(pacing.min_rate);
+                self.decreased = Some(now);
+            }
+        } else if data {
+            let ceiling = self
+                .return_rate
+                .map_or(pacing.max_rate, |rate| (2.0 * rate).max(pacing.min_rate));
+            if self.stats.rate < ceiling {
+                self.stats.rate = (self.stats.rate + 1.0).min(ceiling).min(pacing.max_rate);
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Recovering from poisoned caches.
+//!
+//! A forwarder caches whatever Data answers an Interest, so one copy with a bad signature, once
+//! cached, would answer every later Interest for its name. A [`PoisonGuard`] validates each Data
+//! it fetches; when validation fails, it excludes the copy's implicit digest from then on, asks
+//! the local daemon over the control socket to purge the name from its content store, and
+//! expresses the Interest again with a fresh nonce, so that it misses the purged cache and is
+//! answered from further upstream. A copy with an excluded digest is never validated again.
+//!
+//! [`PoisonGuard::fetch`] validates with a [`KeyChain`]; [`PoisonGuard::express`] takes any
+//! check, such as the digests a manifest lists, and expresses through a [`FaceTable`].
+
+use std::{collections::HashSet, path::PathBuf, sync::Mutex};
+
+use log::{debug, warn};
+use udcn_common::{Data, Interest};
+
+use crate::{
+    consumer::{Consumer, Reply},
+    control,
+    faces::{FaceTable, Transport},
+    keychain::KeyChain,
+    registry::fresh_nonce,
+    segments::implicit_digest,
+};
+
+/// Times an Interest is expressed again after the Data answering it turned out poisoned.
+pub const DEFAULT_MAX_REFETCHES: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoisonStats {
+    /// Data that failed validation or carried an excluded digest.
+    pub poisoned: u64,
+    /// Poisoned Data the local daemon purged from its content store.
+    pub poisoned_purged: u64,
+}
+
+#[derive(Default)]
+struct State {
+    excluded: HashSet<[u8; 32]>,
+    stats: PoisonStats,
+}
+
+/// Fetches validated Data, excluding and purging poisoned copies.
+pub struct PoisonGuard {
+    /// The daemon's control socket, or `None` to exclude poisoned copies without purging them.
+    control_socket: Option<PathBuf>,
+    max_refetches: usize,
+    state: Mutex<State>,
+}
+
+impl Default for PoisonGuard {
+    /// Purging through the control socket in effect.
+    fn default() -> Self {
+        Self::new(Some(control::socket_path()))
+    }
+}
+
+impl PoisonGuard {
+    pub fn new(control_socket: Option<PathBuf>) -> Self {
+        Self {
+            control_socket,
+            max_refetches: DEFAULT_MAX_REFETCHES,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn set_max_refetches(&mut self, max_refetches: usize) {
+        self.max_refetches = max_refetches;
+    }
+
+    pub fn stats(&self) -> PoisonStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Whether Data with this implicit digest failed validation before.
+    pub fn is_excluded(&self, digest: &[u8; 32]) -> bool {
+        self.state.lock().unwrap().excluded.contains(digest)
+    }
+
+    /// Express `interest` through `consumer` and validate the Data answering it with `keychain`,
+    /// expressing it again while the Data is poisoned.
+    pub async fn fetch(
+        &self,
+        keychain: &KeyChain,
+        consumer: &Consumer,
+        interest: &Interest,
+    ) -> anyhow::Result<Data> {
+        let mut interest = interest.clone();
+        for attempt in 0..=self.max_refetches {
+            let data = match consumer.express(&interest).await? {
+                Reply::Data(data) => data,
+                Reply::Nack(reason) => {
+                    anyhow::bail!("'{}' was Nacked with reason {reason}", interest.name)
+                }
+            };
+            let digest = implicit_digest(&data.encode());
+            let invalid = if self.is_excluded(&digest) {
+                Err(anyhow::anyhow!("a copy already found poisoned"))
+            } else {
+                keychain.validate(consumer, &data).await
+            };
+            let Err(e) = invalid else {
+                return Ok(data);
+            };
+            self.poisoned(&data, digest, &e).await;
+            if attempt == self.max_refetches {
+                return Err(self.exhausted(&interest, e));
+            }
+            interest.nonce = fresh_nonce(interest.nonce);
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    /// Express `interest` through `faces` and check the Data answering it with `validate`,
+    /// expressing it again while the Data is poisoned. Nacks are handed back as they come.
+    pub async fn express<T: Transport>(
+        &self,
+        faces: &FaceTable<T>,
+        interest: &Interest,
+        validate: impl Fn(&Data) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Reply> {
+        let mut interest = interest.clone();
+        for attempt in 0..=self.max_refetches {
+            let data = match faces.express(&interest).await?.1 {
+                Reply::Data(data) => data,
+                nack => return Ok(nack),
+            };
+            let digest = implicit_digest(&data.encode());
+            let invalid = if self.is_excluded(&digest) {
+                Err(anyhow::anyhow!("a copy already found poisoned"))
+            } else {
+                validate(&data)
+            };
+            let Err(e) = invalid else {
+                return Ok(Reply::Data(data));
+            };
+            self.poisoned(&data, digest, &e).await;
+            if attempt == self.max_refetches {
+                return Err(self.exhausted(&interest, e));
+            }
+            interest.nonce = fresh_nonce(interest.nonce);
+        }
+        unreachable!("the last attempt returns")
+    }
+
+    fn exhausted(&self, interest: &Interest, e: anyhow::Error) -> anyhow::Error {
+        e.context(format!(
+            "every copy of '{}' fetched in {} attempt(s) was poisoned",
+            interest.name,
+            self.max_refetches + 1
+        ))
+    }
+
+    async fn poisoned(&self, data: &Data, digest: [u8; 32], e: &anyhow::Error) {
+        warn!("Data for '{}' is poisoned: {e:#}", data.name);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.excluded.insert(digest);
+            state.stats.poisoned += 1;
+        }
+        self.purge(data).await;
+    }
+
+    async fn purge(&self, data: &Data) {
+        let Some(socket) = &self.control_socket else {
+            return;
+        };
+        match control::request_at(socket, &format!("cs-purge {}", data.name)).await {
+            Ok(response) => {
+                debug!("{}", response.trim_end());
+                // The copy may have come from further upstream, with nothing cached here
+                if purged_count(&response).is_some_and(|purged| purged > 0) {
+                    self.state.lock().unwrap().stats.poisoned_purged += 1;
+                }
+            }
+            // A consumer need not run next to a daemon
+            Err(e) => debug!("could not purge '{}': {e:#}", data.name),
+        }
+    }
+}
+
+/// The number of entries a `cs-purge` response reports purged.
+fn purged_count(response: &str) -> Option<u64> {
+    response
+        .strip_prefix("Purged ")?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::{
+        io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+        net::UnixListener,
+    };
+    use udcn_common::Name;
+
+    use super::*;
+    use crate::{segments::sha256_signer, Producer};
+
+    #[tokio::test]
+    async fn test_poisoned_copy_is_excluded_purged_and_refetched() {
+        let socket = std::env::temp_dir().join(format!("udcn-poison-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let control = UnixListener::bind(&socket).unwrap();
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let guard = PoisonGuard::new(Some(socket.clone()));
+        let keychain = KeyChain::default();
+        let prefix = Name::from("/doc");
+        let served = AtomicU32::new(0);
+        let forged = || {
+            Data::builder()
+                .name("/doc")
+                .content(b"evil".to_vec())
+                .sign_with(|_| vec![0; 32])
+                .build()
+        };
+
+        let daemon = async {
+            let (stream, _) = control.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut request = String::new();
+            stream.read_line(&mut request).await.unwrap();
+            stream
+                .get_mut()
+                .write_all(b"Purged 1 content store entries for '/doc'\n")
+                .await
+                .unwrap();
+            request
+        };
+        let check = async {
+            let interest = Interest::builder().name("/doc").build();
+            let data = guard.fetch(&keychain, &consumer, &interest).await.unwrap();
+            assert_eq!(data.content, b"good");
+            assert_eq!(served.load(Ordering::Relaxed), 2);
+            assert!(guard.is_excluded(&implicit_digest(&forged().encode())));
+            assert_eq!(
+                guard.stats(),
+                PoisonStats {
+                    poisoned: 1,
+                    poisoned_purged: 1,
+                }
+            );
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |_| {
+                Some(match served.fetch_add(1, Ordering::Relaxed) {
+                    0 => forged(),
+                    _ => Data::builder()
+                        .name("/doc")
+                        .content(b"good".to_vec())
+                        .sign_with(sha256_signer)
+                        .build(),
+                })
+            }) => panic!("producer stopped: {result:?}"),
+            (request, ()) = async { tokio::join!(daemon, check) } => {
+                assert_eq!(request, "cs-purge /doc\n");
+            }
+        }
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[test]
+    fn test_purged_count() {
+        assert_eq!(
+            purged_count("Purged 3 content store entries for '/doc'\n"),
+            Some(3)
+        );
+        assert_eq!(
+            purged_count("Purged 0 content store entries for '/doc'\n"),
+            Some(0)
+        );
+        assert_eq!(purged_count("Error: no such name\n"), None);
+    }
+}
@@ -0,0 +1,963 @@
+//! Answering Interests under a prefix, or dispatching them to several [`InterestFilters`].
+
+use std::{
+    borrow::Cow,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use log::debug;
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::Semaphore,
+};
+use udcn_common::{lp, serialize_nack, Data, Interest, NackReason, Name};
+
+use crate::{
+    disk_store::DiskSegmentStore,
+    fair::{ClientStats, FairLimits, FairScheduler},
+    filter::{Handler, InterestFilters},
+    gate::Gates,
+    mtu::{self, FaceCounters, FaceMtus, MtuPolicy},
+    queue::{FaceQueues, Place, QueueCounters, QueueLimits},
+    segments::SegmentStore,
+    warnings::Warnings,
+};
+
+const MAX_PACKET_SIZE: usize = 65_536;
+
+/// A producer listening on a UDP socket. Replies larger than the MTU of the face they go to
+/// are fragmented or dropped, as set with [`Producer::set_mtu_policy`]. While serving, each face
+/// gets a bounded reply queue, as set with [`Producer::set_queue_limits`], and filters may be
+/// served to clients in turn, as set with [`Producer::set_fair_limits`]. Interests under a gated
+/// prefix need a token, as set with [`Producer::add_gate`]. Failures to answer are logged through
+/// [`Warnings`], so that a peer provoking them cannot flood the log.
+pub struct Producer {
+    socket: Arc<UdpSocket>,
+    mtus: Arc<FaceMtus>,
+    queues: Arc<FaceQueues>,
+    fair: FairScheduler<FilterJob>,
+    warnings: Arc<Warnings>,
+    gates: Gates,
+}
+
+impl Producer {
+    pub async fn bind(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        mtu::forbid_fragmentation(&socket)?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            mtus: Arc::default(),
+            queues: Arc::default(),
+            fair: FairScheduler::default(),
+            warnings: Arc::default(),
+            gates: Gates::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// MTU of faces without one of their own. Without it, a face's MTU is learnt from the first
+    /// datagram the kernel finds too large for the path.
+    pub fn set_mtu(&self, mtu: Option<usize>) {
+        self.mtus.set_default_mtu(mtu);
+    }
+
+    /// MTU of the face at `addr`.
+    pub fn set_face_mtu(&self, addr: SocketAddr, mtu: usize) {
+        self.mtus.set_face_mtu(addr, mtu);
+    }
+
+    pub fn set_mtu_policy(&self, policy: MtuPolicy) {
+        self.mtus.set_policy(policy);
+    }
+
+    /// The MTU of each face one is known for or a packet exceeded, and how many packets were
+    /// fragmented or dropped on the way to it.
+    pub fn face_counters(&self) -> Vec<(SocketAddr, FaceCounters)> {
+        self.mtus.counters()
+    }
+
+    /// Only answer Interests under `prefix` that carry a token issued with `secret`, dropping the
+    /// others unanswered.
+    pub fn add_gate(&self, prefix: Name, secret: Vec<u8>) {
+        self.gates.add(prefix, secret);
+    }
+
+    /// Each gated prefix and the Interests under it dropped for want of a valid token.
+    pub fn unauthorized(&self) -> Vec<(Name, u64)> {
+        self.gates.unauthorized()
+    }
+
+    pub fn set_queue_limits(&self, limits: QueueLimits) {
+        self.queues.set_limits(limits);
+    }
+
+    /// The reply queue of each face Interests were served from, and how many replies were
+    /// marked, refused or failed to send because of it.
+    pub fn queue_counters(&self) -> Vec<(SocketAddr, QueueCounters)> {
+        self.queues.counters()
+    }
+
+    /// Serve filters to clients in turn, within these limits, or as Interests arrive with `None`.
+    /// Takes effect the next time [`Producer::serve_filters`] starts.
+    pub fn set_fair_limits(&self, limits: Option<FairLimits>) {
+        self.fair.set_limits(limits);
+    }
+
+    /// Warnings counted rather than logged while serving.
+    pub fn suppressed_warnings(&self) -> u64 {
+        self.warnings.suppressed()
+    }
+
+    /// How each client was served under fair scheduling.
+    pub fn client_stats(&self) -> Vec<(SocketAddr, ClientStats)> {
+        self.fair.stats()
+    }
+
+    /// Wait for the next decodable Interest. Other packets are skipped.
+    pub async fn recv(&self) -> anyhow::Result<(Interest, SocketAddr)> {
+        let (interest, addr, _) = self.recv_traced().await?;
+        Ok((interest, addr))
+    }
+
+    /// Wait for the next decodable Interest, and its trace ID if it was traced.
+    async fn recv_traced(&self) -> anyhow::Result<(Interest, SocketAddr, Option<u64>)> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            if let Some((interest, trace_id)) = self.take_interest(&buf[..len], addr) {
+                return Ok((interest, addr, trace_id));
+            }
+        }
+    }
+
+    /// The Interest in `packet` from `addr` and its trace ID, unless it is not an Interest or the
+    /// gates turn it away.
+    fn take_interest(&self, packet: &[u8], addr: SocketAddr) -> Option<(Interest, Option<u64>)> {
+        let Some((interest, trace_id)) = decode_interest(packet) else {
+            debug!(
+                "ignoring {}-byte non-Interest packet from {addr}",
+                packet.len()
+            );
+            return None;
+        };
+        if !self.gates.admits(&interest) {
+            debug!(
+                "dropping Interest for '{}' from {addr} without a valid token",
+                interest.name
+            );
+            return None;
+        }
+        Some((interest, trace_id))
+    }
+
+    /// Answer `interest` from `addr` with `data`, or with a NoData Nack when there is none.
+    /// Interests taken in with [`Producer::recv`] hold no place in a reply queue.
+    pub async fn reply(
+        &self,
+        interest: &Interest,
+        addr: SocketAddr,
+        data: Option<&Data>,
+    ) -> anyhow::Result<()> {
+        send_reply(&self.socket, &self.mtus, None, interest, addr, data, None).await
+    }
+
+    /// A place in the reply queue of `addr` for `interest`, or `None` once it has been answered
+    /// with a Congestion Nack as the queue is full.
+    async fn admit(
+        &self,
+        interest: &Interest,
+        addr: SocketAddr,
+        trace_id: Option<u64>,
+    ) -> Option<Place> {
+        if let Some(place) = self.queues.admit(addr) {
+            return Some(place);
+        }
+        debug!(
+            "reply queue of {addr} is full, refusing Interest for '{}'",
+            interest.name
+        );
+        let nack = serialize_nack(&interest.header(), NackReason::Congestion);
+        let sent = send_packet(&self.socket, &self.mtus, None, &nack, addr, trace_id);
+        if let Err(e) = sent.await {
+            self.warnings.warn("refuse", e.root_cause(), || {
+                format!(
+                    "failed to refuse Interest for '{}' from {addr}: {e}",
+                    interest.name
+                )
+            });
+        }
+        None
+    }
+
+    /// Answer every Interest under `prefix` with whatever `handler` returns, and Interests
+    /// outside it with a NoData Nack. Replies to traced Interests carry the same trace ID. Runs
+    /// until the socket fails.
+    pub async fn serve<F>(&self, prefix: &Name, mut handler: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&Interest) -> Option<Data>,
+    {
+        loop {
+            let (interest, addr, trace_id) = self.recv_traced().await?;
+            let Some(place) = self.admit(&interest, addr, trace_id).await else {
+                continue;
+            };
+            let data = if prefix.is_prefix_of(&interest.name) {
+                handler(&interest)
+            } else {
+                None
+            };
+            let sent = send_reply(
+                &self.socket,
+                &self.mtus,
+                Some(&place),
+                &interest,
+                addr,
+                data.as_ref(),
+                trace_id,
+            );
+            if let Err(e) = sent.await {
+                self.warnings.warn("answer", e.root_cause(), || {
+                    format!(
+                        "failed to answer Interest for '{}' from {addr}: {e}",
+                        interest.name
+                    )
+                });
+            }
+        }
+    }
+
+    /// Hand every Interest to the handler of the filter it matches, answering those matching no
+    /// filter with a NoData Nack. Handlers run concurrently, so a slow service does not hold up
+    /// the others, and Interests from a face whose reply queue is full are refused rather than
+    /// piling up. With fair limits set, Interests wait for one of a fixed number of workers,
+    /// which take them from each client in turn. Runs until the socket fails.
+    pub async fn serve_filters(&self, filters: InterestFilters) -> anyhow::Result<()> {
+        let fair = self.fair.limits();
+        let workers = Arc::new(Semaphore::new(
+            fair.map_or(0, |limits| limits.workers.max(1)),
+        ));
+        let next_job = || async {
+            let worker = workers.clone().acquire_owned().await.unwrap();
+            (worker, self.fair.next().await)
+        };
+        loop {
+            tokio::select! {
+                received = self.recv_traced() => {
+                    let (interest, addr, trace_id) = received?;
+                    let Some(place) = self.admit(&interest, addr, trace_id).await else {
+                        continue;
+                    };
+                    let job = FilterJob {
+                        handler: filters.handler(&interest.name).cloned(),
+                        interest,
+                        addr,
+                        trace_id,
+                        place,
+                    };
+                    match fair {
+                        Some(_) => self.fair.push(addr, job),
+                        None => {
+                            tokio::spawn(job.answer(
+                                self.socket.clone(),
+                                self.mtus.clone(),
+                                self.warnings.clone(),
+                            ));
+                        }
+                    }
+                }
+                (worker, job) = next_job(), if fair.is_some() => {
+                    let answer =
+                        job.answer(self.socket.clone(), self.mtus.clone(), self.warnings.clone());
+                    tokio::spawn(async move {
+                        answer.await;
+                        drop(worker);
+                    });
+                }
+            }
+        }
+    }
+
+    /// Answer Interests straight from `store`, and names it does not hold with a NoData Nack. An
+    /// Interest that accepts prefix matches gets the leftmost packet under its name. Nothing is
+    /// encoded or signed here: each Interest costs one lookup and one send, plus wrapping the
+    /// packet for a traced Interest. Runs until the socket fails.
+    pub async fn serve_store(&self, store: &SegmentStore) -> anyhow::Result<()> {
+        self.serve_packets(|interest| store.find(interest)).await
+    }
+
+    /// [`Producer::serve_store`] for a store that changes while it is served, e.g. as content is
+    /// republished under new versions. Each packet is copied out of the store before it is sent.
+    pub async fn serve_shared_store(&self, store: &RwLock<SegmentStore>) -> anyhow::Result<()> {
+        self.serve_packets(|interest| {
+            let store = store.read().unwrap();
+            store
+                .find(interest)
+                .map(|packet| Cow::Owned(packet.into_owned()))
+        })
+        .await
+    }
+
+    /// [`Producer::serve_store`] for a store on disk. Each Interest's packet is read and sent by a
+    /// task of its own, so that Interests for different segments are read from the file in
+    /// parallel, as many at a time as the faces' reply queues hold. Runs until the socket fails.
+    pub async fn serve_disk_store(&self, store: Arc<DiskSegmentStore>) -> anyhow::Result<()> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            let Some((interest, trace_id)) = self.take_interest(&buf[..len], addr) else {
+                continue;
+            };
+            let Some(place) = self.admit(&interest, addr, trace_id).await else {
+                continue;
+            };
+            let (store, socket, mtus, warnings) = (
+                store.clone(),
+                self.socket.clone(),
+                self.mtus.clone(),
+                self.warnings.clone(),
+            );
+            tokio::spawn(async move {
+                let packet = match store.find(&interest).await {
+                    Ok(packet) => packet
+                        .unwrap_or_else(|| serialize_nack(&interest.header(), NackReason::NoData)),
+                    Err(e) => {
+                        warnings.warn("read", &e, || {
+                            format!("failed to read the packet for '{}': {e}", interest.name)
+                        });
+                        return;
+                    }
+                };
+                let sent = send_packet(&socket, &mtus, Some(&place), &packet, addr, trace_id);
+                if let Err(e) = sent.await {
+                    warnings.warn("answer", e.root_cause(), || {
+                        format!(
+                            "failed to answer Interest for '{}' from {addr}: {e}",
+                            interest.name
+                        )
+                    });
+                }
+            });
+        }
+    }
+
+    async fn serve_packets<'a, F>(&self, find: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Interest) -> Option<Cow<'a, [u8]>>,
+    {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            let Some((interest, trace_id)) = self.take_interest(&buf[..len], addr) else {
+                continue;
+            };
+            let Some(place) = self.admit(&interest, addr, trace_id).await else {
+                continue;
+            };
+            let packet = find(&interest).unwrap_or_else(|| {
+                Cow::Owned(serialize_nack(&interest.header(), NackReason::NoData))
+            });
+            let sent = send_packet(
+                &self.socket,
+                &self.mtus,
+                Some(&place),
+                &packet,
+                addr,
+                trace_id,
+            );
+            if let Err(e) = sent.await {
+                self.warnings.warn("answer", e.root_cause(), || {
+                    format!(
+                        "failed to answer Interest for '{}' from {addr}: {e}",
+                        interest.name
+                    )
+                });
+            }
+        }
+    }
+}
+
+/// An Interest taken in by [`Producer::serve_filters`], and the handler for it.
+struct FilterJob {
+    interest: Interest,
+    addr: SocketAddr,
+    trace_id: Option<u64>,
+    place: Place,
+    handler: Option<Handler>,
+}
+
+impl FilterJob {
+    async fn answer(self, socket: Arc<UdpSocket>, mtus: Arc<FaceMtus>, warnings: Arc<Warnings>) {
+        let Self {
+            interest,
+            addr,
+            trace_id,
+            place,
+            handler,
+        } = self;
+        let data = match handler {
+            Some(handler) => handler(interest.clone()).await,
+            None => None,
+        };
+        let sent = send_reply(
+            &socket,
+            &mtus,
+            Some(&place),
+            &interest,
+            addr,
+            data.as_ref(),
+            trace_id,
+        );
+        if let Err(e) = sent.await {
+            warnings.warn("answer", e.root_cause(), || {
+                format!(
+                    "failed to answer Interest for '{}' from {addr}: {e}",
+                    interest.name
+                )
+            });
+        }
+    }
+}
+
+/// An Interest, unwrapped from the LpPacket it was traced with if need be.
+fn decode_interest(packet: &[u8]) -> Option<(Interest, Option<u64>)> {
+    match lp::untrace(packet) {
+        Some((trace_id, packet)) => Some((Interest::decode(packet)?, Some(trace_id))),
+        None => Some((Interest::decode(packet)?, None)),
+    }
+}
+
+async fn send_reply(
+    socket: &UdpSocket,
+    mtus: &FaceMtus,
+    place: Option<&Place>,
+    interest: &Interest,
+    addr: SocketAddr,
+    data: Option<&Data>,
+    trace_id: Option<u64>,
+) -> anyhow::Result<()> {
+    let packet = match data {
+        Some(data) => data.encode(),
+        None => serialize_nack(&interest.header(), NackReason::NoData),
+    };
+    send_packet(socket, mtus, place, &packet, addr, trace_id).await
+}
+
+/// Send `packet` to `addr`, wrapped with the trace ID of a traced Interest, or with a
+/// CongestionMark if its face's queue calls for one. Traced replies are never marked, as the
+/// forwarders reporting on them look for the TraceId alone.
+async fn send_packet(
+    socket: &UdpSocket,
+    mtus: &FaceMtus,
+    place: Option<&Place>,
+    packet: &[u8],
+    addr: SocketAddr,
+    trace_id: Option<u64>,
+) -> anyhow::Result<()> {
+    let wrapped;
+    let packet = match trace_id {
+        Some(trace_id) => {
+            wrapped = lp::trace(packet, trace_id);
+            &wrapped
+        }
+        None if place.is_some_and(Place::mark) => {
+            wrapped = lp::mark_congestion(packet);
+            &wrapped
+        }
+        None => packet,
+    };
+    let sent = mtus.send(socket, packet, addr).await;
+    if let (Err(_), Some(place)) = (&sent, place) {
+        place.send_failed();
+    }
+    Ok(sent?)
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{lp::HopReport, Component, TlvType};
+
+    use super::*;
+    use crate::{segments::sha256_signer, Consumer, InterestFilter, Reply};
+
+    #[tokio::test]
+    async fn test_consumer_and_producer_exchange() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap()).await.unwrap();
+        let prefix = Name::from("/test");
+
+        let serve = producer.serve(&prefix, |interest| {
+            (interest.name == Name::from("/test/data"))
+                .then(|| Data::builder().name(interest.name.clone()).content(&b"hi"[..]).build())
+        });
+        let fetch = async {
+            let found = Interest::builder().name("/test/data").lifetime_ms(1000).build();
+            let missing = Interest::builder().name("/test/other").lifetime_ms(1000).build();
+            let outside = Interest::builder().name("/elsewhere").lifetime_ms(1000).build();
+            (
+                consumer.express(&found).await.unwrap(),
+                consumer.express(&missing).await.unwrap(),
+                consumer.express(&outside).await.unwrap(),
+            )
+        };
+
+        tokio::select! {
+            result = serve => panic!("producer stopped: {result:?}"),
+            (found, missing, outside) = fetch => {
+                match found {
+                    Reply::Data(data) => assert_eq!(data.content, b"hi"),
+                    reply => panic!("unexpected {reply:?}"),
+                }
+                assert_eq!(missing, Reply::Nack(NackReason::NoData as u8));
+                assert_eq!(outside, Reply::Nack(NackReason::NoData as u8));
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_filters_dispatch_by_sub_namespace() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let reply = |content: &'static str| {
+            move |interest: Interest| async move {
+                Some(
+                    Data::builder()
+                        .name(interest.name)
+                        .content(content.as_bytes())
+                        .build(),
+                )
+            }
+        };
+        let mut filters = InterestFilters::new();
+        filters
+            .register(InterestFilter::new("/svc"), reply("svc"))
+            .register(
+                InterestFilter::new("/svc/video")
+                    .regex(r"/seg=\d+")
+                    .unwrap(),
+                reply("video"),
+            )
+            .register(InterestFilter::new("/svc/video"), reply("shadowed"))
+            .register(InterestFilter::new("/svc/empty"), |_| async { None });
+
+        let fetch = |name: &'static str| {
+            let consumer = &consumer;
+            async move {
+                let interest = Interest::builder().name(name).lifetime_ms(1000).build();
+                match consumer.express(&interest).await.unwrap() {
+                    Reply::Data(data) => Ok(String::from_utf8(data.content).unwrap()),
+                    Reply::Nack(reason) => Err(reason),
+                }
+            }
+        };
+        let fetches = async {
+            (
+                fetch("/svc/video/seg=1").await,
+                fetch("/svc/video/meta").await,
+                fetch("/svc/other").await,
+                fetch("/svc/empty/x").await,
+                fetch("/elsewhere").await,
+            )
+        };
+
+        tokio::select! {
+            result = producer.serve_filters(filters) => panic!("producer stopped: {result:?}"),
+            (segment, meta, other, empty, outside) = fetches => {
+                assert_eq!(segment, Ok("video".to_string()));
+                assert_eq!(meta, Ok("shadowed".to_string()));
+                assert_eq!(other, Ok("svc".to_string()));
+                assert_eq!(empty, Err(NackReason::NoData as u8));
+                assert_eq!(outside, Err(NackReason::NoData as u8));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_store_answers_from_published_segments() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut store = SegmentStore::new();
+        store.publish(&Name::from("/file"), b"0123456789", 4, None, sha256_signer);
+
+        let fetch = |interest: Interest| {
+            let consumer = &consumer;
+            async move { consumer.express(&interest).await.unwrap() }
+        };
+        let fetches = async {
+            (
+                fetch(
+                    Interest::builder()
+                        .name(Name::from("/file").append_segment(2))
+                        .build(),
+                )
+                .await,
+                fetch(
+                    Interest::builder()
+                        .name("/file")
+                        .can_be_prefix(true)
+                        .build(),
+                )
+                .await,
+                fetch(
+                    Interest::builder()
+                        .name(Name::from("/file").append_segment(3))
+                        .build(),
+                )
+                .await,
+            )
+        };
+
+        tokio::select! {
+            result = producer.serve_store(&store) => panic!("producer stopped: {result:?}"),
+            (last, first, missing) = fetches => {
+                match (last, first) {
+                    (Reply::Data(last), Reply::Data(first)) => {
+                        assert_eq!(last.content, b"89");
+                        assert_eq!(first.content, b"0123");
+                    }
+                    replies => panic!("unexpected {replies:?}"),
+                }
+                assert_eq!(missing, Reply::Nack(NackReason::NoData as u8));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_disk_store_answers_concurrent_interests() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let path = std::env::temp_dir().join(format!("udcn-disk-serve-{}", std::process::id()));
+        let store = DiskSegmentStore::publish(
+            &path,
+            &Name::from("/file"),
+            &b"0123456789"[..],
+            4,
+            None,
+            sha256_signer,
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // A consumer each, as one waits for its replies one Interest at a time
+        let addr = producer.local_addr().unwrap();
+        let fetch = |segment| async move {
+            let consumer = Consumer::connect(addr).await.unwrap();
+            let interest = Interest::builder()
+                .name(Name::from("/file").append_segment(segment))
+                .build();
+            consumer.express(&interest).await.unwrap()
+        };
+        tokio::select! {
+            result = producer.serve_disk_store(Arc::new(store)) => {
+                panic!("producer stopped: {result:?}")
+            }
+            replies = async { tokio::join!(fetch(0), fetch(1), fetch(2), fetch(3)) } => {
+                match replies {
+                    (Reply::Data(first), Reply::Data(second), Reply::Data(last), missing) => {
+                        assert_eq!(first.content, b"0123");
+                        assert_eq!(second.content, b"4567");
+                        assert_eq!(last.content, b"89");
+                        assert_eq!(missing, Reply::Nack(NackReason::NoData as u8));
+                    }
+                    replies => panic!("unexpected {replies:?}"),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_store_answers_exact_packets_by_digest() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut store = SegmentStore::new();
+        store.publish(&Name::from("/file"), b"0123456789", 4, None, sha256_signer);
+        let name = Name::from("/file").append_segment(1);
+        let digest = store.digest(&name).unwrap();
+        let mut wrong = digest;
+        wrong[31] ^= 0xff;
+
+        let fetches = async {
+            let exact = name
+                .clone()
+                .append(Component::implicit_sha256_digest(digest));
+            let other = name
+                .clone()
+                .append(Component::implicit_sha256_digest(wrong));
+            (
+                consumer
+                    .express(&Interest::builder().name(exact).build())
+                    .await
+                    .unwrap(),
+                consumer
+                    .express(&Interest::builder().name(other).build())
+                    .await
+                    .unwrap(),
+            )
+        };
+
+        tokio::select! {
+            result = producer.serve_store(&store) => panic!("producer stopped: {result:?}"),
+            (exact, other) = fetches => {
+                match exact {
+                    Reply::Data(data) => assert_eq!(data.content, b"4567"),
+                    reply => panic!("unexpected {reply:?}"),
+                }
+                assert_eq!(other, Reply::Nack(NackReason::NoData as u8));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_over_the_mtu_are_fragmented_or_dropped() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let consumer = Consumer::connect(producer.local_addr().unwrap())
+            .await
+            .unwrap();
+        producer.set_mtu(Some(1200));
+        let prefix = Name::from("/big");
+        let content = vec![0x5a; 4000];
+
+        let fetch = async {
+            let interest = Interest::builder().name("/big/a").lifetime_ms(500).build();
+            match consumer.express(&interest).await.unwrap() {
+                Reply::Data(data) => assert_eq!(data.content, content),
+                reply => panic!("unexpected {reply:?}"),
+            }
+            let counters = producer.face_counters();
+            assert_eq!(counters.len(), 1);
+            assert_eq!(counters[0].1.mtu, Some(1200));
+            assert_eq!(counters[0].1.fragmented_out, 1);
+
+            producer.set_mtu_policy(MtuPolicy::Refuse);
+            let interest = Interest::builder().name("/big/b").lifetime_ms(200).build();
+            assert!(consumer.express(&interest).await.is_err());
+            assert_eq!(producer.face_counters()[0].1.oversized_dropped, 1);
+
+            // Nacks fit, so they go out whole
+            let interest = Interest::builder().name("/small").lifetime_ms(500).build();
+            assert_eq!(
+                consumer.express(&interest).await.unwrap(),
+                Reply::Nack(NackReason::NoData as u8)
+            );
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |interest| {
+                Some(
+                    Data::builder()
+                        .name(interest.name.clone())
+                        .content(&content[..])
+                        .build(),
+                )
+            }) => panic!("producer stopped: {result:?}"),
+            () = fetch => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_reply_queues_mark_and_refuse() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let producer_addr = producer.local_addr().unwrap();
+        producer.set_queue_limits(QueueLimits {
+            capacity: 2,
+            mark_threshold: 2,
+        });
+        // One socket, so every Interest comes from the same face
+        let consumer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut filters = InterestFilters::new();
+        filters.register(
+            InterestFilter::new("/slow"),
+            |interest: Interest| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                Some(
+                    Data::builder()
+                        .name(interest.name)
+                        .content(&b"s"[..])
+                        .build(),
+                )
+            },
+        );
+
+        let exchange = async {
+            for name in ["/slow/a", "/slow/b", "/slow/c"] {
+                let interest = Interest::builder().name(name).lifetime_ms(1000).build();
+                consumer
+                    .send_to(&interest.encode(), producer_addr)
+                    .await
+                    .unwrap();
+            }
+            let mut buf = vec![0u8; MAX_PACKET_SIZE];
+            let mut reassembler = lp::Reassembler::new();
+            let mut replies = Vec::new();
+            for _ in 0..3 {
+                let len = consumer.recv(&mut buf).await.unwrap();
+                let packet = match lp::is_lp_packet(&buf[..len]) {
+                    true => reassembler.receive(&buf[..len]).unwrap(),
+                    false => buf[..len].to_vec(),
+                };
+                replies.push(packet);
+            }
+            (replies, reassembler.congestion_marks())
+        };
+
+        tokio::select! {
+            result = producer.serve_filters(filters) => panic!("producer stopped: {result:?}"),
+            (replies, marks) = exchange => {
+                // The third Interest is refused at once, while the first two are being served
+                let nack = udcn_common::parse_nack_packet(&replies[0]).unwrap();
+                assert_eq!(nack.name_hash, Name::from("/slow/c").name_hash());
+                assert_eq!(nack.reason, NackReason::Congestion as u8);
+                assert!(replies[1..].iter().all(|packet| Data::decode(packet).is_some()));
+                // Only the first reply leaves with both queued
+                assert_eq!(marks, 1);
+                let counters = producer.queue_counters();
+                assert_eq!(
+                    counters,
+                    [(
+                        consumer.local_addr().unwrap(),
+                        QueueCounters {
+                            queued: 0,
+                            congestion_marked: 1,
+                            dropped: 1,
+                            send_errors: 0,
+                        }
+                    )]
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fair_scheduling_takes_clients_in_turn() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let producer_addr = producer.local_addr().unwrap();
+        producer.set_fair_limits(Some(FairLimits {
+            workers: 1,
+            ..FairLimits::default()
+        }));
+        let greedy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let polite = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut filters = InterestFilters::new();
+        filters.register(
+            InterestFilter::new("/slow"),
+            |interest: Interest| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                Some(Data::builder().name(interest.name).build())
+            },
+        );
+        async fn send(socket: &UdpSocket, to: SocketAddr, name: &str) {
+            let interest = Interest::builder().name(name).lifetime_ms(2000).build();
+            socket.send_to(&interest.encode(), to).await.unwrap();
+        }
+
+        let exchange = async {
+            for name in ["/slow/g1", "/slow/g2", "/slow/g3", "/slow/g4"] {
+                send(&greedy, producer_addr, name).await;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            send(&polite, producer_addr, "/slow/p1").await;
+            let mut buf = vec![0u8; MAX_PACKET_SIZE];
+            let len = polite.recv(&mut buf).await.unwrap();
+            assert_eq!(
+                Data::decode(&buf[..len]).unwrap().name,
+                Name::from("/slow/p1")
+            );
+            let mut answered = 0;
+            while let Ok(Ok(_)) =
+                tokio::time::timeout(std::time::Duration::ZERO, greedy.recv(&mut buf)).await
+            {
+                answered += 1;
+            }
+            answered
+        };
+
+        tokio::select! {
+            result = producer.serve_filters(filters) => panic!("producer stopped: {result:?}"),
+            answered = exchange => {
+                // The polite client waited for one greedy Interest in flight and one more turn,
+                // not for all four
+                assert_eq!(answered, 2);
+                let stats = producer.client_stats();
+                let polite = stats
+                    .iter()
+                    .find(|(addr, _)| *addr == polite.local_addr().unwrap())
+                    .unwrap();
+                assert_eq!((polite.1.served, polite.1.pending), (1, 0));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_traced_interests_collect_hop_reports() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let producer_addr = producer.local_addr().unwrap();
+        // Stands in for a forwarder: reports the hop, then relays both ways
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let prefix = Name::from("/traced");
+
+        let hop = |trace_id, packet_type| HopReport {
+            trace_id,
+            node: "relay".into(),
+            packet_type,
+            name_hash: Name::from("/traced/a").name_hash(),
+            timestamp_ns: 0,
+        };
+        let forward = async {
+            let mut buf = vec![0u8; MAX_PACKET_SIZE];
+            let (len, consumer) = relay.recv_from(&mut buf).await.unwrap();
+            let (trace_id, _) = lp::untrace(&buf[..len]).unwrap();
+            let report = hop(trace_id, TlvType::Interest as u8).encode();
+            relay.send_to(&report, consumer).await.unwrap();
+            relay.send_to(&buf[..len], producer_addr).await.unwrap();
+
+            let (len, _) = relay.recv_from(&mut buf).await.unwrap();
+            assert_eq!(lp::untrace(&buf[..len]).unwrap().0, trace_id);
+            let report = hop(trace_id, TlvType::Data as u8).encode();
+            relay.send_to(&buf[..len], consumer).await.unwrap();
+            relay.send_to(&report, consumer).await.unwrap();
+            std::future::pending::<()>().await
+        };
+        let fetch = async {
+            let interest = Interest::builder()
+                .name("/traced/a")
+                .lifetime_ms(1000)
+                .build();
+            crate::consumer::trace(relay_addr, &interest, 0xabc)
+                .await
+                .unwrap()
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |interest| {
+                Some(Data::builder().name(interest.name.clone()).content(&b"t"[..]).build())
+            }) => panic!("producer stopped: {result:?}"),
+            () = forward => unreachable!(),
+            trace = fetch => {
+                match trace.reply {
+                    Some(Reply::Data(data)) => assert_eq!(data.content, b"t"),
+                    reply => panic!("unexpected {reply:?}"),
+                }
+                // The report sent after the Data still arrives within the linger
+                let hops: Vec<_> = trace.hops.into_iter().map(|(_, hop)| hop).collect();
+                assert_eq!(
+                    hops,
+                    [
+                        hop(0xabc, TlvType::Interest as u8),
+                        hop(0xabc, TlvType::Data as u8)
+                    ]
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Bounded per-face reply queues, so a producer that cannot keep up tells its consumers instead
+//! of buffering without bound.
+//!
+//! Every Interest a producer takes in holds a place in the queue of the face it came from until
+//! its reply is sent. Replies sent while their face has at least `mark_threshold` replies queued
+//! carry an NDNLPv2 CongestionMark, so consumers know to slow down. Once a face has `capacity`
+//! queued, further Interests from it are answered straight away with a Congestion Nack instead of
+//! being handed to the handler. Replies the socket fails to send are counted too.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+/// How many replies a face may have queued, and from how many on they are marked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub capacity: usize,
+    pub mark_threshold: usize,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            mark_threshold: 64,
+        }
+    }
+}
+
+/// A face's queue, and what happened to replies because of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueCounters {
+    /// Replies queued right now.
+    pub queued: usize,
+    /// Replies sent with a CongestionMark.
+    pub congestion_marked: u64,
+    /// Interests answered with a Congestion Nack as the queue was full.
+    pub dropped: u64,
+    /// Replies the socket failed to send.
+    pub send_errors: u64,
+}
+
+#[derive(Default)]
+struct State {
+    limits: QueueLimits,
+    faces: HashMap<SocketAddr, QueueCounters>,
+}
+
+/// The reply queue of every face a producer has heard from.
+#[derive(Default)]
+pub(crate) struct FaceQueues {
+    state: Mutex<State>,
+}
+
+impl FaceQueues {
+    pub fn set_limits(&self, limits: QueueLimits) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    /// Every face heard from, in no particular order.
+    pub fn counters(&self) -> Vec<(SocketAddr, QueueCounters)> {
+        let state = self.state.lock().unwrap();
+        state
+            .faces
+            .iter()
+            .map(|(&addr, &counters)| (addr, counters))
+            .collect()
+    }
+
+    /// A place in the queue of `addr`, or `None` if it is full.
+    pub fn admit(self: &Arc<Self>, addr: SocketAddr) -> Option<Place> {
+        let mut state = self.state.lock().unwrap();
+        let capacity = state.limits.capacity;
+        let face = state.faces.entry(addr).or_default();
+        if face.queued >= capacity {
+            face.dropped += 1;
+            return None;
+        }
+        face.queued += 1;
+        Some(Place {
+            queues: self.clone(),
+            addr,
+        })
+    }
+}
+
+/// An Interest's place in its face's queue, given up once its reply is sent or abandoned.
+pub(crate) struct Place {
+    queues: Arc<FaceQueues>,
+    addr: SocketAddr,
+}
+
+impl Place {
+    /// Whether the reply should carry a CongestionMark, counting it if so.
+    pub fn mark(&self) -> bool {
+        let mut state = self.queues.state.lock().unwrap();
+        let threshold = state.limits.mark_threshold;
+        let face = state.faces.entry(self.addr).or_default();
+        let congested = face.queued >= threshold;
+        if congested {
+            face.congestion_marked += 1;
+        }
+        congested
+    }
+
+    pub fn send_failed(&self) {
+        let mut state = self.queues.state.lock().unwrap();
+        state.faces.entry(self.addr).or_default().send_errors += 1;
+    }
+}
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        let mut state = self.queues.state.lock().unwrap();
+        if let Some(face) = state.faces.get_mut(&self.addr) {
+            face.queued -= 1;
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Outstanding Interests, so concurrent requests for the same name share one wire Interest.
+//!
+//! An [`InterestRegistry`] does for an application what PIT aggregation does for a forwarder: the
+//! first request for a name sends an Interest, and requests for the same name with the same
+//! selectors made before its reply comes back wait for that reply instead of sending their own.
+//! Each name takes at most `max_waiters` waiting requests, so one hot name cannot pile up without
+//! bound.
+//!
+//! Forwarders drop an Interest whose name and nonce they saw within their duplicate window as a
+//! loop, so an Interest sent again with a nonce the registry used for its name within
+//! [`NONCE_WINDOW`] goes out with a fresh nonce instead.
+//!
+//! Like a [`FaceTable`](crate::FaceTable), a registry sends through a [`Transport`], so it can
+//! run against a [`SimNetwork`](crate::sim::SimNetwork) on a virtual clock.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::BuildHasher as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use log::debug;
+use tokio::{sync::watch, time::Instant};
+use udcn_common::{Interest, Name};
+
+use crate::{
+    consumer::Reply,
+    faces::{Transport, UdpTransport},
+};
+
+/// How long a nonce sent for a name is not reused for it, the daemon's default
+/// `--duplicate-window-ms`.
+pub const NONCE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Requests that may wait on one name unless told otherwise.
+pub const DEFAULT_MAX_WAITERS: usize = 64;
+
+/// Interests with the same key are answered by the same Data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    name: Name,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+}
+
+impl Key {
+    fn of(interest: &Interest) -> Self {
+        Self {
+            name: interest.name.clone(),
+            can_be_prefix: interest.can_be_prefix,
+            must_be_fresh: interest.must_be_fresh,
+        }
+    }
+}
+
+/// The reply, or why there is none, once the wire Interest completes.
+type Outcome = Option<Result<Reply, String>>;
+
+struct Outstanding {
+    waiters: usize,
+    outcome: watch::Receiver<Outcome>,
+}
+
+/// How many requests were sent, coalesced or refused.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegistryStats {
+    /// Interests sent on the wire.
+    pub sent: u64,
+    /// Requests that waited for an Interest already outstanding.
+    pub coalesced: u64,
+    /// Requests refused because their name had `max_waiters` waiting already.
+    pub refused: u64,
+    /// Interests sent with a fresh nonce, as theirs was used within [`NONCE_WINDOW`].
+    pub nonces_refreshed: u64,
+}
+
+#[derive(Default)]
+struct State {
+    outstanding: HashMap<Key, Outstanding>,
+    /// (name hash, nonce) pairs sent within the window, oldest first, and the same pairs for
+    /// lookups.
+    recent_nonces: VecDeque<(Instant, u32, u32)>,
+    recent_set: HashSet<(u32, u32)>,
+    stats: RegistryStats,
+}
+
+impl State {
+    /// The nonce to send `interest` with: its own, unless that was sent for the name recently.
+    fn claim_nonce(&mut self, interest: &Interest, now: Instant) -> u32 {
+        while let Some(&(sent, name_hash, nonce)) = self.recent_nonces.front() {
+            if now.duration_since(sent) < NONCE_WINDOW {
+                break;
+            }
+            self.recent_nonces.pop_front();
+            self.recent_set.remove(&(name_hash, nonce));
+        }
+
+        let name_hash = interest.name.name_hash();
+        let mut nonce = interest.nonce;
+        if self.recent_set.contains(&(name_hash, nonce)) {
+            self.stats.nonces_refreshed += 1;
+            while self.recent_set.contains(&(name_hash, nonce)) {
+                nonce = fresh_nonce(nonce);
+            }
+        }
+        self.recent_nonces.push_back((now, name_hash, nonce));
+        self.recent_set.insert((name_hash, nonce));
+        nonce
+    }
+}
+
+/// A nonce other than `previous`, drawn the way `Interest::builder` draws them.
+pub(crate) fn fresh_nonce(previous: u32) -> u32 {
+    std::collections::hash_map::RandomState::new().hash_one(previous) as u32
+}
+
+/// Interests outstanding towards one forwarder or producer.
+pub struct InterestRegistry<T = UdpTransport> {
+    target: SocketAddr,
+    max_waiters: usize,
+    state: Mutex<State>,
+    transport: T,
+}
+
+impl InterestRegistry {
+    pub fn new(target: SocketAddr, max_waiters: usize) -> Arc<Self> {
+        Self::with_transport(target, max_waiters, UdpTransport)
+    }
+}
+
+impl<T: Transport> InterestRegistry<T> {
+    pub fn with_transport(target: SocketAddr, max_waiters: usize, transport: T) -> Arc<Self> {
+        Arc::new(Self {
+            target,
+            max_waiters,
+            state: Mutex::new(State::default()),
+            transport,
+        })
+    }
+
+    pub fn stats(&self) -> RegistryStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Names with an Interest outstanding, and how many requests wait on each.
+    pub fn outstanding(&self) -> Vec<(Name, usize)> {
+        let state = self.state.lock().unwrap();
+        state
+            .outstanding
+            .iter()
+            .map(|(key, outstanding)| (key.name.clone(), outstanding.waiters))
+            .collect()
+    }
+
+    /// Express `interest`, or wait for the reply to an equivalent one already outstanding.
+    pub async fn express(self: &Arc<Self>, interest: &Interest) -> anyhow::Result<Reply> {
+        let key = Key::of(interest);
+        let mut outcome = {
+            let state = &mut *self.state.lock().unwrap();
+            match state.outstanding.get_mut(&key) {
+                Some(outstanding) if outstanding.waiters >= self.max_waiters => {
+                    state.stats.refused += 1;
+                    anyhow::bail!(
+                        "{} requests for '{}' are already waiting",
+                        self.max_waiters,
+                        interest.name
+                    );
+                }
+                Some(outstanding) => {
+                    outstanding.waiters += 1;
+                    let outcome = outstanding.outcome.clone();
+                    state.stats.coalesced += 1;
+                    debug!("waiting for the Interest for '{}' in flight", interest.name);
+                    outcome
+                }
+                None => {
+                    let mut wire = interest.clone();
+                    wire.nonce = state.claim_nonce(interest, Instant::now());
+                    let (sender, outcome) = watch::channel(None);
+                    state.outstanding.insert(
+                        key.clone(),
+                        Outstanding {
+                            waiters: 1,
+                            outcome: outcome.clone(),
+                        },
+                    );
+                    state.stats.sent += 1;
+                    self.send(key.clone(), wire, sender);
+                    outcome
+                }
+            }
+        };
+        let _waiter = Waiter {
+            registry: self,
+            key: &key,
+            outcome: outcome.clone(),
+        };
+
+        let outcome = outcome
+            .wait_for(Option::is_some)
+            .await
+            .context("the Interest was abandoned")?;
+        match outcome.as_ref().unwrap() {
+            Ok(reply) => Ok(reply.clone()),
+            Err(e) => Err(anyhow::anyhow!("{e}")),
+        }
+    }
+
+    /// Send `interest` on a task of its own, so it completes for the other waiters even if the
+    /// request that sent it is dropped.
+    fn send(self: &Arc<Self>, key: Key, interest: Interest, sender: watch::Sender<Outcome>) {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let reply = registry.transport.attempt(registry.target, &interest).await;
+            // Requests from now on send an Interest of their own
+            registry.state.lock().unwrap().outstanding.remove(&key);
+            let _ = sender.send(Some(reply.map_err(|e| format!("{e:#}"))));
+        });
+    }
+}
+
+/// Counts a request out of its name's waiters once it has its reply or is dropped.
+struct Waiter<'a, T> {
+    registry: &'a InterestRegistry<T>,
+    key: &'a Key,
+    outcome: watch::Receiver<Outcome>,
+}
+
+impl<T> Drop for Waiter<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.registry.state.lock().unwrap();
+        // Unless a later Interest for the name has taken its place
+        if let Some(outstanding) = state.outstanding.get_mut(self.key) {
+            if outstanding.outcome.same_channel(&self.outcome) {
+                outstanding.waiters -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use udcn_common::Data;
+
+    use super::*;
+    use crate::Producer;
+
+    #[tokio::test]
+    async fn test_concurrent_interests_share_one_wire_interest() {
+        let producer = Producer::bind("127.0.0.1:0").await.unwrap();
+        let registry = InterestRegistry::new(producer.local_addr().unwrap(), 3);
+        let prefix = Name::from("/test");
+        let served = AtomicU32::new(0);
+
+        let check = async {
+            let interest = Interest::builder()
+                .name("/test/data")
+                .nonce(7)
+                .lifetime_ms(1000)
+                .build();
+            let (a, b, c, d) = tokio::join!(
+                registry.express(&interest),
+                registry.express(&interest),
+                registry.express(&interest),
+                registry.express(&interest),
+            );
+            let replies = [a, b, c, d];
+            let (answered, refused): (Vec<_>, Vec<_>) = replies.iter().partition(|r| r.is_ok());
+            assert_eq!((answered.len(), refused.len()), (3, 1));
+            assert!(answered
+                .iter()
+                .all(|reply| matches!(reply, Ok(Reply::Data(data)) if data.content == b"hi")));
+            assert_eq!(served.load(Ordering::Relaxed), 1);
+            assert!(registry.outstanding().is_empty());
+
+            // Sent again within the window, the same nonce would be dropped as a loop
+            registry.express(&interest).await.unwrap();
+            assert_eq!(served.load(Ordering::Relaxed), 2);
+            assert_eq!(
+                registry.stats(),
+                RegistryStats {
+                    sent: 2,
+                    coalesced: 2,
+                    refused: 1,
+                    nonces_refreshed: 1,
+                }
+            );
+        };
+
+        tokio::select! {
+            result = producer.serve(&prefix, |interest| {
+                served.fetch_add(1, Ordering::Relaxed);
+                Some(Data::builder().name(interest.name.clone()).content(b"hi".to_vec()).build())
+            }) => panic!("producer stopped: {result:?}"),
+            () = check => {}
+        }
+    }
+}
@@ -0,0 +1,849 @@
+//! Content published ahead of time: split into segments, encoded and signed once, then served by
+//! a lookup in an ordered index of full names per Interest.
+//!
+//! Each packet's implicit digest, the SHA-256 of the encoded packet, is computed once at publish
+//! time too. An Interest whose name ends in a `sha256digest` component only gets the packet with
+//! that digest, which lets consumers ask for an exact packet and detect one that was altered.
+//!
+//! Packets stay fresh for their FreshnessPeriod after publishing, and without one are stale
+//! straight away. Stale packets only answer Interests without MustBeFresh. Fresh ones of the
+//! newest version answer version discovery, an Interest for the unversioned name with
+//! CanBePrefix and MustBeFresh.
+//!
+//! A store can be saved to a file and loaded again, to serve the same signed packets after a
+//! restart without signing them again.
+//!
+//! A store can also keep packets above a size compressed with LZ4 or Zstandard, to hold more
+//! content in the same memory, and decompress each one it answers with. Packets that compression
+//! would not make smaller are kept as they are. [`CompressionStats`] tell how much memory that
+//! saves and how much CPU time it costs.
+//!
+//! Segments are best sized so that each one fits a datagram whole, as a fragmented one is lost
+//! with any of its fragments: [`SegmentSize::Auto`] takes the size from the face MTU, less the
+//! headers in front of the Data and the Data's own name, MetaInfo and signature.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt, fs,
+    io::Read as _,
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use sha2::{Digest as _, Sha256};
+use udcn_common::{Component, Data, Interest, Name, MAX_CONTENT_LEN};
+
+use crate::mtu;
+
+/// An encoded Data packet, ready to be sent as it is unless `codec` compressed it, its implicit
+/// digest and when it goes stale.
+#[derive(Clone, Debug)]
+struct Stored {
+    packet: Vec<u8>,
+    codec: Option<Codec>,
+    digest: [u8; 32],
+    fresh_until: Instant,
+}
+
+impl Indexed for Stored {
+    fn digest(&self) -> &[u8; 32] {
+        &self.digest
+    }
+
+    fn fresh_until(&self) -> Instant {
+        self.fresh_until
+    }
+}
+
+/// What looking up a packet needs to know of it, wherever it is kept.
+pub(crate) trait Indexed {
+    fn digest(&self) -> &[u8; 32];
+    fn fresh_until(&self) -> Instant;
+
+    fn satisfies_freshness(&self, interest: &Interest) -> bool {
+        !interest.must_be_fresh || Instant::now() < self.fresh_until()
+    }
+}
+
+/// The packet in `index` named `name`. A trailing implicit digest component must match the
+/// packet's digest.
+pub(crate) fn lookup<'a, T: Indexed>(index: &'a BTreeMap<Name, T>, name: &Name) -> Option<&'a T> {
+    match name.implicit_digest() {
+        Some(digest) => index
+            .get(&name.prefix(name.len() - 1))
+            .filter(|packet| packet.digest()[..] == *digest),
+        None => index.get(name),
+    }
+}
+
+/// The packet in `index` answering `interest`, as [`SegmentStore::find`] picks it.
+pub(crate) fn find<'a, T: Indexed>(
+    index: &'a BTreeMap<Name, T>,
+    interest: &Interest,
+) -> Option<&'a T> {
+    let name = &interest.name;
+    if !interest.can_be_prefix || name.implicit_digest().is_some() {
+        return lookup(index, name).filter(|packet| packet.satisfies_freshness(interest));
+    }
+    let version = |stored_name: &Name| stored_name.get(name.len()).and_then(Component::to_version);
+    // Everything under a name sorts right after it, and versions in numeric order
+    let mut fresh = index
+        .range(name..)
+        .take_while(|(stored_name, _)| name.is_prefix_of(stored_name))
+        .filter(|(_, packet)| packet.satisfies_freshness(interest));
+    let (first_name, first) = fresh.next()?;
+    Some(match version(first_name) {
+        Some(first_version) if interest.must_be_fresh => {
+            fresh
+                .filter_map(|(stored_name, packet)| Some((version(stored_name)?, packet)))
+                .fold((first_version, first), |newest, (version, packet)| {
+                    if version > newest.0 {
+                        (version, packet)
+                    } else {
+                        newest
+                    }
+                })
+                .1
+        }
+        _ => first,
+    })
+}
+
+/// A compression algorithm for stored packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Fast to compress and very fast to decompress.
+    Lz4,
+    /// Smaller than LZ4, at more CPU time each way.
+    Zstd,
+}
+
+impl Codec {
+    fn compress(self, packet: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Lz4 => lz4_flex::compress_prepend_size(packet),
+            Self::Zstd => ruzstd::encoding::compress_to_vec(
+                packet,
+                ruzstd::encoding::CompressionLevel::Fastest,
+            ),
+        }
+    }
+
+    fn decompress(self, compressed: &[u8]) -> Vec<u8> {
+        // Only packets this store compressed are decompressed, so they always are whole
+        match self {
+            Self::Lz4 => lz4_flex::decompress_size_prepended(compressed)
+                .expect("stored LZ4 packets decompress"),
+            Self::Zstd => {
+                let mut decoder = ruzstd::decoding::StreamingDecoder::new(compressed)
+                    .expect("stored Zstandard packets decompress");
+                let mut packet = Vec::new();
+                decoder
+                    .read_to_end(&mut packet)
+                    .expect("stored Zstandard packets decompress");
+                packet
+            }
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => anyhow::bail!("expected 'lz4' or 'zstd', not '{s}'"),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lz4 => "lz4",
+            Self::Zstd => "zstd",
+        })
+    }
+}
+
+/// Which packets a store keeps compressed, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    /// Packets smaller than this many bytes are kept as they are.
+    pub min_size: usize,
+}
+
+/// What compressing a store's packets saved and cost, since it was made.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Packets kept compressed.
+    pub compressed: u64,
+    /// Their sizes before and after compression.
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+    /// Packets compression would not have made smaller, kept as they are.
+    pub incompressible: u64,
+    pub compress_ns: u64,
+    /// Packets decompressed to answer an Interest or be saved.
+    pub decompressed: u64,
+    pub decompress_ns: u64,
+}
+
+impl CompressionStats {
+    /// How many times smaller the compressed packets are, or 1 if there are none.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.original_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+}
+
+impl fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} packet(s) compressed from {} to {} bytes ({:.2}x) in {} ms, {} incompressible; \
+             {} decompressed in {} ms",
+            self.compressed,
+            self.original_bytes,
+            self.compressed_bytes,
+            self.ratio(),
+            self.compress_ns / 1_000_000,
+            self.incompressible,
+            self.decompressed,
+            self.decompress_ns / 1_000_000
+        )
+    }
+}
+
+/// Decompressions, counted by lookups that share the store.
+#[derive(Debug, Default)]
+struct Decompressions {
+    count: AtomicU64,
+    ns: AtomicU64,
+}
+
+impl Clone for Decompressions {
+    fn clone(&self) -> Self {
+        Self {
+            count: AtomicU64::new(self.count.load(Ordering::Relaxed)),
+            ns: AtomicU64::new(self.ns.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Encoded Data packets by name, in canonical name order.
+#[derive(Clone, Debug, Default)]
+pub struct SegmentStore {
+    packets: BTreeMap<Name, Stored>,
+    compression: Option<Compression>,
+    stats: CompressionStats,
+    decompressions: Decompressions,
+}
+
+impl SegmentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep packets compressed as `compression` says, or all of them as they are with `None`,
+    /// from now on and for those already stored.
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+        let names: Vec<Name> = self.packets.keys().cloned().collect();
+        for name in names {
+            let stored = &self.packets[&name];
+            let packet = self.packet(stored).into_owned();
+            let (packet, codec) = self.compress(packet);
+            let stored = self.packets.get_mut(&name).unwrap();
+            stored.packet = packet;
+            stored.codec = codec;
+        }
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// What compression saved and cost so far.
+    pub fn compression_stats(&self) -> CompressionStats {
+        CompressionStats {
+            decompressed: self.decompressions.count.load(Ordering::Relaxed),
+            decompress_ns: self.decompressions.ns.load(Ordering::Relaxed),
+            ..self.stats
+        }
+    }
+
+    /// `packet` as it is to be kept, and the codec that compressed it if one did.
+    fn compress(&mut self, packet: Vec<u8>) -> (Vec<u8>, Option<Codec>) {
+        let Some(compression) = self.compression.filter(|c| packet.len() >= c.min_size) else {
+            return (packet, None);
+        };
+        let start = Instant::now();
+        let compressed = compression.codec.compress(&packet);
+        self.stats.compress_ns += start.elapsed().as_nanos() as u64;
+        if compressed.len() >= packet.len() {
+            self.stats.incompressible += 1;
+            return (packet, None);
+        }
+        self.stats.compressed += 1;
+        self.stats.original_bytes += packet.len() as u64;
+        self.stats.compressed_bytes += compressed.len() as u64;
+        (compressed, Some(compression.codec))
+    }
+
+    /// The encoded packet `stored` holds, decompressed if it was compressed.
+    fn packet<'a>(&self, stored: &'a Stored) -> Cow<'a, [u8]> {
+        let Some(codec) = stored.codec else {
+            return Cow::Borrowed(&stored.packet);
+        };
+        let start = Instant::now();
+        let packet = codec.decompress(&stored.packet);
+        let elapsed = start.elapsed().as_nanos() as u64;
+        self.decompressions.count.fetch_add(1, Ordering::Relaxed);
+        self.decompressions.ns.fetch_add(elapsed, Ordering::Relaxed);
+        Cow::Owned(packet)
+    }
+
+    /// Split `content` into `segment_size`-byte segments named `<prefix>/seg=<n>`, sign each with
+    /// `signer` and store the encoded packets. Empty content still gets one empty segment.
+    /// Returns the number of segments.
+    pub fn publish(
+        &mut self,
+        prefix: &Name,
+        content: &[u8],
+        segment_size: usize,
+        freshness_period_ms: Option<u64>,
+        signer: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> usize {
+        assert!(segment_size > 0, "segment size must be positive");
+        let chunks: Vec<&[u8]> = if content.is_empty() {
+            vec![&[]]
+        } else {
+            content.chunks(segment_size).collect()
+        };
+        for (segment, chunk) in chunks.iter().enumerate() {
+            let name = prefix.clone().append_segment(segment as u64);
+            self.insert(&build_segment(name, chunk, freshness_period_ms, &signer));
+        }
+        chunks.len()
+    }
+
+    /// Store `data` as it is, fresh for its FreshnessPeriod from now. Returns its implicit
+    /// digest.
+    pub fn insert(&mut self, data: &Data) -> [u8; 32] {
+        let freshness = Duration::from_millis(data.meta.freshness_period_ms.unwrap_or(0));
+        let packet = data.encode();
+        let digest = implicit_digest(&packet);
+        let (packet, codec) = self.compress(packet);
+        let stored = Stored {
+            packet,
+            codec,
+            digest,
+            fresh_until: Instant::now() + freshness,
+        };
+        self.packets.insert(data.name.clone(), stored);
+        digest
+    }
+
+    /// The encoded packet named `name`. A trailing implicit digest component must match the
+    /// packet's digest.
+    pub fn get(&self, name: &Name) -> Option<Cow<'_, [u8]>> {
+        lookup(&self.packets, name).map(|stored| self.packet(stored))
+    }
+
+    /// The packet answering `interest`: the one it names exactly, or with `CanBePrefix` the
+    /// leftmost one under its name, which is the name itself if stored. With `MustBeFresh`,
+    /// stale packets are passed over, and if the packets under the name are versions of it, the
+    /// leftmost fresh one of the newest version answers.
+    pub fn find(&self, interest: &Interest) -> Option<Cow<'_, [u8]>> {
+        find(&self.packets, interest).map(|stored| self.packet(stored))
+    }
+
+    /// Give every packet under `prefix` a new FreshnessPeriod, re-signed with `signer`, e.g. to
+    /// have caches drop an old version soon. Returns how many packets there were.
+    pub fn set_freshness(
+        &mut self,
+        prefix: &Name,
+        freshness_period_ms: u64,
+        signer: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> usize {
+        let fresh_until = Instant::now() + Duration::from_millis(freshness_period_ms);
+        let names: Vec<Name> = self
+            .packets
+            .range(prefix..)
+            .take_while(|(name, _)| prefix.is_prefix_of(name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut updated = 0;
+        for name in names {
+            let Some(mut data) = Data::decode(&self.packet(&self.packets[&name])) else {
+                continue;
+            };
+            data.meta.freshness_period_ms = Some(freshness_period_ms);
+            data.signature_value = signer(&data.signed_portion());
+            let packet = data.encode();
+            let digest = implicit_digest(&packet);
+            let (packet, codec) = self.compress(packet);
+            let stored = self.packets.get_mut(&name).unwrap();
+            *stored = Stored {
+                packet,
+                codec,
+                digest,
+                fresh_until,
+            };
+            updated += 1;
+        }
+        updated
+    }
+
+    /// Remove every packet under `prefix`. Returns how many there were.
+    pub fn remove(&mut self, prefix: &Name) -> usize {
+        let names: Vec<Name> = self
+            .packets
+            .range(prefix..)
+            .take_while(|(name, _)| prefix.is_prefix_of(name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &names {
+            self.packets.remove(name);
+        }
+        names.len()
+    }
+
+    /// The implicit digest of the packet named `name`.
+    pub fn digest(&self, name: &Name) -> Option<[u8; 32]> {
+        self.packets.get(name).map(|stored| stored.digest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Write every packet to `path`, each after its length as a big-endian u32, for
+    /// [`Self::load`].
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        for stored in self.packets.values() {
+            let packet = self.packet(stored);
+            out.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            out.extend_from_slice(&packet);
+        }
+        fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Read the packets [`Self::save`] wrote to `path`, as they were signed. Each stays fresh for
+    /// its FreshnessPeriod from now, as if it had just been published. They are kept as they are
+    /// until [`Self::set_compression`].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut store = Self::new();
+        let mut rest = &contents[..];
+        let now = Instant::now();
+        while !rest.is_empty() {
+            let packet = rest
+                .get(..4)
+                .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+                .and_then(|len| rest.get(4..4 + len))
+                .with_context(|| format!("{} is truncated", path.display()))?;
+            rest = &rest[4 + packet.len()..];
+            let data = Data::decode(packet)
+                .with_context(|| format!("{} holds a packet that is not Data", path.display()))?;
+            let freshness = Duration::from_millis(data.meta.freshness_period_ms.unwrap_or(0));
+            let stored = Stored {
+                packet: packet.to_vec(),
+                codec: None,
+                digest: implicit_digest(packet),
+                fresh_until: now + freshness,
+            };
+            store.packets.insert(data.name, stored);
+        }
+        Ok(store)
+    }
+}
+
+/// How large to publish segments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SegmentSize {
+    /// As large as fits a datagram whole.
+    #[default]
+    Auto,
+    /// Up to [`MAX_CONTENT_LEN`] bytes.
+    Bytes(usize),
+}
+
+impl SegmentSize {
+    /// The segment size in bytes for segments named up to `last`, published as
+    /// [`SegmentStore::publish`] does. `Auto` fits them to an `mtu`-byte MTU, or without one to
+    /// the smallest MTU of the host's interfaces.
+    pub fn resolve(
+        self,
+        mtu: Option<usize>,
+        last: &Name,
+        freshness_period_ms: Option<u64>,
+        signer: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> anyhow::Result<usize> {
+        let mtu = match (self, mtu) {
+            (Self::Bytes(bytes), _) => {
+                anyhow::ensure!(
+                    (1..=MAX_CONTENT_LEN).contains(&bytes),
+                    "the segment size must be between 1 and {MAX_CONTENT_LEN} bytes"
+                );
+                return Ok(bytes);
+            }
+            (Self::Auto, Some(mtu)) => mtu,
+            (Self::Auto, None) => mtu::smallest_interface_mtu()
+                .context("failed to list the network interfaces")?
+                .unwrap_or(mtu::MIN_MTU),
+        };
+        segment_size_within(mtu::packet_limit(mtu), last, freshness_period_ms, signer)
+            .with_context(|| format!("not even a byte of '{last}' fits a {mtu}-byte MTU"))
+    }
+}
+
+impl FromStr for SegmentSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        let bytes = s
+            .parse()
+            .with_context(|| format!("expected 'auto' or a number of bytes, not '{s}'"))?;
+        anyhow::ensure!(
+            (1..=MAX_CONTENT_LEN).contains(&bytes),
+            "the segment size must be between 1 and {MAX_CONTENT_LEN} bytes"
+        );
+        Ok(Self::Bytes(bytes))
+    }
+}
+
+impl fmt::Display for SegmentSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Bytes(bytes) => write!(f, "{bytes}"),
+        }
+    }
+}
+
+pub(crate) fn build_segment(
+    name: Name,
+    content: &[u8],
+    freshness_period_ms: Option<u64>,
+    signer: impl Fn(&[u8]) -> Vec<u8>,
+) -> Data {
+    let mut builder = Data::builder().name(name).content(content);
+    if let Some(freshness_period_ms) = freshness_period_ms {
+        builder = builder.freshness_period_ms(freshness_period_ms);
+    }
+    let mut data = builder.build();
+    data.signature_value = signer(&data.signed_portion());
+    data
+}
+
+/// The largest segment size at which the segment named `last`, published as
+/// [`SegmentStore::publish`] does, encodes to at most `limit` bytes, so each segment fits a
+/// datagram whole. Give the name of the last segment, or one at least as long. `None` if not even
+/// a byte of content fits.
+pub fn segment_size_within(
+    limit: usize,
+    last: &Name,
+    freshness_period_ms: Option<u64>,
+    signer: impl Fn(&[u8]) -> Vec<u8>,
+) -> Option<usize> {
+    // From this size on, the Content length takes as many bytes as it does for any segment that
+    // fits a datagram
+    const PROBE: usize = 256;
+    let probe = build_segment(last.clone(), &[0; PROBE], freshness_period_ms, signer);
+    let overhead = probe.encode().len() - PROBE;
+    limit
+        .checked_sub(overhead)
+        .filter(|&size| size > 0)
+        .map(|size| size.min(u16::MAX as usize))
+}
+
+/// The implicit digest of an encoded Data packet: the SHA-256 of all of it.
+pub fn implicit_digest(packet: &[u8]) -> [u8; 32] {
+    Sha256::digest(packet).into()
+}
+
+/// A DigestSha256 signer: the signature value is the SHA-256 of the signed portion.
+pub fn sha256_signer(signed_portion: &[u8]) -> Vec<u8> {
+    Sha256::digest(signed_portion).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::Component;
+
+    use super::*;
+
+    #[test]
+    fn test_publish_segments_and_signs() {
+        let mut store = SegmentStore::new();
+        let prefix = Name::from("/file");
+        assert_eq!(
+            store.publish(&prefix, b"abcdefg", 3, Some(1000), sha256_signer),
+            3
+        );
+        assert_eq!(store.len(), 3);
+
+        let last = Data::decode(&store.get(&prefix.clone().append_segment(2)).unwrap()).unwrap();
+        assert_eq!(last.content, b"g");
+        assert_eq!(last.meta.freshness_period_ms, Some(1000));
+        assert_eq!(last.signature_value, sha256_signer(&last.signed_portion()));
+        assert!(store.get(&prefix.clone().append_segment(3)).is_none());
+
+        assert_eq!(
+            store.publish(&Name::from("/empty"), b"", 3, None, |_| Vec::new()),
+            1
+        );
+    }
+
+    #[test]
+    fn test_saved_store_loads_without_signing() {
+        let mut store = SegmentStore::new();
+        let prefix = Name::from("/file");
+        store.publish(&prefix, b"abcdefg", 3, Some(1000), sha256_signer);
+        let path = std::env::temp_dir().join(format!("udcn-segments-{}", std::process::id()));
+        store.save(&path).unwrap();
+
+        let loaded = SegmentStore::load(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        for segment in 0..3 {
+            let name = prefix.clone().append_segment(segment);
+            assert_eq!(loaded.get(&name), store.get(&name));
+            assert_eq!(loaded.digest(&name), store.digest(&name));
+        }
+        let fresh = Interest::builder()
+            .name(prefix.clone().append_segment(0))
+            .must_be_fresh(true)
+            .build();
+        assert!(loaded.find(&fresh).is_some());
+
+        fs::write(&path, [0, 0, 0, 9, 1]).unwrap();
+        assert!(SegmentStore::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_find_matches_prefixes_only_with_can_be_prefix() {
+        let mut store = SegmentStore::new();
+        store.publish(&Name::from("/file"), b"abcdef", 2, None, sha256_signer);
+        store.publish(&Name::from("/file/v=2"), b"new", 8, None, sha256_signer);
+        store.publish(&Name::from("/files"), b"other", 8, None, sha256_signer);
+        let find = |uri: &str, can_be_prefix| {
+            let interest = Interest::builder()
+                .name(uri)
+                .can_be_prefix(can_be_prefix)
+                .build();
+            store
+                .find(&interest)
+                .map(|packet| Data::decode(&packet).unwrap().name.to_string())
+        };
+
+        assert_eq!(find("/file/seg=1", false).as_deref(), Some("/file/seg=1"));
+        assert_eq!(find("/file", false), None);
+        // Segments (type 0x32) sort before versions (type 0x36)
+        assert_eq!(find("/file", true).as_deref(), Some("/file/seg=0"));
+        assert_eq!(find("/file/v=2", true).as_deref(), Some("/file/v=2/seg=0"));
+        assert_eq!(find("/file/seg=1", true).as_deref(), Some("/file/seg=1"));
+        assert_eq!(find("/fil", true), None);
+        assert_eq!(find("/files", true).as_deref(), Some("/files/seg=0"));
+    }
+
+    #[test]
+    fn test_find_skips_stale_packets_for_must_be_fresh() {
+        let mut store = SegmentStore::new();
+        store.publish(&Name::from("/news/v=1"), b"old", 8, None, sha256_signer);
+        store.publish(
+            &Name::from("/news/v=2"),
+            b"new",
+            8,
+            Some(60_000),
+            sha256_signer,
+        );
+        let find = |uri: &str, can_be_prefix, must_be_fresh| {
+            let interest = Interest::builder()
+                .name(uri)
+                .can_be_prefix(can_be_prefix)
+                .must_be_fresh(must_be_fresh)
+                .build();
+            store
+                .find(&interest)
+                .map(|packet| Data::decode(&packet).unwrap().name.to_string())
+        };
+
+        assert_eq!(
+            find("/news/v=1/seg=0", false, false).as_deref(),
+            Some("/news/v=1/seg=0")
+        );
+        assert_eq!(find("/news/v=1/seg=0", false, true), None);
+        assert_eq!(
+            find("/news/v=2/seg=0", false, true).as_deref(),
+            Some("/news/v=2/seg=0")
+        );
+        assert_eq!(
+            find("/news", true, false).as_deref(),
+            Some("/news/v=1/seg=0")
+        );
+        assert_eq!(
+            find("/news", true, true).as_deref(),
+            Some("/news/v=2/seg=0")
+        );
+    }
+
+    #[test]
+    fn test_update_to_a_new_version() {
+        let mut store = SegmentStore::new();
+        let prefix = Name::from("/news");
+        let v1 = prefix.clone().append_version(1);
+        let v2 = prefix.clone().append_version(2);
+        store.publish(&v1, b"old", 8, Some(60_000), sha256_signer);
+        store.publish(&v2, b"new", 2, Some(60_000), sha256_signer);
+        let discover = Interest::builder()
+            .name(prefix.clone())
+            .can_be_prefix(true)
+            .must_be_fresh(true)
+            .build();
+        let found = |store: &SegmentStore| {
+            let packet = store.find(&discover)?;
+            Some(Data::decode(&packet).unwrap().name.to_string())
+        };
+        assert_eq!(found(&store).as_deref(), Some("/news/v=2/seg=0"));
+
+        let old_digest = store.digest(&v1.clone().append_segment(0)).unwrap();
+        assert_eq!(store.set_freshness(&v1, 0, sha256_signer), 1);
+        let old = Data::decode(&store.get(&v1.clone().append_segment(0)).unwrap()).unwrap();
+        assert_eq!(old.meta.freshness_period_ms, Some(0));
+        assert_eq!(old.signature_value, sha256_signer(&old.signed_portion()));
+        assert_ne!(store.digest(&old.name).unwrap(), old_digest);
+
+        assert_eq!(store.remove(&v2), 2);
+        assert_eq!(store.len(), 1);
+        // Only the stale old version is left
+        assert_eq!(found(&store), None);
+    }
+
+    #[test]
+    fn test_get_by_implicit_digest() {
+        let mut store = SegmentStore::new();
+        let prefix = Name::from("/file");
+        store.publish(&prefix, b"abcdef", 3, None, sha256_signer);
+
+        let name = prefix.append_segment(1);
+        let digest = store.digest(&name).unwrap();
+        let packet = store.get(&name).unwrap();
+        assert_eq!(implicit_digest(&packet), digest);
+
+        let exact = name
+            .clone()
+            .append(Component::implicit_sha256_digest(digest));
+        assert_eq!(store.get(&exact), Some(packet));
+        let mut wrong = digest;
+        wrong[0] ^= 1;
+        assert!(store
+            .get(&name.append(Component::implicit_sha256_digest(wrong)))
+            .is_none());
+    }
+    #[test]
+    fn test_compressed_packets_are_served_as_published() {
+        let prefix = Name::from("/logs");
+        let content = b"GET /index.html 200\n".repeat(105);
+        let mut plain = SegmentStore::new();
+        plain.publish(&prefix, &content, 1000, Some(1000), sha256_signer);
+        let mut store = SegmentStore::new();
+        store.set_compression(Some(Compression {
+            codec: Codec::Lz4,
+            min_size: 500,
+        }));
+        // The last segment, of 100 bytes, is under the threshold
+        store.publish(&prefix, &content, 1000, Some(1000), sha256_signer);
+
+        let stats = store.compression_stats();
+        assert_eq!((stats.compressed, stats.incompressible), (2, 0));
+        assert!(stats.ratio() > 4.0);
+        for segment in 0..3 {
+            let name = prefix.clone().append_segment(segment);
+            assert_eq!(store.get(&name), plain.get(&name));
+            assert_eq!(store.digest(&name), plain.digest(&name));
+        }
+        assert_eq!(store.compression_stats().decompressed, 2);
+        assert!(matches!(
+            store.get(&prefix.clone().append_segment(2)),
+            Some(Cow::Borrowed(_))
+        ));
+
+        // Switching codecs compresses what is stored again, and saving writes it uncompressed
+        store.set_compression(Some(Compression {
+            codec: Codec::Zstd,
+            min_size: 500,
+        }));
+        assert_eq!(store.compression_stats().compressed, 4);
+        assert_eq!(store.set_freshness(&prefix, 0, sha256_signer), 3);
+        let old = Data::decode(&store.get(&prefix.clone().append_segment(1)).unwrap()).unwrap();
+        assert_eq!(old.meta.freshness_period_ms, Some(0));
+        let path = std::env::temp_dir().join(format!("udcn-compressed-{}", std::process::id()));
+        store.save(&path).unwrap();
+        let loaded = SegmentStore::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get(&old.name), store.get(&old.name));
+        assert_eq!(loaded.compression_stats(), CompressionStats::default());
+
+        assert_eq!("zstd".parse::<Codec>().unwrap(), Codec::Zstd);
+        assert!("gzip".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn test_segment_size_fits_a_datagram() {
+        let prefix = Name::from("/video/clip");
+        let content = vec![7u8; 10_000];
+        let limit = mtu::packet_limit(1500);
+        let last = prefix.clone().append_segment(content.len() as u64);
+        let size = segment_size_within(limit, &last, Some(1000), sha256_signer).unwrap();
+
+        let mut store = SegmentStore::new();
+        let segments = store.publish(&prefix, &content, size, Some(1000), sha256_signer);
+        let largest = (0..segments as u64)
+            .map(|segment| {
+                store
+                    .get(&prefix.clone().append_segment(segment))
+                    .unwrap()
+                    .len()
+            })
+            .max();
+        // The name of the last segment here is shorter than the one sized for
+        assert!(largest.is_some_and(|largest| largest <= limit && largest + 4 > limit));
+        assert_eq!(segment_size_within(50, &last, None, sha256_signer), None);
+
+        let auto = SegmentSize::Auto.resolve(Some(1500), &last, Some(1000), sha256_signer);
+        assert_eq!(auto.unwrap(), size);
+        assert_eq!("auto".parse::<SegmentSize>().unwrap(), SegmentSize::Auto);
+        assert_eq!(
+            "1024".parse::<SegmentSize>().unwrap(),
+            SegmentSize::Bytes(1024)
+        );
+        assert!("0".parse::<SegmentSize>().is_err());
+        // Larger segments would not fit the Data header's content size
+        assert!("65536".parse::<SegmentSize>().is_err());
+        assert!(SegmentSize::Bytes(1 << 20)
+            .resolve(None, &last, None, sha256_signer)
+            .is_err());
+    }
+}
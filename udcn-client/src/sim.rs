@@ -0,0 +1,573 @@
+//! A deterministic stand-in for the network, to test face selection, failover and Interest
+//! aggregation reproducibly, without namespaces or sockets.
+//!
+//! A [`SimNetwork`] is a [`Transport`] whose faces answer as scripted: with a reply after some
+//! delay, by staying silent until the Interest times out, or by refusing it the way an
+//! unreachable peer does, and can mark their replies with a CongestionMark. Delays and timeouts are tokio timers, so on a current-thread runtime
+//! with the clock paused (`#[tokio::test(start_paused = true)]`, which takes tokio's `test-util`
+//! feature) the clock jumps straight to the next timer whenever every task is idle: a run takes
+//! no real time and unfolds the same way every time. Each Interest is logged with the virtual
+//! time it was sent at.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use udcn_client::{sim::{Response, SimNetwork}, FaceTable, Liveness, Reply};
+//! # use udcn_common::{Interest, NackReason};
+//! # async fn example() {
+//! let (primary, backup) = ("10.0.0.1:6363".parse().unwrap(), "10.0.0.2:6363".parse().unwrap());
+//! let network = SimNetwork::new();
+//! network.face(primary, Response::Silence);
+//! network.face(backup, Response::after(Duration::from_millis(5), Reply::Nack(NackReason::NoData as u8)));
+//! let faces = FaceTable::with_transport(&[primary, backup], Liveness::default(), network.clone());
+//! faces.express(&Interest::builder().name("/a").build()).await.unwrap();
+//! # }
+//! ```
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::{sleep, Instant};
+use udcn_common::{Interest, Name};
+
+use crate::{
+    consumer::{Reply, DEFAULT_INTEREST_LIFETIME},
+    faces::Transport,
+};
+
+/// How a face answers one Interest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// The reply, this long after the Interest was sent. Later than the Interest's lifetime, it
+    /// times out instead.
+    Reply { after: Duration, reply: Reply },
+    /// No reply; the Interest times out.
+    Silence,
+    /// The Interest cannot be sent, as to a peer that is not there.
+    Refuse,
+}
+
+impl Response {
+    pub fn after(after: Duration, reply: Reply) -> Self {
+        Self::Reply { after, reply }
+    }
+}
+
+/// An Interest a face was sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sent {
+    /// Virtual time since the network was created.
+    pub at: Duration,
+    pub addr: SocketAddr,
+    pub name: Name,
+    pub nonce: u32,
+}
+
+struct Face {
+    /// Answers to the next Interests, in order.
+    scripted: VecDeque<Response>,
+    /// The answer once the scripted ones run out.
+    otherwise: Response,
+    /// Whether replies carry a CongestionMark.
+    congested: bool,
+}
+
+impl Face {
+    fn new() -> Self {
+        Self {
+            scripted: VecDeque::new(),
+            otherwise: Response::Refuse,
+            congested: false,
+        }
+    }
+}
+
+struct State {
+    start: Instant,
+    faces: HashMap<SocketAddr, Face>,
+    sent: Vec<Sent>,
+}
+
+/// Simulated faces. Clones share the faces and the log, so a test can keep one to script and
+/// inspect while a [`FaceTable`](crate::FaceTable) or
+/// [`InterestRegistry`](crate::InterestRegistry) sends through another. Addresses no face was
+/// set up for refuse every Interest.
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimNetwork {
+    /// A network whose clock starts now.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                start: Instant::now(),
+                faces: HashMap::new(),
+                sent: Vec::new(),
+            })),
+        }
+    }
+
+    /// Answer every Interest sent to `addr` with `response` from now on, once any scripted
+    /// answers are used up.
+    pub fn face(&self, addr: SocketAddr, response: Response) {
+        self.state
+            .lock()
+            .unwrap()
+            .faces
+            .entry(addr)
+            .or_insert_with(Face::new)
+            .otherwise = response;
+    }
+
+    /// Answer the next Interests sent to `addr` with `responses`, in order.
+    pub fn script(&self, addr: SocketAddr, responses: impl IntoIterator<Item = Response>) {
+        self.state
+            .lock()
+            .unwrap()
+            .faces
+            .entry(addr)
+            .or_insert_with(Face::new)
+            .scripted
+            .extend(responses);
+    }
+
+    /// Mark the replies from `addr` with a CongestionMark, or stop marking them.
+    pub fn congest(&self, addr: SocketAddr, congested: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .faces
+            .entry(addr)
+            .or_insert_with(Face::new)
+            .congested = congested;
+    }
+
+    /// Every Interest sent so far, in the order it was sent.
+    pub fn sent(&self) -> Vec<Sent> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// Virtual time since the network was created.
+    pub fn elapsed(&self) -> Duration {
+        self.state.lock().unwrap().start.elapsed()
+    }
+}
+
+impl Default for SimNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for SimNetwork {
+    async fn attempt(&self, addr: SocketAddr, interest: &Interest) -> anyhow::Result<Reply> {
+        Ok(self.attempt_marked(addr, interest).await?.0)
+    }
+
+    async fn attempt_marked(
+        &self,
+        addr: SocketAddr,
+        interest: &Interest,
+    ) -> anyhow::Result<(Reply, bool)> {
+        let (response, congested) = {
+            let state = &mut *self.state.lock().unwrap();
+            state.sent.push(Sent {
+                at: state.start.elapsed(),
+                addr,
+                name: interest.name.clone(),
+                nonce: interest.nonce,
+            });
+            match state.faces.get_mut(&addr) {
+                Some(face) => (
+                    face.scripted
+                        .pop_front()
+                        .unwrap_or_else(|| face.otherwise.clone()),
+                    face.congested,
+                ),
+                None => (Response::Refuse, false),
+            }
+        };
+        let lifetime = interest
+            .lifetime_ms
+            .map_or(DEFAULT_INTEREST_LIFETIME, Duration::from_millis);
+        match response {
+            Response::Reply { after, reply } if after < lifetime => {
+                sleep(after).await;
+                Ok((reply, congested))
+            }
+            Response::Reply { .. } | Response::Silence => {
+                sleep(lifetime).await;
+                anyhow::bail!("Interest for '{}' timed out", interest.name)
+            }
+            Response::Refuse => anyhow::bail!("failed to connect to {addr}: connection refused"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use udcn_common::{Data, NackReason};
+
+    use super::*;
+    use crate::{
+        faces::{
+            FaceState, FaceTable, Liveness, LoadBalance, NackPolicy, Retransmission, Tier,
+            TierStats,
+        },
+        pacing::{Pacing, PacingStats},
+        registry::{InterestRegistry, NONCE_WINDOW},
+    };
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn addr(host: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, host], 6363))
+    }
+
+    fn data(name: &str) -> Reply {
+        Reply::Data(Data::builder().name(name).content(b"hi".to_vec()).build())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failover_and_liveness_on_a_virtual_clock() {
+        let (primary, backup) = (addr(1), addr(2));
+        let network = SimNetwork::new();
+        network.face(primary, Response::Silence);
+        network.face(backup, Response::after(5 * MS, data("/a")));
+        let liveness = Liveness {
+            probe_lifetime: 100 * MS,
+            failures_until_down: 2,
+            ..Liveness::default()
+        };
+        let faces = FaceTable::with_transport(&[primary, backup], liveness, network.clone());
+        let interest = Interest::builder().name("/a").lifetime_ms(200).build();
+
+        // The silent primary is presumed up until probes say otherwise
+        let (from, reply) = faces.express(&interest).await.unwrap();
+        assert_eq!((from, reply), (backup, data("/a")));
+        assert_eq!(network.elapsed(), 205 * MS);
+
+        faces.probe_all().await;
+        faces.probe_all().await;
+        assert_eq!(faces.states()[0], (primary, FaceState::Down));
+        let before = network.sent().len();
+        faces.express(&interest).await.unwrap();
+        let sent = network.sent();
+        assert_eq!(sent[before..].len(), 1);
+        assert_eq!(sent[before].addr, backup);
+
+        // One answered probe brings the primary back
+        network.script(
+            primary,
+            [Response::after(MS, Reply::Nack(NackReason::NoData as u8))],
+        );
+        faces.probe_all().await;
+        assert_eq!(faces.states()[0], (primary, FaceState::Up));
+
+        let times: Vec<_> = sent.iter().map(|sent| sent.at.as_millis()).collect();
+        assert_eq!(times, [0, 200, 205, 305, 310, 410, 415]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refused_faces_fail_over_at_once() {
+        let network = SimNetwork::new();
+        network.face(addr(2), Response::after(MS, data("/a")));
+        let faces =
+            FaceTable::with_transport(&[addr(1), addr(2)], Liveness::default(), network.clone());
+        let (from, _) = faces
+            .express(&Interest::builder().name("/a").build())
+            .await
+            .unwrap();
+        // The refusal costs no time, only the backup's reply does
+        assert_eq!(from, addr(2));
+        assert_eq!(network.elapsed(), MS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retransmission_hides_a_silent_face() {
+        let (primary, backup) = (addr(1), addr(2));
+        let network = SimNetwork::new();
+        network.face(primary, Response::Silence);
+        network.face(backup, Response::after(5 * MS, data("/a")));
+        let faces =
+            FaceTable::with_transport(&[primary, backup], Liveness::default(), network.clone());
+        faces.set_retransmission(Some(Retransmission {
+            timeout: 50 * MS,
+            max_retries: 2,
+        }));
+        let interest = Interest::builder()
+            .name("/a")
+            .nonce(7)
+            .lifetime_ms(200)
+            .build();
+
+        // The backup answers the retransmission long before the primary's lifetime would run out
+        let (from, _) = faces.express(&interest).await.unwrap();
+        assert_eq!((from, network.elapsed()), (backup, 55 * MS));
+        let sent = network.sent();
+        assert_eq!((sent[0].nonce, sent[1].at), (7, 50 * MS));
+        assert_ne!(sent[1].nonce, 7);
+
+        // Every retry unanswered: the last one waits for what is left of the lifetime
+        network.face(backup, Response::Silence);
+        let start = network.elapsed();
+        assert!(faces.express(&interest).await.is_err());
+        let times: Vec<_> = network.sent()[2..]
+            .iter()
+            .map(|sent| (sent.addr, sent.at - start))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (primary, Duration::ZERO),
+                (backup, 50 * MS),
+                (primary, 100 * MS)
+            ]
+        );
+        assert_eq!(network.elapsed() - start, 200 * MS);
+        assert_eq!(faces.retransmissions(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing_queues_nacks_and_backs_off() {
+        let network = SimNetwork::new();
+        network.face(addr(1), Response::after(MS, data("/a")));
+        let faces = FaceTable::with_transport(&[addr(1)], Liveness::default(), network.clone());
+        faces.set_pacing(Some(Pacing {
+            initial_rate: 10.0,
+            burst: 2,
+            queue: 1,
+            ..Pacing::default()
+        }));
+        let interest = Interest::builder().name("/a").build();
+
+        // Two go out at once, the third waits its turn and the fourth finds the queue full
+        let (a, b, c, d) = tokio::join!(
+            faces.express(&interest),
+            faces.express(&interest),
+            faces.express(&interest),
+            faces.express(&interest),
+        );
+        for reply in [a, b, c] {
+            assert_eq!(reply.unwrap().1, data("/a"));
+        }
+        let congestion = Reply::Nack(NackReason::Congestion as u8);
+        assert_eq!(d.unwrap().1, congestion);
+        let times: Vec<_> = network.sent().iter().map(|sent| sent.at).collect();
+        assert_eq!(times, [Duration::ZERO, Duration::ZERO, 100 * MS]);
+
+        // Each Data returned raised the rate by one, and a CongestionMark halves it
+        network.congest(addr(1), true);
+        faces.express(&interest).await.unwrap();
+        assert_eq!(
+            faces.pacing_stats()[0].1,
+            PacingStats {
+                rate: 6.5,
+                queued: 0,
+                delayed: 2,
+                refused: 1,
+                congested: 1,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nack_policy_tries_the_other_faces_first() {
+        let network = SimNetwork::new();
+        let nack = |reason: NackReason| Response::after(MS, Reply::Nack(reason as u8));
+        network.face(addr(1), nack(NackReason::NoRoute));
+        network.face(addr(2), nack(NackReason::Congestion));
+        network.face(addr(3), Response::after(MS, data("/a")));
+        let faces = FaceTable::with_transport(
+            &[addr(1), addr(2), addr(3)],
+            Liveness::default(),
+            network.clone(),
+        );
+        let interest = Interest::builder().name("/a").build();
+
+        let (from, reply) = faces.express(&interest).await.unwrap();
+        assert_eq!(
+            (from, reply),
+            (addr(1), Reply::Nack(NackReason::NoRoute as u8))
+        );
+        faces.set_nack_policy(NackPolicy::Wait);
+        let (from, reply) = faces.express(&interest).await.unwrap();
+        assert_eq!((from, reply), (addr(3), data("/a")));
+
+        // With every face Nacking, the least severe Nack goes through, unless suppressed
+        network.face(addr(3), nack(NackReason::NoData));
+        let (from, reply) = faces.express(&interest).await.unwrap();
+        assert_eq!(
+            (from, reply),
+            (addr(2), Reply::Nack(NackReason::Congestion as u8))
+        );
+        faces.set_nack_policy(NackPolicy::Suppress);
+        let e = faces.express(&interest).await.unwrap_err();
+        assert!(e.to_string().contains("suppressed Nack"), "{e:#}");
+
+        let nacks = faces.nacks();
+        assert_eq!(nacks[0].1, BTreeMap::from([(NackReason::NoRoute as u8, 4)]));
+        assert_eq!(
+            nacks[1].1,
+            BTreeMap::from([(NackReason::Congestion as u8, 3)])
+        );
+        assert_eq!(nacks[2].1, BTreeMap::from([(NackReason::NoData as u8, 2)]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_aware_faces_skip_slow_nexthops() {
+        let (slow, fast) = (addr(1), addr(2));
+        let network = SimNetwork::new();
+        network.face(slow, Response::after(300 * MS, data("/a")));
+        network.face(fast, Response::after(20 * MS, data("/a")));
+        let faces = FaceTable::with_transport(&[slow, fast], Liveness::default(), network.clone());
+        faces.set_deadline_aware(true);
+        let interest = |lifetime_ms| {
+            Interest::builder()
+                .name("/a")
+                .lifetime_ms(lifetime_ms)
+                .build()
+        };
+
+        // Faces that have not answered yet are tried
+        let (from, _) = faces.express(&interest(1000)).await.unwrap();
+        assert_eq!(from, slow);
+        let (from, _) = faces.express(&interest(200)).await.unwrap();
+        assert_eq!(from, fast);
+        assert_eq!(
+            faces.rtts(),
+            [(slow, Some(300 * MS)), (fast, Some(20 * MS))]
+        );
+
+        // Too late for both: Nacked without being sent
+        let before = network.sent().len();
+        let (from, reply) = faces.express(&interest(10)).await.unwrap();
+        assert_eq!(
+            (from, reply),
+            (slow, Reply::Nack(NackReason::TooLate as u8))
+        );
+        assert_eq!(network.sent().len(), before);
+        assert_eq!(faces.too_late(), 1);
+
+        // The lifetime is the budget for every face together, not for each
+        network.face(slow, Response::Silence);
+        network.face(fast, Response::Silence);
+        let faces = FaceTable::with_transport(&[slow, fast], Liveness::default(), network.clone());
+        faces.set_deadline_aware(true);
+        let start = network.elapsed();
+        assert!(faces.express(&interest(200)).await.is_err());
+        assert_eq!(network.elapsed() - start, 200 * MS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_balance_spreads_interests_over_faces_up() {
+        let network = SimNetwork::new();
+        for host in 1..=3 {
+            network.face(addr(host), Response::after(MS, data("/a")));
+        }
+        let liveness = Liveness {
+            failures_until_down: 1,
+            ..Liveness::default()
+        };
+        let faces =
+            FaceTable::with_transport(&[addr(1), addr(2), addr(3)], liveness, network.clone());
+        faces.set_load_balance(Some(LoadBalance {
+            weights: BTreeMap::from([(addr(1), 2)]),
+        }));
+        let interest = Interest::builder().name("/a").build();
+        let mut from = Vec::new();
+        for _ in 0..8 {
+            from.push(faces.express(&interest).await.unwrap().0);
+        }
+        // The heavier face gets twice the share, interleaved with the others
+        let round = [addr(1), addr(2), addr(3), addr(1)];
+        assert_eq!(from, [round, round].concat());
+
+        // A face that is down gets none, and one that fails hands its Interest on
+        network.face(addr(1), Response::Refuse);
+        faces.probe_all().await;
+        assert_eq!(faces.states()[0], (addr(1), FaceState::Down));
+        network.face(addr(3), Response::Refuse);
+        let mut from = Vec::new();
+        for _ in 0..2 {
+            from.push(faces.express(&interest).await.unwrap().0);
+        }
+        assert_eq!(from, [addr(2), addr(2)]);
+        assert_eq!(
+            faces.load_balanced(),
+            [(addr(1), 4), (addr(2), 3), (addr(3), 3)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nearer_tiers_are_exhausted_first() {
+        let (origin, regional, local) = (addr(1), addr(2), addr(3));
+        let network = SimNetwork::new();
+        network.face(origin, Response::after(MS, data("/a")));
+        network.face(regional, Response::after(MS, data("/a")));
+        network.face(local, Response::Refuse);
+        let faces = FaceTable::with_transport(
+            &[origin, regional, local],
+            Liveness::default(),
+            network.clone(),
+        );
+        faces.set_tiers(&BTreeMap::from([
+            (regional, Tier::Regional),
+            (local, Tier::LocalCache),
+        ]));
+        let interest = Interest::builder().name("/a").build();
+
+        let (from, _) = faces.express(&interest).await.unwrap();
+        assert_eq!(from, regional);
+        network.face(regional, Response::Refuse);
+        let (from, _) = faces.express(&interest).await.unwrap();
+        assert_eq!(from, origin);
+        let sent: Vec<_> = network.sent().iter().map(|sent| sent.addr).collect();
+        assert_eq!(sent, [local, regional, local, regional, origin]);
+
+        let stats = |sent, hits| TierStats { sent, hits };
+        assert_eq!(
+            faces.tier_stats(),
+            BTreeMap::from([
+                (Tier::LocalCache, stats(2, 0)),
+                (Tier::Regional, stats(2, 1)),
+                (Tier::Origin, stats(1, 1)),
+            ])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_registry_aggregation_on_a_virtual_clock() {
+        let network = SimNetwork::new();
+        network.face(addr(1), Response::after(100 * MS, data("/a")));
+        let registry = InterestRegistry::with_transport(addr(1), 8, network.clone());
+        let interest = Interest::builder().name("/a").nonce(7).build();
+
+        let later = async {
+            sleep(50 * MS).await;
+            registry.express(&interest).await
+        };
+        let (first, second) = tokio::join!(registry.express(&interest), later);
+        assert_eq!((first.unwrap(), second.unwrap()), (data("/a"), data("/a")));
+
+        // Within the nonce window the nonce is refreshed, after it the Interest keeps its own
+        registry.express(&interest).await.unwrap();
+        sleep(NONCE_WINDOW + 100 * MS).await;
+        registry.express(&interest).await.unwrap();
+
+        let sent: Vec<_> = network
+            .sent()
+            .iter()
+            .map(|sent| (sent.at.as_millis(), sent.nonce == 7))
+            .collect();
+        assert_eq!(sent, [(0, true), (100, false), (800, true)]);
+        assert_eq!(registry.stats().coalesced, 1);
+    }
+}
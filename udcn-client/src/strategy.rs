@@ -0,0 +1,372 @@
+//! Forwarding strategies, chosen per prefix.
+//!
+//! A strategy choice says how a [`FaceTable`](crate::FaceTable) treats the Interests for names
+//! under a prefix. `failover` sends each Interest to the most preferred face that is up, failing
+//! over on a timeout; `retransmit` also sends it again on the next face once one leaves it
+//! unanswered for `retransmit_ms`, up to `max_retries` times. `load-balance` spreads Interests
+//! over the faces that are up, round-robin or in proportion to `weights` such as
+//! `10.0.0.1:6363:3,10.0.0.2:6363:1`, failing over like `failover`, see [`LoadBalance`]. Any of
+//! them can set how faces are probed with `probe_interval_ms` and `failures_until_down`, and with
+//! `on_nack` whether a Nack goes to the consumer at once (`propagate`) or only once the other
+//! faces have been tried (`wait`, or `suppress` to fail instead), see [`NackPolicy`]. With
+//! `deadline_aware=true`, faces whose RTT exceeds what is left of an Interest's lifetime are
+//! skipped, see [`FaceTable::set_deadline_aware`](crate::FaceTable::set_deadline_aware).
+//! Parameters left out take the defaults of [`Liveness`], [`Retransmission`] and [`NackPolicy`].
+//!
+//! A [`StrategyTable`] holds the choices by prefix. A name takes the choice of its longest prefix
+//! that has one, and `failover` with the defaults when none does. The daemon keeps such a table,
+//! which consumers look up with [`control::strategy`](crate::control::strategy).
+
+use std::{collections::BTreeMap, fmt, net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use udcn_common::Name;
+
+use crate::faces::{Liveness, LoadBalance, NackPolicy, Retransmission};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    Failover,
+    Retransmit,
+    LoadBalance,
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Failover => "failover",
+            Self::Retransmit => "retransmit",
+            Self::LoadBalance => "load-balance",
+        })
+    }
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "failover" => Ok(Self::Failover),
+            "retransmit" => Ok(Self::Retransmit),
+            "load-balance" => Ok(Self::LoadBalance),
+            _ => anyhow::bail!(
+                "unknown strategy '{s}', expected failover, retransmit or load-balance"
+            ),
+        }
+    }
+}
+
+/// A strategy and its parameters.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrategyChoice {
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failures_until_down: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retransmit_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_nack: Option<NackPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_aware: Option<bool>,
+    /// Weights of the faces to spread Interests over under `load-balance`, by address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weights: Option<BTreeMap<SocketAddr, u32>>,
+}
+
+impl StrategyChoice {
+    /// Parse a strategy followed by `key=value` parameters, as `udcn strategy set` takes them.
+    pub fn parse(words: &[&str]) -> anyhow::Result<Self> {
+        let (strategy, parameters) = words.split_first().ok_or_else(|| {
+            anyhow::anyhow!("expected a strategy, failover, retransmit or load-balance")
+        })?;
+        let mut choice = Self {
+            strategy: strategy.parse()?,
+            ..Self::default()
+        };
+        for parameter in parameters {
+            let (key, value) = parameter
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("expected key=value, not '{parameter}'"))?;
+            let number = || -> anyhow::Result<u64> {
+                value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{key} must be a number, not '{value}'"))
+            };
+            match key {
+                "probe_interval_ms" => choice.probe_interval_ms = Some(number()?),
+                "failures_until_down" => choice.failures_until_down = Some(number()?.try_into()?),
+                "retransmit_ms" => choice.retransmit_ms = Some(number()?),
+                "max_retries" => choice.max_retries = Some(number()?.try_into()?),
+                "on_nack" => choice.on_nack = Some(value.parse()?),
+                "deadline_aware" => {
+                    choice.deadline_aware = Some(value.parse().map_err(|_| {
+                        anyhow::anyhow!("deadline_aware must be true or false, not '{value}'")
+                    })?)
+                }
+                "weights" => choice.weights = Some(parse_weights(value)?),
+                _ => anyhow::bail!("unknown strategy parameter '{key}'"),
+            }
+        }
+        choice.validate()?;
+        Ok(choice)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.strategy == Strategy::Retransmit
+                || (self.retransmit_ms.is_none() && self.max_retries.is_none()),
+            "retransmit_ms and max_retries only apply to the retransmit strategy"
+        );
+        anyhow::ensure!(
+            self.probe_interval_ms != Some(0) && self.retransmit_ms != Some(0),
+            "probe_interval_ms and retransmit_ms must be positive"
+        );
+        anyhow::ensure!(
+            self.failures_until_down != Some(0),
+            "failures_until_down must be positive"
+        );
+        anyhow::ensure!(
+            self.strategy == Strategy::LoadBalance || self.weights.is_none(),
+            "weights only apply to the load-balance strategy"
+        );
+        anyhow::ensure!(
+            !self
+                .weights
+                .iter()
+                .flatten()
+                .any(|(_, &weight)| weight == 0),
+            "weights must be positive"
+        );
+        Ok(())
+    }
+
+    /// How faces are probed under this choice.
+    pub fn liveness(&self) -> Liveness {
+        let defaults = Liveness::default();
+        Liveness {
+            probe_interval: self
+                .probe_interval_ms
+                .map_or(defaults.probe_interval, Duration::from_millis),
+            failures_until_down: self
+                .failures_until_down
+                .unwrap_or(defaults.failures_until_down),
+            ..defaults
+        }
+    }
+
+    /// What becomes of a Nack from one face.
+    pub fn nack_policy(&self) -> NackPolicy {
+        self.on_nack.unwrap_or_default()
+    }
+
+    /// Whether faces too slow for what is left of an Interest's lifetime are skipped.
+    pub fn deadline_aware(&self) -> bool {
+        self.deadline_aware.unwrap_or(false)
+    }
+
+    /// How Interests are spread over the faces, if they are.
+    pub fn load_balance(&self) -> Option<LoadBalance> {
+        (self.strategy == Strategy::LoadBalance).then(|| LoadBalance {
+            weights: self.weights.clone().unwrap_or_default(),
+        })
+    }
+
+    /// When unanswered Interests are sent again, if they are.
+    pub fn retransmission(&self) -> Option<Retransmission> {
+        let defaults = Retransmission::default();
+        (self.strategy == Strategy::Retransmit).then(|| Retransmission {
+            timeout: self
+                .retransmit_ms
+                .map_or(defaults.timeout, Duration::from_millis),
+            max_retries: self.max_retries.unwrap_or(defaults.max_retries),
+        })
+    }
+}
+
+/// The strategy followed by the parameters set, as [`StrategyChoice::parse`] takes them.
+impl fmt::Display for StrategyChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.strategy)?;
+        let parameters = [
+            ("probe_interval_ms", self.probe_interval_ms),
+            (
+                "failures_until_down",
+                self.failures_until_down.map(u64::from),
+            ),
+            ("retransmit_ms", self.retransmit_ms),
+            ("max_retries", self.max_retries.map(u64::from)),
+        ];
+        for (key, value) in parameters {
+            if let Some(value) = value {
+                write!(f, " {key}={value}")?;
+            }
+        }
+        if let Some(on_nack) = self.on_nack {
+            write!(f, " on_nack={on_nack}")?;
+        }
+        if let Some(deadline_aware) = self.deadline_aware {
+            write!(f, " deadline_aware={deadline_aware}")?;
+        }
+        if let Some(weights) = &self.weights {
+            let weights: Vec<String> = weights
+                .iter()
+                .map(|(addr, weight)| format!("{addr}:{weight}"))
+                .collect();
+            write!(f, " weights={}", weights.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// `<address>:<weight>` pairs separated by commas.
+fn parse_weights(value: &str) -> anyhow::Result<BTreeMap<SocketAddr, u32>> {
+    value
+        .split(',')
+        .map(|pair| {
+            let parsed = pair
+                .rsplit_once(':')
+                .and_then(|(addr, weight)| Some((addr.parse().ok()?, weight.parse().ok()?)));
+            parsed.ok_or_else(|| {
+                anyhow::anyhow!("expected weights as <address>:<weight>, not '{pair}'")
+            })
+        })
+        .collect()
+}
+
+/// Strategy choices by prefix.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StrategyTable {
+    choices: BTreeMap<Name, StrategyChoice>,
+}
+
+impl StrategyTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose `choice` for the names under `prefix`, returning the choice it replaces.
+    pub fn insert(&mut self, prefix: Name, choice: StrategyChoice) -> Option<StrategyChoice> {
+        self.choices.insert(prefix, choice)
+    }
+
+    pub fn remove(&mut self, prefix: &Name) -> Option<StrategyChoice> {
+        self.choices.remove(prefix)
+    }
+
+    /// The choice made for `prefix` itself.
+    pub fn get(&self, prefix: &Name) -> Option<&StrategyChoice> {
+        self.choices.get(prefix)
+    }
+
+    /// The choice `name` falls under and the prefix it was made for, if any was.
+    pub fn lookup(&self, name: &Name) -> Option<(&Name, &StrategyChoice)> {
+        (0..=name.len())
+            .rev()
+            .find_map(|len| self.choices.get_key_value(&name.prefix(len)))
+    }
+
+    /// The choice in effect for `name`: its longest prefix's, or the default.
+    pub fn effective(&self, name: &Name) -> StrategyChoice {
+        self.lookup(name)
+            .map_or_else(StrategyChoice::default, |(_, choice)| choice.clone())
+    }
+
+    /// Every choice, in prefix order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Name, &StrategyChoice)> {
+        self.choices.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.choices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.choices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix_choice_takes_effect() {
+        let mut table = StrategyTable::new();
+        let video = StrategyChoice::parse(&[
+            "retransmit",
+            "retransmit_ms=200",
+            "on_nack=wait",
+            "deadline_aware=true",
+        ])
+        .unwrap();
+        table.insert(Name::from("/video"), video);
+        table.insert(
+            Name::from("/video/live"),
+            StrategyChoice::parse(&["failover", "probe_interval_ms=1000"]).unwrap(),
+        );
+
+        let choice = table.effective(&Name::from("/video/clip/seg=3"));
+        assert_eq!(
+            choice.to_string(),
+            "retransmit retransmit_ms=200 on_nack=wait deadline_aware=true"
+        );
+        assert_eq!(choice.nack_policy(), NackPolicy::Wait);
+        assert!(choice.deadline_aware());
+        let retransmission = choice.retransmission().unwrap();
+        assert_eq!(retransmission.timeout, Duration::from_millis(200));
+        assert_eq!(
+            retransmission.max_retries,
+            Retransmission::default().max_retries
+        );
+        let (prefix, live) = table.lookup(&Name::from("/video/live/seg=0")).unwrap();
+        assert_eq!(prefix, &Name::from("/video/live"));
+        assert_eq!(live.liveness().probe_interval, Duration::from_secs(1));
+        assert_eq!(live.retransmission(), None);
+        assert!(!live.deadline_aware());
+        assert_eq!(
+            table.effective(&Name::from("/other")),
+            StrategyChoice::default()
+        );
+
+        let balanced =
+            StrategyChoice::parse(&["load-balance", "weights=10.0.0.1:6363:3,[::1]:6363:1"])
+                .unwrap();
+        assert_eq!(
+            StrategyChoice::parse(&[
+                "load-balance",
+                balanced.to_string().split_once(' ').unwrap().1
+            ])
+            .unwrap(),
+            balanced
+        );
+        // As the daemon hands it to consumers
+        let json = serde_json::to_string(&balanced).unwrap();
+        assert_eq!(
+            serde_json::from_str::<StrategyChoice>(&json).unwrap(),
+            balanced
+        );
+        let balance = balanced.load_balance().unwrap();
+        assert_eq!(balance.weight("10.0.0.1:6363".parse().unwrap()), 3);
+        assert_eq!(balance.weight("10.0.0.9:6363".parse().unwrap()), 1);
+        assert_eq!(balanced.retransmission(), None);
+        assert_eq!(live.load_balance(), None);
+
+        assert!(StrategyChoice::parse(&["failover", "weights=10.0.0.1:6363:3"]).is_err());
+        assert!(StrategyChoice::parse(&["load-balance", "weights=10.0.0.1:6363:0"]).is_err());
+        assert!(StrategyChoice::parse(&["load-balance", "weights=10.0.0.1:3"]).is_err());
+        assert!(StrategyChoice::parse(&["failover", "retransmit_ms=200"]).is_err());
+        assert!(StrategyChoice::parse(&["multicast"]).is_err());
+        assert!(StrategyChoice::parse(&["failover", "on_nack=drop"]).is_err());
+        assert!(StrategyChoice::parse(&["retransmit", "max_retries"]).is_err());
+        assert!(StrategyChoice::parse(&["failover", "deadline_aware=yes"]).is_err());
+        assert!(StrategyChoice::parse(&["retransmit", "retransmit_ms=0"]).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Warnings for errors that can recur with every packet, such as failing to send to a face.
+//!
+//! Logging each one would let a peer that provokes them flood the log. Warnings are grouped by
+//! where they come from and the error's root cause, leaving out the names and addresses the
+//! message carries: the first of a kind in each interval, [`DEFAULT_WARNING_INTERVAL`] unless
+//! set, is logged in full, and the rest are counted and logged as one line once the interval is
+//! over, with the first's message. Past [`MAX_WARNING_KINDS`] kinds in an interval, warnings
+//! are only counted.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+/// How long warnings of a kind are counted rather than logged after one is.
+pub const DEFAULT_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Kinds of warning told apart in one interval.
+pub const MAX_WARNING_KINDS: usize = 256;
+
+/// The first warning of a kind in the interval, and how many followed it.
+struct Kind {
+    message: String,
+    repeated: u64,
+}
+
+struct State {
+    started: Instant,
+    kinds: HashMap<(&'static str, String), Kind>,
+    /// Warnings of kinds past [`MAX_WARNING_KINDS`].
+    overflow: u64,
+    suppressed: u64,
+}
+
+pub struct Warnings {
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self::new(DEFAULT_WARNING_INTERVAL)
+    }
+}
+
+impl Warnings {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(State {
+                started: Instant::now(),
+                kinds: HashMap::new(),
+                overflow: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Warnings counted rather than logged so far.
+    pub fn suppressed(&self) -> u64 {
+        self.state.lock().unwrap().suppressed
+    }
+
+    /// Log the warning `message` makes, unless one from `site` with the same `error` was logged
+    /// this interval.
+    pub fn warn(&self, site: &'static str, error: &dyn Display, message: impl FnOnce() -> String) {
+        self.warn_at(Instant::now(), site, error, message);
+    }
+
+    /// [`Warnings::warn`] at `now`. Returns whether the warning was logged.
+    fn warn_at(
+        &self,
+        now: Instant,
+        site: &'static str,
+        error: &dyn Display,
+        message: impl FnOnce() -> String,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.started) >= self.interval {
+            self.report(&mut state);
+            state.started = now;
+        }
+        let key = (site, error.to_string());
+        if let Some(kind) = state.kinds.get_mut(&key) {
+            kind.repeated += 1;
+        } else if state.kinds.len() >= MAX_WARNING_KINDS {
+            state.overflow += 1;
+        } else {
+            let message = message();
+            warn!("{message}");
+            state.kinds.insert(
+                key,
+                Kind {
+                    message,
+                    repeated: 0,
+                },
+            );
+            return true;
+        }
+        state.suppressed += 1;
+        false
+    }
+
+    /// Log how many warnings of each kind were counted this interval, and start a new one.
+    fn report(&self, state: &mut State) {
+        let secs = self.interval.as_secs();
+        for kind in state.kinds.values().filter(|kind| kind.repeated > 0) {
+            warn!(
+                "{} (and {} more like it in {secs}s)",
+                kind.message, kind.repeated
+            );
+        }
+        if state.overflow > 0 {
+            warn!("{} more warnings of other kinds in {secs}s", state.overflow);
+        }
+        state.kinds.clear();
+        state.overflow = 0;
+    }
+}
+
+impl Drop for Warnings {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        self.report(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_warnings_are_counted_once_logged() {
+        let warnings = Warnings::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let refused = "Connection refused";
+        let warn = |at: u64, site, error: &str, name: &str| {
+            warnings.warn_at(start + Duration::from_secs(at), site, &error, || {
+                format!("failed to answer Interest for '{name}': {error}")
+            })
+        };
+
+        assert!(warn(0, "answer", refused, "/a"));
+        // Other names, same error: the same kind
+        assert!(!warn(1, "answer", refused, "/b"));
+        assert!(!warn(2, "answer", refused, "/c"));
+        assert!(warn(3, "answer", "No buffer space available", "/a"));
+        assert!(warn(4, "refuse", refused, "/a"));
+        assert_eq!(warnings.suppressed(), 2);
+
+        // A new interval logs each kind again
+        assert!(warn(61, "answer", refused, "/d"));
+        assert!(!warn(62, "answer", refused, "/e"));
+
+        for i in 0..MAX_WARNING_KINDS {
+            warn(63, "answer", &i.to_string(), "/f");
+        }
+        assert_eq!(warnings.state.lock().unwrap().overflow, 1);
+        assert_eq!(warnings.suppressed(), 4);
+    }
+}
@@ -0,0 +1,117 @@
+//! Name component types and the URI form of a component, which need no allocator.
+
+use core::fmt;
+
+use crate::{tlv, TlvType};
+
+/// TLV type of a plain name component.
+pub const GENERIC_COMPONENT: u64 = TlvType::NameComponent as u64;
+/// SHA-256 digest of the whole Data packet, written `sha256digest=<hex>` in URIs.
+pub const IMPLICIT_SHA256_DIGEST_COMPONENT: u64 = 0x01;
+/// SHA-256 digest of an Interest's parameters, written `params-sha256=<hex>` in URIs.
+pub const PARAMETERS_SHA256_DIGEST_COMPONENT: u64 = 0x02;
+
+/// Typed name components from the NDN naming conventions (rev3). Each carries a
+/// NonNegativeInteger value.
+pub const SEGMENT_COMPONENT: u64 = 0x32;
+pub const BYTE_OFFSET_COMPONENT: u64 = 0x34;
+pub const VERSION_COMPONENT: u64 = 0x36;
+pub const TIMESTAMP_COMPONENT: u64 = 0x38;
+pub const SEQUENCE_NUM_COMPONENT: u64 = 0x3A;
+
+/// A naming convention: the typed component carrying it, its URI label and the marker byte
+/// that identified it in the older generic-component convention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Convention {
+    Segment,
+    ByteOffset,
+    Version,
+    Timestamp,
+    SequenceNum,
+}
+
+impl Convention {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Segment,
+        Self::ByteOffset,
+        Self::Version,
+        Self::Timestamp,
+        Self::SequenceNum,
+    ];
+
+    pub fn component_type(self) -> u64 {
+        match self {
+            Self::Segment => SEGMENT_COMPONENT,
+            Self::ByteOffset => BYTE_OFFSET_COMPONENT,
+            Self::Version => VERSION_COMPONENT,
+            Self::Timestamp => TIMESTAMP_COMPONENT,
+            Self::SequenceNum => SEQUENCE_NUM_COMPONENT,
+        }
+    }
+
+    /// Label used for the component in name URIs, as in `/video/v=3/seg=0`.
+    pub fn uri_label(self) -> &'static str {
+        match self {
+            Self::Segment => "seg",
+            Self::ByteOffset => "off",
+            Self::Version => "v",
+            Self::Timestamp => "t",
+            Self::SequenceNum => "seq",
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub(crate) fn marker(self) -> u8 {
+        match self {
+            Self::Segment => 0x00,
+            Self::ByteOffset => 0xFB,
+            Self::Version => 0xFD,
+            Self::Timestamp => 0xFC,
+            Self::SequenceNum => 0xFE,
+        }
+    }
+
+    pub fn from_component_type(typ: u64) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|convention| convention.component_type() == typ)
+    }
+}
+
+/// Write the canonical URI form of a component to `f`: typed components use their `label=`
+/// syntax and values are percent-encoded except for unreserved characters.
+pub fn write_uri(f: &mut impl fmt::Write, typ: u64, value: &[u8]) -> fmt::Result {
+    if let Some(convention) = Convention::from_component_type(typ) {
+        if let Some(number) = tlv::read_nonneg_int(value) {
+            return write!(f, "{}={}", convention.uri_label(), number);
+        }
+    }
+    match typ {
+        IMPLICIT_SHA256_DIGEST_COMPONENT | PARAMETERS_SHA256_DIGEST_COMPONENT
+            if value.len() == 32 =>
+        {
+            let label = if typ == IMPLICIT_SHA256_DIGEST_COMPONENT {
+                "sha256digest"
+            } else {
+                "params-sha256"
+            };
+            write!(f, "{label}=")?;
+            return value.iter().try_for_each(|b| write!(f, "{b:02x}"));
+        }
+        GENERIC_COMPONENT => {}
+        _ => write!(f, "{typ}=")?,
+    }
+
+    // Components made only of periods get three extra so they can't read as `.` or `..`
+    if value.iter().all(|&b| b == b'.') {
+        f.write_str("...")?;
+    }
+    for &b in value {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            write!(f, "{}", b as char)?;
+        } else {
+            write!(f, "%{b:02X}")?;
+        }
+    }
+    Ok(())
+}
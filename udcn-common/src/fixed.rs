@@ -0,0 +1,284 @@
+//! Encoding packets into a caller's buffer, for targets without an allocator.
+//!
+//! The owned [`Interest`](crate::Interest) and [`Data`](crate::Data) need the `alloc` feature.
+//! Here a name is a slice of borrowed [`Element`]s, one per component, and a packet is written
+//! into a `&mut [u8]`: each encoder returns how many bytes it wrote, or `None` if the buffer is
+//! too small. The bytes are the ones the owned types encode to, so nothing downstream can tell
+//! the two apart. Nonces are the caller's to pick, and signing is done over the bytes
+//! [`DataRef::signed_portion_into`] writes.
+
+use core::fmt;
+
+use crate::{
+    component, hash_name, tlv,
+    tlv::{Element, SliceWriter},
+    DataPacket, InterestPacket, NackPacket, NackReason, TlvType, FNV_OFFSET_BASIS, FNV_PRIME,
+};
+
+/// FNV-1a over whatever is written to it, as [`hash_name`] computes it over a whole URI.
+struct UriHasher(u32);
+
+impl fmt::Write for UriHasher {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 ^= byte as u32;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+        Ok(())
+    }
+}
+
+/// The hash the datapath keys its tables with for the name made of `components`, as
+/// `Name::name_hash` computes it, without building the URI.
+pub fn name_hash(components: &[Element]) -> u32 {
+    if components.is_empty() {
+        return hash_name(b"/");
+    }
+    let mut hasher = UriHasher(FNV_OFFSET_BASIS);
+    for component in components {
+        // Writing to the hasher cannot fail
+        let _ = fmt::Write::write_str(&mut hasher, "/");
+        let _ = component::write_uri(&mut hasher, component.typ, component.value);
+    }
+    hasher.0
+}
+
+fn name_value_len(components: &[Element]) -> usize {
+    components
+        .iter()
+        .map(|component| tlv::element_len(component.typ, component.value.len()))
+        .sum()
+}
+
+fn name_len(components: &[Element]) -> usize {
+    tlv::element_len(TlvType::Name as u64, name_value_len(components))
+}
+
+/// Write the Name element for `components`.
+pub fn write_name(out: &mut SliceWriter, components: &[Element]) -> Option<()> {
+    if name_len(components) > out.remaining() {
+        return None;
+    }
+    out.write_element_header(TlvType::Name as u64, name_value_len(components))?;
+    for component in components {
+        out.write_element(component.typ, component.value)?;
+    }
+    Some(())
+}
+
+/// An Interest borrowing its name, without the forwarding hint the owned one may carry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterestRef<'a> {
+    pub name: &'a [Element<'a>],
+    pub nonce: u32,
+    pub can_be_prefix: bool,
+    pub must_be_fresh: bool,
+    pub lifetime_ms: Option<u64>,
+}
+
+impl InterestRef<'_> {
+    pub fn header(&self) -> InterestPacket {
+        InterestPacket::new(name_hash(self.name), self.nonce)
+    }
+
+    /// Encode the Interest into `buf`, returning its length.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut out = SliceWriter::new(buf);
+        out.write(&self.header().to_bytes())?;
+        write_name(&mut out, self.name)?;
+        if self.can_be_prefix {
+            out.write_element(TlvType::CanBePrefix as u64, &[])?;
+        }
+        if self.must_be_fresh {
+            out.write_element(TlvType::MustBeFresh as u64, &[])?;
+        }
+        if let Some(lifetime_ms) = self.lifetime_ms {
+            out.write_nonneg_int_element(TlvType::InterestLifetime as u64, lifetime_ms)?;
+        }
+        Some(out.len())
+    }
+}
+
+/// A Data borrowing its name, content and signature.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DataRef<'a> {
+    pub name: &'a [Element<'a>],
+    pub content: &'a [u8],
+    pub content_type: Option<u64>,
+    pub freshness_period_ms: Option<u64>,
+    /// Without one, the Data carries no SignatureInfo.
+    pub signature_type: Option<u64>,
+    pub key_locator: Option<&'a [Element<'a>]>,
+    pub signature_value: &'a [u8],
+}
+
+impl DataRef<'_> {
+    /// The fixed header the datapath sees for this Data, or `None` if the content is larger than
+    /// it can describe.
+    pub fn header(&self) -> Option<DataPacket> {
+        let content_size = u16::try_from(self.content.len()).ok()?;
+        Some(DataPacket::new(
+            name_hash(self.name),
+            content_size,
+            hash_name(self.signature_value),
+        ))
+    }
+
+    fn meta_value_len(&self) -> usize {
+        let content_type = self.content_type.map_or(0, |typ| {
+            tlv::nonneg_int_element_len(TlvType::ContentType as u64, typ)
+        });
+        let freshness = self.freshness_period_ms.map_or(0, |ms| {
+            tlv::nonneg_int_element_len(TlvType::FreshnessPeriod as u64, ms)
+        });
+        content_type + freshness
+    }
+
+    /// Write the MetaInfo element, if those fields call for one.
+    fn write_meta(&self, out: &mut SliceWriter) -> Option<()> {
+        if self.content_type.is_some() || self.freshness_period_ms.is_some() {
+            out.write_element_header(TlvType::MetaInfo as u64, self.meta_value_len())?;
+            if let Some(content_type) = self.content_type {
+                out.write_nonneg_int_element(TlvType::ContentType as u64, content_type)?;
+            }
+            if let Some(freshness_period_ms) = self.freshness_period_ms {
+                out.write_nonneg_int_element(TlvType::FreshnessPeriod as u64, freshness_period_ms)?;
+            }
+        }
+        Some(())
+    }
+
+    fn write_signature_info(&self, out: &mut SliceWriter) -> Option<()> {
+        let Some(signature_type) = self.signature_type else {
+            return Some(());
+        };
+        let mut value_len =
+            tlv::nonneg_int_element_len(TlvType::SignatureType as u64, signature_type);
+        if let Some(key_locator) = self.key_locator {
+            value_len += tlv::element_len(TlvType::KeyLocator as u64, name_len(key_locator));
+        }
+        out.write_element_header(TlvType::SignatureInfo as u64, value_len)?;
+        out.write_nonneg_int_element(TlvType::SignatureType as u64, signature_type)?;
+        if let Some(key_locator) = self.key_locator {
+            out.write_element_header(TlvType::KeyLocator as u64, name_len(key_locator))?;
+            write_name(out, key_locator)?;
+        }
+        Some(())
+    }
+
+    /// Write the Name, MetaInfo, Content and SignatureInfo elements a signature covers into
+    /// `buf`, returning their length.
+    pub fn signed_portion_into(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut out = SliceWriter::new(buf);
+        write_name(&mut out, self.name)?;
+        self.write_meta(&mut out)?;
+        out.write_element(TlvType::Content as u64, self.content)?;
+        self.write_signature_info(&mut out)?;
+        Some(out.len())
+    }
+
+    /// Encode the Data into `buf`, returning its length.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut out = SliceWriter::new(buf);
+        out.write(&self.header()?.to_bytes())?;
+        out.write(self.content)?;
+        write_name(&mut out, self.name)?;
+        self.write_meta(&mut out)?;
+        self.write_signature_info(&mut out)?;
+        if !self.signature_value.is_empty() {
+            out.write_element(TlvType::SignatureValue as u64, self.signature_value)?;
+        }
+        Some(out.len())
+    }
+}
+
+/// Encode a Nack for `interest` into `buf`, returning its length.
+pub fn encode_nack_into(
+    interest: &InterestPacket,
+    reason: NackReason,
+    buf: &mut [u8],
+) -> Option<usize> {
+    let mut out = SliceWriter::new(buf);
+    out.write(&NackPacket::for_interest(interest, reason).to_bytes())?;
+    Some(out.len())
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::{Data, Interest, Name, SIGNATURE_HMAC_WITH_SHA256};
+
+    #[test]
+    fn test_fixed_encoding_matches_owned_packets() {
+        let name = Name::from_uri("/sensors/room%201/seq=42").unwrap();
+        let components: alloc::vec::Vec<Element> = name
+            .components()
+            .iter()
+            .map(|component| Element {
+                typ: component.typ,
+                value: &component.value,
+            })
+            .collect();
+        assert_eq!(name_hash(&components), name.name_hash());
+        assert_eq!(name_hash(&[]), Name::new().name_hash());
+
+        let interest = InterestRef {
+            name: &components,
+            nonce: 7,
+            can_be_prefix: true,
+            must_be_fresh: false,
+            lifetime_ms: Some(4000),
+        };
+        let owned = Interest::builder()
+            .name(name.clone())
+            .nonce(7)
+            .can_be_prefix(true)
+            .lifetime_ms(4000)
+            .build()
+            .encode();
+        let mut buf = [0u8; 128];
+        let len = interest.encode_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &owned[..]);
+        assert_eq!(interest.encode_into(&mut buf[..owned.len() - 1]), None);
+
+        let locator = [Element {
+            typ: crate::name::GENERIC_COMPONENT,
+            value: b"KEY",
+        }];
+        let data = DataRef {
+            name: &components,
+            content: b"21.5",
+            freshness_period_ms: Some(1000),
+            signature_type: Some(SIGNATURE_HMAC_WITH_SHA256),
+            key_locator: Some(&locator),
+            signature_value: &[0xAB; 32],
+            ..DataRef::default()
+        };
+        let owned = Data::builder()
+            .name(name.clone())
+            .content(&b"21.5"[..])
+            .freshness_period_ms(1000)
+            .signature_info(
+                SIGNATURE_HMAC_WITH_SHA256,
+                Some(Name::from_uri("/KEY").unwrap()),
+            )
+            .sign_with(|_| alloc::vec![0xAB; 32])
+            .build();
+        let len = data.encode_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &owned.encode()[..]);
+        let len = data.signed_portion_into(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &owned.signed_portion()[..]);
+        assert_eq!(data.encode_into(&mut buf[..40]), None);
+
+        let header = interest.header();
+        let len = encode_nack_into(&header, NackReason::NoData, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            &crate::serialize_nack(&header, NackReason::NoData)[..]
+        );
+        assert_eq!(
+            encode_nack_into(&header, NackReason::NoData, &mut buf[..2]),
+            None
+        );
+    }
+}
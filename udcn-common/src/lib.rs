@@ -2,23 +2,133 @@
 
 use core::mem;
 
+pub mod component;
+pub mod fixed;
+#[cfg(feature = "alloc")]
+pub mod lp;
+#[cfg(feature = "alloc")]
+pub mod name;
+#[cfg(feature = "alloc")]
+pub mod ndn03;
+#[cfg(feature = "alloc")]
+pub mod packet;
+pub mod pit;
+pub mod tlv;
+
+pub use component::Convention;
+#[cfg(feature = "alloc")]
+pub use name::{Component, Name, NameError};
+#[cfg(feature = "alloc")]
+pub use ndn03::{ConvertError, Format};
+#[cfg(feature = "alloc")]
+pub use packet::{
+    Data, DataBuilder, Interest, InterestBuilder, MetaInfo, SignatureInfo, MAX_CONTENT_LEN,
+};
+pub use pit::PitLayout;
+
 pub const NDN_ETHERTYPE: u16 = 0x8624;
 pub const NDN_UDP_PORT: u16 = 6363;
+/// bpffs directory where the daemon pins its maps, one file per map name.
+pub const MAP_PIN_DIR: &str = "/sys/fs/bpf/udcn";
+
+/// Entries the PIT holds.
+pub const PIT_CAPACITY: u32 = 1024;
+/// Downstream faces one PIT entry can wait on at once.
+pub const PIT_MAX_FACES: usize = 4;
+/// Lifetime of an Interest that carries no InterestLifetime, in milliseconds.
+pub const DEFAULT_INTEREST_LIFETIME_MS: u32 = 4000;
+/// Entries `DATA_CACHE` holds.
+pub const DATA_CACHE_CAPACITY: u32 = 512;
+/// Bytes of a Data packet a `DATA_CACHE` entry keeps.
+pub const DATA_CACHE_PAYLOAD_LEN: usize = 256;
+/// Content store entries across all partitions.
+pub const CS_CAPACITY: u32 = 512;
+/// Prefixes `ROUTES` holds, for `run --nack-unroutable`, unless `--autosize` sizes it.
+pub const ROUTES_CAPACITY: u32 = 1024;
+/// Most prefixes that can be given a reserved share of the content store.
+pub const MAX_CS_PARTITIONS: usize = 4;
+/// Longest first name component a partition can be selected by.
+pub const MAX_CS_PARTITION_COMPONENT_LEN: usize = 32;
+/// The LRU map holding each reserved partition, indexed by partition number minus one. Names
+/// outside every reserved prefix share `CONTENT_STORE` (partition 0).
+pub const CS_PARTITION_MAPS: [&str; MAX_CS_PARTITIONS] =
+    ["CS_PARTITION_1", "CS_PARTITION_2", "CS_PARTITION_3", "CS_PARTITION_4"];
+
+/// Most prefixes that can be kept out of the content store (`run --no-cache`).
+pub const MAX_NO_CACHE_PREFIXES: u32 = 64;
+/// Most components, and longest component value, a non-cacheable prefix can have.
+pub const MAX_NO_CACHE_PREFIX_COMPONENTS: usize = 4;
+pub const MAX_NO_CACHE_COMPONENT_LEN: usize = 32;
+/// Entries `CS_PINNED` holds, on top of the LRU partitions.
+pub const CS_PINNED_CAPACITY: u32 = 256;
+/// Most names, and most prefixes, that can be pinned in the content store.
+pub const MAX_CS_PINS: u32 = 64;
+/// MetaInfo ContentType a producer gives Data that forwarders must not cache, a µDCN extension.
+pub const CONTENT_TYPE_NO_CACHE: u64 = 0x4e43;
+/// MetaInfo ContentType of a certificate, whose content is a public or shared key.
+pub const CONTENT_TYPE_KEY: u64 = 2;
+
+/// SignatureType of a Data signed with the SHA-256 of its signed portion, which needs no key.
+pub const SIGNATURE_DIGEST_SHA256: u64 = 0;
+pub const SIGNATURE_SHA256_WITH_RSA: u64 = 1;
+pub const SIGNATURE_SHA256_WITH_ECDSA: u64 = 3;
+pub const SIGNATURE_HMAC_WITH_SHA256: u64 = 4;
+
+/// Slots in the counting filter of recent content store misses used for popularity admission.
+pub const CS_MISS_FILTER_SLOTS: u32 = 4096;
+
+/// 64-bit words in each half of the `CS_BLOOM` filter over cached name hashes.
+pub const CS_BLOOM_WORDS: u32 = 256;
+pub const CS_BLOOM_BITS: u32 = CS_BLOOM_WORDS * 64;
+
+/// Other XDP programs the datapath can hand packets on to, one per interface (`run --chain`).
+pub const MAX_CHAINED_PROGRAMS: u32 = 8;
+
+pub const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+pub const FNV_PRIME: u32 = 0x01000193;
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TlvType {
+    /// µDCN-native Nack. Standard NDN carries Nacks in an NDNLPv2 header instead.
+    Nack = 0x03,
     Interest = 0x05,
     Data = 0x06,
     Name = 0x07,
     NameComponent = 0x08,
     Nonce = 0x0A,
+    InterestLifetime = 0x0C,
+    MustBeFresh = 0x12,
     Content = 0x15,
     MetaInfo = 0x14,
     SignatureInfo = 0x16,
     SignatureValue = 0x17,
+    ContentType = 0x18,
+    FreshnessPeriod = 0x19,
+    SignatureType = 0x1B,
+    KeyLocator = 0x1C,
+    ForwardingHint = 0x1E,
+    CanBePrefix = 0x21,
+    ApplicationParameters = 0x24,
+    // NDNLPv2 link protocol, used to fragment packets larger than a face's MTU
+    Fragment = 0x50,
+    Sequence = 0x51,
+    FragIndex = 0x52,
+    FragCount = 0x53,
+    LpPacket = 0x64,
+    // µDCN tracing extension to the link protocol: the trace ID of a traced packet, and the
+    // report each forwarder on its path sends back to the consumer
+    TraceId = 0x54,
+    HopReport = 0x55,
+    HopNode = 0x56,
+    HopPacketType = 0x57,
+    HopNameHash = 0x58,
+    HopTimestamp = 0x59,
 }
 
+/// NDNLPv2 CongestionMark, whose type number does not fit a [`TlvType`].
+pub const CONGESTION_MARK: u64 = 0x0340;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct TlvHeader {
@@ -50,24 +160,793 @@ pub struct DataPacket {
     pub signature: u32,
 }
 
+/// Reason carried by a Nack, using the NDNLPv2 code points where one exists.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NackReason {
+    Congestion = 50,
+    Duplicate = 100,
+    NoRoute = 150,
+    /// The producer is reachable but has no Data under the requested name (µDCN extension).
+    NoData = 151,
+    /// The Interest's lifetime ran out while it was pending (µDCN extension).
+    Expired = 152,
+    /// No nexthop was expected to answer within what remained of the Interest's lifetime, so it
+    /// was not sent (µDCN extension).
+    TooLate = 153,
+}
+
+impl NackReason {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            50 => Some(Self::Congestion),
+            100 => Some(Self::Duplicate),
+            150 => Some(Self::NoRoute),
+            151 => Some(Self::NoData),
+            152 => Some(Self::Expired),
+            153 => Some(Self::TooLate),
+            _ => None,
+        }
+    }
+
+    /// Whether this reason says the content is unreachable, so the answer may be negatively cached.
+    pub fn is_cacheable(self) -> bool {
+        matches!(self, Self::NoRoute | Self::NoData)
+    }
+}
+
+/// Nack for an Interest. It has the same size and field offsets as `InterestPacket`, so the XDP
+/// program can turn a received Interest into a Nack in place.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
+pub struct NackPacket {
+    pub header: NdnPacketHeader,
+    pub reason: u8,
+    pub reserved: u8,
+    pub name_hash: u32,
+    pub nonce: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PitEntry {
     pub name_hash: u32,
+    /// The face the first Interest came from.
     pub face_id: u32,
+    /// When the entry was created, in `bpf_ktime_get_ns` time.
     pub timestamp: u64,
+    /// The downstream faces waiting for the Data, each until its own Interest lifetime runs
+    /// out. Free slots have a `face_id` of 0.
+    pub records: [PitRecord; PIT_MAX_FACES],
+    /// The tenant whose quota the entry counts against, 0 for none.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tenant: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reserved: u32,
+}
+
+/// One downstream face of a PIT entry. The address and port are kept so the daemon can send the
+/// face a Nack when the record lapses, since a face id cannot be turned back into them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PitRecord {
+    pub face_id: u32,
+    /// Nonce of the face's latest Interest, echoed in the Nack.
+    pub nonce: u32,
+    /// IPv4 source address and UDP source port, in network byte order.
+    pub addr: [u8; 4],
+    /// The address the Interest was sent to, which a Nack to the face comes from. Zero leaves the
+    /// choice to the kernel.
+    pub local_addr: [u8; 4],
+    pub port: [u8; 2],
+    pub reserved: [u8; 6],
+    /// When the face's Interest lifetime runs out, in `bpf_ktime_get_ns` time.
+    pub expires_at: u64,
+}
+
+impl PitRecord {
+    pub fn is_free(&self) -> bool {
+        self.face_id == 0
+    }
+}
+
+/// A downstream record the datapath found lapsed, published on the `PIT_LAPSED` ring buffer for
+/// the daemon to Nack.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LapsedRecord {
+    pub name_hash: u32,
+    pub reserved: u32,
+    pub record: PitRecord,
+}
+
+impl PitEntry {
+    pub fn new(name_hash: u32, record: PitRecord, now: u64) -> Self {
+        let mut records = [PitRecord::default(); PIT_MAX_FACES];
+        records[0] = record;
+        Self {
+            name_hash,
+            face_id: record.face_id,
+            timestamp: now,
+            records,
+            tenant: 0,
+            reserved: 0,
+        }
+    }
+
+    /// Add `record`, or refresh the one its face already has. Records that lapsed by `now` are
+    /// handed to `lapsed` and freed first. Returns false if every slot is taken by a live record.
+    #[inline(always)]
+    pub fn add_downstream(
+        &mut self,
+        record: PitRecord,
+        now: u64,
+        lapsed: impl FnMut(&PitRecord),
+    ) -> bool {
+        // A face retransmitting after its lifetime ran out just gets a new lifetime
+        for slot in self.records.iter_mut() {
+            if slot.face_id == record.face_id {
+                *slot = record;
+                return true;
+            }
+        }
+        self.expire(now, lapsed);
+        for slot in self.records.iter_mut() {
+            if slot.is_free() {
+                *slot = record;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Hand every record that lapsed by `now` to `lapsed` and free it.
+    #[inline(always)]
+    pub fn expire(&mut self, now: u64, mut lapsed: impl FnMut(&PitRecord)) {
+        for slot in self.records.iter_mut() {
+            if !slot.is_free() && slot.expires_at <= now {
+                lapsed(slot);
+                *slot = PitRecord::default();
+            }
+        }
+    }
+
+    /// Whether no downstream face is waiting any more.
+    pub fn is_empty(&self) -> bool {
+        self.records.iter().all(PitRecord::is_free)
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CacheEntry {
     pub name_hash: u32,
     pub data_size: u16,
+    /// Non-zero when the daemon fetched the Data ahead of demand rather than a face asking for it.
+    pub prefetched: u8,
+    /// The tenant whose quota the entry counts against, 0 for none.
+    #[cfg_attr(feature = "serde", serde(alias = "reserved"))]
+    pub tenant: u8,
+    /// When the Data was cached (bpf_ktime ns), which is also the entry's version: a
+    /// `CachedPayload` belongs to the entry whose timestamp it carries.
     pub timestamp: u64,
+    /// When the Data's FreshnessPeriod runs out (bpf_ktime ns). Until then it may answer
+    /// Interests with MustBeFresh; after, only those without.
+    pub fresh_until: u64,
+    /// Non-zero in `CS_PINNED`, which holds the Data of pinned names and prefixes outside the
+    /// LRU partitions, so that nothing evicts it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pinned: u8,
+    /// Skipped, as `tenant` once went by this name.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub reserved: [u8; 7],
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self, now: u64) -> bool {
+        now < self.fresh_until
+    }
+}
+
+/// What `DATA_CACHE` holds for a name, which userspace writes: the start of the cached Data, for
+/// the version of its content store entry.
+///
+/// The datapath and the daemon both cache Data, so the two must never disagree on which copy of
+/// a name is cached. Whichever caches a new copy deletes the name's payload. A payload written for
+/// another copy, as the daemon may race with the datapath, is older than its entry: the datapath
+/// never answers with it, and deletes it when it finds it. The datapath also announces each copy
+/// it caches with a `Cached` event while anyone listens, as the daemon does while it imports
+/// payloads, deleting those the announced copies replaced.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CachedPayload {
+    /// The `CacheEntry::timestamp` of the entry the payload was written for.
+    pub version: u64,
+    pub data: [u8; DATA_CACHE_PAYLOAD_LEN],
+}
+
+impl CachedPayload {
+    /// Whether the payload is of the copy of the Data `entry` describes.
+    pub fn belongs_to(&self, entry: &CacheEntry) -> bool {
+        self.version == entry.timestamp
+    }
 }
 
+/// Remembered NoRoute/NoData answer for a name, valid until `expires_at` (bpf_ktime ns).
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
+pub struct NegativeCacheEntry {
+    pub expires_at: u64,
+    pub reason: u8,
+    pub reserved: [u8; 7],
+}
+
+/// Key of the recently-seen Interest table used for duplicate nonce detection.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NonceKey {
+    pub name_hash: u32,
+    pub nonce: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct NonceRecord {
+    pub seen_at: u64,
+    pub face_id: u32,
+    pub reserved: u32,
+}
+
+/// Which Data the datapath admits into the content store.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CsAdmission {
+    /// Everything that satisfies a PIT entry.
+    #[default]
+    Always = 0,
+    /// Each Data with probability `cs_admission_probability`.
+    Probabilistic = 1,
+    /// Data whose name missed the content store `cs_admission_threshold` times within the
+    /// current window, keeping one-hit wonders out.
+    Popularity = 2,
+}
+
+impl CsAdmission {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Always),
+            1 => Some(Self::Probabilistic),
+            2 => Some(Self::Popularity),
+            _ => None,
+        }
+    }
+}
+
+/// How much the XDP program logs through aya-log. The values match `aya_log_ebpf::Level`, with 0
+/// turning logging off.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub enum DatapathLogLevel {
+    #[default]
+    Off = 0,
+    /// Conditions that should never happen, like a missing map entry.
+    Error = 1,
+    /// Drops.
+    Warn = 2,
+    /// Forwarding decisions.
+    Info = 3,
+    /// Map lookups and updates.
+    Debug = 4,
+    /// Parse results of every NDN packet.
+    Trace = 5,
+}
+
+/// The log target datapath records are emitted under, so they can be filtered apart from the
+/// daemon's own.
+pub const DATAPATH_LOG_TARGET: &str = "udcn::datapath";
+
+/// Slot of the content store miss filter: misses counted during admission window `window`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MissCounter {
+    pub window: u32,
+    pub count: u32,
+}
+
+impl MissCounter {
+    /// Count a miss in `window`, starting over if the slot last counted one in another window.
+    pub fn record(&mut self, window: u32) {
+        if self.window == window {
+            self.count = self.count.saturating_add(1);
+        } else {
+            *self = Self { window, count: 1 };
+        }
+    }
+
+    /// The misses counted in `window`.
+    pub fn misses(&self, window: u32) -> u32 {
+        if self.window == window {
+            self.count
+        } else {
+            0
+        }
+    }
+}
+
+/// The two `CS_MISSES` slots of `name_hash`; a name's miss count is the smaller of the two.
+pub fn cs_miss_slots(name_hash: u32) -> [u32; 2] {
+    [
+        name_hash % CS_MISS_FILTER_SLOTS,
+        name_hash.wrapping_mul(0x9e37_79b1).rotate_left(16) % CS_MISS_FILTER_SLOTS,
+    ]
+}
+
+/// Why the datapath dropped a packet, indexing `PacketStats::drops`. Interests under no route
+/// are Nacked rather than dropped (`PacketStats::unroutable_nacks`), and hop limits and scopes are
+/// not implemented in the datapath yet, so `NoFibMatch`, `Hoplimit` and `ScopeViolation` stay at
+/// zero.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DropReason {
+    /// Shorter than the header its packet type announces.
+    Malformed = 0,
+    /// No FIB entry matches the Interest.
+    NoFibMatch = 1,
+    /// The PIT, or the tenant's share of it, had no room for the Interest.
+    PitFull = 2,
+    /// Over a rate limit, such as a tenant's Interest rate quota.
+    RateLimited = 3,
+    /// The Interest ran out of hops.
+    Hoplimit = 4,
+    /// Sent outside the scope its name is restricted to.
+    ScopeViolation = 5,
+    /// The Interest repeats a (name, nonce) pair from the same face.
+    DuplicateNonce = 6,
+    /// Data that no PIT entry is waiting for.
+    UnsolicitedData = 7,
+    /// Dropped on purpose by fault injection.
+    Injected = 8,
+    /// A UDP datagram whose checksum is wrong, while checksums are verified.
+    BadChecksum = 9,
+    /// A packet whose name a prefix ACL denies.
+    AclDropped = 10,
+    /// A packet from a source address greylisted for misbehaving.
+    Greylisted = 11,
+}
+
+pub const DROP_REASONS: usize = 12;
+
+impl DropReason {
+    pub const ALL: [Self; DROP_REASONS] = [
+        Self::Malformed,
+        Self::NoFibMatch,
+        Self::PitFull,
+        Self::RateLimited,
+        Self::Hoplimit,
+        Self::ScopeViolation,
+        Self::DuplicateNonce,
+        Self::UnsolicitedData,
+        Self::Injected,
+        Self::BadChecksum,
+        Self::AclDropped,
+        Self::Greylisted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::NoFibMatch => "no_fib_match",
+            Self::PitFull => "pit_full",
+            Self::RateLimited => "rate_limited",
+            Self::Hoplimit => "hoplimit",
+            Self::ScopeViolation => "scope_violation",
+            Self::DuplicateNonce => "duplicate_nonce",
+            Self::UnsolicitedData => "unsolicited_data",
+            Self::Injected => "injected",
+            Self::BadChecksum => "bad_checksum",
+            Self::AclDropped => "acl_dropped",
+            Self::Greylisted => "greylisted",
+        }
+    }
+}
+
+/// What the datapath did with a packet, as reported in a `DatapathEvent`.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventOutcome {
+    /// Passed on to the network stack.
+    Forwarded = 0,
+    /// Answered from the content store.
+    CacheHit = 1,
+    /// Answered with a cached Nack; `DatapathEvent::reason` is its `NackReason`.
+    NegativeCacheHit = 2,
+    /// Data that satisfied a PIT entry and entered the content store.
+    Cached = 3,
+    /// Data that satisfied a PIT entry but was refused by the admission policy.
+    NotCached = 4,
+    /// Dropped; `DatapathEvent::reason` is the `DropReason`.
+    Dropped = 5,
+    /// Data that satisfied a PIT entry but is marked non-cacheable, by its namespace or its
+    /// ContentType.
+    Uncacheable = 6,
+    /// Carried a trace ID; `DatapathEvent::trace_id` is set. Traced packets are passed on to the
+    /// network stack without touching the PIT or content store.
+    Traced = 7,
+    /// An Interest under no route, answered with a NoRoute Nack.
+    Unroutable = 8,
+}
+
+impl EventOutcome {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Forwarded),
+            1 => Some(Self::CacheHit),
+            2 => Some(Self::NegativeCacheHit),
+            3 => Some(Self::Cached),
+            4 => Some(Self::NotCached),
+            5 => Some(Self::Dropped),
+            6 => Some(Self::Uncacheable),
+            7 => Some(Self::Traced),
+            8 => Some(Self::Unroutable),
+            _ => None,
+        }
+    }
+}
+
+/// What the offloaded classifier (`run --xdp-mode offload`) took a packet for; its index in the
+/// NIC's `OFFLOAD_STATS` counters.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OffloadClass {
+    /// Not NDN over UDP port 6363, or too short to tell.
+    Other = 0,
+    Interest = 1,
+    Data = 2,
+    Nack = 3,
+    LpPacket = 4,
+}
+
+pub const OFFLOAD_CLASSES: u32 = 5;
+
+impl OffloadClass {
+    pub const ALL: [Self; OFFLOAD_CLASSES as usize] =
+        [Self::Other, Self::Interest, Self::Data, Self::Nack, Self::LpPacket];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Interest => "interest",
+            Self::Data => "data",
+            Self::Nack => "nack",
+            Self::LpPacket => "lp_packet",
+        }
+    }
+}
+
+/// The datapath's clock against wall-clock time, in the `TIME_BASE` map: `bpf_ktime_get_ns`, which
+/// counts from boot as CLOCK_MONOTONIC does, and CLOCK_REALTIME read at the same instant. The
+/// daemon writes it once, when it attaches, and converts every timestamp from the maps and events
+/// with it, so conversions agree with each other however the wall clock is stepped meanwhile.
+/// Programs reading the pinned maps should use it rather than pair up clock readings of their own.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeBase {
+    pub monotonic_ns: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub realtime_ns: u64,
+}
+
+impl TimeBase {
+    /// A `bpf_ktime_get_ns` timestamp as nanoseconds since the Unix epoch. Timestamps from before
+    /// the base was taken convert too.
+    pub fn realtime_ns(&self, monotonic_ns: u64) -> u64 {
+        self.realtime_ns
+            .wrapping_add(monotonic_ns.wrapping_sub(self.monotonic_ns))
+    }
+
+    /// Nanoseconds since the Unix epoch as a `bpf_ktime_get_ns` timestamp.
+    pub fn monotonic_ns(&self, realtime_ns: u64) -> u64 {
+        self.monotonic_ns
+            .wrapping_add(realtime_ns.wrapping_sub(self.realtime_ns))
+    }
+}
+
+/// One forwarding decision, published on the `EVENTS` ring buffer while the daemon has
+/// subscribers. Aligned so 32-bit hosts, where a u64 may only need 4-byte alignment, see the
+/// same trailing padding as the datapath.
+#[repr(C, align(8))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DatapathEvent {
+    /// `bpf_ktime_get_ns` when the packet was handled.
+    pub timestamp_ns: u64,
+    pub name_hash: u32,
+    /// The face the packet arrived on.
+    pub face_id: u32,
+    /// The packet's `TlvType`.
+    pub packet_type: u8,
+    /// An `EventOutcome`.
+    pub outcome: u8,
+    /// The `DropReason` of a drop, or the `NackReason` of a Nack.
+    pub reason: u8,
+    pub reserved: u8,
+    /// Bytes in the frame, headers included.
+    pub packet_len: u32,
+    /// The trace ID of a traced packet, or 0.
+    pub trace_id: u64,
+}
+
+/// A traced packet seen by the datapath, published on the `TRACE_HOPS` ring buffer for the daemon
+/// to report back to the consumer.
+#[repr(C, align(8))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceHop {
+    /// `bpf_ktime_get_ns` when the packet was seen.
+    pub timestamp_ns: u64,
+    pub trace_id: u64,
+    pub name_hash: u32,
+    /// Where the report goes: the sender of an Interest, or the receiver of Data or a Nack.
+    pub addr: [u8; 4],
+    /// The other end of the packet, which the report comes from.
+    pub local_addr: [u8; 4],
+    pub port: [u8; 2],
+    /// The `TlvType` of the traced packet.
+    pub packet_type: u8,
+    pub reserved: u8,
+}
+
+/// Most bytes of a packet the datapath samples; longer packets are truncated. Also the longest
+/// Data fault injection can hold back.
+pub const MAX_SAMPLE_LEN: u32 = 2048;
+
+/// A packet sampled onto the `SAMPLES` perf buffer for `udcn capture`. The kernel appends its
+/// first `captured_len` bytes, from the Ethernet header on.
+#[repr(C, align(8))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PacketSample {
+    /// `bpf_ktime_get_ns` when the packet arrived.
+    pub timestamp_ns: u64,
+    /// The interface it arrived on.
+    pub ifindex: u32,
+    /// Its length on the wire.
+    pub packet_len: u32,
+    pub captured_len: u32,
+    pub reserved: u32,
+}
+
+/// Packet sampling, written by the daemon into the single-entry `SAMPLE_CONFIG` map while a
+/// capture runs.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleConfig {
+    /// Sample one in this many NDN packets, picked at random; 0 turns sampling off.
+    pub rate: u32,
+    /// Bytes kept of each sampled packet, at most `MAX_SAMPLE_LEN`.
+    pub snaplen: u32,
+    /// Packet types never sampled, as `sample_type_bit`s.
+    pub skip_types: u32,
+    /// Non-zero to sample only packets whose header carries this name hash.
+    pub name_hash: u32,
+}
+
+/// Bits of `SampleConfig::skip_types`: one per NDN packet type, and one for anything else.
+pub const SAMPLE_TYPE_BITS: u32 = 0b1111;
+
+/// The bit of `SampleConfig::skip_types` for packets of TLV type `packet_type`.
+#[inline(always)]
+pub fn sample_type_bit(packet_type: u8) -> u32 {
+    if packet_type == TlvType::Interest as u8 {
+        1
+    } else if packet_type == TlvType::Data as u8 {
+        2
+    } else if packet_type == TlvType::Nack as u8 {
+        4
+    } else {
+        8
+    }
+}
+
+/// Faults the datapath injects for testing, written into the single-entry `FAULTS` map by daemons
+/// built with the `faults` feature. All zero, the default, injects none.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Chance, out of `u32::MAX`, that an Interest is dropped.
+    pub interest_drop_probability: u32,
+    /// Data satisfying a PIT entry is handed to the daemon on `HELD_DATA` instead of passed on,
+    /// and the daemon sends it on this much later.
+    pub data_delay_ms: u32,
+    /// Non-zero to flip bits in the signature of every Data.
+    pub corrupt_signatures: u32,
+    /// Prefixes in `FAULT_BLACKHOLES`, whose Interests are dropped; 0 skips the lookup.
+    pub blackholes: u32,
+}
+
+/// Runtime knobs written by the daemon into the single-entry `CONFIG` map.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DatapathConfig {
+    /// How long a NoRoute/NoData Nack is cached; 0 disables negative caching.
+    pub negative_cache_ttl_ms: u32,
+    /// Window in which an Interest repeating a (name, nonce) pair on the same face is dropped;
+    /// 0 disables duplicate detection.
+    pub duplicate_window_ms: u32,
+    /// A `CsAdmission` value.
+    pub cs_admission: u32,
+    /// Chance, out of `u32::MAX`, that `CsAdmission::Probabilistic` admits a Data packet.
+    pub cs_admission_probability: u32,
+    /// Misses `CsAdmission::Popularity` needs to see for a name before admitting its Data.
+    pub cs_admission_threshold: u32,
+    /// Length of the windows `CsAdmission::Popularity` counts misses in; counts start over in
+    /// each window.
+    pub cs_admission_window_ms: u32,
+    /// A `DatapathLogLevel` value: the most verbose records the XDP program emits.
+    pub log_level: u32,
+    /// Nonzero to split the shared content store partition into a probation segment,
+    /// `CONTENT_STORE`, and a protected one, `CS_PROTECTED`, that entries move to when they
+    /// answer an Interest.
+    pub cs_segmented: u32,
+    /// Nonzero to answer an Interest the content store cannot, and whose name is under no prefix
+    /// in `ROUTES`, with a NoRoute Nack from XDP instead of passing it to the network stack.
+    pub nack_unroutable: u32,
+    /// A `PitLayout` value: whether pending Interests go in the `PIT` hash map or the
+    /// open-addressing `PIT_FINGERPRINTS` and `PIT_ENTRIES` arrays.
+    pub pit_layout: u32,
+    /// Tenants in `TENANT_PREFIXES`; 0 skips looking names up and counting usage.
+    pub tenants: u32,
+    /// Nonzero to drop UDP datagrams whose checksum is wrong before parsing them. Datagrams
+    /// without a checksum (0) or longer than `MAX_CHECKSUM_LEN` are taken as they are.
+    pub verify_udp_checksums: u32,
+    /// Rules in `ACL`; 0 skips looking names up, letting every packet through.
+    pub acl_rules: u32,
+    /// Nonzero to drop packets whose name no ACL rule matches, rather than let them through.
+    pub acl_default_deny: u32,
+    /// Offences within `greylist_window_ms` that greylist a source address for `greylist_ms`;
+    /// 0 disables greylisting.
+    pub greylist_offences: u32,
+    pub greylist_window_ms: u32,
+    pub greylist_ms: u32,
+    /// `DECAPSULATE_*` flags: the overlay tunnels whose packets are classified by the NDN packet
+    /// they carry; 0 classifies packets as they arrive.
+    pub decapsulate: u32,
+    /// Nonzero to inject the faults in `FAULTS`. Only daemons built with the `faults` feature
+    /// set it, so the maps of others cannot make the datapath drop or corrupt packets.
+    pub fault_injection: u32,
+}
+
+/// `DatapathConfig::decapsulate` flags.
+pub const DECAPSULATE_VXLAN: u32 = 1;
+pub const DECAPSULATE_GTPU: u32 = 2;
+
+/// The UDP ports VXLAN and GTP-U tunnel endpoints listen on.
+pub const VXLAN_UDP_PORT: u16 = 4789;
+pub const GTPU_UDP_PORT: u16 = 2152;
+
+/// Longest UDP datagram, header included, whose checksum the datapath verifies; it sums the
+/// datagram a 16-bit word at a time, and the verifier bounds the loop.
+pub const MAX_CHECKSUM_LEN: usize = 1500;
+
+/// Most rules the `ACL` map holds.
+pub const MAX_ACL_RULES: u32 = 256;
+
+/// Key of an `ACL` rule: the `no_cache_key` of its prefix, and the face it applies to, or 0 for
+/// every face.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AclKey {
+    pub face_id: u32,
+    pub prefix: u32,
+}
+
+/// Most source addresses `REPUTATION` tracks; the least recently offending are forgotten first.
+pub const REPUTATION_CAPACITY: u32 = 4096;
+
+/// How a source IPv4 address has misbehaved, in `REPUTATION` by the address read big-endian.
+/// Malformed packets, bad checksums and Interests over a rate limit count as offences, as do
+/// those the daemon is told of.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reputation {
+    /// When the window the offences are counted in began (bpf_ktime ns).
+    pub window_start: u64,
+    /// Until when the address's packets are dropped (bpf_ktime ns); 0 if never greylisted.
+    pub greylisted_until: u64,
+    /// Offences in the current window.
+    pub offences: u32,
+    /// Packets dropped while greylisted.
+    pub dropped: u32,
+}
+
+impl Reputation {
+    /// Count an offence at `now`, greylisting the address once `config.greylist_offences` fall
+    /// in one window.
+    pub fn offend(&mut self, now: u64, config: &DatapathConfig) {
+        if config.greylist_offences == 0 {
+            return;
+        }
+        if now.saturating_sub(self.window_start) >= config.greylist_window_ms as u64 * 1_000_000 {
+            self.window_start = now;
+            self.offences = 0;
+        }
+        self.offences = self.offences.saturating_add(1);
+        if self.offences >= config.greylist_offences {
+            self.greylisted_until = now + config.greylist_ms as u64 * 1_000_000;
+            self.offences = 0;
+        }
+    }
+
+    pub fn is_greylisted(&self, now: u64) -> bool {
+        now < self.greylisted_until
+    }
+}
+
+/// What an `ACL` rule does with the packets under its prefix.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AclAction {
+    #[default]
+    Allow = 0,
+    Deny = 1,
+}
+
+/// Most tenants the daemon config can define. Tenants are numbered from 1; index 0 of the
+/// tenant maps is for names under no tenant.
+pub const MAX_TENANTS: u32 = 16;
+
+/// A tenant's quotas, written by the daemon into `TENANT_QUOTAS` at the tenant's index. A quota
+/// of 0 leaves the resource unlimited.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TenantQuota {
+    pub pit_entries: u32,
+    pub cs_bytes: u32,
+    pub interests_per_sec: u32,
+    pub reserved: u32,
+}
+
+/// A tenant's usage and what its quotas turned away, in `TENANT_USAGE` at the tenant's index.
+///
+/// The datapath counts PIT entries and content store bytes in as it adds them, and PIT entries
+/// out as Data satisfies them, so both drift as entries lapse, are Nacked or are evicted; the
+/// daemon recounts them from the tables periodically. The counters are updated without atomics,
+/// so CPUs racing on one tenant can lose counts.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TenantUsage {
+    pub pit_entries: u32,
+    pub cs_bytes: u32,
+    /// Interests counted in the current one-second window, and when it started
+    /// (`bpf_ktime_get_ns`).
+    pub window_interests: u32,
+    pub interests: u32,
+    pub window_start: u64,
+    /// Interests dropped over the rate quota, Interests dropped over the PIT quota, and Data
+    /// kept out of the content store over the byte quota.
+    pub rate_limited: u32,
+    pub pit_rejects: u32,
+    pub cs_rejects: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PacketStats {
     pub interest_received: u32,
     pub data_received: u32,
@@ -75,17 +954,155 @@ pub struct PacketStats {
     pub cache_misses: u32,
     pub pit_hits: u32,
     pub forwards: u32,
-    pub drops: u32,
+    /// Dropped packets by `DropReason`; serialized as a map from reason to count.
+    #[cfg_attr(feature = "serde", serde(with = "drops_by_reason"))]
+    pub drops: [u32; DROP_REASONS],
+    pub negative_cache_hits: u32,
+    pub duplicate_interests: u32,
+    pub cs_admission_rejects: u32,
+    /// Content store hits by Data still within its FreshnessPeriod, and by stale Data answering
+    /// an Interest without MustBeFresh. A stale entry never answers one with MustBeFresh.
+    pub fresh_hits: u32,
+    pub stale_hits: u32,
+    /// Data kept out of the content store because it is marked non-cacheable.
+    pub uncacheable: u32,
+    /// Content store hits answered by Data the daemon prefetched; also counted in `cache_hits`.
+    pub prefetch_hits: u32,
+    /// Content store hits in the probation and protected segments of the shared partition, while
+    /// it is segmented; also counted in `cache_hits`. Each probation hit promotes its entry.
+    pub cs_probation_hits: u32,
+    pub cs_protected_hits: u32,
+    /// Interests under no route answered with a NoRoute Nack by the datapath, while
+    /// `DatapathConfig::nack_unroutable` is set.
+    pub unroutable_nacks: u32,
+}
+
+impl PacketStats {
+    pub fn total_drops(&self) -> u32 {
+        self.drops.iter().fold(0, |total, count| total.wrapping_add(*count))
+    }
 }
 
+#[cfg(feature = "serde")]
+mod drops_by_reason {
+    use core::fmt;
+
+    use serde::{
+        de::{MapAccess, Visitor},
+        ser::SerializeMap as _,
+        Deserializer, Serializer,
+    };
+
+    use super::{DropReason, DROP_REASONS};
+
+    pub fn serialize<S: Serializer>(
+        drops: &[u32; DROP_REASONS],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(DROP_REASONS))?;
+        for reason in DropReason::ALL {
+            map.serialize_entry(&reason, &drops[reason as usize])?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u32; DROP_REASONS], D::Error> {
+        struct DropsVisitor;
+
+        impl<'de> Visitor<'de> for DropsVisitor {
+            type Value = [u32; DROP_REASONS];
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map from drop reason to count")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut drops = [0; DROP_REASONS];
+                while let Some((reason, count)) = map.next_entry::<DropReason, u32>()? {
+                    drops[reason as usize] = count;
+                }
+                Ok(drops)
+            }
+        }
+
+        deserializer.deserialize_map(DropsVisitor)
+    }
+}
+
+// The wire headers and map values are laid out by the compiler for both the 64-bit BPF target and
+// the host, which may be 32-bit (armv7) or big-endian. Pin the layouts the datapath relies on so a
+// struct that would only break on some hosts fails to build on all of them.
+const _: () = {
+    use core::mem::{offset_of, size_of};
+
+    assert!(size_of::<InterestPacket>() == 12);
+    assert!(offset_of!(InterestPacket, name_hash) == 4);
+    assert!(offset_of!(InterestPacket, nonce) == 8);
+    assert!(size_of::<DataPacket>() == 16);
+    assert!(offset_of!(DataPacket, content_size) == 8);
+    assert!(offset_of!(DataPacket, signature) == 12);
+    assert!(size_of::<NackPacket>() == size_of::<InterestPacket>());
+
+    assert!(size_of::<PitRecord>() == 32 && offset_of!(PitRecord, expires_at) == 24);
+    assert!(size_of::<PitEntry>() == 24 + 32 * PIT_MAX_FACES);
+    assert!(offset_of!(PitEntry, tenant) == 16 + 32 * PIT_MAX_FACES);
+    assert!(size_of::<LapsedRecord>() == 40 && offset_of!(LapsedRecord, record) == 8);
+    assert!(offset_of!(PitEntry, timestamp) == 8 && offset_of!(PitEntry, records) == 16);
+    assert!(size_of::<CacheEntry>() == 32 && offset_of!(CacheEntry, timestamp) == 8);
+    assert!(size_of::<NegativeCacheEntry>() == 16);
+    assert!(size_of::<NonceRecord>() == 16);
+    assert!(
+        size_of::<DatapathEvent>() == 32
+            && offset_of!(DatapathEvent, packet_len) == 20
+            && offset_of!(DatapathEvent, trace_id) == 24
+    );
+    assert!(size_of::<TraceHop>() == 32 && offset_of!(TraceHop, addr) == 20);
+    assert!(size_of::<PacketSample>() == 24);
+    assert!(size_of::<FaultConfig>() == 16);
+    assert!(size_of::<TenantUsage>() == 40 && offset_of!(TenantUsage, window_start) == 16);
+    assert!(size_of::<Reputation>() == 24);
+};
+
 // Implement Pod trait for Aya - PacketStats is just u32 fields so it's safe
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for PacketStats {}
 
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for DatapathConfig {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for PitEntry {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for CacheEntry {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for CachedPayload {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for SampleConfig {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FaultConfig {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TenantQuota {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TenantUsage {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TimeBase {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for AclKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Reputation {}
+
 pub fn hash_name(name: &[u8]) -> u32 {
-    const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
-    const FNV_PRIME: u32 = 0x01000193;
-    
     let mut hash = FNV_OFFSET_BASIS;
     for byte in name {
         hash ^= *byte as u32;
@@ -94,6 +1111,52 @@ pub fn hash_name(name: &[u8]) -> u32 {
     hash
 }
 
+/// Key of the `CS_PARTITION_PREFIXES` map: the hash of the value of a name's first component.
+pub fn cs_partition_key(first_component: &[u8]) -> u32 {
+    hash_name(first_component)
+}
+
+/// Key of the `NO_CACHE_PREFIXES` map: the hash of a prefix's component elements as they are
+/// encoded in a Name, so prefixes of any length share one map.
+pub fn no_cache_key(encoded_components: &[u8]) -> u32 {
+    hash_name(encoded_components)
+}
+
+impl DatapathConfig {
+    /// The popularity admission window `now` falls in.
+    pub fn cs_admission_window(&self, now: u64) -> u32 {
+        (now / 1_000_000 / self.cs_admission_window_ms.max(1) as u64) as u32
+    }
+
+    /// Whether Data enters the content store. `roll` is a random draw and `misses` how often its
+    /// name missed the store in the current window; each is only asked for by the policy that
+    /// needs it.
+    pub fn admits_to_cs(&self, roll: impl FnOnce() -> u32, misses: impl FnOnce() -> u32) -> bool {
+        match CsAdmission::from_u32(self.cs_admission) {
+            Some(CsAdmission::Probabilistic) => roll() <= self.cs_admission_probability,
+            Some(CsAdmission::Popularity) => misses() >= self.cs_admission_threshold,
+            _ => true,
+        }
+    }
+}
+
+/// The `CS_BLOOM` bits set for a cached `name_hash`, each below `CS_BLOOM_BITS`.
+pub fn cs_bloom_bits(name_hash: u32) -> [u32; 2] {
+    [
+        name_hash % CS_BLOOM_BITS,
+        name_hash.wrapping_mul(0x85eb_ca6b).rotate_right(13) % CS_BLOOM_BITS,
+    ]
+}
+
+/// Face identifier for a UDP peer, derived from its IPv4 address and port as they appear on the
+/// wire. The XDP program and userspace derive it the same way, so both agree on face numbering.
+pub fn udp_face_id(src_addr: [u8; 4], src_port: [u8; 2]) -> u32 {
+    let mut endpoint = [0u8; 6];
+    endpoint[..4].copy_from_slice(&src_addr);
+    endpoint[4..].copy_from_slice(&src_port);
+    hash_name(&endpoint)
+}
+
 impl TlvHeader {
     pub fn parse(data: &[u8]) -> Option<Self> {
         if data.len() < 2 {
@@ -106,6 +1169,20 @@ impl TlvHeader {
     }
 }
 
+impl NdnPacketHeader {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            packet_type: *data.first()?,
+            packet_length: *data.get(1)?,
+        })
+    }
+
+    fn write_to(&self, out: &mut [u8]) {
+        out[0] = self.packet_type;
+        out[1] = self.packet_length;
+    }
+}
+
 impl InterestPacket {
     pub fn new(name_hash: u32, nonce: u32) -> Self {
         Self {
@@ -117,6 +1194,25 @@ impl InterestPacket {
             nonce,
         }
     }
+
+    /// Read the header field by field, so `data` needs no particular alignment.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.get(..mem::size_of::<InterestPacket>())?;
+        Some(Self {
+            header: NdnPacketHeader::from_bytes(data)?,
+            name_hash: read_u32(data, mem::offset_of!(InterestPacket, name_hash)),
+            nonce: read_u32(data, mem::offset_of!(InterestPacket, nonce)),
+        })
+    }
+
+    /// The wire image of this header, with padding bytes zeroed.
+    pub fn to_bytes(&self) -> [u8; mem::size_of::<InterestPacket>()] {
+        let mut out = [0; mem::size_of::<InterestPacket>()];
+        self.header.write_to(&mut out);
+        write_u32(&mut out, mem::offset_of!(InterestPacket, name_hash), self.name_hash);
+        write_u32(&mut out, mem::offset_of!(InterestPacket, nonce), self.nonce);
+        out
+    }
 }
 
 impl DataPacket {
@@ -131,79 +1227,140 @@ impl DataPacket {
             signature,
         }
     }
+
+    /// Read the header field by field, so `data` needs no particular alignment.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.get(..mem::size_of::<DataPacket>())?;
+        Some(Self {
+            header: NdnPacketHeader::from_bytes(data)?,
+            name_hash: read_u32(data, mem::offset_of!(DataPacket, name_hash)),
+            content_size: read_u16(data, mem::offset_of!(DataPacket, content_size)),
+            signature: read_u32(data, mem::offset_of!(DataPacket, signature)),
+        })
+    }
+
+    /// The wire image of this header, with padding bytes zeroed.
+    pub fn to_bytes(&self) -> [u8; mem::size_of::<DataPacket>()] {
+        let mut out = [0; mem::size_of::<DataPacket>()];
+        self.header.write_to(&mut out);
+        write_u32(&mut out, mem::offset_of!(DataPacket, name_hash), self.name_hash);
+        write_u16(&mut out, mem::offset_of!(DataPacket, content_size), self.content_size);
+        write_u32(&mut out, mem::offset_of!(DataPacket, signature), self.signature);
+        out
+    }
 }
 
-pub fn parse_interest_packet(data: &[u8]) -> Option<InterestPacket> {
-    if data.len() < mem::size_of::<InterestPacket>() {
-        return None;
-    }
-    
-    let packet = unsafe { &*(data.as_ptr() as *const InterestPacket) };
-    
-    if packet.header.packet_type == TlvType::Interest as u8 {
-        Some(*packet)
-    } else {
-        None
+impl NackPacket {
+    pub fn new(name_hash: u32, nonce: u32, reason: NackReason) -> Self {
+        Self {
+            header: NdnPacketHeader {
+                packet_type: TlvType::Nack as u8,
+                packet_length: mem::size_of::<NackPacket>() as u8,
+            },
+            reason: reason as u8,
+            reserved: 0,
+            name_hash,
+            nonce,
+        }
     }
+
+    pub fn for_interest(interest: &InterestPacket, reason: NackReason) -> Self {
+        Self::new(interest.name_hash, interest.nonce, reason)
+    }
+
+    /// Read the header field by field, so `data` needs no particular alignment.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.get(..mem::size_of::<NackPacket>())?;
+        Some(Self {
+            header: NdnPacketHeader::from_bytes(data)?,
+            reason: data[mem::offset_of!(NackPacket, reason)],
+            reserved: data[mem::offset_of!(NackPacket, reserved)],
+            name_hash: read_u32(data, mem::offset_of!(NackPacket, name_hash)),
+            nonce: read_u32(data, mem::offset_of!(NackPacket, nonce)),
+        })
+    }
+
+    /// The wire image of this header, with padding bytes zeroed.
+    pub fn to_bytes(&self) -> [u8; mem::size_of::<NackPacket>()] {
+        let mut out = [0; mem::size_of::<NackPacket>()];
+        self.header.write_to(&mut out);
+        out[mem::offset_of!(NackPacket, reason)] = self.reason;
+        out[mem::offset_of!(NackPacket, reserved)] = self.reserved;
+        write_u32(&mut out, mem::offset_of!(NackPacket, name_hash), self.name_hash);
+        write_u32(&mut out, mem::offset_of!(NackPacket, nonce), self.nonce);
+        out
+    }
+}
+
+// Header fields are read and written through byte arrays because packet buffers carry no
+// alignment guarantee. Multi-byte fields are in network byte order on the wire and host order
+// in the structs.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn write_u16(out: &mut [u8], offset: usize, value: u16) {
+    out[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn write_u32(out: &mut [u8], offset: usize, value: u32) {
+    out[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn parse_interest_packet(data: &[u8]) -> Option<InterestPacket> {
+    let packet = InterestPacket::from_bytes(data)?;
+    (packet.header.packet_type == TlvType::Interest as u8).then_some(packet)
 }
 
 pub fn parse_data_packet(data: &[u8]) -> Option<DataPacket> {
-    if data.len() < mem::size_of::<DataPacket>() {
-        return None;
-    }
-    
-    let packet = unsafe { &*(data.as_ptr() as *const DataPacket) };
-    
-    if packet.header.packet_type == TlvType::Data as u8 {
-        Some(*packet)
-    } else {
-        None
-    }
+    let packet = DataPacket::from_bytes(data)?;
+    (packet.header.packet_type == TlvType::Data as u8).then_some(packet)
+}
+
+pub fn parse_nack_packet(data: &[u8]) -> Option<NackPacket> {
+    let packet = NackPacket::from_bytes(data)?;
+    (packet.header.packet_type == TlvType::Nack as u8).then_some(packet)
 }
 
 pub fn is_ndn_packet(data: &[u8]) -> bool {
-    if data.len() < mem::size_of::<NdnPacketHeader>() {
+    let Some(header) = NdnPacketHeader::from_bytes(data) else {
         return false;
-    }
-    
-    let header = unsafe { &*(data.as_ptr() as *const NdnPacketHeader) };
-    header.packet_type == TlvType::Interest as u8 || header.packet_type == TlvType::Data as u8
+    };
+    header.packet_type == TlvType::Interest as u8
+        || header.packet_type == TlvType::Data as u8
+        || header.packet_type == TlvType::Nack as u8
 }
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
-#[cfg(feature = "std")]
-pub fn serialize_interest(name: &str, nonce: u32) -> std::vec::Vec<u8> {
+#[cfg(feature = "alloc")]
+pub fn serialize_interest(name: &str, nonce: u32) -> alloc::vec::Vec<u8> {
     let name_hash = hash_name(name.as_bytes());
-    let packet = InterestPacket::new(name_hash, nonce);
-    let bytes = unsafe {
-        core::slice::from_raw_parts(
-            &packet as *const _ as *const u8,
-            mem::size_of::<InterestPacket>(),
-        )
-    };
-    bytes.to_vec()
+    InterestPacket::new(name_hash, nonce).to_bytes().to_vec()
 }
 
-#[cfg(feature = "std")]
-pub fn serialize_data(name: &str, content: &[u8], signature: u32) -> std::vec::Vec<u8> {
+#[cfg(feature = "alloc")]
+pub fn serialize_data(name: &str, content: &[u8], signature: u32) -> alloc::vec::Vec<u8> {
     let name_hash = hash_name(name.as_bytes());
     let packet = DataPacket::new(name_hash, content.len() as u16, signature);
-    let mut result = std::vec::Vec::new();
-    
-    let packet_bytes = unsafe {
-        core::slice::from_raw_parts(
-            &packet as *const _ as *const u8,
-            mem::size_of::<DataPacket>(),
-        )
-    };
-    
-    result.extend_from_slice(packet_bytes);
+    let mut result = alloc::vec::Vec::new();
+    result.extend_from_slice(&packet.to_bytes());
     result.extend_from_slice(content);
     result
 }
 
+#[cfg(feature = "alloc")]
+pub fn serialize_nack(interest: &InterestPacket, reason: NackReason) -> alloc::vec::Vec<u8> {
+    NackPacket::for_interest(interest, reason).to_bytes().to_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -313,6 +1470,307 @@ mod tests {
         assert_eq!(parsed.content_size, content.len() as u16);
     }
 
+    // Kept free of proptest so it stays cheap to run under Miri.
+    #[test]
+    fn test_headers_parse_at_odd_alignment() {
+        let interest = InterestPacket::new(0x12345678, 0x9ABCDEF0);
+        let data = DataPacket::new(0x12345678, 300, 0x0BADF00D);
+        let nack = NackPacket::new(0x12345678, 0x9ABCDEF0, NackReason::NoRoute);
+
+        let mut buf = [0u8; 32];
+        for offset in 1..4 {
+            let interest_bytes = interest.to_bytes();
+            buf[offset..offset + interest_bytes.len()].copy_from_slice(&interest_bytes);
+            let parsed = parse_interest_packet(&buf[offset..]).unwrap();
+            assert_eq!((parsed.name_hash, parsed.nonce), (interest.name_hash, interest.nonce));
+
+            let data_bytes = data.to_bytes();
+            buf[offset..offset + data_bytes.len()].copy_from_slice(&data_bytes);
+            let parsed = parse_data_packet(&buf[offset..]).unwrap();
+            assert_eq!(parsed.name_hash, data.name_hash);
+            assert_eq!(parsed.content_size, data.content_size);
+            assert_eq!(parsed.signature, data.signature);
+
+            let nack_bytes = nack.to_bytes();
+            buf[offset..offset + nack_bytes.len()].copy_from_slice(&nack_bytes);
+            let parsed = parse_nack_packet(&buf[offset..]).unwrap();
+            assert_eq!(parsed.reason, NackReason::NoRoute as u8);
+            assert_eq!((parsed.name_hash, parsed.nonce), (nack.name_hash, nack.nonce));
+            assert!(is_ndn_packet(&buf[offset..]));
+        }
+    }
+
+    #[test]
+    fn test_headers_use_network_byte_order() {
+        let interest = InterestPacket::new(0x12345678, 0x9ABCDEF0).to_bytes();
+        let name_hash = mem::offset_of!(InterestPacket, name_hash);
+        let nonce = mem::offset_of!(InterestPacket, nonce);
+        assert_eq!(interest[name_hash..name_hash + 4], [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(interest[nonce..nonce + 4], [0x9A, 0xBC, 0xDE, 0xF0]);
+
+        let data = DataPacket::new(0x12345678, 0x0102, 0x0BADF00D).to_bytes();
+        let content_size = mem::offset_of!(DataPacket, content_size);
+        let signature = mem::offset_of!(DataPacket, signature);
+        assert_eq!(data[content_size..content_size + 2], [0x01, 0x02]);
+        assert_eq!(data[signature..signature + 4], [0x0B, 0xAD, 0xF0, 0x0D]);
+    }
+
+    #[test]
+    fn test_short_buffers_are_rejected() {
+        let interest = InterestPacket::new(1, 2).to_bytes();
+        let data = DataPacket::new(1, 2, 3).to_bytes();
+        let nack = NackPacket::new(1, 2, NackReason::NoData).to_bytes();
+        for len in 0..interest.len() {
+            assert!(parse_interest_packet(&interest[..len]).is_none());
+            assert!(parse_nack_packet(&nack[..len]).is_none());
+        }
+        for len in 0..data.len() {
+            assert!(parse_data_packet(&data[..len]).is_none());
+        }
+        assert!(!is_ndn_packet(&interest[..1]));
+    }
+
+    #[test]
+    fn test_cs_bloom_bits() {
+        let bits = cs_bloom_bits(hash_name(b"/video/stream1"));
+        assert!(bits.iter().all(|bit| *bit < CS_BLOOM_BITS));
+        assert_ne!(bits[0], bits[1]);
+        assert_eq!(bits, cs_bloom_bits(hash_name(b"/video/stream1")));
+    }
+
+    #[test]
+    fn test_cs_admission() {
+        let no_roll = || -> u32 { panic!("rolled") };
+        let no_misses = || -> u32 { panic!("counted misses") };
+        let always = DatapathConfig::default();
+        assert!(always.admits_to_cs(no_roll, no_misses));
+        let unknown = DatapathConfig {
+            cs_admission: 7,
+            ..DatapathConfig::default()
+        };
+        assert!(unknown.admits_to_cs(no_roll, no_misses));
+
+        let probabilistic = DatapathConfig {
+            cs_admission: CsAdmission::Probabilistic as u32,
+            cs_admission_probability: u32::MAX / 4,
+            ..DatapathConfig::default()
+        };
+        assert!(probabilistic.admits_to_cs(|| 0, no_misses));
+        assert!(probabilistic.admits_to_cs(|| u32::MAX / 4, no_misses));
+        assert!(!probabilistic.admits_to_cs(|| u32::MAX / 4 + 1, no_misses));
+        let never = DatapathConfig {
+            cs_admission_probability: 0,
+            ..probabilistic
+        };
+        assert!(!never.admits_to_cs(|| 1, no_misses));
+
+        let popularity = DatapathConfig {
+            cs_admission: CsAdmission::Popularity as u32,
+            cs_admission_threshold: 2,
+            ..DatapathConfig::default()
+        };
+        assert!(!popularity.admits_to_cs(no_roll, || 0));
+        assert!(!popularity.admits_to_cs(no_roll, || 1));
+        assert!(popularity.admits_to_cs(no_roll, || 2));
+    }
+
+    #[test]
+    fn test_cs_misses_count_per_window() {
+        let config = DatapathConfig {
+            cs_admission: CsAdmission::Popularity as u32,
+            cs_admission_threshold: 2,
+            cs_admission_window_ms: 1000,
+            ..DatapathConfig::default()
+        };
+        let ms = 1_000_000;
+        let window = config.cs_admission_window(500 * ms);
+        assert_eq!(window, config.cs_admission_window(999 * ms));
+        assert_ne!(window, config.cs_admission_window(1000 * ms));
+
+        let mut counter = MissCounter::default();
+        counter.record(window);
+        assert!(!config.admits_to_cs(|| 0, || counter.misses(window)));
+        counter.record(window);
+        assert!(config.admits_to_cs(|| 0, || counter.misses(window)));
+        // The next window starts from nothing, and so does a miss counted in it
+        let next = config.cs_admission_window(1500 * ms);
+        assert_eq!(counter.misses(next), 0);
+        counter.record(next);
+        assert_eq!(counter.misses(next), 1);
+        assert_eq!(counter.misses(window), 0);
+
+        let slots = cs_miss_slots(hash_name(b"/video/stream1"));
+        assert!(slots.iter().all(|slot| *slot < CS_MISS_FILTER_SLOTS));
+        assert_ne!(slots[0], slots[1]);
+        // A zero window length is treated as 1ms rather than dividing by zero
+        let zero = DatapathConfig {
+            cs_admission_window_ms: 0,
+            ..config
+        };
+        assert_eq!(zero.cs_admission_window(5 * ms), 5);
+    }
+
+    #[test]
+    fn test_udp_face_id() {
+        let face = udp_face_id([10, 0, 100, 1], 6363u16.to_be_bytes());
+
+        assert_eq!(face, udp_face_id([10, 0, 100, 1], 6363u16.to_be_bytes()));
+        assert_ne!(face, udp_face_id([10, 0, 100, 1], 6364u16.to_be_bytes()));
+        assert_ne!(face, udp_face_id([10, 0, 100, 2], 6363u16.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_nack_matches_interest_layout() {
+        assert_eq!(mem::size_of::<NackPacket>(), mem::size_of::<InterestPacket>());
+        assert_eq!(
+            mem::offset_of!(NackPacket, name_hash),
+            mem::offset_of!(InterestPacket, name_hash)
+        );
+        assert_eq!(mem::offset_of!(NackPacket, nonce), mem::offset_of!(InterestPacket, nonce));
+    }
+
+    #[test]
+    fn test_nack_reason_roundtrip() {
+        for reason in [
+            NackReason::Congestion,
+            NackReason::Duplicate,
+            NackReason::NoRoute,
+            NackReason::NoData,
+            NackReason::Expired,
+            NackReason::TooLate,
+        ] {
+            assert_eq!(NackReason::from_u8(reason as u8), Some(reason));
+        }
+        assert_eq!(NackReason::from_u8(0), None);
+        assert!(NackReason::NoRoute.is_cacheable());
+        assert!(!NackReason::Congestion.is_cacheable());
+        assert!(!NackReason::Expired.is_cacheable());
+    }
+
+    #[test]
+    fn test_pit_records_expire_per_face() {
+        let record = |face_id, expires_at| PitRecord {
+            face_id,
+            expires_at,
+            ..PitRecord::default()
+        };
+        let mut entry = PitEntry::new(1, record(10, 100), 0);
+        // The face id of the last record handed over as lapsed
+        let mut lapsed = 0;
+        assert!(entry.add_downstream(record(11, 400), 50, |r| lapsed = r.face_id));
+        assert!(entry.add_downstream(record(12, 300), 50, |r| lapsed = r.face_id));
+        assert_eq!(lapsed, 0);
+
+        // Face 11 retransmits and is refreshed in place; face 10's record lapsed and makes room
+        assert!(entry.add_downstream(record(11, 900), 200, |r| lapsed = r.face_id));
+        assert_eq!(lapsed, 0);
+        assert!(entry.add_downstream(record(13, 900), 200, |r| lapsed = r.face_id));
+        assert_eq!(lapsed, 10);
+        assert!(entry.add_downstream(record(14, 900), 200, |r| lapsed = r.face_id));
+        assert!(!entry.add_downstream(record(15, 900), 200, |r| lapsed = r.face_id));
+        assert_eq!(lapsed, 10);
+
+        entry.expire(350, |r| lapsed = r.face_id);
+        assert_eq!(lapsed, 12);
+        assert!(!entry.is_empty());
+        entry.expire(900, |_| {});
+        assert!(entry.is_empty());
+        assert_eq!(entry.face_id, 10);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_drops_serialize_by_reason() {
+        let mut stats = PacketStats::default();
+        stats.drops[DropReason::PitFull as usize] = 3;
+        stats.drops[DropReason::UnsolicitedData as usize] = 2;
+        assert_eq!(stats.total_drops(), 5);
+
+        let json = serde_json::to_value(stats).unwrap();
+        assert_eq!(json["drops"]["pit_full"], 3);
+        assert_eq!(json["drops"]["unsolicited_data"], 2);
+        assert_eq!(json["drops"]["malformed"], 0);
+        let decoded: PacketStats = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.drops, stats.drops);
+        for reason in DropReason::ALL {
+            assert_eq!(serde_json::to_value(reason).unwrap(), reason.as_str());
+        }
+    }
+
+    #[test]
+    fn test_payloads_belong_to_one_version() {
+        let entry = CacheEntry {
+            name_hash: 0xabcd,
+            data_size: 300,
+            prefetched: 0,
+            tenant: 0,
+            timestamp: 5_000,
+            fresh_until: 9_000,
+            pinned: 0,
+            reserved: [0; 7],
+        };
+        let payload = CachedPayload {
+            version: entry.timestamp,
+            data: [7; DATA_CACHE_PAYLOAD_LEN],
+        };
+        assert!(payload.belongs_to(&entry));
+        // A newer copy of the same Data, cached over the one the payload was written for
+        let newer = CacheEntry {
+            timestamp: 6_000,
+            ..entry
+        };
+        assert!(!payload.belongs_to(&newer));
+    }
+
+    #[test]
+    fn test_time_base_converts_both_ways() {
+        let base = TimeBase {
+            monotonic_ns: 5_000_000_000,
+            realtime_ns: 1_700_000_000_000_000_000,
+        };
+        assert_eq!(base.realtime_ns(6_000_000_000), 1_700_000_001_000_000_000);
+        // Timestamps taken before the base
+        assert_eq!(base.realtime_ns(4_500_000_000), 1_699_999_999_500_000_000);
+        assert_eq!(base.monotonic_ns(base.realtime_ns(123)), 123);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_nack_serialization() {
+        let interest = InterestPacket::new(hash_name(b"/missing"), 0x12345678);
+        let serialized = serialize_nack(&interest, NackReason::NoData);
+
+        assert_eq!(serialized[0], TlvType::Nack as u8);
+        assert!(parse_interest_packet(&serialized).is_none());
+
+        let nack = parse_nack_packet(&serialized).unwrap();
+        assert_eq!(nack.name_hash, interest.name_hash);
+        assert_eq!(nack.nonce, interest.nonce);
+        assert_eq!(NackReason::from_u8(nack.reason), Some(NackReason::NoData));
+    }
+
+    #[test]
+    fn test_repeat_offenders_are_greylisted() {
+        let config = DatapathConfig {
+            greylist_offences: 3,
+            greylist_window_ms: 1000,
+            greylist_ms: 5000,
+            ..DatapathConfig::default()
+        };
+        let ms = 1_000_000;
+        let mut reputation = Reputation::default();
+        reputation.offend(10 * ms, &config);
+        reputation.offend(20 * ms, &config);
+        // The window ran out before the third
+        reputation.offend(1500 * ms, &config);
+        assert!(!reputation.is_greylisted(1500 * ms));
+        reputation.offend(1600 * ms, &config);
+        reputation.offend(1700 * ms, &config);
+        assert!(reputation.is_greylisted(1700 * ms));
+        assert!(!reputation.is_greylisted(6700 * ms));
+        assert_eq!(reputation.offences, 0);
+    }
+
     #[cfg(feature = "std")]
     #[test]
     fn test_is_ndn_packet() {
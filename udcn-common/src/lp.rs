@@ -0,0 +1,403 @@
+//! NDNLPv2 fragmentation for packets larger than a face's MTU.
+//!
+//! Each fragment travels as an LpPacket carrying a Sequence, its FragIndex, the FragCount and a
+//! slice of the packet as its Fragment. Fragments of one packet get consecutive sequence numbers,
+//! so the first one's (Sequence minus FragIndex) identifies the packet on the receiving side.
+//! The XDP program leaves fragments to the network stack, so forwarders pass them on without
+//! looking them up.
+//!
+//! A packet can also be traced by wrapping it in an LpPacket with a TraceId ahead of its
+//! Fragment. Each forwarder that sees it reports back to the consumer with a [`HopReport`], and
+//! handles the packet inside as if it came unwrapped.
+//!
+//! A producer falling behind marks its replies by wrapping them in an LpPacket with a
+//! CongestionMark, which consumers count to know to slow down.
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
+use core::hash::BuildHasher as _;
+
+use crate::{tlv, TlvType};
+
+/// Bytes an LpPacket adds around its Fragment, at most: the LpPacket and Fragment headers, an
+/// 8-byte Sequence, and a FragIndex and FragCount of up to 2 bytes each.
+pub const LP_FRAGMENT_OVERHEAD: usize = 4 + 10 + 4 + 4 + 4;
+
+/// Bytes the LpPacket a traced or congestion-marked packet travels in adds, at most: the LpPacket
+/// and Fragment headers and a TraceId, which is longer than a CongestionMark.
+pub const LP_REPLY_OVERHEAD: usize = 4 + 10 + 4;
+
+/// Most fragments a packet is split into, which keeps FragIndex and FragCount within 2 bytes.
+pub const MAX_FRAGMENTS: usize = u16::MAX as usize;
+
+pub use crate::CONGESTION_MARK;
+
+/// Partially received packets a [`Reassembler`] keeps; the oldest is given up when another one
+/// starts arriving.
+const MAX_PARTIAL_PACKETS: usize = 64;
+
+/// LpPackets a [`Reassembler`] unwraps from inside one another, as when a traced packet was
+/// fragmented on the way. Deeper nesting is malformed, and would otherwise recurse once a level.
+const MAX_NESTING: usize = 4;
+
+/// Whether `buf` is an LpPacket.
+pub fn is_lp_packet(buf: &[u8]) -> bool {
+    buf.first() == Some(&(TlvType::LpPacket as u8))
+}
+
+/// Split `packet` into LpPackets of at most `max_size` bytes, numbered from `sequence` on. `None`
+/// if `max_size` leaves no room for content or the packet would need too many fragments.
+pub fn fragment(packet: &[u8], max_size: usize, sequence: u64) -> Option<Vec<Vec<u8>>> {
+    let chunk_size = max_size
+        .checked_sub(LP_FRAGMENT_OVERHEAD)
+        .filter(|&size| size > 0)?;
+    let chunks: Vec<&[u8]> = packet.chunks(chunk_size).collect();
+    if chunks.len() > MAX_FRAGMENTS {
+        return None;
+    }
+    let count = chunks.len() as u64;
+    let fragments = chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut value = Vec::with_capacity(chunk.len() + LP_FRAGMENT_OVERHEAD);
+            tlv::write_element(
+                &mut value,
+                TlvType::Sequence as u64,
+                &sequence.wrapping_add(index as u64).to_be_bytes(),
+            );
+            tlv::write_nonneg_int_element(&mut value, TlvType::FragIndex as u64, index as u64);
+            tlv::write_nonneg_int_element(&mut value, TlvType::FragCount as u64, count);
+            tlv::write_element(&mut value, TlvType::Fragment as u64, chunk);
+            let mut lp_packet = Vec::with_capacity(value.len() + 4);
+            tlv::write_element(&mut lp_packet, TlvType::LpPacket as u64, &value);
+            lp_packet
+        })
+        .collect();
+    Some(fragments)
+}
+
+/// Wrap `packet` in an LpPacket carrying `trace_id`. The TraceId comes first, so the datapath
+/// finds it at a fixed offset.
+pub fn trace(packet: &[u8], trace_id: u64) -> Vec<u8> {
+    let mut value = Vec::with_capacity(packet.len() + 14);
+    tlv::write_element(&mut value, TlvType::TraceId as u64, &trace_id.to_be_bytes());
+    tlv::write_element(&mut value, TlvType::Fragment as u64, packet);
+    let mut lp_packet = Vec::with_capacity(value.len() + 4);
+    tlv::write_element(&mut lp_packet, TlvType::LpPacket as u64, &value);
+    lp_packet
+}
+
+/// Wrap `packet` in an LpPacket with a CongestionMark.
+pub fn mark_congestion(packet: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(packet.len() + 10);
+    tlv::write_nonneg_int_element(&mut value, CONGESTION_MARK, 1);
+    tlv::write_element(&mut value, TlvType::Fragment as u64, packet);
+    let mut lp_packet = Vec::with_capacity(value.len() + 4);
+    tlv::write_element(&mut lp_packet, TlvType::LpPacket as u64, &value);
+    lp_packet
+}
+
+/// A trace ID from the randomly seeded hasher std already provides. Never 0, which events use
+/// for untraced packets.
+#[cfg(feature = "std")]
+pub fn random_trace_id() -> u64 {
+    std::collections::hash_map::RandomState::new()
+        .hash_one(0u8)
+        .max(1)
+}
+
+/// The trace ID and packet of a traced LpPacket.
+pub fn untrace(buf: &[u8]) -> Option<(u64, &[u8])> {
+    let (element, _) = tlv::read_element(buf)?;
+    if element.typ != TlvType::LpPacket as u64 {
+        return None;
+    }
+    let (trace_id, rest) = tlv::read_element(element.value)?;
+    if trace_id.typ != TlvType::TraceId as u64 {
+        return None;
+    }
+    let (fragment, _) = tlv::read_element(rest)?;
+    if fragment.typ != TlvType::Fragment as u64 {
+        return None;
+    }
+    Some((
+        u64::from_be_bytes(trace_id.value.try_into().ok()?),
+        fragment.value,
+    ))
+}
+
+/// What a forwarder saw of a traced packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HopReport {
+    pub trace_id: u64,
+    /// The forwarder's host name.
+    pub node: String,
+    /// The `TlvType` of the traced packet.
+    pub packet_type: u8,
+    pub name_hash: u32,
+    /// Wall-clock time the forwarder saw the packet, in nanoseconds since the Unix epoch.
+    pub timestamp_ns: u64,
+}
+
+impl HopReport {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut report = Vec::new();
+        tlv::write_element(&mut report, TlvType::HopNode as u64, self.node.as_bytes());
+        tlv::write_nonneg_int_element(
+            &mut report,
+            TlvType::HopPacketType as u64,
+            self.packet_type as u64,
+        );
+        tlv::write_nonneg_int_element(
+            &mut report,
+            TlvType::HopNameHash as u64,
+            self.name_hash as u64,
+        );
+        tlv::write_nonneg_int_element(&mut report, TlvType::HopTimestamp as u64, self.timestamp_ns);
+        let mut value = Vec::with_capacity(report.len() + 14);
+        tlv::write_element(
+            &mut value,
+            TlvType::TraceId as u64,
+            &self.trace_id.to_be_bytes(),
+        );
+        tlv::write_element(&mut value, TlvType::HopReport as u64, &report);
+        let mut lp_packet = Vec::with_capacity(value.len() + 4);
+        tlv::write_element(&mut lp_packet, TlvType::LpPacket as u64, &value);
+        lp_packet
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (element, _) = tlv::read_element(buf)?;
+        if element.typ != TlvType::LpPacket as u64 {
+            return None;
+        }
+        let (trace_id, rest) = tlv::read_element(element.value)?;
+        let (report, _) = tlv::read_element(rest)?;
+        if trace_id.typ != TlvType::TraceId as u64 || report.typ != TlvType::HopReport as u64 {
+            return None;
+        }
+
+        let mut hop = Self {
+            trace_id: u64::from_be_bytes(trace_id.value.try_into().ok()?),
+            node: String::new(),
+            packet_type: 0,
+            name_hash: 0,
+            timestamp_ns: 0,
+        };
+        let mut rest = report.value;
+        while !rest.is_empty() {
+            let (field, next) = tlv::read_element(rest)?;
+            match field.typ {
+                t if t == TlvType::HopNode as u64 => {
+                    hop.node = String::from_utf8(field.value.to_vec()).ok()?
+                }
+                t if t == TlvType::HopPacketType as u64 => {
+                    hop.packet_type = tlv::read_nonneg_int(field.value)?.try_into().ok()?
+                }
+                t if t == TlvType::HopNameHash as u64 => {
+                    hop.name_hash = tlv::read_nonneg_int(field.value)?.try_into().ok()?
+                }
+                t if t == TlvType::HopTimestamp as u64 => {
+                    hop.timestamp_ns = tlv::read_nonneg_int(field.value)?
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+        Some(hop)
+    }
+}
+
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+/// Puts fragmented packets back together.
+#[derive(Default)]
+pub struct Reassembler {
+    partial: BTreeMap<u64, Partial>,
+    congestion_marks: u64,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// LpPackets taken in so far that carried a CongestionMark.
+    pub fn congestion_marks(&self) -> u64 {
+        self.congestion_marks
+    }
+
+    /// Take in an LpPacket, returning the packet it completes, if any. An LpPacket without
+    /// fragmentation fields carries a whole packet, and one without a Fragment, such as a
+    /// [`HopReport`], carries none. Malformed LpPackets are ignored.
+    pub fn receive(&mut self, lp_packet: &[u8]) -> Option<Vec<u8>> {
+        self.receive_nested(lp_packet, 0)
+    }
+
+    fn receive_nested(&mut self, lp_packet: &[u8], depth: usize) -> Option<Vec<u8>> {
+        let (element, _) = tlv::read_element(lp_packet)?;
+        if element.typ != TlvType::LpPacket as u64 {
+            return None;
+        }
+
+        let (mut sequence, mut index, mut count, mut fragment) = (None, 0, 1, None);
+        let mut marked = false;
+        let mut rest = element.value;
+        while !rest.is_empty() {
+            let (field, next) = tlv::read_element(rest)?;
+            match field.typ {
+                t if t == TlvType::Sequence as u64 => {
+                    sequence = Some(u64::from_be_bytes(field.value.try_into().ok()?))
+                }
+                t if t == TlvType::FragIndex as u64 => index = tlv::read_nonneg_int(field.value)?,
+                t if t == TlvType::FragCount as u64 => count = tlv::read_nonneg_int(field.value)?,
+                t if t == TlvType::Fragment as u64 => fragment = Some(field.value),
+                CONGESTION_MARK => marked = tlv::read_nonneg_int(field.value)? > 0,
+                _ => {}
+            }
+            rest = next;
+        }
+        if marked {
+            self.congestion_marks += 1;
+        }
+        let fragment = fragment?;
+        if count == 1 {
+            return self.unwrap(fragment.to_vec(), depth);
+        }
+        if index >= count || count > MAX_FRAGMENTS as u64 {
+            return None;
+        }
+
+        let key = sequence?.wrapping_sub(index);
+        if !self.partial.contains_key(&key) && self.partial.len() >= MAX_PARTIAL_PACKETS {
+            self.partial.pop_first();
+        }
+        let partial = self.partial.entry(key).or_insert_with(|| Partial {
+            fragments: vec![None; count as usize],
+            missing: count as usize,
+        });
+        let slot = partial.fragments.get_mut(index as usize)?;
+        if slot.is_none() {
+            *slot = Some(fragment.to_vec());
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+        let partial = self.partial.remove(&key)?;
+        self.unwrap(
+            partial.fragments.into_iter().flatten().flatten().collect(),
+            depth,
+        )
+    }
+
+    /// A traced packet may have been fragmented on the way, leaving an LpPacket inside another.
+    fn unwrap(&mut self, packet: Vec<u8>, depth: usize) -> Option<Vec<u8>> {
+        if is_lp_packet(&packet) {
+            if depth == MAX_NESTING {
+                return None;
+            }
+            self.receive_nested(&packet, depth + 1)
+        } else {
+            Some(packet)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragments_reassemble_in_any_order() {
+        let packet: Vec<u8> = (0..=255).cycle().take(3000).collect();
+        let fragments = fragment(&packet, 1000, 7).unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments.iter().all(|f| f.len() <= 1000 && is_lp_packet(f)));
+
+        let mut reassembler = Reassembler::new();
+        for f in fragments[1..].iter().rev() {
+            assert_eq!(reassembler.receive(f), None);
+        }
+        // A duplicate fragment changes nothing
+        assert_eq!(reassembler.receive(&fragments[1]), None);
+        assert_eq!(reassembler.receive(&fragments[0]), Some(packet));
+
+        let small = fragment(b"abc", 1000, 0).unwrap();
+        assert_eq!(small.len(), 1);
+        assert_eq!(reassembler.receive(&small[0]).as_deref(), Some(&b"abc"[..]));
+        assert_eq!(fragment(b"abc", LP_FRAGMENT_OVERHEAD, 0), None);
+    }
+
+    #[test]
+    fn test_deeply_nested_packets_are_dropped() {
+        let mut packet = b"\x05\x00".to_vec();
+        // The outermost LpPacket and MAX_NESTING inside it, and one more
+        for _ in 0..MAX_NESTING + 2 {
+            packet = trace(&packet, 1);
+        }
+        assert_eq!(Reassembler::new().receive(&packet), None);
+        packet = untrace(&packet).unwrap().1.to_vec();
+        assert_eq!(
+            Reassembler::new().receive(&packet).as_deref(),
+            Some(&b"\x05\x00"[..])
+        );
+    }
+
+    #[test]
+    fn test_traced_packets_and_hop_reports() {
+        let traced = trace(b"\x05packet", 0xfeed);
+        assert_eq!(untrace(&traced), Some((0xfeed, &b"\x05packet"[..])));
+        // The datapath expects the TraceId right after the LpPacket header
+        assert_eq!(&traced[2..4], &[TlvType::TraceId as u8, 8]);
+
+        // Fragmented on the way, a traced packet still comes out whole
+        let mut reassembler = Reassembler::new();
+        let fragments = fragment(&traced, 30, 0).unwrap();
+        let (last, rest) = fragments.split_last().unwrap();
+        for f in rest {
+            assert_eq!(reassembler.receive(f), None);
+        }
+        assert_eq!(
+            reassembler.receive(last).as_deref(),
+            Some(&b"\x05packet"[..])
+        );
+
+        let hop = HopReport {
+            trace_id: 0xfeed,
+            node: "router1".into(),
+            packet_type: TlvType::Interest as u8,
+            name_hash: 0x1234,
+            timestamp_ns: 1_700_000_000_000_000_000,
+        };
+        let encoded = hop.encode();
+        assert_eq!(HopReport::decode(&encoded), Some(hop));
+        assert_eq!(untrace(&encoded), None);
+        assert_eq!(reassembler.receive(&encoded), None);
+        assert_eq!(HopReport::decode(&traced), None);
+    }
+
+    #[test]
+    fn test_congestion_marks_are_counted() {
+        let mut reassembler = Reassembler::new();
+        let marked = mark_congestion(b"\x06data");
+        assert_eq!(
+            reassembler.receive(&marked).as_deref(),
+            Some(&b"\x06data"[..])
+        );
+        assert_eq!(reassembler.congestion_marks(), 1);
+
+        // Marked before being fragmented, the mark is counted once
+        let fragments = fragment(&marked, 30, 0).unwrap();
+        assert!(fragments.len() > 1);
+        for f in &fragments {
+            reassembler.receive(f);
+        }
+        assert_eq!(reassembler.congestion_marks(), 2);
+        assert_eq!(untrace(&marked), None);
+    }
+}
@@ -0,0 +1,619 @@
+//! Hierarchical NDN names and their URI representation.
+
+use alloc::{string::ToString as _, vec::Vec};
+use core::{cmp::Ordering, fmt, str::FromStr};
+
+pub use crate::component::{
+    Convention, BYTE_OFFSET_COMPONENT, GENERIC_COMPONENT, IMPLICIT_SHA256_DIGEST_COMPONENT,
+    PARAMETERS_SHA256_DIGEST_COMPONENT, SEGMENT_COMPONENT, SEQUENCE_NUM_COMPONENT,
+    TIMESTAMP_COMPONENT, VERSION_COMPONENT,
+};
+use crate::{component, hash_name, tlv, TlvType};
+
+/// Why a name URI could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameError {
+    /// A `%` not followed by two hex digits.
+    InvalidPercentEncoding,
+    /// A `.` or `..` component, which URIs reserve for relative paths.
+    DotComponent,
+    /// A `<type>=` prefix naming a type outside 1..=65535.
+    InvalidComponentType,
+    /// A convention component such as `seg=` without a decimal number.
+    InvalidNumber,
+    /// A digest component without exactly 32 hex-encoded bytes.
+    InvalidDigest,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidPercentEncoding => "invalid percent-encoding in name component",
+            Self::DotComponent => "'.' and '..' are not valid name components",
+            Self::InvalidComponentType => "name component type must be between 1 and 65535",
+            Self::InvalidNumber => "naming convention component needs a decimal number",
+            Self::InvalidDigest => "digest component needs 64 hex digits",
+        })
+    }
+}
+
+impl core::error::Error for NameError {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Component {
+    pub typ: u64,
+    pub value: Vec<u8>,
+}
+
+/// NDN canonical order: by type, then by value length, then by value bytes.
+impl Ord for Component {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.typ
+            .cmp(&other.typ)
+            .then(self.value.len().cmp(&other.value.len()))
+            .then_with(|| self.value.cmp(&other.value))
+    }
+}
+
+impl PartialOrd for Component {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Component {
+    pub fn generic(value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            typ: GENERIC_COMPONENT,
+            value: value.into(),
+        }
+    }
+
+    /// A typed component carrying `number` under `convention`.
+    pub fn with_convention(convention: Convention, number: u64) -> Self {
+        let mut value = Vec::new();
+        tlv::write_nonneg_int(&mut value, number);
+        Self {
+            typ: convention.component_type(),
+            value,
+        }
+    }
+
+    pub fn segment(segment: u64) -> Self {
+        Self::with_convention(Convention::Segment, segment)
+    }
+
+    pub fn byte_offset(offset: u64) -> Self {
+        Self::with_convention(Convention::ByteOffset, offset)
+    }
+
+    pub fn version(version: u64) -> Self {
+        Self::with_convention(Convention::Version, version)
+    }
+
+    /// A timestamp in microseconds since the Unix epoch.
+    pub fn timestamp(micros: u64) -> Self {
+        Self::with_convention(Convention::Timestamp, micros)
+    }
+
+    pub fn sequence_num(sequence: u64) -> Self {
+        Self::with_convention(Convention::SequenceNum, sequence)
+    }
+
+    /// The implicit digest component naming the Data packet whose SHA-256 is `digest`.
+    pub fn implicit_sha256_digest(digest: [u8; 32]) -> Self {
+        Self {
+            typ: IMPLICIT_SHA256_DIGEST_COMPONENT,
+            value: digest.to_vec(),
+        }
+    }
+
+    /// The number this component carries under `convention`. Both the typed form and the older
+    /// marker-prefixed generic form are accepted.
+    pub fn convention_value(&self, convention: Convention) -> Option<u64> {
+        if self.typ == convention.component_type() {
+            return tlv::read_nonneg_int(&self.value);
+        }
+        match self.value.split_first() {
+            Some((&marker, number))
+                if self.typ == GENERIC_COMPONENT && marker == convention.marker() =>
+            {
+                tlv::read_nonneg_int(number)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_segment(&self) -> Option<u64> {
+        self.convention_value(Convention::Segment)
+    }
+
+    pub fn to_byte_offset(&self) -> Option<u64> {
+        self.convention_value(Convention::ByteOffset)
+    }
+
+    pub fn to_version(&self) -> Option<u64> {
+        self.convention_value(Convention::Version)
+    }
+
+    pub fn to_timestamp(&self) -> Option<u64> {
+        self.convention_value(Convention::Timestamp)
+    }
+
+    pub fn to_sequence_num(&self) -> Option<u64> {
+        self.convention_value(Convention::SequenceNum)
+    }
+}
+
+impl Component {
+    /// Parse one `/`-free component of a name URI.
+    pub fn from_uri(uri: &str) -> Result<Self, NameError> {
+        if let Some((label, value)) = uri.split_once('=') {
+            if let Some(typ) = digest_component_type(label) {
+                return Ok(Self {
+                    typ,
+                    value: decode_hex_digest(value).ok_or(NameError::InvalidDigest)?,
+                });
+            }
+            if let Some(convention) = Convention::ALL.into_iter().find(|c| c.uri_label() == label) {
+                let number = value.parse().map_err(|_| NameError::InvalidNumber)?;
+                return Ok(Self::with_convention(convention, number));
+            }
+            if !label.is_empty() && label.bytes().all(|b| b.is_ascii_digit()) {
+                let typ = label.parse().map_err(|_| NameError::InvalidComponentType)?;
+                if !(1..=0xFFFF).contains(&typ) {
+                    return Err(NameError::InvalidComponentType);
+                }
+                return Ok(Self {
+                    typ,
+                    value: decode_component_value(value)?,
+                });
+            }
+        }
+
+        Ok(Self::generic(decode_component_value(uri)?))
+    }
+}
+
+impl fmt::Display for Component {
+    /// Canonical URI form: typed components use their `label=` syntax and values are
+    /// percent-encoded except for unreserved characters.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        component::write_uri(f, self.typ, &self.value)
+    }
+}
+
+fn digest_component_type(label: &str) -> Option<u64> {
+    match label {
+        "sha256digest" => Some(IMPLICIT_SHA256_DIGEST_COMPONENT),
+        "params-sha256" => Some(PARAMETERS_SHA256_DIGEST_COMPONENT),
+        _ => None,
+    }
+}
+
+fn decode_hex_digest(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != 64 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Percent-decode a component value, applying the rule that a value of only periods
+/// carries three extra ones.
+fn decode_component_value(uri: &str) -> Result<Vec<u8>, NameError> {
+    let bytes = uri.as_bytes();
+    if bytes.iter().all(|&b| b == b'.') {
+        return match bytes.len() {
+            0..=2 => Err(NameError::DotComponent),
+            len => Ok(alloc::vec![b'.'; len - 3]),
+        };
+    }
+
+    let mut value = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = uri.get(i + 1..i + 3).ok_or(NameError::InvalidPercentEncoding)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(NameError::InvalidPercentEncoding);
+            }
+            value.push(u8::from_str_radix(hex, 16).map_err(|_| NameError::InvalidPercentEncoding)?);
+            i += 3;
+        } else {
+            value.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(value)
+}
+
+/// Names order canonically component by component, with a name before every name it is a prefix
+/// of, so the names under a prefix sort right after it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Name {
+    components: Vec<Component>,
+}
+
+impl Name {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a name URI such as `/a/%C1.Router/site` or `ndn:/video/v=3/seg=0`.
+    pub fn from_uri(uri: &str) -> Result<Self, NameError> {
+        let path = uri.strip_prefix("ndn:").unwrap_or(uri);
+        let components = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(Component::from_uri)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { components })
+    }
+
+    pub fn components(&self) -> &[Component] {
+        &self.components
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub fn push(&mut self, component: Component) {
+        self.components.push(component);
+    }
+
+    /// This name with `component` appended.
+    pub fn append(mut self, component: Component) -> Self {
+        self.push(component);
+        self
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Component> {
+        self.components.get(index)
+    }
+
+    pub fn last(&self) -> Option<&Component> {
+        self.components.last()
+    }
+
+    /// The first `len` components of this name.
+    pub fn prefix(&self, len: usize) -> Name {
+        Name {
+            components: self.components[..len.min(self.components.len())].to_vec(),
+        }
+    }
+
+    pub fn append_segment(self, segment: u64) -> Self {
+        self.append(Component::segment(segment))
+    }
+
+    pub fn append_byte_offset(self, offset: u64) -> Self {
+        self.append(Component::byte_offset(offset))
+    }
+
+    pub fn append_version(self, version: u64) -> Self {
+        self.append(Component::version(version))
+    }
+
+    pub fn append_timestamp(self, micros: u64) -> Self {
+        self.append(Component::timestamp(micros))
+    }
+
+    pub fn append_sequence_num(self, sequence: u64) -> Self {
+        self.append(Component::sequence_num(sequence))
+    }
+
+    /// The segment number, when the last component is a segment component.
+    pub fn segment(&self) -> Option<u64> {
+        self.last()?.to_segment()
+    }
+
+    /// The version number of the last version component in the name, which precedes the
+    /// segment component in segmented content.
+    pub fn version(&self) -> Option<u64> {
+        self.components.iter().rev().find_map(Component::to_version)
+    }
+
+    pub fn is_prefix_of(&self, other: &Name) -> bool {
+        other.components.starts_with(&self.components)
+    }
+
+    /// The value of the implicit digest component this name ends in, if any.
+    pub fn implicit_digest(&self) -> Option<&[u8]> {
+        self.last()
+            .filter(|component| component.typ == IMPLICIT_SHA256_DIGEST_COMPONENT)
+            .map(|component| component.value.as_slice())
+    }
+
+    /// The hash the datapath keys its tables with for this name. A trailing implicit digest is
+    /// left out: Data names never carry it and the datapath cannot compute it, so an Interest for
+    /// an exact packet shares the PIT and content store entries of the packet's name.
+    pub fn name_hash(&self) -> u32 {
+        match self.implicit_digest() {
+            Some(_) => self.prefix(self.len() - 1).name_hash(),
+            None => hash_name(self.to_string().as_bytes()),
+        }
+    }
+
+    /// Append this name as a Name TLV element to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        for component in &self.components {
+            tlv::write_element(&mut value, component.typ, &component.value);
+        }
+        tlv::write_element(out, TlvType::Name as u64, &value);
+    }
+
+    /// Decode the value of a Name TLV element.
+    pub fn decode(mut value: &[u8]) -> Option<Self> {
+        let mut name = Self::new();
+        while !value.is_empty() {
+            let (element, rest) = tlv::read_element(value)?;
+            name.push(Component {
+                typ: element.typ,
+                value: element.value.to_vec(),
+            });
+            value = rest;
+        }
+        Some(name)
+    }
+}
+
+impl FromStr for Name {
+    type Err = NameError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        Self::from_uri(uri)
+    }
+}
+
+impl From<&str> for Name {
+    /// Parse a name URI like [`Name::from_uri`], keeping any component that fails to parse as
+    /// a generic component holding its literal text.
+    fn from(uri: &str) -> Self {
+        let path = uri.strip_prefix("ndn:").unwrap_or(uri);
+        Self {
+            components: path
+                .split('/')
+                .filter(|component| !component.is_empty())
+                .map(|component| {
+                    Component::from_uri(component)
+                        .unwrap_or_else(|_| Component::generic(component.as_bytes()))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.components.is_empty() {
+            return f.write_str("/");
+        }
+        for component in &self.components {
+            write!(f, "/{component}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Names serialize as their canonical URI so JSON and config files stay human-readable.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uri = <alloc::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Self::from_uri(&uri).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Component types on both sides of the 1- and 3-byte VAR-NUMBER boundaries.
+    fn component_type() -> impl Strategy<Value = u64> {
+        prop_oneof![
+            Just(GENERIC_COMPONENT),
+            1u64..=0x3F,
+            0xF0u64..=0x1_0010,
+            0xFFFF_FFF0u64..=0x1_0000_0010,
+        ]
+    }
+
+    /// Component values mostly short, sometimes long enough to need a 3- or 5-byte TLV length.
+    fn component_value() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            20 => prop::collection::vec(any::<u8>(), 0..16),
+            4 => prop::collection::vec(any::<u8>(), 250..260),
+            1 => prop::collection::vec(any::<u8>(), 65_530..65_540),
+        ]
+    }
+
+    /// Arbitrary names, including component types and lengths no URI could express.
+    pub(crate) fn arb_name() -> impl Strategy<Value = Name> {
+        let component = (component_type(), component_value())
+            .prop_map(|(typ, value)| Component { typ, value });
+        prop::collection::vec(component, 0..6).prop_map(|components| Name { components })
+    }
+
+    /// Names restricted to components that have a URI form, with conventions encoded minimally.
+    fn uri_name() -> impl Strategy<Value = Name> {
+        let component = prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..16).prop_map(Component::generic),
+            (prop::sample::select(Convention::ALL.to_vec()), any::<u64>())
+                .prop_map(|(convention, number)| Component::with_convention(convention, number)),
+            (3u64..=0xFFFF, prop::collection::vec(any::<u8>(), 0..16))
+                .prop_filter("convention types print as numbers", |(typ, _)| {
+                    Convention::from_component_type(*typ).is_none()
+                })
+                .prop_map(|(typ, value)| Component { typ, value }),
+            (1u64..=2, prop::collection::vec(any::<u8>(), 32))
+                .prop_map(|(typ, value)| Component { typ, value }),
+        ];
+        prop::collection::vec(component, 0..6).prop_map(|components| Name { components })
+    }
+
+    proptest! {
+        #[test]
+        fn test_name_tlv_roundtrip(name in arb_name()) {
+            let mut out = Vec::new();
+            name.encode(&mut out);
+            let (element, rest) = tlv::read_element(&out).unwrap();
+            prop_assert!(rest.is_empty());
+            prop_assert_eq!(Name::decode(element.value), Some(name));
+        }
+
+        #[test]
+        fn test_name_uri_roundtrip(name in uri_name()) {
+            prop_assert_eq!(Name::from_uri(&name.to_string()), Ok(name));
+        }
+
+        #[test]
+        fn test_name_decode_never_panics(buf in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = Name::decode(&buf);
+        }
+    }
+
+    #[test]
+    fn test_convention_components_roundtrip() {
+        let name = Name::from("/video")
+            .append_version(1_700_000_000)
+            .append_segment(300);
+
+        assert_eq!(name.to_string(), "/video/v=1700000000/seg=300");
+        assert_eq!(name.segment(), Some(300));
+        assert_eq!(name.version(), Some(1_700_000_000));
+        assert_eq!(name.prefix(2).segment(), None);
+        assert_eq!(name.prefix(2).version(), Some(1_700_000_000));
+
+        let mut encoded = Vec::new();
+        name.encode(&mut encoded);
+        let (element, rest) = tlv::read_element(&encoded).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(Name::decode(element.value).unwrap(), name);
+    }
+
+    #[test]
+    fn test_convention_values_use_shortest_encoding() {
+        assert_eq!(Component::segment(0).value, [0]);
+        assert_eq!(Component::segment(256).value, [1, 0]);
+        assert_eq!(Component::sequence_num(1 << 40).value.len(), 8);
+    }
+
+    #[test]
+    fn test_marker_components_are_understood() {
+        let segment = Component::generic(&[0x00, 0x01, 0x00][..]);
+        let version = Component::generic(&[0xFD, 0x05][..]);
+
+        assert_eq!(segment.to_segment(), Some(256));
+        assert_eq!(version.to_version(), Some(5));
+        assert_eq!(version.to_segment(), None);
+        assert_eq!(Component::generic("seg").to_segment(), None);
+    }
+
+    #[test]
+    fn test_uri_percent_decoding() {
+        let name = Name::from_uri("/a/%C1.Router/site").unwrap();
+
+        assert_eq!(name.len(), 3);
+        assert_eq!(name.get(1).unwrap().value, b"\xC1.Router");
+        assert_eq!(name.to_string(), "/a/%C1.Router/site");
+        assert_eq!(Name::from_uri("ndn:/a/b%20c/").unwrap().to_string(), "/a/b%20c");
+    }
+
+    #[test]
+    fn test_uri_canonical_printing() {
+        let name = Name::new()
+            .append(Component::generic("hello world"))
+            .append(Component::generic(""))
+            .append(Component::generic("."))
+            .append(Component {
+                typ: 200,
+                value: b"x/y".to_vec(),
+            });
+
+        assert_eq!(name.to_string(), "/hello%20world/.../..../200=x%2Fy");
+        assert_eq!(Name::from_uri(&name.to_string()).unwrap(), name);
+        assert_eq!(Name::new().to_string(), "/");
+        assert!(Name::from_uri("/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_uri_typed_components() {
+        let digest = "ab".repeat(32);
+        let name = Name::from_uri(&alloc::format!("/a/v=7/seg=2/sha256digest={digest}")).unwrap();
+
+        assert_eq!(name.get(1).unwrap().to_version(), Some(7));
+        assert_eq!(name.get(2).unwrap().to_segment(), Some(2));
+        assert_eq!(name.last().unwrap().typ, IMPLICIT_SHA256_DIGEST_COMPONENT);
+        assert_eq!(name.last().unwrap().value, [0xAB; 32]);
+        assert_eq!(name.to_string(), alloc::format!("/a/v=7/seg=2/sha256digest={digest}"));
+
+        assert_eq!(name.implicit_digest(), Some(&[0xAB; 32][..]));
+        assert_eq!(name.name_hash(), Name::from("/a/v=7/seg=2").name_hash());
+        assert_eq!(Name::from("/a/v=7/seg=2").implicit_digest(), None);
+        let exact = name.prefix(3).append(Component::implicit_sha256_digest([0xAB; 32]));
+        assert_eq!(exact, name);
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let mut names: Vec<Name> = ["/b/seg=1", "/aa", "/b", "/b/seg=0", "/b/x", "/c"]
+            .into_iter()
+            .map(Name::from)
+            .collect();
+        names.sort();
+        let uris: Vec<_> = names.iter().map(|name| name.to_string()).collect();
+        // Shorter values first, generic components (type 8) before segments (type 0x32)
+        assert_eq!(uris, ["/b", "/b/x", "/b/seg=0", "/b/seg=1", "/c", "/aa"]);
+    }
+
+    #[test]
+    fn test_uri_errors() {
+        assert_eq!(Name::from_uri("/a/%G1"), Err(NameError::InvalidPercentEncoding));
+        assert_eq!(Name::from_uri("/a/%4"), Err(NameError::InvalidPercentEncoding));
+        assert_eq!(Name::from_uri("/a/../b"), Err(NameError::DotComponent));
+        assert_eq!(Name::from_uri("/0=x"), Err(NameError::InvalidComponentType));
+        assert_eq!(Name::from_uri("/70000=x"), Err(NameError::InvalidComponentType));
+        assert_eq!(Name::from_uri("/seg=x"), Err(NameError::InvalidNumber));
+        assert_eq!(Name::from_uri("/sha256digest=ab"), Err(NameError::InvalidDigest));
+
+        // The infallible conversion keeps such components literally
+        assert_eq!(Name::from("/seg=x").get(0).unwrap().value, b"seg=x");
+    }
+
+    #[test]
+    fn test_typed_and_generic_components_differ() {
+        let typed = Name::from("/a").append_segment(1);
+        let generic = Name::from("/a").append(Component::generic(&[1][..]));
+
+        assert_ne!(typed, generic);
+        assert_ne!(typed.name_hash(), generic.name_hash());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_uses_canonical_uri() {
+        let name = Name::from_uri("/a/b%20c").unwrap().append_segment(3);
+        let json = serde_json::to_string(&name).unwrap();
+        assert_eq!(json, r#""/a/b%20c/seg=3""#);
+        assert_eq!(serde_json::from_str::<Name>(&json).unwrap(), name);
+        assert!(serde_json::from_str::<Name>(r#""/a/../b""#).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Converting packets between the µDCN-native wire format and NDN packet format v0.3.
+//!
+//! A native packet starts with the fixed-layout header the XDP program parses, and a native Data
+//! carries its content between that header and its TLV elements. An NDN packet is a single
+//! Interest or Data element instead, with the nonce as a Nonce element and the content as a
+//! Content element. The elements a Data's signature covers are the same in both formats and in the
+//! same order, so a converted Data still verifies against its signature.
+//!
+//! Native Nacks carry only the Interest's name hash, and standard NDN has no Nack packet outside
+//! an NDNLPv2 header, so they do not convert.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{
+    hash_name,
+    name::Name,
+    packet::{decode_delegations, MetaInfo, SignatureInfo, MAX_CONTENT_LEN},
+    parse_nack_packet, tlv, Data, Interest, TlvType,
+};
+
+/// A wire format packets can be converted between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The fixed header the datapath parses, followed by TLV elements.
+    Native,
+    /// NDN packet format v0.3, as other NDN forwarders and libraries send it.
+    Ndn03,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Native => "native",
+            Self::Ndn03 => "ndn03",
+        })
+    }
+}
+
+/// Why a packet could not be converted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvertError {
+    /// Not an Interest or Data in the format it was said to be in.
+    Malformed,
+    /// A native Nack, which has no NDN packet to become.
+    Nack,
+    /// A Data with more content than the native header's 16-bit size can describe.
+    ContentTooLarge,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Malformed => "not an Interest or Data in the given format",
+            Self::Nack => "native Nacks have no NDN packet format equivalent",
+            Self::ContentTooLarge => "Data content exceeds the 64 KiB a native packet can carry",
+        })
+    }
+}
+
+impl core::error::Error for ConvertError {}
+
+/// The value of the single `typ` element `buf` holds.
+fn outer_value(buf: &[u8], typ: TlvType) -> Option<&[u8]> {
+    let (element, rest) = tlv::read_element(buf)?;
+    (element.typ == typ as u64 && rest.is_empty()).then_some(element.value)
+}
+
+impl Interest {
+    /// Encode the Interest as an NDN v0.3 Interest element, its nonce in a Nonce element.
+    pub fn encode_ndn03(&self) -> Vec<u8> {
+        let mut value = Vec::new();
+        self.name.encode(&mut value);
+        if self.can_be_prefix {
+            tlv::write_element(&mut value, TlvType::CanBePrefix as u64, &[]);
+        }
+        if self.must_be_fresh {
+            tlv::write_element(&mut value, TlvType::MustBeFresh as u64, &[]);
+        }
+        if !self.forwarding_hint.is_empty() {
+            let mut delegations = Vec::new();
+            for delegation in &self.forwarding_hint {
+                delegation.encode(&mut delegations);
+            }
+            tlv::write_element(&mut value, TlvType::ForwardingHint as u64, &delegations);
+        }
+        tlv::write_element(&mut value, TlvType::Nonce as u64, &self.nonce.to_be_bytes());
+        if let Some(lifetime_ms) = self.lifetime_ms {
+            tlv::write_nonneg_int_element(
+                &mut value,
+                TlvType::InterestLifetime as u64,
+                lifetime_ms,
+            );
+        }
+        if let Some(parameters) = &self.application_parameters {
+            tlv::write_element(
+                &mut value,
+                TlvType::ApplicationParameters as u64,
+                parameters,
+            );
+        }
+
+        let mut out = Vec::new();
+        tlv::write_element(&mut out, TlvType::Interest as u64, &value);
+        out
+    }
+
+    /// Decode an NDN v0.3 Interest element. One without a Nonce is given a nonce hashed from its
+    /// elements, so converting it again gives the same packet. Elements the native format has no
+    /// field for, such as HopLimit, are dropped.
+    pub fn decode_ndn03(buf: &[u8]) -> Option<Self> {
+        let mut rest = outer_value(buf, TlvType::Interest)?;
+        let value = rest;
+
+        let mut name = None;
+        let mut nonce = None;
+        let mut interest = Interest {
+            name: Name::new(),
+            nonce: 0,
+            can_be_prefix: false,
+            must_be_fresh: false,
+            lifetime_ms: None,
+            forwarding_hint: Vec::new(),
+            application_parameters: None,
+        };
+        while !rest.is_empty() {
+            let (element, next) = tlv::read_element(rest)?;
+            match element.typ {
+                t if t == TlvType::Name as u64 => name = Some(Name::decode(element.value)?),
+                t if t == TlvType::CanBePrefix as u64 => interest.can_be_prefix = true,
+                t if t == TlvType::MustBeFresh as u64 => interest.must_be_fresh = true,
+                t if t == TlvType::ForwardingHint as u64 => {
+                    interest.forwarding_hint = decode_delegations(element.value)?
+                }
+                t if t == TlvType::Nonce as u64 => {
+                    nonce = Some(u32::from_be_bytes(element.value.try_into().ok()?))
+                }
+                t if t == TlvType::InterestLifetime as u64 => {
+                    interest.lifetime_ms = Some(tlv::read_nonneg_int(element.value)?)
+                }
+                t if t == TlvType::ApplicationParameters as u64 => {
+                    interest.application_parameters = Some(element.value.to_vec())
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+
+        interest.name = name?;
+        interest.nonce = nonce.unwrap_or_else(|| hash_name(value));
+        Some(interest)
+    }
+}
+
+impl Data {
+    /// Encode the Data as an NDN v0.3 Data element. Its signature is kept as it is.
+    pub fn encode_ndn03(&self) -> Vec<u8> {
+        let mut value = self.signed_portion();
+        if !self.signature_value.is_empty() {
+            tlv::write_element(
+                &mut value,
+                TlvType::SignatureValue as u64,
+                &self.signature_value,
+            );
+        }
+
+        let mut out = Vec::new();
+        tlv::write_element(&mut out, TlvType::Data as u64, &value);
+        out
+    }
+
+    /// Decode an NDN v0.3 Data element. One without a Content element has empty content.
+    pub fn decode_ndn03(buf: &[u8]) -> Option<Self> {
+        let mut rest = outer_value(buf, TlvType::Data)?;
+
+        let mut name = None;
+        let mut meta = MetaInfo::default();
+        let mut content = Vec::new();
+        let mut signature_info = None;
+        let mut signature_value = Vec::new();
+        while !rest.is_empty() {
+            let (element, next) = tlv::read_element(rest)?;
+            match element.typ {
+                t if t == TlvType::Name as u64 => name = Some(Name::decode(element.value)?),
+                t if t == TlvType::MetaInfo as u64 => meta = MetaInfo::decode(element.value)?,
+                t if t == TlvType::Content as u64 => content = element.value.to_vec(),
+                t if t == TlvType::SignatureInfo as u64 => {
+                    signature_info = Some(SignatureInfo::decode(element.value)?)
+                }
+                t if t == TlvType::SignatureValue as u64 => {
+                    signature_value = element.value.to_vec()
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+
+        Some(Data {
+            name: name?,
+            meta,
+            content,
+            signature_info,
+            signature_value,
+        })
+    }
+}
+
+/// Convert an Interest or Data from the `from` format to the `to` one.
+pub fn convert(packet: &[u8], from: Format, to: Format) -> Result<Vec<u8>, ConvertError> {
+    enum Decoded {
+        Interest(Interest),
+        Data(Data),
+    }
+
+    let decoded = match from {
+        Format::Native => {
+            if parse_nack_packet(packet).is_some() {
+                return Err(ConvertError::Nack);
+            }
+            Interest::decode(packet)
+                .map(Decoded::Interest)
+                .or_else(|| Data::decode(packet).map(Decoded::Data))
+        }
+        Format::Ndn03 => Interest::decode_ndn03(packet)
+            .map(Decoded::Interest)
+            .or_else(|| Data::decode_ndn03(packet).map(Decoded::Data)),
+    }
+    .ok_or(ConvertError::Malformed)?;
+
+    Ok(match (decoded, to) {
+        (Decoded::Interest(interest), Format::Native) => interest.encode(),
+        (Decoded::Interest(interest), Format::Ndn03) => interest.encode_ndn03(),
+        (Decoded::Data(data), Format::Native) => {
+            if data.content.len() > MAX_CONTENT_LEN {
+                return Err(ConvertError::ContentTooLarge);
+            }
+            data.encode()
+        }
+        (Decoded::Data(data), Format::Ndn03) => data.encode_ndn03(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SIGNATURE_HMAC_WITH_SHA256;
+
+    #[test]
+    fn test_packets_convert_both_ways() {
+        let interest = Interest::builder()
+            .name(Name::from_uri("/video/clip/seg=3").unwrap())
+            .nonce(0x0102_0304)
+            .can_be_prefix(true)
+            .lifetime_ms(4000)
+            .forwarding_hint(Name::from_uri("/isp").unwrap())
+            .application_parameters(&b"token"[..])
+            .build();
+        let ndn = convert(&interest.encode(), Format::Native, Format::Ndn03).unwrap();
+        assert_eq!(ndn[0], TlvType::Interest as u8);
+        assert!(ndn.windows(6).any(|nonce| nonce == [0x0A, 4, 1, 2, 3, 4]));
+        assert_eq!(Interest::decode_ndn03(&ndn), Some(interest.clone()));
+        assert_eq!(
+            convert(&ndn, Format::Ndn03, Format::Native),
+            Ok(interest.encode())
+        );
+
+        let data = Data::builder()
+            .name(Name::from_uri("/video/clip/seg=3").unwrap())
+            .content(&b"frame"[..])
+            .freshness_period_ms(1000)
+            .signature_info(
+                SIGNATURE_HMAC_WITH_SHA256,
+                Some(Name::from_uri("/KEY").unwrap()),
+            )
+            .sign_with(|signed| alloc::vec![signed.len() as u8; 32])
+            .build();
+        let ndn = convert(&data.encode(), Format::Native, Format::Ndn03).unwrap();
+        let (outer, _) = tlv::read_element(&ndn).unwrap();
+        // The signed portion is carried over byte for byte
+        assert!(outer.value.starts_with(&data.signed_portion()));
+        assert_eq!(
+            convert(&ndn, Format::Ndn03, Format::Native),
+            Ok(data.encode())
+        );
+
+        // An NDN Interest without a Nonce gets the same one each time
+        let mut value = Vec::new();
+        interest.name.encode(&mut value);
+        let mut bare = Vec::new();
+        tlv::write_element(&mut bare, TlvType::Interest as u64, &value);
+        let first = convert(&bare, Format::Ndn03, Format::Native).unwrap();
+        assert_eq!(
+            convert(&bare, Format::Ndn03, Format::Native).unwrap(),
+            first
+        );
+
+        let nack = crate::serialize_nack(&interest.header(), crate::NackReason::NoRoute);
+        assert_eq!(
+            convert(&nack, Format::Native, Format::Ndn03),
+            Err(ConvertError::Nack)
+        );
+        assert_eq!(
+            convert(&interest.encode(), Format::Ndn03, Format::Native),
+            Err(ConvertError::Malformed)
+        );
+        let large = Data::builder()
+            .name(Name::from_uri("/large").unwrap())
+            .content(alloc::vec![0; 70_000])
+            .build()
+            .encode_ndn03();
+        assert_eq!(
+            convert(&large, Format::Ndn03, Format::Native),
+            Err(ConvertError::ContentTooLarge)
+        );
+    }
+}
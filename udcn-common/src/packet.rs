@@ -0,0 +1,691 @@
+//! Structured Interest and Data packets and their builders.
+//!
+//! On the wire each packet starts with the fixed-layout header the XDP program parses
+//! (`InterestPacket` or `DataPacket`, multi-byte fields in network byte order), followed by NDN
+//! TLV elements carrying the full name and the fields only userspace looks at. For Data the content sits between the header and those
+//! elements, as it does in packets built by `serialize_data`.
+
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::hash::BuildHasher as _;
+use core::mem;
+
+use crate::{
+    hash_name, name::Name, parse_data_packet, parse_interest_packet, tlv, DataPacket,
+    InterestPacket, TlvType,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interest {
+    pub name: Name,
+    pub nonce: u32,
+    pub can_be_prefix: bool,
+    pub must_be_fresh: bool,
+    pub lifetime_ms: Option<u64>,
+    /// Delegation names, in order of preference, to route by when no FIB entry matches `name`,
+    /// e.g. the provider namespace a producer is reachable under.
+    pub forwarding_hint: Vec<Name>,
+    /// Parameters for the producer, such as the access token of a gated namespace.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub application_parameters: Option<Vec<u8>>,
+}
+
+impl Interest {
+    pub fn builder() -> InterestBuilder {
+        InterestBuilder::default()
+    }
+
+    /// The fixed header the datapath sees for this Interest.
+    pub fn header(&self) -> InterestPacket {
+        InterestPacket::new(self.name.name_hash(), self.nonce)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+
+    /// Append the encoded Interest to `out`, e.g. a buffer shared by a batch of them.
+    pub fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.header().to_bytes());
+
+        self.name.encode(out);
+        if self.can_be_prefix {
+            tlv::write_element(out, TlvType::CanBePrefix as u64, &[]);
+        }
+        if self.must_be_fresh {
+            tlv::write_element(out, TlvType::MustBeFresh as u64, &[]);
+        }
+        if let Some(lifetime_ms) = self.lifetime_ms {
+            tlv::write_nonneg_int_element(out, TlvType::InterestLifetime as u64, lifetime_ms);
+        }
+        // After the InterestLifetime rather than before it as in NDN, which keeps the lifetime
+        // within the few elements the XDP program looks through
+        if !self.forwarding_hint.is_empty() {
+            let mut value = Vec::new();
+            for delegation in &self.forwarding_hint {
+                delegation.encode(&mut value);
+            }
+            tlv::write_element(out, TlvType::ForwardingHint as u64, &value);
+        }
+        if let Some(parameters) = &self.application_parameters {
+            tlv::write_element(out, TlvType::ApplicationParameters as u64, parameters);
+        }
+    }
+
+    /// Decode an Interest built by [`Interest::encode`]. Packets without a Name element, such as
+    /// those from `serialize_interest`, only carry the fixed header and are rejected.
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let header = parse_interest_packet(buf)?;
+        let mut rest = &buf[mem::size_of::<InterestPacket>()..];
+
+        let mut name = None;
+        let mut interest = Interest {
+            name: Name::new(),
+            nonce: header.nonce,
+            can_be_prefix: false,
+            must_be_fresh: false,
+            lifetime_ms: None,
+            forwarding_hint: Vec::new(),
+            application_parameters: None,
+        };
+        while !rest.is_empty() {
+            let (element, next) = tlv::read_element(rest)?;
+            match element.typ {
+                t if t == TlvType::Name as u64 => name = Some(Name::decode(element.value)?),
+                t if t == TlvType::CanBePrefix as u64 => interest.can_be_prefix = true,
+                t if t == TlvType::MustBeFresh as u64 => interest.must_be_fresh = true,
+                t if t == TlvType::InterestLifetime as u64 => {
+                    interest.lifetime_ms = Some(tlv::read_nonneg_int(element.value)?)
+                }
+                t if t == TlvType::ForwardingHint as u64 => {
+                    interest.forwarding_hint = decode_delegations(element.value)?
+                }
+                t if t == TlvType::ApplicationParameters as u64 => {
+                    interest.application_parameters = Some(element.value.to_vec())
+                }
+                // Unrecognized non-critical elements are skipped
+                _ => {}
+            }
+            rest = next;
+        }
+
+        interest.name = name?;
+        Some(interest)
+    }
+}
+
+/// The Names in the value of a ForwardingHint element.
+pub(crate) fn decode_delegations(mut value: &[u8]) -> Option<Vec<Name>> {
+    let mut delegations = Vec::new();
+    while !value.is_empty() {
+        let (element, rest) = tlv::read_element(value)?;
+        if element.typ != TlvType::Name as u64 {
+            return None;
+        }
+        delegations.push(Name::decode(element.value)?);
+        value = rest;
+    }
+    Some(delegations)
+}
+
+#[derive(Default)]
+pub struct InterestBuilder {
+    name: Name,
+    nonce: Option<u32>,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: Option<u64>,
+    forwarding_hint: Vec<Name>,
+    application_parameters: Option<Vec<u8>>,
+}
+
+impl InterestBuilder {
+    pub fn name(mut self, name: impl Into<Name>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Use a specific nonce instead of a random one.
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn can_be_prefix(mut self, can_be_prefix: bool) -> Self {
+        self.can_be_prefix = can_be_prefix;
+        self
+    }
+
+    pub fn must_be_fresh(mut self, must_be_fresh: bool) -> Self {
+        self.must_be_fresh = must_be_fresh;
+        self
+    }
+
+    pub fn lifetime_ms(mut self, lifetime_ms: u64) -> Self {
+        self.lifetime_ms = Some(lifetime_ms);
+        self
+    }
+
+    /// Add a delegation to the ForwardingHint, after any added before.
+    pub fn forwarding_hint(mut self, delegation: impl Into<Name>) -> Self {
+        self.forwarding_hint.push(delegation.into());
+        self
+    }
+
+    pub fn application_parameters(mut self, parameters: impl Into<Vec<u8>>) -> Self {
+        self.application_parameters = Some(parameters.into());
+        self
+    }
+
+    /// The Interest, with a random nonce unless one was set. Without the `std` feature there is
+    /// nothing to draw one from, and only [`InterestBuilder::try_build`] is available.
+    #[cfg(feature = "std")]
+    pub fn build(self) -> Interest {
+        let nonce = self.nonce.unwrap_or_else(random_nonce);
+        self.finish(nonce)
+    }
+
+    /// The Interest, or without the `std` feature, `None` unless a nonce was set.
+    pub fn try_build(self) -> Option<Interest> {
+        #[cfg(feature = "std")]
+        let nonce = self.nonce.unwrap_or_else(random_nonce);
+        #[cfg(not(feature = "std"))]
+        let nonce = self.nonce?;
+        Some(self.finish(nonce))
+    }
+
+    fn finish(self, nonce: u32) -> Interest {
+        Interest {
+            name: self.name,
+            nonce,
+            can_be_prefix: self.can_be_prefix,
+            must_be_fresh: self.must_be_fresh,
+            lifetime_ms: self.lifetime_ms,
+            forwarding_hint: self.forwarding_hint,
+            application_parameters: self.application_parameters,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetaInfo {
+    pub content_type: Option<u64>,
+    pub freshness_period_ms: Option<u64>,
+}
+
+impl MetaInfo {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.content_type.is_none() && self.freshness_period_ms.is_none()
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        if let Some(content_type) = self.content_type {
+            tlv::write_nonneg_int_element(&mut value, TlvType::ContentType as u64, content_type);
+        }
+        if let Some(freshness_period_ms) = self.freshness_period_ms {
+            tlv::write_nonneg_int_element(
+                &mut value,
+                TlvType::FreshnessPeriod as u64,
+                freshness_period_ms,
+            );
+        }
+        tlv::write_element(out, TlvType::MetaInfo as u64, &value);
+    }
+
+    pub(crate) fn decode(mut value: &[u8]) -> Option<Self> {
+        let mut meta = Self::default();
+        while !value.is_empty() {
+            let (element, rest) = tlv::read_element(value)?;
+            match element.typ {
+                t if t == TlvType::ContentType as u64 => {
+                    meta.content_type = Some(tlv::read_nonneg_int(element.value)?)
+                }
+                t if t == TlvType::FreshnessPeriod as u64 => {
+                    meta.freshness_period_ms = Some(tlv::read_nonneg_int(element.value)?)
+                }
+                _ => {}
+            }
+            value = rest;
+        }
+        Some(meta)
+    }
+}
+
+/// How a Data is signed: the algorithm and, for keyed ones, the name of the key or certificate
+/// that checks the signature.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignatureInfo {
+    pub signature_type: u64,
+    pub key_locator: Option<Name>,
+}
+
+impl SignatureInfo {
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        let mut value = Vec::new();
+        tlv::write_nonneg_int_element(
+            &mut value,
+            TlvType::SignatureType as u64,
+            self.signature_type,
+        );
+        if let Some(key_locator) = &self.key_locator {
+            let mut locator = Vec::new();
+            key_locator.encode(&mut locator);
+            tlv::write_element(&mut value, TlvType::KeyLocator as u64, &locator);
+        }
+        tlv::write_element(out, TlvType::SignatureInfo as u64, &value);
+    }
+
+    pub(crate) fn decode(mut value: &[u8]) -> Option<Self> {
+        let mut signature_type = None;
+        let mut key_locator = None;
+        while !value.is_empty() {
+            let (element, rest) = tlv::read_element(value)?;
+            match element.typ {
+                t if t == TlvType::SignatureType as u64 => {
+                    signature_type = Some(tlv::read_nonneg_int(element.value)?)
+                }
+                t if t == TlvType::KeyLocator as u64 => {
+                    let (name, _) = tlv::read_element(element.value)?;
+                    if name.typ != TlvType::Name as u64 {
+                        return None;
+                    }
+                    key_locator = Some(Name::decode(name.value)?)
+                }
+                _ => {}
+            }
+            value = rest;
+        }
+        Some(Self {
+            signature_type: signature_type?,
+            key_locator,
+        })
+    }
+}
+
+/// Most content a Data can carry, as much as the header's 16-bit `content_size` describes.
+pub const MAX_CONTENT_LEN: usize = u16::MAX as usize;
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Data {
+    pub name: Name,
+    pub meta: MetaInfo,
+    pub content: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub signature_info: Option<SignatureInfo>,
+    pub signature_value: Vec<u8>,
+}
+
+impl Data {
+    pub fn builder() -> DataBuilder {
+        DataBuilder::default()
+    }
+
+    /// The fixed header the datapath sees for this Data. Its 32-bit `signature` field holds a
+    /// fingerprint of the signature value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the content is longer than [`MAX_CONTENT_LEN`].
+    pub fn header(&self) -> DataPacket {
+        let content_size = u16::try_from(self.content.len()).expect("Data content exceeds 64 KiB");
+        DataPacket::new(self.name.name_hash(), content_size, hash_name(&self.signature_value))
+    }
+
+    /// The Name, MetaInfo, Content and SignatureInfo elements a signature covers.
+    pub fn signed_portion(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.name.encode(&mut out);
+        if !self.meta.is_empty() {
+            self.meta.encode(&mut out);
+        }
+        tlv::write_element(&mut out, TlvType::Content as u64, &self.content);
+        if let Some(signature_info) = &self.signature_info {
+            signature_info.encode(&mut out);
+        }
+        out
+    }
+
+    /// The name of the key or certificate that checks this Data's signature, if it names one.
+    pub fn key_locator(&self) -> Option<&Name> {
+        self.signature_info.as_ref()?.key_locator.as_ref()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.header().to_bytes().to_vec();
+        out.extend_from_slice(&self.content);
+        self.name.encode(&mut out);
+        if !self.meta.is_empty() {
+            self.meta.encode(&mut out);
+        }
+        if let Some(signature_info) = &self.signature_info {
+            signature_info.encode(&mut out);
+        }
+        if !self.signature_value.is_empty() {
+            tlv::write_element(&mut out, TlvType::SignatureValue as u64, &self.signature_value);
+        }
+        out
+    }
+
+    /// Decode a Data packet built by [`Data::encode`].
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let header = parse_data_packet(buf)?;
+        let content_start = mem::size_of::<DataPacket>();
+        let content_end = content_start + header.content_size as usize;
+        let content = buf.get(content_start..content_end)?.to_vec();
+        let mut rest = &buf[content_end..];
+
+        let mut name = None;
+        let mut meta = MetaInfo::default();
+        let mut signature_info = None;
+        let mut signature_value = Vec::new();
+        while !rest.is_empty() {
+            let (element, next) = tlv::read_element(rest)?;
+            match element.typ {
+                t if t == TlvType::Name as u64 => name = Some(Name::decode(element.value)?),
+                t if t == TlvType::MetaInfo as u64 => meta = MetaInfo::decode(element.value)?,
+                t if t == TlvType::SignatureInfo as u64 => {
+                    signature_info = Some(SignatureInfo::decode(element.value)?)
+                }
+                t if t == TlvType::SignatureValue as u64 => {
+                    signature_value = element.value.to_vec()
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+
+        Some(Data {
+            name: name?,
+            meta,
+            content,
+            signature_info,
+            signature_value,
+        })
+    }
+}
+
+type Signer = Box<dyn FnOnce(&[u8]) -> Vec<u8>>;
+
+#[derive(Default)]
+pub struct DataBuilder {
+    name: Name,
+    meta: MetaInfo,
+    content: Vec<u8>,
+    signature_info: Option<SignatureInfo>,
+    signer: Option<Signer>,
+}
+
+impl DataBuilder {
+    pub fn name(mut self, name: impl Into<Name>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn content(mut self, content: impl Into<Vec<u8>>) -> Self {
+        self.content = content.into();
+        self
+    }
+
+    pub fn meta(mut self, meta: MetaInfo) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn freshness_period_ms(mut self, freshness_period_ms: u64) -> Self {
+        self.meta.freshness_period_ms = Some(freshness_period_ms);
+        self
+    }
+
+    /// Set the ContentType, e.g. [`crate::CONTENT_TYPE_NO_CACHE`] to keep the Data out of
+    /// forwarders' content stores.
+    pub fn content_type(mut self, content_type: u64) -> Self {
+        self.meta.content_type = Some(content_type);
+        self
+    }
+
+    /// Describe the signature [`DataBuilder::sign_with`] makes, so verifiers know the algorithm
+    /// and which key to check it with.
+    pub fn signature_info(mut self, signature_type: u64, key_locator: Option<Name>) -> Self {
+        self.signature_info = Some(SignatureInfo {
+            signature_type,
+            key_locator,
+        });
+        self
+    }
+
+    /// Sign the packet when it is built: `signer` receives the [`Data::signed_portion`] and
+    /// returns the signature value.
+    pub fn sign_with(mut self, signer: impl FnOnce(&[u8]) -> Vec<u8> + 'static) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    pub fn build(self) -> Data {
+        let mut data = Data {
+            name: self.name,
+            meta: self.meta,
+            content: self.content,
+            signature_info: self.signature_info,
+            signature_value: Vec::new(),
+        };
+        if let Some(signer) = self.signer {
+            data.signature_value = signer(&data.signed_portion());
+        }
+        data
+    }
+}
+
+/// A nonce from the randomly seeded hasher std already provides, avoiding an RNG dependency.
+#[cfg(feature = "std")]
+fn random_nonce() -> u32 {
+    std::collections::hash_map::RandomState::new().hash_one(0u8) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{name::tests::arb_name, serialize_interest};
+
+    fn arb_interest() -> impl Strategy<Value = Interest> {
+        (
+            arb_name(),
+            any::<u32>(),
+            any::<bool>(),
+            any::<bool>(),
+            any::<Option<u64>>(),
+            prop::collection::vec(arb_name(), 0..3),
+            prop::option::of(prop::collection::vec(any::<u8>(), 0..48)),
+        )
+            .prop_map(
+                |(
+                    name,
+                    nonce,
+                    can_be_prefix,
+                    must_be_fresh,
+                    lifetime_ms,
+                    forwarding_hint,
+                    application_parameters,
+                )| {
+                    Interest {
+                        name,
+                        nonce,
+                        can_be_prefix,
+                        must_be_fresh,
+                        lifetime_ms,
+                        forwarding_hint,
+                        application_parameters,
+                    }
+                },
+            )
+    }
+
+    fn arb_data() -> impl Strategy<Value = Data> {
+        let meta = (any::<Option<u64>>(), any::<Option<u64>>()).prop_map(
+            |(content_type, freshness_period_ms)| MetaInfo { content_type, freshness_period_ms },
+        );
+        let signature_info = prop::option::of((any::<u64>(), prop::option::of(arb_name())))
+            .prop_map(|info| {
+                info.map(|(signature_type, key_locator)| SignatureInfo {
+                    signature_type,
+                    key_locator,
+                })
+            });
+        (
+            arb_name(),
+            meta,
+            prop::collection::vec(any::<u8>(), 0..300),
+            signature_info,
+            prop::collection::vec(any::<u8>(), 0..300),
+        )
+            .prop_map(|(name, meta, content, signature_info, signature_value)| Data {
+                name,
+                meta,
+                content,
+                signature_info,
+                signature_value,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_interest_roundtrip(interest in arb_interest()) {
+            let encoded = interest.encode();
+            prop_assert_eq!(Interest::decode(&encoded), Some(interest));
+        }
+
+        #[test]
+        fn test_data_roundtrip(data in arb_data()) {
+            let encoded = data.encode();
+            prop_assert_eq!(Data::decode(&encoded), Some(data));
+        }
+
+        #[test]
+        fn test_decoders_handle_truncation(interest in arb_interest(), data in arb_data()) {
+            let interest = interest.encode();
+            for len in 0..interest.len().min(64) {
+                let _ = Interest::decode(&interest[..len]);
+            }
+            let data = data.encode();
+            for len in 0..data.len().min(64) {
+                let _ = Data::decode(&data[..len]);
+            }
+        }
+
+        #[test]
+        fn test_decoders_never_panic(buf in prop::collection::vec(any::<u8>(), 0..128)) {
+            let _ = Interest::decode(&buf);
+            let _ = Data::decode(&buf);
+        }
+    }
+
+    #[test]
+    fn test_try_build_keeps_the_nonce_set() {
+        let interest = Interest::builder().name("/a").nonce(7).try_build().unwrap();
+        assert_eq!(interest.nonce, 7);
+        assert_eq!(interest.name, Name::from("/a"));
+    }
+
+    // Builds Interests without a nonce, which takes std
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_interest_builder_roundtrip() {
+        let interest = Interest::builder()
+            .name("/video/stream1")
+            .must_be_fresh(true)
+            .lifetime_ms(4000)
+            .build();
+
+        let encoded = interest.encode();
+        let header = parse_interest_packet(&encoded).unwrap();
+        assert_eq!(header.name_hash, hash_name(b"/video/stream1"));
+        assert_eq!(header.nonce, interest.nonce);
+
+        let decoded = Interest::decode(&encoded).unwrap();
+        assert_eq!(decoded, interest);
+        assert!(!decoded.can_be_prefix);
+    }
+
+    // Builds Interests without a nonce, which takes std
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_forwarding_hint_roundtrip() {
+        let interest = Interest::builder()
+            .name("/alice/video/1")
+            .lifetime_ms(2000)
+            .forwarding_hint("/isp-a")
+            .forwarding_hint("/isp-b/pop1")
+            .build();
+        let encoded = interest.encode();
+        let decoded = Interest::decode(&encoded).unwrap();
+        assert_eq!(decoded.forwarding_hint, [Name::from("/isp-a"), Name::from("/isp-b/pop1")]);
+        // The hint does not change what the datapath keys the Interest by
+        let header = parse_interest_packet(&encoded).unwrap();
+        assert_eq!(header.name_hash, hash_name(b"/alice/video/1"));
+
+        // A ForwardingHint holding anything but Names is malformed
+        let mut bad = Interest::builder().name("/a").build().encode();
+        tlv::write_element(&mut bad, TlvType::ForwardingHint as u64, &[0x08, 0x01, b'x']);
+        assert!(Interest::decode(&bad).is_none());
+    }
+
+    #[test]
+    fn test_interest_without_name_is_rejected() {
+        let legacy = serialize_interest("/test", 7);
+        assert!(parse_interest_packet(&legacy).is_some());
+        assert!(Interest::decode(&legacy).is_none());
+    }
+
+    #[test]
+    fn test_data_builder_signs_and_roundtrips() {
+        let data = Data::builder()
+            .name("/test/data")
+            .content(&b"Hello, NDN!"[..])
+            .freshness_period_ms(1000)
+            .sign_with(|signed| alloc::vec![hash_name(signed) as u8])
+            .build();
+        assert_eq!(data.signature_value, alloc::vec![hash_name(&data.signed_portion()) as u8]);
+
+        let encoded = data.encode();
+        let header = parse_data_packet(&encoded).unwrap();
+        assert_eq!(header.name_hash, hash_name(b"/test/data"));
+        assert_eq!(header.content_size as usize, data.content.len());
+
+        assert_eq!(Data::decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_key_locator_is_signed_and_roundtrips() {
+        let unsigned = Data::builder().name("/test/data").build();
+        let data = Data::builder()
+            .name("/test/data")
+            .signature_info(crate::SIGNATURE_HMAC_WITH_SHA256, Some(Name::from("/test/KEY/1")))
+            .sign_with(|signed| signed.to_vec())
+            .build();
+        assert_eq!(data.key_locator(), Some(&Name::from("/test/KEY/1")));
+        assert!(data.signed_portion().starts_with(&unsigned.signed_portion()));
+        assert_eq!(data.signature_value, data.signed_portion());
+        assert_eq!(Data::decode(&data.encode()).unwrap(), data);
+
+        // SignatureInfo without a SignatureType
+        let mut bad = unsigned.encode();
+        tlv::write_element(&mut bad, TlvType::SignatureInfo as u64, &[]);
+        assert_eq!(Data::decode(&bad), None);
+    }
+
+    #[test]
+    fn test_truncated_data_is_rejected() {
+        let encoded = Data::builder().name("/a/b").content(&b"payload"[..]).build().encode();
+        for len in 0..encoded.len() {
+            assert!(Data::decode(&encoded[..len]).is_none(), "decoded truncation at {len}");
+        }
+    }
+}
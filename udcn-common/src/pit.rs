@@ -0,0 +1,162 @@
+//! The open-addressing PIT, for evaluating against the default hash map layout.
+//!
+//! By default the PIT is a BPF hash map, and what it does as it fills up is up to the kernel. The
+//! open-addressing layout instead stores entries in a `PIT_SLOTS`-slot array, the
+//! `PIT_ENTRIES` map, and keeps each slot's fingerprint in a parallel array, `PIT_FINGERPRINTS`.
+//! Each name hash has a home slot and can only be stored in the `PIT_PROBE_LIMIT` slots starting
+//! there. A lookup therefore reads at most that many fingerprints, and reads an entry only when
+//! its fingerprint matches. An insert that finds none of those slots free overflows, even while
+//! the rest of the table is empty. A removed entry leaves a tombstone, so a probe continues past
+//! it to entries stored further on, and later inserts reuse it.
+//!
+//! The probing takes closures that read a slot's fingerprint and name hash. That lets the
+//! datapath, the daemon and the benchmark share it over their own storage.
+
+use crate::PIT_CAPACITY;
+
+/// Slots in the open-addressing PIT: twice the hash map's capacity, so it is at most half full
+/// when holding as many entries.
+pub const PIT_SLOTS: u32 = 2 * PIT_CAPACITY;
+/// Slots from its home slot an entry may be stored in, and so the most a lookup reads.
+pub const PIT_PROBE_LIMIT: u32 = 8;
+
+/// The fingerprint of a slot that never held an entry, which ends a probe.
+pub const EMPTY: u32 = 0;
+/// The fingerprint of a slot whose entry was removed.
+pub const TOMBSTONE: u32 = 1;
+
+const _: () = assert!(PIT_SLOTS.is_power_of_two() && PIT_PROBE_LIMIT <= PIT_SLOTS);
+
+/// Where the datapath keeps the PIT, chosen at load time with `run --pit-layout`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PitLayout {
+    /// The `PIT` hash map.
+    #[default]
+    HashMap = 0,
+    /// The `PIT_FINGERPRINTS` and `PIT_ENTRIES` arrays, probed as this module describes.
+    OpenAddressing = 1,
+}
+
+impl PitLayout {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::HashMap),
+            1 => Some(Self::OpenAddressing),
+            _ => None,
+        }
+    }
+}
+
+/// What probing for a name hash found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Probe {
+    /// The slot holding its entry.
+    Found(u32),
+    /// The first free slot it may be stored in; it has no entry.
+    Vacant(u32),
+    /// It has no entry, and none of its slots is free.
+    Full,
+}
+
+/// The fingerprint a slot holding `name_hash` carries: the hash itself, moved off the two
+/// markers. Hashes that share one are told apart by the entry's own name hash.
+#[inline(always)]
+pub fn fingerprint(name_hash: u32) -> u32 {
+    if name_hash <= TOMBSTONE {
+        name_hash + 2
+    } else {
+        name_hash
+    }
+}
+
+/// The first slot `name_hash` may be stored in. The hash is mixed first, as names differing only
+/// in their last bytes differ little in the low bits of their FNV hash.
+#[inline(always)]
+pub fn home(name_hash: u32) -> u32 {
+    name_hash.wrapping_mul(0x9e37_79b9) >> (32 - PIT_SLOTS.trailing_zeros())
+}
+
+/// Probe the slots of `name_hash`, reading their fingerprints with `fingerprint_at` and, where
+/// the fingerprint matches, the name hash of their entry with `name_hash_at`.
+#[inline(always)]
+pub fn probe(
+    name_hash: u32,
+    mut fingerprint_at: impl FnMut(u32) -> u32,
+    mut name_hash_at: impl FnMut(u32) -> u32,
+) -> Probe {
+    let wanted = fingerprint(name_hash);
+    let home = home(name_hash);
+    let mut vacant = None;
+    for offset in 0..PIT_PROBE_LIMIT {
+        let slot = (home + offset) & (PIT_SLOTS - 1);
+        match fingerprint_at(slot) {
+            EMPTY => return Probe::Vacant(vacant.unwrap_or(slot)),
+            TOMBSTONE => vacant = vacant.or(Some(slot)),
+            found if found == wanted && name_hash_at(slot) == name_hash => {
+                return Probe::Found(slot)
+            }
+            _ => {}
+        }
+    }
+    vacant.map_or(Probe::Full, Probe::Vacant)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    /// Fingerprints and name hashes by slot.
+    struct Table(Vec<u32>, Vec<u32>);
+
+    impl Table {
+        fn probe(&self, name_hash: u32) -> Probe {
+            probe(
+                name_hash,
+                |slot| self.0[slot as usize],
+                |slot| self.1[slot as usize],
+            )
+        }
+
+        fn insert(&mut self, name_hash: u32) -> Probe {
+            let probe = self.probe(name_hash);
+            if let Probe::Vacant(slot) = probe {
+                self.0[slot as usize] = fingerprint(name_hash);
+                self.1[slot as usize] = name_hash;
+            }
+            probe
+        }
+    }
+
+    /// `count` name hashes sharing the home slot of `name_hash`.
+    fn colliding(name_hash: u32, count: usize) -> Vec<u32> {
+        (name_hash..)
+            .filter(|other| home(*other) == home(name_hash))
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn test_probing_overflows_past_the_limit_and_reuses_tombstones() {
+        let mut table = Table(vec![EMPTY; PIT_SLOTS as usize], vec![0; PIT_SLOTS as usize]);
+        let hashes = colliding(0, PIT_PROBE_LIMIT as usize + 1);
+        let home = home(hashes[0]);
+        for (offset, hash) in hashes[..PIT_PROBE_LIMIT as usize].iter().enumerate() {
+            let slot = (home + offset as u32) & (PIT_SLOTS - 1);
+            assert_eq!(table.insert(*hash), Probe::Vacant(slot));
+        }
+        let last = hashes[PIT_PROBE_LIMIT as usize];
+        assert_eq!(table.insert(last), Probe::Full);
+
+        assert_eq!(table.probe(hashes[0]), Probe::Found(home));
+        let second = (home + 1) & (PIT_SLOTS - 1);
+        table.0[home as usize] = TOMBSTONE;
+        assert_eq!(table.probe(hashes[1]), Probe::Found(second));
+        assert_eq!(table.insert(last), Probe::Vacant(home));
+        assert_eq!(table.probe(last), Probe::Found(home));
+        assert_eq!(PitLayout::from_u32(1), Some(PitLayout::OpenAddressing));
+        assert_eq!(PitLayout::from_u32(2), None);
+    }
+}
@@ -0,0 +1,253 @@
+//! NDN TLV primitives: VAR-NUMBER types and lengths, elements and NonNegativeInteger values.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Decode the VAR-NUMBER at the start of `buf`, returning it with the number of bytes it used.
+pub fn read_var_number(buf: &[u8]) -> Option<(u64, usize)> {
+    match *buf.first()? {
+        253 => Some((u16::from_be_bytes(buf.get(1..3)?.try_into().ok()?) as u64, 3)),
+        254 => Some((u32::from_be_bytes(buf.get(1..5)?.try_into().ok()?) as u64, 5)),
+        255 => Some((u64::from_be_bytes(buf.get(1..9)?.try_into().ok()?), 9)),
+        first => Some((first as u64, 1)),
+    }
+}
+
+/// Number of bytes `value` takes as a VAR-NUMBER.
+pub fn var_number_len(value: u64) -> usize {
+    match value {
+        0..=252 => 1,
+        253..=0xFFFF => 3,
+        0x1_0000..=0xFFFF_FFFF => 5,
+        _ => 9,
+    }
+}
+
+/// Number of bytes an element of type `typ` with a `value_len`-byte value takes.
+pub fn element_len(typ: u64, value_len: usize) -> usize {
+    var_number_len(typ) + var_number_len(value_len as u64) + value_len
+}
+
+/// Number of bytes a NonNegativeInteger element holding `value` takes.
+pub fn nonneg_int_element_len(typ: u64, value: u64) -> usize {
+    element_len(typ, nonneg_int_len(value))
+}
+
+/// A TLV element borrowed from a packet buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Element<'a> {
+    pub typ: u64,
+    pub value: &'a [u8],
+}
+
+/// Split the TLV element at the start of `buf` from the bytes following it.
+pub fn read_element(buf: &[u8]) -> Option<(Element<'_>, &[u8])> {
+    let (typ, type_len) = read_var_number(buf)?;
+    let (length, length_len) = read_var_number(buf.get(type_len..)?)?;
+    let start = type_len + length_len;
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+    let value = buf.get(start..end)?;
+    Some((Element { typ, value }, &buf[end..]))
+}
+
+/// Decode a NonNegativeInteger element value (1, 2, 4 or 8 big-endian bytes).
+pub fn read_nonneg_int(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        1 => Some(value[0] as u64),
+        2 => Some(u16::from_be_bytes(value.try_into().ok()?) as u64),
+        4 => Some(u32::from_be_bytes(value.try_into().ok()?) as u64),
+        8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub fn write_var_number(out: &mut Vec<u8>, value: u64) {
+    match var_number_len(value) {
+        1 => out.push(value as u8),
+        3 => {
+            out.push(253);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        5 => {
+            out.push(254);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(255);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub fn write_element(out: &mut Vec<u8>, typ: u64, value: &[u8]) {
+    write_var_number(out, typ);
+    write_var_number(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+/// Number of bytes the shortest NonNegativeInteger encoding of `value` takes.
+pub fn nonneg_int_len(value: u64) -> usize {
+    if value <= u8::MAX as u64 {
+        1
+    } else if value <= u16::MAX as u64 {
+        2
+    } else if value <= u32::MAX as u64 {
+        4
+    } else {
+        8
+    }
+}
+
+/// Write `value` as a NonNegativeInteger using the shortest allowed encoding.
+#[cfg(feature = "alloc")]
+pub fn write_nonneg_int(out: &mut Vec<u8>, value: u64) {
+    let len = nonneg_int_len(value);
+    out.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+/// Write a NonNegativeInteger element using the shortest allowed encoding.
+#[cfg(feature = "alloc")]
+pub fn write_nonneg_int_element(out: &mut Vec<u8>, typ: u64, value: u64) {
+    write_var_number(out, typ);
+    write_var_number(out, nonneg_int_len(value) as u64);
+    write_nonneg_int(out, value);
+}
+
+/// Writes into a fixed buffer rather than a `Vec`, for encoding without an allocator. A write
+/// that does not fit fails and leaves the buffer as it was.
+pub struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes that still fit.
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len.checked_add(bytes.len())?;
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    pub fn write_var_number(&mut self, value: u64) -> Option<()> {
+        match var_number_len(value) {
+            1 => self.write(&[value as u8]),
+            3 => self.write_prefixed(253, &(value as u16).to_be_bytes()),
+            5 => self.write_prefixed(254, &(value as u32).to_be_bytes()),
+            _ => self.write_prefixed(255, &value.to_be_bytes()),
+        }
+    }
+
+    fn write_prefixed(&mut self, first: u8, rest: &[u8]) -> Option<()> {
+        if self.buf.len() < self.len + 1 + rest.len() {
+            return None;
+        }
+        self.write(&[first])?;
+        self.write(rest)
+    }
+
+    /// Write the type and length of an element whose `value_len`-byte value is written next.
+    pub fn write_element_header(&mut self, typ: u64, value_len: usize) -> Option<()> {
+        if self.buf.len() < self.len + var_number_len(typ) + var_number_len(value_len as u64) {
+            return None;
+        }
+        self.write_var_number(typ)?;
+        self.write_var_number(value_len as u64)
+    }
+
+    pub fn write_element(&mut self, typ: u64, value: &[u8]) -> Option<()> {
+        if self.buf.len() < self.len + element_len(typ, value.len()) {
+            return None;
+        }
+        self.write_element_header(typ, value.len())?;
+        self.write(value)
+    }
+
+    pub fn write_nonneg_int_element(&mut self, typ: u64, value: u64) -> Option<()> {
+        let len = nonneg_int_len(value);
+        if self.buf.len() < self.len + element_len(typ, len) {
+            return None;
+        }
+        self.write_element_header(typ, len)?;
+        self.write(&value.to_be_bytes()[8 - len..])
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Values on both sides of every VAR-NUMBER width change.
+    fn boundary_number() -> impl Strategy<Value = u64> {
+        prop_oneof![
+            0u64..=0x1FF,
+            0xFF00u64..=0x1_00FF,
+            0xFFFF_FF00u64..=0x1_0000_00FF,
+            any::<u64>(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_var_number_roundtrip(value in boundary_number()) {
+            let mut out = Vec::new();
+            write_var_number(&mut out, value);
+            prop_assert_eq!(out.len(), var_number_len(value));
+            prop_assert_eq!(read_var_number(&out), Some((value, out.len())));
+            prop_assert_eq!(read_var_number(&out[..out.len() - 1]), None);
+        }
+
+        #[test]
+        fn test_nonneg_int_roundtrip(value in boundary_number()) {
+            let mut out = Vec::new();
+            write_nonneg_int(&mut out, value);
+            prop_assert_eq!(out.len(), nonneg_int_len(value));
+            prop_assert_eq!(read_nonneg_int(&out), Some(value));
+        }
+
+        #[test]
+        fn test_element_roundtrip(
+            typ in boundary_number(),
+            value in prop::collection::vec(any::<u8>(), 0..300),
+            trailer in prop::collection::vec(any::<u8>(), 0..4),
+        ) {
+            let mut out = Vec::new();
+            write_element(&mut out, typ, &value);
+            let encoded_len = out.len();
+            out.extend_from_slice(&trailer);
+
+            let (element, rest) = read_element(&out).unwrap();
+            prop_assert_eq!(element, Element { typ, value: &value });
+            prop_assert_eq!(rest, &trailer[..]);
+            prop_assert_eq!(read_element(&out[..encoded_len - 1]), None);
+        }
+
+        #[test]
+        fn test_read_element_stays_in_bounds(buf in prop::collection::vec(any::<u8>(), 0..64)) {
+            if let Some((element, rest)) = read_element(&buf) {
+                prop_assert!(element.value.len() + rest.len() < buf.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! The part of the datapath a SmartNIC can run, for `run --xdp-mode offload`: it tells NDN
+//! packets apart by type and counts them on the NIC, then passes every packet on to the full
+//! program in the driver.
+//!
+//! Offloaded programs only get plain array and hash maps and a handful of helpers, so nothing
+//! here touches the PIT, content store, ring buffers or tail calls the driver program relies on.
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aya_ebpf::{
+    bindings::xdp_action,
+    macros::{map, xdp},
+    maps::Array,
+    programs::XdpContext,
+};
+use udcn_common::{OffloadClass, TlvType, OFFLOAD_CLASSES};
+
+/// Packets seen on the NIC, by `OffloadClass`.
+#[map]
+static OFFLOAD_STATS: Array<u64> = Array::with_max_entries(OFFLOAD_CLASSES, 0);
+
+#[xdp]
+pub fn udcn_classify(ctx: XdpContext) -> u32 {
+    let class = classify(&ctx);
+    if let Some(count) = OFFLOAD_STATS.get_ptr_mut(class as u32) {
+        // Several NIC cores count at once
+        let count = unsafe { AtomicU64::from_ptr(count) };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    xdp_action::XDP_PASS
+}
+
+fn classify(ctx: &XdpContext) -> OffloadClass {
+    let data = ctx.data();
+    let data_end = ctx.data_end();
+    if data + 34 > data_end {
+        return OffloadClass::Other;
+    }
+    let eth_type = unsafe { u16::from_be(((data + 12) as *const u16).read_unaligned()) };
+    let ip_protocol = unsafe { *((data + 14 + 9) as *const u8) };
+    if eth_type != 0x0800 || ip_protocol != 17 {
+        return OffloadClass::Other;
+    }
+
+    let ip_header_len = ((unsafe { *((data + 14) as *const u8) } & 0x0f) * 4) as usize;
+    let udp_header_start = data + 14 + ip_header_len;
+    // The UDP header and the first byte of the NDN packet
+    if udp_header_start + 9 > data_end {
+        return OffloadClass::Other;
+    }
+    let (src_port, dst_port) = unsafe {
+        (
+            u16::from_be((udp_header_start as *const u16).read_unaligned()),
+            u16::from_be(((udp_header_start + 2) as *const u16).read_unaligned()),
+        )
+    };
+    if src_port != 6363 && dst_port != 6363 {
+        return OffloadClass::Other;
+    }
+
+    match unsafe { *((udp_header_start + 8) as *const u8) } {
+        t if t == TlvType::Interest as u8 => OffloadClass::Interest,
+        t if t == TlvType::Data as u8 => OffloadClass::Data,
+        t if t == TlvType::Nack as u8 => OffloadClass::Nack,
+        t if t == TlvType::LpPacket as u8 => OffloadClass::LpPacket,
+        _ => OffloadClass::Other,
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
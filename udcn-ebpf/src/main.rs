@@ -1,40 +1,230 @@
 #![no_std]
 #![no_main]
 
+use core::{
+    mem::offset_of,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use aya_ebpf::{
     bindings::xdp_action, 
+    helpers::{bpf_get_prandom_u32, bpf_ktime_get_ns},
     macros::{xdp, map},
-    maps::{HashMap, LruHashMap, Array},
+    maps::{HashMap, LruHashMap, Array, PerfEventArray, ProgramArray, RingBuf},
     programs::XdpContext,
 };
-use udcn_common::{PitEntry, CacheEntry, PacketStats};
+use udcn_common::{
+    pit::{self, PitLayout},
+    cs_bloom_bits, udp_face_id, CacheEntry, CsAdmission, DataPacket, DatapathConfig,
+    DatapathEvent, DatapathLogLevel, DropReason, EventOutcome, FaultConfig, InterestPacket, LapsedRecord, MissCounter, NackPacket, NackReason, NegativeCacheEntry, NonceKey, NonceRecord,
+    PacketSample, PacketStats, PitEntry, PitRecord, SampleConfig, TlvType, sample_type_bit, TraceHop, cs_miss_slots, CS_BLOOM_WORDS, CS_CAPACITY, CS_MISS_FILTER_SLOTS,
+    CS_PINNED_CAPACITY, DATA_CACHE_CAPACITY, FNV_OFFSET_BASIS, FNV_PRIME, MAX_CS_PARTITIONS,
+    MAX_CHAINED_PROGRAMS, MAX_CS_PARTITION_COMPONENT_LEN, PIT_CAPACITY, DATAPATH_LOG_TARGET,
+    DEFAULT_INTEREST_LIFETIME_MS, CONTENT_TYPE_NO_CACHE, MAX_NO_CACHE_COMPONENT_LEN,
+    MAX_NO_CACHE_PREFIXES, MAX_NO_CACHE_PREFIX_COMPONENTS, MAX_SAMPLE_LEN, MAX_TENANTS, MAX_CHECKSUM_LEN, MAX_CS_PINS,
+    AclAction, AclKey, CachedPayload, MAX_ACL_RULES, Reputation, REPUTATION_CAPACITY,
+    DECAPSULATE_GTPU, DECAPSULATE_VXLAN, GTPU_UDP_PORT, VXLAN_UDP_PORT,
+    TenantQuota, TenantUsage, TimeBase, CONGESTION_MARK, ROUTES_CAPACITY,
+};
+
+/// Log through aya-log when the daemon's `--datapath-log-level` lets `$level` through, so
+/// records cost one config lookup when they are off.
+macro_rules! datapath_log {
+    ($ctx:expr, $level:ident, $($arg:tt)+) => {
+        if log_enabled(DatapathLogLevel::$level) {
+            aya_log_ebpf::log!(
+                $ctx,
+                target: DATAPATH_LOG_TARGET,
+                aya_log_ebpf::Level::$level,
+                $($arg)+
+            );
+        }
+    };
+}
+
+#[map]
+static PIT: HashMap<u32, PitEntry> = HashMap::with_max_entries(PIT_CAPACITY, 0);
+
+/// The open-addressing PIT, used instead of `PIT` when `DatapathConfig::pit_layout` selects it:
+/// each slot's fingerprint, and the entry in it (see `udcn_common::pit`). The daemon sizes both
+/// to `PIT_SLOTS` at load time when it does.
+#[map]
+static PIT_FINGERPRINTS: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+static PIT_ENTRIES: Array<PitEntry> = Array::with_max_entries(1, 0);
+
+/// Downstream PIT records the datapath found lapsed, for the daemon to Nack.
+#[map]
+static PIT_LAPSED: RingBuf = RingBuf::with_byte_size(16 * 1024, 0);
+
+/// The shared content store partition. The daemon shrinks it at load time by whatever it
+/// reserves for the `CS_PARTITION_n` maps.
+#[map]
+static CONTENT_STORE: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(CS_CAPACITY, 0);
+
+/// The protected segment of the shared partition, when `DatapathConfig::cs_segmented` is set:
+/// Data enters `CONTENT_STORE` on probation and moves here once it answers an Interest, so a
+/// scan of names requested once only evicts other such names. Sized by the daemon at load time.
+#[map]
+static CS_PROTECTED: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(1, 0);
+
+/// Data of pinned names and prefixes, from whichever partition it would go to. A hash map rather
+/// than an LRU one, so nothing but the daemon removes an entry; once it is full, pinned Data goes
+/// to its partition like any other.
+#[map]
+static CS_PINNED: HashMap<u32, CacheEntry> = HashMap::with_max_entries(CS_PINNED_CAPACITY, 0);
+
+/// Names pinned in the content store, by name hash, and pinned prefixes, keyed like
+/// `NO_CACHE_PREFIXES`.
+#[map]
+static CS_PIN_NAMES: HashMap<u32, u8> = HashMap::with_max_entries(MAX_CS_PINS, 0);
+
+#[map]
+static CS_PIN_PREFIXES: HashMap<u32, u8> = HashMap::with_max_entries(MAX_CS_PINS, 0);
+
+/// Partition number (1-based) by `cs_partition_key` of a name's first component.
+#[map]
+static CS_PARTITION_PREFIXES: HashMap<u32, u32> =
+    HashMap::with_max_entries(MAX_CS_PARTITIONS as u32, 0);
+
+/// Prefixes whose Data never enters the content store, by `no_cache_key`.
+#[map]
+static NO_CACHE_PREFIXES: HashMap<u32, u8> = HashMap::with_max_entries(MAX_NO_CACHE_PREFIXES, 0);
+
+/// Prefixes something behind the network stack serves, keyed like `NO_CACHE_PREFIXES`. Only
+/// consulted while `DatapathConfig::nack_unroutable` is set.
+#[map]
+static ROUTES: HashMap<u32, u8> = HashMap::with_max_entries(ROUTES_CAPACITY, 0);
+
+/// `AclAction`s by the face and prefix they apply to, the prefix keyed like
+/// `NO_CACHE_PREFIXES`. Only consulted while `DatapathConfig::acl_rules` is set.
+#[map]
+static ACL: HashMap<AclKey, u8> = HashMap::with_max_entries(MAX_ACL_RULES, 0);
+
+/// How each source address has misbehaved, by the address read big-endian. Only consulted while
+/// `DatapathConfig::greylist_offences` is set.
+#[map]
+static REPUTATION: LruHashMap<u32, Reputation> =
+    LruHashMap::with_max_entries(REPUTATION_CAPACITY, 0);
+
+/// Tenant numbers by prefix, keyed like `NO_CACHE_PREFIXES`. Only consulted while
+/// `DatapathConfig::tenants` is set.
+#[map]
+static TENANT_PREFIXES: HashMap<u32, u32> = HashMap::with_max_entries(MAX_TENANTS, 0);
+
+/// Quotas and usage by tenant number; index 0, for names under no tenant, is unused.
+#[map]
+static TENANT_QUOTAS: Array<TenantQuota> = Array::with_max_entries(MAX_TENANTS + 1, 0);
+
+#[map]
+static TENANT_USAGE: Array<TenantUsage> = Array::with_max_entries(MAX_TENANTS + 1, 0);
+
+// Reserved partitions, each its own LRU so one prefix can only evict its own content. Sized by
+// the daemon at load time.
+#[map]
+static CS_PARTITION_1: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(1, 0);
+
+#[map]
+static CS_PARTITION_2: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(1, 0);
+
+#[map]
+static CS_PARTITION_3: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(1, 0);
+
+#[map]
+static CS_PARTITION_4: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(1, 0);
 
+/// Bloom filter over the name hashes in every content store partition, checked before the LRU
+/// lookups. Two halves: inserts set bits in both, lookups test the one `CS_BLOOM_ACTIVE` selects,
+/// and the daemon periodically rebuilds the other from the partitions and switches to it, which
+/// clears out bits of evicted entries.
 #[map]
-static PIT: HashMap<u32, PitEntry> = HashMap::with_max_entries(1024, 0);
+static CS_BLOOM: Array<u64> = Array::with_max_entries(2 * CS_BLOOM_WORDS, 0);
 
 #[map]
-static CONTENT_STORE: LruHashMap<u32, CacheEntry> = LruHashMap::with_max_entries(512, 0);
+static CS_BLOOM_ACTIVE: Array<u32> = Array::with_max_entries(1, 0);
 
 #[map]
 static STATS: Array<PacketStats> = Array::with_max_entries(1, 0);
 
+/// Written by the daemon for the programs reading the pinned maps; the datapath only ever reads
+/// `bpf_ktime_get_ns`.
 #[map]
-static DATA_CACHE: HashMap<u32, [u8; 256]> = HashMap::with_max_entries(512, 0);
+static TIME_BASE: Array<TimeBase> = Array::with_max_entries(1, 0);
+
+/// Payloads the daemon keeps for cached names; see `CachedPayload` for how they are kept in step
+/// with the content store.
+#[map]
+static DATA_CACHE: HashMap<u32, CachedPayload> = HashMap::with_max_entries(DATA_CACHE_CAPACITY, 0);
+
+#[map]
+static CONFIG: Array<DatapathConfig> = Array::with_max_entries(1, 0);
+
+#[map]
+static NEGATIVE_CACHE: LruHashMap<u32, NegativeCacheEntry> = LruHashMap::with_max_entries(256, 0);
+
+/// Counting Bloom filter of recent content store misses, for `CsAdmission::Popularity`.
+#[map]
+static CS_MISSES: Array<MissCounter> = Array::with_max_entries(CS_MISS_FILTER_SLOTS, 0);
+
+/// Forwarding decisions for `udcn events`, published only while `EVENTS_ENABLED` is set.
+#[map]
+static EVENTS: RingBuf = RingBuf::with_byte_size(256 * 1024, 0);
+
+/// Traced packets, for the daemon to send hop reports for.
+#[map]
+static TRACE_HOPS: RingBuf = RingBuf::with_byte_size(64 * 1024, 0);
+
+#[map]
+static EVENTS_ENABLED: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Sampled packets for `udcn capture`, taken while `SAMPLE_CONFIG` has a rate set.
+#[map]
+static SAMPLES: PerfEventArray<PacketSample> = PerfEventArray::new(0);
+
+#[map]
+static SAMPLE_CONFIG: Array<SampleConfig> = Array::with_max_entries(1, 0);
+
+#[map]
+static NONCE_HISTORY: LruHashMap<NonceKey, NonceRecord> = LruHashMap::with_max_entries(2048, 0);
+
+/// Fault injection for testing, set by daemons built with the `faults` feature. Only looked at
+/// while `DatapathConfig::fault_injection` is set.
+#[map]
+static FAULTS: Array<FaultConfig> = Array::with_max_entries(1, 0);
+
+/// Prefixes whose Interests fault injection drops, keyed like `NO_CACHE_PREFIXES`.
+#[map]
+static FAULT_BLACKHOLES: HashMap<u32, u8> = HashMap::with_max_entries(MAX_NO_CACHE_PREFIXES, 0);
+
+/// Data fault injection holds back, for the daemon to send on after the delay.
+#[map]
+static HELD_DATA: PerfEventArray<PacketSample> = PerfEventArray::new(0);
+
+/// Programs µDCN was chained in front of (`run --chain`), by slot.
+#[map]
+static NEXT_PROGRAM: ProgramArray = ProgramArray::with_max_entries(MAX_CHAINED_PROGRAMS, 0);
+
+/// `NEXT_PROGRAM` slot by the ifindex of the interface the program was attached to.
+#[map]
+static CHAIN_SLOTS: HashMap<u32, u32> = HashMap::with_max_entries(MAX_CHAINED_PROGRAMS, 0);
 
 #[xdp]
 pub fn udcn(ctx: XdpContext) -> u32 {
-    match try_udcn(ctx) {
+    match try_udcn(&ctx) {
+        Ok(xdp_action::XDP_PASS) => pass_to_next_program(&ctx),
         Ok(ret) => ret,
         Err(_) => xdp_action::XDP_ABORTED,
     }
 }
 
-fn try_udcn(ctx: XdpContext) -> Result<u32, u32> {
-    let data = ctx.data();
+fn try_udcn(ctx: &XdpContext) -> Result<u32, u32> {
     let data_end = ctx.data_end();
-    
-    // Count all packets that reach XDP (use drops as a general packet counter)
-    update_stats(|stats| stats.drops += 1);
+    // The rest parses the frame a tunnel carries as if it were the packet itself
+    let (data, encapsulated) = match encapsulated_frame(ctx.data(), data_end) {
+        Some(inner) => (inner, true),
+        None => (ctx.data(), false),
+    };
     
     // Ensure we have at least Ethernet (14) + minimal IP (20) bytes
     if data + 34 > data_end {
@@ -42,14 +232,15 @@ fn try_udcn(ctx: XdpContext) -> Result<u32, u32> {
     }
     
 
-    // Check if this is an IPv4 packet (ethertype = 0x0800)
+    // Check if this is an IPv4 packet (ethertype = 0x0800); encapsulated_frame checked it for
+    // frames from a tunnel, some of which have no Ethernet header
     let eth_type = unsafe {
         let ptr = (data + 12) as *const u16;
-        u16::from_be(*ptr)
+        u16::from_be(ptr.read_unaligned())
     };
     
     
-    if eth_type != 0x0800 {
+    if !encapsulated && eth_type != 0x0800 {
         return Ok(xdp_action::XDP_PASS);
     }
 
@@ -74,13 +265,13 @@ fn try_udcn(ctx: XdpContext) -> Result<u32, u32> {
     let udp_header_start = data + 14 + ip_header_len;
     let udp_dst_port = unsafe {
         let ptr = (udp_header_start + 2) as *const u16;
-        u16::from_be(*ptr)
+        u16::from_be(ptr.read_unaligned())
     };
     
     // Also check source port for return traffic
     let udp_src_port = unsafe {
         let ptr = (udp_header_start) as *const u16;
-        u16::from_be(*ptr)
+        u16::from_be(ptr.read_unaligned())
     };
     
     // Count UDP packets that reach port check
@@ -101,130 +292,1709 @@ fn try_udcn(ctx: XdpContext) -> Result<u32, u32> {
         return Ok(xdp_action::XDP_PASS);
     }
 
+    sample_packet(ctx, udp_payload_start, data_end);
+
+    // A packet an LpPacket carries whole, traced or congestion-marked, is handled as if it came
+    // unwrapped; the LpPacket's fields stay in front of it, for whoever it goes on to
+    let lp_packet = unsafe { *(udp_payload_start as *const u8) } == TlvType::LpPacket as u8;
+    let udp_payload_start = if lp_packet {
+        match lp_fragment(data, udp_header_start, udp_payload_start, data_end) {
+            Some(inner) => inner,
+            None => return Ok(xdp_action::XDP_PASS),
+        }
+    } else {
+        udp_payload_start
+    };
+    if udp_payload_start + 2 > data_end {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
     // Get NDN packet type from UDP payload
     let packet_type = unsafe { *(udp_payload_start as *const u8) };
-    
+
     // Quick check: is this potentially an NDN packet?
-    if packet_type != 0x05 && packet_type != 0x06 {
+    if packet_type != TlvType::Interest as u8
+        && packet_type != TlvType::Data as u8
+        && packet_type != TlvType::Nack as u8
+    {
         return Ok(xdp_action::XDP_PASS);
     }
 
+    // The source address and port identify the face the packet arrived on
+    let (src_addr, src_port) = unsafe {
+        (
+            ((data + 14 + 12) as *const [u8; 4]).read_unaligned(),
+            (udp_header_start as *const [u8; 2]).read_unaligned(),
+        )
+    };
+    let face_id = udp_face_id(src_addr, src_port);
+    let tlv_type = if packet_type == TlvType::Interest as u8 {
+        TlvType::Interest
+    } else if packet_type == TlvType::Data as u8 {
+        TlvType::Data
+    } else {
+        TlvType::Nack
+    };
+
+    if greylisted(src_addr) {
+        return drop_packet(ctx, tlv_type, 0, face_id, DropReason::Greylisted);
+    }
+
+    // A corrupted datagram would otherwise be parsed, and its garbage hashes enter the PIT and CS
+    let verify = CONFIG.get(0).is_some_and(|config| config.verify_udp_checksums != 0);
+    if verify && !udp_checksum_ok(data, udp_header_start, data_end) {
+        record_offence(src_addr);
+        return drop_packet(ctx, tlv_type, 0, face_id, DropReason::BadChecksum);
+    }
+
     // Count NDN packet types
     update_stats(|stats| {
-        if packet_type == 0x05 {
+        if packet_type == TlvType::Interest as u8 {
             stats.interest_received += 1;
-        } else if packet_type == 0x06 {
+        } else if packet_type == TlvType::Data as u8 {
             stats.data_received += 1;
         }
     });
 
-    // For Interest packets, we need at least 12 bytes (header + name_hash + nonce)
-    if packet_type == 0x05 {
-        if udp_payload_start + 12 > data_end {
-            return Ok(xdp_action::XDP_PASS);
+    if packet_type == TlvType::Interest as u8 {
+        if udp_payload_start + core::mem::size_of::<InterestPacket>() > data_end {
+            record_offence(src_addr);
+            return drop_packet(ctx, TlvType::Interest, 0, face_id, DropReason::Malformed);
         }
         
-        // Parse Interest packet manually with verified bounds
+        // Parse Interest packet manually with verified bounds; header fields are big-endian
         let name_hash = unsafe {
-            let ptr = (udp_payload_start + 2) as *const u32;
-            *ptr
+            let ptr = (udp_payload_start + offset_of!(InterestPacket, name_hash)) as *const u32;
+            u32::from_be(ptr.read_unaligned())
         };
         let nonce = unsafe {
-            let ptr = (udp_payload_start + 6) as *const u32;
-            *ptr
+            let ptr = (udp_payload_start + offset_of!(InterestPacket, nonce)) as *const u32;
+            u32::from_be(ptr.read_unaligned())
         };
         
-        let interest = udcn_common::InterestPacket::new(name_hash, nonce);
-        return handle_interest(interest);
+        let interest = InterestPacket::new(name_hash, nonce);
+        let name_start = udp_payload_start + core::mem::size_of::<InterestPacket>();
+        if acl_denies(face_id, name_start, data_end) {
+            let reason = DropReason::AclDropped;
+            return drop_packet(ctx, TlvType::Interest, name_hash, face_id, reason);
+        }
+        if inject_interest_drop(name_start, data_end) {
+            let reason = DropReason::Injected;
+            return drop_packet(ctx, TlvType::Interest, name_hash, face_id, reason);
+        }
+        let prefixes = InterestPrefixes {
+            partition: cs_partition(name_start, data_end),
+            routed: routed(name_start, data_end),
+            tenant: tenant_of(name_start, data_end),
+        };
+        let (lifetime_ms, must_be_fresh) = interest_selectors(name_start, data_end);
+        datapath_log!(
+            ctx,
+            Trace,
+            "Interest {:x} nonce {:x} from face {:x}, CS partition {}, tenant {}, lifetime {} ms, MustBeFresh {}",
+            name_hash,
+            nonce,
+            face_id,
+            prefixes.partition,
+            prefixes.tenant,
+            lifetime_ms,
+            must_be_fresh as u8
+        );
+        let downstream = PitRecord {
+            face_id,
+            nonce,
+            addr: src_addr,
+            local_addr: unsafe { ((data + 14 + 16) as *const [u8; 4]).read_unaligned() },
+            port: src_port,
+            reserved: [0; 6],
+            expires_at: unsafe { bpf_ktime_get_ns() } + lifetime_ms as u64 * 1_000_000,
+        };
+        let action = handle_interest(
+            ctx,
+            udp_header_start,
+            udp_payload_start,
+            interest,
+            prefixes,
+            must_be_fresh,
+            downstream,
+        )?;
+        // Bouncing an answer back in place would leave the tunnel's own headers pointing the
+        // wrong way, so the tunnel endpoint gets the Interest instead
+        if encapsulated && action == xdp_action::XDP_TX {
+            return Ok(xdp_action::XDP_PASS);
+        }
+        return Ok(action);
     }
     
-    // For Data packets, we need at least 10 bytes (header + name_hash + content_size + signature)
-    if packet_type == 0x06 {
-        if udp_payload_start + 10 > data_end {
-            return Ok(xdp_action::XDP_PASS);
+    if packet_type == TlvType::Data as u8 {
+        if udp_payload_start + core::mem::size_of::<DataPacket>() > data_end {
+            record_offence(src_addr);
+            return drop_packet(ctx, TlvType::Data, 0, face_id, DropReason::Malformed);
         }
         
-        // Parse Data packet manually with verified bounds
+        // Parse Data packet manually with verified bounds; header fields are big-endian
         let name_hash = unsafe {
-            let ptr = (udp_payload_start + 2) as *const u32;
-            *ptr
+            let ptr = (udp_payload_start + offset_of!(DataPacket, name_hash)) as *const u32;
+            u32::from_be(ptr.read_unaligned())
         };
         let content_size = unsafe {
-            let ptr = (udp_payload_start + 6) as *const u16;
-            *ptr
+            let ptr = (udp_payload_start + offset_of!(DataPacket, content_size)) as *const u16;
+            u16::from_be(ptr.read_unaligned())
         };
         let signature = unsafe {
-            let ptr = (udp_payload_start + 8) as *const u32;
-            *ptr
+            let ptr = (udp_payload_start + offset_of!(DataPacket, signature)) as *const u32;
+            u32::from_be(ptr.read_unaligned())
         };
         
-        let data_pkt = udcn_common::DataPacket::new(name_hash, content_size, signature);
+        let data_pkt = DataPacket::new(name_hash, content_size, signature);
+
+        // The Name follows the content; with content too large to bound for the verifier, neither
+        // the ACL nor the NoCache ContentType and prefixes can be checked, so such Data is dropped
+        // while ACL rules are defined and never cached
+        let bounded = (content_size as usize) <= MAX_PARTITIONED_CONTENT_SIZE;
+        let name_start = udp_payload_start
+            + core::mem::size_of::<DataPacket>()
+            + (content_size as usize & MAX_PARTITIONED_CONTENT_SIZE);
+        let denied = match bounded {
+            true => acl_denies(face_id, name_start, data_end),
+            false => acl_active(),
+        };
+        if denied {
+            let reason = DropReason::AclDropped;
+            return drop_packet(ctx, TlvType::Data, name_hash, face_id, reason);
+        }
+        let placement = if bounded {
+            let (freshness_ms, content_type) = meta_info(name_start, data_end);
+            CsPlacement {
+                partition: cs_partition(name_start, data_end),
+                freshness_ms,
+                cacheable: content_type != CONTENT_TYPE_NO_CACHE && !no_cache_prefix(name_start, data_end),
+                pinned: name_pinned(name_hash) || under_prefix(&CS_PIN_PREFIXES, name_start, data_end),
+            }
+        } else {
+            // Never cached, so it cannot end up in a partition its Interests do not look in
+            CsPlacement {
+                partition: 0,
+                freshness_ms: 0,
+                cacheable: false,
+                pinned: false,
+            }
+        };
+        datapath_log!(
+            ctx,
+            Trace,
+            "Data {:x} ({} content bytes) from face {:x}, CS partition {}, fresh for {} ms, cacheable {}, pinned {}",
+            name_hash,
+            content_size,
+            face_id,
+            placement.partition,
+            placement.freshness_ms,
+            placement.cacheable as u8,
+            placement.pinned as u8
+        );
         
+        inject_signature_corruption(udp_header_start, udp_payload_start, data_end);
+
         // Create a minimal payload slice for caching
-        let payload_len = (data_end - udp_payload_start) as usize;
+        let payload_len = data_end - udp_payload_start;
         let payload = unsafe {
             core::slice::from_raw_parts(udp_payload_start as *const u8, payload_len)
         };
         
-        return handle_data(data_pkt, payload);
+        return handle_data(ctx, data_pkt, face_id, placement, payload);
+    }
+
+    if packet_type == TlvType::Nack as u8 {
+        if udp_payload_start + core::mem::size_of::<NackPacket>() > data_end {
+            record_offence(src_addr);
+            return drop_packet(ctx, TlvType::Nack, 0, face_id, DropReason::Malformed);
+        }
+
+        let name_hash = unsafe {
+            let ptr = (udp_payload_start + offset_of!(NackPacket, name_hash)) as *const u32;
+            u32::from_be(ptr.read_unaligned())
+        };
+        let reason = unsafe { *((udp_payload_start + offset_of!(NackPacket, reason)) as *const u8) };
+        datapath_log!(
+            ctx,
+            Trace,
+            "Nack {:x} reason {} from face {:x}",
+            name_hash,
+            reason,
+            face_id
+        );
+
+        return handle_nack(ctx, name_hash, face_id, reason);
     }
 
     Ok(xdp_action::XDP_PASS)
 }
 
-fn handle_interest(interest: udcn_common::InterestPacket) -> Result<u32, u32> {
+/// Hand a packet µDCN let through to the program it displaced on this interface, if any, so
+/// firewalls and load balancers on the same NIC still see it.
+#[inline(always)]
+fn pass_to_next_program(ctx: &XdpContext) -> u32 {
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    if let Some(slot) = unsafe { CHAIN_SLOTS.get(&ifindex) } {
+        // Only returns if the slot is empty
+        let _ = unsafe { NEXT_PROGRAM.tail_call(ctx, *slot) };
+    }
+    xdp_action::XDP_PASS
+}
+
+/// Where Data goes in the content store, as its MetaInfo and name prefixes decide.
+#[derive(Clone, Copy)]
+struct CsPlacement {
+    /// The partition it is cached in, unless it is pinned.
+    partition: u32,
+    freshness_ms: u64,
+    cacheable: bool,
+    /// Whether it goes to `CS_PINNED`.
+    pinned: bool,
+}
+
+/// What an Interest's name prefixes decide for it.
+#[derive(Clone, Copy)]
+struct InterestPrefixes {
+    /// The content store partition it is looked up in.
+    partition: u32,
+    /// Whether it may go on to the network stack; see `routed`.
+    routed: bool,
+    /// The tenant it counts against, 0 for none.
+    tenant: u32,
+}
+
+#[inline(always)]
+fn handle_interest(
+    ctx: &XdpContext,
+    udp_header_start: usize,
+    packet_start: usize,
+    interest: InterestPacket,
+    prefixes: InterestPrefixes,
+    must_be_fresh: bool,
+    downstream: PitRecord,
+) -> Result<u32, u32> {
     let name_hash = interest.name_hash;
+    let face_id = downstream.face_id;
+    let partition = prefixes.partition;
+
+    if is_duplicate_interest(&interest, face_id) {
+        update_stats(|stats| stats.duplicate_interests += 1);
+        let reason = DropReason::DuplicateNonce;
+        return drop_packet(ctx, TlvType::Interest, name_hash, face_id, reason);
+    }
+
+    if !tenant_admits_interest(prefixes.tenant) {
+        datapath_log!(ctx, Debug, "Interest {:x} over tenant {}'s rate", name_hash, prefixes.tenant);
+        record_offence(downstream.addr);
+        return drop_packet(ctx, TlvType::Interest, name_hash, face_id, DropReason::RateLimited);
+    }
     
-    if let Some(_cache_entry) = unsafe { CONTENT_STORE.get(&name_hash) } {
-        update_stats(|stats| stats.cache_hits += 1);
+    // The Bloom filter rules out most misses without touching the LRU maps. Stale Data only
+    // answers Interests that do not ask for fresh Data; the rest go upstream for a fresh copy
+    let in_bloom = cs_bloom_contains(name_hash);
+    let segmented = partition == 0 && cs_segmented();
+    let (cached, segment) = if in_bloom {
+        cs_get(partition, segmented, &name_hash)
+    } else {
+        (None, CsSegment::Unsegmented)
+    };
+    let fresh = cached.map(|entry| entry.is_fresh(unsafe { bpf_ktime_get_ns() }));
+    if let Some(fresh) = fresh.filter(|fresh| *fresh || !must_be_fresh) {
+        let prefetched = cached.is_some_and(|entry| entry.prefetched != 0);
+        // A second reference earns the entry a place in the protected segment
+        if let (CsSegment::Probation, Some(entry)) = (segment, cached) {
+            cs_promote(&name_hash, *entry);
+        }
+        update_stats(|stats| {
+            stats.cache_hits += 1;
+            if fresh {
+                stats.fresh_hits += 1;
+            } else {
+                stats.stale_hits += 1;
+            }
+            if prefetched {
+                stats.prefetch_hits += 1;
+            }
+            match segment {
+                CsSegment::Probation => stats.cs_probation_hits += 1,
+                CsSegment::Protected => stats.cs_protected_hits += 1,
+                CsSegment::Unsegmented | CsSegment::Pinned => {}
+            }
+        });
+        datapath_log!(
+            ctx,
+            Debug,
+            "CS hit for {:x} in partition {} (fresh {})",
+            name_hash,
+            partition,
+            fresh as u8
+        );
         
-        if let Some(_cached_data) = unsafe { DATA_CACHE.get(&name_hash) } {
-            return Ok(xdp_action::XDP_TX);
+        if let (Some(payload), Some(entry)) = (unsafe { DATA_CACHE.get(&name_hash) }, cached) {
+            if payload.belongs_to(entry) {
+                datapath_log!(ctx, Info, "Interest {:x} answered from the CS", name_hash);
+                emit_event(ctx, TlvType::Interest, name_hash, face_id, EventOutcome::CacheHit, 0);
+                return Ok(xdp_action::XDP_TX);
+            }
+            // Written for a copy of the Data the CS no longer holds
+            datapath_log!(ctx, Debug, "Deleting an outdated payload for {:x}", name_hash);
+            let _ = DATA_CACHE.remove(&name_hash);
         }
+    } else if fresh.is_some() {
+        datapath_log!(ctx, Debug, "CS entry for {:x} is stale, Interest wants fresh Data", name_hash);
+    } else {
+        datapath_log!(
+            ctx,
+            Debug,
+            "CS miss for {:x} in partition {} (Bloom filter {})",
+            name_hash,
+            partition,
+            in_bloom as u8
+        );
+        record_cs_miss(name_hash);
     }
 
-    // Cache miss - will add to PIT
+    // Content known to be unreachable is answered with the cached Nack instead of
+    // occupying a PIT entry and going upstream again
+    if let Some(entry) = unsafe { NEGATIVE_CACHE.get(&name_hash) } {
+        if unsafe { bpf_ktime_get_ns() } < entry.expires_at {
+            let reason = entry.reason;
+            update_stats(|stats| stats.negative_cache_hits += 1);
+            datapath_log!(
+                ctx,
+                Info,
+                "Interest {:x} answered with cached Nack reason {}",
+                name_hash,
+                reason
+            );
+            let outcome = EventOutcome::NegativeCacheHit;
+            emit_event(ctx, TlvType::Interest, name_hash, face_id, outcome, reason);
+            return reply_nack(ctx, udp_header_start, packet_start, reason);
+        }
+        let _ = NEGATIVE_CACHE.remove(&name_hash);
+        datapath_log!(ctx, Debug, "Negative cache entry for {:x} expired", name_hash);
+    }
 
-    let pit_entry = PitEntry {
-        name_hash,
-        face_id: 1,
-        timestamp: 0,
-    };
+    // Nothing behind the network stack serves the name, so the slow path could only Nack or
+    // drop it too
+    if !prefixes.routed {
+        let reason = NackReason::NoRoute as u8;
+        update_stats(|stats| stats.unroutable_nacks += 1);
+        datapath_log!(ctx, Info, "Interest {:x} under no route, Nacked", name_hash);
+        emit_event(ctx, TlvType::Interest, name_hash, face_id, EventOutcome::Unroutable, reason);
+        return reply_nack(ctx, udp_header_start, packet_start, reason);
+    }
 
-    if let Err(_) = unsafe { PIT.insert(&name_hash, &pit_entry, 0) } {
-        update_stats(|stats| stats.drops += 1);
-        return Ok(xdp_action::XDP_DROP);
+    // Cache miss - wait in the PIT, next to any other faces already waiting for the name
+    let now = unsafe { bpf_ktime_get_ns() };
+    if let Some(entry) = pit_entry(name_hash) {
+        let entry = unsafe { &mut *entry };
+        if !entry.add_downstream(downstream, now, |record| report_lapsed(name_hash, record)) {
+            return drop_packet(ctx, TlvType::Interest, name_hash, face_id, DropReason::PitFull);
+        }
+    } else {
+        let mut entry = PitEntry::new(name_hash, downstream, now);
+        entry.tenant = prefixes.tenant;
+        if !tenant_has_pit_room(prefixes.tenant) || pit_insert(&entry).is_err() {
+            return drop_packet(ctx, TlvType::Interest, name_hash, face_id, DropReason::PitFull);
+        }
+        tenant_count_pit_entry(prefixes.tenant, true);
     }
+    datapath_log!(ctx, Debug, "PIT entry for {:x} from face {:x}", name_hash, face_id);
 
+    datapath_log!(ctx, Info, "Interest {:x} forwarded", name_hash);
+    emit_event(ctx, TlvType::Interest, name_hash, face_id, EventOutcome::Forwarded, 0);
     Ok(xdp_action::XDP_PASS)
 }
 
-fn handle_data(data_pkt: udcn_common::DataPacket, _full_packet: &[u8]) -> Result<u32, u32> {
+/// Record the Interest's (name, nonce) pair and report whether the same face already sent it
+/// within the duplicate window. The same pair from a different face is a legitimate
+/// retransmission and is only recorded.
+#[inline(always)]
+fn is_duplicate_interest(interest: &InterestPacket, face_id: u32) -> bool {
+    let window_ms = CONFIG.get(0).map_or(0, |config| config.duplicate_window_ms);
+    if window_ms == 0 {
+        return false;
+    }
+
+    let now = unsafe { bpf_ktime_get_ns() };
+    let key = NonceKey {
+        name_hash: interest.name_hash,
+        nonce: interest.nonce,
+    };
+
+    if let Some(record) = unsafe { NONCE_HISTORY.get(&key) } {
+        if record.face_id == face_id && now - record.seen_at < window_ms as u64 * 1_000_000 {
+            return true;
+        }
+    }
+
+    let record = NonceRecord {
+        seen_at: now,
+        face_id,
+        reserved: 0,
+    };
+    let _ = NONCE_HISTORY.insert(&key, &record, 0);
+    false
+}
+
+fn handle_data(
+    ctx: &XdpContext,
+    data_pkt: DataPacket,
+    face_id: u32,
+    placement: CsPlacement,
+    _full_packet: &[u8],
+) -> Result<u32, u32> {
     let name_hash = data_pkt.name_hash;
+    let CsPlacement {
+        partition,
+        freshness_ms,
+        cacheable,
+        pinned,
+    } = placement;
     
-    if let Some(_pit_entry) = unsafe { PIT.get(&name_hash) } {
+    // The Data counts against the tenant of the Interests it answers
+    let (pending, tenant) = match pit_entry(name_hash) {
+        Some(entry) => {
+            // Faces whose Interest lifetime ran out are no longer waiting for the Data
+            let entry = unsafe { &mut *entry };
+            let now = unsafe { bpf_ktime_get_ns() };
+            entry.expire(now, |record| report_lapsed(name_hash, record));
+            let live = !entry.is_empty();
+            let tenant = entry.tenant;
+            if !live {
+                pit_remove(name_hash);
+                tenant_count_pit_entry(tenant, false);
+                datapath_log!(ctx, Debug, "PIT entry for {:x} expired", name_hash);
+            }
+            (live, tenant)
+        }
+        None => (false, 0),
+    };
+    if pending {
         update_stats(|stats| stats.pit_hits += 1);
+        datapath_log!(ctx, Debug, "PIT hit for {:x}", name_hash);
         
-        let _ = unsafe { PIT.remove(&name_hash) };
+        pit_remove(name_hash);
+        tenant_count_pit_entry(tenant, false);
+        let _ = NEGATIVE_CACHE.remove(&name_hash);
 
+        let now = unsafe { bpf_ktime_get_ns() };
         let cache_entry = CacheEntry {
             name_hash,
             data_size: data_pkt.content_size,
-            timestamp: 0,
+            prefetched: 0,
+            tenant: tenant as u8,
+            timestamp: now,
+            fresh_until: now.saturating_add(freshness_ms.saturating_mul(1_000_000)),
+            pinned: pinned as u8,
+            reserved: [0; 7],
         };
 
-        let _ = unsafe { CONTENT_STORE.insert(&name_hash, &cache_entry, 0) };
+        let outcome = if !cacheable {
+            update_stats(|stats| stats.uncacheable += 1);
+            datapath_log!(ctx, Info, "Data {:x} is marked non-cacheable", name_hash);
+            EventOutcome::Uncacheable
+        } else if !admit_to_cs(name_hash) {
+            update_stats(|stats| stats.cs_admission_rejects += 1);
+            datapath_log!(ctx, Info, "Data {:x} not admitted to the CS", name_hash);
+            EventOutcome::NotCached
+        } else if !tenant_admits_to_cs(tenant, data_pkt.content_size as u32) {
+            datapath_log!(ctx, Info, "Data {:x} over tenant {}'s CS quota", name_hash, tenant);
+            EventOutcome::NotCached
+        } else {
+            if cs_insert(partition, &name_hash, &cache_entry).is_err() {
+                datapath_log!(
+                    ctx,
+                    Error,
+                    "failed to insert {:x} into CS partition {}",
+                    name_hash,
+                    partition
+                );
+            }
+            // The payload kept for the name, if any, is of the copy this one replaces
+            let _ = DATA_CACHE.remove(&name_hash);
+            datapath_log!(ctx, Info, "Data {:x} cached in partition {}", name_hash, partition);
+            EventOutcome::Cached
+        };
+        emit_event(ctx, TlvType::Data, name_hash, face_id, outcome, 0);
 
         // For now, skip actual data caching to avoid verifier issues
         // In a real implementation, we'd copy packet data here
         
+        return Ok(hold_data(ctx));
+    }
+
+    drop_packet(ctx, TlvType::Data, name_hash, face_id, DropReason::UnsolicitedData)
+}
+
+/// Whether the admission policy lets Data named `name_hash` into the content store.
+#[inline(always)]
+fn admit_to_cs(name_hash: u32) -> bool {
+    let Some(config) = CONFIG.get(0) else {
+        return true;
+    };
+    let misses = || {
+        let window = config.cs_admission_window(unsafe { bpf_ktime_get_ns() });
+        let mut misses = u32::MAX;
+        for slot in cs_miss_slots(name_hash) {
+            let count = CS_MISSES.get(slot).map_or(0, |counter| counter.misses(window));
+            misses = misses.min(count);
+        }
+        misses
+    };
+    config.admits_to_cs(|| unsafe { bpf_get_prandom_u32() }, misses)
+}
+
+/// The usage counters of `tenant`, or `None` for names under no tenant.
+#[inline(always)]
+fn tenant_usage(tenant: u32) -> Option<(TenantQuota, &'static mut TenantUsage)> {
+    if tenant == 0 {
+        return None;
+    }
+    let quota = *TENANT_QUOTAS.get(tenant)?;
+    let usage = TENANT_USAGE.get_ptr_mut(tenant)?;
+    Some((quota, unsafe { &mut *usage }))
+}
+
+/// Whether `tenant` has Interests left this second. The window and its count are shared by
+/// every CPU without locking, so a busy tenant can get a few Interests over its rate.
+#[inline(always)]
+fn tenant_admits_interest(tenant: u32) -> bool {
+    let Some((quota, usage)) = tenant_usage(tenant) else {
+        return true;
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+    if now.wrapping_sub(usage.window_start) >= 1_000_000_000 {
+        usage.window_start = now;
+        usage.window_interests = 0;
+    }
+    usage.interests = usage.interests.wrapping_add(1);
+    if quota.interests_per_sec != 0 && usage.window_interests >= quota.interests_per_sec {
+        usage.rate_limited = usage.rate_limited.wrapping_add(1);
+        return false;
+    }
+    usage.window_interests += 1;
+    true
+}
+
+/// Whether `tenant` may add another PIT entry.
+#[inline(always)]
+fn tenant_has_pit_room(tenant: u32) -> bool {
+    let Some((quota, usage)) = tenant_usage(tenant) else {
+        return true;
+    };
+    if quota.pit_entries != 0 && usage.pit_entries >= quota.pit_entries {
+        usage.pit_rejects = usage.pit_rejects.wrapping_add(1);
+        return false;
+    }
+    true
+}
+
+/// Count a PIT entry of `tenant` as added or removed. The daemon recounts the entries
+/// periodically, which also catches those that expired without being removed here.
+#[inline(always)]
+fn tenant_count_pit_entry(tenant: u32, added: bool) {
+    if let Some((_, usage)) = tenant_usage(tenant) {
+        usage.pit_entries = match added {
+            true => usage.pit_entries.saturating_add(1),
+            false => usage.pit_entries.saturating_sub(1),
+        };
+    }
+}
+
+/// Whether `tenant` has room in the content store for `bytes` more, counting them if it has.
+/// Evictions are only seen when the daemon recounts the content store.
+#[inline(always)]
+fn tenant_admits_to_cs(tenant: u32, bytes: u32) -> bool {
+    let Some((quota, usage)) = tenant_usage(tenant) else {
+        return true;
+    };
+    if quota.cs_bytes != 0 && usage.cs_bytes.saturating_add(bytes) > quota.cs_bytes {
+        usage.cs_rejects = usage.cs_rejects.wrapping_add(1);
+        return false;
+    }
+    usage.cs_bytes = usage.cs_bytes.saturating_add(bytes);
+    true
+}
+
+/// Count a content store miss for `name_hash` when popularity admission needs it.
+#[inline(always)]
+fn record_cs_miss(name_hash: u32) {
+    let Some(config) = CONFIG.get(0) else {
+        return;
+    };
+    if config.cs_admission != CsAdmission::Popularity as u32 {
+        return;
+    }
+    let window = config.cs_admission_window(unsafe { bpf_ktime_get_ns() });
+    for slot in cs_miss_slots(name_hash) {
+        if let Some(counter) = CS_MISSES.get_ptr_mut(slot) {
+            unsafe { &mut *counter }.record(window);
+        }
+    }
+}
+
+/// Largest Data content whose trailing Name is parsed to pick a CS partition. A mask, so the
+/// verifier can bound the Name's offset.
+const MAX_PARTITIONED_CONTENT_SIZE: usize = 0x7ff;
+
+/// Longest Name element value `interest_lifetime_ms` skips over. A mask, like
+/// `MAX_PARTITIONED_CONTENT_SIZE`.
+const MAX_NAME_SIZE: usize = 0x3ff;
+
+/// Where the element after the Name element starting at `name_start` begins, or `None` if
+/// there is no Name there or it is too long to bound for the verifier.
+#[inline(always)]
+fn after_name(name_start: usize, data_end: usize) -> Option<usize> {
+    if name_start + 4 > data_end {
+        return None;
+    }
+    let (name_type, name_len) = unsafe {
+        (*(name_start as *const u8), *((name_start + 1) as *const u8))
+    };
+    if name_type != TlvType::Name as u8 {
+        return None;
+    }
+    let (value_start, value_len) = if name_len < 253 {
+        (name_start + 2, name_len as usize)
+    } else {
+        let len = unsafe { u16::from_be(((name_start + 2) as *const u16).read_unaligned()) };
+        (name_start + 4, len as usize)
+    };
+    if value_len > MAX_NAME_SIZE {
+        return None;
+    }
+    Some(value_start + (value_len & MAX_NAME_SIZE))
+}
+
+/// The InterestLifetime of the Interest whose Name element starts at `name_start`, or the NDN
+/// default without one, and whether it has MustBeFresh. Only the few elements `Interest::encode`
+/// puts after the Name are looked at, and lifetimes must fit in 4 bytes.
+#[inline(always)]
+fn interest_selectors(name_start: usize, data_end: usize) -> (u32, bool) {
+    let mut must_be_fresh = false;
+    let Some(mut offset) = after_name(name_start, data_end) else {
+        return (DEFAULT_INTEREST_LIFETIME_MS, must_be_fresh);
+    };
+
+    // CanBePrefix and MustBeFresh come before the lifetime
+    for _ in 0..3 {
+        if offset + 2 > data_end {
+            break;
+        }
+        let (typ, len) = unsafe { (*(offset as *const u8), *((offset + 1) as *const u8)) };
+        if typ == TlvType::MustBeFresh as u8 {
+            must_be_fresh = true;
+        } else if typ == TlvType::InterestLifetime as u8 {
+            if len as usize > 4 || offset + 6 > data_end {
+                break;
+            }
+            let mut lifetime_ms = 0u32;
+            for i in 0..4 {
+                if i < len as usize {
+                    let byte = unsafe { *((offset + 2 + i) as *const u8) };
+                    lifetime_ms = lifetime_ms << 8 | byte as u32;
+                }
+            }
+            return (lifetime_ms, must_be_fresh);
+        }
+        offset += 2 + len as usize;
+    }
+    (DEFAULT_INTEREST_LIFETIME_MS, must_be_fresh)
+}
+
+/// The FreshnessPeriod, in milliseconds, and the ContentType from the MetaInfo following the
+/// Data's Name element at `name_start`. Data without a FreshnessPeriod is stale as soon as it
+/// arrives; without a ContentType it is a BLOB (0).
+#[inline(always)]
+fn meta_info(name_start: usize, data_end: usize) -> (u64, u64) {
+    let (mut freshness_ms, mut content_type) = (0, 0);
+    let Some(meta_start) = after_name(name_start, data_end) else {
+        return (freshness_ms, content_type);
+    };
+    if meta_start + 2 > data_end || unsafe { *(meta_start as *const u8) } != TlvType::MetaInfo as u8 {
+        return (freshness_ms, content_type);
+    }
+
+    let mut offset = meta_start + 2;
+    for _ in 0..2 {
+        if offset + 2 > data_end {
+            break;
+        }
+        let (typ, len) = unsafe { (*(offset as *const u8), *((offset + 1) as *const u8)) };
+        if len as usize > 8 {
+            break;
+        }
+        let mut value = 0u64;
+        for i in 0..8 {
+            if i < len as usize {
+                let at = offset + 2 + i;
+                if at + 1 > data_end {
+                    return (freshness_ms, content_type);
+                }
+                value = value << 8 | unsafe { *(at as *const u8) } as u64;
+            }
+        }
+        if typ == TlvType::ContentType as u8 {
+            content_type = value;
+        } else if typ == TlvType::FreshnessPeriod as u8 {
+            freshness_ms = value;
+        }
+        offset += 2 + len as usize;
+    }
+    (freshness_ms, content_type)
+}
+
+/// Whether `name_hash` is pinned in the content store by name; see `CS_PIN_PREFIXES` for
+/// prefixes.
+#[inline(always)]
+fn name_pinned(name_hash: u32) -> bool {
+    unsafe { CS_PIN_NAMES.get(&name_hash) }.is_some()
+}
+
+/// Whether the Name element at `name_start` falls under a prefix in `NO_CACHE_PREFIXES`.
+#[inline(always)]
+fn no_cache_prefix(name_start: usize, data_end: usize) -> bool {
+    under_prefix(&NO_CACHE_PREFIXES, name_start, data_end)
+}
+
+/// Whether the Interest whose Name element starts at `name_start` may go on to the network
+/// stack: always, unless `DatapathConfig::nack_unroutable` is set and no prefix in `ROUTES`
+/// covers the name. The routes of a ForwardingHint's delegations are left to the forwarder, so a
+/// hinted Interest always goes on.
+#[inline(always)]
+fn routed(name_start: usize, data_end: usize) -> bool {
+    if CONFIG.get(0).is_none_or(|config| config.nack_unroutable == 0) {
+        return true;
+    }
+    under_prefix(&ROUTES, name_start, data_end) || forwarding_hinted(name_start, data_end)
+}
+
+/// Whether the Interest whose Name element starts at `name_start` may carry a ForwardingHint.
+/// Only the elements `Interest::encode` puts before one are looked through; anything else might
+/// hide one.
+#[inline(always)]
+fn forwarding_hinted(name_start: usize, data_end: usize) -> bool {
+    let Some(mut offset) = after_name(name_start, data_end) else {
+        return true;
+    };
+    // CanBePrefix, MustBeFresh and InterestLifetime, then the ForwardingHint
+    for _ in 0..4 {
+        if offset + 2 > data_end {
+            return false;
+        }
+        let (typ, len) = unsafe { (*(offset as *const u8), *((offset + 1) as *const u8)) };
+        if typ == TlvType::ApplicationParameters as u8 {
+            return false;
+        }
+        // Lengths from 253 up take more bytes, which no element before the hint needs
+        if typ == TlvType::ForwardingHint as u8 || len >= 253 {
+            return true;
+        }
+        offset += 2 + len as usize;
+    }
+    true
+}
+
+/// Whether any prefix ACL rules are defined.
+#[inline(always)]
+fn acl_active() -> bool {
+    CONFIG.get(0).is_some_and(|config| config.acl_rules != 0)
+}
+
+/// Whether a prefix ACL drops the packet from `face_id` whose Name element starts at
+/// `name_start`. The rule for the longest prefix of the name decides, a rule for the face winning
+/// over one for every face; a name no rule matches is dropped only with
+/// `DatapathConfig::acl_default_deny` set. Never, without a lookup, while no rules are defined.
+#[inline(always)]
+fn acl_denies(face_id: u32, name_start: usize, data_end: usize) -> bool {
+    let Some(config) = CONFIG.get(0) else {
+        return false;
+    };
+    if config.acl_rules == 0 {
+        return false;
+    }
+    let mut denied = config.acl_default_deny != 0;
+    let Some(name_end) = after_name(name_start, data_end) else {
+        return denied;
+    };
+    // Lengths from 253 up are followed by a 2-byte length
+    let name_len = unsafe { *((name_start + 1) as *const u8) };
+    let mut offset = if name_len < 253 { name_start + 2 } else { name_start + 4 };
+
+    let mut prefix = FNV_OFFSET_BASIS;
+    for _ in 0..MAX_NO_CACHE_PREFIX_COMPONENTS {
+        if offset + 2 > name_end || offset + 2 > data_end {
+            break;
+        }
+        let component_len = unsafe { *((offset + 1) as *const u8) } as usize;
+        if component_len > MAX_NO_CACHE_COMPONENT_LEN {
+            break;
+        }
+        for i in 0..2 + MAX_NO_CACHE_COMPONENT_LEN {
+            if i >= 2 + component_len {
+                break;
+            }
+            if offset + i + 1 > data_end {
+                return denied;
+            }
+            prefix ^= unsafe { *((offset + i) as *const u8) } as u32;
+            prefix = prefix.wrapping_mul(FNV_PRIME);
+        }
+        let action = unsafe { ACL.get(&AclKey { face_id, prefix }) }
+            .or_else(|| unsafe { ACL.get(&AclKey { face_id: 0, prefix }) });
+        if let Some(action) = action {
+            denied = *action == AclAction::Deny as u8;
+        }
+        offset += 2 + component_len;
+    }
+    denied
+}
+
+/// The tenant whose prefix the Interest whose Name element starts at `name_start` falls under,
+/// or 0 for none. Always 0, without a lookup, while no tenants are defined.
+#[inline(always)]
+fn tenant_of(name_start: usize, data_end: usize) -> u32 {
+    if CONFIG.get(0).is_none_or(|config| config.tenants == 0) {
+        return 0;
+    }
+    prefix_value(&TENANT_PREFIXES, name_start, data_end).unwrap_or(0)
+}
+
+/// Whether the Name element at `name_start` falls under a prefix in `prefixes`.
+#[inline(always)]
+fn under_prefix(prefixes: &HashMap<u32, u8>, name_start: usize, data_end: usize) -> bool {
+    prefix_value(prefixes, name_start, data_end).is_some()
+}
+
+/// The value in `prefixes` of the shortest prefix of the Name element at `name_start` it holds.
+/// Keys hash the encoded component elements, so the hash after each of the first components is
+/// the key of the prefix ending there.
+#[inline(always)]
+fn prefix_value<V: Copy>(prefixes: &HashMap<u32, V>, name_start: usize, data_end: usize) -> Option<V> {
+    let name_end = after_name(name_start, data_end)?;
+    // Lengths from 253 up are followed by a 2-byte length
+    let name_len = unsafe { *((name_start + 1) as *const u8) };
+    let mut offset = if name_len < 253 { name_start + 2 } else { name_start + 4 };
+
+    let mut key = FNV_OFFSET_BASIS;
+    for _ in 0..MAX_NO_CACHE_PREFIX_COMPONENTS {
+        if offset + 2 > name_end || offset + 2 > data_end {
+            return None;
+        }
+        let component_len = unsafe { *((offset + 1) as *const u8) } as usize;
+        if component_len > MAX_NO_CACHE_COMPONENT_LEN {
+            return None;
+        }
+        for i in 0..2 + MAX_NO_CACHE_COMPONENT_LEN {
+            if i >= 2 + component_len {
+                break;
+            }
+            if offset + i + 1 > data_end {
+                return None;
+            }
+            key ^= unsafe { *((offset + i) as *const u8) } as u32;
+            key = key.wrapping_mul(FNV_PRIME);
+        }
+        if let Some(value) = unsafe { prefixes.get(&key) } {
+            return Some(*value);
+        }
+        offset += 2 + component_len;
+    }
+    None
+}
+
+/// The content store partition for the name whose Name element starts at `name_start`: the one
+/// reserved for its first component, or 0 (the shared `CONTENT_STORE`).
+#[inline(always)]
+fn cs_partition(name_start: usize, data_end: usize) -> u32 {
+    if name_start + 4 > data_end {
+        return 0;
+    }
+    let (name_type, name_len) = unsafe {
+        (*(name_start as *const u8), *((name_start + 1) as *const u8))
+    };
+    if name_type != TlvType::Name as u8 {
+        return 0;
+    }
+    // Lengths from 253 up are followed by a 2-byte length
+    let component_start = if name_len < 253 { name_start + 2 } else { name_start + 4 };
+    if component_start + 2 > data_end {
+        return 0;
+    }
+    let component_len = unsafe { *((component_start + 1) as *const u8) } as usize;
+    if component_len > MAX_CS_PARTITION_COMPONENT_LEN {
+        return 0;
+    }
+
+    let value_start = component_start + 2;
+    let mut key = FNV_OFFSET_BASIS;
+    for i in 0..MAX_CS_PARTITION_COMPONENT_LEN {
+        if i >= component_len {
+            break;
+        }
+        if value_start + i + 1 > data_end {
+            return 0;
+        }
+        key ^= unsafe { *((value_start + i) as *const u8) } as u32;
+        key = key.wrapping_mul(FNV_PRIME);
+    }
+    unsafe { CS_PARTITION_PREFIXES.get(&key) }.copied().unwrap_or(0)
+}
+
+/// Which segment of the shared partition a content store entry was found in.
+#[derive(Clone, Copy)]
+enum CsSegment {
+    /// A reserved partition, or the shared one while it is not segmented.
+    Unsegmented,
+    /// `CS_PINNED`, whatever the partition.
+    Pinned,
+    Probation,
+    Protected,
+}
+
+#[inline(always)]
+fn cs_segmented() -> bool {
+    CONFIG.get(0).is_some_and(|config| config.cs_segmented != 0)
+}
+
+/// Look `name_hash` up among the pinned entries, then in `partition`; in the shared one, when
+/// `segmented`, in the protected segment before the probation one.
+#[inline(always)]
+fn cs_get(
+    partition: u32,
+    segmented: bool,
+    name_hash: &u32,
+) -> (Option<&'static CacheEntry>, CsSegment) {
+    unsafe {
+        if let Some(entry) = CS_PINNED.get(name_hash) {
+            return (Some(entry), CsSegment::Pinned);
+        }
+        let entry = match partition {
+            1 => CS_PARTITION_1.get(name_hash),
+            2 => CS_PARTITION_2.get(name_hash),
+            3 => CS_PARTITION_3.get(name_hash),
+            4 => CS_PARTITION_4.get(name_hash),
+            _ if segmented => {
+                if let Some(entry) = CS_PROTECTED.get(name_hash) {
+                    return (Some(entry), CsSegment::Protected);
+                }
+                return (CONTENT_STORE.get(name_hash), CsSegment::Probation);
+            }
+            _ => CONTENT_STORE.get(name_hash),
+        };
+        (entry, CsSegment::Unsegmented)
+    }
+}
+
+/// Move an entry on probation into the protected segment. The protected segment evicts its
+/// least recently used entry to make room; it is dropped rather than put back on probation.
+#[inline(always)]
+fn cs_promote(name_hash: &u32, entry: CacheEntry) {
+    if CS_PROTECTED.insert(name_hash, &entry, 0).is_ok() {
+        let _ = CONTENT_STORE.remove(name_hash);
+    }
+}
+
+#[inline(always)]
+fn cs_bloom_contains(name_hash: u32) -> bool {
+    let half = CS_BLOOM_ACTIVE.get(0).copied().unwrap_or(0) & 1;
+    for bit in cs_bloom_bits(name_hash) {
+        match CS_BLOOM.get(half * CS_BLOOM_WORDS + bit / 64) {
+            Some(word) if word & (1 << (bit % 64)) != 0 => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[inline(always)]
+fn cs_bloom_insert(name_hash: u32) {
+    for half in 0..2 {
+        for bit in cs_bloom_bits(name_hash) {
+            if let Some(word) = CS_BLOOM.get_ptr_mut(half * CS_BLOOM_WORDS + bit / 64) {
+                // Other CPUs may be setting bits in the same word
+                let word = unsafe { AtomicU64::from_ptr(word) };
+                word.fetch_or(1 << (bit % 64), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn cs_insert(partition: u32, name_hash: &u32, entry: &CacheEntry) -> Result<(), i64> {
+    cs_bloom_insert(*name_hash);
+    // Fresh Data for a pinned name replaces it there, even when its prefix could not be checked
+    let pinned = entry.pinned != 0 || unsafe { CS_PINNED.get(name_hash) }.is_some();
+    if pinned && CS_PINNED.insert(name_hash, &CacheEntry { pinned: 1, ..*entry }, 0).is_ok() {
+        return Ok(());
+    }
+    // With `CS_PINNED` full, it takes its chances in the partition
+    let entry = &CacheEntry { pinned: 0, ..*entry };
+    match partition {
+        1 => CS_PARTITION_1.insert(name_hash, entry, 0),
+        2 => CS_PARTITION_2.insert(name_hash, entry, 0),
+        3 => CS_PARTITION_3.insert(name_hash, entry, 0),
+        4 => CS_PARTITION_4.insert(name_hash, entry, 0),
+        // Fresh Data for a protected name replaces it where it is
+        _ if cs_segmented() && unsafe { CS_PROTECTED.get(name_hash) }.is_some() => {
+            CS_PROTECTED.insert(name_hash, entry, 0)
+        }
+        _ => CONTENT_STORE.insert(name_hash, entry, 0),
+    }
+}
+
+fn handle_nack(ctx: &XdpContext, name_hash: u32, face_id: u32, reason: u8) -> Result<u32, u32> {
+    // The Interest is answered either way, so its PIT entry is no longer pending
+    let tenant = pit_entry(name_hash).map_or(0, |entry| unsafe { (*entry).tenant });
+    pit_remove(name_hash);
+    tenant_count_pit_entry(tenant, false);
+
+    let ttl_ms = CONFIG.get(0).map_or(0, |config| config.negative_cache_ttl_ms);
+    let cacheable = NackReason::from_u8(reason).is_some_and(NackReason::is_cacheable);
+    if ttl_ms > 0 && cacheable {
+        let entry = NegativeCacheEntry {
+            expires_at: unsafe { bpf_ktime_get_ns() } + ttl_ms as u64 * 1_000_000,
+            reason,
+            reserved: [0; 7],
+        };
+        let _ = NEGATIVE_CACHE.insert(&name_hash, &entry, 0);
+        datapath_log!(ctx, Debug, "Nack for {:x} negatively cached for {} ms", name_hash, ttl_ms);
+    }
+
+    datapath_log!(ctx, Info, "Nack {:x} reason {} forwarded", name_hash, reason);
+    emit_event(ctx, TlvType::Nack, name_hash, face_id, EventOutcome::Forwarded, reason);
+    Ok(xdp_action::XDP_PASS)
+}
+
+/// Turn the Interest at `packet_start` in the current frame into a Nack and bounce it back to its
+/// sender; an LpPacket it came in stays around it.
+///
+/// The Nack has the same size as the Interest, so only the addresses, the UDP checksum and the
+/// packet type need rewriting. Swapping the IP addresses leaves the IP header checksum valid.
+#[inline(always)]
+fn reply_nack(
+    ctx: &XdpContext,
+    udp_header_start: usize,
+    packet_start: usize,
+    reason: u8,
+) -> Result<u32, u32> {
+    let data = ctx.data();
+    let data_end = ctx.data_end();
+
+    if data + 34 > data_end || packet_start + core::mem::size_of::<NackPacket>() > data_end {
         return Ok(xdp_action::XDP_PASS);
     }
+    // An Interest from a tunnel is left as it is, see try_udcn
+    let ip_header_len = (unsafe { *((data + 14) as *const u8) } & 0x0f) as usize * 4;
+    if udp_header_start != data + 14 + ip_header_len {
+        return Ok(xdp_action::XDP_PASS);
+    }
+
+    unsafe {
+        let eth = data as *mut [u8; 6];
+        let (dst_mac, src_mac) = (*eth, *eth.add(1));
+        *eth = src_mac;
+        *eth.add(1) = dst_mac;
+
+        let saddr = (data + 14 + 12) as *mut u32;
+        let daddr = (data + 14 + 16) as *mut u32;
+        let (src_ip, dst_ip) = (saddr.read_unaligned(), daddr.read_unaligned());
+        saddr.write_unaligned(dst_ip);
+        daddr.write_unaligned(src_ip);
+
+        let sport = udp_header_start as *mut u16;
+        let dport = (udp_header_start + 2) as *mut u16;
+        let (src_port, dst_port) = (sport.read_unaligned(), dport.read_unaligned());
+        sport.write_unaligned(dst_port);
+        dport.write_unaligned(src_port);
+        // A zero UDP checksum means "not computed" for IPv4
+        ((udp_header_start + 6) as *mut u16).write_unaligned(0);
+
+        *(packet_start as *mut u8) = TlvType::Nack as u8;
+        *((packet_start + offset_of!(NackPacket, reason)) as *mut u8) = reason;
+        *((packet_start + offset_of!(NackPacket, reserved)) as *mut u8) = 0;
+    }
+
+    Ok(xdp_action::XDP_TX)
+}
+
+/// The faults to inject, while `DatapathConfig::fault_injection` allows any.
+#[inline(always)]
+fn faults() -> Option<&'static FaultConfig> {
+    if CONFIG.get(0).is_none_or(|config| config.fault_injection == 0) {
+        return None;
+    }
+    FAULTS.get(0)
+}
+
+/// Whether fault injection drops the Interest whose Name element starts at `name_start`: at
+/// random, or because the name is under a blackholed prefix.
+#[inline(always)]
+fn inject_interest_drop(name_start: usize, data_end: usize) -> bool {
+    let Some(faults) = faults() else {
+        return false;
+    };
+    let probability = faults.interest_drop_probability;
+    if probability != 0 && unsafe { bpf_get_prandom_u32() } <= probability {
+        return true;
+    }
+    faults.blackholes != 0 && under_prefix(&FAULT_BLACKHOLES, name_start, data_end)
+}
+
+/// Flip the bits of a Data packet's signature fingerprint and of its last byte, which in signed
+/// Data belongs to the SignatureValue, when fault injection asks. The UDP checksum is cleared,
+/// which IPv4 reads as none, so the network stack still delivers the packet.
+#[inline(always)]
+fn inject_signature_corruption(udp_header_start: usize, udp_payload_start: usize, data_end: usize) {
+    if faults().is_none_or(|faults| faults.corrupt_signatures == 0) {
+        return;
+    }
+    let signature = udp_payload_start + offset_of!(DataPacket, signature);
+    if signature + 4 > data_end || udp_header_start + 8 > data_end {
+        return;
+    }
+    unsafe {
+        let ptr = signature as *mut u32;
+        ptr.write_unaligned(!ptr.read_unaligned());
+        ((udp_header_start + 6) as *mut u16).write_unaligned(0);
+    }
+    // The verifier only takes offsets it can bound, so the last byte is reached from the start
+    let payload_len = data_end - udp_payload_start;
+    if payload_len > MAX_SAMPLE_LEN as usize {
+        return;
+    }
+    let last = udp_payload_start + ((payload_len - 1) & (MAX_SAMPLE_LEN as usize - 1));
+    if last + 1 > data_end {
+        return;
+    }
+    unsafe {
+        let ptr = last as *mut u8;
+        *ptr = !*ptr;
+    }
+}
+
+/// Copy Data on its way to the network stack onto `HELD_DATA` and drop it when fault injection
+/// delays Data; the daemon sends it on later. Data too long to copy whole goes on undelayed.
+#[inline(always)]
+fn hold_data(ctx: &XdpContext) -> u32 {
+    if faults().is_none_or(|faults| faults.data_delay_ms == 0) {
+        return xdp_action::XDP_PASS;
+    }
+    let packet_len = (ctx.data_end() - ctx.data()) as u32;
+    if packet_len > MAX_SAMPLE_LEN {
+        return xdp_action::XDP_PASS;
+    }
+    let held = PacketSample {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        ifindex: unsafe { (*ctx.ctx).ingress_ifindex },
+        packet_len,
+        captured_len: packet_len.min(MAX_SAMPLE_LEN),
+        reserved: 0,
+    };
+    // The kernel appends the whole packet to the header
+    HELD_DATA.output(ctx, &held, held.captured_len);
+    xdp_action::XDP_DROP
+}
+
+/// Most GTP-U extension headers skipped to reach the packet a G-PDU carries.
+const MAX_GTPU_EXTENSION_HEADERS: usize = 2;
+
+/// Where the frame carried in a VXLAN or GTP-U packet starts, if the packet is one of those
+/// `DatapathConfig::decapsulate` asks for; one level of encapsulation is parsed. VXLAN carries
+/// Ethernet frames, checked here to hold IPv4. GTP-U carries IP packets with no Ethernet header,
+/// so the frame returned for one starts 14 bytes before its IPv4 header, and only the offsets
+/// from it are meaningful.
+#[inline(always)]
+fn encapsulated_frame(data: usize, data_end: usize) -> Option<usize> {
+    let decapsulate = CONFIG.get(0).map_or(0, |config| config.decapsulate);
+    if decapsulate == 0 || data + 34 > data_end {
+        return None;
+    }
+    let eth_type = unsafe { u16::from_be(((data + 12) as *const u16).read_unaligned()) };
+    if eth_type != 0x0800 || unsafe { *((data + 14 + 9) as *const u8) } != 17 {
+        return None;
+    }
+    let udp_header_start = data + 14 + (unsafe { *((data + 14) as *const u8) } & 0x0f) as usize * 4;
+    if udp_header_start + 8 > data_end {
+        return None;
+    }
+    let dst_port = unsafe { u16::from_be(((udp_header_start + 2) as *const u16).read_unaligned()) };
+    let tunnel_header = udp_header_start + 8;
+
+    if dst_port == VXLAN_UDP_PORT && decapsulate & DECAPSULATE_VXLAN != 0 {
+        // Flags with the I bit set, which says the VNI is valid, then the inner frame
+        let inner = tunnel_header + 8;
+        if inner + 14 > data_end || unsafe { *(tunnel_header as *const u8) } & 0x08 == 0 {
+            return None;
+        }
+        let inner_type = unsafe { u16::from_be(((inner + 12) as *const u16).read_unaligned()) };
+        return (inner_type == 0x0800).then_some(inner);
+    }
+
+    if dst_port == GTPU_UDP_PORT && decapsulate & DECAPSULATE_GTPU != 0 {
+        if tunnel_header + 8 > data_end {
+            return None;
+        }
+        // Version 1 of the GTP protocol type, carrying a user packet (a G-PDU)
+        let (flags, message_type) = unsafe {
+            (*(tunnel_header as *const u8), *((tunnel_header + 1) as *const u8))
+        };
+        if flags >> 4 != 0x3 || message_type != 0xff {
+            return None;
+        }
+        let mut inner = tunnel_header + 8;
+        // Any of the E, S and PN flags adds a sequence number, an N-PDU number and the type of
+        // the first extension header
+        if flags & 0x07 != 0 {
+            if inner + 4 > data_end {
+                return None;
+            }
+            let mut next_type = unsafe { *((inner + 3) as *const u8) };
+            inner += 4;
+            // Extension headers, such as the PDU session container of 5G, each a length in
+            // 4-byte units, content, and the type of the next one
+            for _ in 0..MAX_GTPU_EXTENSION_HEADERS {
+                if next_type == 0 {
+                    break;
+                }
+                if inner + 1 > data_end {
+                    return None;
+                }
+                let len = unsafe { *(inner as *const u8) } as usize * 4;
+                if len == 0 || inner + len > data_end {
+                    return None;
+                }
+                next_type = unsafe { *((inner + len - 1) as *const u8) };
+                inner += len;
+            }
+            if next_type != 0 {
+                return None;
+            }
+        }
+        if inner + 20 > data_end || unsafe { *(inner as *const u8) } >> 4 != 4 {
+            return None;
+        }
+        return Some(inner - 14);
+    }
+    None
+}
+
+/// Whether packets from `addr` are dropped for now, as it misbehaved; counts the packet if so.
+#[inline(always)]
+fn greylisted(addr: [u8; 4]) -> bool {
+    if CONFIG.get(0).is_none_or(|config| config.greylist_offences == 0) {
+        return false;
+    }
+    let Some(reputation) = REPUTATION.get_ptr_mut(&u32::from_be_bytes(addr)) else {
+        return false;
+    };
+    let reputation = unsafe { &mut *reputation };
+    if !reputation.is_greylisted(unsafe { bpf_ktime_get_ns() }) {
+        return false;
+    }
+    reputation.dropped = reputation.dropped.wrapping_add(1);
+    true
+}
+
+/// Count an offence against `addr`, greylisting it if it keeps misbehaving; see `Reputation`.
+#[inline(always)]
+fn record_offence(addr: [u8; 4]) {
+    let Some(config) = CONFIG.get(0) else {
+        return;
+    };
+    if config.greylist_offences == 0 {
+        return;
+    }
+    let key = u32::from_be_bytes(addr);
+    let now = unsafe { bpf_ktime_get_ns() };
+    if let Some(reputation) = REPUTATION.get_ptr_mut(&key) {
+        unsafe { &mut *reputation }.offend(now, config);
+        return;
+    }
+    let mut reputation = Reputation {
+        window_start: now,
+        ..Reputation::default()
+    };
+    reputation.offend(now, config);
+    let _ = REPUTATION.insert(&key, &reputation, 0);
+}
 
-    update_stats(|stats| stats.drops += 1);
+/// Count the drop, report it and drop the packet.
+#[inline(always)]
+fn drop_packet(
+    ctx: &XdpContext,
+    packet_type: TlvType,
+    name_hash: u32,
+    face_id: u32,
+    reason: DropReason,
+) -> Result<u32, u32> {
+    update_stats(|stats| stats.drops[reason as usize] += 1);
+    datapath_log!(
+        ctx,
+        Warn,
+        "dropped {:x} from face {:x}: {}",
+        name_hash,
+        face_id,
+        reason.as_str()
+    );
+    emit_event(ctx, packet_type, name_hash, face_id, EventOutcome::Dropped, reason as u8);
     Ok(xdp_action::XDP_DROP)
 }
 
+/// Whether the checksum of the UDP datagram at `udp_header_start` is right, or absent (0). A
+/// datagram longer than what the frame holds fails; one longer than `MAX_CHECKSUM_LEN` passes
+/// unverified.
+#[inline(always)]
+fn udp_checksum_ok(data: usize, udp_header_start: usize, data_end: usize) -> bool {
+    if data + 34 > data_end || udp_header_start + 8 > data_end {
+        return false;
+    }
+    let (checksum, udp_len) = unsafe {
+        (
+            ((udp_header_start + 6) as *const u16).read_unaligned(),
+            u16::from_be(((udp_header_start + 4) as *const u16).read_unaligned()) as usize,
+        )
+    };
+    if checksum == 0 {
+        return true;
+    }
+    if udp_len < 8 || udp_header_start + udp_len > data_end {
+        return false;
+    }
+    if udp_len > MAX_CHECKSUM_LEN {
+        return true;
+    }
+
+    // The pseudo-header: source and destination addresses, protocol and UDP length
+    let word = |at: usize| unsafe { u16::from_be((at as *const u16).read_unaligned()) } as u32;
+    let mut sum = 17 + udp_len as u32;
+    sum += word(data + 26) + word(data + 28) + word(data + 30) + word(data + 32);
+    for i in 0..MAX_CHECKSUM_LEN / 2 {
+        let offset = 2 * i;
+        if offset + 2 > udp_len || udp_header_start + offset + 2 > data_end {
+            break;
+        }
+        sum += word(udp_header_start + offset);
+    }
+    // An odd last byte is summed as if padded with a zero
+    if udp_len % 2 == 1 {
+        let last = udp_header_start + udp_len - 1;
+        if last + 1 > data_end {
+            return false;
+        }
+        sum += (unsafe { *(last as *const u8) } as u32) << 8;
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum == 0xffff
+}
+
+#[inline(always)]
+fn open_pit() -> bool {
+    CONFIG
+        .get(0)
+        .is_some_and(|config| config.pit_layout == PitLayout::OpenAddressing as u32)
+}
+
+#[inline(always)]
+fn pit_probe(name_hash: u32) -> pit::Probe {
+    pit::probe(
+        name_hash,
+        |slot| PIT_FINGERPRINTS.get(slot).copied().unwrap_or(pit::EMPTY),
+        |slot| PIT_ENTRIES.get(slot).map_or(!name_hash, |entry| entry.name_hash),
+    )
+}
+
+/// The pending entry for `name_hash`, in whichever PIT layout is in use.
+#[inline(always)]
+fn pit_entry(name_hash: u32) -> Option<*mut PitEntry> {
+    if !open_pit() {
+        return PIT.get_ptr_mut(&name_hash);
+    }
+    match pit_probe(name_hash) {
+        pit::Probe::Found(slot) => PIT_ENTRIES.get_ptr_mut(slot),
+        _ => None,
+    }
+}
+
+/// Add `entry` for a name that has none. Fails when the PIT has no room for it: the hash map
+/// is full, or none of the name's slots in the open-addressing layout is free. Two CPUs adding
+/// names with the same free slot at once can lose one of them; its face retransmits.
+#[inline(always)]
+fn pit_insert(entry: &PitEntry) -> Result<(), ()> {
+    if !open_pit() {
+        return PIT.insert(&entry.name_hash, entry, 0).map_err(|_| ());
+    }
+    let (pit::Probe::Vacant(slot) | pit::Probe::Found(slot)) = pit_probe(entry.name_hash) else {
+        return Err(());
+    };
+    let (Some(stored), Some(fingerprint)) =
+        (PIT_ENTRIES.get_ptr_mut(slot), PIT_FINGERPRINTS.get_ptr_mut(slot))
+    else {
+        return Err(());
+    };
+    // The entry goes in before the fingerprint that makes lookups read it
+    unsafe {
+        *stored = *entry;
+        *fingerprint = pit::fingerprint(entry.name_hash);
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn pit_remove(name_hash: u32) {
+    if !open_pit() {
+        let _ = PIT.remove(&name_hash);
+        return;
+    }
+    if let pit::Probe::Found(slot) = pit_probe(name_hash) {
+        if let Some(fingerprint) = PIT_FINGERPRINTS.get_ptr_mut(slot) {
+            unsafe { *fingerprint = pit::TOMBSTONE };
+        }
+    }
+}
+
+/// Hand a downstream record that lapsed before Data arrived to the daemon, which Nacks the face.
+#[inline(always)]
+fn report_lapsed(name_hash: u32, record: &PitRecord) {
+    let lapsed = LapsedRecord {
+        name_hash,
+        reserved: 0,
+        record: *record,
+    };
+    // With the ring buffer full the face goes without a Nack and times out on its own
+    let _ = PIT_LAPSED.output(&lapsed, 0);
+}
+
+/// Where the packet an LpPacket carries whole starts, if it carries one: after a TraceId, a
+/// CongestionMark or both, as `lp::trace` and `lp::mark_congestion` put them ahead of the
+/// Fragment. Fragments of larger packets, and anything else, are left to the network stack. A
+/// traced packet is published on `TRACE_HOPS`, and as an event if anyone is listening.
+#[inline(always)]
+fn lp_fragment(
+    data: usize,
+    udp_header_start: usize,
+    udp_payload_start: usize,
+    data_end: usize,
+) -> Option<usize> {
+    // The LpPacket header's length takes one or three bytes
+    let length = unsafe { *((udp_payload_start + 1) as *const u8) };
+    let mut offset = if length < 253 {
+        udp_payload_start + 2
+    } else if length == 253 {
+        udp_payload_start + 4
+    } else {
+        return None;
+    };
+    let mut trace_id = 0;
+    for _ in 0..2 {
+        if offset + 4 > data_end {
+            return None;
+        }
+        let field_type = unsafe { *(offset as *const u8) };
+        if field_type == TlvType::TraceId as u8 {
+            if offset + 10 > data_end || unsafe { *((offset + 1) as *const u8) } != 8 {
+                return None;
+            }
+            trace_id = unsafe { u64::from_be(((offset + 2) as *const u64).read_unaligned()) };
+            offset += 10;
+        } else if field_type == 253 {
+            // The CongestionMark's type takes three bytes
+            let (mark_type, mark_length) = unsafe {
+                (
+                    u16::from_be(((offset + 1) as *const u16).read_unaligned()),
+                    *((offset + 3) as *const u8),
+                )
+            };
+            if mark_type as u64 != CONGESTION_MARK || mark_length > 8 {
+                return None;
+            }
+            offset += 4 + mark_length as usize;
+        } else {
+            break;
+        }
+    }
+
+    if offset + 2 > data_end {
+        return None;
+    }
+    let (fragment_type, fragment_length) = unsafe {
+        (*(offset as *const u8), *((offset + 1) as *const u8))
+    };
+    if fragment_type != TlvType::Fragment as u8 {
+        return None;
+    }
+    let inner = if fragment_length < 253 {
+        offset + 2
+    } else if fragment_length == 253 {
+        offset + 4
+    } else {
+        return None;
+    };
+    if inner + 2 > data_end {
+        return None;
+    }
+    if trace_id != 0 {
+        report_traced(data, udp_header_start, inner, data_end, trace_id);
+    }
+    Some(inner)
+}
+
+/// Publish the traced packet starting at `inner` on `TRACE_HOPS`, and as an event if anyone is
+/// listening.
+#[inline(always)]
+fn report_traced(
+    data: usize,
+    udp_header_start: usize,
+    inner: usize,
+    data_end: usize,
+    trace_id: u64,
+) {
+    let packet_type = unsafe { *(inner as *const u8) };
+    let name_hash_offset = if packet_type == TlvType::Interest as u8 {
+        offset_of!(InterestPacket, name_hash)
+    } else if packet_type == TlvType::Data as u8 {
+        offset_of!(DataPacket, name_hash)
+    } else if packet_type == TlvType::Nack as u8 {
+        offset_of!(NackPacket, name_hash)
+    } else {
+        return;
+    };
+    if inner + name_hash_offset + 4 > data_end {
+        return;
+    }
+    let name_hash = unsafe { u32::from_be(((inner + name_hash_offset) as *const u32).read_unaligned()) };
+    let (src_addr, src_port, dst_addr, dst_port) = unsafe {
+        (
+            ((data + 14 + 12) as *const [u8; 4]).read_unaligned(),
+            (udp_header_start as *const [u8; 2]).read_unaligned(),
+            ((data + 14 + 16) as *const [u8; 4]).read_unaligned(),
+            ((udp_header_start + 2) as *const [u8; 2]).read_unaligned(),
+        )
+    };
+    // The consumer sent the Interest, and is where Data and Nacks are headed
+    let (addr, local_addr, port) = if packet_type == TlvType::Interest as u8 {
+        (src_addr, dst_addr, src_port)
+    } else {
+        (dst_addr, src_addr, dst_port)
+    };
+    let timestamp_ns = unsafe { bpf_ktime_get_ns() };
+    let hop = TraceHop {
+        timestamp_ns,
+        trace_id,
+        name_hash,
+        addr,
+        local_addr,
+        port,
+        packet_type,
+        reserved: 0,
+    };
+    // With the ring buffer full the consumer misses this hop's report
+    let _ = TRACE_HOPS.output(&hop, 0);
+
+    if EVENTS_ENABLED.get(0).copied().unwrap_or(0) != 0 {
+        let event = DatapathEvent {
+            timestamp_ns,
+            name_hash,
+            face_id: udp_face_id(src_addr, src_port),
+            packet_type,
+            outcome: EventOutcome::Traced as u8,
+            reason: 0,
+            reserved: 0,
+            packet_len: (data_end - data) as u32,
+            trace_id,
+        };
+        let _ = EVENTS.output(&event, 0);
+    }
+}
+
+/// Publish a forwarding decision for `udcn events`, if anyone is listening.
+#[inline(always)]
+fn emit_event(
+    ctx: &XdpContext,
+    packet_type: TlvType,
+    name_hash: u32,
+    face_id: u32,
+    outcome: EventOutcome,
+    reason: u8,
+) {
+    if EVENTS_ENABLED.get(0).copied().unwrap_or(0) == 0 {
+        return;
+    }
+    let event = DatapathEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        name_hash,
+        face_id,
+        packet_type: packet_type as u8,
+        outcome: outcome as u8,
+        reason,
+        reserved: 0,
+        packet_len: (ctx.data_end() - ctx.data()) as u32,
+        trace_id: 0,
+    };
+    // A full ring buffer means the daemon is behind; the event is lost, not the packet
+    let _ = EVENTS.output(&event, 0);
+}
+
+/// Copy one in `SampleConfig::rate` NDN packets to `SAMPLES`, truncated to the snap length.
+/// Packets of a skipped type, or for another name than the one asked for, are not counted.
+#[inline(always)]
+fn sample_packet(ctx: &XdpContext, udp_payload_start: usize, data_end: usize) {
+    let Some(config) = SAMPLE_CONFIG.get(0) else {
+        return;
+    };
+    if config.rate == 0 {
+        return;
+    }
+    let packet_type = unsafe { *(udp_payload_start as *const u8) };
+    if config.skip_types & sample_type_bit(packet_type) != 0 {
+        return;
+    }
+    if config.name_hash != 0 && header_name_hash(udp_payload_start, data_end) != Some(config.name_hash) {
+        return;
+    }
+    if unsafe { bpf_get_prandom_u32() } % config.rate != 0 {
+        return;
+    }
+    let packet_len = (ctx.data_end() - ctx.data()) as u32;
+    let sample = PacketSample {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        ifindex: unsafe { (*ctx.ctx).ingress_ifindex },
+        packet_len,
+        captured_len: packet_len.min(config.snaplen).min(MAX_SAMPLE_LEN),
+        reserved: 0,
+    };
+    // The kernel appends that many bytes of the packet to the sample
+    SAMPLES.output(ctx, &sample, sample.captured_len);
+}
+
+/// The name hash in the header of the Interest, Data or Nack starting at `packet`.
+#[inline(always)]
+fn header_name_hash(packet: usize, data_end: usize) -> Option<u32> {
+    let packet_type = unsafe { *(packet as *const u8) };
+    let name_hash_offset = if packet_type == TlvType::Interest as u8 {
+        offset_of!(InterestPacket, name_hash)
+    } else if packet_type == TlvType::Data as u8 {
+        offset_of!(DataPacket, name_hash)
+    } else if packet_type == TlvType::Nack as u8 {
+        offset_of!(NackPacket, name_hash)
+    } else {
+        return None;
+    };
+    if packet + name_hash_offset + 4 > data_end {
+        return None;
+    }
+    Some(unsafe { u32::from_be(((packet + name_hash_offset) as *const u32).read_unaligned()) })
+}
+
+#[inline(always)]
+fn log_enabled(level: DatapathLogLevel) -> bool {
+    CONFIG.get(0).is_some_and(|config| config.log_level >= level as u32)
+}
+
 fn update_stats<F>(f: F) 
 where 
     F: FnOnce(&mut PacketStats),
@@ -0,0 +1,275 @@
+#ifndef UDCN_H
+#define UDCN_H
+
+/* Generated by cbindgen from udcn-ffi/src/lib.rs; run `make ffi-header` after changing it. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define UDCN_DROP_REASONS 12
+
+typedef enum UdcnStatus {
+  UDCN_STATUS_OK = 0,
+  /**
+   * A pointer was null or a string was not valid UTF-8.
+   */
+  UDCN_STATUS_INVALID_ARGUMENT = -1,
+  /**
+   * The name URI could not be parsed.
+   */
+  UDCN_STATUS_INVALID_NAME = -2,
+  /**
+   * The packet could not be decoded.
+   */
+  UDCN_STATUS_MALFORMED = -3,
+  /**
+   * The output buffer is too small; the required size was written to the length out-parameter.
+   */
+  UDCN_STATUS_BUFFER_TOO_SMALL = -4,
+  /**
+   * No entry exists for the requested key.
+   */
+  UDCN_STATUS_NOT_FOUND = -5,
+  /**
+   * A map could not be opened or read.
+   */
+  UDCN_STATUS_MAP_ERROR = -6,
+} UdcnStatus;
+
+/**
+ * Index into `UdcnStats::drops`, matching the datapath's drop reasons.
+ */
+typedef enum UdcnDropReason {
+  UDCN_DROP_REASON_MALFORMED = 0,
+  UDCN_DROP_REASON_NO_FIB_MATCH = 1,
+  UDCN_DROP_REASON_PIT_FULL = 2,
+  UDCN_DROP_REASON_RATE_LIMITED = 3,
+  UDCN_DROP_REASON_HOPLIMIT = 4,
+  UDCN_DROP_REASON_SCOPE_VIOLATION = 5,
+  UDCN_DROP_REASON_DUPLICATE_NONCE = 6,
+  UDCN_DROP_REASON_UNSOLICITED_DATA = 7,
+  UDCN_DROP_REASON_INJECTED = 8,
+  UDCN_DROP_REASON_BAD_CHECKSUM = 9,
+  UDCN_DROP_REASON_ACL_DROPPED = 10,
+  UDCN_DROP_REASON_GREYLISTED = 11,
+} UdcnDropReason;
+
+/**
+ * Read-only handles to the maps a running daemon pinned. Opaque to C.
+ */
+typedef struct UdcnMaps UdcnMaps;
+
+/**
+ * Fixed fields of a decoded Interest.
+ */
+typedef struct UdcnInterestInfo {
+  uint32_t name_hash;
+  uint32_t nonce;
+  bool can_be_prefix;
+  bool must_be_fresh;
+  bool has_lifetime;
+  uint64_t lifetime_ms;
+} UdcnInterestInfo;
+
+/**
+ * Fixed fields of a decoded Data. `content` points into the decoded packet buffer.
+ */
+typedef struct UdcnDataInfo {
+  uint32_t name_hash;
+  const uint8_t *content;
+  size_t content_len;
+  bool has_freshness_period;
+  uint64_t freshness_period_ms;
+} UdcnDataInfo;
+
+/**
+ * Datapath counters, as read from the pinned `STATS` map.
+ */
+typedef struct UdcnStats {
+  uint32_t interest_received;
+  uint32_t data_received;
+  uint32_t cache_hits;
+  uint32_t cache_misses;
+  uint32_t pit_hits;
+  uint32_t forwards;
+  /**
+   * Dropped packets, indexed by `UdcnDropReason`.
+   */
+  uint32_t drops[UDCN_DROP_REASONS];
+  uint32_t negative_cache_hits;
+  uint32_t duplicate_interests;
+  uint32_t cs_admission_rejects;
+  uint32_t fresh_hits;
+  uint32_t stale_hits;
+  uint32_t uncacheable;
+  uint32_t prefetch_hits;
+  uint32_t cs_probation_hits;
+  uint32_t cs_protected_hits;
+  uint32_t unroutable_nacks;
+} UdcnStats;
+
+typedef struct UdcnPitEntry {
+  uint32_t face_id;
+  uint64_t timestamp;
+} UdcnPitEntry;
+
+typedef struct UdcnCsEntry {
+  uint16_t data_size;
+  uint64_t timestamp;
+  /**
+   * Monotonic nanoseconds until which the entry satisfies Interests with MustBeFresh.
+   * [`udcn_maps_realtime_ns`] converts it, like `timestamp`, to wall-clock time.
+   */
+  uint64_t fresh_until;
+  /**
+   * Whether the entry is pinned, so that it is never evicted.
+   */
+  bool pinned;
+} UdcnCsEntry;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Hash `uri` the way the datapath keys its tables.
+ *
+ * # Safety
+ *
+ * `uri` must be a NUL-terminated string and `out_hash` valid for writes.
+ */
+enum UdcnStatus udcn_name_hash(const char *uri, uint32_t *out_hash);
+
+/**
+ * Encode an Interest for `uri` into `buf`. A `lifetime_ms` of 0 omits the InterestLifetime.
+ *
+ * # Safety
+ *
+ * `uri` must be a NUL-terminated string, `buf` valid for `buf_len` bytes of writes and
+ * `out_len` valid for writes.
+ */
+enum UdcnStatus udcn_encode_interest(const char *uri,
+                                     uint32_t nonce,
+                                     bool can_be_prefix,
+                                     bool must_be_fresh,
+                                     uint64_t lifetime_ms,
+                                     uint8_t *buf,
+                                     size_t buf_len,
+                                     size_t *out_len);
+
+/**
+ * Encode an unsigned Data for `uri` into `buf`. A `freshness_period_ms` of 0 omits the
+ * FreshnessPeriod.
+ *
+ * # Safety
+ *
+ * `uri` must be a NUL-terminated string, `content` valid for `content_len` bytes of reads
+ * (or null when `content_len` is 0), `buf` valid for `buf_len` bytes of writes and `out_len`
+ * valid for writes.
+ */
+enum UdcnStatus udcn_encode_data(const char *uri,
+                                 const uint8_t *content,
+                                 size_t content_len,
+                                 uint64_t freshness_period_ms,
+                                 uint8_t *buf,
+                                 size_t buf_len,
+                                 size_t *out_len);
+
+/**
+ * Decode the Interest in `packet`, writing its canonical name URI (NUL-terminated) to
+ * `name_buf`. `name_len` receives the URI length without the terminator.
+ *
+ * # Safety
+ *
+ * `packet` must be valid for `packet_len` bytes of reads, `name_buf` valid for `name_buf_len`
+ * bytes of writes, and `name_len` and `out` valid for writes.
+ */
+enum UdcnStatus udcn_decode_interest(const uint8_t *packet,
+                                     size_t packet_len,
+                                     char *name_buf,
+                                     size_t name_buf_len,
+                                     size_t *name_len,
+                                     struct UdcnInterestInfo *out);
+
+/**
+ * Decode the Data in `packet`, writing its canonical name URI (NUL-terminated) to `name_buf`.
+ * `out.content` points into `packet` and is valid as long as it is.
+ *
+ * # Safety
+ *
+ * `packet` must be valid for `packet_len` bytes of reads, `name_buf` valid for `name_buf_len`
+ * bytes of writes, and `name_len` and `out` valid for writes.
+ */
+enum UdcnStatus udcn_decode_data(const uint8_t *packet,
+                                 size_t packet_len,
+                                 char *name_buf,
+                                 size_t name_buf_len,
+                                 size_t *name_len,
+                                 struct UdcnDataInfo *out);
+
+/**
+ * Open the maps pinned by a running daemon under `pin_dir`, or the default directory when
+ * `pin_dir` is null. Returns null on failure. Release the handle with [`udcn_maps_close`].
+ *
+ * # Safety
+ *
+ * `pin_dir` must be null or a NUL-terminated string.
+ */
+struct UdcnMaps *udcn_maps_open(const char *pin_dir);
+
+/**
+ * # Safety
+ *
+ * `maps` must be null or a handle from [`udcn_maps_open`] that has not been closed.
+ */
+void udcn_maps_close(struct UdcnMaps *maps);
+
+/**
+ * # Safety
+ *
+ * `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+ */
+enum UdcnStatus udcn_maps_stats(const struct UdcnMaps *maps, struct UdcnStats *out);
+
+/**
+ * Convert `timestamp`, in the monotonic nanoseconds of the maps and entries, to nanoseconds since
+ * the Unix epoch, the way the daemon does.
+ *
+ * # Safety
+ *
+ * `maps` must be a live handle from [`udcn_maps_open`] and `out_ns` valid for writes.
+ */
+enum UdcnStatus udcn_maps_realtime_ns(const struct UdcnMaps *maps,
+                                      uint64_t timestamp,
+                                      uint64_t *out_ns);
+
+/**
+ * Look up the pending Interest for `name_hash`.
+ *
+ * # Safety
+ *
+ * `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+ */
+enum UdcnStatus udcn_maps_pit_lookup(const struct UdcnMaps *maps,
+                                     uint32_t name_hash,
+                                     struct UdcnPitEntry *out);
+
+/**
+ * Look up the Content Store entry for `name_hash`, in whichever partition holds it.
+ *
+ * # Safety
+ *
+ * `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+ */
+enum UdcnStatus udcn_maps_cs_lookup(const struct UdcnMaps *maps,
+                                    uint32_t name_hash,
+                                    struct UdcnCsEntry *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UDCN_H */
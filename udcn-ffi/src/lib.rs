@@ -0,0 +1,674 @@
+//! C ABI over the µDCN packet codec and the daemon's pinned maps, for forwarders written in C or
+//! C++. The header is `include/udcn.h`.
+//!
+//! Every fallible function returns a [`UdcnStatus`]. Output buffers are caller-owned; functions
+//! that write a variable amount of data report how much they needed, so callers can retry with a
+//! larger buffer on [`UdcnStatus::BufferTooSmall`].
+
+use std::{
+    ffi::{c_char, CStr},
+    path::Path,
+    ptr, slice,
+};
+
+use aya::maps::{Array, HashMap, Map, MapData};
+use udcn_common::{
+    pit, CacheEntry, Data, Interest, MetaInfo, Name, PacketStats, PitEntry, TimeBase,
+    CS_PARTITION_MAPS, DROP_REASONS, MAP_PIN_DIR,
+};
+
+pub const UDCN_DROP_REASONS: usize = 12;
+const _: () = assert!(UDCN_DROP_REASONS == DROP_REASONS);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UdcnStatus {
+    Ok = 0,
+    /// A pointer was null or a string was not valid UTF-8.
+    InvalidArgument = -1,
+    /// The name URI could not be parsed.
+    InvalidName = -2,
+    /// The packet could not be decoded.
+    Malformed = -3,
+    /// The output buffer is too small; the required size was written to the length out-parameter.
+    BufferTooSmall = -4,
+    /// No entry exists for the requested key.
+    NotFound = -5,
+    /// A map could not be opened or read.
+    MapError = -6,
+}
+
+/// Index into `UdcnStats::drops`, matching the datapath's drop reasons.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UdcnDropReason {
+    Malformed = 0,
+    NoFibMatch = 1,
+    PitFull = 2,
+    RateLimited = 3,
+    Hoplimit = 4,
+    ScopeViolation = 5,
+    DuplicateNonce = 6,
+    UnsolicitedData = 7,
+    Injected = 8,
+    BadChecksum = 9,
+    AclDropped = 10,
+    Greylisted = 11,
+}
+
+/// Fixed fields of a decoded Interest.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdcnInterestInfo {
+    pub name_hash: u32,
+    pub nonce: u32,
+    pub can_be_prefix: bool,
+    pub must_be_fresh: bool,
+    pub has_lifetime: bool,
+    pub lifetime_ms: u64,
+}
+
+/// Fixed fields of a decoded Data. `content` points into the decoded packet buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct UdcnDataInfo {
+    pub name_hash: u32,
+    pub content: *const u8,
+    pub content_len: usize,
+    pub has_freshness_period: bool,
+    pub freshness_period_ms: u64,
+}
+
+/// Datapath counters, as read from the pinned `STATS` map.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdcnStats {
+    pub interest_received: u32,
+    pub data_received: u32,
+    pub cache_hits: u32,
+    pub cache_misses: u32,
+    pub pit_hits: u32,
+    pub forwards: u32,
+    /// Dropped packets, indexed by `UdcnDropReason`.
+    pub drops: [u32; UDCN_DROP_REASONS],
+    pub negative_cache_hits: u32,
+    pub duplicate_interests: u32,
+    pub cs_admission_rejects: u32,
+    pub fresh_hits: u32,
+    pub stale_hits: u32,
+    pub uncacheable: u32,
+    pub prefetch_hits: u32,
+    pub cs_probation_hits: u32,
+    pub cs_protected_hits: u32,
+    pub unroutable_nacks: u32,
+}
+
+impl From<PacketStats> for UdcnStats {
+    fn from(stats: PacketStats) -> Self {
+        Self {
+            interest_received: stats.interest_received,
+            data_received: stats.data_received,
+            cache_hits: stats.cache_hits,
+            cache_misses: stats.cache_misses,
+            pit_hits: stats.pit_hits,
+            forwards: stats.forwards,
+            drops: stats.drops,
+            negative_cache_hits: stats.negative_cache_hits,
+            duplicate_interests: stats.duplicate_interests,
+            cs_admission_rejects: stats.cs_admission_rejects,
+            fresh_hits: stats.fresh_hits,
+            stale_hits: stats.stale_hits,
+            uncacheable: stats.uncacheable,
+            prefetch_hits: stats.prefetch_hits,
+            cs_probation_hits: stats.cs_probation_hits,
+            cs_protected_hits: stats.cs_protected_hits,
+            unroutable_nacks: stats.unroutable_nacks,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdcnPitEntry {
+    pub face_id: u32,
+    pub timestamp: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdcnCsEntry {
+    pub data_size: u16,
+    pub timestamp: u64,
+    /// Monotonic nanoseconds until which the entry satisfies Interests with MustBeFresh.
+    /// [`udcn_maps_realtime_ns`] converts it, like `timestamp`, to wall-clock time.
+    pub fresh_until: u64,
+    /// Whether the entry is pinned, so that it is never evicted.
+    pub pinned: bool,
+}
+
+/// Read-only handles to the maps a running daemon pinned. Opaque to C.
+pub struct UdcnMaps {
+    pit: HashMap<MapData, u32, PitEntry>,
+    /// The open-addressing PIT, which is empty unless the daemon runs with `--pit-layout
+    /// open-addressing`.
+    pit_fingerprints: Array<MapData, u32>,
+    pit_entries: Array<MapData, PitEntry>,
+    /// The pinned entries, the shared partition, then the reserved ones.
+    content_store: Vec<HashMap<MapData, u32, CacheEntry>>,
+    stats: Array<MapData, PacketStats>,
+    time_base: Array<MapData, TimeBase>,
+}
+
+/// Hash `uri` the way the datapath keys its tables.
+///
+/// # Safety
+///
+/// `uri` must be a NUL-terminated string and `out_hash` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_name_hash(uri: *const c_char, out_hash: *mut u32) -> UdcnStatus {
+    let name = match parse_name(uri) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    match out_hash.as_mut() {
+        Some(out_hash) => {
+            *out_hash = name.name_hash();
+            UdcnStatus::Ok
+        }
+        None => UdcnStatus::InvalidArgument,
+    }
+}
+
+/// Encode an Interest for `uri` into `buf`. A `lifetime_ms` of 0 omits the InterestLifetime.
+///
+/// # Safety
+///
+/// `uri` must be a NUL-terminated string, `buf` valid for `buf_len` bytes of writes and
+/// `out_len` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_encode_interest(
+    uri: *const c_char,
+    nonce: u32,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: u64,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> UdcnStatus {
+    let name = match parse_name(uri) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    let interest = Interest {
+        name,
+        nonce,
+        can_be_prefix,
+        must_be_fresh,
+        lifetime_ms: (lifetime_ms > 0).then_some(lifetime_ms),
+        forwarding_hint: Vec::new(),
+        application_parameters: None,
+    };
+    write_out(&interest.encode(), buf, buf_len, out_len)
+}
+
+/// Encode an unsigned Data for `uri` into `buf`. A `freshness_period_ms` of 0 omits the
+/// FreshnessPeriod.
+///
+/// # Safety
+///
+/// `uri` must be a NUL-terminated string, `content` valid for `content_len` bytes of reads
+/// (or null when `content_len` is 0), `buf` valid for `buf_len` bytes of writes and `out_len`
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_encode_data(
+    uri: *const c_char,
+    content: *const u8,
+    content_len: usize,
+    freshness_period_ms: u64,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> UdcnStatus {
+    let name = match parse_name(uri) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    let Some(content) = input_slice(content, content_len) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    if content.len() > u16::MAX as usize {
+        return UdcnStatus::InvalidArgument;
+    }
+    let data = Data {
+        name,
+        meta: MetaInfo {
+            content_type: None,
+            freshness_period_ms: (freshness_period_ms > 0).then_some(freshness_period_ms),
+        },
+        content: content.to_vec(),
+        signature_info: None,
+        signature_value: Vec::new(),
+    };
+    write_out(&data.encode(), buf, buf_len, out_len)
+}
+
+/// Decode the Interest in `packet`, writing its canonical name URI (NUL-terminated) to
+/// `name_buf`. `name_len` receives the URI length without the terminator.
+///
+/// # Safety
+///
+/// `packet` must be valid for `packet_len` bytes of reads, `name_buf` valid for `name_buf_len`
+/// bytes of writes, and `name_len` and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_decode_interest(
+    packet: *const u8,
+    packet_len: usize,
+    name_buf: *mut c_char,
+    name_buf_len: usize,
+    name_len: *mut usize,
+    out: *mut UdcnInterestInfo,
+) -> UdcnStatus {
+    let Some(packet) = input_slice(packet, packet_len) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    let Some(out) = out.as_mut() else {
+        return UdcnStatus::InvalidArgument;
+    };
+    let Some(interest) = Interest::decode(packet) else {
+        return UdcnStatus::Malformed;
+    };
+
+    *out = UdcnInterestInfo {
+        name_hash: interest.name.name_hash(),
+        nonce: interest.nonce,
+        can_be_prefix: interest.can_be_prefix,
+        must_be_fresh: interest.must_be_fresh,
+        has_lifetime: interest.lifetime_ms.is_some(),
+        lifetime_ms: interest.lifetime_ms.unwrap_or(0),
+    };
+    write_name(&interest.name, name_buf, name_buf_len, name_len)
+}
+
+/// Decode the Data in `packet`, writing its canonical name URI (NUL-terminated) to `name_buf`.
+/// `out.content` points into `packet` and is valid as long as it is.
+///
+/// # Safety
+///
+/// `packet` must be valid for `packet_len` bytes of reads, `name_buf` valid for `name_buf_len`
+/// bytes of writes, and `name_len` and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_decode_data(
+    packet: *const u8,
+    packet_len: usize,
+    name_buf: *mut c_char,
+    name_buf_len: usize,
+    name_len: *mut usize,
+    out: *mut UdcnDataInfo,
+) -> UdcnStatus {
+    let Some(packet) = input_slice(packet, packet_len) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    let Some(out) = out.as_mut() else {
+        return UdcnStatus::InvalidArgument;
+    };
+    let Some(data) = Data::decode(packet) else {
+        return UdcnStatus::Malformed;
+    };
+
+    // Content directly follows the fixed header, so hand out a view of the caller's buffer
+    let content_start = std::mem::size_of::<udcn_common::DataPacket>();
+    *out = UdcnDataInfo {
+        name_hash: data.name.name_hash(),
+        content: packet[content_start..].as_ptr(),
+        content_len: data.content.len(),
+        has_freshness_period: data.meta.freshness_period_ms.is_some(),
+        freshness_period_ms: data.meta.freshness_period_ms.unwrap_or(0),
+    };
+    write_name(&data.name, name_buf, name_buf_len, name_len)
+}
+
+/// Open the maps pinned by a running daemon under `pin_dir`, or the default directory when
+/// `pin_dir` is null. Returns null on failure. Release the handle with [`udcn_maps_close`].
+///
+/// # Safety
+///
+/// `pin_dir` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_open(pin_dir: *const c_char) -> *mut UdcnMaps {
+    let dir = if pin_dir.is_null() {
+        MAP_PIN_DIR
+    } else {
+        match CStr::from_ptr(pin_dir).to_str() {
+            Ok(dir) => dir,
+            Err(_) => return ptr::null_mut(),
+        }
+    };
+    match open_maps(Path::new(dir)) {
+        Ok(maps) => Box::into_raw(Box::new(maps)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `maps` must be null or a handle from [`udcn_maps_open`] that has not been closed.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_close(maps: *mut UdcnMaps) {
+    if !maps.is_null() {
+        drop(Box::from_raw(maps));
+    }
+}
+
+/// # Safety
+///
+/// `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_stats(maps: *const UdcnMaps, out: *mut UdcnStats) -> UdcnStatus {
+    let (Some(maps), Some(out)) = (maps.as_ref(), out.as_mut()) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    match maps.stats.get(&0, 0) {
+        Ok(stats) => {
+            *out = stats.into();
+            UdcnStatus::Ok
+        }
+        Err(_) => UdcnStatus::MapError,
+    }
+}
+
+/// Convert `timestamp`, in the monotonic nanoseconds of the maps and entries, to nanoseconds since
+/// the Unix epoch, the way the daemon does.
+///
+/// # Safety
+///
+/// `maps` must be a live handle from [`udcn_maps_open`] and `out_ns` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_realtime_ns(
+    maps: *const UdcnMaps,
+    timestamp: u64,
+    out_ns: *mut u64,
+) -> UdcnStatus {
+    let (Some(maps), Some(out_ns)) = (maps.as_ref(), out_ns.as_mut()) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    match maps.time_base.get(&0, 0) {
+        Ok(base) => {
+            *out_ns = base.realtime_ns(timestamp);
+            UdcnStatus::Ok
+        }
+        Err(_) => UdcnStatus::MapError,
+    }
+}
+
+/// Look up the pending Interest for `name_hash`.
+///
+/// # Safety
+///
+/// `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_pit_lookup(
+    maps: *const UdcnMaps,
+    name_hash: u32,
+    out: *mut UdcnPitEntry,
+) -> UdcnStatus {
+    let (Some(maps), Some(out)) = (maps.as_ref(), out.as_mut()) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    let entry = maps.pit.get(&name_hash, 0).or_else(|e| {
+        let probe = pit::probe(
+            name_hash,
+            |slot| maps.pit_fingerprints.get(&slot, 0).unwrap_or(pit::EMPTY),
+            |slot| {
+                maps.pit_entries
+                    .get(&slot, 0)
+                    .map_or(!name_hash, |entry| entry.name_hash)
+            },
+        );
+        match probe {
+            pit::Probe::Found(slot) => maps.pit_entries.get(&slot, 0),
+            _ => Err(e),
+        }
+    });
+    match entry {
+        Ok(entry) => {
+            *out = UdcnPitEntry {
+                face_id: entry.face_id,
+                timestamp: entry.timestamp,
+            };
+            UdcnStatus::Ok
+        }
+        Err(aya::maps::MapError::KeyNotFound) => UdcnStatus::NotFound,
+        Err(_) => UdcnStatus::MapError,
+    }
+}
+
+/// Look up the Content Store entry for `name_hash`, in whichever partition holds it.
+///
+/// # Safety
+///
+/// `maps` must be a live handle from [`udcn_maps_open`] and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn udcn_maps_cs_lookup(
+    maps: *const UdcnMaps,
+    name_hash: u32,
+    out: *mut UdcnCsEntry,
+) -> UdcnStatus {
+    let (Some(maps), Some(out)) = (maps.as_ref(), out.as_mut()) else {
+        return UdcnStatus::InvalidArgument;
+    };
+    for partition in &maps.content_store {
+        match partition.get(&name_hash, 0) {
+            Ok(entry) => {
+                *out = UdcnCsEntry {
+                    data_size: entry.data_size,
+                    timestamp: entry.timestamp,
+                    fresh_until: entry.fresh_until,
+                    pinned: entry.pinned != 0,
+                };
+                return UdcnStatus::Ok;
+            }
+            Err(aya::maps::MapError::KeyNotFound) => {}
+            Err(_) => return UdcnStatus::MapError,
+        }
+    }
+    UdcnStatus::NotFound
+}
+
+fn open_maps(dir: &Path) -> Result<UdcnMaps, aya::maps::MapError> {
+    Ok(UdcnMaps {
+        pit: HashMap::try_from(Map::HashMap(MapData::from_pin(dir.join("PIT"))?))?,
+        pit_fingerprints: Array::try_from(Map::Array(MapData::from_pin(
+            dir.join("PIT_FINGERPRINTS"),
+        )?))?,
+        pit_entries: Array::try_from(Map::Array(MapData::from_pin(dir.join("PIT_ENTRIES"))?))?,
+        content_store: std::iter::once(Map::HashMap(MapData::from_pin(dir.join("CS_PINNED"))?))
+            .chain(
+                ["CS_PROTECTED", "CONTENT_STORE"]
+                    .into_iter()
+                    .chain(CS_PARTITION_MAPS)
+                    .map(|name| MapData::from_pin(dir.join(name)).map(Map::LruHashMap))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+            .map(HashMap::try_from)
+            .collect::<Result<_, _>>()?,
+        stats: Array::try_from(Map::Array(MapData::from_pin(dir.join("STATS"))?))?,
+        time_base: Array::try_from(Map::Array(MapData::from_pin(dir.join("TIME_BASE"))?))?,
+    })
+}
+
+unsafe fn parse_name(uri: *const c_char) -> Result<Name, UdcnStatus> {
+    if uri.is_null() {
+        return Err(UdcnStatus::InvalidArgument);
+    }
+    let uri = CStr::from_ptr(uri)
+        .to_str()
+        .map_err(|_| UdcnStatus::InvalidArgument)?;
+    Name::from_uri(uri).map_err(|_| UdcnStatus::InvalidName)
+}
+
+unsafe fn input_slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn write_out(bytes: &[u8], buf: *mut u8, buf_len: usize, out_len: *mut usize) -> UdcnStatus {
+    let Some(out_len) = out_len.as_mut() else {
+        return UdcnStatus::InvalidArgument;
+    };
+    *out_len = bytes.len();
+    if bytes.len() > buf_len {
+        return UdcnStatus::BufferTooSmall;
+    }
+    if buf.is_null() {
+        return UdcnStatus::InvalidArgument;
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    UdcnStatus::Ok
+}
+
+unsafe fn write_name(
+    name: &Name,
+    name_buf: *mut c_char,
+    name_buf_len: usize,
+    name_len: *mut usize,
+) -> UdcnStatus {
+    let uri = name.to_string();
+    let Some(name_len) = name_len.as_mut() else {
+        return UdcnStatus::InvalidArgument;
+    };
+    *name_len = uri.len();
+    if uri.len() >= name_buf_len {
+        return UdcnStatus::BufferTooSmall;
+    }
+    if name_buf.is_null() {
+        return UdcnStatus::InvalidArgument;
+    }
+    ptr::copy_nonoverlapping(uri.as_ptr(), name_buf.cast(), uri.len());
+    *name_buf.add(uri.len()) = 0;
+    UdcnStatus::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interest_roundtrip_through_c_api() {
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        let status = unsafe {
+            udcn_encode_interest(
+                c"/a/seg=3".as_ptr(),
+                7,
+                false,
+                true,
+                4000,
+                buf.as_mut_ptr(),
+                8,
+                &mut len,
+            )
+        };
+        assert_eq!(status, UdcnStatus::BufferTooSmall);
+        let needed = len;
+
+        let status = unsafe {
+            udcn_encode_interest(
+                c"/a/seg=3".as_ptr(),
+                7,
+                false,
+                true,
+                4000,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut len,
+            )
+        };
+        assert_eq!((status, len), (UdcnStatus::Ok, needed));
+
+        let mut name = [0 as c_char; 32];
+        let mut name_len = 0;
+        let mut info = UdcnInterestInfo::default();
+        let status = unsafe {
+            udcn_decode_interest(
+                buf.as_ptr(),
+                len,
+                name.as_mut_ptr(),
+                name.len(),
+                &mut name_len,
+                &mut info,
+            )
+        };
+        assert_eq!(status, UdcnStatus::Ok);
+        assert_eq!(unsafe { CStr::from_ptr(name.as_ptr()) }, c"/a/seg=3");
+        assert_eq!(name_len, "/a/seg=3".len());
+        assert_eq!(
+            (info.nonce, info.must_be_fresh, info.lifetime_ms),
+            (7, true, 4000)
+        );
+
+        let mut name_hash = 0;
+        assert_eq!(
+            unsafe { udcn_name_hash(c"/a/seg=3".as_ptr(), &mut name_hash) },
+            UdcnStatus::Ok
+        );
+        assert_eq!(name_hash, info.name_hash);
+    }
+
+    #[test]
+    fn test_data_content_points_into_packet() {
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        let content = b"hello";
+        let status = unsafe {
+            udcn_encode_data(
+                c"/d".as_ptr(),
+                content.as_ptr(),
+                content.len(),
+                0,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut len,
+            )
+        };
+        assert_eq!(status, UdcnStatus::Ok);
+
+        let mut name = [0 as c_char; 8];
+        let mut name_len = 0;
+        let mut info = std::mem::MaybeUninit::<UdcnDataInfo>::uninit();
+        let status = unsafe {
+            udcn_decode_data(
+                buf.as_ptr(),
+                len,
+                name.as_mut_ptr(),
+                name.len(),
+                &mut name_len,
+                info.as_mut_ptr(),
+            )
+        };
+        assert_eq!(status, UdcnStatus::Ok);
+        let info = unsafe { info.assume_init() };
+        assert_eq!(
+            unsafe { slice::from_raw_parts(info.content, info.content_len) },
+            content
+        );
+        assert!(!info.has_freshness_period);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let mut name_hash = 0;
+        assert_eq!(
+            unsafe { udcn_name_hash(ptr::null(), &mut name_hash) },
+            UdcnStatus::InvalidArgument
+        );
+        assert_eq!(
+            unsafe { udcn_name_hash(c"/a/%zz".as_ptr(), &mut name_hash) },
+            UdcnStatus::InvalidName
+        );
+        assert!(unsafe { udcn_maps_open(c"/nonexistent".as_ptr()) }.is_null());
+    }
+}
@@ -0,0 +1,262 @@
+//! Python bindings over `udcn-client`, for scripting experiments and test harnesses.
+//!
+//! ```python
+//! import udcn
+//!
+//! data = udcn.express_interest("/video/stream1", "127.0.0.1:6363", lifetime_ms=1000)
+//! print(data.name, data.content)
+//! print(udcn.stats()["cache_hits"])
+//! ```
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use pyo3::{
+    create_exception,
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+use tokio::runtime::Runtime;
+use udcn_client::{control, Consumer, Producer, Reply};
+use udcn_common::{Data, DropReason, Interest, NackReason, Name};
+
+create_exception!(udcn, NackError, PyException, "The Interest was answered with a Nack.");
+create_exception!(udcn, UdcnError, PyException, "A µDCN operation failed.");
+
+/// How often `serve` wakes up to let Python deliver signals such as Ctrl-C.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+#[pyclass(name = "Interest", frozen, get_all)]
+struct PyInterest {
+    name: String,
+    nonce: u32,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: Option<u64>,
+}
+
+#[pyclass(name = "Data", frozen, get_all)]
+struct PyData {
+    name: String,
+    content: Py<PyBytes>,
+    freshness_period_ms: Option<u64>,
+}
+
+impl PyData {
+    fn new(py: Python<'_>, data: Data) -> Self {
+        Self {
+            name: data.name.to_string(),
+            content: PyBytes::new(py, &data.content).unbind(),
+            freshness_period_ms: data.meta.freshness_period_ms,
+        }
+    }
+}
+
+/// Express an Interest for `name` to the forwarder or producer at `target` and return the Data.
+/// Raises `NackError` on a Nack and `UdcnError` on timeout.
+#[pyfunction]
+#[pyo3(signature = (name, target, lifetime_ms=None, can_be_prefix=false, must_be_fresh=false))]
+fn express_interest(
+    py: Python<'_>,
+    name: &str,
+    target: &str,
+    lifetime_ms: Option<u64>,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+) -> PyResult<PyData> {
+    let name = parse_name(name)?;
+    let target: SocketAddr = target.parse().map_err(|e| PyValueError::new_err(format!("{e}")))?;
+    let mut builder = Interest::builder()
+        .name(name)
+        .can_be_prefix(can_be_prefix)
+        .must_be_fresh(must_be_fresh);
+    if let Some(lifetime_ms) = lifetime_ms {
+        builder = builder.lifetime_ms(lifetime_ms);
+    }
+    let interest = builder.build();
+
+    let reply = py.allow_threads(|| {
+        block_on(async {
+            let consumer = Consumer::connect(target).await?;
+            consumer.express(&interest).await
+        })
+    })?;
+    match reply {
+        Reply::Data(data) => Ok(PyData::new(py, data)),
+        Reply::Nack(reason) => Err(NackError::new_err(match NackReason::from_u8(reason) {
+            Some(reason) => format!("{reason:?}"),
+            None => format!("reason {reason}"),
+        })),
+    }
+}
+
+/// Answer Interests under `prefix` on the UDP address `bind` until interrupted. `handler` is
+/// called with each `Interest` and returns the content as `bytes`, or `None` to send a NoData
+/// Nack.
+#[pyfunction]
+#[pyo3(signature = (prefix, bind, handler, freshness_period_ms=None))]
+fn serve(
+    py: Python<'_>,
+    prefix: &str,
+    bind: &str,
+    handler: PyObject,
+    freshness_period_ms: Option<u64>,
+) -> PyResult<()> {
+    let prefix = parse_name(prefix)?;
+    let runtime = runtime()?;
+    let producer = runtime.block_on(Producer::bind(bind)).map_err(to_py_err)?;
+
+    loop {
+        py.check_signals()?;
+        let received = py.allow_threads(|| {
+            runtime.block_on(async {
+                tokio::time::timeout(SIGNAL_CHECK_INTERVAL, producer.recv()).await.ok()
+            })
+        });
+        let Some(received) = received else {
+            continue;
+        };
+        let (interest, addr) = received.map_err(to_py_err)?;
+
+        let content: Option<Vec<u8>> = if prefix.is_prefix_of(&interest.name) {
+            let py_interest = PyInterest {
+                name: interest.name.to_string(),
+                nonce: interest.nonce,
+                can_be_prefix: interest.can_be_prefix,
+                must_be_fresh: interest.must_be_fresh,
+                lifetime_ms: interest.lifetime_ms,
+            };
+            handler.call1(py, (py_interest,))?.extract(py)?
+        } else {
+            None
+        };
+        let data = content.map(|content| {
+            let mut builder = Data::builder().name(interest.name.clone()).content(content);
+            if let Some(freshness_period_ms) = freshness_period_ms {
+                builder = builder.freshness_period_ms(freshness_period_ms);
+            }
+            builder.build()
+        });
+
+        py.allow_threads(|| runtime.block_on(producer.reply(&interest, addr, data.as_ref())))
+            .map_err(to_py_err)?;
+    }
+}
+
+/// The datapath counters of the running daemon, as a dict.
+#[pyfunction]
+fn stats(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+    let stats = py.allow_threads(|| block_on(control::stats()))?;
+    let dict = PyDict::new(py);
+    dict.set_item("interest_received", stats.interest_received)?;
+    dict.set_item("data_received", stats.data_received)?;
+    dict.set_item("cache_hits", stats.cache_hits)?;
+    dict.set_item("cache_misses", stats.cache_misses)?;
+    dict.set_item("pit_hits", stats.pit_hits)?;
+    dict.set_item("forwards", stats.forwards)?;
+    let drops = PyDict::new(py);
+    for reason in DropReason::ALL {
+        drops.set_item(reason.as_str(), stats.drops[reason as usize])?;
+    }
+    dict.set_item("drops", drops)?;
+    dict.set_item("negative_cache_hits", stats.negative_cache_hits)?;
+    dict.set_item("duplicate_interests", stats.duplicate_interests)?;
+    dict.set_item("cs_admission_rejects", stats.cs_admission_rejects)?;
+    dict.set_item("fresh_hits", stats.fresh_hits)?;
+    dict.set_item("stale_hits", stats.stale_hits)?;
+    dict.set_item("uncacheable", stats.uncacheable)?;
+    dict.set_item("prefetch_hits", stats.prefetch_hits)?;
+    dict.set_item("cs_probation_hits", stats.cs_probation_hits)?;
+    dict.set_item("cs_protected_hits", stats.cs_protected_hits)?;
+    dict.set_item("unroutable_nacks", stats.unroutable_nacks)?;
+    Ok(dict)
+}
+
+/// Register `name` with the daemon's name table and return its hash.
+#[pyfunction]
+fn register_name(py: Python<'_>, name: &str) -> PyResult<u32> {
+    let name = parse_name(name)?;
+    py.allow_threads(|| block_on(control::register(&name)))
+}
+
+/// Route Interests under `prefix` upstream, until the daemon stops if `ephemeral`.
+#[pyfunction]
+#[pyo3(signature = (prefix, ephemeral=false))]
+fn route_add(py: Python<'_>, prefix: &str, ephemeral: bool) -> PyResult<()> {
+    let prefix = parse_name(prefix)?;
+    py.allow_threads(|| block_on(control::route_add(&prefix, ephemeral)))?;
+    Ok(())
+}
+
+/// Stop routing Interests under `prefix`.
+#[pyfunction]
+fn route_remove(py: Python<'_>, prefix: &str) -> PyResult<()> {
+    let prefix = parse_name(prefix)?;
+    py.allow_threads(|| block_on(control::route_remove(&prefix)))?;
+    Ok(())
+}
+
+/// The routed prefixes, as `(prefix, sources)` tuples, the sources being where each route comes
+/// from: `"config"`, `"saved"` or `"ephemeral"`.
+#[pyfunction]
+fn route_list(py: Python<'_>) -> PyResult<Vec<(String, Vec<String>)>> {
+    let routes = py.allow_threads(|| block_on(control::routes()))?;
+    Ok(routes
+        .into_iter()
+        .map(|(prefix, sources)| (prefix.to_string(), sources))
+        .collect())
+}
+
+/// Send a raw control-socket request, e.g. `"pit-list"`, and return the response text.
+#[pyfunction]
+fn control_request(py: Python<'_>, request: &str) -> PyResult<String> {
+    py.allow_threads(|| block_on(control::request(request)))
+}
+
+#[pyfunction]
+fn name_hash(name: &str) -> PyResult<u32> {
+    Ok(parse_name(name)?.name_hash())
+}
+
+#[pyfunction]
+fn canonical_name(name: &str) -> PyResult<String> {
+    Ok(parse_name(name)?.to_string())
+}
+
+#[pymodule]
+fn udcn(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("NackError", m.py().get_type::<NackError>())?;
+    m.add("UdcnError", m.py().get_type::<UdcnError>())?;
+    m.add_class::<PyInterest>()?;
+    m.add_class::<PyData>()?;
+    m.add_function(wrap_pyfunction!(express_interest, m)?)?;
+    m.add_function(wrap_pyfunction!(serve, m)?)?;
+    m.add_function(wrap_pyfunction!(stats, m)?)?;
+    m.add_function(wrap_pyfunction!(register_name, m)?)?;
+    m.add_function(wrap_pyfunction!(route_add, m)?)?;
+    m.add_function(wrap_pyfunction!(route_remove, m)?)?;
+    m.add_function(wrap_pyfunction!(route_list, m)?)?;
+    m.add_function(wrap_pyfunction!(control_request, m)?)?;
+    m.add_function(wrap_pyfunction!(name_hash, m)?)?;
+    m.add_function(wrap_pyfunction!(canonical_name, m)?)?;
+    Ok(())
+}
+
+fn parse_name(uri: &str) -> PyResult<Name> {
+    Name::from_uri(uri).map_err(|e| PyValueError::new_err(format!("invalid name {uri:?}: {e}")))
+}
+
+fn runtime() -> PyResult<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| UdcnError::new_err(e.to_string()))
+}
+
+fn block_on<T>(future: impl Future<Output = anyhow::Result<T>>) -> PyResult<T> {
+    runtime()?.block_on(future).map_err(to_py_err)
+}
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    UdcnError::new_err(format!("{e:#}"))
+}
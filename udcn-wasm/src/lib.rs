@@ -0,0 +1,242 @@
+//! Browser bindings for encoding and decoding µDCN packets.
+//!
+//! Build with `wasm-pack build udcn-wasm --target web`. Names cross the boundary as NDN URIs
+//! and packets as `Uint8Array`s, so a page can talk to the daemon's gateway with the same wire
+//! format the native tools use.
+
+use udcn_common::{Data, Interest, MetaInfo, Name};
+use wasm_bindgen::prelude::*;
+
+/// Decoded Interest fields. Times are in milliseconds.
+#[wasm_bindgen]
+pub struct DecodedInterest {
+    name: String,
+    nonce: u32,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl DecodedInterest {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn nonce(&self) -> u32 {
+        self.nonce
+    }
+
+    #[wasm_bindgen(getter, js_name = canBePrefix)]
+    pub fn can_be_prefix(&self) -> bool {
+        self.can_be_prefix
+    }
+
+    #[wasm_bindgen(getter, js_name = mustBeFresh)]
+    pub fn must_be_fresh(&self) -> bool {
+        self.must_be_fresh
+    }
+
+    #[wasm_bindgen(getter, js_name = lifetimeMs)]
+    pub fn lifetime_ms(&self) -> Option<u32> {
+        self.lifetime_ms
+    }
+}
+
+/// Decoded Data fields. Times are in milliseconds.
+#[wasm_bindgen]
+pub struct DecodedData {
+    name: String,
+    content: Vec<u8>,
+    content_type: Option<u32>,
+    freshness_period_ms: Option<u32>,
+    signature_value: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl DecodedData {
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn content(&self) -> Vec<u8> {
+        self.content.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = contentType)]
+    pub fn content_type(&self) -> Option<u32> {
+        self.content_type
+    }
+
+    #[wasm_bindgen(getter, js_name = freshnessPeriodMs)]
+    pub fn freshness_period_ms(&self) -> Option<u32> {
+        self.freshness_period_ms
+    }
+
+    #[wasm_bindgen(getter, js_name = signatureValue)]
+    pub fn signature_value(&self) -> Vec<u8> {
+        self.signature_value.clone()
+    }
+}
+
+/// Encode an Interest for `name`.
+///
+/// std has no entropy source on `wasm32-unknown-unknown`, so the caller supplies the nonce,
+/// typically from `crypto.getRandomValues`.
+#[wasm_bindgen(js_name = encodeInterest)]
+pub fn encode_interest(
+    name: &str,
+    nonce: u32,
+    can_be_prefix: bool,
+    must_be_fresh: bool,
+    lifetime_ms: Option<u32>,
+) -> Result<Vec<u8>, JsError> {
+    let interest = Interest {
+        name: Name::from_uri(name)?,
+        nonce,
+        can_be_prefix,
+        must_be_fresh,
+        lifetime_ms: lifetime_ms.map(u64::from),
+        forwarding_hint: Vec::new(),
+        application_parameters: None,
+    };
+    Ok(interest.encode())
+}
+
+#[wasm_bindgen(js_name = decodeInterest)]
+pub fn decode_interest(packet: &[u8]) -> Result<DecodedInterest, JsError> {
+    let interest = Interest::decode(packet).ok_or_else(|| JsError::new("malformed Interest"))?;
+    Ok(DecodedInterest {
+        name: interest.name.to_string(),
+        nonce: interest.nonce,
+        can_be_prefix: interest.can_be_prefix,
+        must_be_fresh: interest.must_be_fresh,
+        lifetime_ms: interest.lifetime_ms.map(saturate),
+    })
+}
+
+/// Encode an unsigned Data packet.
+#[wasm_bindgen(js_name = encodeData)]
+pub fn encode_data(
+    name: &str,
+    content: &[u8],
+    freshness_period_ms: Option<u32>,
+) -> Result<Vec<u8>, JsError> {
+    if content.len() > u16::MAX as usize {
+        return Err(JsError::new("Data content exceeds 64 KiB"));
+    }
+    let data = Data {
+        name: Name::from_uri(name)?,
+        meta: MetaInfo {
+            content_type: None,
+            freshness_period_ms: freshness_period_ms.map(u64::from),
+        },
+        content: content.to_vec(),
+        signature_info: None,
+        signature_value: Vec::new(),
+    };
+    Ok(data.encode())
+}
+
+#[wasm_bindgen(js_name = decodeData)]
+pub fn decode_data(packet: &[u8]) -> Result<DecodedData, JsError> {
+    let data = Data::decode(packet).ok_or_else(|| JsError::new("malformed Data"))?;
+    Ok(DecodedData {
+        name: data.name.to_string(),
+        content: data.content,
+        content_type: data.meta.content_type.map(saturate),
+        freshness_period_ms: data.meta.freshness_period_ms.map(saturate),
+        signature_value: data.signature_value,
+    })
+}
+
+/// The hash the datapath keys its tables with for `name`.
+#[wasm_bindgen(js_name = nameHash)]
+pub fn name_hash(name: &str) -> Result<u32, JsError> {
+    Ok(Name::from_uri(name)?.name_hash())
+}
+
+/// The canonical URI form of `name`.
+#[wasm_bindgen(js_name = canonicalName)]
+pub fn canonical_name(name: &str) -> Result<String, JsError> {
+    Ok(Name::from_uri(name)?.to_string())
+}
+
+// JavaScript numbers can't hold every u64, and no protocol value here comes close to u32::MAX
+fn saturate(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only the success paths run natively: building a JsError needs a JavaScript host
+
+    #[test]
+    fn test_interest_roundtrip() {
+        let packet = encode_interest("/video/seg=1", 0xdead_beef, true, true, Some(4000)).unwrap();
+        let interest = decode_interest(&packet).unwrap();
+        assert_eq!(interest.name(), "/video/seg=1");
+        assert_eq!(interest.nonce(), 0xdead_beef);
+        assert!(interest.can_be_prefix());
+        assert!(interest.must_be_fresh());
+        assert_eq!(interest.lifetime_ms(), Some(4000));
+
+        let native = Interest::decode(&packet).unwrap();
+        assert_eq!(native.name, Name::from("/video/seg=1"));
+        assert_eq!(native.lifetime_ms, Some(4000));
+
+        let packet = encode_interest("/video", 1, false, false, None).unwrap();
+        let interest = decode_interest(&packet).unwrap();
+        assert!(!interest.can_be_prefix());
+        assert!(!interest.must_be_fresh());
+        assert_eq!(interest.lifetime_ms(), None);
+    }
+
+    #[test]
+    fn test_data_roundtrip() {
+        let packet = encode_data("/video/seg=1", b"frame", Some(1000)).unwrap();
+        let data = decode_data(&packet).unwrap();
+        assert_eq!(data.name(), "/video/seg=1");
+        assert_eq!(data.content(), b"frame");
+        assert_eq!(data.content_type(), None);
+        assert_eq!(data.freshness_period_ms(), Some(1000));
+        assert!(data.signature_value().is_empty());
+
+        let native = Data::decode(&packet).unwrap();
+        assert_eq!(native.content, b"frame");
+    }
+
+    #[test]
+    fn test_decode_natively_built_data() {
+        let packet = Data::builder()
+            .name("/video/seg=1")
+            .content(&b"frame"[..])
+            .content_type(2)
+            .freshness_period_ms(u64::MAX)
+            .signature_info(0, None)
+            .sign_with(|_| vec![7; 32])
+            .build()
+            .encode();
+        let data = decode_data(&packet).unwrap();
+        assert_eq!(data.content_type(), Some(2));
+        assert_eq!(data.freshness_period_ms(), Some(u32::MAX));
+        assert_eq!(data.signature_value(), vec![7; 32]);
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            name_hash("/video/seg=1").unwrap(),
+            Name::from("/video/seg=1").name_hash()
+        );
+        assert_eq!(canonical_name("/video/%73eg").unwrap(), "/video/seg");
+        assert_eq!(saturate(u64::MAX), u32::MAX);
+        assert_eq!(saturate(5), 5);
+    }
+}
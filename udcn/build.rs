@@ -1,3 +1,5 @@
+use std::env;
+
 use anyhow::{anyhow, Context as _};
 use aya_build::cargo_metadata;
 
@@ -10,5 +12,12 @@ fn main() -> anyhow::Result<()> {
         .into_iter()
         .find(|cargo_metadata::Package { name, .. }| name == "udcn-ebpf")
         .ok_or_else(|| anyhow!("udcn-ebpf package not found"))?;
+    // The eBPF object is built by a nested cargo for bpfel/bpfeb, whichever matches the target's
+    // endianness. Flags meant for the target being cross-compiled to (linkers, target features)
+    // would otherwise leak into that build and break it.
+    for key in ["CARGO_ENCODED_RUSTFLAGS", "RUSTFLAGS", "CARGO_BUILD_TARGET"] {
+        println!("cargo:rerun-if-env-changed={key}");
+        env::remove_var(key);
+    }
     aya_build::build_ebpf([ebpf_package])
 }
@@ -0,0 +1,263 @@
+//! Who may change the daemon over the control socket, and an audit log of who did.
+//!
+//! Requests that only read the daemon's state answer anyone who can open the socket. Requests that
+//! change it, the ones the journal records, and those that hand out packets or cached content
+//! (`capture` and `state-export payloads`) are only taken from root, the daemon's own user,
+//! members of `--group`, and the users and groups the `[control]` section allows. The kernel
+//! vouches for the peer's uid, gid and pid (SO_PEERCRED) and its supplementary groups
+//! (SO_PEERGROUPS), as they were when it connected. With `audit_log`, each such request, taken or
+//! refused, is appended as a `<unix ms> uid=<uid> gid=<gid> pid=<pid> allowed|denied <request>`
+//! line.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    os::fd::AsRawFd as _,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use log::warn;
+use serde::Deserialize;
+use tokio::net::UnixStream;
+
+use crate::journal;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Users, besides root and the daemon's own, who may change the daemon.
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+    /// Groups, besides `--group`, whose members may change the daemon.
+    #[serde(default)]
+    pub allowed_gids: Vec<u32>,
+    /// Where to log changing requests and who sent them; not logged without this.
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+}
+
+/// The process at the other end of a control connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peer {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+    /// Supplementary groups, or none if they could not be read.
+    pub groups: Vec<u32>,
+}
+
+impl Peer {
+    pub fn of(stream: &UnixStream) -> anyhow::Result<Self> {
+        let cred = stream
+            .peer_cred()
+            .context("failed to read the peer's credentials")?;
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+            groups: peer_groups(stream),
+        })
+    }
+}
+
+/// The peer's supplementary groups from SO_PEERGROUPS, or none if the kernel does not say.
+fn peer_groups(stream: &UnixStream) -> Vec<u32> {
+    let mut groups = vec![0u32; 32];
+    loop {
+        let mut len = (groups.len() * size_of::<u32>()) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        let needed = len as usize / size_of::<u32>();
+        if ret == 0 {
+            groups.truncate(needed);
+            return groups;
+        }
+        // ERANGE says how large the buffer needs to be
+        let too_small = std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE);
+        if !too_small || needed <= groups.len() {
+            return Vec::new();
+        }
+        groups.resize(needed, 0);
+    }
+}
+
+/// Whether `request` is only taken from allowed peers: it changes the daemon, or hands out
+/// sampled packets or cached content.
+fn restricted(request: &str) -> bool {
+    let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+    journal::mutates(request)
+        || command == "capture"
+        || (command == "state-export" && argument == "payloads")
+}
+
+pub struct Access {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+    audit: Option<Mutex<(PathBuf, File)>>,
+}
+
+impl Access {
+    /// Access as `config` allows, besides for root, `own_uid` and members of `group`. The audit
+    /// log is opened here, while the daemon still may.
+    pub fn new(config: &ControlConfig, own_uid: u32, group: Option<u32>) -> anyhow::Result<Self> {
+        let mut uids = vec![0, own_uid];
+        uids.extend(&config.allowed_uids);
+        let mut gids: Vec<u32> = group.into_iter().collect();
+        gids.extend(&config.allowed_gids);
+        let audit = match &config.audit_log {
+            Some(path) => Some(Mutex::new((path.clone(), open(path)?))),
+            None => None,
+        };
+        Ok(Self { uids, gids, audit })
+    }
+
+    /// Whether `peer` may change the daemon.
+    pub fn allows(&self, peer: &Peer) -> bool {
+        self.uids.contains(&peer.uid)
+            || std::iter::once(&peer.gid)
+                .chain(&peer.groups)
+                .any(|gid| self.gids.contains(gid))
+    }
+
+    /// Refuse `request` if it is restricted and `peer` may not send it, auditing it either way.
+    pub fn authorize(&self, peer: &Peer, request: &str) -> anyhow::Result<()> {
+        if !restricted(request) {
+            return Ok(());
+        }
+        let allowed = self.allows(peer);
+        if let Some(audit) = &self.audit {
+            let mut audit = audit.lock().unwrap();
+            let (path, file) = &mut *audit;
+            if let Err(e) = writeln!(file, "{}", audit_line(now_ms(), peer, allowed, request)) {
+                warn!("failed to audit '{request}' to {}: {e}", path.display());
+            }
+        }
+        anyhow::ensure!(
+            allowed,
+            "uid {} is not allowed to send this request; see the [control] section",
+            peer.uid
+        );
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open audit log {}", path.display()))
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn audit_line(at_ms: u128, peer: &Peer, allowed: bool, request: &str) -> String {
+    let pid = peer.pid.map_or("-".to_string(), |pid| pid.to_string());
+    let verdict = match allowed {
+        true => "allowed",
+        false => "denied",
+    };
+    format!(
+        "{at_ms} uid={} gid={} pid={pid} {verdict} {request}",
+        peer.uid, peer.gid
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_allowed_peers_change_the_daemon() {
+        let path = std::env::temp_dir().join(format!("udcn-audit-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ControlConfig {
+            allowed_uids: vec![1001],
+            allowed_gids: vec![50],
+            audit_log: Some(path.clone()),
+        };
+        let access = Access::new(&config, 999, Some(40)).unwrap();
+        let peer = |uid, gid, groups: &[u32]| Peer {
+            uid,
+            gid,
+            pid: Some(4242),
+            groups: groups.to_vec(),
+        };
+
+        for allowed in [
+            peer(0, 0, &[]),
+            peer(999, 999, &[]),
+            peer(1001, 1001, &[]),
+            peer(1002, 40, &[]),
+            peer(1002, 1002, &[27, 50]),
+        ] {
+            assert!(access.allows(&allowed), "{allowed:?}");
+        }
+        let stranger = peer(1002, 1002, &[27]);
+        assert!(!access.allows(&stranger));
+
+        // Reading is open to anyone who can open the socket
+        access.authorize(&stranger, "stats").unwrap();
+        access.authorize(&stranger, "fault").unwrap();
+        access.authorize(&stranger, "state-export").unwrap();
+        assert!(access
+            .authorize(&stranger, "state-export payloads")
+            .is_err());
+        assert!(access.authorize(&stranger, "capture").is_err());
+        let e = access.authorize(&stranger, "cs-flush").unwrap_err();
+        assert!(e.to_string().starts_with("uid 1002 is not allowed"));
+        access
+            .authorize(&peer(1001, 1001, &[]), "register /video")
+            .unwrap();
+
+        let audit = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = audit
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "uid=1002 gid=1002 pid=4242 denied state-export payloads",
+                "uid=1002 gid=1002 pid=4242 denied capture",
+                "uid=1002 gid=1002 pid=4242 denied cs-flush",
+                "uid=1001 gid=1001 pid=4242 allowed register /video"
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_groups_come_from_the_kernel() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let peer = Peer::of(&ours).unwrap();
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+        let mut groups = vec![0; 256];
+        let len = unsafe { libc::getgroups(groups.len() as i32, groups.as_mut_ptr()) };
+        groups.truncate(len as usize);
+        let mut expected = groups;
+        let mut got = peer.groups;
+        expected.sort_unstable();
+        got.sort_unstable();
+        assert_eq!(got, expected);
+        drop(theirs);
+    }
+}
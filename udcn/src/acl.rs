@@ -0,0 +1,124 @@
+//! Prefix ACLs: names the datapath drops before it looks at the PIT or the content store, so an
+//! operator can shut out an abusive namespace at line rate.
+//!
+//! The `[acl]` section of the configuration file lists rules that allow or deny a prefix of up to
+//! four components, on every face or on the one a UDP peer sends from. The rule for the longest
+//! prefix of a packet's name decides, a face's rule winning over one for every face at the same
+//! length. A name no rule matches is let through, unless `default = "deny"` turns the rules into
+//! an allow list. Interests and Data are checked; Nacks, which carry no name, pass. Data with more
+//! than 2047 bytes of content, whose name XDP cannot reach, is dropped while any rule is defined.
+//! Dropped packets count as `acl_dropped`.
+
+use std::net::SocketAddrV4;
+
+use aya::maps::HashMap;
+use serde::Deserialize;
+use udcn_common::{udp_face_id, AclAction, AclKey, Name, MAX_ACL_RULES};
+
+use crate::nocache::NoCachePrefix;
+
+/// The `[acl]` section of the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclConfig {
+    /// What happens to names no rule matches.
+    #[serde(default)]
+    pub default: AclAction,
+    #[serde(default)]
+    pub rules: Vec<AclRule>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclRule {
+    pub prefix: Name,
+    pub action: AclAction,
+    /// The UDP peer whose packets the rule applies to; those of every face without it.
+    pub face: Option<SocketAddrV4>,
+}
+
+impl AclRule {
+    fn key(&self) -> anyhow::Result<AclKey> {
+        let face_id = self.face.map_or(0, |face| {
+            udp_face_id(face.ip().octets(), face.port().to_be_bytes())
+        });
+        let prefix = NoCachePrefix::new(self.prefix.clone())?.key();
+        Ok(AclKey { face_id, prefix })
+    }
+}
+
+/// Check that the datapath can hold and look up `acl`'s rules, and that no two clash.
+pub fn validate(acl: &AclConfig) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        acl.rules.len() <= MAX_ACL_RULES as usize,
+        "at most {MAX_ACL_RULES} ACL rules can be defined"
+    );
+    anyhow::ensure!(
+        acl.default == AclAction::Allow || !acl.rules.is_empty(),
+        "an ACL that denies by default needs rules allowing something"
+    );
+    for (i, rule) in acl.rules.iter().enumerate() {
+        rule.key()
+            .map_err(|e| anyhow::anyhow!("invalid prefix for an ACL rule: {e}"))?;
+        anyhow::ensure!(
+            acl.rules[..i]
+                .iter()
+                .all(|other| (&other.prefix, other.face) != (&rule.prefix, rule.face)),
+            "two ACL rules are for '{}'{}",
+            rule.prefix,
+            rule.face
+                .map_or(String::new(), |face| format!(" from {face}"))
+        );
+    }
+    Ok(())
+}
+
+/// Tell the datapath the rules.
+pub fn install(ebpf: &mut aya::Ebpf, acl: &AclConfig) -> anyhow::Result<()> {
+    validate(acl)?;
+    let mut map: HashMap<_, AclKey, u8> = HashMap::try_from(ebpf.map_mut("ACL").unwrap())?;
+    for rule in &acl.rules {
+        map.insert(rule.key()?, rule.action as u8, 0)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_are_keyed_by_face_and_prefix() {
+        let acl: AclConfig = toml::from_str(
+            "default = \"deny\"\n\n\
+             [[rules]]\nprefix = \"/video\"\naction = \"allow\"\n\n\
+             [[rules]]\nprefix = \"/video\"\naction = \"deny\"\nface = \"192.0.2.7:6363\"\n",
+        )
+        .unwrap();
+        validate(&acl).unwrap();
+        assert_eq!(acl.default, AclAction::Deny);
+        let (every, one) = (acl.rules[0].key().unwrap(), acl.rules[1].key().unwrap());
+        assert_eq!((every.face_id, every.prefix), (0, one.prefix));
+        assert_eq!(
+            one.face_id,
+            udp_face_id([192, 0, 2, 7], 6363u16.to_be_bytes())
+        );
+
+        let twice = AclConfig {
+            rules: vec![acl.rules[0].clone(), acl.rules[0].clone()],
+            ..acl.clone()
+        };
+        assert!(validate(&twice).is_err());
+        let long = AclConfig {
+            rules: vec![AclRule {
+                prefix: Name::from("/a/b/c/d/e"),
+                ..acl.rules[0].clone()
+            }],
+            ..acl
+        };
+        assert!(validate(&long).is_err());
+        assert!(
+            toml::from_str::<AclConfig>("[[rules]]\nprefix = \"/a\"\naction = \"drop\"\n").is_err()
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Alarm thresholds on the datapath counters and tables, evaluated every second.
+//!
+//! Each `[[alarms]]` entry of the configuration file compares one metric, in percent, against a
+//! threshold. Ratios of counters (`hit_ratio`, `drop_ratio`) are taken over the last
+//! `window_secs`; occupancies (`pit_occupancy`, `cs_occupancy`) are the latest reading. An alarm
+//! logs a warning when it starts firing and a note when it stops, and the control socket answers
+//! `alarms` with where each one stands, which `udcn health` turns into its exit code.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+use udcn_client::control::{AlarmStatus, TableOccupancy};
+use udcn_common::{PacketStats, CS_PARTITION_MAPS};
+
+use crate::events::Clock;
+
+/// How often the daemon evaluates alarms.
+pub const EVALUATION_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Content store hits out of the Interests that looked it up.
+    HitRatio,
+    /// Dropped packets out of the Interests and Data received.
+    DropRatio,
+    PitOccupancy,
+    /// Entries in every content store partition out of their combined capacity.
+    CsOccupancy,
+}
+
+impl Metric {
+    fn is_ratio(self) -> bool {
+        matches!(self, Self::HitRatio | Self::DropRatio)
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::HitRatio => "hit_ratio",
+            Self::DropRatio => "drop_ratio",
+            Self::PitOccupancy => "pit_occupancy",
+            Self::CsOccupancy => "cs_occupancy",
+        })
+    }
+}
+
+/// One `[[alarms]]` entry of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmConfig {
+    /// Defaults to the metric.
+    pub name: Option<String>,
+    pub metric: Metric,
+    /// Fire while the metric is below this percentage...
+    pub below: Option<f64>,
+    /// ...or above this one.
+    pub above: Option<f64>,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+impl AlarmConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let name = self.name();
+        let threshold = match (self.below, self.above) {
+            (Some(threshold), None) | (None, Some(threshold)) => threshold,
+            _ => anyhow::bail!("alarm '{name}' needs exactly one of below and above"),
+        };
+        anyhow::ensure!(
+            (0.0..=100.0).contains(&threshold),
+            "alarm '{name}' threshold must be a percentage between 0 and 100"
+        );
+        anyhow::ensure!(
+            self.window_secs > 0,
+            "alarm '{name}' window_secs must be positive"
+        );
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.metric.to_string())
+    }
+
+    fn condition(&self) -> String {
+        let (comparison, threshold) = match (self.below, self.above) {
+            (Some(below), _) => ("below", below),
+            (_, above) => ("above", above.unwrap_or_default()),
+        };
+        let mut condition = format!("{} {comparison} {threshold}%", self.metric);
+        if self.metric.is_ratio() {
+            condition += &format!(" over {}s", self.window_secs);
+        }
+        condition
+    }
+
+    fn crossed(&self, value: f64) -> bool {
+        self.below.is_some_and(|below| value < below)
+            || self.above.is_some_and(|above| value > above)
+    }
+}
+
+struct Alarm {
+    config: AlarmConfig,
+    name: String,
+    value: Option<f64>,
+    firing: bool,
+    since_unix_ms: Option<u64>,
+}
+
+/// The configured alarms and the counter readings their windows need.
+#[derive(Default)]
+pub struct Alarms {
+    alarms: Vec<Alarm>,
+    samples: VecDeque<(Instant, PacketStats)>,
+}
+
+impl Alarms {
+    pub fn new(configs: Vec<AlarmConfig>) -> Self {
+        let alarms = configs
+            .into_iter()
+            .map(|config| Alarm {
+                name: config.name(),
+                config,
+                value: None,
+                firing: false,
+                since_unix_ms: None,
+            })
+            .collect();
+        Self {
+            alarms,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Take a reading at `now` and update every alarm, logging those that start or stop firing.
+    pub fn evaluate(&mut self, now: Instant, stats: PacketStats, tables: &[TableOccupancy]) {
+        let longest = self
+            .alarms
+            .iter()
+            .map(|alarm| Duration::from_secs(alarm.config.window_secs))
+            .max()
+            .unwrap_or_default();
+        self.samples.push_back((now, stats));
+        // Keep the newest reading at least `longest` old, as the start of the longest window
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(taken, _)| now.duration_since(*taken) >= longest)
+        {
+            self.samples.pop_front();
+        }
+
+        for index in 0..self.alarms.len() {
+            let value = self.value(&self.alarms[index].config, now, &stats, tables);
+            let alarm = &mut self.alarms[index];
+            alarm.value = value;
+            let firing = value.is_some_and(|value| alarm.config.crossed(value));
+            if firing == alarm.firing {
+                continue;
+            }
+            alarm.firing = firing;
+            alarm.since_unix_ms = Some(Clock::datapath().now_ns() / 1_000_000);
+            let value = value.map_or("n/a".to_string(), |value| format!("{value:.1}%"));
+            if firing {
+                warn!(
+                    "alarm '{}' firing: {} ({value})",
+                    alarm.name,
+                    alarm.config.condition()
+                );
+            } else {
+                info!("alarm '{}' cleared ({value})", alarm.name);
+            }
+        }
+    }
+
+    /// The metric `config` watches, or `None` if there is nothing to take a ratio of yet.
+    fn value(
+        &self,
+        config: &AlarmConfig,
+        now: Instant,
+        stats: &PacketStats,
+        tables: &[TableOccupancy],
+    ) -> Option<f64> {
+        let percent =
+            |part: u64, whole: u64| (whole > 0).then(|| part as f64 * 100.0 / whole as f64);
+        let window = Duration::from_secs(config.window_secs);
+        // The reading the window starts from: the newest at least a window old, else the oldest
+        let start = self
+            .samples
+            .iter()
+            .rev()
+            .find(|(taken, _)| now.duration_since(*taken) >= window)
+            .or(self.samples.front())
+            .map(|(_, stats)| stats)?;
+        let delta = |counter: fn(&PacketStats) -> u32| {
+            u64::from(counter(stats).wrapping_sub(counter(start)))
+        };
+
+        match config.metric {
+            Metric::HitRatio => {
+                let hits = delta(|stats| stats.cache_hits);
+                percent(hits, hits + delta(|stats| stats.cache_misses))
+            }
+            Metric::DropRatio => percent(
+                delta(PacketStats::total_drops),
+                delta(|stats| stats.interest_received) + delta(|stats| stats.data_received),
+            ),
+            Metric::PitOccupancy => {
+                let pit = tables.iter().find(|table| table.table == "PIT")?;
+                percent(pit.entries.into(), pit.capacity.into())
+            }
+            Metric::CsOccupancy => {
+                let (entries, capacity) = tables
+                    .iter()
+                    .filter(|table| {
+                        table.table == "CONTENT_STORE"
+                            || table.table == "CS_PROTECTED"
+                            || CS_PARTITION_MAPS.contains(&table.table.as_str())
+                    })
+                    .fold((0, 0), |(entries, capacity), table| {
+                        (
+                            entries + u64::from(table.entries),
+                            capacity + u64::from(table.capacity),
+                        )
+                    });
+                percent(entries, capacity)
+            }
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<AlarmStatus> {
+        self.alarms
+            .iter()
+            .map(|alarm| AlarmStatus {
+                name: alarm.name.clone(),
+                condition: alarm.config.condition(),
+                value: alarm.value,
+                firing: alarm.firing,
+                since_unix_ms: alarm.since_unix_ms,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm(toml: &str) -> AlarmConfig {
+        let config: AlarmConfig = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn test_hit_ratio_over_window() {
+        let mut alarms = Alarms::new(vec![alarm(
+            "metric = \"hit_ratio\"\nbelow = 20\nwindow_secs = 10\n",
+        )]);
+        let start = Instant::now();
+        let reading = |hits, misses| PacketStats {
+            cache_hits: hits,
+            cache_misses: misses,
+            ..Default::default()
+        };
+
+        // Nothing looked up yet: no ratio, no alarm
+        alarms.evaluate(start, reading(0, 0), &[]);
+        assert_eq!(alarms.statuses()[0].value, None);
+        alarms.evaluate(start + Duration::from_secs(5), reading(1, 9), &[]);
+        let status = &alarms.statuses()[0];
+        assert!(status.firing && status.since_unix_ms.is_some());
+        assert_eq!(status.condition, "hit_ratio below 20% over 10s");
+
+        // The window has moved past the misses
+        alarms.evaluate(start + Duration::from_secs(15), reading(11, 9), &[]);
+        alarms.evaluate(start + Duration::from_secs(16), reading(12, 9), &[]);
+        let status = &alarms.statuses()[0];
+        assert_eq!(status.value, Some(100.0));
+        assert!(!status.firing);
+    }
+
+    #[test]
+    fn test_occupancy_and_validation() {
+        let mut alarms = Alarms::new(vec![alarm("metric = \"pit_occupancy\"\nabove = 90\n")]);
+        let pit = TableOccupancy {
+            table: "PIT".to_string(),
+            entries: 950,
+            capacity: 1000,
+        };
+        alarms.evaluate(Instant::now(), PacketStats::default(), &[pit]);
+        assert!(alarms.statuses()[0].firing);
+        assert_eq!(alarms.statuses()[0].condition, "pit_occupancy above 90%");
+
+        let invalid: AlarmConfig = toml::from_str("metric = \"hit_ratio\"\n").unwrap();
+        assert!(invalid.validate().is_err());
+        let invalid: AlarmConfig = toml::from_str("metric = \"hit_ratio\"\nbelow = 120\n").unwrap();
+        assert!(invalid.validate().is_err());
+        assert!(toml::from_str::<AlarmConfig>("metric = \"rtt\"\nabove = 1\n").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Attaching the XDP program to interfaces that may already run another one.
+//!
+//! An interface with an XDP program is an error unless told otherwise, rather than having it
+//! silently replaced. `--force` detaches the program first. `--chain` puts µDCN in front of it:
+//! the displaced program goes into the datapath's `NEXT_PROGRAM` array, gets every packet µDCN
+//! lets through, and is attached again when the daemon exits. Either way, a displaced program
+//! is pinned first and attached again if µDCN then fails to attach.
+
+use std::{
+    ffi::CString,
+    fmt, fs,
+    os::{
+        fd::{AsFd as _, AsRawFd as _, BorrowedFd},
+        unix::ffi::OsStrExt as _,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context as _;
+use aya::{
+    maps::{HashMap, ProgramArray},
+    programs::{loaded_programs, xdp::XdpLinkId, ProgramFd, Xdp, XdpFlags},
+};
+use log::{info, warn};
+use udcn_common::MAX_CHAINED_PROGRAMS;
+
+use crate::netns::ip;
+
+/// What to do about an XDP program already attached to an interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Refuse to start.
+    Refuse,
+    /// Detach it.
+    Replace,
+    /// Run in front of it and hand it whatever µDCN passes.
+    Chain,
+}
+
+/// An XDP program found attached to an interface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Existing {
+    pub id: u32,
+    pub name: Option<String>,
+    /// The `ip link` keyword for the mode it is attached in, e.g. `xdpdrv`.
+    pub mode: &'static str,
+}
+
+impl fmt::Display for Existing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "'{name}' (id {}, {})", self.id, self.mode),
+            None => write!(f, "id {} ({})", self.id, self.mode),
+        }
+    }
+}
+
+/// The ifindex of `iface` and the XDP program attached to it, if any.
+pub fn existing_program(iface: &str) -> anyhow::Result<(u32, Option<Existing>)> {
+    let output = Command::new("ip")
+        .args(["-details", "-json", "link", "show", "dev", iface])
+        .output()
+        .context("failed to run ip")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "no interface {iface}: {}",
+        String::from_utf8_lossy(&output.stderr).trim_end()
+    );
+    parse_link(&output.stdout)
+}
+
+/// Parse `ip -details -json link show` output for a single interface.
+fn parse_link(json: &[u8]) -> anyhow::Result<(u32, Option<Existing>)> {
+    let links: serde_json::Value = serde_json::from_slice(json).context("invalid ip output")?;
+    let link = &links[0];
+    let ifindex = link["ifindex"]
+        .as_u64()
+        .context("ip output has no ifindex")? as u32;
+
+    // Programs attached in several modes at once are listed under `attached`
+    let xdp = &link["xdp"];
+    let attached = match xdp["attached"].as_array() {
+        Some(attached) => attached.first().unwrap_or(&serde_json::Value::Null),
+        None => xdp,
+    };
+    let Some(id) = attached["prog"]["id"].as_u64() else {
+        return Ok((ifindex, None));
+    };
+    let mode = match attached["mode"].as_u64() {
+        Some(2) => "xdpgeneric",
+        Some(3) => "xdpoffload",
+        _ => "xdpdrv",
+    };
+    let name = attached["prog"]["name"].as_str().map(str::to_string);
+    Ok((
+        ifindex,
+        Some(Existing {
+            id: id as u32,
+            name,
+            mode,
+        }),
+    ))
+}
+
+/// A program displaced from an interface, pinned so it can be attached again.
+struct Displaced {
+    iface: String,
+    existing: Existing,
+    pin: PathBuf,
+    /// Whether µDCN hands it packets, rather than having replaced it.
+    chained: bool,
+}
+
+/// The interfaces the program was attached to.
+pub struct Attached {
+    links: Vec<XdpLinkId>,
+    displaced: Vec<Displaced>,
+}
+
+/// Attach the `udcn` program to each of `ifaces`, dealing with programs already there according
+/// to `policy`. Displaced programs are pinned under `pin_dir`, and attached again if attaching
+/// fails.
+pub fn attach(
+    ebpf: &mut aya::Ebpf,
+    ifaces: &[String],
+    policy: Policy,
+    flags: XdpFlags,
+    pin_dir: &Path,
+) -> anyhow::Result<Attached> {
+    let mut attached = Attached {
+        links: Vec::new(),
+        displaced: Vec::new(),
+    };
+    if let Err(e) = attached.attach(ebpf, ifaces, policy, flags, pin_dir) {
+        attached.detach(ebpf);
+        return Err(e);
+    }
+    // Replaced programs are not coming back
+    attached.displaced.retain(|displaced| {
+        if !displaced.chained {
+            let _ = fs::remove_file(&displaced.pin);
+        }
+        displaced.chained
+    });
+    Ok(attached)
+}
+
+impl Attached {
+    fn attach(
+        &mut self,
+        ebpf: &mut aya::Ebpf,
+        ifaces: &[String],
+        policy: Policy,
+        flags: XdpFlags,
+        pin_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let mut slot = 0;
+        for iface in ifaces {
+            let (ifindex, Some(existing)) = existing_program(iface)? else {
+                continue;
+            };
+            if policy == Policy::Refuse {
+                anyhow::bail!(
+                    "{iface} already has XDP program {existing} attached; pass --chain to run \
+                     µDCN in front of it or --force to replace it"
+                );
+            }
+            let fd = program_fd(iface, &existing)?;
+            let chained = policy == Policy::Chain;
+            if chained {
+                anyhow::ensure!(
+                    slot < MAX_CHAINED_PROGRAMS,
+                    "at most {MAX_CHAINED_PROGRAMS} XDP programs can be chained"
+                );
+                chain(ebpf, iface, ifindex, slot, &existing, &fd)?;
+                slot += 1;
+            }
+            let pin = pin_existing(iface, &existing, &fd, pin_dir)?;
+            if let Err(e) = detach_existing(iface, &existing) {
+                let _ = fs::remove_file(&pin);
+                return Err(e);
+            }
+            match chained {
+                true => info!("Chaining µDCN in front of XDP program {existing} on {iface}"),
+                false => warn!("Replacing XDP program {existing} on {iface}"),
+            }
+            self.displaced.push(Displaced {
+                iface: iface.clone(),
+                existing,
+                pin,
+                chained,
+            });
+        }
+
+        let program: &mut Xdp = ebpf.program_mut("udcn").unwrap().try_into()?;
+        for iface in ifaces {
+            let link = program.attach(iface, flags).with_context(|| {
+                format!(
+                    "failed to attach the XDP program to {iface}; `udcn doctor` checks its \
+                     driver, and --xdp-mode generic works with any"
+                )
+            })?;
+            self.links.push(link);
+        }
+        Ok(())
+    }
+
+    /// Detach µDCN and put displaced programs back where they were.
+    pub fn detach(self, ebpf: &mut aya::Ebpf) {
+        let program: &mut Xdp = ebpf.program_mut("udcn").unwrap().try_into().unwrap();
+        for link in self.links {
+            if let Err(e) = program.detach(link) {
+                warn!("failed to detach the XDP program: {e}");
+            }
+        }
+        for displaced in self.displaced {
+            let args = format!(
+                "link set dev {} {} pinned {}",
+                displaced.iface,
+                displaced.existing.mode,
+                displaced.pin.display()
+            );
+            match ip(&args) {
+                Ok(()) => info!(
+                    "Reattached XDP program {} to {}",
+                    displaced.existing, displaced.iface
+                ),
+                Err(e) => warn!(
+                    "failed to reattach XDP program {} to {}: {e:#}",
+                    displaced.existing, displaced.iface
+                ),
+            }
+            let _ = fs::remove_file(&displaced.pin);
+        }
+    }
+}
+
+/// A file descriptor for `existing`, attached to `iface`.
+fn program_fd(iface: &str, existing: &Existing) -> anyhow::Result<ProgramFd> {
+    let info = loaded_programs()
+        .filter_map(Result::ok)
+        .find(|program| program.id() == existing.id)
+        .with_context(|| format!("XDP program {existing} on {iface} is gone"))?;
+    Ok(info.fd()?)
+}
+
+/// Hand the packets µDCN passes on `ifindex` to `existing`.
+fn chain(
+    ebpf: &mut aya::Ebpf,
+    iface: &str,
+    ifindex: u32,
+    slot: u32,
+    existing: &Existing,
+    fd: &ProgramFd,
+) -> anyhow::Result<()> {
+    let mut next: ProgramArray<_> = ProgramArray::try_from(ebpf.map_mut("NEXT_PROGRAM").unwrap())?;
+    next.set(slot, fd, 0).with_context(|| {
+        format!("XDP program {existing} on {iface} cannot be chained; use --force to replace it")
+    })?;
+    let mut slots: HashMap<_, u32, u32> = HashMap::try_from(ebpf.map_mut("CHAIN_SLOTS").unwrap())?;
+    slots.insert(ifindex, slot, 0)?;
+    Ok(())
+}
+
+/// Pin `existing` for attaching again.
+fn pin_existing(
+    iface: &str,
+    existing: &Existing,
+    fd: &ProgramFd,
+    pin_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let dir = pin_dir.join("displaced");
+    fs::create_dir_all(&dir)?;
+    let pin = dir.join(iface);
+    let _ = fs::remove_file(&pin);
+    pin_program(fd.as_fd(), &pin)
+        .with_context(|| format!("failed to pin XDP program {existing} to {}", pin.display()))?;
+    Ok(pin)
+}
+
+fn detach_existing(iface: &str, existing: &Existing) -> anyhow::Result<()> {
+    ip(&format!("link set dev {iface} {} off", existing.mode)).with_context(|| {
+        format!(
+            "failed to detach XDP program {existing} from {iface}; it may be held by a bpf_link \
+             of another process"
+        )
+    })
+}
+
+/// `BPF_OBJ_PIN`, which aya only offers for programs it loaded itself.
+pub fn pin_program(fd: BorrowedFd<'_>, path: &Path) -> std::io::Result<()> {
+    #[repr(C)]
+    struct ObjPinAttr {
+        pathname: u64,
+        bpf_fd: u32,
+        file_flags: u32,
+    }
+    const BPF_OBJ_PIN: libc::c_long = 6;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let attr = ObjPinAttr {
+        pathname: path.as_ptr() as u64,
+        bpf_fd: fd.as_raw_fd() as u32,
+        file_flags: 0,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_PIN,
+            &attr as *const ObjPinAttr,
+            std::mem::size_of::<ObjPinAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link() {
+        let bare = br#"[{"ifindex":3,"ifname":"eth0","flags":["UP"]}]"#;
+        assert_eq!(parse_link(bare).unwrap(), (3, None));
+
+        let single = br#"[{"ifindex":4,"ifname":"eth1","xdp":{"mode":1,"prog":{"id":42,"name":"xdp_fw","tag":"0123456789abcdef","jited":1}}}]"#;
+        assert_eq!(
+            parse_link(single).unwrap(),
+            (
+                4,
+                Some(Existing {
+                    id: 42,
+                    name: Some("xdp_fw".to_string()),
+                    mode: "xdpdrv",
+                })
+            )
+        );
+
+        let multi = br#"[{"ifindex":5,"xdp":{"mode":4,"attached":[{"mode":2,"prog":{"id":7}}]}}]"#;
+        let (_, existing) = parse_link(multi).unwrap();
+        assert_eq!(existing.unwrap().to_string(), "id 7 (xdpgeneric)");
+    }
+}
@@ -0,0 +1,342 @@
+//! Sizing the datapath maps from what the forwarder is expected to carry (`run --autosize`).
+//!
+//! Rather than entry counts, the operator declares targets: the Interests per second the PIT must
+//! hold for their lifetime, the memory the content store may take and the prefixes it must be
+//! able to route. The PIT gets twice the entries the rate keeps pending on average, for bursts;
+//! the content store and its Data cache get one entry each for every Data the budget pays for.
+//! The maps together may lock at most half the memory the daemon can lock, so that the host keeps
+//! the rest: on kernels that charge BPF memory to `RLIMIT_MEMLOCK`, its hard limit, and on others
+//! what the memory cgroup and the host have available. When the targets do not fit, the content
+//! store shrinks first, as a smaller cache only costs hits while a smaller PIT drops Interests.
+//!
+//! Costs are the kernel's hash map overhead plus each key and value, rounded up to 8 bytes; they
+//! leave out the per-CPU spare entries preallocated maps keep. Maps with a compiled-in size, such
+//! as `CS_PINNED`, the `CS_BLOOM` filter and the open-addressing PIT, keep it.
+
+use std::{fmt, fs, mem, path::Path};
+
+use udcn_common::{
+    CacheEntry, CachedPayload, PitEntry, PitLayout, CS_CAPACITY, DATA_CACHE_CAPACITY, PIT_CAPACITY,
+    ROUTES_CAPACITY,
+};
+
+use crate::health;
+
+/// Bytes the kernel keeps for each hash map entry besides its key and value.
+const ENTRY_OVERHEAD: u64 = 48;
+/// Share of the memory the daemon can lock that the maps may take.
+const MEMORY_PERCENT: u64 = 50;
+/// PIT entries for every Interest the rate keeps pending on average.
+const PIT_HEADROOM: u64 = 2;
+
+/// Entries in each map the daemon sizes when the program is loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapSizes {
+    /// The hash map PIT.
+    pub pit: u32,
+    /// The content store across all partitions and segments, leaving out `CS_PINNED`.
+    pub cs: u32,
+    pub data_cache: u32,
+    /// `ROUTES`, for `--nack-unroutable`.
+    pub routes: u32,
+}
+
+impl Default for MapSizes {
+    fn default() -> Self {
+        Self {
+            pit: PIT_CAPACITY,
+            cs: CS_CAPACITY,
+            data_cache: DATA_CACHE_CAPACITY,
+            routes: ROUTES_CAPACITY,
+        }
+    }
+}
+
+impl MapSizes {
+    /// Size the maps `loader` will create that neither the content store layout nor the PIT
+    /// layout does.
+    pub fn size_maps(&self, loader: &mut aya::EbpfLoader) {
+        loader.set_max_entries("DATA_CACHE", self.data_cache);
+        loader.set_max_entries("ROUTES", self.routes);
+    }
+}
+
+/// What `run --autosize` sizes the maps for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Targets {
+    /// Interests per second.
+    pub interest_rate: u32,
+    pub interest_lifetime_ms: u32,
+    /// Bytes the content store and its Data cache may take.
+    pub cs_bytes: u64,
+    pub routes: u32,
+}
+
+/// The sizes derived from some targets, and what they cost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sizing {
+    pub targets: Targets,
+    pub sizes: MapSizes,
+    pub pit_layout: PitLayout,
+    /// The memory the maps could take, if it could be found.
+    pub limit: Option<u64>,
+    /// Entries the content store budget paid for, before it was shrunk to fit `limit`.
+    pub cs_wanted: u32,
+}
+
+impl Sizing {
+    /// Size the maps for `targets`, with `available` bytes the daemon can lock.
+    pub fn derive(
+        targets: &Targets,
+        pit_layout: PitLayout,
+        available: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            targets.interest_rate > 0 && targets.interest_lifetime_ms > 0,
+            "--interest-rate and --interest-lifetime-ms must be positive"
+        );
+        let pending = u64::from(targets.interest_rate) * u64::from(targets.interest_lifetime_ms);
+        let pit = clamp((pending * PIT_HEADROOM).div_ceil(1000));
+        if pit_layout == PitLayout::OpenAddressing {
+            anyhow::ensure!(
+                pit <= PIT_CAPACITY,
+                "the open-addressing PIT holds {PIT_CAPACITY} entries, but {pit} are needed; use \
+                 --pit-layout hash-map"
+            );
+        }
+        let routes = targets.routes.max(1);
+        let cs_wanted = clamp(targets.cs_bytes / cs_entry_cost()).max(1);
+
+        let limit = available.map(|available| available * MEMORY_PERCENT / 100);
+        let mut sizes = MapSizes {
+            pit: if pit_layout == PitLayout::HashMap {
+                pit
+            } else {
+                PIT_CAPACITY
+            },
+            cs: cs_wanted,
+            data_cache: cs_wanted,
+            routes,
+        };
+        if let Some(limit) = limit {
+            let fixed = pit_bytes(&sizes) + routes_bytes(&sizes);
+            anyhow::ensure!(
+                fixed + cs_entry_cost() <= limit,
+                "the PIT and routes need {}, more than the {} the maps may take; lower \
+                 --interest-rate or raise the memory limit",
+                format_bytes(fixed),
+                format_bytes(limit)
+            );
+            let cs = clamp((limit - fixed) / cs_entry_cost()).min(cs_wanted);
+            sizes.cs = cs;
+            sizes.data_cache = cs;
+        }
+        Ok(Self {
+            targets: targets.clone(),
+            sizes,
+            pit_layout,
+            limit,
+            cs_wanted,
+        })
+    }
+
+    /// The bytes the sized maps take.
+    pub fn total_bytes(&self) -> u64 {
+        pit_bytes(&self.sizes)
+            + u64::from(self.sizes.cs) * cs_entry_cost()
+            + routes_bytes(&self.sizes)
+    }
+}
+
+impl fmt::Display for Sizing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Self { targets, sizes, .. } = self;
+        writeln!(
+            f,
+            "Maps sized for {} Interests/s over {} ms, a {} content store and {} routes:",
+            targets.interest_rate,
+            targets.interest_lifetime_ms,
+            format_bytes(targets.cs_bytes),
+            targets.routes
+        )?;
+        let pit = match self.pit_layout {
+            PitLayout::HashMap => format!("{:>10} entries", sizes.pit),
+            PitLayout::OpenAddressing => format!("{:>10} entries (fixed)", sizes.pit),
+        };
+        writeln!(
+            f,
+            "  {:<14} {pit:<24} {:>10}",
+            "PIT",
+            format_bytes(pit_bytes(sizes))
+        )?;
+        let cs_bytes = u64::from(sizes.cs) * cs_entry_cost();
+        for (map, entries, bytes) in [
+            (
+                "CONTENT_STORE",
+                sizes.cs,
+                cs_bytes - u64::from(sizes.cs) * payload_cost(),
+            ),
+            (
+                "DATA_CACHE",
+                sizes.data_cache,
+                u64::from(sizes.data_cache) * payload_cost(),
+            ),
+            ("ROUTES", sizes.routes, routes_bytes(sizes)),
+        ] {
+            let entries = format!("{entries:>10} entries");
+            writeln!(f, "  {map:<14} {entries:<24} {:>10}", format_bytes(bytes))?;
+        }
+        if sizes.cs < self.cs_wanted {
+            writeln!(
+                f,
+                "  The content store was shrunk from {} entries to fit",
+                self.cs_wanted
+            )?;
+        }
+        match self.limit {
+            Some(limit) => writeln!(
+                f,
+                "  {} of the {} the maps may take",
+                format_bytes(self.total_bytes()),
+                format_bytes(limit)
+            ),
+            None => writeln!(
+                f,
+                "  {}; the memory available could not be found",
+                format_bytes(self.total_bytes())
+            ),
+        }
+    }
+}
+
+/// A size in bytes, with an optional `K`, `M` or `G` suffix for binary multiples.
+pub fn parse_bytes(argument: &str) -> anyhow::Result<u64> {
+    let argument = argument.trim();
+    let size = argument.trim_end_matches("iB").trim_end_matches('B');
+    let (number, shift) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 30),
+        _ => (size, 0),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("expected a size such as 65536, 512K or 64M"))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow::anyhow!("{argument} is too large"))
+}
+
+/// The bytes the daemon can lock, before `MEMORY_PERCENT` is taken of them.
+pub fn available_memory() -> Option<u64> {
+    if !health::bpf_charged_to_memcg() {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // The daemon lifts the soft limit up to the hard one
+        if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } == 0
+            && limit.rlim_max != libc::RLIM_INFINITY
+        {
+            return Some(limit.rlim_max);
+        }
+    }
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let available_kb: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    let available = available_kb * 1024;
+    Some(cgroup_headroom().map_or(available, |headroom| headroom.min(available)))
+}
+
+/// What the process's cgroup v2 memory limit leaves, if it has one.
+fn cgroup_headroom() -> Option<u64> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let dir = Path::new("/sys/fs/cgroup").join(path.trim_start_matches('/'));
+    let read = |file| {
+        fs::read_to_string(dir.join(file))
+            .ok()?
+            .trim()
+            .parse::<u64>()
+            .ok()
+    };
+    // An unlimited cgroup's memory.max is `max`, which does not parse
+    Some(read("memory.max")?.saturating_sub(read("memory.current")?))
+}
+
+fn clamp(entries: u64) -> u32 {
+    entries.min(u64::from(u32::MAX)) as u32
+}
+
+fn entry_cost(key: usize, value: usize) -> u64 {
+    ENTRY_OVERHEAD + (key.next_multiple_of(8) + value.next_multiple_of(8)) as u64
+}
+
+fn payload_cost() -> u64 {
+    entry_cost(mem::size_of::<u32>(), mem::size_of::<CachedPayload>())
+}
+
+/// A content store entry and its Data cache payload.
+fn cs_entry_cost() -> u64 {
+    entry_cost(mem::size_of::<u32>(), mem::size_of::<CacheEntry>()) + payload_cost()
+}
+
+fn pit_bytes(sizes: &MapSizes) -> u64 {
+    u64::from(sizes.pit) * entry_cost(mem::size_of::<u32>(), mem::size_of::<PitEntry>())
+}
+
+fn routes_bytes(sizes: &MapSizes) -> u64 {
+    u64::from(sizes.routes) * entry_cost(mem::size_of::<u32>(), mem::size_of::<u8>())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..0x10_0000 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        0x10_0000..0x4000_0000 => format!("{:.1} MiB", bytes as f64 / f64::from(1 << 20)),
+        _ => format!("{:.1} GiB", bytes as f64 / f64::from(1 << 30)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_are_sized_from_targets_and_memory() {
+        let targets = Targets {
+            interest_rate: 10_000,
+            interest_lifetime_ms: 2_000,
+            cs_bytes: parse_bytes("32M").unwrap(),
+            routes: 16,
+        };
+        let sizing = Sizing::derive(&targets, PitLayout::HashMap, None).unwrap();
+        assert_eq!(sizing.sizes.pit, 40_000);
+        assert_eq!(sizing.sizes.cs, (32 << 20) / cs_entry_cost() as u32);
+        assert_eq!(sizing.sizes.data_cache, sizing.sizes.cs);
+        assert_eq!(sizing.sizes.routes, 16);
+        assert!(sizing.to_string().contains("40000 entries"));
+
+        // With little memory, the content store gives way and the PIT does not
+        let available = 2 * (pit_bytes(&sizing.sizes) + (8 << 20));
+        let tight = Sizing::derive(&targets, PitLayout::HashMap, Some(available)).unwrap();
+        assert_eq!(tight.sizes.pit, 40_000);
+        assert!(tight.sizes.cs < sizing.sizes.cs);
+        assert!(tight.total_bytes() <= available / 2);
+        assert!(tight.to_string().contains("shrunk"));
+        assert!(Sizing::derive(&targets, PitLayout::HashMap, Some(1 << 20)).is_err());
+        assert!(Sizing::derive(&targets, PitLayout::OpenAddressing, None).is_err());
+
+        assert_eq!(parse_bytes("65536").unwrap(), 65_536);
+        assert_eq!(parse_bytes("512KiB").unwrap(), 512 << 10);
+        assert_eq!(parse_bytes("1G").unwrap(), 1 << 30);
+        assert!(parse_bytes("lots").is_err());
+    }
+}
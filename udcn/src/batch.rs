@@ -0,0 +1,308 @@
+//! Batched access to the datapath's hash maps.
+//!
+//! Walking a map key by key costs a syscall or two per entry, so sweeping a full PIT or content
+//! store takes thousands of them. The `BPF_MAP_*_BATCH` commands (Linux 5.6) move up to
+//! `BATCH_SIZE` entries per syscall instead. On older kernels every operation falls back to the
+//! per-key calls. Each operation is timed, and the daemon answers `timings` with the totals, so
+//! `udcn timings` and the metrics exporter show what table management costs.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    mem::size_of,
+    os::fd::{AsFd as _, AsRawFd as _},
+    sync::Mutex,
+    time::Instant,
+};
+
+use aya::{
+    maps::{Array, HashMap, IterableMap as _, MapData},
+    Pod,
+};
+use udcn_client::control::OperationTiming;
+
+/// Entries moved per syscall.
+const BATCH_SIZE: usize = 1024;
+
+const BPF_MAP_LOOKUP_BATCH: libc::c_long = 24;
+const BPF_MAP_UPDATE_BATCH: libc::c_long = 26;
+const BPF_MAP_DELETE_BATCH: libc::c_long = 27;
+
+/// `ENOTSUPP`, which the kernel returns for map types without batch operations.
+const ENOTSUPP: i32 = 524;
+
+#[repr(C)]
+#[derive(Default)]
+struct BatchAttr {
+    in_batch: u64,
+    out_batch: u64,
+    keys: u64,
+    values: u64,
+    count: u32,
+    map_fd: u32,
+    elem_flags: u64,
+    flags: u64,
+}
+
+fn bpf(command: libc::c_long, attr: &mut BatchAttr) -> io::Result<()> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            command,
+            attr as *mut BatchAttr,
+            size_of::<BatchAttr>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether `e` means the kernel or map type has no batch operations, rather than that one failed.
+fn unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EINVAL | libc::EOPNOTSUPP | ENOTSUPP)
+    )
+}
+
+fn map_fd(map: &MapData) -> u32 {
+    map.fd().as_fd().as_raw_fd() as u32
+}
+
+/// Every entry of `map`, or `None` if the kernel cannot read it in batches.
+fn lookup_batch<K: Pod, V: Pod>(map: &MapData) -> io::Result<Option<Vec<(K, V)>>> {
+    let mut entries = Vec::new();
+    // Pod types are valid as all zeroes
+    let mut keys: Vec<K> = vec![unsafe { std::mem::zeroed() }; BATCH_SIZE];
+    let mut values: Vec<V> = vec![unsafe { std::mem::zeroed() }; BATCH_SIZE];
+    // Where the previous call stopped, for the next one to carry on from; hash maps use a u32
+    let mut tokens = [0u64; 2];
+    let mut first = true;
+    loop {
+        let mut attr = BatchAttr {
+            in_batch: if first {
+                0
+            } else {
+                &tokens[0] as *const u64 as u64
+            },
+            out_batch: &mut tokens[1] as *mut u64 as u64,
+            keys: keys.as_mut_ptr() as u64,
+            values: values.as_mut_ptr() as u64,
+            count: BATCH_SIZE as u32,
+            map_fd: map_fd(map),
+            ..Default::default()
+        };
+        let done = match bpf(BPF_MAP_LOOKUP_BATCH, &mut attr) {
+            Ok(()) => false,
+            // The last entries come with ENOENT
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => true,
+            Err(e) if first && unsupported(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let count = attr.count as usize;
+        entries.extend(
+            keys[..count]
+                .iter()
+                .copied()
+                .zip(values[..count].iter().copied()),
+        );
+        if done {
+            return Ok(Some(entries));
+        }
+        tokens[0] = tokens[1];
+        first = false;
+    }
+}
+
+/// Run `command` over `keys` (and `values`) a batch at a time, skipping keys it fails on with
+/// `skip` so that an entry the datapath removed meanwhile does not stop the rest. Returns how many
+/// succeeded, or `None` if the kernel has no batch operations.
+fn apply_batch<K: Pod, V: Pod>(
+    map: &MapData,
+    command: libc::c_long,
+    keys: &[K],
+    values: Option<&[V]>,
+    elem_flags: u64,
+    skip: i32,
+) -> io::Result<Option<usize>> {
+    let mut done = 0;
+    let mut applied = 0;
+    while done < keys.len() {
+        let count = (keys.len() - done).min(BATCH_SIZE);
+        let mut attr = BatchAttr {
+            keys: keys[done..].as_ptr() as u64,
+            values: values.map_or(0, |values| values[done..].as_ptr() as u64),
+            count: count as u32,
+            map_fd: map_fd(map),
+            elem_flags,
+            ..Default::default()
+        };
+        match bpf(command, &mut attr) {
+            Ok(()) => {
+                done += count;
+                applied += count;
+            }
+            Err(e) if e.raw_os_error() == Some(skip) => {
+                // `count` is how many went through before the one that failed
+                done += attr.count as usize + 1;
+                applied += attr.count as usize;
+            }
+            Err(e) if done == 0 && unsupported(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(applied))
+}
+
+/// Every entry of `map`.
+pub fn entries<K: Pod, V: Pod>(
+    map: &HashMap<MapData, K, V>,
+) -> anyhow::Result<(Vec<(K, V)>, bool)> {
+    if let Some(entries) = lookup_batch(map.map())? {
+        return Ok((entries, true));
+    }
+    let entries = map.iter().collect::<Result<_, _>>()?;
+    Ok((entries, false))
+}
+
+/// Every value of `map`, by index.
+pub fn values<V: Pod>(map: &Array<MapData, V>) -> anyhow::Result<(Vec<V>, bool)> {
+    if let Some(entries) = lookup_batch::<u32, V>(map.map())? {
+        return Ok((entries.into_iter().map(|(_, value)| value).collect(), true));
+    }
+    let values = map.iter().collect::<Result<_, _>>()?;
+    Ok((values, false))
+}
+
+/// Overwrite the entries of `map` that are still there with `entries`. Returns how many were.
+pub fn update_existing<K: Pod, V: Pod>(
+    map: &mut HashMap<MapData, K, V>,
+    entries: &[(K, V)],
+) -> anyhow::Result<(usize, bool)> {
+    /// `BPF_EXIST`: only update an entry that is still there.
+    const BPF_EXIST: u64 = 2;
+
+    let (keys, values): (Vec<K>, Vec<V>) = entries.iter().copied().unzip();
+    let batched = apply_batch(
+        map.map(),
+        BPF_MAP_UPDATE_BATCH,
+        &keys,
+        Some(&values),
+        BPF_EXIST,
+        libc::ENOENT,
+    )?;
+    if let Some(updated) = batched {
+        return Ok((updated, true));
+    }
+    let updated = entries
+        .iter()
+        .filter(|(key, value)| map.insert(key, value, BPF_EXIST).is_ok())
+        .count();
+    Ok((updated, false))
+}
+
+/// Remove `keys` from `map`, ignoring those already gone. Returns how many were removed.
+pub fn delete<K: Pod, V: Pod>(
+    map: &mut HashMap<MapData, K, V>,
+    keys: &[K],
+) -> anyhow::Result<(usize, bool)> {
+    let batched =
+        apply_batch::<K, V>(map.map(), BPF_MAP_DELETE_BATCH, keys, None, 0, libc::ENOENT)?;
+    if let Some(removed) = batched {
+        return Ok((removed, true));
+    }
+    let removed = keys.iter().filter(|key| map.remove(key).is_ok()).count();
+    Ok((removed, false))
+}
+
+/// How long each kind of table management has taken since the daemon started.
+#[derive(Default)]
+pub struct Timings {
+    operations: Mutex<BTreeMap<&'static str, OperationTiming>>,
+}
+
+impl Timings {
+    /// Record that `operation`, started at `start`, went through `entries` entries.
+    pub fn record(&self, operation: &'static str, start: Instant, entries: usize, batched: bool) {
+        let elapsed_us = start.elapsed().as_micros() as u64;
+        let mut operations = self.operations.lock().unwrap();
+        let timing = operations
+            .entry(operation)
+            .or_insert_with(|| OperationTiming {
+                operation: operation.to_string(),
+                ..Default::default()
+            });
+        timing.calls += 1;
+        timing.entries += entries as u64;
+        timing.batched = batched;
+        timing.last_us = elapsed_us;
+        timing.total_us += elapsed_us;
+    }
+
+    pub fn snapshot(&self) -> Vec<OperationTiming> {
+        self.operations.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// A line per operation, for `udcn timings`.
+pub fn format_timings(timings: &[OperationTiming]) -> String {
+    let mut out = format!(
+        "{:<18} {:>8} {:>10} {:>10} {:>10} {:>8}\n",
+        "OPERATION", "CALLS", "ENTRIES", "LAST", "MEAN", "BATCHED"
+    );
+    for timing in timings {
+        let mean_us = timing.total_us / timing.calls.max(1);
+        let _ = writeln!(
+            out,
+            "{:<18} {:>8} {:>10} {:>10} {:>10} {:>8}",
+            timing.operation,
+            timing.calls,
+            timing.entries,
+            format!("{} us", timing.last_us),
+            format!("{mean_us} us"),
+            if timing.batched { "yes" } else { "no" }
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_accumulate() {
+        let timings = Timings::default();
+        let start = Instant::now();
+        timings.record("pit_gc", start, 3, true);
+        timings.record("pit_gc", start, 5, false);
+        timings.record("cs_flush", start, 0, true);
+
+        let snapshot = timings.snapshot();
+        assert_eq!(
+            snapshot
+                .iter()
+                .map(|timing| timing.operation.as_str())
+                .collect::<Vec<_>>(),
+            ["cs_flush", "pit_gc"]
+        );
+        let pit_gc = &snapshot[1];
+        assert_eq!(
+            (pit_gc.calls, pit_gc.entries, pit_gc.batched),
+            (2, 8, false)
+        );
+        assert!(pit_gc.total_us >= pit_gc.last_us);
+        let table = format_timings(&snapshot);
+        assert!(table
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("pit_gc                    2          8"));
+        assert!(table.lines().nth(2).unwrap().ends_with("      no"));
+        assert!(unsupported(&io::Error::from_raw_os_error(ENOTSUPP)));
+        assert!(!unsupported(&io::Error::from_raw_os_error(libc::ENOENT)));
+    }
+}
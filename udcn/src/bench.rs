@@ -0,0 +1,357 @@
+//! `udcn bench`: how long the XDP program takes to decide on a packet, measured from the NIC.
+//!
+//! `bench load` sends Interests for names no one has asked for before, under [`BENCH_PREFIX`], so
+//! the datapath passes each of them up the stack. `bench latency`, run on the forwarder, receives
+//! them on the NDN port with `SO_TIMESTAMPING` and pairs each with the datapath's decision for its
+//! name, streamed by the daemon with `events-raw`. The NIC's receive timestamp to the decision is
+//! the time spent before and in the XDP program; the decision to the socket's software timestamp
+//! is the rest of the way up the stack.
+//!
+//! Hardware timestamps are read from the NIC's clock, so they only compare with the datapath's once
+//! that clock is synchronized to the system clock, e.g. by `phc2sys`. Without them, only the
+//! decision-to-socket latency is reported. XDP RX metadata hints would hand the timestamp to the
+//! program itself, but they are kfuncs the datapath has no way to call yet.
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fmt::Write as _,
+    io, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::AsRawFd as _,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use log::{info, warn};
+use tokio::{
+    io::Interest as Readiness,
+    net::UdpSocket,
+    time::{self, MissedTickBehavior},
+};
+use udcn_client::control::{self, RawEvent};
+use udcn_common::{EventOutcome, Interest, Name, TlvType};
+
+/// Names of benchmark Interests: the prefix, the time the run started and a sequence number.
+pub const BENCH_PREFIX: &str = "/udcn/bench";
+
+/// Decisions or packets waiting for their other half. Anything left unpaired this long was lost.
+const MAX_UNPAIRED: usize = 65_536;
+
+/// Send `count` Interests to `target`, `rate` a second.
+pub async fn load(target: SocketAddr, count: u64, rate: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(rate > 0, "the rate must be at least one Interest a second");
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    let run = Name::from(BENCH_PREFIX).append_timestamp(unix_ns() / 1_000);
+    let mut ticker = time::interval(Duration::from_secs(1) / rate.min(1_000_000_000) as u32);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+    info!("Sending {count} Interests under {run} to {target}");
+    for sequence in 0..count {
+        ticker.tick().await;
+        let interest = Interest::builder()
+            .name(run.clone().append_sequence_num(sequence))
+            .lifetime_ms(100)
+            .build();
+        socket.send_to(&interest.encode(), target).await?;
+    }
+    info!("Sent {count} Interests");
+    Ok(())
+}
+
+/// Pair `count` benchmark Interests received on `listen` with the datapath's decisions, then print
+/// the latency percentiles. Hardware timestamping is switched on for `interface` if given.
+pub async fn latency(
+    listen: SocketAddr,
+    interface: Option<&str>,
+    count: usize,
+) -> anyhow::Result<()> {
+    if let Some(interface) = interface {
+        enable_hardware_timestamps(interface)
+            .with_context(|| format!("failed to switch on hardware timestamps for {interface}"))?;
+    }
+    let mut events = control::raw_events(Some("interest")).await?;
+    let socket = UdpSocket::bind(listen)
+        .await
+        .with_context(|| format!("failed to bind {listen}"))?;
+    request_timestamps(&socket)?;
+    let prefix = Name::from(BENCH_PREFIX);
+
+    info!("Waiting for {count} benchmark Interests on {listen}, sent with `udcn bench load`");
+    let mut pairing = Pairing::default();
+    let mut buf = vec![0u8; 9000];
+    while pairing.paired() < count {
+        tokio::select! {
+            line = events.next_line() => {
+                let Some(line) = line? else {
+                    anyhow::bail!("the daemon went away");
+                };
+                match line.parse::<RawEvent>() {
+                    Ok(event) if is_forwarded_interest(&event) => {
+                        pairing.decided(event.name_hash, event.timestamp_ns);
+                    }
+                    Ok(_) => {}
+                    Err(_) => warn!("datapath events were lost: {line}"),
+                }
+            }
+            received = socket.async_io(Readiness::READABLE, || recv_timestamped(&socket, &mut buf)) => {
+                let (len, stamps) = received?;
+                match Interest::decode(&buf[..len]) {
+                    Some(interest) if prefix.is_prefix_of(&interest.name) => {
+                        pairing.received(interest.name.name_hash(), stamps);
+                    }
+                    _ => {}
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    print!("{}", pairing.report());
+    Ok(())
+}
+
+fn is_forwarded_interest(event: &RawEvent) -> bool {
+    event.packet_type == TlvType::Interest as u8 && event.outcome == EventOutcome::Forwarded as u8
+}
+
+pub(crate) fn unix_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// When a packet reached the NIC and the socket, in nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Stamps {
+    hardware_ns: Option<u64>,
+    software_ns: Option<u64>,
+}
+
+/// Matches datapath decisions with the packets they passed, whichever turns up first.
+#[derive(Default)]
+struct Pairing {
+    decisions: HashMap<u32, u64>,
+    arrivals: HashMap<u32, Stamps>,
+    /// Signed: an unsynchronized NIC clock can put the decision before the packet arrived.
+    nic_to_decision: Vec<i64>,
+    decision_to_socket: Vec<i64>,
+    pairs: usize,
+}
+
+impl Pairing {
+    fn paired(&self) -> usize {
+        self.pairs
+    }
+
+    fn decided(&mut self, name_hash: u32, decision_ns: u64) {
+        match self.arrivals.remove(&name_hash) {
+            Some(stamps) => self.pair(decision_ns, stamps),
+            None => insert_bounded(&mut self.decisions, name_hash, decision_ns),
+        }
+    }
+
+    fn received(&mut self, name_hash: u32, stamps: Stamps) {
+        match self.decisions.remove(&name_hash) {
+            Some(decision_ns) => self.pair(decision_ns, stamps),
+            None => insert_bounded(&mut self.arrivals, name_hash, stamps),
+        }
+    }
+
+    fn pair(&mut self, decision_ns: u64, stamps: Stamps) {
+        self.pairs += 1;
+        if let Some(hardware_ns) = stamps.hardware_ns {
+            self.nic_to_decision
+                .push(decision_ns as i64 - hardware_ns as i64);
+        }
+        if let Some(software_ns) = stamps.software_ns {
+            self.decision_to_socket
+                .push(software_ns as i64 - decision_ns as i64);
+        }
+    }
+
+    fn report(&mut self) -> String {
+        let mut out = format!(
+            "Paired {} Interests with their datapath decisions\n",
+            self.pairs
+        );
+        if self.nic_to_decision.is_empty() {
+            out += "NIC to decision:     no hardware timestamps (pass --interface, and check the \
+                    NIC supports them with `ethtool -T`)\n";
+        } else {
+            out += &format_percentiles("NIC to decision:", &mut self.nic_to_decision);
+            if self.nic_to_decision[0] < 0 {
+                out += "  (negative: the NIC clock is not synchronized to the system clock)\n";
+            }
+        }
+        if !self.decision_to_socket.is_empty() {
+            out += &format_percentiles("decision to socket:", &mut self.decision_to_socket);
+        }
+        out
+    }
+}
+
+/// Unpaired entries are lost packets or events; start over rather than grow without bound.
+fn insert_bounded<V>(map: &mut HashMap<u32, V>, key: u32, value: V) {
+    if map.len() >= MAX_UNPAIRED {
+        map.clear();
+    }
+    map.insert(key, value);
+}
+
+/// The nearest-rank percentile `p` of `sorted`, which must not be empty.
+pub(crate) fn percentile(sorted: &[i64], p: f64) -> i64 {
+    // In thousandths, as 99.9 / 100 does not come out exact
+    let permille = (p * 10.0).round() as usize;
+    let rank = (permille * sorted.len()).div_ceil(1000);
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn format_percentiles(label: &str, samples: &mut [i64]) -> String {
+    samples.sort_unstable();
+    let mut line = format!("{label:<20}");
+    for (name, p) in [("p50", 50.0), ("p99", 99.0), ("p99.9", 99.9)] {
+        let _ = write!(
+            line,
+            " {name} {:>9.3}µs",
+            percentile(samples, p) as f64 / 1_000.0
+        );
+    }
+    let _ = writeln!(line, "  ({} samples)", samples.len());
+    line
+}
+
+/// Ask for the NIC's and the kernel's receive timestamps of each datagram.
+fn request_timestamps(socket: &UdpSocket) -> io::Result<()> {
+    let flags = (libc::SOF_TIMESTAMPING_RX_HARDWARE
+        | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+        | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+        | libc::SOF_TIMESTAMPING_SOFTWARE) as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Have `interface` timestamp every packet it receives. This is a setting of the NIC, left on for
+/// whatever else uses it, as PTP daemons do.
+fn enable_hardware_timestamps(interface: &str) -> io::Result<()> {
+    let name = CString::new(interface)?;
+    let mut request: libc::ifreq = unsafe { mem::zeroed() };
+    if name.as_bytes_with_nul().len() > request.ifr_name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "interface name is too long",
+        ));
+    }
+    for (dst, &src) in request.ifr_name.iter_mut().zip(name.as_bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let mut config = libc::hwtstamp_config {
+        flags: 0,
+        tx_type: libc::HWTSTAMP_TX_OFF as libc::c_int,
+        rx_filter: libc::HWTSTAMP_FILTER_ALL as libc::c_int,
+    };
+    request.ifr_ifru.ifru_data = &mut config as *mut libc::hwtstamp_config as *mut libc::c_char;
+
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let ret = unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCSHWTSTAMP, &mut request) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive a datagram along with its `SCM_TIMESTAMPING` timestamps, if the kernel attached any.
+fn recv_timestamped(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<(usize, Stamps)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64s to keep the control messages aligned
+    let mut control = [0u64; 32];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut stamps = Stamps::default();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_TIMESTAMPING {
+            // Software, legacy and raw hardware timestamps, zero when missing
+            let times =
+                unsafe { (libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3]).read_unaligned() };
+            let ns = |ts: &libc::timespec| {
+                let ns = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+                (ns != 0).then_some(ns)
+            };
+            stamps.software_ns = ns(&times[0]);
+            stamps.hardware_ns = ns(&times[2]);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((len as usize, stamps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_decisions_with_arrivals() {
+        let mut pairing = Pairing::default();
+        // The decision can come in before or after the packet
+        pairing.decided(1, 10_000);
+        pairing.received(
+            1,
+            Stamps {
+                hardware_ns: Some(8_000),
+                software_ns: Some(15_000),
+            },
+        );
+        pairing.received(
+            2,
+            Stamps {
+                hardware_ns: None,
+                software_ns: Some(30_000),
+            },
+        );
+        pairing.decided(2, 21_000);
+        pairing.decided(3, 40_000);
+        assert_eq!(pairing.paired(), 2);
+        assert_eq!(pairing.nic_to_decision, [2_000]);
+        assert_eq!(pairing.decision_to_socket, [5_000, 9_000]);
+
+        let report = pairing.report();
+        assert!(report.starts_with("Paired 2 Interests"));
+        assert!(report.contains("NIC to decision:     p50     2.000µs"));
+        assert!(report.contains("p99.9     9.000µs  (2 samples)"));
+        assert!(!report.contains("not synchronized"));
+
+        let samples: Vec<i64> = (1..=1000).collect();
+        assert_eq!(percentile(&samples, 50.0), 500);
+        assert_eq!(percentile(&samples, 99.0), 990);
+        assert_eq!(percentile(&samples, 99.9), 999);
+        assert_eq!(percentile(&[7], 99.9), 7);
+    }
+}
@@ -0,0 +1,499 @@
+//! `udcn bench pair`: a timed throughput and latency test between two hosts, through whatever
+//! forwarders lie between them.
+//!
+//! The producer listens for the consumer on a TCP control channel. The consumer connects, names
+//! the run and the content size, and once the producer serves the run's prefix, sends Interests
+//! for `<prefix>/<run>/<n>` to its first forwarder at a steady rate for the test's duration. Some
+//! of them can ask again for names already fetched, so content stores along the path get a chance
+//! to answer. The consumer then tells the producer to stop, receives its side of the results and
+//! prints one merged JSON report. Messages on the channel are JSON, one per line.
+//!
+//! Data the producer never saw an Interest for was answered by a content store on the way. Both
+//! ends snapshot the cache hits of their own host's daemon, if one runs there, so those hits are
+//! attributed to the consumer's and producer's nodes, and the rest to the forwarders in between.
+//! The snapshots count whatever else the daemons forward during the run too.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::Context as _;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream, UdpSocket,
+    },
+    time::{self, Duration, Instant, MissedTickBehavior},
+};
+use udcn_client::{control, Producer};
+use udcn_common::{parse_nack_packet, Data, Interest, Name, PacketStats};
+
+use crate::{
+    bench::{percentile, unix_ns},
+    trace::node_name,
+};
+
+/// Lifetime of the benchmark Interests; replies later than this count as timeouts.
+const INTEREST_LIFETIME: Duration = Duration::from_secs(1);
+
+/// How long the producer's Data may be served from content stores, longer than any run.
+const FRESHNESS_PERIOD_MS: u64 = 3_600_000;
+
+/// A message on the control channel.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Message {
+    /// From the consumer: serve Data of `content_size` bytes under `run`.
+    Start {
+        run: String,
+        content_size: usize,
+    },
+    /// From the producer, once it serves the run.
+    Ready {
+        node: String,
+    },
+    /// From the consumer, once its last Interest is answered or timed out.
+    Stop,
+    Report(ProducerReport),
+}
+
+/// Cache hits and misses a daemon counted during the run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheCounts {
+    fn between(before: &PacketStats, after: &PacketStats) -> Self {
+        Self {
+            hits: after.cache_hits.wrapping_sub(before.cache_hits) as u64,
+            misses: after.cache_misses.wrapping_sub(before.cache_misses) as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProducerReport {
+    pub node: String,
+    /// Interests that reached the producer and were answered.
+    pub answered: u64,
+    /// The producer's host's daemon, if one runs there.
+    pub daemon: Option<CacheCounts>,
+}
+
+/// Latency percentiles from sending an Interest to its Data arriving, in microseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub p50: f64,
+    pub p99: f64,
+    pub p999: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConsumerReport {
+    pub node: String,
+    pub sent: u64,
+    pub data: u64,
+    pub nacks: u64,
+    pub timeouts: u64,
+    /// Data received a second over the test's duration.
+    pub data_per_sec: f64,
+    /// Content bytes received a second over the test's duration.
+    pub goodput_bytes_per_sec: f64,
+    /// `None` if no Data came back.
+    pub latency_us: Option<Latency>,
+    /// The consumer's host's daemon, if one runs there.
+    pub daemon: Option<CacheCounts>,
+}
+
+/// Where the Data the producer never saw came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Caching {
+    /// Data received beyond the Interests the producer answered.
+    pub answered_in_network: u64,
+    pub consumer_node_hits: Option<u64>,
+    pub producer_node_hits: Option<u64>,
+    /// The rest, answered by the forwarders between the two ends.
+    pub intermediate_hits: u64,
+}
+
+impl Caching {
+    fn attribute(consumer: &ConsumerReport, producer: &ProducerReport) -> Self {
+        let answered_in_network = consumer.data.saturating_sub(producer.answered);
+        let consumer_node_hits = consumer.daemon.map(|counts| counts.hits);
+        let producer_node_hits = producer.daemon.map(|counts| counts.hits);
+        let ends = consumer_node_hits.unwrap_or(0) + producer_node_hits.unwrap_or(0);
+        Self {
+            answered_in_network,
+            consumer_node_hits,
+            producer_node_hits,
+            intermediate_hits: answered_in_network.saturating_sub(ends),
+        }
+    }
+}
+
+/// The merged report of a run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub run: String,
+    pub duration_secs: f64,
+    pub rate: u64,
+    pub repeat_percent: u8,
+    pub content_size: usize,
+    pub consumer: ConsumerReport,
+    pub producer: ProducerReport,
+    pub caching: Caching,
+}
+
+/// What the consumer side of a run sends.
+#[derive(Clone, Debug)]
+pub struct ConsumerOptions {
+    /// The producer's control channel.
+    pub control: SocketAddr,
+    /// The first forwarder on the way to the producer.
+    pub target: SocketAddr,
+    pub prefix: Name,
+    pub duration: Duration,
+    /// Interests a second.
+    pub rate: u64,
+    pub content_size: usize,
+    /// Share of Interests, in percent, for names already fetched.
+    pub repeat_percent: u8,
+}
+
+struct Channel {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Channel {
+    fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, message: &Message) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> anyhow::Result<Message> {
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .context("the other end closed the control channel")?;
+        serde_json::from_str(&line).with_context(|| format!("invalid message '{line}'"))
+    }
+}
+
+/// The local daemon's counters, if one is running.
+async fn local_stats() -> Option<PacketStats> {
+    match control::stats().await {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            debug!("no local daemon to count cache hits with: {e:#}");
+            None
+        }
+    }
+}
+
+async fn cache_counts_since(before: Option<PacketStats>) -> Option<CacheCounts> {
+    let after = local_stats().await?;
+    Some(CacheCounts::between(&before?, &after))
+}
+
+/// Wait on `control` for a consumer, serve its run on `bind` until it stops, and report back.
+pub async fn producer(control: SocketAddr, bind: &str) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(control)
+        .await
+        .with_context(|| format!("failed to listen on {control}"))?;
+    info!("Waiting for the consumer on {control}");
+    let (stream, peer) = listener.accept().await?;
+    answer_consumer(Channel::new(stream), peer, bind).await
+}
+
+async fn answer_consumer(mut channel: Channel, peer: SocketAddr, bind: &str) -> anyhow::Result<()> {
+    let Message::Start { run, content_size } = channel.receive().await? else {
+        anyhow::bail!("expected the consumer to start a run");
+    };
+    let run = Name::from_uri(&run).map_err(|e| anyhow::anyhow!("invalid run name: {e}"))?;
+    let producer = Producer::bind(bind).await?;
+    if let Err(e) = control::register(&run).await {
+        debug!("could not register '{run}' with the daemon: {e:#}");
+    }
+    info!("Serving run {run} for {peer} on {bind}");
+
+    let before = local_stats().await;
+    let node = node_name();
+    let answered = AtomicU64::new(0);
+    let content = vec![b'x'; content_size];
+    channel.send(&Message::Ready { node: node.clone() }).await?;
+    let serve = producer.serve(&run, |interest| {
+        answered.fetch_add(1, Ordering::Relaxed);
+        Some(
+            Data::builder()
+                .name(interest.name.clone())
+                .content(content.clone())
+                .freshness_period_ms(FRESHNESS_PERIOD_MS)
+                .build(),
+        )
+    });
+    tokio::select! {
+        result = serve => {
+            result?;
+            anyhow::bail!("the producer stopped serving");
+        }
+        message = channel.receive() => anyhow::ensure!(
+            message? == Message::Stop,
+            "expected the consumer to stop the run"
+        ),
+    }
+
+    let report = ProducerReport {
+        node,
+        answered: answered.load(Ordering::Relaxed),
+        daemon: cache_counts_since(before).await,
+    };
+    info!("Answered {} Interests", report.answered);
+    channel.send(&Message::Report(report)).await
+}
+
+/// Start a run with the producer at `options.control`, send its Interests, and merge both sides'
+/// results.
+pub async fn consumer(options: &ConsumerOptions) -> anyhow::Result<Report> {
+    anyhow::ensure!(
+        options.rate > 0,
+        "the rate must be at least one Interest a second"
+    );
+    anyhow::ensure!(
+        options.repeat_percent <= 100,
+        "repeat_percent is at most 100"
+    );
+    let stream = TcpStream::connect(options.control)
+        .await
+        .with_context(|| format!("failed to reach the producer at {}", options.control))?;
+    let mut channel = Channel::new(stream);
+    let run = options.prefix.clone().append_timestamp(unix_ns() / 1_000);
+    channel
+        .send(&Message::Start {
+            run: run.to_string(),
+            content_size: options.content_size,
+        })
+        .await?;
+    let Message::Ready { node } = channel.receive().await? else {
+        anyhow::bail!("expected the producer to be ready");
+    };
+    info!("Producer {node} is serving {run}");
+
+    let before = local_stats().await;
+    let load = send_load(&run, options).await?;
+    let consumer = ConsumerReport {
+        node: node_name(),
+        daemon: cache_counts_since(before).await,
+        ..load
+    };
+    channel.send(&Message::Stop).await?;
+    let Message::Report(producer) = channel.receive().await? else {
+        anyhow::bail!("expected the producer's report");
+    };
+
+    Ok(Report {
+        run: run.to_string(),
+        duration_secs: options.duration.as_secs_f64(),
+        rate: options.rate,
+        repeat_percent: options.repeat_percent,
+        content_size: options.content_size,
+        caching: Caching::attribute(&consumer, &producer),
+        consumer,
+        producer,
+    })
+}
+
+/// Which name the Interest numbered `sequence` asks for, given how many distinct names were asked
+/// for before it: a new one, or with `repeat_percent` an older one, far enough back that its Data
+/// has likely come through already.
+fn pick_name(sequence: u64, fresh: u64, repeat_percent: u8) -> (u64, bool) {
+    let percent = repeat_percent as u64;
+    let repeat = fresh > 0 && (sequence + 1) * percent / 100 > sequence * percent / 100;
+    match repeat {
+        true => (fresh / 2, false),
+        false => (fresh, true),
+    }
+}
+
+/// Send the run's Interests and time their replies.
+async fn send_load(run: &Name, options: &ConsumerOptions) -> anyhow::Result<ConsumerReport> {
+    let local: SocketAddr = match options.target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(options.target).await?;
+    let mut ticker =
+        time::interval(Duration::from_secs(1) / options.rate.min(1_000_000_000) as u32);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+    info!(
+        "Sending {} Interests a second under {run} to {} for {:?}",
+        options.rate, options.target, options.duration
+    );
+    let start = Instant::now();
+    let end = start + options.duration;
+    // When the Interests for each name still waiting for Data were sent
+    let mut waiting: HashMap<u32, Vec<Instant>> = HashMap::new();
+    let mut latencies = Vec::new();
+    let (mut sent, mut fresh, mut nacks, mut content_bytes) = (0, 0, 0, 0);
+    let mut buf = vec![0u8; 65_536];
+    loop {
+        let sending = Instant::now() < end;
+        if !sending && waiting.is_empty() {
+            break;
+        }
+        tokio::select! {
+            _ = ticker.tick(), if sending => {
+                let (index, new) = pick_name(sent, fresh, options.repeat_percent);
+                fresh += new as u64;
+                let interest = Interest::builder()
+                    .name(run.clone().append_sequence_num(index))
+                    .lifetime_ms(INTEREST_LIFETIME.as_millis() as u64)
+                    .build();
+                socket.send(&interest.encode()).await?;
+                waiting.entry(interest.name.name_hash()).or_default().push(Instant::now());
+                sent += 1;
+            }
+            received = socket.recv(&mut buf) => {
+                let len = match received {
+                    Ok(len) => len,
+                    Err(e) => {
+                        debug!("bench receive failed: {e}");
+                        continue;
+                    }
+                };
+                let arrived = Instant::now();
+                if let Some(nack) = parse_nack_packet(&buf[..len]) {
+                    if let Some(sent_at) = waiting.get_mut(&nack.name_hash) {
+                        sent_at.pop();
+                        nacks += 1;
+                        if sent_at.is_empty() {
+                            waiting.remove(&nack.name_hash);
+                        }
+                    }
+                } else if let Some(data) = Data::decode(&buf[..len]) {
+                    // A forwarder aggregating Interests for the same name answers them all at once
+                    for sent_at in waiting.remove(&data.name.name_hash()).unwrap_or_default() {
+                        latencies.push(arrived.duration_since(sent_at).as_nanos() as i64);
+                        content_bytes += data.content.len() as u64;
+                    }
+                }
+            }
+            () = time::sleep_until(end + INTEREST_LIFETIME), if !sending => break,
+        }
+    }
+
+    let data = latencies.len() as u64;
+    let timeouts = waiting.values().map(|sent_at| sent_at.len() as u64).sum();
+    let secs = options.duration.as_secs_f64();
+    latencies.sort_unstable();
+    let latency_us = (!latencies.is_empty()).then(|| Latency {
+        p50: percentile(&latencies, 50.0) as f64 / 1_000.0,
+        p99: percentile(&latencies, 99.0) as f64 / 1_000.0,
+        p999: percentile(&latencies, 99.9) as f64 / 1_000.0,
+    });
+    Ok(ConsumerReport {
+        node: String::new(),
+        sent,
+        data,
+        nacks,
+        timeouts,
+        data_per_sec: data as f64 / secs,
+        goodput_bytes_per_sec: content_bytes as f64 / secs,
+        latency_us,
+        daemon: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pair_runs_and_attributes_cache_hits() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control = listener.local_addr().unwrap();
+        let data = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = data.local_addr().unwrap();
+        drop(data);
+        let producer = async {
+            let (stream, peer) = listener.accept().await.unwrap();
+            answer_consumer(Channel::new(stream), peer, &target.to_string()).await
+        };
+        let options = ConsumerOptions {
+            control,
+            target,
+            prefix: Name::from("/udcn/bench/pair"),
+            duration: Duration::from_millis(200),
+            rate: 500,
+            content_size: 100,
+            repeat_percent: 50,
+        };
+        let (produced, report) = tokio::join!(producer, consumer(&options));
+        produced.unwrap();
+        let report = report.unwrap();
+
+        // Straight to the producer: every Interest reaches it, none is answered on the way
+        let consumer = &report.consumer;
+        assert!(consumer.sent > 50, "{report:?}");
+        assert_eq!(
+            consumer.data + consumer.nacks + consumer.timeouts,
+            consumer.sent
+        );
+        assert_eq!(report.producer.answered, consumer.data);
+        assert_eq!(report.caching.answered_in_network, 0);
+        assert!(consumer.latency_us.is_some());
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+
+        // Half the Interests ask again for names already fetched
+        let picked: Vec<_> = (0..6)
+            .scan(0, |fresh, sequence| {
+                let (index, new) = pick_name(sequence, *fresh, 50);
+                *fresh += new as u64;
+                Some(index)
+            })
+            .collect();
+        assert_eq!(picked, [0, 0, 1, 1, 2, 1]);
+
+        let hits = |hits| Some(CacheCounts { hits, misses: 0 });
+        let consumer = ConsumerReport {
+            data: 100,
+            daemon: hits(10),
+            ..consumer.clone()
+        };
+        let producer = ProducerReport {
+            node: "p1".to_string(),
+            answered: 60,
+            daemon: hits(5),
+        };
+        assert_eq!(
+            Caching::attribute(&consumer, &producer),
+            Caching {
+                answered_in_network: 40,
+                consumer_node_hits: Some(10),
+                producer_node_hits: Some(5),
+                intermediate_hits: 25,
+            }
+        );
+    }
+}
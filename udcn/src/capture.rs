@@ -0,0 +1,508 @@
+//! Packet sampling for `udcn capture`: while a capture runs, the XDP program copies one in `rate`
+//! NDN packets, truncated to `snaplen` bytes, onto the `SAMPLES` perf buffer, and the daemon fans
+//! them out to captures. The CLI dissects each one or writes them to a pcap file.
+//!
+//! `udcn sample` changes the rate and snap length of running captures; the daemon keeps them for
+//! the next capture that does not ask for its own. Sampling stops when the last capture ends.
+//!
+//! Both can also take a [filter expression](crate::expr). The datapath samples only among the
+//! packets of the types and name the filter allows, and the daemon sends each capture only the
+//! sampled packets that match its own filter.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    mem::size_of,
+    net::Ipv4Addr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use aya::{
+    maps::{Array, MapData, PerfEventArray},
+    util::online_cpus,
+};
+use bytes::BytesMut;
+use log::warn;
+use tokio::{
+    io::unix::AsyncFd,
+    sync::broadcast::{self, error::RecvError},
+};
+use udcn_client::control::{self, CapturedPacket};
+use udcn_common::{
+    parse_data_packet, parse_interest_packet, parse_nack_packet, udp_face_id, Data, Interest, Name,
+    PacketSample, SampleConfig, TlvType, MAX_SAMPLE_LEN,
+};
+
+use crate::{
+    events::{format_time, Clock},
+    expr::{Filter, Subject},
+};
+
+/// Samples a slow capture may fall behind by before it starts missing them.
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// Sampling until a capture or `udcn sample` asks for something else.
+pub const DEFAULT_SAMPLING: SampleConfig = SampleConfig {
+    rate: 100,
+    snaplen: 128,
+    skip_types: 0,
+    name_hash: 0,
+};
+
+/// Samples read from a perf buffer at once.
+const READ_BATCH: usize = 16;
+
+/// Fans the `SAMPLES` perf buffers out to captures, and keeps the datapath sampling only while
+/// there are any.
+pub struct Sampler {
+    sender: broadcast::Sender<CapturedPacket>,
+    config: Mutex<Array<MapData, SampleConfig>>,
+    requested: Mutex<SampleConfig>,
+}
+
+impl Sampler {
+    /// Take the sampling maps and start draining the perf buffer of every CPU.
+    pub fn new(ebpf: &mut aya::Ebpf) -> anyhow::Result<Arc<Self>> {
+        let samples = PerfEventArray::try_from(ebpf.take_map("SAMPLES").unwrap())?;
+        let config = Array::try_from(ebpf.take_map("SAMPLE_CONFIG").unwrap())?;
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        let clock = Clock::datapath();
+
+        let publish = sender.clone();
+        read_samples(samples, "packet sampling", move |sample, data| {
+            let packet = CapturedPacket {
+                timestamp_ns: clock.realtime_ns(sample.timestamp_ns),
+                ifindex: sample.ifindex,
+                packet_len: sample.packet_len,
+                data: data.to_vec(),
+            };
+            // Nobody capturing is not an error; sampling stops soon
+            let _ = publish.send(packet);
+        })?;
+
+        Ok(Arc::new(Self {
+            sender,
+            config: Mutex::new(config),
+            requested: Mutex::new(DEFAULT_SAMPLING),
+        }))
+    }
+
+    /// Start a capture, with `config` from now on if given.
+    pub fn subscribe(self: &Arc<Self>, config: Option<SampleConfig>) -> Capture {
+        let receiver = self.sender.subscribe();
+        if let Some(config) = config {
+            *self.requested.lock().unwrap() = config;
+        }
+        self.update_config();
+        Capture {
+            sampler: self.clone(),
+            receiver: Some(receiver),
+        }
+    }
+
+    /// Sample with `config` from now on. Returns how many captures are running.
+    pub fn set(&self, config: SampleConfig) -> usize {
+        *self.requested.lock().unwrap() = config;
+        self.update_config();
+        self.sender.receiver_count()
+    }
+
+    /// What the next capture that does not ask for its own sampling samples with.
+    pub fn requested(&self) -> SampleConfig {
+        *self.requested.lock().unwrap()
+    }
+
+    fn update_config(&self) {
+        let config = match self.sender.receiver_count() {
+            0 => SampleConfig::default(),
+            _ => *self.requested.lock().unwrap(),
+        };
+        if let Err(e) = self.config.lock().unwrap().set(0, config, 0) {
+            warn!("failed to set packet sampling to 1 in {}: {e}", config.rate);
+        }
+    }
+}
+
+/// Parse the `[rate snaplen] [filter]` arguments of `capture` and `sample`, keeping the rate and
+/// snap length of `current` when only a filter is given. The filter's datapath part goes into the
+/// returned config.
+pub fn parse_config(
+    arguments: &str,
+    current: SampleConfig,
+) -> anyhow::Result<(SampleConfig, Filter)> {
+    let (config, filter) = match arguments.starts_with(|c: char| c.is_ascii_digit()) {
+        true => {
+            let mut arguments = arguments.splitn(3, ' ');
+            let (Some(rate), Some(snaplen)) = (arguments.next(), arguments.next()) else {
+                anyhow::bail!("expected a sampling rate and a snap length");
+            };
+            let config = SampleConfig {
+                rate: rate.parse()?,
+                snaplen: snaplen.parse()?,
+                ..current
+            };
+            (config, arguments.next().unwrap_or_default())
+        }
+        false => (current, arguments),
+    };
+    anyhow::ensure!(
+        (1..=MAX_SAMPLE_LEN).contains(&config.snaplen),
+        "the snap length must be between 1 and {MAX_SAMPLE_LEN} bytes"
+    );
+    let filter: Filter = filter.parse()?;
+    Ok((filter.sample_config(config), filter))
+}
+
+/// A running capture. The datapath stops sampling once the last one is dropped.
+pub struct Capture {
+    sampler: Arc<Sampler>,
+    receiver: Option<broadcast::Receiver<CapturedPacket>>,
+}
+
+impl Capture {
+    /// The next sampled packet, or how many were missed because this capture fell behind.
+    pub async fn recv(&mut self) -> Result<CapturedPacket, u64> {
+        let receiver = self.receiver.as_mut().unwrap();
+        loop {
+            match receiver.recv().await {
+                Ok(packet) => return Ok(packet),
+                Err(RecvError::Lagged(missed)) => return Err(missed),
+                // The sampler holds a sender for as long as the capture holds the sampler
+                Err(RecvError::Closed) => std::future::pending::<()>().await,
+            }
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.receiver = None;
+        self.sampler.update_config();
+    }
+}
+
+/// Drain the buffer of every CPU of a perf array the datapath copies packets onto, calling
+/// `handle` with each packet's header and captured bytes. `what` names the array in warnings.
+pub fn read_samples<F>(
+    mut samples: PerfEventArray<MapData>,
+    what: &'static str,
+    handle: F,
+) -> anyhow::Result<()>
+where
+    F: Fn(PacketSample, &[u8]) + Clone + Send + 'static,
+{
+    let cpus = online_cpus().map_err(|(path, e)| anyhow::anyhow!("failed to read {path}: {e}"))?;
+    for cpu in cpus {
+        let mut buffer = AsyncFd::new(samples.open(cpu, None)?)?;
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let capacity = size_of::<PacketSample>() + MAX_SAMPLE_LEN as usize + 8;
+            let mut buffers = vec![BytesMut::with_capacity(capacity); READ_BATCH];
+            loop {
+                let mut guard = match buffer.readable_mut().await {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        warn!("{what} on CPU {cpu} stopped: {e}");
+                        return;
+                    }
+                };
+                loop {
+                    let events = match guard.get_inner_mut().read_events(&mut buffers) {
+                        Ok(events) => events,
+                        Err(e) => {
+                            warn!("{what} on CPU {cpu} stopped: {e}");
+                            return;
+                        }
+                    };
+                    for bytes in &buffers[..events.read] {
+                        if let Some((sample, data)) = parse_sample(bytes) {
+                            handle(sample, data);
+                        }
+                    }
+                    if events.read < READ_BATCH {
+                        break;
+                    }
+                }
+                guard.clear_ready();
+            }
+        });
+    }
+    Ok(())
+}
+
+fn parse_sample(bytes: &[u8]) -> Option<(PacketSample, &[u8])> {
+    let header_len = size_of::<PacketSample>();
+    if bytes.len() < header_len {
+        return None;
+    }
+    // SAFETY: the length was checked, and every bit pattern is a valid PacketSample
+    let sample = unsafe { (bytes.as_ptr() as *const PacketSample).read_unaligned() };
+    let data = bytes.get(header_len..header_len + sample.captured_len as usize)?;
+    Some((sample, data))
+}
+
+/// The IPv4 header, UDP ports and UDP payload of a captured frame, or `None` if it was truncated
+/// before the ports.
+fn split_udp(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let ip_header_len = data.get(14).map_or(0, |ihl| usize::from(ihl & 0x0f) * 4);
+    let udp = 14 + ip_header_len;
+    let payload = data.get(udp + 8..).unwrap_or_default();
+    Some((data.get(14..34)?, data.get(udp..udp + 4)?, payload))
+}
+
+/// The name of the Interest or Data `packet` holds, unless it was truncated.
+pub fn decoded_name(packet: &CapturedPacket) -> Option<Name> {
+    if packet.data.len() < packet.packet_len as usize {
+        return None;
+    }
+    let (_, _, payload) = split_udp(&packet.data)?;
+    Interest::decode(payload)
+        .map(|interest| interest.name)
+        .or_else(|| Data::decode(payload).map(|data| data.name))
+}
+
+/// What a capture's filter sees of `packet`, looking up the name of a truncated one by its hash
+/// with `name`. `None` if it was truncated before the UDP payload.
+pub fn subject(packet: &CapturedPacket, name: impl FnOnce(u32) -> Option<Name>) -> Option<Subject> {
+    let (ip, ports, payload) = split_udp(&packet.data)?;
+    let packet_type = *payload.first()?;
+    let decoded = decoded_name(packet);
+    let name_hash = match packet_type {
+        t if t == TlvType::Interest as u8 => parse_interest_packet(payload).map(|h| h.name_hash),
+        t if t == TlvType::Data as u8 => parse_data_packet(payload).map(|h| h.name_hash),
+        t if t == TlvType::Nack as u8 => parse_nack_packet(payload).map(|h| h.name_hash),
+        _ => None,
+    }
+    .or_else(|| decoded.as_ref().map(Name::name_hash));
+    let name = match decoded {
+        Some(name) => Some(name),
+        None => name_hash.and_then(name),
+    };
+    Some(Subject {
+        name_hash,
+        name,
+        packet_type,
+        face_id: udp_face_id([ip[12], ip[13], ip[14], ip[15]], [ports[0], ports[1]]),
+        size: packet.packet_len,
+        dropped: false,
+    })
+}
+
+/// One line of `udcn capture`: time, interface, addresses and what the NDN packet is.
+pub fn dissect(packet: &CapturedPacket) -> String {
+    let mut line = format!(
+        "{} if {} {} bytes ",
+        format_time(packet.timestamp_ns),
+        packet.ifindex,
+        packet.packet_len
+    );
+    let data = &packet.data;
+    let Some((ip, ports, payload)) = split_udp(data) else {
+        line += "(truncated before the UDP header)";
+        return line;
+    };
+    let _ = write!(
+        line,
+        "{}:{} > {}:{} ",
+        Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]),
+        u16::from_be_bytes([ports[0], ports[1]]),
+        Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]),
+        u16::from_be_bytes([ports[2], ports[3]])
+    );
+
+    let truncated = data.len() < packet.packet_len as usize;
+    line += &match payload.first() {
+        _ if !truncated && Interest::decode(payload).is_some() => {
+            format!("interest {}", Interest::decode(payload).unwrap().name)
+        }
+        _ if !truncated && Data::decode(payload).is_some() => {
+            let data = Data::decode(payload).unwrap();
+            format!(
+                "data {} ({} bytes of content)",
+                data.name,
+                data.content.len()
+            )
+        }
+        Some(&t) if t == TlvType::Interest as u8 => match parse_interest_packet(payload) {
+            Some(header) => format!("interest {:#010x}", header.name_hash),
+            None => "interest".to_string(),
+        },
+        Some(&t) if t == TlvType::Data as u8 => match parse_data_packet(payload) {
+            Some(header) => format!("data {:#010x}", header.name_hash),
+            None => "data".to_string(),
+        },
+        Some(&t) if t == TlvType::Nack as u8 => match parse_nack_packet(payload) {
+            Some(header) => format!("nack {:#010x} reason {}", header.name_hash, header.reason),
+            None => "nack".to_string(),
+        },
+        Some(&t) if t == TlvType::LpPacket as u8 => "lp_packet".to_string(),
+        _ => "not NDN".to_string(),
+    };
+    if truncated {
+        line += " (truncated)";
+    }
+    line
+}
+
+/// `udcn capture`: print a line per sampled packet matching `filter`, or write them to `write` as
+/// pcap, until `count` have been seen or the daemon goes away.
+pub async fn run(
+    config: Option<SampleConfig>,
+    filter: Option<&str>,
+    write: Option<&Path>,
+    count: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut pcap = match write {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let snaplen = config.map_or(MAX_SAMPLE_LEN, |config| config.snaplen);
+            Some(PcapWriter::new(BufWriter::new(file), snaplen)?)
+        }
+        None => None,
+    };
+    let settings = config.map(|config| (config.rate, config.snaplen));
+    let mut lines = control::capture(settings, filter).await?;
+    let mut seen = 0;
+    while count.is_none_or(|count| seen < count) {
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+        if let Some(missed) = line.strip_prefix("missed ") {
+            eprintln!("... {missed} sampled packet(s) missed");
+            continue;
+        }
+        let packet: CapturedPacket = line.parse()?;
+        match &mut pcap {
+            Some(pcap) => pcap.write(&packet)?,
+            None => println!("{}", dissect(&packet)),
+        }
+        seen += 1;
+    }
+    Ok(())
+}
+
+/// Writes captured packets in the pcap format, with nanosecond timestamps.
+pub struct PcapWriter<W> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the file header for packets of up to `snaplen` bytes.
+    pub fn new(mut out: W, snaplen: u32) -> io::Result<Self> {
+        /// The nanosecond-resolution pcap magic number.
+        const MAGIC: u32 = 0xa1b2_3c4d;
+        const LINKTYPE_ETHERNET: u32 = 1;
+
+        out.write_all(&MAGIC.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        // Time zone offset and timestamp accuracy
+        out.write_all(&[0; 8])?;
+        out.write_all(&snaplen.to_le_bytes())?;
+        out.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, packet: &CapturedPacket) -> io::Result<()> {
+        let seconds = (packet.timestamp_ns / 1_000_000_000) as u32;
+        let nanos = (packet.timestamp_ns % 1_000_000_000) as u32;
+        self.out.write_all(&seconds.to_le_bytes())?;
+        self.out.write_all(&nanos.to_le_bytes())?;
+        self.out
+            .write_all(&(packet.data.len() as u32).to_le_bytes())?;
+        self.out.write_all(&packet.packet_len.to_le_bytes())?;
+        self.out.write_all(&packet.data)?;
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::Name;
+
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 14];
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        let mut ip = [0u8; 20];
+        ip[0] = 0x45;
+        ip[9] = 17;
+        ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&40000u16.to_be_bytes());
+        frame.extend_from_slice(&6363u16.to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_dissect_and_pcap() {
+        let interest = Interest::builder().name(Name::from("/video/a")).build();
+        let data = frame(&interest.encode());
+        let packet = CapturedPacket {
+            timestamp_ns: 3_723_000_001_000,
+            ifindex: 2,
+            packet_len: data.len() as u32,
+            data: data.clone(),
+        };
+        assert_eq!(
+            dissect(&packet),
+            format!(
+                "01:02:03.000001 if 2 {} bytes 10.0.0.1:40000 > 10.0.0.2:6363 interest /video/a",
+                data.len()
+            )
+        );
+        assert_eq!(
+            packet.to_string().parse::<CapturedPacket>().unwrap(),
+            packet
+        );
+
+        let truncated = CapturedPacket {
+            data: data[..60].to_vec(),
+            ..packet.clone()
+        };
+        assert!(dissect(&truncated).ends_with(&format!(
+            "interest {:#010x} (truncated)",
+            parse_interest_packet(&data[42..]).unwrap().name_hash
+        )));
+        assert!(parse_config("10 4096", DEFAULT_SAMPLING).is_err());
+        assert_eq!(
+            parse_config("10 64", DEFAULT_SAMPLING).unwrap().0,
+            SampleConfig {
+                rate: 10,
+                snaplen: 64,
+                ..DEFAULT_SAMPLING
+            }
+        );
+        let (config, filter) = parse_config("interest, name ~ /video", DEFAULT_SAMPLING).unwrap();
+        assert_eq!((config.rate, config.snaplen), (100, 128));
+        assert_ne!(config.skip_types, 0);
+        let seen = subject(&packet, |_| None).unwrap();
+        assert_eq!(seen.name, Some(Name::from_uri("/video/a").unwrap()));
+        assert!(filter.matches(&seen));
+        // A truncated packet's name is only known if the daemon resolves its hash
+        assert!(!filter.matches(&subject(&truncated, |_| None).unwrap()));
+        let resolved = subject(&truncated, |_| Some(Name::from("/video/a"))).unwrap();
+        assert!(filter.matches(&resolved));
+
+        let mut pcap = PcapWriter::new(Vec::new(), 128).unwrap();
+        pcap.write(&truncated).unwrap();
+        let file = pcap.out;
+        assert_eq!(file.len(), 24 + 16 + 60);
+        assert_eq!(&file[..4], &[0x4d, 0x3c, 0xb2, 0xa1]);
+        assert_eq!(&file[24..28], &3723u32.to_le_bytes());
+        assert_eq!(&file[32..36], &60u32.to_le_bytes());
+        assert_eq!(&file[36..40], &packet.packet_len.to_le_bytes());
+    }
+}
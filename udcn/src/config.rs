@@ -0,0 +1,333 @@
+//! The daemon's configuration file, given with `udcn run --config`, for settings too structured
+//! for command-line options:
+//!
+//! ```toml
+//! [otlp]
+//! endpoint = "http://127.0.0.1:4318"  # an OTLP/HTTP collector
+//! interval_secs = 10                   # optional, how often to export
+//! service_name = "udcn"                # optional
+//!
+//! [prefetch]
+//! upstream = "192.0.2.1:6363"          # where prefetch Interests go
+//! prefixes = ["/video/intro"]          # optional, warmed segment by segment
+//! ahead = 4                            # optional, segments fetched ahead of Interests
+//! max_segments = 1024                  # optional, segments warmed per prefix
+//! idle_below = 100                     # optional, Interests/s under which to prefetch
+//! interval_secs = 1                    # optional, how often to check
+//!
+//! [flows]
+//! collector = "192.0.2.10:4739"        # an IPFIX collector, over UDP
+//! interval_secs = 60                   # optional, how often to export
+//! prefix_components = 2                # optional, name components that make up a flow
+//! observation_domain = 0               # optional
+//!
+//! [[tunnels]]                          # repeatable
+//! remote = "site-b.example.net:6363"   # the other site's forwarder, looked up again periodically
+//! listen = "0.0.0.0:6363"              # optional, where Interests to relay arrive
+//! prefixes = ["/site-b"]               # optional, only relay Interests under these
+//! resolve_secs = 60                    # optional, how often `remote` is looked up again
+//! on_nack = "suppress"                 # optional, or propagate: relay Nacks to consumers
+//! egress_bytes_per_sec = 250000        # optional, the most sent towards `remote` a second
+//! egress_burst_bytes = 25000           # optional, a tenth of a second at the rate by default
+//! egress_queue_bytes = 62500           # optional, a quarter of a second at the rate by default
+//!
+//! [tunnels.dtls]                       # optional, encrypt the tunnel; the remote is a listener
+//! psk_identity = "site-a"              # a pre-shared key and the identity to give with it, or
+//! psk_file = "/etc/udcn/site-b.psk"
+//! # cert_file, key_file and ca_file    # a PEM certificate and key, and the CAs to check against
+//!
+//! [[dtls_listeners]]                   # repeatable, takes DTLS tunnels from other sites
+//! listen = "0.0.0.0:6364"
+//!
+//! [dtls_listeners.dtls]                # as for tunnels
+//! psk_identity = "site-a"              # the only identity taken with the key
+//! psk_file = "/etc/udcn/site-a.psk"
+//!
+//! [strategies."/video"]                # repeatable, per prefix
+//! strategy = "retransmit"              # or failover, the default for every prefix
+//! probe_interval_ms = 500              # optional, how often faces are probed
+//! failures_until_down = 3              # optional, timeouts before a face is down
+//! retransmit_ms = 200                  # optional, for retransmit
+//! max_retries = 2                      # optional, for retransmit
+//! on_nack = "wait"                     # optional, propagate, wait or suppress
+//! deadline_aware = true                # optional, skip faces too slow for the lifetime left
+//!
+//! [[alarms]]                           # repeatable
+//! name = "low-hit-ratio"               # optional, defaults to the metric
+//! metric = "hit_ratio"                 # hit_ratio, drop_ratio, pit_occupancy or cs_occupancy
+//! below = 20                           # or `above`, in percent
+//! window_secs = 60                     # optional, for hit_ratio and drop_ratio
+//!
+//! [[tenants]]                          # repeatable, up to 16
+//! prefix = "/customer-a"               # names the quotas apply to; tenants may not nest
+//! name = "customer-a"                  # optional, defaults to the prefix
+//! pit_entries = 2000                   # optional, pending Interests
+//! cs_bytes = 16777216                  # optional, content bytes in the content store
+//! interests_per_sec = 5000             # optional
+//!
+//! [acl]
+//! default = "deny"                     # optional, or allow: names no rule matches
+//!
+//! [[acl.rules]]                        # repeatable, up to 256
+//! prefix = "/spam"                     # names the rule applies to, of up to four components
+//! action = "deny"                      # or allow
+//! face = "192.0.2.7:6363"              # optional, only packets from this UDP peer
+//!
+//! [[gates]]                           # repeatable
+//! prefix = "/paid"                     # Interests under it need a token; its Data is not cached
+//! secret_file = "/etc/udcn/paid.key"   # the secret tokens are issued with
+//!
+//! [control]
+//! allowed_uids = [1001]                # optional, who besides root may change the daemon
+//! allowed_gids = [1500]                # optional, groups whose members may
+//! audit_log = "/var/log/udcn/audit"    # optional, who changed what
+//! ```
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use udcn_client::strategy::StrategyChoice;
+use udcn_common::Name;
+
+use crate::{
+    access::ControlConfig, acl::AclConfig, alarms::AlarmConfig, dtls::ListenerConfig,
+    flows::FlowsConfig, gates::GateConfig, otlp::OtlpConfig, prefetch::PrefetchConfig,
+    tenants::TenantConfig, tunnels::TunnelConfig,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// OpenTelemetry export; off without this section.
+    #[serde(default)]
+    pub otlp: Option<OtlpConfig>,
+    /// Content store prefetching; off without this section.
+    #[serde(default)]
+    pub prefetch: Option<PrefetchConfig>,
+    /// Flow record export; off without this section.
+    #[serde(default)]
+    pub flows: Option<FlowsConfig>,
+    /// Permanent tunnels to other sites.
+    #[serde(default)]
+    pub tunnels: Vec<TunnelConfig>,
+    /// Where DTLS tunnels from other sites arrive.
+    #[serde(default)]
+    pub dtls_listeners: Vec<ListenerConfig>,
+    /// Forwarding strategies by prefix, which `udcn strategy set` can override.
+    #[serde(default)]
+    pub strategies: BTreeMap<Name, StrategyChoice>,
+    /// Thresholds evaluated every second.
+    #[serde(default)]
+    pub alarms: Vec<AlarmConfig>,
+    /// Namespaces with quotas; none without this section.
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+    /// Prefixes the datapath drops or lets through; every packet passes without this section.
+    #[serde(default)]
+    pub acl: AclConfig,
+    /// Namespaces whose Interests need an access token; none without this section.
+    #[serde(default)]
+    pub gates: Vec<GateConfig>,
+    /// Who may change the daemon over the control socket.
+    #[serde(default)]
+    pub control: ControlConfig,
+}
+
+pub fn load(path: &Path) -> anyhow::Result<DaemonConfig> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse(&contents).with_context(|| format!("invalid configuration in {}", path.display()))
+}
+
+fn parse(contents: &str) -> anyhow::Result<DaemonConfig> {
+    let config: DaemonConfig = toml::from_str(contents)?;
+    if let Some(otlp) = &config.otlp {
+        otlp.endpoint()?;
+        anyhow::ensure!(
+            otlp.interval_secs > 0,
+            "otlp.interval_secs must be positive"
+        );
+    }
+    if let Some(prefetch) = &config.prefetch {
+        prefetch.validate()?;
+    }
+    if let Some(flows) = &config.flows {
+        flows.validate()?;
+    }
+    for (i, tunnel) in config.tunnels.iter().enumerate() {
+        tunnel.validate()?;
+        anyhow::ensure!(
+            config.tunnels[..i]
+                .iter()
+                .all(|other| other.listen != tunnel.listen),
+            "two tunnels listen on {}",
+            tunnel.listen
+        );
+    }
+    for listener in &config.dtls_listeners {
+        listener.dtls.validate()?;
+    }
+    for (prefix, strategy) in &config.strategies {
+        strategy
+            .validate()
+            .with_context(|| format!("invalid strategy for '{prefix}'"))?;
+    }
+    for alarm in &config.alarms {
+        alarm.validate()?;
+    }
+    crate::tenants::validate(&config.tenants)?;
+    crate::acl::validate(&config.acl)?;
+    crate::gates::validate(&config.gates)?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_otlp_section() {
+        let config = parse("[otlp]\nendpoint = \"http://collector:4318/otel\"\n").unwrap();
+        let otlp = config.otlp.unwrap();
+        assert_eq!(otlp.interval_secs, 10);
+        assert_eq!(otlp.service_name, "udcn");
+
+        assert!(parse("").unwrap().otlp.is_none());
+        assert!(parse("[otlp]\nendpoint = \"https://collector\"\n").is_err());
+        assert!(parse("[otlp]\nendpoint = \"http://c\"\ninterval_secs = 0\n").is_err());
+        assert!(parse("[metrics]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_prefetch_section() {
+        let config = parse(
+            "[prefetch]\nupstream = \"10.0.0.1:6363\"\nprefixes = [\"/video/a\"]\nahead = 4\n",
+        )
+        .unwrap();
+        let prefetch = config.prefetch.unwrap();
+        assert_eq!(prefetch.prefixes, [Name::from("/video/a")]);
+        assert_eq!((prefetch.ahead, prefetch.max_segments), (4, 1024));
+
+        assert!(parse("[prefetch]\nupstream = \"[::1]:6363\"\n").is_err());
+        assert!(parse("[prefetch]\nupstream = \"10.0.0.1:6363\"\ninterval_secs = 0\n").is_err());
+        assert!(parse("[prefetch]\nupstream = \"10.0.0.1:6363\"\nprefixes = [\"a%\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_flows_section() {
+        let config =
+            parse("[flows]\ncollector = \"192.0.2.10:4739\"\nprefix_components = 3\n").unwrap();
+        let flows = config.flows.unwrap();
+        assert_eq!((flows.interval_secs, flows.prefix_components), (60, 3));
+
+        assert!(parse("[flows]\ncollector = \"collector\"\n").is_err());
+        assert!(parse("[flows]\ncollector = \"192.0.2.10:4739\"\ninterval_secs = 0\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_tunnels() {
+        let config =
+            parse("[[tunnels]]\nremote = \"site-b.example.net:6363\"\nprefixes = [\"/site-b\"]\n")
+                .unwrap();
+        let tunnel = &config.tunnels[0];
+        assert_eq!(tunnel.listen, "0.0.0.0:6363".parse().unwrap());
+        assert_eq!(tunnel.resolve_secs, 60);
+
+        assert!(parse("[[tunnels]]\nremote = \"site-b.example.net\"\n").is_err());
+        assert!(parse("[[tunnels]]\nremote = \":6363\"\n").is_err());
+        assert!(parse("[[tunnels]]\nremote = \"b:6363\"\nresolve_secs = 0\n").is_err());
+        assert!(parse("[[tunnels]]\nremote = \"b:6363\"\non_nack = \"wait\"\n").is_err());
+        assert!(parse("[[tunnels]]\nremote = \"b:6363\"\negress_burst_bytes = 1500\n").is_err());
+        assert!(parse("[[tunnels]]\nremote = \"b:6363\"\negress_bytes_per_sec = 0\n").is_err());
+        let config =
+            parse("[[tunnels]]\nremote = \"b:6363\"\negress_bytes_per_sec = 250000\n").unwrap();
+        assert_eq!(config.tunnels[0].egress_bytes_per_sec, Some(250_000));
+        assert!(
+            parse("[[tunnels]]\nremote = \"b:6363\"\n\n[[tunnels]]\nremote = \"c:6363\"\n")
+                .is_err()
+        );
+
+        let dtls = "[[tunnels]]\nremote = \"b:6364\"\n[tunnels.dtls]\npsk_identity = \"a\"\n";
+        assert!(parse(dtls).is_err());
+        let config = parse(&format!("{dtls}psk_file = \"a.psk\"\n")).unwrap();
+        assert!(config.tunnels[0].dtls.is_some());
+        let listener = "[[dtls_listeners]]\nlisten = \"0.0.0.0:6364\"\n[dtls_listeners.dtls]\n";
+        assert!(parse(&format!(
+            "{listener}cert_file = \"b.pem\"\nkey_file = \"b.key\"\n"
+        ))
+        .is_err());
+        let config = parse(&format!(
+            "{listener}psk_identity = \"a\"\npsk_file = \"a.psk\"\n"
+        ))
+        .unwrap();
+        assert_eq!(
+            config.dtls_listeners[0].listen,
+            "0.0.0.0:6364".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_strategies() {
+        let config = parse(
+            "[strategies.\"/video\"]\nstrategy = \"retransmit\"\nretransmit_ms = 200\n\n\
+             [strategies.\"/\"]\nprobe_interval_ms = 1000\n",
+        )
+        .unwrap();
+        let video = &config.strategies[&Name::from("/video")];
+        assert_eq!(video.to_string(), "retransmit retransmit_ms=200");
+        assert_eq!(
+            config.strategies[&Name::new()].to_string(),
+            "failover probe_interval_ms=1000"
+        );
+
+        assert!(parse("[strategies.\"/a\"]\nstrategy = \"flood\"\n").is_err());
+        assert!(parse("[strategies.\"/a\"]\nmax_retries = 2\n").is_err());
+        assert!(parse("[strategies.\"/a\"]\nretries = 2\n").is_err());
+        let config = parse(
+            "[strategies.\"/cache\"]\nstrategy = \"load-balance\"\n\
+             weights = { \"10.0.0.1:6363\" = 3, \"10.0.0.2:6363\" = 1 }\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.strategies[&Name::from("/cache")].to_string(),
+            "load-balance weights=10.0.0.1:6363:3,10.0.0.2:6363:1"
+        );
+        assert!(parse("[strategies.\"/a\"]\nweights = { \"10.0.0.1:6363\" = 3 }\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_alarms() {
+        let config = parse(
+            "[[alarms]]\nmetric = \"hit_ratio\"\nbelow = 20\n\n\
+             [[alarms]]\nname = \"pit\"\nmetric = \"pit_occupancy\"\nabove = 90\n",
+        )
+        .unwrap();
+        assert_eq!(config.alarms.len(), 2);
+        assert!(parse("[[alarms]]\nmetric = \"hit_ratio\"\nbelow = 20\nabove = 90\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_tenants() {
+        let config = parse(
+            "[[tenants]]\nprefix = \"/customer-a\"\npit_entries = 2000\n\n\
+             [[tenants]]\nname = \"b\"\nprefix = \"/customer-b\"\ninterests_per_sec = 100\n",
+        )
+        .unwrap();
+        assert_eq!(config.tenants[0].name(), "/customer-a");
+        assert_eq!(config.tenants[1].interests_per_sec, 100);
+
+        assert!(parse("[[tenants]]\nprefix = \"/a\"\n\n[[tenants]]\nprefix = \"/a/b\"\n").is_err());
+        assert!(parse("[[tenants]]\nprefix = \"/a\"\nrate = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_control_section() {
+        let config =
+            parse("[control]\nallowed_uids = [1001]\naudit_log = \"/tmp/audit\"\n").unwrap();
+        assert_eq!(config.control.allowed_uids, [1001]);
+        assert!(config.control.allowed_gids.is_empty());
+        assert!(parse("").unwrap().control.audit_log.is_none());
+        assert!(parse("[control]\nallowed_users = [\"alice\"]\n").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Daemon side of the Unix control socket; the protocol is described in `udcn_client::control`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use log::{debug, warn};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+    time::{sleep, Duration},
+};
+use udcn_client::control::{self, CapturedPacket, ERROR_PREFIX};
+use udcn_common::{DatapathEvent, SampleConfig, TlvType};
+
+use crate::{
+    access::{Access, Peer},
+    capture::{self, Sampler},
+    daemon::Daemon,
+    ether,
+    events::{self, Clock, EventHub},
+    expr::Filter,
+    journal, names, otlp, preload, state,
+};
+
+/// The longest `names-top` counts Interests for.
+const MAX_TOP_SECS: u64 = 60;
+
+/// Create the control socket, replacing one left behind by a previous daemon.
+pub fn bind() -> anyhow::Result<UnixListener> {
+    let path = control::socket_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    // A socket left behind by a previous daemon would make bind fail
+    let _ = fs::remove_file(&path);
+    UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind control socket {}", path.display()))
+}
+
+/// Accept control connections until the listener fails.
+pub async fn serve(
+    listener: UnixListener,
+    daemon: Arc<Mutex<Daemon>>,
+    access: Arc<Access>,
+    hub: Arc<EventHub>,
+    sampler: Arc<Sampler>,
+) -> anyhow::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let daemon = daemon.clone();
+        let access = access.clone();
+        let hub = hub.clone();
+        let sampler = sampler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, daemon, &access, hub, sampler).await {
+                warn!("control connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    daemon: Arc<Mutex<Daemon>>,
+    access: &Access,
+    hub: Arc<EventHub>,
+    sampler: Arc<Sampler>,
+) -> anyhow::Result<()> {
+    let peer = Peer::of(&stream)?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request).await?;
+    let request = request.trim_end();
+    debug!("control request: {request}");
+    let (request, replayed) = journal::strip_replayed(request);
+    if let Err(e) = access.authorize(&peer, request) {
+        warn!("refused control request '{request}': {e}");
+        return respond(reader.into_inner(), Err(e)).await;
+    }
+
+    let result = match request.split_once(' ').unwrap_or((request, "")) {
+        (command @ ("events" | "events-raw"), filter) => match filter.parse() {
+            Ok(filter) => {
+                let raw = command == "events-raw";
+                return stream_events(reader.into_inner(), daemon, hub, filter, raw).await;
+            }
+            Err(e) => Err(e),
+        },
+        ("capture", arguments) => {
+            let config = match arguments {
+                "" => Ok((None, Filter::default())),
+                arguments => capture::parse_config(arguments, sampler.requested())
+                    .map(|(config, filter)| (Some(config), filter)),
+            };
+            match config {
+                Ok((config, filter)) => {
+                    let stream = reader.into_inner();
+                    return stream_samples(stream, daemon, sampler, config, filter).await;
+                }
+                Err(e) => Err(e),
+            }
+        }
+        ("sample", arguments) => {
+            capture::parse_config(arguments, sampler.requested()).map(|(config, _)| {
+                let captures = sampler.set(config);
+                format!(
+                    "Sampling 1 in {} packets, {} bytes each, for {captures} capture(s)\n",
+                    config.rate, config.snaplen
+                )
+            })
+        }
+        ("cs-preload", argument) => match argument.parse() {
+            Ok(request) => {
+                // The manifest's entries and the files they import follow the request line
+                let mut body = Vec::new();
+                (&mut reader)
+                    .take(preload::MAX_BODY)
+                    .read_to_end(&mut body)
+                    .await?;
+                preload::preload(daemon.clone(), &request, &body)
+                    .await
+                    .map(|report| preload::format_report(&report))
+            }
+            Err(e) => Err(e),
+        },
+        ("state-import", _) => {
+            // The export follows the request line, whose path is only there for the journal
+            let mut body = Vec::new();
+            (&mut reader)
+                .take(state::MAX_BODY)
+                .read_to_end(&mut body)
+                .await?;
+            let state = serde_json::from_slice(&body).context("the body is not an exported state");
+            match state {
+                Ok(state) => state::import(&daemon, &hub, &state)
+                    .await
+                    .map(|imported| imported.to_string()),
+                Err(e) => Err(e),
+            }
+        }
+        ("face-create", uri) => ether::create(uri, daemon.clone()),
+        ("names-top", arguments) => top_names(&daemon, &hub, arguments).await,
+        (command, _) => {
+            let mut span = otlp::span("control_request");
+            span.attr("command", command);
+            daemon.lock().unwrap().handle(request)
+        }
+    };
+    if result.is_ok() && !replayed {
+        daemon.lock().unwrap().journal(request);
+    }
+    respond(reader.into_inner(), result).await
+}
+
+async fn respond(mut stream: UnixStream, result: anyhow::Result<String>) -> anyhow::Result<()> {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => format!("{ERROR_PREFIX}{e:#}\n"),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Write a line per datapath event matching `filter` until the client goes away, formatted for
+/// people or, if `raw`, as `RawEvent`s.
+async fn stream_events(
+    mut stream: UnixStream,
+    daemon: Arc<Mutex<Daemon>>,
+    hub: Arc<EventHub>,
+    filter: Filter,
+    raw: bool,
+) -> anyhow::Result<()> {
+    let wanted = |event: &DatapathEvent| {
+        let name = match filter.uses_names() {
+            true => daemon.lock().unwrap().name(event.name_hash),
+            false => None,
+        };
+        filter.matches(&events::subject(event, name))
+    };
+    let mut subscription = hub.subscribe();
+    let clock = Clock::datapath();
+    let (mut reader, mut writer) = stream.split();
+    let mut scratch = [0u8; 64];
+    loop {
+        let line = tokio::select! {
+            // Clients send nothing after the request, so any read result means they are done
+            _ = reader.read(&mut scratch) => return Ok(()),
+            event = subscription.recv() => match event {
+                Ok(event) if wanted(&event) => {
+                    if raw {
+                        format!("{}\n", events::raw_event(&event, &clock))
+                    } else {
+                        let name = daemon.lock().unwrap().display_name(event.name_hash);
+                        events::format_event(&event, &name, &clock) + "\n"
+                    }
+                }
+                Ok(_) => continue,
+                Err(missed) if raw => format!("missed {missed}\n"),
+                Err(missed) => format!("... {missed} event(s) missed\n"),
+            },
+        };
+        writer.write_all(line.as_bytes()).await?;
+    }
+}
+
+/// `names-top [k [secs]]`: the `k` names the datapath handled the most Interests for over the
+/// next `secs` seconds, a `count name` line each, most first.
+async fn top_names(
+    daemon: &Mutex<Daemon>,
+    hub: &Arc<EventHub>,
+    arguments: &str,
+) -> anyhow::Result<String> {
+    let mut arguments = arguments.split_whitespace().map(str::parse::<u64>);
+    let k = arguments.next().transpose()?.unwrap_or(10);
+    let secs = arguments.next().transpose()?.unwrap_or(5);
+    anyhow::ensure!(arguments.next().is_none(), "usage: names-top [k [secs]]");
+    anyhow::ensure!(
+        (1..=MAX_TOP_SECS).contains(&secs),
+        "names-top counts for 1 to {MAX_TOP_SECS} seconds"
+    );
+
+    let mut subscription = hub.subscribe();
+    let mut interests = HashMap::new();
+    let window = sleep(Duration::from_secs(secs));
+    tokio::pin!(window);
+    loop {
+        tokio::select! {
+            () = &mut window => break,
+            event = subscription.recv() => match event {
+                Ok(event) if event.packet_type == TlvType::Interest as u8 => {
+                    *interests.entry(event.name_hash).or_default() += 1;
+                }
+                Ok(_) => {}
+                Err(missed) => debug!("names-top missed {missed} datapath events"),
+            },
+        }
+    }
+    drop(subscription);
+    let daemon = daemon.lock().unwrap();
+    Ok(names::top(&interests, k as usize)
+        .into_iter()
+        .map(|(name_hash, count)| format!("{count:>10} {}\n", daemon.display_name(name_hash)))
+        .collect())
+}
+
+/// Write a line per sampled packet matching `filter` until the client goes away.
+async fn stream_samples(
+    mut stream: UnixStream,
+    daemon: Arc<Mutex<Daemon>>,
+    sampler: Arc<Sampler>,
+    config: Option<SampleConfig>,
+    filter: Filter,
+) -> anyhow::Result<()> {
+    // Packets that are not NDN over UDP only get through an empty filter
+    let wanted = |packet: &CapturedPacket| {
+        filter.is_empty()
+            || capture::subject(packet, |name_hash| daemon.lock().unwrap().name(name_hash))
+                .is_some_and(|subject| filter.matches(&subject))
+    };
+    let mut capture = sampler.subscribe(config);
+    let (mut reader, mut writer) = stream.split();
+    let mut scratch = [0u8; 64];
+    loop {
+        let line = tokio::select! {
+            // Clients send nothing after the request, so any read result means they are done
+            _ = reader.read(&mut scratch) => return Ok(()),
+            packet = capture.recv() => match packet {
+                Ok(packet) => {
+                    if let Some(name) = capture::decoded_name(&packet) {
+                        daemon.lock().unwrap().learn_name(&name);
+                    }
+                    if !wanted(&packet) {
+                        continue;
+                    }
+                    format!("{packet}\n")
+                }
+                Err(missed) => format!("missed {missed}\n"),
+            },
+        };
+        writer.write_all(line.as_bytes()).await?;
+    }
+}
@@ -0,0 +1,277 @@
+//! `udcn convert`: translating stored packets and captures between the native wire format and NDN
+//! packet format v0.3, so those kept from older deployments stay usable as the wire format moves.
+//!
+//! A file holds either a single packet, packets each after its length as a big-endian u32 as
+//! `udcn serve --segment-store` keeps them, or a pcap capture as `udcn capture --write` writes it. The
+//! frames of a capture keep their Ethernet, IPv4 and UDP headers, with the lengths in them fixed
+//! up for the converted payload and the UDP checksum cleared, which IPv4 reads as none. Frames
+//! that were truncated, are not NDN or carry a native Nack are copied over as they are.
+
+use std::fmt;
+
+use anyhow::{bail, Context as _};
+use udcn_client::control::CapturedPacket;
+use udcn_common::{ndn03, Format, NDN_ETHERTYPE, NDN_UDP_PORT};
+
+use crate::capture::PcapWriter;
+
+/// The pcap magic numbers for microsecond and nanosecond timestamps, as written little-endian.
+const PCAP_MAGIC_US: [u8; 4] = [0xd4, 0xc3, 0xb2, 0xa1];
+const PCAP_MAGIC_NS: [u8; 4] = [0x4d, 0x3c, 0xb2, 0xa1];
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// What a conversion wrote.
+#[derive(Debug)]
+pub struct Converted {
+    pub bytes: Vec<u8>,
+    pub converted: usize,
+    /// Capture frames copied over as they were.
+    pub unchanged: usize,
+}
+
+impl fmt::Display for Converted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Converted {} packets", self.converted)?;
+        if self.unchanged > 0 {
+            write!(f, ", copied {} frames unchanged", self.unchanged)?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert the packets in `contents`, which is laid out as one of the files the module describes.
+pub fn convert(contents: &[u8], from: Format, to: Format) -> anyhow::Result<Converted> {
+    match contents.first() {
+        None => bail!("the file is empty"),
+        Some(_) if contents.starts_with(&PCAP_MAGIC_US) || contents.starts_with(&PCAP_MAGIC_NS) => {
+            convert_capture(contents, from, to)
+        }
+        // A packet starts with its type, which is never 0, and a length prefix with 0 for any
+        // packet under 16 MiB
+        Some(0) => convert_stored(contents, from, to),
+        Some(_) => Ok(Converted {
+            bytes: ndn03::convert(contents, from, to)?,
+            converted: 1,
+            unchanged: 0,
+        }),
+    }
+}
+
+fn convert_stored(contents: &[u8], from: Format, to: Format) -> anyhow::Result<Converted> {
+    let mut converted = Converted {
+        bytes: Vec::with_capacity(contents.len()),
+        converted: 0,
+        unchanged: 0,
+    };
+    let mut rest = contents;
+    while !rest.is_empty() {
+        let packet = rest
+            .get(..4)
+            .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+            .and_then(|len| rest.get(4..4 + len))
+            .context("the file is truncated")?;
+        rest = &rest[4 + packet.len()..];
+        let packet = ndn03::convert(packet, from, to)
+            .with_context(|| format!("failed to convert packet {}", converted.converted + 1))?;
+        converted
+            .bytes
+            .extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        converted.bytes.extend_from_slice(&packet);
+        converted.converted += 1;
+    }
+    Ok(converted)
+}
+
+fn convert_capture(contents: &[u8], from: Format, to: Format) -> anyhow::Result<Converted> {
+    let header = contents
+        .get(..PCAP_HEADER_LEN)
+        .context("the capture is truncated")?;
+    let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    if field(20) != LINKTYPE_ETHERNET {
+        bail!("only captures of Ethernet frames can be converted");
+    }
+    let nanos_per_tick = if contents.starts_with(&PCAP_MAGIC_NS) {
+        1
+    } else {
+        1000
+    };
+
+    let mut packets = Vec::new();
+    let mut rest = &contents[PCAP_HEADER_LEN..];
+    while !rest.is_empty() {
+        let record = rest
+            .get(..PCAP_RECORD_HEADER_LEN)
+            .context("the capture is truncated")?;
+        let field = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let data = rest
+            .get(PCAP_RECORD_HEADER_LEN..PCAP_RECORD_HEADER_LEN + field(8) as usize)
+            .context("the capture is truncated")?;
+        rest = &rest[PCAP_RECORD_HEADER_LEN + data.len()..];
+        packets.push(CapturedPacket {
+            timestamp_ns: field(0) as u64 * 1_000_000_000 + field(4) as u64 * nanos_per_tick,
+            ifindex: 0,
+            packet_len: field(12),
+            data: data.to_vec(),
+        });
+    }
+
+    let mut converted = 0;
+    for packet in &mut packets {
+        if let Some(frame) = convert_frame(packet, from, to) {
+            packet.packet_len = frame.len() as u32;
+            packet.data = frame;
+            converted += 1;
+        }
+    }
+    let snaplen = packets
+        .iter()
+        .map(|packet| packet.data.len() as u32)
+        .fold(field(16), u32::max);
+    let mut pcap = PcapWriter::new(Vec::with_capacity(contents.len()), snaplen)?;
+    for packet in &packets {
+        pcap.write(packet)?;
+    }
+    Ok(Converted {
+        bytes: pcap.into_inner(),
+        converted,
+        unchanged: packets.len() - converted,
+    })
+}
+
+/// `packet`'s frame with its NDN payload converted, or `None` to keep it as it is.
+fn convert_frame(packet: &CapturedPacket, from: Format, to: Format) -> Option<Vec<u8>> {
+    let frame = &packet.data;
+    if frame.len() < packet.packet_len as usize {
+        return None;
+    }
+    let ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+    if ethertype == NDN_ETHERTYPE {
+        let payload = ndn03::convert(&frame[14..], from, to).ok()?;
+        return Some([&frame[..14], &payload].concat());
+    }
+
+    if ethertype != 0x0800 || frame.get(14 + 9) != Some(&17) {
+        return None;
+    }
+    let ip_header_len = usize::from(frame[14] & 0x0f) * 4;
+    let udp = 14 + ip_header_len;
+    let ports = frame.get(udp..udp + 4)?;
+    let ndn_port = NDN_UDP_PORT.to_be_bytes();
+    if ports[..2] != ndn_port && ports[2..] != ndn_port {
+        return None;
+    }
+    let payload = ndn03::convert(frame.get(udp + 8..)?, from, to).ok()?;
+
+    let mut converted = [&frame[..udp + 8], &payload].concat();
+    let ip_len = u16::try_from(ip_header_len + 8 + payload.len()).ok()?;
+    converted[16..18].copy_from_slice(&ip_len.to_be_bytes());
+    converted[24..26].fill(0);
+    let checksum = ipv4_checksum(&converted[14..udp]);
+    converted[24..26].copy_from_slice(&checksum.to_be_bytes());
+    converted[udp + 4..udp + 6].copy_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    converted[udp + 6..udp + 8].fill(0);
+    Some(converted)
+}
+
+/// The ones' complement checksum of an IPv4 header whose checksum field is zeroed.
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{Data, Interest, Name};
+
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        let mut ip = [0u8; 20];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(28 + payload.len() as u16).to_be_bytes());
+        ip[9] = 17;
+        ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        let checksum = ipv4_checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&40000u16.to_be_bytes());
+        frame.extend_from_slice(&NDN_UDP_PORT.to_be_bytes());
+        frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0; 2]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_convert_files_and_captures() {
+        let interest = Interest::builder()
+            .name(Name::from_uri("/video/a").unwrap())
+            .nonce(9)
+            .build();
+        let data = Data::builder()
+            .name(Name::from_uri("/video/a").unwrap())
+            .content(&b"frame"[..])
+            .build();
+
+        let single = convert(&interest.encode(), Format::Native, Format::Ndn03).unwrap();
+        assert_eq!(single.bytes, interest.encode_ndn03());
+
+        let mut stored = Vec::new();
+        for packet in [data.encode(), data.encode()] {
+            stored.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            stored.extend_from_slice(&packet);
+        }
+        let converted = convert(&stored, Format::Native, Format::Ndn03).unwrap();
+        assert_eq!(converted.converted, 2);
+        let back = convert(&converted.bytes, Format::Ndn03, Format::Native).unwrap();
+        assert_eq!(back.bytes, stored);
+        assert!(convert(&stored[..stored.len() - 1], Format::Native, Format::Ndn03).is_err());
+
+        let mut pcap = PcapWriter::new(Vec::new(), 1500).unwrap();
+        let packets = [
+            frame(&interest.encode()),
+            frame(&data.encode()),
+            frame(b"not ndn"),
+        ];
+        for (i, data) in packets.iter().enumerate() {
+            pcap.write(&CapturedPacket {
+                timestamp_ns: 1_000_000_000 + i as u64,
+                ifindex: 2,
+                packet_len: data.len() as u32,
+                data: data.clone(),
+            })
+            .unwrap();
+        }
+        let converted = convert(&pcap.into_inner(), Format::Native, Format::Ndn03).unwrap();
+        assert_eq!((converted.converted, converted.unchanged), (2, 1));
+        // The headers now give the converted lengths, and the IPv4 checksum still holds
+        let mut expected = PcapWriter::new(Vec::new(), 1500).unwrap();
+        let frames = [
+            frame(&interest.encode_ndn03()),
+            frame(&data.encode_ndn03()),
+            frame(b"not ndn"),
+        ];
+        for (i, data) in frames.iter().enumerate() {
+            expected
+                .write(&CapturedPacket {
+                    timestamp_ns: 1_000_000_000 + i as u64,
+                    ifindex: 0,
+                    packet_len: data.len() as u32,
+                    data: data.clone(),
+                })
+                .unwrap();
+        }
+        assert_eq!(converted.bytes, expected.into_inner());
+    }
+}
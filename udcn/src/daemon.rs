@@ -0,0 +1,1114 @@
+//! State owned by the running daemon and the requests it answers on the control socket.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::Context as _;
+use aya::maps::{Array, HashMap, MapData};
+use log::{debug, warn};
+use udcn_client::{
+    control::{FaceStatus, OperationTiming, TableOccupancy, TenantStatus},
+    Gates,
+};
+use udcn_common::{
+    cs_bloom_bits, udp_face_id, CacheEntry, CachedPayload, Data, DatapathConfig, Interest, Name,
+    PacketStats, PitEntry, PitLayout, PitRecord, Reputation, CS_BLOOM_WORDS, CS_PARTITION_MAPS,
+    CS_PINNED_CAPACITY, DATA_CACHE_PAYLOAD_LEN, DEFAULT_INTEREST_LIFETIME_MS,
+};
+
+use crate::{
+    alarms::Alarms, autosize::MapSizes, batch, ether, events::ktime_ns, journal::Journal,
+    names::NameTable, nocache::NoCachePrefix, offload::Offloads, partitions::Layout, pins,
+    pit::Pit, prefetch, preload, routes, state, strategies, tenants, tunnels,
+};
+
+/// Pin every map under `dir` so other processes, such as C forwarders using `udcn-ffi`, can open
+/// them. Pins left by a previous run are replaced.
+pub fn pin_maps(ebpf: &aya::Ebpf, dir: &Path) -> anyhow::Result<()> {
+    unpin_maps(dir);
+    fs::create_dir_all(dir)?;
+    for (name, map) in ebpf.maps() {
+        map.pin(dir.join(name))?;
+    }
+    Ok(())
+}
+
+pub fn unpin_maps(dir: &Path) {
+    match fs::remove_dir_all(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        // A daemon running as `--user` can empty the directory it was handed, but not remove it
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            debug!("left {} behind: {e}", dir.display())
+        }
+        Err(e) => warn!("failed to remove pinned maps in {}: {e}", dir.display()),
+    }
+}
+
+/// One content store partition: its map's name, what it is reserved for, its capacity and the
+/// map.
+struct CsPartition {
+    table: &'static str,
+    prefix: Option<Name>,
+    capacity: u32,
+    map: HashMap<MapData, u32, CacheEntry>,
+}
+
+impl CsPartition {
+    /// How `cs partitions` names it.
+    fn label(&self) -> String {
+        match &self.prefix {
+            Some(prefix) => prefix.to_string(),
+            None if self.table == "CS_PROTECTED" => "(shared, protected)".to_string(),
+            None if self.table == pins::TABLE => "(pinned)".to_string(),
+            None => "(shared)".to_string(),
+        }
+    }
+}
+
+pub struct Daemon {
+    pit: Pit,
+    /// The shared partition first, then the reserved ones, then the shared partition's protected
+    /// segment if it is segmented, then the pinned entries.
+    content_store: Vec<CsPartition>,
+    pins: pins::Pins,
+    data_cache: HashMap<MapData, u32, CachedPayload>,
+    data_cache_capacity: u32,
+    cs_bloom: Array<MapData, u64>,
+    cs_bloom_active: Array<MapData, u32>,
+    stats: Array<MapData, PacketStats>,
+    names: NameTable,
+    /// Where `cs-prefetch` requests go, if prefetching is configured.
+    prefetch: Option<Arc<prefetch::Queue>>,
+    /// Prefixes kept out of the content store (`run --no-cache`), which the daemon does not
+    /// cache under either.
+    no_cache: Vec<NoCachePrefix>,
+    gates: Arc<Gates>,
+    /// The daemon's own faces, such as tunnels to other sites.
+    faces: Vec<Arc<tunnels::Face>>,
+    /// Ethernet faces created at runtime.
+    ether_faces: Vec<Arc<ether::Face>>,
+    strategies: strategies::Strategies,
+    routes: routes::Routes,
+    /// The routes the datapath looks Interests up in, for `run --nack-unroutable`.
+    routes_map: HashMap<MapData, u32, u8>,
+    routes_capacity: u32,
+    /// What the datapath was configured with.
+    config: DatapathConfig,
+    /// How each source address misbehaved, by the address.
+    reputation: HashMap<MapData, u32, Reputation>,
+    alarms: Alarms,
+    offloads: Option<Arc<Offloads>>,
+    /// Quotas by namespace, if any tenants are configured.
+    tenants: Option<tenants::Tenants>,
+    timings: batch::Timings,
+    /// Where changes made over the control socket are recorded, if anywhere.
+    journal: Option<Journal>,
+    #[cfg(feature = "faults")]
+    faults: Option<crate::faults::Faults>,
+}
+
+impl Daemon {
+    pub fn new(
+        ebpf: &mut aya::Ebpf,
+        names: NameTable,
+        layout: &Layout,
+        pit_layout: PitLayout,
+        sizes: &MapSizes,
+    ) -> anyhow::Result<Self> {
+        let mut content_store = vec![CsPartition {
+            table: "CONTENT_STORE",
+            prefix: None,
+            capacity: layout.shared,
+            map: HashMap::try_from(ebpf.take_map("CONTENT_STORE").unwrap())?,
+        }];
+        for ((prefix, capacity), map) in layout.reserved.iter().zip(CS_PARTITION_MAPS) {
+            content_store.push(CsPartition {
+                table: map,
+                prefix: Some(prefix.clone()),
+                capacity: *capacity,
+                map: HashMap::try_from(ebpf.take_map(map).unwrap())?,
+            });
+        }
+        if layout.protected > 0 {
+            content_store.push(CsPartition {
+                table: "CS_PROTECTED",
+                prefix: None,
+                capacity: layout.protected,
+                map: HashMap::try_from(ebpf.take_map("CS_PROTECTED").unwrap())?,
+            });
+        }
+        content_store.push(CsPartition {
+            table: pins::TABLE,
+            prefix: None,
+            capacity: CS_PINNED_CAPACITY,
+            map: HashMap::try_from(ebpf.take_map(pins::TABLE).unwrap())?,
+        });
+        Ok(Self {
+            pit: Pit::new(ebpf, pit_layout, sizes.pit)?,
+            content_store,
+            pins: pins::Pins::new(ebpf)?,
+            data_cache: HashMap::try_from(ebpf.take_map("DATA_CACHE").unwrap())?,
+            data_cache_capacity: sizes.data_cache,
+            cs_bloom: Array::try_from(ebpf.take_map("CS_BLOOM").unwrap())?,
+            cs_bloom_active: Array::try_from(ebpf.take_map("CS_BLOOM_ACTIVE").unwrap())?,
+            stats: Array::try_from(ebpf.take_map("STATS").unwrap())?,
+            names,
+            prefetch: None,
+            no_cache: Vec::new(),
+            gates: Arc::default(),
+            faces: Vec::new(),
+            ether_faces: Vec::new(),
+            strategies: strategies::Strategies::default(),
+            routes: routes::Routes::default(),
+            routes_map: HashMap::try_from(ebpf.take_map("ROUTES").unwrap())?,
+            routes_capacity: sizes.routes,
+            config: Array::<_, DatapathConfig>::try_from(ebpf.map("CONFIG").unwrap())?
+                .get(&0, 0)?,
+            reputation: HashMap::try_from(ebpf.take_map("REPUTATION").unwrap())?,
+            alarms: Alarms::default(),
+            offloads: None,
+            tenants: None,
+            timings: batch::Timings::default(),
+            journal: None,
+            #[cfg(feature = "faults")]
+            faults: None,
+        })
+    }
+
+    pub fn stats(&self) -> anyhow::Result<PacketStats> {
+        Ok(self.stats.get(&0, 0)?)
+    }
+
+    /// How full the PIT, each content store partition, the Data cache and `ROUTES` are, by reading
+    /// them.
+    pub fn tables(&self) -> anyhow::Result<Vec<TableOccupancy>> {
+        let start = Instant::now();
+        let occupancy = |table: &str, entries: usize, capacity| TableOccupancy {
+            table: table.to_string(),
+            entries: entries as u32,
+            capacity,
+        };
+        let (pit, batched) = self.pit.entries()?;
+        let mut tables = vec![occupancy("PIT", pit.len(), self.pit.capacity())];
+        for partition in &self.content_store {
+            let (entries, _) = batch::entries(&partition.map)?;
+            tables.push(occupancy(
+                partition.table,
+                entries.len(),
+                partition.capacity,
+            ));
+        }
+        let (data, _) = batch::entries(&self.data_cache)?;
+        tables.push(occupancy(
+            "DATA_CACHE",
+            data.len(),
+            self.data_cache_capacity,
+        ));
+        let (routes, _) = batch::entries(&self.routes_map)?;
+        tables.push(occupancy("ROUTES", routes.len(), self.routes_capacity));
+        let entries = tables.iter().map(|table| table.entries as usize).sum();
+        self.timings.record("table_scrape", start, entries, batched);
+        Ok(tables)
+    }
+
+    /// Rebuild the inactive half of the content store Bloom filter from what the partitions hold
+    /// now, then make the datapath use it. Bits of evicted entries only go away this way.
+    pub fn rebuild_cs_bloom(&mut self) -> anyhow::Result<()> {
+        let start = Instant::now();
+        let inactive = (self.cs_bloom_active.get(&0, 0)? & 1) ^ 1;
+        // Entries cached from here on set their bits in both halves, so the half is cleared
+        // before the partitions are read and what they hold is ORed in, never written over them
+        for index in 0..CS_BLOOM_WORDS {
+            self.cs_bloom.set(inactive * CS_BLOOM_WORDS + index, 0, 0)?;
+        }
+        let (cached, batched) = self.or_cs_bloom(inactive)?;
+        // A bit the datapath sets between one of those reads and its write is lost; a second
+        // pass puts back any an entry cached meanwhile needs
+        self.or_cs_bloom(inactive)?;
+        self.cs_bloom_active.set(0, inactive, 0)?;
+        self.timings
+            .record("cs_bloom_rebuild", start, cached, batched);
+        Ok(())
+    }
+
+    /// Set the bits of every name the partitions hold in `half` of the content store Bloom
+    /// filter, writing only the words missing some. Returns how many entries were read and
+    /// whether in batches.
+    fn or_cs_bloom(&mut self, half: u32) -> anyhow::Result<(usize, bool)> {
+        let mut words = vec![0u64; CS_BLOOM_WORDS as usize];
+        let (mut cached, mut batched) = (0, true);
+        for partition in &self.content_store {
+            let (entries, partition_batched) = batch::entries(&partition.map)?;
+            (cached, batched) = (cached + entries.len(), batched && partition_batched);
+            for (name_hash, _) in entries {
+                for bit in cs_bloom_bits(name_hash) {
+                    words[bit as usize / 64] |= 1 << (bit % 64);
+                }
+            }
+        }
+        for (index, word) in words.into_iter().enumerate() {
+            let index = half * CS_BLOOM_WORDS + index as u32;
+            let current = self.cs_bloom.get(&index, 0)?;
+            if current | word != current {
+                self.cs_bloom.set(index, current | word, 0)?;
+            }
+        }
+        Ok((cached, batched))
+    }
+
+    /// Empty every content store partition and the Data cache, except for pinned Data. Returns
+    /// how many entries went.
+    pub fn flush_content_store(&mut self) -> anyhow::Result<usize> {
+        let start = Instant::now();
+        let (mut flushed, mut batched) = (0, true);
+        let (pinned, _) = batch::entries(&self.pinned().map)?;
+        for partition in &mut self.content_store {
+            if partition.table == pins::TABLE {
+                continue;
+            }
+            let (entries, _) = batch::entries(&partition.map)?;
+            let keys: Vec<u32> = entries
+                .into_iter()
+                .map(|(name_hash, _)| name_hash)
+                .collect();
+            let (removed, partition_batched) = batch::delete(&mut partition.map, &keys)?;
+            (flushed, batched) = (flushed + removed, batched && partition_batched);
+        }
+        let (data, _) = batch::entries(&self.data_cache)?;
+        let keys: Vec<u32> = data
+            .into_iter()
+            .map(|(name_hash, _)| name_hash)
+            .filter(|name_hash| !pinned.iter().any(|(pinned, _)| pinned == name_hash))
+            .collect();
+        batch::delete(&mut self.data_cache, &keys)?;
+        self.timings.record("cs_flush", start, flushed, batched);
+        // The Bloom filter forgets the names at its next rebuild; until then they only cost a
+        // lookup
+        Ok(flushed)
+    }
+
+    /// Remove the Data cached under exactly `name` from its partition and the Data cache, as
+    /// consumers ask for Data that failed validation, and return how many entries went. Pinned
+    /// Data goes too.
+    pub fn purge_content(&mut self, name: &Name) -> usize {
+        let start = Instant::now();
+        let name_hash = name.name_hash();
+        let mut purged = 0;
+        for partition in &mut self.content_store {
+            purged += usize::from(partition.map.remove(&name_hash).is_ok());
+        }
+        purged += usize::from(self.data_cache.remove(&name_hash).is_ok());
+        self.timings.record("cs_purge", start, purged, false);
+        purged
+    }
+
+    /// Free the downstream records that lapsed by `now` in every PIT entry, removing entries left
+    /// with none, and return them. A record the datapath adds to an entry between the read and
+    /// the write back is lost; its face retransmits.
+    pub fn expire_pit_records(&mut self, now: u64) -> anyhow::Result<Vec<(u32, PitRecord)>> {
+        let start = Instant::now();
+        let mut lapsed = Vec::new();
+        let (mut changed, mut emptied) = (Vec::new(), Vec::new());
+        let (entries, batched) = self.pit.entries()?;
+        let swept = entries.len();
+        for (name_hash, mut entry) in entries {
+            let before = lapsed.len();
+            entry.expire(now, |record| lapsed.push((name_hash, *record)));
+            if lapsed.len() == before {
+                continue;
+            }
+            if entry.is_empty() {
+                emptied.push(name_hash);
+            } else {
+                changed.push((name_hash, entry));
+            }
+        }
+        // Either way, an entry the datapath removed meanwhile stays removed
+        self.pit.update_existing(&changed)?;
+        self.pit.delete(&emptied)?;
+        self.timings.record("pit_gc", start, swept, batched);
+        Ok(lapsed)
+    }
+
+    /// How long table management has taken, by operation.
+    pub fn timings(&self) -> Vec<OperationTiming> {
+        self.timings.snapshot()
+    }
+
+    pub fn set_alarms(&mut self, alarms: Alarms) {
+        self.alarms = alarms;
+    }
+
+    /// Take a reading of the counters and tables and update the alarms with it.
+    pub fn evaluate_alarms(&mut self) -> anyhow::Result<()> {
+        let (stats, tables) = (self.stats()?, self.tables()?);
+        self.alarms.evaluate(Instant::now(), stats, &tables);
+        Ok(())
+    }
+
+    pub fn set_offloads(&mut self, offloads: Arc<Offloads>) {
+        self.offloads = Some(offloads);
+    }
+
+    pub fn set_tenants(&mut self, tenants: tenants::Tenants) {
+        self.tenants = Some(tenants);
+    }
+
+    pub fn tenants(&self) -> anyhow::Result<Vec<TenantStatus>> {
+        match &self.tenants {
+            Some(tenants) => tenants.statuses(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Recount each tenant's PIT entries and content store bytes, evict the oldest entries of
+    /// tenants over a quota, and write the counts over the datapath's. Faces waiting on an
+    /// evicted PIT entry go without the Data and retransmit.
+    pub fn sync_tenants(&mut self) -> anyhow::Result<()> {
+        let Some(tenants) = &mut self.tenants else {
+            return Ok(());
+        };
+        let start = Instant::now();
+        let (entries, mut batched) = self.pit.entries()?;
+        let mut counted = entries.len();
+        let pending = entries
+            .into_iter()
+            .map(|(name_hash, entry)| tenants::Held {
+                tenant: entry.tenant,
+                name_hash,
+                timestamp: entry.timestamp,
+                amount: 1,
+                pinned: false,
+            })
+            .collect();
+        let (pit_entries, evicted) =
+            tenants::enforce(pending, |tenant| tenants.quota(tenant, true));
+        self.pit.delete(&evicted)?;
+
+        let mut cached = Vec::new();
+        for partition in &self.content_store {
+            let (entries, partition_batched) = batch::entries(&partition.map)?;
+            (counted, batched) = (counted + entries.len(), batched && partition_batched);
+            cached.extend(entries.into_iter().map(|(name_hash, entry)| tenants::Held {
+                tenant: u32::from(entry.tenant),
+                name_hash,
+                timestamp: entry.timestamp,
+                amount: u32::from(entry.data_size),
+                pinned: entry.pinned != 0,
+            }));
+        }
+        let (cs_bytes, evicted) = tenants::enforce(cached, |tenant| tenants.quota(tenant, false));
+        for name_hash in &evicted {
+            for partition in &mut self.content_store {
+                let _ = partition.map.remove(name_hash);
+            }
+            let _ = self.data_cache.remove(name_hash);
+        }
+
+        tenants.set_usage(&pit_entries, &cs_bytes)?;
+        self.timings.record("tenant_sync", start, counted, batched);
+        Ok(())
+    }
+
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Record a control request that succeeded in the journal, if it changed anything.
+    pub fn journal(&mut self, request: &str) {
+        if let Some(journal) = &mut self.journal {
+            journal.record(request);
+        }
+    }
+
+    #[cfg(feature = "faults")]
+    pub fn set_faults(&mut self, faults: crate::faults::Faults) {
+        self.faults = Some(faults);
+    }
+
+    pub fn set_prefetch_queue(&mut self, queue: Arc<prefetch::Queue>) {
+        self.prefetch = Some(queue);
+    }
+
+    /// Where the prefetcher fetches from, if prefetching is configured.
+    pub fn prefetch_upstream(&self) -> Option<std::net::SocketAddr> {
+        self.prefetch.as_ref().map(|queue| queue.upstream)
+    }
+
+    pub fn set_no_cache(&mut self, no_cache: Vec<NoCachePrefix>) {
+        self.no_cache = no_cache;
+    }
+
+    pub fn no_cache(&self) -> &[NoCachePrefix] {
+        &self.no_cache
+    }
+
+    pub fn set_gates(&mut self, gates: Arc<Gates>) {
+        self.gates = gates;
+    }
+
+    /// The gated namespaces the tunnels check Interests against.
+    pub fn gates(&self) -> Arc<Gates> {
+        self.gates.clone()
+    }
+
+    pub fn set_faces(&mut self, faces: Vec<Arc<tunnels::Face>>) {
+        self.faces = faces;
+    }
+
+    /// List `face`, unless the interface already has one.
+    pub fn add_ether_face(&mut self, face: Arc<ether::Face>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.ether_faces
+                .iter()
+                .all(|other| other.interface() != face.interface()),
+            "{} already has an Ethernet face",
+            face.interface()
+        );
+        self.ether_faces.push(face);
+        Ok(())
+    }
+
+    pub fn set_strategies(&mut self, strategies: strategies::Strategies) {
+        self.strategies = strategies;
+    }
+
+    /// Take over `routes`, which are already installed in the datapath.
+    pub fn set_routes(&mut self, routes: routes::Routes) {
+        self.routes = routes;
+    }
+
+    /// Route `prefix`, in the datapath as well, keeping it unless `ephemeral`.
+    fn add_route(&mut self, prefix: NoCachePrefix, ephemeral: bool) -> anyhow::Result<()> {
+        let key = prefix.key();
+        self.routes.add(prefix, ephemeral)?;
+        Ok(self.routes_map.insert(key, 1, 0)?)
+    }
+
+    pub fn faces(&self) -> Vec<FaceStatus> {
+        let tunnels = self.faces.iter().map(|face| face.status());
+        tunnels
+            .chain(self.ether_faces.iter().map(|face| face.status()))
+            .collect()
+    }
+
+    /// How many of `name_hashes` each content store partition and segment holds.
+    pub fn cs_tiers(&self, name_hashes: &[u32]) -> anyhow::Result<Vec<preload::Tier>> {
+        let wanted: std::collections::HashSet<_> = name_hashes.iter().collect();
+        self.content_store
+            .iter()
+            .map(|partition| {
+                let (entries, _) = batch::entries(&partition.map)?;
+                Ok(preload::Tier {
+                    tier: partition.label(),
+                    capacity: partition.capacity,
+                    preloaded: entries
+                        .iter()
+                        .filter(|(name_hash, _)| wanted.contains(name_hash))
+                        .count(),
+                })
+            })
+            .collect()
+    }
+
+    /// Whether any content store partition holds `name_hash`.
+    pub fn is_cached(&self, name_hash: u32) -> bool {
+        self.content_store
+            .iter()
+            .any(|partition| partition.map.get(&name_hash, 0).is_ok())
+    }
+
+    /// Add a PIT entry for `interest` from `face`, the daemon's own, so the datapath takes the
+    /// Data answering it as solicited. A pending entry for the name is left as it is.
+    pub fn expect_data(&mut self, interest: &Interest, face: SocketAddrV4) -> anyhow::Result<()> {
+        let now = ktime_ns();
+        let lifetime_ms = interest
+            .lifetime_ms
+            .unwrap_or(u64::from(DEFAULT_INTEREST_LIFETIME_MS));
+        let (addr, port) = (face.ip().octets(), face.port().to_be_bytes());
+        let record = PitRecord {
+            face_id: udp_face_id(addr, port),
+            nonce: interest.nonce,
+            addr,
+            local_addr: [0; 4],
+            port,
+            reserved: [0; 6],
+            expires_at: now + lifetime_ms * 1_000_000,
+        };
+        let mut entry = PitEntry::new(self.names.insert(&interest.name), record, now);
+        if let Some(tenants) = &mut self.tenants {
+            entry.tenant = tenants.tenant_of(&interest.name);
+            tenants.admit(entry.tenant, true, 1)?;
+        }
+        self.pit.insert_new(entry)
+    }
+
+    /// Cache `data`, fetched ahead of demand, in the partition the datapath would pick for it,
+    /// marked as prefetched so the Interests it answers are counted.
+    pub fn cache_prefetched(&mut self, data: &Data) -> anyhow::Result<()> {
+        let now = ktime_ns();
+        let freshness_ms = data.meta.freshness_period_ms.unwrap_or(0);
+        let entry = CacheEntry {
+            name_hash: self.names.insert(&data.name),
+            data_size: data.content.len().min(u16::MAX as usize) as u16,
+            prefetched: 1,
+            tenant: 0,
+            timestamp: now,
+            fresh_until: now.saturating_add(freshness_ms.saturating_mul(1_000_000)),
+            pinned: 0,
+            reserved: [0; 7],
+        };
+        self.cache(Some(&data.name), entry)
+    }
+
+    /// Cache `entry` for Data named `name`, if the daemon knows it, counting it against its
+    /// tenant's quota and putting it where the datapath would: with the pinned entries if a pin
+    /// covers it, or else in its partition. The payload kept for an older copy goes. Data the
+    /// datapath would keep out is refused, and so is Data of an unknown name while any prefix is
+    /// gated, since the datapath would answer a gated Interest for it without a token, or while
+    /// any partition is reserved, since Interests for it may look in that partition.
+    fn cache(&mut self, name: Option<&Name>, mut entry: CacheEntry) -> anyhow::Result<()> {
+        match name {
+            Some(name) => anyhow::ensure!(
+                !self.no_cache.iter().any(|prefix| prefix.0.is_prefix_of(name)),
+                "'{name}' is under a non-cacheable prefix"
+            ),
+            None => anyhow::ensure!(self.gates.is_empty(), "the name may be gated"),
+        }
+        if let Some(tenants) = &mut self.tenants {
+            let tenant = name.map_or(0, |name| tenants.tenant_of(name));
+            tenants.admit(tenant, false, u32::from(entry.data_size))?;
+            entry.tenant = tenant as u8;
+        }
+        let pinned = self.pins.covers(entry.name_hash, name);
+        entry.pinned = pinned as u8;
+        let partition = match pinned {
+            true => self.pinned(),
+            false => self
+                .partition_for(name)
+                .context("the name may be under a reserved partition's prefix")?,
+        };
+        partition
+            .map
+            .insert(entry.name_hash, entry, 0)
+            .with_context(|| format!("failed to insert into {}", partition.table))?;
+        let _ = self.data_cache.remove(&entry.name_hash);
+
+        // Set in both halves, as the datapath does; a bit it sets in the same word meanwhile is
+        // lost until the next rebuild
+        for half in 0..2 {
+            for bit in cs_bloom_bits(entry.name_hash) {
+                let index = half * CS_BLOOM_WORDS + bit / 64;
+                let word = self.cs_bloom.get(&index, 0)?;
+                self.cs_bloom.set(index, word | 1 << (bit % 64), 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The names, strategy overrides, pins and cached entries the daemon holds, with what the
+    /// Data cache holds for each entry if `payloads`, leaving out payloads of older copies.
+    pub fn export_state(&self, payloads: bool) -> anyhow::Result<state::State> {
+        let now = ktime_ns();
+        let mut cached = Vec::new();
+        for partition in &self.content_store {
+            for (name_hash, entry) in batch::entries(&partition.map)?.0 {
+                let payload = payloads
+                    .then(|| self.data_cache.get(&name_hash, 0).ok())
+                    .flatten()
+                    .filter(|payload| payload.belongs_to(&entry))
+                    .map(|payload| payload.data.to_vec());
+                cached.push(state::Cached::new(&entry, now, payload));
+            }
+        }
+        Ok(state::State {
+            version: state::VERSION,
+            exported_at_unix_ms: state::unix_ms(),
+            names: self.names.uris().map(str::to_string).collect(),
+            strategies: self.strategies.override_lines(),
+            routes: self
+                .routes
+                .saved()
+                .iter()
+                .map(|route| route.0.to_string())
+                .collect(),
+            pins: self.pins.pins().iter().map(ToString::to_string).collect(),
+            cached,
+        })
+    }
+
+    /// Take on the state another daemon exported: its names, strategy overrides, routes and pins,
+    /// then its cached entries wherever this daemon's layout and pins put them. Entries already
+    /// cached here are left as they are.
+    pub fn import_state(&mut self, state: &state::State) -> anyhow::Result<state::Imported> {
+        anyhow::ensure!(
+            state.version == state::VERSION,
+            "cannot import state of version {}, only {}",
+            state.version,
+            state::VERSION
+        );
+        let mut imported = state::Imported::default();
+        for uri in &state.names {
+            self.names.insert(&Name::from_uri(uri)?);
+            imported.names += 1;
+        }
+        for line in &state.strategies {
+            let (prefix, choice) = strategies::parse_line(line)?;
+            self.strategies.set(prefix, choice)?;
+            imported.strategies += 1;
+        }
+        for route in &state.routes {
+            self.add_route(route.parse()?, false)?;
+            imported.routes += 1;
+        }
+        for pin in &state.pins {
+            let pin: pins::Pin = pin.parse()?;
+            if !self.pins.pins().contains(&pin) {
+                self.pin_content(pin)?;
+                imported.pins += 1;
+            }
+        }
+
+        let now = ktime_ns();
+        for cached in &state.cached {
+            if self.is_cached(cached.name_hash) {
+                imported.already_cached += 1;
+                continue;
+            }
+            let name = self.name(cached.name_hash);
+            let entry = cached.entry(now);
+            let result = self.cache(name.as_ref(), entry).and_then(|()| {
+                let Some(payload) = &cached.payload else {
+                    return Ok(());
+                };
+                let payload = CachedPayload {
+                    version: entry.timestamp,
+                    data: payload.as_slice().try_into().with_context(|| {
+                        format!("a payload is not {DATA_CACHE_PAYLOAD_LEN} bytes")
+                    })?,
+                };
+                Ok(self.data_cache.insert(cached.name_hash, payload, 0)?)
+            });
+            match result {
+                Ok(()) => imported.cached += 1,
+                Err(e) => {
+                    let name = self.display_name(cached.name_hash);
+                    debug!("failed to import '{name}': {e:#}");
+                    imported.failed += 1;
+                }
+            }
+        }
+        Ok(imported)
+    }
+
+    /// The datapath announced caching a copy of `name_hash`, deleting its payload as it did.
+    /// Delete one written since for an older copy, as an import racing with it may have, and
+    /// return whether there was one.
+    pub fn datapath_cached(&mut self, name_hash: u32) -> bool {
+        let Ok(payload) = self.data_cache.get(&name_hash, 0) else {
+            return false;
+        };
+        let entry = self
+            .content_store
+            .iter()
+            .find_map(|partition| partition.map.get(&name_hash, 0).ok());
+        if entry.is_some_and(|entry| payload.belongs_to(&entry)) {
+            return false;
+        }
+        self.data_cache.remove(&name_hash).is_ok()
+    }
+
+    /// Delete every payload that does not belong to the entry cached for its name, for when the
+    /// datapath's announcements were missed. Returns how many went.
+    pub fn drop_stale_payloads(&mut self) -> anyhow::Result<usize> {
+        let (payloads, _) = batch::entries(&self.data_cache)?;
+        let stale: Vec<u32> = payloads
+            .into_iter()
+            .filter(|(name_hash, payload)| {
+                !self.content_store.iter().any(|partition| {
+                    partition
+                        .map
+                        .get(name_hash, 0)
+                        .is_ok_and(|entry| payload.belongs_to(&entry))
+                })
+            })
+            .map(|(name_hash, _)| name_hash)
+            .collect();
+        Ok(batch::delete(&mut self.data_cache, &stale)?.0)
+    }
+
+    /// The pinned entries, which come last.
+    fn pinned(&mut self) -> &mut CsPartition {
+        self.content_store.last_mut().unwrap()
+    }
+
+    /// The partition the datapath looks Data named `name` up in when it is not pinned. Data the
+    /// daemon cannot name only has one while no partition is reserved.
+    fn partition_for(&mut self, name: Option<&Name>) -> Option<&mut CsPartition> {
+        let reserved = |partition: &CsPartition| partition.prefix.is_some();
+        let index = match name {
+            Some(name) => self.content_store.iter().position(|partition| {
+                partition
+                    .prefix
+                    .as_ref()
+                    .is_some_and(|prefix| prefix.is_prefix_of(name))
+            }),
+            None if self.content_store.iter().any(reserved) => return None,
+            None => None,
+        };
+        Some(&mut self.content_store[index.unwrap_or(0)])
+    }
+
+    /// Pin `pin`, moving the Data already cached under it into `CS_PINNED` while there is room.
+    /// Returns how many entries moved.
+    pub fn pin_content(&mut self, pin: pins::Pin) -> anyhow::Result<usize> {
+        if let pins::Pin::Name(name) = &pin {
+            self.names.insert(name);
+        }
+        anyhow::ensure!(self.pins.pin(pin.clone())?, "'{pin}' is already pinned");
+        let mut moving = Vec::new();
+        for (index, partition) in self.content_store.iter().enumerate() {
+            if partition.table == pins::TABLE {
+                continue;
+            }
+            for (name_hash, entry) in batch::entries(&partition.map)?.0 {
+                if pin.matches(name_hash, self.name(name_hash).as_ref()) {
+                    moving.push((index, name_hash, entry));
+                }
+            }
+        }
+        let mut moved = 0;
+        for (index, name_hash, entry) in moving {
+            let entry = CacheEntry { pinned: 1, ..entry };
+            if self.pinned().map.insert(name_hash, entry, 0).is_err() {
+                warn!(
+                    "{} is full, leaving Data under '{pin}' unpinned",
+                    pins::TABLE
+                );
+                break;
+            }
+            let _ = self.content_store[index].map.remove(&name_hash);
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    /// Unpin `pin`, moving the pinned Data no other pin covers back to its partition. Returns how
+    /// many entries moved.
+    pub fn unpin_content(&mut self, pin: &pins::Pin) -> anyhow::Result<usize> {
+        anyhow::ensure!(self.pins.unpin(pin)?, "'{pin}' is not pinned");
+        let mut moved = 0;
+        for (name_hash, entry) in batch::entries(&self.pinned().map)?.0 {
+            let name = self.name(name_hash);
+            if self.pins.covers(name_hash, name.as_ref()) {
+                continue;
+            }
+            let entry = CacheEntry { pinned: 0, ..entry };
+            let Some(partition) = self.partition_for(name.as_ref()) else {
+                // Interests may look for it in any partition, so it goes rather than be missed
+                let _ = self.pinned().map.remove(&name_hash);
+                continue;
+            };
+            partition
+                .map
+                .insert(name_hash, entry, 0)
+                .with_context(|| format!("failed to insert into {}", partition.table))?;
+            let _ = self.pinned().map.remove(&name_hash);
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    /// The name `name_hash` was registered as, if it was.
+    pub fn name(&self, name_hash: u32) -> Option<Name> {
+        Name::from_uri(self.names.resolve(name_hash)?).ok()
+    }
+
+    /// Remember `name`, seen outside the datapath, for listings to show.
+    pub fn learn_name(&mut self, name: &Name) {
+        self.names.insert(name);
+    }
+
+    /// `name_hash` as a name URI, if the daemon has been told about it.
+    pub fn display_name(&self, name_hash: u32) -> String {
+        self.names.display(name_hash)
+    }
+
+    pub fn handle(&mut self, request: &str) -> anyhow::Result<String> {
+        let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+        match command {
+            "stats" => Ok(serde_json::to_string(&self.stats()?)? + "\n"),
+            "tables" => Ok(serde_json::to_string(&self.tables()?)? + "\n"),
+            "alarms" => Ok(serde_json::to_string(&self.alarms.statuses())? + "\n"),
+            "timings" => Ok(serde_json::to_string(&self.timings())? + "\n"),
+            "faces" => Ok(serde_json::to_string(&self.faces())? + "\n"),
+            "tenants" => Ok(serde_json::to_string(&self.tenants()?)? + "\n"),
+            "register" => {
+                let name = Name::from_uri(argument)?;
+                let name_hash = self.names.insert(&name);
+                Ok(format!("{name_hash:#010x}\n"))
+            }
+            "strategy" => {
+                let name = Name::from_uri(argument)?;
+                Ok(serde_json::to_string(&self.strategies.effective(&name))? + "\n")
+            }
+            "strategy-list" => Ok(self.strategies.format_table()),
+            "strategy-set" => {
+                let (prefix, choice) = strategies::parse_line(argument)?;
+                let message = format!("Set the strategy for '{prefix}' to {choice}\n");
+                self.strategies.set(prefix, choice)?;
+                Ok(message)
+            }
+            "strategy-unset" => {
+                let prefix = Name::from_uri(argument)?;
+                anyhow::ensure!(
+                    self.strategies.unset(&prefix)?,
+                    "no strategy was set for '{prefix}'"
+                );
+                Ok(format!("Unset the strategy for '{prefix}'\n"))
+            }
+            "route-list" => Ok(self.routes.format_table()),
+            "route-add" => {
+                let (ephemeral, prefix) = match argument.strip_prefix("--ephemeral ") {
+                    Some(prefix) => (true, prefix),
+                    None => (false, argument),
+                };
+                let prefix: NoCachePrefix = prefix.parse()?;
+                let message = if ephemeral {
+                    format!("Routed '{}' until the daemon stops\n", prefix.0)
+                } else {
+                    format!("Routed '{}'\n", prefix.0)
+                };
+                self.add_route(prefix, ephemeral)?;
+                Ok(message)
+            }
+            "route-remove" => {
+                let prefix: NoCachePrefix = argument.parse()?;
+                if self.routes.remove(&prefix)? {
+                    return Ok(format!(
+                        "Removed the route for '{}', which the configuration still routes\n",
+                        prefix.0
+                    ));
+                }
+                if let Err(e) = self.routes_map.remove(&prefix.key()) {
+                    debug!("'{}' was not in the datapath's routes: {e}", prefix.0);
+                }
+                Ok(format!("Removed the route for '{}'\n", prefix.0))
+            }
+            "acl-list-dynamic" => self.list_reputation(),
+            "acl-clear-dynamic" => {
+                if argument.is_empty() {
+                    let cleared = self.clear_reputation()?;
+                    return Ok(format!("Cleared {cleared} source addresses\n"));
+                }
+                let addr: Ipv4Addr = argument.parse()?;
+                anyhow::ensure!(
+                    self.reputation.remove(&u32::from(addr)).is_ok(),
+                    "{addr} has no record"
+                );
+                Ok(format!("Cleared {addr}\n"))
+            }
+            "acl-report" => {
+                let addr: Ipv4Addr = argument.parse()?;
+                anyhow::ensure!(
+                    self.config.greylist_offences > 0,
+                    "greylisting is off; run the daemon with --greylist-after"
+                );
+                let (key, now) = (u32::from(addr), ktime_ns());
+                let mut reputation = self.reputation.get(&key, 0).unwrap_or(Reputation {
+                    window_start: now,
+                    ..Reputation::default()
+                });
+                reputation.offend(now, &self.config);
+                self.reputation.insert(key, reputation, 0)?;
+                Ok(if reputation.is_greylisted(now) {
+                    format!("Greylisted {addr}\n")
+                } else {
+                    format!("Counted an offence against {addr}\n")
+                })
+            }
+            "cs-list" => self.list_content_store(),
+            "cs-partitions" => self.list_cs_partitions(),
+            "pit-list" => self.list_pit(),
+            "cs-flush" => {
+                let flushed = self.flush_content_store()?;
+                Ok(format!("Flushed {flushed} content store entries\n"))
+            }
+            "cs-purge" => {
+                let name = Name::from_uri(argument)?;
+                let purged = self.purge_content(&name);
+                Ok(format!(
+                    "Purged {purged} content store entries for '{name}'\n"
+                ))
+            }
+            "cs-pin" => {
+                let pin: pins::Pin = argument.parse()?;
+                let moved = self.pin_content(pin.clone())?;
+                Ok(format!("Pinned '{pin}', moving {moved} cached entries\n"))
+            }
+            "cs-unpin" => {
+                let pin: pins::Pin = argument.parse()?;
+                let moved = self.unpin_content(&pin)?;
+                Ok(format!("Unpinned '{pin}', moving {moved} cached entries\n"))
+            }
+            "cs-pins" => self.list_pins(),
+            "state-export" => {
+                let payloads = match argument {
+                    "" => false,
+                    "payloads" => true,
+                    _ => anyhow::bail!("unknown parameter '{argument}'"),
+                };
+                Ok(serde_json::to_string(&self.export_state(payloads)?)? + "\n")
+            }
+            "cs-prefetch" => {
+                let prefix = Name::from_uri(argument)?;
+                let queue = self
+                    .prefetch
+                    .as_ref()
+                    .context("prefetching is off; configure it in the [prefetch] section")?;
+                anyhow::ensure!(queue.warm(prefix.clone()), "the prefetch queue is full");
+                Ok(format!("Queued '{prefix}' for prefetching\n"))
+            }
+            "offload" => Ok(self
+                .offloads
+                .as_ref()
+                .context(
+                    "no interface is in offload mode; start the daemon with --xdp-mode offload",
+                )?
+                .format_table()),
+            #[cfg(feature = "faults")]
+            "fault" => self
+                .faults
+                .as_mut()
+                .context("fault injection is not set up")?
+                .handle(argument),
+            _ => anyhow::bail!("unknown command '{command}'"),
+        }
+    }
+
+    fn list_content_store(&self) -> anyhow::Result<String> {
+        let now = ktime_ns();
+        let mut out = format!("{:<48} {:>10} {:>12}\n", "NAME", "SIZE", "FRESH");
+        for partition in &self.content_store {
+            for entry in partition.map.iter() {
+                let (name_hash, entry) = entry?;
+                let fresh = match entry.fresh_until.checked_sub(now) {
+                    Some(remaining) if remaining > 0 => format!("{} ms", remaining / 1_000_000),
+                    _ => "stale".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{:<48} {:>10} {:>12}",
+                    self.names.display(name_hash),
+                    entry.data_size,
+                    fresh
+                )?;
+            }
+        }
+        Ok(out)
+    }
+
+    fn list_pins(&self) -> anyhow::Result<String> {
+        let (pinned, _) = batch::entries(&self.content_store.last().unwrap().map)?;
+        let pins: Vec<_> = self
+            .pins
+            .pins()
+            .iter()
+            .map(|pin| {
+                let cached = pinned
+                    .iter()
+                    .filter(|(name_hash, _)| {
+                        pin.matches(*name_hash, self.name(*name_hash).as_ref())
+                    })
+                    .count();
+                (pin.clone(), cached)
+            })
+            .collect();
+        Ok(pins::format_pins(&pins))
+    }
+
+    fn list_cs_partitions(&self) -> anyhow::Result<String> {
+        let mut out = format!(
+            "{:<32} {:>10} {:>10} {:>6}\n",
+            "PREFIX", "CAPACITY", "ENTRIES", "USED"
+        );
+        for partition in &self.content_store {
+            let entries = batch::entries(&partition.map)?.0.len() as u32;
+            writeln!(
+                out,
+                "{:<32} {:>10} {:>10} {:>5.1}%",
+                partition.label(),
+                partition.capacity,
+                entries,
+                entries as f64 * 100.0 / partition.capacity as f64
+            )?;
+        }
+        Ok(out)
+    }
+
+    /// The source addresses that misbehaved, and whether each is greylisted.
+    fn list_reputation(&self) -> anyhow::Result<String> {
+        let now = ktime_ns();
+        let mut out = format!(
+            "{:<16} {:>8} {:>16} {:>10}\n",
+            "ADDRESS", "OFFENCES", "GREYLISTED", "DROPPED"
+        );
+        let mut entries = batch::entries(&self.reputation)?.0;
+        entries.sort_by_key(|(addr, _)| *addr);
+        for (addr, reputation) in entries {
+            let greylisted = if reputation.is_greylisted(now) {
+                format!(
+                    "{} s more",
+                    (reputation.greylisted_until - now).div_ceil(1_000_000_000)
+                )
+            } else {
+                "-".to_string()
+            };
+            writeln!(
+                out,
+                "{:<16} {:>8} {:>16} {:>10}",
+                Ipv4Addr::from(addr).to_string(),
+                reputation.offences,
+                greylisted,
+                reputation.dropped
+            )?;
+        }
+        Ok(out)
+    }
+
+    /// Forget every source address's offences, lifting their greylisting. Returns how many.
+    fn clear_reputation(&mut self) -> anyhow::Result<usize> {
+        let (entries, _) = batch::entries(&self.reputation)?;
+        let addrs: Vec<u32> = entries.into_iter().map(|(addr, _)| addr).collect();
+        Ok(batch::delete(&mut self.reputation, &addrs)?.0)
+    }
+
+    /// One line per downstream face, with how long until its record lapses.
+    fn list_pit(&self) -> anyhow::Result<String> {
+        let now = ktime_ns();
+        let mut out = format!("{:<48} {:>10} {:>12}\n", "NAME", "FACE", "EXPIRES");
+        for (name_hash, entry) in self.pit.entries()?.0 {
+            for record in entry.records.iter().filter(|record| !record.is_free()) {
+                let expires = match record.expires_at.checked_sub(now) {
+                    Some(remaining) => format!("{} ms", remaining / 1_000_000),
+                    None => "lapsed".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{:<48} {:#010x} {:>12}",
+                    self.names.display(name_hash),
+                    record.face_id,
+                    expires
+                )?;
+            }
+        }
+        Ok(out)
+    }
+}
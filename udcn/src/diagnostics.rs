@@ -0,0 +1,340 @@
+//! What loading the datapath told us, for `udcn doctor --verbose`.
+//!
+//! Each time the daemon loads the XDP program, it writes a report to [`DEFAULT_LOAD_REPORT_PATH`].
+//! The report holds the instructions in each function of the object, the instructions the verifier
+//! processed against its limit, the program's translated and JITed sizes, the memory each map
+//! locks, and whether the object, program and kernel have BTF. If the verifier rejects the program,
+//! the report holds the verifier's log instead. aya only keeps that log when loading fails, so a
+//! report for a successful load carries the processed instruction count in its place (Linux 5.16
+//! and later). That count is how close a feature brings the program to the verifier's limits.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    os::fd::{AsFd as _, AsRawFd as _, BorrowedFd},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use aya::{
+    maps::MapInfo,
+    programs::{ProgramError, ProgramInfo, Xdp},
+    Ebpf, EbpfLoader,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_LOAD_REPORT_PATH: &str = "/var/lib/udcn/load-report.json";
+
+/// Instructions the verifier processes at most before giving up on a program
+/// (`BPF_COMPLEXITY_LIMIT_INSNS`).
+pub const VERIFIER_INSN_LIMIT: u32 = 1_000_000;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoadReport {
+    /// When the daemon loaded the program, in seconds since the Unix epoch.
+    pub loaded_at: u64,
+    /// Why loading failed, if it did.
+    pub error: Option<String>,
+    /// What the verifier said about a program it rejected.
+    pub verifier_log: Option<String>,
+    pub verified_insns: Option<u32>,
+    pub translated_bytes: Option<u32>,
+    pub jited_bytes: Option<u32>,
+    pub memlock_bytes: Option<u64>,
+    pub functions: Vec<FunctionSize>,
+    pub maps: Vec<MapUsage>,
+    pub btf: BtfStatus,
+}
+
+/// A function of the object and how many instructions it compiled to.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FunctionSize {
+    /// The program whose section holds the function, or `.text` for subprograms.
+    pub section: String,
+    pub function: String,
+    pub insns: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapUsage {
+    pub name: String,
+    pub map_type: String,
+    pub max_entries: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub memlock_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BtfStatus {
+    /// Whether the object carries BTF for its types, and BTF.ext for its functions and lines.
+    pub object: bool,
+    pub object_ext: bool,
+    /// Whether the kernel describes itself with BTF.
+    pub kernel: bool,
+    /// The BTF the kernel loaded with the program, if it took it.
+    pub program_btf_id: Option<u32>,
+}
+
+impl LoadReport {
+    /// What can be told from `object` before loading it.
+    pub fn new(object: &[u8]) -> Self {
+        let mut report = Self {
+            loaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            ..Self::default()
+        };
+        report.btf.kernel = Path::new("/sys/kernel/btf/vmlinux").exists();
+        let obj = match aya_obj::Object::parse(object) {
+            Ok(obj) => obj,
+            Err(e) => {
+                report.error = Some(format!("invalid object: {e}"));
+                return report;
+            }
+        };
+        report.btf.object = obj.btf.is_some();
+        report.btf.object_ext = obj.btf_ext.is_some();
+        for ((section_index, _), function) in &obj.functions {
+            let section = obj
+                .programs
+                .iter()
+                .find(|(_, program)| program.section_index == *section_index)
+                .map_or(".text", |(name, _)| name.as_str());
+            report.functions.push(FunctionSize {
+                section: section.to_string(),
+                function: function.name.clone(),
+                insns: function.instructions.len(),
+            });
+        }
+        report
+    }
+
+    /// Fill in what the kernel says about the loaded `program` and its maps.
+    fn record(&mut self, program: &ProgramInfo) {
+        self.verified_insns = program.verified_instruction_count();
+        self.translated_bytes = program.size_translated();
+        self.jited_bytes = Some(program.size_jitted()).filter(|size| *size > 0);
+        self.memlock_bytes = program.memory_locked().ok().map(u64::from);
+        self.btf.program_btf_id = program.btf_id();
+        let ids = program.map_ids().ok().flatten().unwrap_or_default();
+        for map in ids.into_iter().filter_map(|id| MapInfo::from_id(id).ok()) {
+            self.maps.push(MapUsage {
+                name: map.name_as_str().unwrap_or("?").to_string(),
+                map_type: map
+                    .map_type()
+                    .map_or_else(|_| "?".to_string(), |map_type| format!("{map_type:?}")),
+                max_entries: map.max_entries(),
+                key_size: map.key_size(),
+                value_size: map.value_size(),
+                memlock_bytes: map.fd().ok().and_then(|fd| memlock(fd.as_fd())),
+            });
+        }
+        self.maps.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The share of the verifier's instruction limit the program used, if known.
+    pub fn verifier_headroom_used(&self) -> Option<f64> {
+        self.verified_insns
+            .map(|insns| f64::from(insns) / f64::from(VERIFIER_INSN_LIMIT))
+    }
+
+    pub fn format(&self) -> String {
+        let mut out = String::new();
+        let ago = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+            .saturating_sub(self.loaded_at);
+        let outcome = self.error.as_deref().unwrap_or("loaded");
+        let _ = writeln!(out, "Last datapath load, {ago} s ago: {outcome}");
+        let yes_no = |yes: bool| if yes { "yes" } else { "no" };
+        let _ = writeln!(
+            out,
+            "BTF: object {}, BTF.ext {}, kernel {}, program {}",
+            yes_no(self.btf.object),
+            yes_no(self.btf.object_ext),
+            yes_no(self.btf.kernel),
+            self.btf
+                .program_btf_id
+                .map_or_else(|| "none".to_string(), |id| format!("id {id}"))
+        );
+        if let (Some(insns), Some(used)) = (self.verified_insns, self.verifier_headroom_used()) {
+            let _ = writeln!(
+                out,
+                "Verified instructions: {insns} of {VERIFIER_INSN_LIMIT} ({:.1}%)",
+                used * 100.0
+            );
+        }
+        let sizes = [
+            ("translated", self.translated_bytes.map(u64::from)),
+            ("JITed", self.jited_bytes.map(u64::from)),
+            ("memlock", self.memlock_bytes),
+        ];
+        let sizes: Vec<String> = sizes
+            .iter()
+            .filter_map(|(what, bytes)| bytes.map(|bytes| format!("{bytes} bytes {what}")))
+            .collect();
+        if !sizes.is_empty() {
+            let _ = writeln!(out, "Program: {}", sizes.join(", "));
+        }
+
+        if !self.functions.is_empty() {
+            let _ = writeln!(out, "\n{:<12} {:<40} {:>8}", "SECTION", "FUNCTION", "INSNS");
+            for function in &self.functions {
+                let _ = writeln!(
+                    out,
+                    "{:<12} {:<40} {:>8}",
+                    function.section, function.function, function.insns
+                );
+            }
+        }
+        if !self.maps.is_empty() {
+            let _ = writeln!(
+                out,
+                "\n{:<20} {:<16} {:>8} {:>6} {:>6} {:>10}",
+                "MAP", "TYPE", "ENTRIES", "KEY", "VALUE", "MEMLOCK"
+            );
+            for map in &self.maps {
+                let memlock = map
+                    .memlock_bytes
+                    .map_or_else(|| "-".to_string(), |bytes| format!("{} KiB", bytes / 1024));
+                let _ = writeln!(
+                    out,
+                    "{:<20} {:<16} {:>8} {:>6} {:>6} {:>10}",
+                    map.name, map.map_type, map.max_entries, map.key_size, map.value_size, memlock
+                );
+            }
+            let total: u64 = self.maps.iter().filter_map(|map| map.memlock_bytes).sum();
+            let _ = writeln!(out, "Maps lock {} KiB in all", total / 1024);
+        }
+        if let Some(log) = &self.verifier_log {
+            let _ = write!(out, "\nVerifier log:\n{log}");
+            if !log.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// Load `object` with `loader`, then its `udcn` program, and write what happened to `path`
+/// whether or not it worked.
+pub fn load(loader: &mut EbpfLoader, object: &[u8], path: &Path) -> anyhow::Result<Ebpf> {
+    let mut report = LoadReport::new(object);
+    let loaded = load_program(loader, object, &mut report);
+    if let Err(e) = &loaded {
+        report.error.get_or_insert_with(|| format!("{e:#}"));
+    }
+    if let Err(e) = report.save(path) {
+        warn!(
+            "failed to write the load report to {}: {e:#}",
+            path.display()
+        );
+    }
+    loaded
+}
+
+fn load_program(
+    loader: &mut EbpfLoader,
+    object: &[u8],
+    report: &mut LoadReport,
+) -> anyhow::Result<Ebpf> {
+    let mut ebpf = loader.load(object)?;
+    let program: &mut Xdp = ebpf.program_mut("udcn").unwrap().try_into()?;
+    if let Err(e) = program.load() {
+        if let ProgramError::LoadError {
+            io_error,
+            verifier_log,
+        } = &e
+        {
+            report.error = Some(format!("the verifier rejected the program: {io_error}"));
+            report.verifier_log = Some(format!("{verifier_log:?}"));
+        }
+        return Err(e.into());
+    }
+    match program.info() {
+        Ok(info) => report.record(&info),
+        Err(e) => warn!("failed to read the loaded program's info: {e}"),
+    }
+    Ok(ebpf)
+}
+
+/// The memory the kernel charged for the BPF object behind `fd`, from its fdinfo.
+fn memlock(fd: BorrowedFd<'_>) -> Option<u64> {
+    let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", fd.as_raw_fd())).ok()?;
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("memlock:"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_round_trips_and_shows_the_limits() {
+        let report = LoadReport {
+            loaded_at: 0,
+            error: Some("the verifier rejected the program: Permission denied".to_string()),
+            verifier_log: Some("0: (b7) r0 = 2\nprocessed 1000001 insns".to_string()),
+            verified_insns: Some(250_000),
+            translated_bytes: Some(8192),
+            jited_bytes: None,
+            memlock_bytes: Some(12_288),
+            functions: vec![FunctionSize {
+                section: "udcn".to_string(),
+                function: "udcn".to_string(),
+                insns: 4096,
+            }],
+            maps: vec![MapUsage {
+                name: "PIT".to_string(),
+                map_type: "Hash".to_string(),
+                max_entries: 1024,
+                key_size: 4,
+                value_size: 112,
+                memlock_bytes: Some(131_072),
+            }],
+            btf: BtfStatus {
+                object: true,
+                object_ext: true,
+                kernel: true,
+                program_btf_id: None,
+            },
+        };
+        let path = std::env::temp_dir().join(format!("udcn-load-report-{}", std::process::id()));
+        report.save(&path).unwrap();
+        assert_eq!(LoadReport::load(&path).unwrap(), report);
+        fs::remove_file(&path).unwrap();
+
+        let text = report.format();
+        assert!(text.contains("Permission denied"), "{text}");
+        assert!(
+            text.contains("BTF: object yes, BTF.ext yes, kernel yes, program none"),
+            "{text}"
+        );
+        assert!(text.contains("250000 of 1000000 (25.0%)"), "{text}");
+        assert!(
+            text.contains("Program: 8192 bytes translated, 12288 bytes memlock"),
+            "{text}"
+        );
+        assert!(text.contains("Maps lock 128 KiB in all"), "{text}");
+        assert!(text.ends_with("processed 1000001 insns\n"), "{text}");
+    }
+}
@@ -0,0 +1,680 @@
+//! DTLS for tunnels between sites, so NDN crosses an untrusted network encrypted and only between
+//! peers that authenticated each other.
+//!
+//! A `[tunnels.dtls]` table makes a tunnel the client of a DTLS 1.2 session with its `remote`,
+//! which must be a `[[dtls_listeners]]` entry of the daemon at the other site. A listener takes
+//! sessions from any number of peers and relays the Interests of each to the local forwarder,
+//! from a socket of the session's own, so the replies come back to it and go out to that peer
+//! alone. Both ends authenticate with a pre-shared key and identity, or with a certificate and
+//! key the other end's `ca_file` vouches for. Credentials are read as the daemon starts, before it
+//! drops privileges.
+//!
+//! OpenSSL runs the record layer a datagram at a time but, on sockets it does not own, does not
+//! retransmit a lost handshake flight. A handshake that has not finished within
+//! [`HANDSHAKE_TIMEOUT`] is given up: a tunnel tries again when it next resolves its remote, and a
+//! listener starts afresh when the peer does.
+//!
+//! A listener keeps nothing for a peer until the peer has answered a HelloVerifyRequest, whose
+//! cookie is an HMAC of the peer's address under a key the listener draws as it starts. The
+//! cookie is checked as by OpenSSL's `DTLSv1_listen`, so ClientHellos from spoofed addresses take
+//! no session and draw a reply smaller than themselves.
+
+use std::{
+    collections::HashMap,
+    ffi::{c_int, c_void},
+    fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use foreign_types::ForeignTypeRef as _;
+use log::{debug, info, warn};
+use openssl::{
+    error::ErrorStack,
+    ex_data::Index,
+    hash::MessageDigest,
+    memcmp,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+    sign::Signer,
+    ssl::{
+        Ssl, SslContext, SslContextBuilder, SslFiletype, SslMethod, SslOptions, SslRef,
+        SslVerifyMode, SslVersion,
+    },
+};
+use serde::Deserialize;
+use tokio::{
+    io::{split, AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, ReadBuf},
+    net::UdpSocket,
+    sync::{mpsc, Mutex},
+    time::timeout,
+};
+use tokio_openssl::SslStream;
+use udcn_common::NDN_UDP_PORT;
+
+/// How long a handshake may take before it is given up.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a listener keeps a session nothing arrives on.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Sessions one listener holds at most; datagrams from further peers are dropped.
+const MAX_SESSIONS: usize = 1024;
+
+/// Datagrams waiting to be encrypted or taken, per direction and session, beyond which they are
+/// dropped as a full socket buffer would drop them.
+const QUEUE_LEN: usize = 1024;
+
+/// Largest datagram relayed.
+const MAX_PACKET_SIZE: usize = 65_536;
+
+/// Records are kept to this size, so they cross common paths without IP fragmentation.
+const MTU: u32 = 1400;
+
+const PSK_CIPHERS: &str = "PSK-AES256-GCM-SHA384:PSK-AES128-GCM-SHA256";
+
+// In libssl, which openssl-sys links, but not declared by it
+extern "C" {
+    fn DTLSv1_listen(ssl: *mut openssl_sys::SSL, client: *mut c_void) -> c_int;
+    fn BIO_ADDR_new() -> *mut c_void;
+    fn BIO_ADDR_free(addr: *mut c_void);
+}
+
+/// A `dtls` table: how a tunnel or listener authenticates its peers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DtlsConfig {
+    /// The identity the client gives with the pre-shared key, and the only one a listener takes.
+    #[serde(default)]
+    pub psk_identity: Option<String>,
+    /// A file holding the pre-shared key, as raw bytes.
+    #[serde(default)]
+    pub psk_file: Option<PathBuf>,
+    /// This end's PEM certificate chain.
+    #[serde(default)]
+    pub cert_file: Option<PathBuf>,
+    /// This end's PEM private key.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// PEM certificates of the authorities the peer's certificate must chain to.
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+}
+
+impl DtlsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let psk = self.psk_identity.is_some() || self.psk_file.is_some();
+        let certificate =
+            self.cert_file.is_some() || self.key_file.is_some() || self.ca_file.is_some();
+        anyhow::ensure!(
+            psk != certificate,
+            "dtls needs either psk_identity and psk_file, or cert_file, key_file and ca_file"
+        );
+        anyhow::ensure!(
+            !psk || (self.psk_identity.is_some() && self.psk_file.is_some()),
+            "dtls.psk_identity and psk_file go together"
+        );
+        anyhow::ensure!(
+            !certificate
+                || (self.cert_file.is_some() && self.key_file.is_some() && self.ca_file.is_some()),
+            "dtls.cert_file, key_file and ca_file go together"
+        );
+        Ok(())
+    }
+
+    /// A context for the client end of sessions, or with `server` the listening end, with the
+    /// credentials read.
+    pub fn context(&self, server: bool) -> anyhow::Result<SslContext> {
+        let mut builder = SslContextBuilder::new(SslMethod::dtls())?;
+        builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+        builder.set_options(SslOptions::NO_QUERY_MTU);
+        match (&self.psk_identity, &self.psk_file) {
+            (Some(identity), Some(psk_file)) => {
+                let psk = fs::read(psk_file)
+                    .with_context(|| format!("failed to read {}", psk_file.display()))?;
+                anyhow::ensure!(!psk.is_empty(), "{} is empty", psk_file.display());
+                builder.set_cipher_list(PSK_CIPHERS)?;
+                let identity = identity.clone().into_bytes();
+                match server {
+                    true => builder.set_psk_server_callback(move |_, offered, out| {
+                        match offered == Some(&identity[..]) && psk.len() <= out.len() {
+                            true => {
+                                out[..psk.len()].copy_from_slice(&psk);
+                                Ok(psk.len())
+                            }
+                            // No key, which fails the handshake
+                            false => Ok(0),
+                        }
+                    }),
+                    false => builder.set_psk_client_callback(move |_, _, identity_out, out| {
+                        // The identity goes out NUL-terminated
+                        if identity.len() >= identity_out.len() || psk.len() > out.len() {
+                            return Ok(0);
+                        }
+                        identity_out[..identity.len()].copy_from_slice(&identity);
+                        identity_out[identity.len()] = 0;
+                        out[..psk.len()].copy_from_slice(&psk);
+                        Ok(psk.len())
+                    }),
+                }
+            }
+            _ => {
+                let (Some(cert_file), Some(key_file), Some(ca_file)) =
+                    (&self.cert_file, &self.key_file, &self.ca_file)
+                else {
+                    anyhow::bail!("dtls has no credentials");
+                };
+                builder
+                    .set_certificate_chain_file(cert_file)
+                    .with_context(|| format!("failed to load {}", cert_file.display()))?;
+                builder
+                    .set_private_key_file(key_file, SslFiletype::PEM)
+                    .with_context(|| format!("failed to load {}", key_file.display()))?;
+                builder.check_private_key()?;
+                builder
+                    .set_ca_file(ca_file)
+                    .with_context(|| format!("failed to load {}", ca_file.display()))?;
+                builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+            }
+        }
+        if server {
+            let mut secret = [0u8; 32];
+            rand_bytes(&mut secret)?;
+            let key = Arc::new(PKey::hmac(&secret)?);
+            let verify_key = key.clone();
+            builder.set_options(SslOptions::COOKIE_EXCHANGE);
+            builder.set_cookie_generate_cb(move |ssl, out| {
+                let cookie = cookie(&key, ssl)?;
+                out[..cookie.len()].copy_from_slice(&cookie);
+                Ok(cookie.len())
+            });
+            builder.set_cookie_verify_cb(move |ssl, offered| {
+                cookie(&verify_key, ssl).is_ok_and(|cookie| {
+                    cookie.len() == offered.len() && memcmp::eq(&cookie, offered)
+                })
+            });
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Where the listening end keeps the address a handshake comes from, for its cookie.
+fn peer_index() -> Result<Index<Ssl, SocketAddr>, ErrorStack> {
+    static INDEX: OnceLock<Index<Ssl, SocketAddr>> = OnceLock::new();
+    if let Some(index) = INDEX.get() {
+        return Ok(*index);
+    }
+    let index = Ssl::new_ex_index()?;
+    Ok(*INDEX.get_or_init(|| index))
+}
+
+/// The cookie for the peer of `ssl`, an HMAC of its address.
+fn cookie(key: &PKey<Private>, ssl: &SslRef) -> Result<Vec<u8>, ErrorStack> {
+    let Some(peer) = ssl.ex_data(peer_index()?) else {
+        return Err(ErrorStack::get());
+    };
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(peer.to_string().as_bytes())?;
+    signer.sign_to_vec()
+}
+
+/// A socket's datagrams as the byte stream OpenSSL reads records from, one per read. A listener's
+/// sessions share its socket, and take the datagrams it receives from their peer off a queue.
+struct Datagrams {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    incoming: Option<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl AsyncRead for Datagrams {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let Some(incoming) = &mut self.incoming else {
+            return self.socket.poll_recv(cx, buf);
+        };
+        match incoming.poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                let len = datagram.len().min(buf.remaining());
+                buf.put_slice(&datagram[..len]);
+                Poll::Ready(Ok(()))
+            }
+            // The listener is gone, which reads as the end of the stream
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for Datagrams {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.incoming {
+            Some(_) => self.socket.poll_send_to(cx, buf, self.peer),
+            None => self.socket.poll_send(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An established session: packets handed to it go out encrypted, and those from the peer come
+/// out decrypted.
+pub struct Session {
+    outgoing: mpsc::Sender<Vec<u8>>,
+    incoming: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl Session {
+    /// Handshake as the client over `socket`, which is connected to the peer.
+    pub async fn connect(socket: Arc<UdpSocket>, context: &SslContext) -> anyhow::Result<Self> {
+        let peer = socket.peer_addr()?;
+        let datagrams = Datagrams {
+            socket,
+            peer,
+            incoming: None,
+        };
+        let mut ssl = Ssl::new(context)?;
+        ssl.set_mtu(MTU)?;
+        let mut stream = SslStream::new(ssl, datagrams)?;
+        timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).connect())
+            .await
+            .context("the DTLS handshake timed out")?
+            .context("the DTLS handshake failed")?;
+        Ok(Self::start(stream))
+    }
+
+    /// Go on with the handshake `ssl` verified the cookie of, as the listening end with `peer`,
+    /// whose further datagrams `incoming` queues.
+    async fn accept(
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        incoming: mpsc::Receiver<Vec<u8>>,
+        ssl: Ssl,
+    ) -> anyhow::Result<Self> {
+        let datagrams = Datagrams {
+            socket,
+            peer,
+            incoming: Some(incoming),
+        };
+        let mut stream = SslStream::new(ssl, datagrams)?;
+        timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).accept())
+            .await
+            .context("the DTLS handshake timed out")?
+            .context("the DTLS handshake failed")?;
+        Ok(Self::start(stream))
+    }
+
+    fn start(stream: SslStream<Datagrams>) -> Self {
+        let (outgoing, to_send) = mpsc::channel(QUEUE_LEN);
+        let (received, incoming) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(relay(stream, to_send, received));
+        Self {
+            outgoing,
+            incoming: Mutex::new(incoming),
+        }
+    }
+
+    /// Hand `packet` to the session without waiting for room, as `UdpSocket::try_send` does.
+    pub fn try_send(&self, packet: &[u8]) -> io::Result<usize> {
+        match self.outgoing.try_send(packet.to_vec()) {
+            Ok(()) => Ok(packet.len()),
+            Err(mpsc::error::TrySendError::Full(_)) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Receive the next packet from the peer into `buf`. Fails once the session has ended.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let packet = self.incoming.lock().await.recv().await;
+        let packet = packet.ok_or(io::ErrorKind::ConnectionAborted)?;
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok(len)
+    }
+}
+
+/// What the listening end makes of a datagram from a peer it has no session with.
+enum Hello {
+    /// A ClientHello whose cookie verified, taken in by a handshake ready to go on from it.
+    Verified(Ssl),
+    /// Anything else, and the HelloVerifyRequest that answers it if it was a ClientHello.
+    Unverified(Option<Vec<u8>>),
+}
+
+/// Check the cookie of a ClientHello from `peer`, keeping nothing for it unless it verifies.
+fn hello(context: &SslContext, peer: SocketAddr, datagram: &[u8]) -> anyhow::Result<Hello> {
+    let mut ssl = Ssl::new(context)?;
+    ssl.set_mtu(MTU)?;
+    ssl.set_ex_data(peer_index()?, peer);
+    let len = c_int::try_from(datagram.len())?;
+    // Run DTLSv1_listen over memory BIOs, which `ssl` owns once set
+    let (rbio, wbio) = unsafe {
+        (
+            openssl_sys::BIO_new(openssl_sys::BIO_s_mem()),
+            openssl_sys::BIO_new(openssl_sys::BIO_s_mem()),
+        )
+    };
+    if rbio.is_null() || wbio.is_null() {
+        unsafe {
+            openssl_sys::BIO_free_all(rbio);
+            openssl_sys::BIO_free_all(wbio);
+        }
+        return Err(ErrorStack::get().into());
+    }
+    unsafe { openssl_sys::SSL_set_bio(ssl.as_ptr(), rbio, wbio) };
+    if unsafe { openssl_sys::BIO_write(rbio, datagram.as_ptr().cast(), len) } != len {
+        return Err(ErrorStack::get().into());
+    }
+    let client = unsafe { BIO_ADDR_new() };
+    if client.is_null() {
+        return Err(ErrorStack::get().into());
+    }
+    let listened = unsafe { DTLSv1_listen(ssl.as_ptr(), client) };
+    unsafe { BIO_ADDR_free(client) };
+    match listened {
+        1.. => Ok(Hello::Verified(ssl)),
+        0 => {
+            let mut reply = vec![0u8; MTU as usize];
+            let len =
+                unsafe { openssl_sys::BIO_read(wbio, reply.as_mut_ptr().cast(), MTU as c_int) };
+            reply.truncate(usize::try_from(len).unwrap_or(0));
+            Ok(Hello::Unverified((!reply.is_empty()).then_some(reply)))
+        }
+        _ => Err(ErrorStack::get().into()),
+    }
+}
+
+/// Encrypt what `to_send` queues and decrypt what the peer sends into `received`, until either
+/// side goes away.
+async fn relay(
+    stream: SslStream<Datagrams>,
+    mut to_send: mpsc::Receiver<Vec<u8>>,
+    received: mpsc::Sender<Vec<u8>>,
+) {
+    let peer = stream.get_ref().peer;
+    let (mut reader, mut writer) = split(stream);
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        tokio::select! {
+            read = reader.read(&mut buf) => match read {
+                Ok(0) => {
+                    debug!("DTLS session with {peer} closed");
+                    return;
+                }
+                Ok(len) => {
+                    if let Err(mpsc::error::TrySendError::Closed(_)) =
+                        received.try_send(buf[..len].to_vec())
+                    {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    debug!("DTLS session with {peer} failed: {e}");
+                    return;
+                }
+            },
+            packet = to_send.recv() => {
+                let Some(packet) = packet else {
+                    let _ = writer.shutdown().await;
+                    return;
+                };
+                if let Err(e) = writer.write_all(&packet).await {
+                    debug!("failed to send to {peer} over DTLS: {e}");
+                    return;
+                }
+            },
+        }
+    }
+}
+
+/// A `[[dtls_listeners]]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Where DTLS tunnels from other sites arrive.
+    pub listen: SocketAddr,
+    pub dtls: DtlsConfig,
+}
+
+/// A listener with its credentials read.
+pub struct Listener {
+    listen: SocketAddr,
+    context: SslContext,
+}
+
+impl Listener {
+    pub fn load(config: &ListenerConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            listen: config.listen,
+            context: config
+                .dtls
+                .context(true)
+                .with_context(|| format!("invalid DTLS credentials for {}", config.listen))?,
+        })
+    }
+
+    /// Bind the listening socket and start taking sessions, relaying them to the forwarder on
+    /// the local NDN port.
+    pub fn start(self) -> anyhow::Result<()> {
+        let socket = std::net::UdpSocket::bind(self.listen)
+            .with_context(|| format!("failed to listen for DTLS tunnels on {}", self.listen))?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+        info!("Taking DTLS tunnels on {}", self.listen);
+        let forwarder = SocketAddr::from((Ipv4Addr::LOCALHOST, NDN_UDP_PORT));
+        tokio::spawn(async move {
+            if let Err(e) = listen(socket, self.context, forwarder).await {
+                warn!("DTLS listener on {} stopped: {e}", self.listen);
+            }
+        });
+        Ok(())
+    }
+}
+
+async fn listen(
+    socket: Arc<UdpSocket>,
+    context: SslContext,
+    forwarder: SocketAddr,
+) -> io::Result<()> {
+    let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        if let Some(session) = sessions.get(&peer) {
+            match session.try_send(buf[..len].to_vec()) {
+                Ok(()) => continue,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    debug!("DTLS session with {peer} is behind, dropping a datagram");
+                    continue;
+                }
+                // Ended, so this may start a new one
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+        sessions.retain(|_, session| !session.is_closed());
+        if sessions.len() >= MAX_SESSIONS {
+            debug!("too many DTLS sessions, dropping a datagram from {peer}");
+            continue;
+        }
+        // Only a peer that answered with its cookie gets a session, so spoofed ones take no slot
+        let ssl = match hello(&context, peer, &buf[..len]) {
+            Ok(Hello::Verified(ssl)) => ssl,
+            Ok(Hello::Unverified(reply)) => {
+                if let Some(reply) = reply {
+                    if let Err(e) = socket.try_send_to(&reply, peer) {
+                        debug!("failed to ask {peer} for a DTLS cookie: {e}");
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                debug!("DTLS hello from {peer}: {e:#}");
+                continue;
+            }
+        };
+        let (queue, incoming) = mpsc::channel(QUEUE_LEN);
+        sessions.insert(peer, queue);
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(socket, peer, incoming, ssl, forwarder).await {
+                debug!("DTLS session with {peer}: {e:#}");
+            }
+        });
+    }
+}
+
+/// Relay a peer's packets to `forwarder` and the replies back, until the session ends or stays
+/// idle for [`IDLE_TIMEOUT`].
+async fn serve(
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    ssl: Ssl,
+    forwarder: SocketAddr,
+) -> anyhow::Result<()> {
+    let session = Session::accept(socket, peer, incoming, ssl).await?;
+    info!("DTLS tunnel from {peer}");
+    let local = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut reply_buf = vec![0u8; MAX_PACKET_SIZE];
+    loop {
+        tokio::select! {
+            received = timeout(IDLE_TIMEOUT, session.recv(&mut buf)) => {
+                let Ok(received) = received else {
+                    info!("DTLS tunnel from {peer} idle, closing it");
+                    return Ok(());
+                };
+                let len = received?;
+                if let Err(e) = local.try_send_to(&buf[..len], forwarder) {
+                    debug!("failed to relay a packet from {peer}: {e}");
+                }
+            },
+            received = local.recv(&mut reply_buf) => {
+                let len = received?;
+                if let Err(e) = session.try_send(&reply_buf[..len]) {
+                    debug!("failed to relay a reply to {peer}: {e}");
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sessions_authenticate_with_a_pre_shared_key() {
+        let dir = std::env::temp_dir().join(format!("udcn-dtls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("right.psk"), b"0123456789abcdef").unwrap();
+        fs::write(dir.join("wrong.psk"), b"fedcba9876543210").unwrap();
+        let config = |psk: &str| DtlsConfig {
+            psk_identity: Some("site-a".to_string()),
+            psk_file: Some(dir.join(psk)),
+            cert_file: None,
+            key_file: None,
+            ca_file: None,
+        };
+        config("right.psk").validate().unwrap();
+        assert!(DtlsConfig {
+            cert_file: Some(dir.join("cert.pem")),
+            ..config("right.psk")
+        }
+        .validate()
+        .is_err());
+
+        // The forwarder answers every packet with its reverse
+        let forwarder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let forwarder_addr = forwarder.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (len, from) = forwarder.recv_from(&mut buf).await.unwrap();
+                buf[..len].reverse();
+                forwarder.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+        let listening = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let listen_addr = listening.local_addr().unwrap();
+        let context = config("right.psk").context(true).unwrap();
+        tokio::spawn(listen(listening, context, forwarder_addr));
+
+        let connect = |psk: &'static str| {
+            let context = config(psk).context(false).unwrap();
+            async move {
+                let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                socket.connect(listen_addr).await.unwrap();
+                Session::connect(Arc::new(socket), &context).await
+            }
+        };
+        let session = connect("right.psk").await.unwrap();
+        session.try_send(b"interest").unwrap();
+        let mut buf = [0u8; 64];
+        let len = session.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"tseretni");
+        assert!(connect("wrong.psk").await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_wait_for_a_verified_cookie() {
+        let dir = std::env::temp_dir().join(format!("udcn-dtls-cookie-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("site-a.psk"), b"0123456789abcdef").unwrap();
+        let config = DtlsConfig {
+            psk_identity: Some("site-a".to_string()),
+            psk_file: Some(dir.join("site-a.psk")),
+            cert_file: None,
+            key_file: None,
+            ca_file: None,
+        };
+        let server = config.context(true).unwrap();
+
+        // A client whose datagrams the test passes on by hand
+        let middle = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(middle.local_addr().unwrap()).await.unwrap();
+        let client = socket.local_addr().unwrap();
+        let context = config.context(false).unwrap();
+        let handshake =
+            tokio::spawn(async move { Session::connect(Arc::new(socket), &context).await });
+        let mut buf = [0u8; 2048];
+        let (len, _) = middle.recv_from(&mut buf).await.unwrap();
+        let Hello::Unverified(Some(verify)) = hello(&server, client, &buf[..len]).unwrap() else {
+            panic!("no HelloVerifyRequest");
+        };
+        assert!(verify.len() < len);
+
+        middle.send_to(&verify, client).await.unwrap();
+        let (len, _) = middle.recv_from(&mut buf).await.unwrap();
+        let spoofed = SocketAddr::from(([10, 0, 0, 1], client.port()));
+        assert!(matches!(
+            hello(&server, spoofed, &buf[..len]).unwrap(),
+            Hello::Unverified(_)
+        ));
+        assert!(matches!(
+            hello(&server, client, &buf[..len]).unwrap(),
+            Hello::Verified(_)
+        ));
+        handshake.abort();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,497 @@
+//! Ethernet faces, which carry NDN straight over a LAN segment without IP, as
+//! `udcn face create ether://eth0` creates them.
+//!
+//! The datapath only parses NDN over UDP, so an Ethernet face runs in userspace, on a packet
+//! socket for frames of the NDN ethertype (0x8624) on the interface. Interests arriving from the
+//! segment are relayed to the forwarder on the local NDN port, and its Data and Nacks go back to
+//! the address each Interest's frame came from, learnt as it arrived, rather than to the whole
+//! segment. The other way, Interests that applications send to the face's own UDP socket, which
+//! `udcn face list` shows as its local address, go out to the NDN multicast address every
+//! forwarder on the segment listens on, and Data from the segment answering them goes back to the
+//! applications waiting for it.
+//!
+//! A face created with `multicast` sends Data that more than one host on the segment waits for
+//! once, to the NDN multicast address, rather than a copy to each. The Ethernet faces of those
+//! hosts are in the multicast group, so each takes the Data to its own applications; the copies
+//! saved are counted as suppressed duplicates. Nacks, which answer a single Interest each, always
+//! go to each host.
+//!
+//! Packets larger than the interface's MTU are split into NDNLPv2 fragments, and fragments from
+//! the segment are reassembled. Opening the packet socket takes CAP_NET_RAW, which the daemon keeps
+//! after dropping privileges if it was started with it.
+
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fmt, io, mem,
+    net::{Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use log::{debug, info, warn};
+use tokio::{io::unix::AsyncFd, net::UdpSocket, time::Instant};
+use udcn_client::control::FaceStatus;
+use udcn_common::{lp, parse_nack_packet, Interest, NackReason, NDN_ETHERTYPE, NDN_UDP_PORT};
+
+use crate::{
+    daemon::Daemon,
+    otlp,
+    tunnels::{self, Pending},
+};
+
+/// Largest packet relayed, once reassembled.
+const MAX_PACKET_SIZE: usize = 65_536;
+
+/// The NDN Ethernet multicast address, which the NDN IPv4 multicast group also maps to.
+pub const NDN_MULTICAST_MAC: Mac = Mac([0x01, 0x00, 0x5e, 0x00, 0x17, 0xaa]);
+
+/// An Ethernet address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mac(pub [u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// The interface an `ether://<interface>` face URI names.
+pub fn parse_uri(uri: &str) -> anyhow::Result<&str> {
+    let interface = uri
+        .strip_prefix("ether://")
+        .with_context(|| format!("'{uri}' is not an Ethernet face URI such as ether://eth0"))?;
+    anyhow::ensure!(
+        !interface.is_empty()
+            && interface.len() < libc::IFNAMSIZ
+            && !interface.contains(['/', ' ', '\0']),
+        "'{interface}' is not an interface name"
+    );
+    Ok(interface)
+}
+
+/// A packet socket for NDN frames on one interface, in the NDN multicast group there.
+struct PacketSocket {
+    /// Shared with the face, which reads the send buffer through it.
+    fd: AsyncFd<Arc<OwnedFd>>,
+    ifindex: i32,
+    mtu: usize,
+}
+
+impl PacketSocket {
+    fn open(interface: &str) -> anyhow::Result<Self> {
+        let name = CString::new(interface)?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) } as i32;
+        if ifindex == 0 {
+            anyhow::bail!("no interface {interface}");
+        }
+        let protocol = NDN_ETHERTYPE.to_be();
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::c_int::from(protocol),
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).with_context(|| {
+                format!("failed to open a packet socket on {interface}, which takes CAP_NET_RAW")
+            });
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = ifindex;
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                (&addr as *const libc::sockaddr_ll).cast(),
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to bind a packet socket to {interface}"));
+        }
+
+        let mut membership: libc::packet_mreq = unsafe { mem::zeroed() };
+        membership.mr_ifindex = ifindex;
+        membership.mr_type = libc::PACKET_MR_MULTICAST as u16;
+        membership.mr_alen = 6;
+        membership.mr_address[..6].copy_from_slice(&NDN_MULTICAST_MAC.0);
+        let ret = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_ADD_MEMBERSHIP,
+                (&membership as *const libc::packet_mreq).cast(),
+                mem::size_of::<libc::packet_mreq>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to join the NDN multicast group on {interface}"));
+        }
+
+        let mut request: libc::ifreq = unsafe { mem::zeroed() };
+        for (dst, &src) in request.ifr_name.iter_mut().zip(name.as_bytes()) {
+            *dst = src as libc::c_char;
+        }
+        if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFMTU, &mut request) } < 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to read the MTU of {interface}"));
+        }
+        let mtu = unsafe { request.ifr_ifru.ifru_mtu }.max(0) as usize;
+
+        Ok(Self {
+            fd: AsyncFd::new(Arc::new(fd))?,
+            ifindex,
+            mtu,
+        })
+    }
+
+    /// Receive a frame's payload into `buf`, with the address it came from. Frames the socket
+    /// sent itself, which the kernel loops back to it, are skipped.
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<(usize, Mac)> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+                let mut addr_len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+                let len = unsafe {
+                    libc::recvfrom(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr().cast(),
+                        buf.len(),
+                        0,
+                        (&mut addr as *mut libc::sockaddr_ll).cast(),
+                        &mut addr_len,
+                    )
+                };
+                if len < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut mac = [0; 6];
+                mac.copy_from_slice(&addr.sll_addr[..6]);
+                Ok((len as usize, Mac(mac), addr.sll_pkttype))
+            });
+            match received {
+                Ok(Ok((_, _, libc::PACKET_OUTGOING))) => continue,
+                Ok(received) => return received.map(|(len, mac, _)| (len, mac)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Hand a frame carrying `payload` to `to` to the socket, without waiting for room.
+    fn try_send(&self, payload: &[u8], to: Mac) -> io::Result<usize> {
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = NDN_ETHERTYPE.to_be();
+        addr.sll_ifindex = self.ifindex;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&to.0);
+        let len = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                payload.as_ptr().cast(),
+                payload.len(),
+                0,
+                (&addr as *const libc::sockaddr_ll).cast(),
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+}
+
+/// An Ethernet face as one of the daemon's faces, and what sending on it has run into.
+pub struct Face {
+    interface: String,
+    local: SocketAddr,
+    /// The packet socket, for its send buffer. Shared so it stays open, and is not some other
+    /// file the number was reused for, after the relay stops.
+    fd: Arc<OwnedFd>,
+    /// Whether Data for several hosts goes out once, to the NDN multicast address.
+    multicast: bool,
+    state: Mutex<FaceState>,
+}
+
+#[derive(Default)]
+struct FaceState {
+    sent: u64,
+    would_block_drops: u64,
+    send_errors: u64,
+    suppressed_duplicates: u64,
+    nacks: BTreeMap<String, u64>,
+}
+
+impl Face {
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Count what became of a packet handed to a socket with `sent` as the result.
+    fn sent(&self, sent: io::Result<usize>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match sent {
+            Ok(_) => {
+                state.sent += 1;
+                Ok(())
+            }
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    state.would_block_drops += 1;
+                } else {
+                    state.send_errors += 1;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Count a Nack from the segment.
+    fn nacked(&self, reason: u8) {
+        let reason = match NackReason::from_u8(reason) {
+            Some(reason) => format!("{reason:?}"),
+            None => reason.to_string(),
+        };
+        *self.state.lock().unwrap().nacks.entry(reason).or_default() += 1;
+    }
+
+    pub fn status(&self) -> FaceStatus {
+        let state = self.state.lock().unwrap();
+        let (send_queue_bytes, send_buffer_bytes) = tunnels::send_buffer(self.fd.as_raw_fd());
+        FaceStatus {
+            face: format!("ether {}", self.interface),
+            local: self.local.to_string(),
+            remote: Some(NDN_MULTICAST_MAC.to_string()),
+            send_queue_bytes,
+            send_buffer_bytes,
+            sent: state.sent,
+            would_block_drops: state.would_block_drops,
+            send_errors: state.send_errors,
+            suppressed_duplicates: state.suppressed_duplicates,
+            nacks: state.nacks.clone(),
+            ..FaceStatus::default()
+        }
+    }
+}
+
+/// The packet socket and what sends on it need.
+struct Link {
+    socket: PacketSocket,
+    face: Arc<Face>,
+    /// The sequence number of the next fragment.
+    sequence: u64,
+}
+
+impl Link {
+    /// Send `packet` to `to`, in fragments if it does not fit in a frame.
+    fn send(&mut self, packet: &[u8], to: Mac) {
+        if packet.len() <= self.socket.mtu {
+            if let Err(e) = self.face.sent(self.socket.try_send(packet, to)) {
+                debug!("failed to send to {to} on {}: {e}", self.face.interface);
+            }
+            return;
+        }
+        let Some(fragments) = lp::fragment(packet, self.socket.mtu, self.sequence) else {
+            debug!(
+                "a {} byte packet is too large for {}",
+                packet.len(),
+                self.face.interface
+            );
+            self.face.state.lock().unwrap().send_errors += 1;
+            return;
+        };
+        self.sequence = self.sequence.wrapping_add(fragments.len() as u64);
+        for fragment in fragments {
+            if let Err(e) = self.face.sent(self.socket.try_send(&fragment, to)) {
+                debug!(
+                    "failed to send a fragment to {to} on {}: {e}",
+                    self.face.interface
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// The hosts Interests in `waiting` came from, once each.
+fn hosts(mut waiting: Vec<Mac>) -> Vec<Mac> {
+    waiting.sort_unstable();
+    waiting.dedup();
+    waiting
+}
+
+/// Create the Ethernet face a `face-create` request's `<uri> [multicast]` argument names and
+/// start relaying, listing it as one of the daemon's faces.
+pub fn create(argument: &str, daemon: Arc<Mutex<Daemon>>) -> anyhow::Result<String> {
+    let (uri, multicast) = match argument.split_once(' ') {
+        None => (argument, false),
+        Some((uri, "multicast")) => (uri, true),
+        Some((_, option)) => anyhow::bail!("unknown face option '{option}'"),
+    };
+    let interface = parse_uri(uri)?;
+    let socket = PacketSocket::open(interface)?;
+    let udp = StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    udp.set_nonblocking(true)?;
+    let udp = UdpSocket::from_std(udp)?;
+    let face = Arc::new(Face {
+        interface: interface.to_string(),
+        local: udp.local_addr()?,
+        fd: socket.fd.get_ref().clone(),
+        multicast,
+        state: Mutex::new(FaceState::default()),
+    });
+    daemon.lock().unwrap().add_ether_face(face.clone())?;
+    info!(
+        "Ethernet face on {interface}, MTU {}, takes Interests on {}",
+        socket.mtu, face.local
+    );
+    let created = format!(
+        "Created face {uri} with MTU {}, taking Interests on {}\n",
+        socket.mtu, face.local
+    );
+    let link = Link {
+        socket,
+        face,
+        sequence: 0,
+    };
+    tokio::spawn(async move { run(link, udp).await });
+    Ok(created)
+}
+
+async fn run(mut link: Link, udp: UdpSocket) {
+    let forwarder = SocketAddr::from((Ipv4Addr::LOCALHOST, NDN_UDP_PORT));
+    // Interests from the segment, by the address they came from, and from local applications
+    let mut from_segment = Pending::<Mac>::default();
+    let mut from_applications = Pending::<SocketAddr>::default();
+    let (mut segment_reassembler, mut local_reassembler) =
+        (lp::Reassembler::new(), lp::Reassembler::new());
+    let mut frame_buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+    loop {
+        tokio::select! {
+            received = link.socket.recv(&mut frame_buf) => {
+                let (len, source) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Ethernet face on {} stopped: {e}", link.face.interface);
+                        return;
+                    }
+                };
+                let reassembled;
+                let packet = if lp::is_lp_packet(&frame_buf[..len]) {
+                    match segment_reassembler.receive(&frame_buf[..len]) {
+                        Some(packet) => {
+                            reassembled = packet;
+                            &reassembled[..]
+                        }
+                        None => continue,
+                    }
+                } else {
+                    &frame_buf[..len]
+                };
+                let mut span = otlp::span("ether_from_segment");
+                span.attr("interface", &link.face.interface);
+                if let Some(interest) = Interest::decode(packet) {
+                    if !from_segment.insert(&interest, source, Instant::now()) {
+                        debug!("too many Interests pending on the face, dropping '{}'", interest.name);
+                        continue;
+                    }
+                    if let Err(e) = udp.try_send_to(packet, forwarder) {
+                        debug!("failed to relay '{}' from {source}: {e}", interest.name);
+                    }
+                    continue;
+                }
+                if let Some(nack) = parse_nack_packet(packet) {
+                    link.face.nacked(nack.reason);
+                }
+                for application in from_applications.answer(packet) {
+                    if let Err(e) = udp.try_send_to(packet, application) {
+                        debug!("failed to relay a reply to {application}: {e}");
+                    }
+                }
+            },
+            received = udp.recv_from(&mut buf) => {
+                let (len, from) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("Ethernet face on {}: {e}", link.face.interface);
+                        continue;
+                    }
+                };
+                let reassembled;
+                let packet = if lp::is_lp_packet(&buf[..len]) {
+                    match local_reassembler.receive(&buf[..len]) {
+                        Some(packet) => {
+                            reassembled = packet;
+                            &reassembled[..]
+                        }
+                        None => continue,
+                    }
+                } else {
+                    &buf[..len]
+                };
+                let mut span = otlp::span("ether_to_segment");
+                span.attr("interface", &link.face.interface);
+                if let Some(interest) = Interest::decode(packet) {
+                    if !from_applications.insert(&interest, from, Instant::now()) {
+                        debug!("too many Interests pending on the face, dropping '{}'", interest.name);
+                        continue;
+                    }
+                    link.send(packet, NDN_MULTICAST_MAC);
+                    continue;
+                }
+                let hosts = hosts(from_segment.answer(packet));
+                if link.face.multicast && hosts.len() > 1 && parse_nack_packet(packet).is_none() {
+                    link.face.state.lock().unwrap().suppressed_duplicates += hosts.len() as u64 - 1;
+                    link.send(packet, NDN_MULTICAST_MAC);
+                    continue;
+                }
+                for host in hosts {
+                    link.send(packet, host);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::Data;
+
+    use super::*;
+
+    #[test]
+    fn test_replies_go_back_to_the_learnt_address() {
+        assert_eq!(parse_uri("ether://eth0").unwrap(), "eth0");
+        assert!(parse_uri("udp://eth0").is_err());
+        assert!(parse_uri("ether://").is_err());
+        assert!(parse_uri("ether://a-very-long-interface").is_err());
+        assert_eq!(NDN_MULTICAST_MAC.to_string(), "01:00:5e:00:17:aa");
+
+        let (a, b) = (Mac([2, 0, 0, 0, 0, 1]), Mac([2, 0, 0, 0, 0, 2]));
+        let now = Instant::now();
+        let mut pending = Pending::<Mac>::default();
+        let clip = Interest::builder().name("/lan/clip").nonce(1).build();
+        let other = Interest::builder().name("/lan/other").nonce(2).build();
+        assert!(pending.insert(&clip, a, now));
+        assert!(pending.insert(&other, b, now));
+        let data = Data::builder().name("/lan/clip/seg=0").build().encode();
+        assert_eq!(pending.answer(&data), [a]);
+        assert!(pending.answer(&data).is_empty());
+
+        // A host waiting twice is sent one copy
+        assert_eq!(hosts(vec![b, a, b]), [a, b]);
+    }
+}
@@ -0,0 +1,340 @@
+//! Live datapath events for `udcn events`: the XDP program publishes each forwarding decision on
+//! the `EVENTS` ring buffer while someone is subscribed, and the daemon fans them out.
+
+use std::{
+    fmt::Write as _,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use aya::maps::{Array, MapData, RingBuf};
+use log::warn;
+use tokio::{
+    io::unix::AsyncFd,
+    sync::broadcast::{self, error::RecvError},
+};
+use udcn_client::control::RawEvent;
+use udcn_common::{DatapathEvent, DropReason, EventOutcome, NackReason, Name, TimeBase, TlvType};
+
+use crate::expr::Subject;
+
+/// Events a slow subscriber may fall behind by before it starts missing them.
+const SUBSCRIBER_BACKLOG: usize = 4096;
+
+/// What a subscriber's filter sees of `event`, whose name the daemon resolved to `name`.
+pub fn subject(event: &DatapathEvent, name: Option<Name>) -> Subject {
+    Subject {
+        name_hash: Some(event.name_hash),
+        name,
+        packet_type: event.packet_type,
+        face_id: event.face_id,
+        size: event.packet_len,
+        dropped: event.outcome == EventOutcome::Dropped as u8,
+    }
+}
+
+/// Fans the `EVENTS` ring buffer out to subscribers, and keeps the datapath publishing only while
+/// there are any.
+pub struct EventHub {
+    sender: broadcast::Sender<DatapathEvent>,
+    enabled: Mutex<Array<MapData, u32>>,
+}
+
+impl EventHub {
+    /// Take the event maps and start draining the ring buffer.
+    pub fn new(ebpf: &mut aya::Ebpf) -> anyhow::Result<Arc<Self>> {
+        let ring = RingBuf::try_from(ebpf.take_map("EVENTS").unwrap())?;
+        let enabled = Array::try_from(ebpf.take_map("EVENTS_ENABLED").unwrap())?;
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        let hub = Arc::new(Self {
+            sender: sender.clone(),
+            enabled: Mutex::new(enabled),
+        });
+
+        let mut ring = AsyncFd::new(ring)?;
+        tokio::spawn(async move {
+            loop {
+                let mut guard = match ring.readable_mut().await {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        warn!("datapath event channel stopped: {e}");
+                        return;
+                    }
+                };
+                let ring = guard.get_inner_mut();
+                while let Some(item) = ring.next() {
+                    if let Some(event) = parse_event(&item) {
+                        // Nobody listening is not an error; the datapath stops publishing soon
+                        let _ = sender.send(event);
+                    }
+                }
+                guard.clear_ready();
+            }
+        });
+        Ok(hub)
+    }
+
+    pub fn subscribe(self: &Arc<Self>) -> Subscription {
+        let receiver = self.sender.subscribe();
+        self.update_enabled();
+        Subscription {
+            hub: self.clone(),
+            receiver: Some(receiver),
+        }
+    }
+
+    fn update_enabled(&self) {
+        let enabled = u32::from(self.sender.receiver_count() > 0);
+        if let Err(e) = self.enabled.lock().unwrap().set(0, enabled, 0) {
+            warn!(
+                "failed to switch datapath events {}: {e}",
+                if enabled == 1 { "on" } else { "off" }
+            );
+        }
+    }
+}
+
+/// A subscriber's view of the event stream. The datapath stops publishing once the last one is
+/// dropped.
+pub struct Subscription {
+    hub: Arc<EventHub>,
+    receiver: Option<broadcast::Receiver<DatapathEvent>>,
+}
+
+impl Subscription {
+    /// The next event, or how many were missed because this subscriber fell behind.
+    pub async fn recv(&mut self) -> Result<DatapathEvent, u64> {
+        let receiver = self.receiver.as_mut().unwrap();
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Ok(event),
+                Err(RecvError::Lagged(missed)) => return Err(missed),
+                // The hub holds a sender for as long as the subscription holds the hub
+                Err(RecvError::Closed) => std::future::pending::<()>().await,
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.receiver = None;
+        self.hub.update_enabled();
+    }
+}
+
+fn parse_event(bytes: &[u8]) -> Option<DatapathEvent> {
+    if bytes.len() < core::mem::size_of::<DatapathEvent>() {
+        return None;
+    }
+    // SAFETY: the length was checked, and every bit pattern is a valid DatapathEvent
+    Some(unsafe { (bytes.as_ptr() as *const DatapathEvent).read_unaligned() })
+}
+
+/// The time base every `Clock` in the daemon converts with, taken when first needed.
+static TIME_BASE: OnceLock<TimeBase> = OnceLock::new();
+
+/// Converts the datapath's `bpf_ktime_get_ns` timestamps, which count from boot, to wall-clock
+/// time.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    base: TimeBase,
+}
+
+impl Clock {
+    /// The daemon's clock, with the time base it writes to `TIME_BASE` when it attaches.
+    pub fn datapath() -> Self {
+        Self {
+            base: *TIME_BASE.get_or_init(take_time_base),
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch.
+    pub fn realtime_ns(&self, timestamp_ns: u64) -> u64 {
+        self.base.realtime_ns(timestamp_ns)
+    }
+
+    /// Nanoseconds since the Unix epoch now, following the datapath's clock rather than steps of
+    /// the wall clock, so that durations measured with it stay right.
+    pub fn now_ns(&self) -> u64 {
+        self.realtime_ns(ktime_ns())
+    }
+
+    /// `HH:MM:SS.micros` in UTC.
+    pub fn format(&self, timestamp_ns: u64) -> String {
+        format_time(self.realtime_ns(timestamp_ns))
+    }
+}
+
+/// Nanoseconds since the Unix epoch as `HH:MM:SS.micros` in UTC.
+pub fn format_time(realtime_ns: u64) -> String {
+    let seconds_of_day = realtime_ns / 1_000_000_000 % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        realtime_ns / 1_000 % 1_000_000
+    )
+}
+
+/// Read CLOCK_REALTIME between two readings of the datapath's clock and pair it with their
+/// midpoint, keeping the closest of a few tries.
+fn take_time_base() -> TimeBase {
+    let mut best = (u64::MAX, TimeBase::default());
+    for _ in 0..3 {
+        let before = ktime_ns();
+        let realtime_ns = clock_ns(libc::CLOCK_REALTIME);
+        let gap = ktime_ns() - before;
+        if gap < best.0 {
+            let monotonic_ns = before + gap / 2;
+            best = (
+                gap,
+                TimeBase {
+                    monotonic_ns,
+                    realtime_ns,
+                },
+            );
+        }
+    }
+    best.1
+}
+
+/// Write the daemon's time base to `TIME_BASE`, for programs reading the pinned maps.
+pub fn install_time_base(ebpf: &mut aya::Ebpf) -> anyhow::Result<TimeBase> {
+    let base = Clock::datapath().base;
+    let mut map: Array<_, TimeBase> = Array::try_from(ebpf.map_mut("TIME_BASE").unwrap())?;
+    map.set(0, base, 0)?;
+    Ok(base)
+}
+
+/// The datapath's clock, `bpf_ktime_get_ns`, now.
+pub fn ktime_ns() -> u64 {
+    clock_ns(libc::CLOCK_MONOTONIC)
+}
+
+pub fn clock_ns(clock: libc::clockid_t) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// One line of `udcn events`: time, packet type, name, face and what happened.
+pub fn format_event(event: &DatapathEvent, name: &str, clock: &Clock) -> String {
+    let packet_type = match event.packet_type {
+        t if t == TlvType::Interest as u8 => "interest",
+        t if t == TlvType::Data as u8 => "data",
+        t if t == TlvType::Nack as u8 => "nack",
+        _ => "unknown",
+    };
+    let nack_reason = || match NackReason::from_u8(event.reason) {
+        Some(reason) => format!("{reason:?}"),
+        None => event.reason.to_string(),
+    };
+    let outcome = match EventOutcome::from_u8(event.outcome) {
+        Some(EventOutcome::Forwarded) if event.packet_type == TlvType::Nack as u8 => {
+            format!("forwarded ({})", nack_reason())
+        }
+        Some(EventOutcome::Forwarded) => "forwarded".to_string(),
+        Some(EventOutcome::CacheHit) => "cache hit".to_string(),
+        Some(EventOutcome::NegativeCacheHit) => format!("negative cache hit ({})", nack_reason()),
+        Some(EventOutcome::Cached) => "cached".to_string(),
+        Some(EventOutcome::NotCached) => "not admitted to the content store".to_string(),
+        Some(EventOutcome::Uncacheable) => "not cacheable".to_string(),
+        Some(EventOutcome::Traced) => format!("traced {:016x}", event.trace_id),
+        Some(EventOutcome::Unroutable) => format!("no route, Nacked ({})", nack_reason()),
+        Some(EventOutcome::Dropped) => match DropReason::ALL.get(event.reason as usize) {
+            Some(reason) => format!("dropped ({})", reason.as_str()),
+            None => format!("dropped (reason {})", event.reason),
+        },
+        None => format!("outcome {}", event.outcome),
+    };
+
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "{} {:<8} {:<48} face {:#010x} {}",
+        clock.format(event.timestamp_ns),
+        packet_type,
+        name,
+        event.face_id,
+        outcome
+    );
+    line
+}
+
+/// An event as `events-raw` reports it, with the time in nanoseconds since the Unix epoch.
+pub fn raw_event(event: &DatapathEvent, clock: &Clock) -> RawEvent {
+    RawEvent {
+        timestamp_ns: clock.realtime_ns(event.timestamp_ns),
+        name_hash: event.name_hash,
+        packet_type: event.packet_type,
+        outcome: event.outcome,
+        reason: event.reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::Filter;
+
+    #[test]
+    fn test_format_event() {
+        let clock = Clock {
+            base: TimeBase::default(),
+        };
+        let event = DatapathEvent {
+            timestamp_ns: (3600 + 2 * 60 + 3) * 1_000_000_000 + 456_789_000,
+            name_hash: 0x1234,
+            face_id: 0x0a000001,
+            packet_type: TlvType::Interest as u8,
+            outcome: EventOutcome::Dropped as u8,
+            reason: DropReason::PitFull as u8,
+            reserved: 0,
+            packet_len: 64,
+            trace_id: 0,
+        };
+        let line = format_event(&event, "/a/b", &clock);
+        assert!(line.starts_with("01:02:03.456789 interest /a/b "));
+        assert!(line.ends_with("face 0x0a000001 dropped (pit_full)"));
+        let matches = |filter: &str| {
+            filter
+                .parse::<Filter>()
+                .unwrap()
+                .matches(&subject(&event, None))
+        };
+        assert!(matches("drop"));
+        assert!(matches("interest"));
+        assert!(!matches("data"));
+        assert!(matches("face == 0x0a000001, size < 100"));
+
+        let nack = DatapathEvent {
+            packet_type: TlvType::Interest as u8,
+            outcome: EventOutcome::NegativeCacheHit as u8,
+            reason: NackReason::NoRoute as u8,
+            ..event
+        };
+        assert!(format_event(&nack, "/a/b", &clock).ends_with("negative cache hit (NoRoute)"));
+
+        let traced = DatapathEvent {
+            outcome: EventOutcome::Traced as u8,
+            reason: 0,
+            trace_id: 0xabc,
+            ..event
+        };
+        assert!(format_event(&traced, "/a/b", &clock).ends_with("traced 0000000000000abc"));
+
+        let clock = Clock {
+            base: TimeBase {
+                monotonic_ns: 0,
+                realtime_ns: 1_000,
+            },
+        };
+        let raw = raw_event(&event, &clock).to_string();
+        assert_eq!(raw, "3723456790000 0x00001234 5 5 2");
+        assert_eq!(raw.parse::<RawEvent>().unwrap(), raw_event(&event, &clock));
+    }
+}
@@ -0,0 +1,356 @@
+//! Filter expressions for `udcn events`, `udcn capture` and `udcn sample`:
+//!
+//! ```text
+//! name ~ /video/*, type == data, face == 3, size > 1000
+//! ```
+//!
+//! A filter is a comma-separated list of clauses, all of which a packet must satisfy. `name ~`
+//! matches names under a prefix, in which `*` stands for any one component, and `name ==` matches
+//! a whole name. `type` is `interest`, `data` or `nack`. `face` and `size`, the length of the
+//! frame on the wire, compare as numbers. `!=` and `!~` negate a clause. The bare words
+//! `interest`, `data` and `nack` are short for `type ==` clauses, and `drop` matches packets the
+//! datapath dropped, which only events know about.
+//!
+//! The daemon matches every event and sampled packet against the whole filter, resolving name
+//! hashes through its name table; a name clause fails for a name it cannot resolve, unless the
+//! clause names a whole name whose hash can be compared instead. Sampling also hands the
+//! datapath what it can check itself, the packet types and the hash of a whole name, so that it
+//! samples among the packets that may match rather than among all of them.
+
+use std::str::FromStr;
+
+use udcn_common::{sample_type_bit, Component, Name, SampleConfig, TlvType, SAMPLE_TYPE_BITS};
+
+/// What a filter is matched against: an event, or a sampled packet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Subject {
+    /// The name hash from the packet's header, if it is an NDN packet.
+    pub name_hash: Option<u32>,
+    /// The name, if it is known.
+    pub name: Option<Name>,
+    /// The packet's TLV type.
+    pub packet_type: u8,
+    pub face_id: u32,
+    /// The frame's length on the wire.
+    pub size: u32,
+    pub dropped: bool,
+}
+
+/// A comparison between numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn compare(self, left: u32, right: u32) -> bool {
+        match self {
+            Self::Eq => left == right,
+            Self::Ne => left != right,
+            Self::Lt => left < right,
+            Self::Le => left <= right,
+            Self::Gt => left > right,
+            Self::Ge => left >= right,
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` is not read as `<`.
+const OPERATORS: [(&str, Operator); 8] = [
+    ("==", Operator::Compare(Op::Eq)),
+    ("!=", Operator::Compare(Op::Ne)),
+    ("<=", Operator::Compare(Op::Le)),
+    (">=", Operator::Compare(Op::Ge)),
+    ("!~", Operator::Under { negated: true }),
+    ("<", Operator::Compare(Op::Lt)),
+    (">", Operator::Compare(Op::Gt)),
+    ("~", Operator::Under { negated: false }),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Compare(Op),
+    Under { negated: bool },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Clause {
+    Name {
+        /// The components, `None` where the pattern has a `*`.
+        pattern: Vec<Option<Component>>,
+        /// Whether the name must end with the pattern, rather than fall under it.
+        whole: bool,
+        negated: bool,
+        /// The name hash of a whole name without wildcards.
+        name_hash: Option<u32>,
+    },
+    Type {
+        packet_type: u8,
+        negated: bool,
+    },
+    Face(Op, u32),
+    Size(Op, u32),
+    Dropped,
+}
+
+impl Clause {
+    fn matches(&self, subject: &Subject) -> bool {
+        match self {
+            Self::Name {
+                pattern,
+                whole,
+                negated,
+                name_hash,
+            } => {
+                let matched = match (&subject.name, name_hash) {
+                    (Some(name), _) => {
+                        let long_enough = match whole {
+                            true => name.len() == pattern.len(),
+                            false => name.len() >= pattern.len(),
+                        };
+                        long_enough
+                            && pattern
+                                .iter()
+                                .zip(name.components())
+                                .all(|(wanted, component)| {
+                                    wanted.as_ref().is_none_or(|wanted| wanted == component)
+                                })
+                    }
+                    (None, Some(name_hash)) if subject.name_hash.is_some() => {
+                        subject.name_hash == Some(*name_hash)
+                    }
+                    (None, _) => return false,
+                };
+                matched != *negated
+            }
+            Self::Type {
+                packet_type,
+                negated,
+            } => (subject.packet_type == *packet_type) != *negated,
+            Self::Face(op, face_id) => op.compare(subject.face_id, *face_id),
+            Self::Size(op, size) => op.compare(subject.size, *size),
+            Self::Dropped => subject.dropped,
+        }
+    }
+}
+
+/// A parsed filter expression. The empty filter matches everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    clauses: Vec<Clause>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    /// Whether any clause needs the subject's name.
+    pub fn uses_names(&self) -> bool {
+        self.clauses
+            .iter()
+            .any(|clause| matches!(clause, Clause::Name { .. }))
+    }
+
+    pub fn matches(&self, subject: &Subject) -> bool {
+        self.clauses.iter().all(|clause| clause.matches(subject))
+    }
+
+    /// `config` with the datapath's part of the filter: the packet types no packet matching it
+    /// can have and, if it names one whole name, that name's hash.
+    pub fn sample_config(&self, config: SampleConfig) -> SampleConfig {
+        let mut skip_types = 0;
+        let mut name_hash = 0;
+        for clause in &self.clauses {
+            match clause {
+                Clause::Type {
+                    packet_type,
+                    negated: false,
+                } => skip_types |= SAMPLE_TYPE_BITS & !sample_type_bit(*packet_type),
+                Clause::Type {
+                    packet_type,
+                    negated: true,
+                } => skip_types |= sample_type_bit(*packet_type),
+                Clause::Name {
+                    negated: false,
+                    name_hash: Some(hash),
+                    ..
+                } if name_hash == 0 => name_hash = *hash,
+                _ => {}
+            }
+        }
+        SampleConfig {
+            skip_types,
+            name_hash,
+            ..config
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let clauses = s
+            .split(',')
+            .map(parse_clause)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { clauses })
+    }
+}
+
+fn parse_clause(clause: &str) -> anyhow::Result<Clause> {
+    let clause = clause.trim();
+    let field_len = clause
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(clause.len());
+    let (field, rest) = clause.split_at(field_len);
+    let rest = rest.trim_start();
+    if rest.is_empty() {
+        return match field {
+            "drop" => Ok(Clause::Dropped),
+            packet_type => parse_type(packet_type, false),
+        };
+    }
+    let (operator, value) = OPERATORS
+        .iter()
+        .find_map(|(token, operator)| Some((*operator, rest.strip_prefix(token)?.trim())))
+        .ok_or_else(|| anyhow::anyhow!("expected an operator after '{field}' in '{clause}'"))?;
+    match (field, operator) {
+        ("name", Operator::Under { negated }) => parse_name(value, false, negated),
+        ("name", Operator::Compare(Op::Eq)) => parse_name(value, true, false),
+        ("name", Operator::Compare(Op::Ne)) => parse_name(value, true, true),
+        ("type", Operator::Compare(Op::Eq)) => parse_type(value, false),
+        ("type", Operator::Compare(Op::Ne)) => parse_type(value, true),
+        ("face", Operator::Compare(op)) => Ok(Clause::Face(op, parse_number(value)?)),
+        ("size", Operator::Compare(op)) => Ok(Clause::Size(op, value.parse()?)),
+        ("name" | "type" | "face" | "size", _) => {
+            anyhow::bail!("'{field}' cannot be compared that way in '{clause}'")
+        }
+        _ => anyhow::bail!(
+            "unknown field '{field}' in '{clause}', expected name, type, face or size"
+        ),
+    }
+}
+
+fn parse_name(value: &str, whole: bool, negated: bool) -> anyhow::Result<Clause> {
+    let uri = value.strip_prefix("ndn:").unwrap_or(value);
+    anyhow::ensure!(uri.starts_with('/'), "'{value}' is not a name");
+    let pattern = uri
+        .split('/')
+        .skip(1)
+        .filter(|component| !component.is_empty())
+        .map(|component| match component {
+            "*" => Ok(None),
+            component => Component::from_uri(component).map(Some),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("invalid name '{value}': {e}"))?;
+    let name_hash = match whole {
+        true => pattern
+            .iter()
+            .cloned()
+            .try_fold(Name::new(), |name, component| Some(name.append(component?)))
+            .map(|name| name.name_hash()),
+        false => None,
+    };
+    Ok(Clause::Name {
+        pattern,
+        whole,
+        negated,
+        name_hash,
+    })
+}
+
+fn parse_type(value: &str, negated: bool) -> anyhow::Result<Clause> {
+    let packet_type = match value {
+        "interest" => TlvType::Interest,
+        "data" => TlvType::Data,
+        "nack" => TlvType::Nack,
+        _ => anyhow::bail!("unknown packet type '{value}', expected interest, data or nack"),
+    };
+    Ok(Clause::Type {
+        packet_type: packet_type as u8,
+        negated,
+    })
+}
+
+/// A decimal or `0x`-prefixed hexadecimal number, as face IDs are printed in hex.
+fn parse_number(value: &str) -> anyhow::Result<u32> {
+    Ok(match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => value.parse()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_match_and_compile_for_the_datapath() {
+        let filter: Filter = "name ~ /video/*, type == data, face == 3, size > 1000"
+            .parse()
+            .unwrap();
+        let name = Name::from_uri("/video/a/seg=1").unwrap();
+        let data = Subject {
+            name_hash: Some(name.name_hash()),
+            name: Some(name.clone()),
+            packet_type: TlvType::Data as u8,
+            face_id: 3,
+            size: 1200,
+            dropped: false,
+        };
+        assert!(filter.matches(&data));
+        assert!(!filter.matches(&Subject {
+            size: 1000,
+            ..data.clone()
+        }));
+        assert!(!filter.matches(&Subject {
+            name: Some(Name::from_uri("/video").unwrap()),
+            ..data.clone()
+        }));
+        // A name the daemon cannot resolve fails a prefix, but a whole name is checked by hash
+        let unresolved = Subject {
+            name: None,
+            ..data.clone()
+        };
+        assert!(!filter.matches(&unresolved));
+        assert!("name == /video/a/seg=1"
+            .parse::<Filter>()
+            .unwrap()
+            .matches(&unresolved));
+        assert!(!"name != /video/a/seg=1"
+            .parse::<Filter>()
+            .unwrap()
+            .matches(&data));
+
+        assert!(!"drop, interest".parse::<Filter>().unwrap().uses_names());
+        assert!("face == 0x3, nack".parse::<Filter>().is_ok());
+        assert!("type ~ data".parse::<Filter>().is_err());
+        assert!("colour == red".parse::<Filter>().is_err());
+        assert!("type == lp".parse::<Filter>().is_err());
+        assert!(Filter::default().matches(&Subject::default()));
+
+        let compiled = "type != nack, name == /video/a/seg=1"
+            .parse::<Filter>()
+            .unwrap()
+            .sample_config(SampleConfig::default());
+        assert_eq!(compiled.skip_types, sample_type_bit(TlvType::Nack as u8));
+        assert_eq!(compiled.name_hash, name.name_hash());
+        let compiled = filter.sample_config(SampleConfig::default());
+        assert_eq!(
+            compiled.skip_types,
+            SAMPLE_TYPE_BITS & !sample_type_bit(TlvType::Data as u8)
+        );
+        assert_eq!(compiled.name_hash, 0);
+    }
+}
@@ -0,0 +1,251 @@
+//! Fault injection, for exercising retransmission, Nack and failover logic deterministically in
+//! tests. Only daemons built with the `faults` feature have it.
+//!
+//! `udcn fault` tells the datapath to drop a share of Interests at random, to drop every Interest
+//! under blackholed prefixes, to flip bits in the signature of Data, or to hold Data back.
+//! Dropped Interests count as `injected` drops. Held Data is copied onto the `HELD_DATA` perf
+//! buffer and dropped, and the daemon sends the frame on unchanged once the delay is over, through
+//! a raw IP socket so that it keeps its addresses. Faults last until cleared or the daemon stops.
+
+use std::{
+    fmt::Write as _,
+    io, mem,
+    os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use aya::maps::{Array, HashMap, MapData, PerfEventArray};
+use log::{debug, info, warn};
+use tokio::time::sleep;
+use udcn_common::{FaultConfig, Name};
+
+use crate::{capture, events::ktime_ns, nocache::NoCachePrefix};
+
+/// Faults the datapath is injecting.
+pub struct Faults {
+    config: Array<MapData, FaultConfig>,
+    blackhole_map: HashMap<MapData, u32, u8>,
+    current: FaultConfig,
+    /// Blackholed prefixes; they take the same form as `--no-cache` ones.
+    blackholes: Vec<NoCachePrefix>,
+    /// The delay held Data is sent on after, shared with the tasks doing it. `None` without the
+    /// raw socket that takes.
+    delay_ms: Option<Arc<AtomicU32>>,
+}
+
+impl Faults {
+    /// Take the fault injection maps and start sending on held Data. Holding Data needs
+    /// CAP_NET_RAW, so this has to happen before the daemon drops privileges.
+    pub fn new(ebpf: &mut aya::Ebpf) -> anyhow::Result<Self> {
+        let config = Array::try_from(ebpf.take_map("FAULTS").unwrap())?;
+        let blackhole_map = HashMap::try_from(ebpf.take_map("FAULT_BLACKHOLES").unwrap())?;
+        let held = PerfEventArray::try_from(ebpf.take_map("HELD_DATA").unwrap())?;
+        let delay_ms = match open_raw_socket() {
+            Ok(socket) => {
+                let delay_ms = Arc::new(AtomicU32::new(0));
+                send_on_held(held, Arc::new(socket), delay_ms.clone())?;
+                Some(delay_ms)
+            }
+            Err(e) => {
+                warn!("Data cannot be delayed without CAP_NET_RAW: {e}");
+                None
+            }
+        };
+        info!("Fault injection is available; see `udcn fault`");
+        Ok(Self {
+            config,
+            blackhole_map,
+            current: FaultConfig::default(),
+            blackholes: Vec::new(),
+            delay_ms,
+        })
+    }
+
+    /// Answer a `fault` request: change one fault, or with no argument change nothing, and
+    /// describe the faults in effect.
+    pub fn handle(&mut self, request: &str) -> anyhow::Result<String> {
+        let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+        match command {
+            "" => {}
+            "drop-interests" => {
+                let percent: f64 = argument.parse().context("expected a percentage")?;
+                anyhow::ensure!(
+                    (0.0..=100.0).contains(&percent),
+                    "the percentage must be between 0 and 100"
+                );
+                self.current.interest_drop_probability = (percent / 100.0 * u32::MAX as f64) as u32;
+            }
+            "delay-data" => {
+                let delay_ms: u32 = argument.parse().context("expected milliseconds")?;
+                self.delay_ms
+                    .as_ref()
+                    .context("the daemon cannot delay Data without CAP_NET_RAW")?
+                    .store(delay_ms, Ordering::Relaxed);
+                self.current.data_delay_ms = delay_ms;
+            }
+            "corrupt-signatures" => {
+                self.current.corrupt_signatures = match argument {
+                    "on" => 1,
+                    "off" => 0,
+                    _ => anyhow::bail!("expected on or off"),
+                };
+            }
+            "blackhole" => {
+                let prefix: NoCachePrefix = argument.parse()?;
+                if !self.blackholes.contains(&prefix) {
+                    self.blackhole_map
+                        .insert(prefix.key(), 1, 0)
+                        .context("failed to blackhole the prefix")?;
+                    self.blackholes.push(prefix);
+                }
+            }
+            "restore" => {
+                let prefix: NoCachePrefix = argument.parse()?;
+                let index = self
+                    .blackholes
+                    .iter()
+                    .position(|blackhole| *blackhole == prefix)
+                    .with_context(|| format!("'{}' is not blackholed", prefix.0))?;
+                self.blackhole_map.remove(&prefix.key())?;
+                self.blackholes.remove(index);
+            }
+            "clear" => {
+                for prefix in self.blackholes.drain(..) {
+                    self.blackhole_map.remove(&prefix.key())?;
+                }
+                self.current = FaultConfig::default();
+                if let Some(delay_ms) = &self.delay_ms {
+                    delay_ms.store(0, Ordering::Relaxed);
+                }
+            }
+            _ => anyhow::bail!("unknown fault '{command}'"),
+        }
+        self.current.blackholes = self.blackholes.len() as u32;
+        self.config.set(0, self.current, 0)?;
+        if !command.is_empty() {
+            info!("Fault injection: {request}");
+        }
+        Ok(describe(
+            &self.current,
+            self.blackholes.iter().map(|prefix| &prefix.0),
+        ))
+    }
+}
+
+fn describe<'a>(faults: &FaultConfig, blackholes: impl Iterator<Item = &'a Name>) -> String {
+    let mut out = String::new();
+    if faults.interest_drop_probability != 0 {
+        let percent = faults.interest_drop_probability as f64 * 100.0 / u32::MAX as f64;
+        let _ = writeln!(out, "Dropping {percent:.1}% of Interests");
+    }
+    for prefix in blackholes {
+        let _ = writeln!(out, "Dropping Interests under '{prefix}'");
+    }
+    if faults.data_delay_ms != 0 {
+        let _ = writeln!(out, "Delaying Data by {} ms", faults.data_delay_ms);
+    }
+    if faults.corrupt_signatures != 0 {
+        out += "Corrupting Data signatures\n";
+    }
+    if out.is_empty() {
+        out += "No faults injected\n";
+    }
+    out
+}
+
+/// A raw IP socket, which sends packets with the IP header they come with.
+fn open_raw_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_RAW,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Send each frame the datapath holds back on, without its Ethernet header, once `delay_ms` has
+/// passed since it arrived.
+fn send_on_held(
+    held: PerfEventArray<MapData>,
+    socket: Arc<OwnedFd>,
+    delay_ms: Arc<AtomicU32>,
+) -> anyhow::Result<()> {
+    capture::read_samples(held, "holding Data", move |sample, frame| {
+        let Some(packet) = frame.get(14..).filter(|packet| packet.len() >= 20) else {
+            return;
+        };
+        let packet = packet.to_vec();
+        let delay_ns = u64::from(delay_ms.load(Ordering::Relaxed)) * 1_000_000;
+        let remaining = (sample.timestamp_ns + delay_ns).saturating_sub(ktime_ns());
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_nanos(remaining)).await;
+            if let Err(e) = send_ip(&socket, &packet) {
+                warn!("failed to send on held Data: {e}");
+            } else {
+                debug!("sent on Data held for {} ms", delay_ns / 1_000_000);
+            }
+        });
+    })
+}
+
+/// Send an IPv4 packet, header included, to its destination address.
+fn send_ip(socket: &OwnedFd, packet: &[u8]) -> io::Result<()> {
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr {
+            s_addr: u32::from_ne_bytes([packet[16], packet[17], packet[18], packet[19]]),
+        },
+        sin_zero: [0; 8],
+    };
+    let ret = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            libc::MSG_DONTWAIT,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_faults() {
+        assert_eq!(
+            describe(&FaultConfig::default(), std::iter::empty()),
+            "No faults injected\n"
+        );
+        let faults = FaultConfig {
+            interest_drop_probability: u32::MAX / 4,
+            data_delay_ms: 200,
+            corrupt_signatures: 1,
+            blackholes: 1,
+        };
+        let prefix = Name::from("/video");
+        assert_eq!(
+            describe(&faults, std::iter::once(&prefix)),
+            "Dropping 25.0% of Interests\nDropping Interests under '/video'\n\
+             Delaying Data by 200 ms\nCorrupting Data signatures\n"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Interest filters for `udcn serve`, given on the command line or read from a TOML file:
+//!
+//! ```toml
+//! [[filter]]
+//! prefix = "/video"
+//! regex = '/seg=\d+'  # optional, matched against the components after the prefix
+//! content = "segment"
+//! freshness_ms = 1000  # optional
+//! ```
+
+use std::{fs, future, path::Path};
+
+use anyhow::Context as _;
+use log::info;
+use serde::Deserialize;
+use udcn_client::{InterestFilter, InterestFilters};
+use udcn_common::{Data, Interest, Name};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FiltersFile {
+    #[serde(rename = "filter")]
+    filters: Vec<FilterConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterConfig {
+    prefix: Name,
+    #[serde(default)]
+    regex: Option<String>,
+    content: String,
+    /// FreshnessPeriod of the Data answering the filter.
+    #[serde(default)]
+    freshness_ms: Option<u64>,
+}
+
+pub fn load(path: &Path) -> anyhow::Result<InterestFilters> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse(&contents).with_context(|| format!("invalid filters in {}", path.display()))
+}
+
+fn parse(contents: &str) -> anyhow::Result<InterestFilters> {
+    let file: FiltersFile = toml::from_str(contents)?;
+    let mut filters = InterestFilters::new();
+    for config in file.filters {
+        let mut filter = InterestFilter::new(config.prefix);
+        if let Some(regex) = &config.regex {
+            filter = filter.regex(regex)?;
+        }
+        filters.register(filter, answer_with(config.content.into_bytes(), config.freshness_ms));
+    }
+    Ok(filters)
+}
+
+/// A handler answering every Interest with Data of the same name carrying `content`, fresh for
+/// `freshness_ms` if given.
+pub fn answer_with(
+    content: Vec<u8>,
+    freshness_ms: Option<u64>,
+) -> impl Fn(Interest) -> future::Ready<Option<Data>> + Send + Sync + 'static {
+    move |interest| {
+        info!("Sending Data response for '{}'", interest.name);
+        let mut builder = Data::builder()
+            .name(interest.name)
+            .content(content.as_slice());
+        if let Some(freshness_ms) = freshness_ms {
+            builder = builder.freshness_period_ms(freshness_ms);
+        }
+        future::ready(Some(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filters() {
+        let filters = parse(
+            r#"
+            [[filter]]
+            prefix = "/video"
+            regex = '/seg=\d+'
+            content = "segment"
+
+            [[filter]]
+            prefix = "/chat"
+            content = "hello"
+            freshness_ms = 1000
+            "#,
+        )
+        .unwrap();
+        let prefixes: Vec<_> = filters
+            .iter()
+            .map(|filter| filter.prefix().to_string())
+            .collect();
+        assert_eq!(prefixes, ["/video", "/chat"]);
+
+        let answer = answer_with(b"hello".to_vec(), Some(1000));
+        let data = answer(Interest::builder().name("/chat").build()).into_inner();
+        assert_eq!(data.unwrap().meta.freshness_period_ms, Some(1000));
+
+        assert!(parse("[[filter]]\nprefix = \"/a\"\nregex = \"(\"\ncontent = \"\"\n").is_err());
+    }
+}
@@ -0,0 +1,492 @@
+//! Per-prefix flow records for network accounting, exported over UDP as IPFIX (RFC 7011).
+//!
+//! While export is configured the daemon follows the datapath events and accounts each packet to
+//! a flow: the first `prefix_components` components of its name, as registered with the daemon,
+//! and the face it arrived on. A flow counts Interests, the Data answering them and its bytes
+//! (whole frames), content store hits, and the mean time from forwarding an Interest to its Data
+//! arriving. Dropped packets are left out; `udcn stats` counts those. Every `interval_secs` the
+//! flows seen since the last export go to the collector, each message carrying the template
+//! first so collectors can decode it whenever they start listening.
+//!
+//! NDN-specific fields use enterprise-specific Information Elements under [`ENTERPRISE_NUMBER`];
+//! the face ID goes in `ingressInterface` and the data bytes in `octetDeltaCount`.
+
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, UdpSocket as StdUdpSocket},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::{
+    net::UdpSocket,
+    time::{sleep, Duration},
+};
+use udcn_common::{DatapathEvent, EventOutcome, Name, TlvType, DEFAULT_INTEREST_LIFETIME_MS};
+
+use crate::{
+    daemon::Daemon,
+    events::{self, Clock, EventHub},
+};
+
+/// The private enterprise number NDN fields are registered under. 32473 is the one RFC 5612
+/// reserves for documentation and examples; collectors have to be told about these fields.
+pub const ENTERPRISE_NUMBER: u32 = 32473;
+
+/// Flow key for names the daemon has not been told about.
+const UNREGISTERED: &str = "<unregistered>";
+
+/// Largest message sent, to stay within a typical path MTU.
+const MAX_MESSAGE_SIZE: usize = 1400;
+
+/// Interests waiting for Data, for latency; more are not timed until some are answered.
+const MAX_OUTSTANDING: usize = 65_536;
+
+const IPFIX_VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const TEMPLATE_ID: u16 = 256;
+const VARIABLE_LENGTH: u16 = 65535;
+
+/// Information Element ID, length and enterprise number (0 for IANA's) of each field of a
+/// record, in order.
+const FIELDS: [(u16, u16, u32); 9] = [
+    // flowStartMilliseconds, flowEndMilliseconds
+    (152, 8, 0),
+    (153, 8, 0),
+    // ingressInterface
+    (10, 4, 0),
+    // ndnPrefix
+    (1, VARIABLE_LENGTH, ENTERPRISE_NUMBER),
+    // ndnInterestCount, ndnDataCount
+    (2, 8, ENTERPRISE_NUMBER),
+    (3, 8, ENTERPRISE_NUMBER),
+    // octetDeltaCount
+    (1, 8, 0),
+    // ndnCacheHitCount, ndnMeanLatencyMicroseconds
+    (4, 8, ENTERPRISE_NUMBER),
+    (5, 4, ENTERPRISE_NUMBER),
+];
+
+/// The `[flows]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowsConfig {
+    /// The IPFIX collector, e.g. `192.0.2.10:4739`.
+    pub collector: SocketAddr,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Name components that make up a flow's prefix.
+    #[serde(default = "default_prefix_components")]
+    pub prefix_components: usize,
+    /// Observation Domain ID in each message, to tell several daemons apart.
+    #[serde(default)]
+    pub observation_domain: u32,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+fn default_prefix_components() -> usize {
+    2
+}
+
+impl FlowsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.interval_secs > 0,
+            "flows.interval_secs must be positive"
+        );
+        anyhow::ensure!(
+            self.prefix_components > 0,
+            "flows.prefix_components must be positive"
+        );
+        Ok(())
+    }
+}
+
+/// What one prefix exchanged on one face since the last export.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlowRecord {
+    pub prefix: String,
+    pub face_id: u32,
+    /// Milliseconds since the Unix epoch of the first and last packet.
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub interests: u64,
+    pub data: u64,
+    pub data_bytes: u64,
+    pub cache_hits: u64,
+    latency_total_ns: u64,
+    latency_samples: u64,
+}
+
+impl FlowRecord {
+    pub fn mean_latency_us(&self) -> u32 {
+        (self.latency_total_ns / self.latency_samples.max(1) / 1000) as u32
+    }
+}
+
+/// Flows being accounted until the next export.
+pub struct Flows {
+    prefix_components: usize,
+    clock: Clock,
+    flows: HashMap<(String, u32), FlowRecord>,
+    /// When each outstanding Interest was forwarded, by name hash.
+    forwarded: HashMap<u32, u64>,
+}
+
+impl Flows {
+    pub fn new(prefix_components: usize, clock: Clock) -> Self {
+        Self {
+            prefix_components,
+            clock,
+            flows: HashMap::new(),
+            forwarded: HashMap::new(),
+        }
+    }
+
+    /// Account `event`, for a packet named `name` if the daemon knows the name.
+    pub fn record(&mut self, event: &DatapathEvent, name: Option<&Name>) {
+        let outcome = EventOutcome::from_u8(event.outcome);
+        if outcome == Some(EventOutcome::Dropped) {
+            return;
+        }
+        let latency_ns = match event.packet_type {
+            t if t == TlvType::Interest as u8 => {
+                if outcome == Some(EventOutcome::Forwarded)
+                    && self.forwarded.len() < MAX_OUTSTANDING
+                {
+                    self.forwarded
+                        .entry(event.name_hash)
+                        .or_insert(event.timestamp_ns);
+                }
+                None
+            }
+            t if t == TlvType::Data as u8 => self
+                .forwarded
+                .remove(&event.name_hash)
+                .map(|forwarded| event.timestamp_ns.saturating_sub(forwarded)),
+            _ => {
+                // A Nack ends the wait without Data
+                self.forwarded.remove(&event.name_hash);
+                return;
+            }
+        };
+
+        let prefix = name.map_or_else(
+            || UNREGISTERED.to_string(),
+            |name| name.prefix(self.prefix_components).to_string(),
+        );
+        let time_ms = self.clock.realtime_ns(event.timestamp_ns) / 1_000_000;
+        let flow = self
+            .flows
+            .entry((prefix.clone(), event.face_id))
+            .or_insert_with(|| FlowRecord {
+                prefix,
+                face_id: event.face_id,
+                start_ms: time_ms,
+                ..Default::default()
+            });
+        flow.end_ms = time_ms;
+        if event.packet_type == TlvType::Interest as u8 {
+            flow.interests += 1;
+            if outcome == Some(EventOutcome::CacheHit) {
+                flow.cache_hits += 1;
+            }
+        } else {
+            flow.data += 1;
+            flow.data_bytes += u64::from(event.packet_len);
+        }
+        if let Some(latency_ns) = latency_ns {
+            flow.latency_total_ns += latency_ns;
+            flow.latency_samples += 1;
+        }
+    }
+
+    /// The flows since the last call, by prefix and face. Interests left unanswered past the
+    /// default lifetime stop being timed.
+    pub fn take(&mut self, now_ns: u64) -> Vec<FlowRecord> {
+        let lifetime_ns = u64::from(DEFAULT_INTEREST_LIFETIME_MS) * 1_000_000;
+        self.forwarded
+            .retain(|_, forwarded| now_ns.saturating_sub(*forwarded) < lifetime_ns);
+        let mut records: Vec<FlowRecord> = self.flows.drain().map(|(_, record)| record).collect();
+        records.sort_by(|a, b| (&a.prefix, a.face_id).cmp(&(&b.prefix, b.face_id)));
+        records
+    }
+}
+
+fn template_set() -> Vec<u8> {
+    let mut record = Vec::new();
+    record.extend_from_slice(&TEMPLATE_ID.to_be_bytes());
+    record.extend_from_slice(&(FIELDS.len() as u16).to_be_bytes());
+    for (id, length, enterprise) in FIELDS {
+        if enterprise == 0 {
+            record.extend_from_slice(&id.to_be_bytes());
+            record.extend_from_slice(&length.to_be_bytes());
+        } else {
+            record.extend_from_slice(&(id | 0x8000).to_be_bytes());
+            record.extend_from_slice(&length.to_be_bytes());
+            record.extend_from_slice(&enterprise.to_be_bytes());
+        }
+    }
+    set(TEMPLATE_SET_ID, &record)
+}
+
+fn set(id: u16, records: &[u8]) -> Vec<u8> {
+    let mut set = Vec::with_capacity(4 + records.len());
+    set.extend_from_slice(&id.to_be_bytes());
+    set.extend_from_slice(&((4 + records.len()) as u16).to_be_bytes());
+    set.extend_from_slice(records);
+    set
+}
+
+fn encode_record(record: &FlowRecord, out: &mut Vec<u8>) {
+    out.extend_from_slice(&record.start_ms.to_be_bytes());
+    out.extend_from_slice(&record.end_ms.to_be_bytes());
+    out.extend_from_slice(&record.face_id.to_be_bytes());
+    // Variable-length fields: one length byte, or 255 and two more
+    let prefix = &record.prefix.as_bytes()[..record.prefix.len().min(u16::MAX as usize)];
+    if prefix.len() < 255 {
+        out.push(prefix.len() as u8);
+    } else {
+        out.push(255);
+        out.extend_from_slice(&(prefix.len() as u16).to_be_bytes());
+    }
+    out.extend_from_slice(prefix);
+    out.extend_from_slice(&record.interests.to_be_bytes());
+    out.extend_from_slice(&record.data.to_be_bytes());
+    out.extend_from_slice(&record.data_bytes.to_be_bytes());
+    out.extend_from_slice(&record.cache_hits.to_be_bytes());
+    out.extend_from_slice(&record.mean_latency_us().to_be_bytes());
+}
+
+/// Exports in progress: the sequence number of the next message, which counts the data records
+/// sent before it.
+pub struct Exporter {
+    observation_domain: u32,
+    sequence: u32,
+}
+
+impl Exporter {
+    pub fn new(observation_domain: u32) -> Self {
+        Self {
+            observation_domain,
+            sequence: 0,
+        }
+    }
+
+    /// `records` as IPFIX messages of at most [`MAX_MESSAGE_SIZE`] bytes, each led by the
+    /// template.
+    pub fn encode(&mut self, records: &[FlowRecord], export_time: u32) -> Vec<Vec<u8>> {
+        let template = template_set();
+        let mut messages = Vec::new();
+        let mut remaining = records;
+        while !remaining.is_empty() {
+            let mut data = Vec::new();
+            let mut count = 0;
+            for record in remaining {
+                let mut encoded = Vec::new();
+                encode_record(record, &mut encoded);
+                // At least one record per message, however long its prefix
+                if count > 0
+                    && 16 + template.len() + 4 + data.len() + encoded.len() > MAX_MESSAGE_SIZE
+                {
+                    break;
+                }
+                data.extend_from_slice(&encoded);
+                count += 1;
+            }
+            remaining = &remaining[count..];
+
+            let data = set(TEMPLATE_ID, &data);
+            let length = 16 + template.len() + data.len();
+            let mut message = Vec::with_capacity(length);
+            message.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+            message.extend_from_slice(&(length as u16).to_be_bytes());
+            message.extend_from_slice(&export_time.to_be_bytes());
+            message.extend_from_slice(&self.sequence.to_be_bytes());
+            message.extend_from_slice(&self.observation_domain.to_be_bytes());
+            message.extend_from_slice(&template);
+            message.extend_from_slice(&data);
+            messages.push(message);
+            self.sequence = self.sequence.wrapping_add(count as u32);
+        }
+        messages
+    }
+}
+
+/// Account the datapath events to flows, and send them to the collector every interval.
+pub fn start(
+    config: FlowsConfig,
+    daemon: Arc<Mutex<Daemon>>,
+    hub: &Arc<EventHub>,
+) -> anyhow::Result<()> {
+    let local: SocketAddr = if config.collector.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = StdUdpSocket::bind(local)?;
+    socket
+        .connect(config.collector)
+        .with_context(|| format!("failed to reach flow collector {}", config.collector))?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+    info!(
+        "Exporting flow records for {}-component prefixes to {} every {} s",
+        config.prefix_components, config.collector, config.interval_secs
+    );
+
+    let flows = Arc::new(Mutex::new(Flows::new(
+        config.prefix_components,
+        Clock::datapath(),
+    )));
+    let mut events = hub.subscribe();
+    let recording = flows.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(missed) => {
+                    debug!("flow accounting missed {missed} datapath events");
+                    continue;
+                }
+            };
+            let name = daemon.lock().unwrap().name(event.name_hash);
+            recording.lock().unwrap().record(&event, name.as_ref());
+        }
+    });
+
+    let mut exporter = Exporter::new(config.observation_domain);
+    let interval = Duration::from_secs(config.interval_secs);
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            let now_ns = events::ktime_ns();
+            let records = flows.lock().unwrap().take(now_ns);
+            if records.is_empty() {
+                continue;
+            }
+            let export_time = (Clock::datapath().realtime_ns(now_ns) / 1_000_000_000) as u32;
+            for message in exporter.encode(&records, export_time) {
+                if let Err(e) = socket.send(&message).await {
+                    warn!("failed to export flow records: {e}");
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(packet_type: TlvType, outcome: EventOutcome, timestamp_ns: u64) -> DatapathEvent {
+        DatapathEvent {
+            timestamp_ns,
+            name_hash: 0x1234,
+            face_id: 7,
+            packet_type: packet_type as u8,
+            outcome: outcome as u8,
+            reason: 0,
+            reserved: 0,
+            packet_len: 100,
+            trace_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_flows_account_per_prefix_and_face() {
+        let mut flows = Flows::new(2, Clock::datapath());
+        let name = Name::from("/video/clip/seg=1");
+        let ms = 1_000_000;
+        flows.record(
+            &event(TlvType::Interest, EventOutcome::Forwarded, ms),
+            Some(&name),
+        );
+        flows.record(
+            &event(TlvType::Data, EventOutcome::Cached, 5 * ms),
+            Some(&name),
+        );
+        flows.record(
+            &event(TlvType::Interest, EventOutcome::CacheHit, 6 * ms),
+            Some(&name),
+        );
+        flows.record(
+            &event(TlvType::Interest, EventOutcome::Dropped, 7 * ms),
+            Some(&name),
+        );
+        flows.record(
+            &event(TlvType::Interest, EventOutcome::Forwarded, 8 * ms),
+            None,
+        );
+
+        let records = flows.take(8 * ms);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].prefix, "/video/clip");
+        assert_eq!(
+            (
+                records[0].interests,
+                records[0].data,
+                records[0].data_bytes,
+                records[0].cache_hits
+            ),
+            (2, 1, 100, 1)
+        );
+        assert_eq!(records[0].mean_latency_us(), 4000);
+        assert_eq!(records[0].end_ms - records[0].start_ms, 5);
+        assert_eq!(records[1].prefix, UNREGISTERED);
+        assert!(flows.take(8 * ms).is_empty());
+        // Unanswered past the Interest lifetime
+        assert!(flows.take(10_000 * ms).is_empty() && flows.forwarded.is_empty());
+    }
+
+    #[test]
+    fn test_ipfix_messages() {
+        let record = FlowRecord {
+            prefix: "/video/clip".to_string(),
+            face_id: 7,
+            start_ms: 1,
+            end_ms: 2,
+            interests: 3,
+            data: 4,
+            data_bytes: 5,
+            cache_hits: 6,
+            latency_total_ns: 7_000,
+            latency_samples: 1,
+        };
+        let mut exporter = Exporter::new(42);
+        let messages = exporter.encode(std::slice::from_ref(&record), 1000);
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(&message[..2], &[0, 10]);
+        assert_eq!(
+            u16::from_be_bytes([message[2], message[3]]) as usize,
+            message.len()
+        );
+        assert_eq!(&message[8..16], &[0, 0, 0, 0, 0, 0, 0, 42]);
+        // Template set: 4 IANA fields of 4 bytes and 5 enterprise ones of 8
+        let template_len = u16::from_be_bytes([message[18], message[19]]) as usize;
+        assert_eq!(template_len, 4 + 4 + 4 * 4 + 5 * 8);
+        let data = &message[16 + template_len..];
+        assert_eq!(&data[..2], &TEMPLATE_ID.to_be_bytes());
+        assert_eq!(data.len(), 4 + 20 + 1 + 11 + 32 + 4);
+        assert_eq!(&data[25..36], b"/video/clip");
+        assert_eq!(&data[data.len() - 4..], &7u32.to_be_bytes());
+
+        // Split once the records outgrow a message, numbered by the records before
+        let records = vec![record; 40];
+        let messages = exporter.encode(&records, 1000);
+        assert!(messages.len() > 1);
+        assert!(messages
+            .iter()
+            .all(|message| message.len() <= MAX_MESSAGE_SIZE));
+        assert_eq!(&messages[0][4..8], &1000u32.to_be_bytes());
+        assert_eq!(&messages[0][8..12], &1u32.to_be_bytes());
+    }
+}
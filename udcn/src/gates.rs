@@ -0,0 +1,95 @@
+//! Gated namespaces on the forwarder: prefixes whose Interests the tunnels only relay if they
+//! carry a valid access token, as `udcn_client::gate` describes.
+//!
+//! The datapath cannot check a MAC, so it must not answer a gated Interest itself: Data under a
+//! gated prefix is kept out of the content store, and every Interest for it reaches the tunnels,
+//! which drop those without a token before giving them a PIT entry. Data XDP cannot read the Name
+//! of is never cached, and the daemon refuses to preload or import gated Data, or Data whose name
+//! it does not know, so nothing reaches the store around the prefix check. Dropped Interests are
+//! counted per prefix on the metrics page.
+
+use std::{fs, path::PathBuf};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use udcn_client::Gates;
+use udcn_common::Name;
+
+use crate::nocache::NoCachePrefix;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GateConfig {
+    pub prefix: Name,
+    /// A file holding the secret tokens for the prefix are issued with, as raw bytes.
+    pub secret_file: PathBuf,
+}
+
+impl GateConfig {
+    /// The prefix, as the content store is told to keep it out.
+    pub fn no_cache(&self) -> anyhow::Result<NoCachePrefix> {
+        NoCachePrefix::new(self.prefix.clone())
+            .with_context(|| format!("invalid prefix for the gate on '{}'", self.prefix))
+    }
+}
+
+/// Check that the datapath can keep every gated prefix out of the content store, and that none
+/// is gated twice.
+pub fn validate(gates: &[GateConfig]) -> anyhow::Result<()> {
+    for (i, gate) in gates.iter().enumerate() {
+        gate.no_cache()?;
+        anyhow::ensure!(
+            gates[..i].iter().all(|other| other.prefix != gate.prefix),
+            "'{}' is gated twice",
+            gate.prefix
+        );
+    }
+    Ok(())
+}
+
+/// The gates, with their secrets read.
+pub fn load(gates: &[GateConfig]) -> anyhow::Result<Gates> {
+    let loaded = Gates::default();
+    for gate in gates {
+        let secret = fs::read(&gate.secret_file)
+            .with_context(|| format!("failed to read {}", gate.secret_file.display()))?;
+        anyhow::ensure!(
+            !secret.is_empty(),
+            "{} is empty",
+            gate.secret_file.display()
+        );
+        loaded.add(gate.prefix.clone(), secret);
+    }
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_client::Token;
+
+    use super::*;
+
+    #[test]
+    fn test_gates_load_their_secrets() {
+        let secret_file =
+            std::env::temp_dir().join(format!("udcn-gate-secret-{}", std::process::id()));
+        fs::write(&secret_file, b"secret").unwrap();
+        let gate = GateConfig {
+            prefix: Name::from("/paid"),
+            secret_file: secret_file.clone(),
+        };
+        validate(std::slice::from_ref(&gate)).unwrap();
+        assert!(validate(&[gate.clone(), gate.clone()]).is_err());
+        let gates = load(std::slice::from_ref(&gate)).unwrap();
+
+        let token = Token::issue(b"secret", &gate.prefix, u64::MAX);
+        let interest = udcn_common::Interest::builder()
+            .name("/paid/a")
+            .application_parameters(token.0)
+            .build();
+        assert!(gates.admits(&interest));
+        fs::write(&secret_file, b"").unwrap();
+        assert!(load(&[gate]).is_err());
+        fs::remove_file(&secret_file).unwrap();
+    }
+}
@@ -0,0 +1,539 @@
+//! `udcn health`, which checks that a running daemon is doing its job, and `udcn doctor`, which
+//! looks for the usual reasons a daemon fails to start.
+//!
+//! Both print one line per check, with a hint on what to do about each that did not pass, and
+//! fail if any check did. Warnings are things that may be fine, such as a quiet network.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::ErrorKind,
+    net::{TcpListener, UdpSocket},
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use tokio::time::{sleep, timeout};
+use udcn_client::control;
+
+use crate::{
+    attach,
+    diagnostics::{self, LoadReport},
+    privileges,
+};
+
+/// How long the control socket has to answer.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maps every daemon pins; if these are missing, none were pinned.
+const PINNED_MAPS: [&str; 4] = ["CONFIG", "PIT", "CONTENT_STORE", "STATS"];
+
+/// Drivers with native XDP support. Others fall back to the slower generic mode, if that.
+const NATIVE_XDP_DRIVERS: &[&str] = &[
+    "bnxt_en",
+    "bond",
+    "dpaa2-eth",
+    "ena",
+    "enetc",
+    "fec",
+    "gve",
+    "hv_netvsc",
+    "i40e",
+    "ice",
+    "igb",
+    "igc",
+    "ixgbe",
+    "ixgbevf",
+    "mlx4_core",
+    "mlx4_en",
+    "mlx5_core",
+    "mvneta",
+    "mvpp2",
+    "nfp",
+    "qede",
+    "sfc",
+    "stmmac",
+    "thunder-nicvf",
+    "tun",
+    "veth",
+    "virtio_net",
+];
+
+/// The datapath publishes events and lapsed records on BPF ring buffers, new in Linux 5.8.
+const MIN_KERNEL: (u32, u32) = (5, 8);
+
+/// From Linux 5.11, BPF memory is charged to the memory cgroup instead of `RLIMIT_MEMLOCK`.
+const MEMCG_KERNEL: (u32, u32) = (5, 11);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    summary: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn ok(summary: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            summary: summary.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(summary: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warn,
+            summary: summary.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(summary: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            summary: summary.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+fn format_checks(checks: &[Check]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        let status = match check.status {
+            Status::Ok => " ok ",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        let _ = writeln!(out, "[{status}] {}", check.summary);
+        if let Some(hint) = &check.hint {
+            let _ = writeln!(out, "       {hint}");
+        }
+    }
+    out
+}
+
+/// Print `checks` and return how many failed.
+fn report(checks: &[Check]) -> usize {
+    print!("{}", format_checks(checks));
+    checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count()
+}
+
+/// Check the daemon serving the control socket, attached to `ifaces` with its maps pinned under
+/// `pin_dir`, watching its counters for `wait`. Returns how many checks failed.
+pub async fn health(ifaces: &[String], pin_dir: &Path, wait: Duration) -> usize {
+    let mut checks = Vec::new();
+    let socket = control::socket_path();
+    let started = Instant::now();
+    let before = match timeout(CONTROL_TIMEOUT, control::stats()).await {
+        Ok(Ok(stats)) => {
+            let took = started.elapsed().as_secs_f64() * 1000.0;
+            checks.push(Check::ok(format!(
+                "control socket {} answered in {took:.1} ms",
+                socket.display()
+            )));
+            Some(stats)
+        }
+        Ok(Err(e)) => {
+            checks.push(Check::fail(
+                format!("control socket {} did not answer: {e:#}", socket.display()),
+                "start the daemon with `udcn run`, or point UDCN_CONTROL_SOCKET at its socket",
+            ));
+            None
+        }
+        Err(_) => {
+            checks.push(Check::fail(
+                format!(
+                    "control socket {} did not answer within {}s",
+                    socket.display(),
+                    CONTROL_TIMEOUT.as_secs()
+                ),
+                "the daemon may be stuck; its log and `udcn events` show what it is doing",
+            ));
+            None
+        }
+    };
+
+    for iface in ifaces {
+        checks.push(attached(iface));
+    }
+
+    let missing: Vec<_> = PINNED_MAPS
+        .into_iter()
+        .filter(|map| !pin_dir.join(map).exists())
+        .collect();
+    if missing.is_empty() {
+        checks.push(Check::ok(format!(
+            "maps pinned under {}",
+            pin_dir.display()
+        )));
+    } else {
+        checks.push(Check::fail(
+            format!(
+                "{} not pinned under {}",
+                missing.join(", "),
+                pin_dir.display()
+            ),
+            "the daemon pins its maps at startup; look for `failed to pin maps` in its log, or \
+             pass the --pin-dir it was given",
+        ));
+    }
+
+    if let Some(before) = before {
+        sleep(wait).await;
+        checks.push(match control::stats().await {
+            Ok(after) => {
+                let packets = after
+                    .interest_received
+                    .wrapping_sub(before.interest_received)
+                    .wrapping_add(after.data_received.wrapping_sub(before.data_received));
+                if packets > 0 {
+                    Check::ok(format!(
+                        "{packets} packets handled in {}s",
+                        wait.as_secs_f64()
+                    ))
+                } else {
+                    Check::warn(
+                        format!("no packets handled in {}s", wait.as_secs_f64()),
+                        format!(
+                            "fine on a quiet network; otherwise check that NDN traffic on UDP \
+                             6363 reaches {}",
+                            ifaces.join(", ")
+                        ),
+                    )
+                }
+            }
+            Err(e) => Check::fail(
+                format!("control socket stopped answering: {e:#}"),
+                "the daemon may have exited; check its log",
+            ),
+        });
+
+        match control::alarms().await {
+            Ok(alarms) => {
+                let firing: Vec<_> = alarms.iter().filter(|alarm| alarm.firing).collect();
+                for alarm in &firing {
+                    let value = alarm
+                        .value
+                        .map_or("-".to_string(), |value| format!("{value:.1}%"));
+                    checks.push(Check::fail(
+                        format!(
+                            "alarm '{}' firing: {} ({value})",
+                            alarm.name, alarm.condition
+                        ),
+                        "see `udcn stats` for the counters behind it",
+                    ));
+                }
+                if firing.is_empty() && !alarms.is_empty() {
+                    checks.push(Check::ok(format!("{} alarm(s) quiet", alarms.len())));
+                }
+            }
+            Err(e) => checks.push(Check::fail(
+                format!("failed to read alarms: {e:#}"),
+                "the daemon may have exited; check its log",
+            )),
+        }
+    }
+    report(&checks)
+}
+
+fn attached(iface: &str) -> Check {
+    match attach::existing_program(iface) {
+        Ok((_, Some(existing))) if existing.name.as_deref() == Some("udcn") => {
+            Check::ok(format!("µDCN attached to {iface} ({})", existing.mode))
+        }
+        Ok((_, Some(existing))) => Check::fail(
+            format!("{iface} runs XDP program {existing}, not µDCN"),
+            "something replaced µDCN; restart the daemon with --chain to run in front of it",
+        ),
+        Ok((_, None)) => Check::fail(
+            format!("no XDP program attached to {iface}"),
+            format!("is the daemon running with -i {iface}?"),
+        ),
+        Err(e) => Check::fail(
+            format!("failed to inspect {iface}: {e:#}"),
+            "pass the interfaces the daemon runs on with -i",
+        ),
+    }
+}
+
+/// Share of the verifier's instruction limit past which a feature or two more may not fit.
+const VERIFIER_HEADROOM_WARNING: f64 = 0.8;
+
+/// Check whether a daemon could start on `ifaces` here, then print the last load report if
+/// `verbose`. Returns how many checks failed.
+pub fn doctor(ifaces: &[String], verbose: bool) -> usize {
+    let mut checks = Vec::new();
+
+    checks.push(match privileges::missing_capabilities() {
+        Ok(missing) if missing.is_empty() => Check::ok("capabilities to load and attach XDP"),
+        Ok(missing) => Check::fail(
+            format!("missing {}", missing.join(", ")),
+            format!(
+                "run the daemon as root or grant them with `{}`",
+                privileges::SETCAP_HINT
+            ),
+        ),
+        Err(e) => Check::warn(
+            format!("failed to read capabilities: {e:#}"),
+            "run the daemon as root",
+        ),
+    });
+
+    let release = kernel_release();
+    let version = release.as_deref().and_then(parse_kernel_release);
+    checks.push(match version {
+        Some(version) if version >= MIN_KERNEL => {
+            Check::ok(format!("Linux {}", release.as_deref().unwrap_or_default()))
+        }
+        Some(_) => Check::fail(
+            format!(
+                "Linux {} is too old",
+                release.as_deref().unwrap_or_default()
+            ),
+            format!(
+                "the datapath needs BPF ring buffers, from Linux {}.{}",
+                MIN_KERNEL.0, MIN_KERNEL.1
+            ),
+        ),
+        None => Check::warn(
+            "unknown kernel version",
+            format!(
+                "the datapath needs Linux {}.{} or later",
+                MIN_KERNEL.0, MIN_KERNEL.1
+            ),
+        ),
+    });
+
+    checks.push(if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        Check::ok("kernel BTF at /sys/kernel/btf/vmlinux")
+    } else {
+        Check::warn(
+            "the kernel exposes no BTF",
+            "verifier errors are harder to read without it; use a kernel built with \
+             CONFIG_DEBUG_INFO_BTF, as most distribution kernels are",
+        )
+    });
+
+    checks.push(memlock(version));
+
+    for iface in ifaces {
+        checks.extend(interface(iface));
+    }
+
+    checks.push(match UdpSocket::bind(("0.0.0.0", 6363)) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => Check::warn(
+            "UDP port 6363 is taken by another process",
+            "`udcn serve` binds it by default; find the owner with `ss -ulpn sport = :6363`",
+        ),
+        _ => Check::ok("UDP port 6363 is free"),
+    });
+    checks.push(match TcpListener::bind(("127.0.0.1", 9363)) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => Check::warn(
+            "TCP port 9363 is taken by another process",
+            "pick another --metrics-listen address, or find the owner with \
+             `ss -tlpn sport = :9363`",
+        ),
+        _ => Check::ok("TCP port 9363 is free for --metrics-listen"),
+    });
+
+    let socket = control::socket_path();
+    if socket.exists() {
+        checks.push(match std::os::unix::net::UnixStream::connect(&socket) {
+            Ok(_) => Check::warn(
+                format!("a daemon already answers on {}", socket.display()),
+                "a second one would take its control socket over; give it its own with \
+                     UDCN_CONTROL_SOCKET",
+            ),
+            Err(_) => Check::ok(format!(
+                "{} is left over from a daemon that exited; it is replaced on start",
+                socket.display()
+            )),
+        });
+    }
+
+    let load = LoadReport::load(Path::new(diagnostics::DEFAULT_LOAD_REPORT_PATH)).ok();
+    checks.extend(load.as_ref().map(last_load));
+    let failures = report(&checks);
+    if verbose {
+        match &load {
+            Some(load) => print!("\n{}", load.format()),
+            None => println!(
+                "\nNo load report at {}; the daemon writes one each time it loads the datapath",
+                diagnostics::DEFAULT_LOAD_REPORT_PATH
+            ),
+        }
+    }
+    failures
+}
+
+/// How the daemon's last load of the datapath went.
+fn last_load(load: &LoadReport) -> Check {
+    if let Some(error) = &load.error {
+        let hint = if load.verifier_log.is_some() {
+            "`udcn doctor --verbose` shows the verifier log"
+        } else {
+            "the daemon's log has the details"
+        };
+        return Check::fail(format!("the datapath last failed to load: {error}"), hint);
+    }
+    match (load.verified_insns, load.verifier_headroom_used()) {
+        (Some(insns), Some(used)) if used >= VERIFIER_HEADROOM_WARNING => Check::warn(
+            format!(
+                "the datapath took {insns} verifier instructions, {:.0}% of the limit",
+                used * 100.0
+            ),
+            "new features may push it over; `udcn doctor --verbose` shows the largest functions",
+        ),
+        (Some(insns), Some(used)) => Check::ok(format!(
+            "the datapath last loaded in {insns} verifier instructions, {:.0}% of the limit",
+            used * 100.0
+        )),
+        _ => Check::ok("the datapath last loaded"),
+    }
+}
+
+fn memlock(version: Option<(u32, u32)>) -> Check {
+    if version.is_some_and(|version| version >= MEMCG_KERNEL) {
+        return Check::ok("BPF memory is charged to the memory cgroup, not RLIMIT_MEMLOCK");
+    }
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return Check::warn(
+            "failed to read RLIMIT_MEMLOCK",
+            "if loading fails with EPERM, raise it with `ulimit -l unlimited`",
+        );
+    }
+    if limit.rlim_max == libc::RLIM_INFINITY {
+        Check::ok("RLIMIT_MEMLOCK can be lifted by the daemon")
+    } else {
+        Check::fail(
+            format!("RLIMIT_MEMLOCK is capped at {} KiB", limit.rlim_max / 1024),
+            "the maps will not fit; run `ulimit -l unlimited` as root first, or set \
+             LimitMEMLOCK=infinity in the systemd unit",
+        )
+    }
+}
+
+fn interface(iface: &str) -> Vec<Check> {
+    if !Path::new("/sys/class/net").join(iface).exists() {
+        return vec![Check::fail(
+            format!("no interface {iface}"),
+            "create it (see Setup Dedicated NDN Interface in the README), or pass the interfaces \
+             to use with -i",
+        )];
+    }
+    let mut checks = vec![match driver(iface) {
+        Some(driver) if NATIVE_XDP_DRIVERS.contains(&driver.as_str()) => {
+            Check::ok(format!("{iface} uses {driver}, which supports native XDP"))
+        }
+        Some(driver) => Check::warn(
+            format!("{iface} uses {driver}, which may lack native XDP"),
+            "attaching fails without it; the kernel's generic mode works with any driver, \
+             though slower",
+        ),
+        None => Check::warn(
+            format!("unknown driver for {iface}"),
+            "attaching fails if it lacks native XDP",
+        ),
+    }];
+    match attach::existing_program(iface) {
+        Ok((_, Some(existing))) if existing.name.as_deref() != Some("udcn") => {
+            checks.push(Check::warn(
+                format!("{iface} already runs XDP program {existing}"),
+                "the daemon refuses to replace it; pass --chain to run in front of it or \
+                 --force to replace it",
+            ))
+        }
+        Ok(_) => {}
+        Err(e) => checks.push(Check::warn(
+            format!("failed to inspect {iface}: {e:#}"),
+            "the daemon inspects interfaces with `ip`; check that iproute2 is installed",
+        )),
+    }
+    checks
+}
+
+/// The driver behind `iface`, from sysfs, or `ethtool` for virtual interfaces.
+pub fn driver(iface: &str) -> Option<String> {
+    let link = Path::new("/sys/class/net")
+        .join(iface)
+        .join("device/driver");
+    if let Ok(target) = fs::read_link(link) {
+        return Some(target.file_name()?.to_string_lossy().into_owned());
+    }
+    let output = Command::new("ethtool").args(["-i", iface]).output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("driver: "))
+        .map(str::to_string)
+}
+
+/// Whether the running kernel charges BPF memory to the memory cgroup rather than
+/// `RLIMIT_MEMLOCK`.
+pub fn bpf_charged_to_memcg() -> bool {
+    kernel_release()
+        .as_deref()
+        .and_then(parse_kernel_release)
+        .is_some_and(|version| version >= MEMCG_KERNEL)
+}
+
+fn kernel_release() -> Option<String> {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    Some(release.trim().to_string())
+}
+
+/// The major and minor version of a release such as `5.15.0-91-generic`.
+fn parse_kernel_release(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_and_kernel_release() {
+        let checks = [
+            Check::ok("Linux 6.1.0"),
+            Check::fail("no interface udcn0", "create it"),
+        ];
+        assert_eq!(
+            format_checks(&checks),
+            "[ ok ] Linux 6.1.0\n[FAIL] no interface udcn0\n       create it\n"
+        );
+
+        assert_eq!(parse_kernel_release("5.15.0-91-generic"), Some((5, 15)));
+        assert_eq!(parse_kernel_release("6.8-rc1"), Some((6, 8)));
+        assert!(parse_kernel_release("5.4.0").unwrap() < MIN_KERNEL);
+        assert_eq!(parse_kernel_release("linux"), None);
+
+        let load = LoadReport {
+            verified_insns: Some(900_000),
+            ..LoadReport::default()
+        };
+        assert_eq!(last_load(&load).status, Status::Warn);
+        let load = LoadReport {
+            error: Some("invalid object".to_string()),
+            ..load
+        };
+        assert_eq!(last_load(&load).status, Status::Fail);
+    }
+}
@@ -0,0 +1,188 @@
+//! Journal of the changes made to a running daemon over the control socket (`run --journal`).
+//!
+//! Each request that changes the daemon's state, such as registering a name, flushing, warming or
+//! pinning in the content store, changing the sampling rate or injecting a fault, is appended to
+//! the journal once it succeeds, as a `<unix ms> <request>` line. `udcn replay-journal` sends the
+//! requests to a daemon again in order, to rebuild its state after a restart or to reproduce the
+//! sequence from a bug report. Replayed requests arrive as `replayed <request>` and are not journaled
+//! again, so a daemon can replay its own journal.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use log::warn;
+
+use crate::{preload, state};
+
+/// What a replayed request starts with.
+pub const REPLAYED: &str = "replayed";
+
+/// Whether `request` changes the daemon's state, rather than only reading it.
+pub fn mutates(request: &str) -> bool {
+    let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+    match command {
+        "register" | "cs-flush" | "cs-purge" | "cs-prefetch" | "cs-preload" | "cs-pin"
+        | "cs-unpin" | "sample" | "strategy-set" | "strategy-unset" | "route-add"
+        | "route-remove" | "acl-clear-dynamic" | "acl-report" | "face-create" | "state-import" => {
+            true
+        }
+        "fault" => !argument.is_empty(),
+        _ => false,
+    }
+}
+
+/// `request` without the [`REPLAYED`] marker, and whether it had one.
+pub fn strip_replayed(request: &str) -> (&str, bool) {
+    match request.strip_prefix(REPLAYED) {
+        Some(request) if request.starts_with(' ') => (&request[1..], true),
+        _ => (request, false),
+    }
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Open the journal at `path` for appending, creating it if need be.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open journal {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Append `request` if it changed anything. Failing to is logged rather than failing the
+    /// request, which has taken effect already.
+    pub fn record(&mut self, request: &str) {
+        if !mutates(request) {
+            return;
+        }
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        if let Err(e) = writeln!(self.file, "{now_ms} {request}") {
+            warn!(
+                "failed to journal '{request}' to {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// The requests in a journal, in order. Blank lines and `#` comments, which a bug report may
+/// carry, are skipped.
+pub fn parse(contents: &str) -> anyhow::Result<Vec<&str>> {
+    let mut requests = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let request = line
+            .split_once(' ')
+            .filter(|(at_ms, _)| at_ms.parse::<u64>().is_ok())
+            .map(|(_, request)| request.trim_start())
+            .filter(|request| !request.is_empty())
+            .with_context(|| format!("line {}: expected `<unix ms> <request>`", number + 1))?;
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+/// Send each request in the journal at `path` to the running daemon, or only print them if
+/// `dry_run`. Returns how many the daemon refused; the rest are still sent.
+pub async fn replay(path: &Path, dry_run: bool) -> anyhow::Result<usize> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let requests =
+        parse(&contents).with_context(|| format!("invalid journal {}", path.display()))?;
+    let mut failures = 0;
+    for request in requests {
+        if dry_run {
+            println!("{request}");
+            continue;
+        }
+        let line = format!("{REPLAYED} {request}");
+        // Preloads and imports carry their files, read again with the replaying user's permissions
+        let sent = if let Some(argument) = request.strip_prefix("cs-preload ") {
+            match argument.parse() {
+                Ok(preload) => preload::send(&line, &preload).await,
+                Err(e) => Err(e),
+            }
+        } else if let Some(path) = request.strip_prefix("state-import ") {
+            state::send(&line, Path::new(path)).await
+        } else {
+            udcn_client::control::request(&line).await
+        };
+        match sent {
+            Ok(_) => println!("{request}"),
+            Err(e) => {
+                println!("{request}: {e:#}");
+                failures += 1;
+            }
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_records_mutations_for_replay() {
+        let path = std::env::temp_dir().join(format!("udcn-journal-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut journal = Journal::open(&path).unwrap();
+        for request in [
+            "register /video",
+            "stats",
+            "cs-flush",
+            "fault",
+            "fault drop-interests 20",
+            "sample 100 128",
+        ] {
+            journal.record(request);
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        let requests = parse(&contents).unwrap();
+        assert_eq!(
+            requests,
+            [
+                "register /video",
+                "cs-flush",
+                "fault drop-interests 20",
+                "sample 100 128"
+            ]
+        );
+
+        assert_eq!(
+            strip_replayed("replayed register /a"),
+            ("register /a", true)
+        );
+        assert_eq!(strip_replayed("replayedx"), ("replayedx", false));
+        assert_eq!(
+            parse("# from a bug report\n\n1700000000000 cs-flush\n").unwrap(),
+            ["cs-flush"]
+        );
+        assert!(parse("cs-flush\n").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Nacks for downstream faces whose PIT record lapsed before Data arrived.
+//!
+//! A PIT entry keeps a record per downstream face, each expiring with that face's own Interest
+//! lifetime. The datapath frees lapsed records it comes across while handling the name and
+//! publishes them on `PIT_LAPSED`; a periodic sweep in the daemon catches entries no packet
+//! touches. Either way the face is sent a Nack (Expired). Consumers connect their socket to the
+//! forwarder, so the Nack has to come from the NDN port and the address the Interest was sent
+//! to, as Nacks from XDP do. It goes out on a raw socket, opened before the daemon drops
+//! privileges, that writes its own IP header.
+
+use std::{
+    io, mem,
+    os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd},
+    sync::{Arc, Mutex},
+};
+
+use aya::maps::{MapData, RingBuf};
+use log::{debug, warn};
+use tokio::{
+    io::unix::AsyncFd,
+    time::{sleep, Duration},
+};
+use udcn_common::{LapsedRecord, NackPacket, NackReason, PitRecord, NDN_UDP_PORT};
+
+use crate::{daemon::Daemon, events::ktime_ns, otlp};
+
+/// Sends Nacks, and hop reports for traced packets, to downstream faces from the NDN port.
+pub struct NackSender {
+    socket: OwnedFd,
+}
+
+impl NackSender {
+    /// Open the raw socket, which takes CAP_NET_RAW.
+    pub fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_INET,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_UDP,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sender = Self {
+            socket: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        sender.include_header()?;
+        sender.ignore_incoming()?;
+        Ok(sender)
+    }
+
+    /// Take the IP header from each datagram, so it can come from an address other than the one
+    /// the route to the face would pick.
+    fn include_header(&self) -> io::Result<()> {
+        let on: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                self.socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_HDRINCL,
+                &on as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// A raw UDP socket is handed a copy of every UDP datagram the host receives; a filter that
+    /// rejects them all keeps them from queueing up unread.
+    fn ignore_incoming(&self) -> io::Result<()> {
+        const BPF_RET_K: u16 = 0x06;
+        let mut reject = libc::sock_filter {
+            code: BPF_RET_K,
+            jt: 0,
+            jf: 0,
+            k: 0,
+        };
+        let program = libc::sock_fprog {
+            len: 1,
+            filter: &mut reject,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                self.socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Send `payload` from the NDN port at `from` to `port` at `addr`. A zero `from` leaves the
+    /// source address to the kernel.
+    pub fn send(
+        &self,
+        from: [u8; 4],
+        addr: [u8; 4],
+        port: [u8; 2],
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let datagram = ip_datagram(from, addr, &udp_datagram(port, payload));
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr),
+            },
+            sin_zero: [0; 8],
+        };
+        let ret = unsafe {
+            libc::sendto(
+                self.socket.as_raw_fd(),
+                datagram.as_ptr() as *const libc::c_void,
+                datagram.len(),
+                libc::MSG_DONTWAIT,
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Tell the face of `record` its Interest for `name_hash` expired.
+    pub fn nack(&self, name_hash: u32, record: &PitRecord) {
+        let [a, b, c, d] = record.addr;
+        let port = u16::from_be_bytes(record.port);
+        let mut span = otlp::span("nack_send");
+        span.attr("face", format!("{a}.{b}.{c}.{d}:{port}"));
+        let nack = NackPacket::new(name_hash, record.nonce, NackReason::Expired).to_bytes();
+        match self.send(record.local_addr, record.addr, record.port, &nack) {
+            Ok(()) => {
+                debug!("Nacked lapsed Interest {name_hash:#010x} from {a}.{b}.{c}.{d}:{port}")
+            }
+            Err(e) => warn!(
+                "failed to Nack lapsed Interest {name_hash:#010x} from {a}.{b}.{c}.{d}:{port}: {e}"
+            ),
+        }
+    }
+}
+
+/// An IPv4 header from `from` to `addr`, then the UDP datagram. The kernel fills in the total
+/// length, the identification and the checksum, and the source address when `from` is zero.
+fn ip_datagram(from: [u8; 4], addr: [u8; 4], udp: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(20 + udp.len());
+    // Version 4, five words of header, no TOS
+    datagram.extend_from_slice(&[0x45, 0]);
+    datagram.extend_from_slice(&(20 + udp.len() as u16).to_be_bytes());
+    // Identification, then Don't Fragment
+    datagram.extend_from_slice(&[0, 0, 0x40, 0]);
+    datagram.extend_from_slice(&[64, libc::IPPROTO_UDP as u8, 0, 0]);
+    datagram.extend_from_slice(&from);
+    datagram.extend_from_slice(&addr);
+    datagram.extend_from_slice(udp);
+    datagram
+}
+
+/// A UDP header from the NDN port to `port`, then the payload. The checksum is left at zero,
+/// which IPv4 reads as none.
+fn udp_datagram(port: [u8; 2], payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&NDN_UDP_PORT.to_be_bytes());
+    datagram.extend_from_slice(&port);
+    datagram.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Nack the records the datapath reports lapsed, and sweep the PIT for the rest every
+/// `interval`. Without a sender, lapsed records are still freed, just not Nacked.
+pub fn start(
+    ebpf: &mut aya::Ebpf,
+    daemon: Arc<Mutex<Daemon>>,
+    sender: Option<Arc<NackSender>>,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    if let Some(sender) = sender.clone() {
+        let ring = RingBuf::try_from(ebpf.take_map("PIT_LAPSED").unwrap())?;
+        let ring = AsyncFd::new(ring)?;
+        tokio::spawn(read_lapsed(ring, sender));
+    }
+
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            let mut span = otlp::span("pit_sweep");
+            let now = ktime_ns();
+            let lapsed = match daemon.lock().unwrap().expire_pit_records(now) {
+                Ok(lapsed) => lapsed,
+                Err(e) => {
+                    warn!("failed to sweep the PIT: {e:#}");
+                    continue;
+                }
+            };
+            span.attr("lapsed", lapsed.len());
+            if let Some(sender) = &sender {
+                for (name_hash, record) in lapsed {
+                    sender.nack(name_hash, &record);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn read_lapsed(mut ring: AsyncFd<RingBuf<MapData>>, sender: Arc<NackSender>) {
+    loop {
+        let mut guard = match ring.readable_mut().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                warn!("lapsed PIT record channel stopped: {e}");
+                return;
+            }
+        };
+        let ring = guard.get_inner_mut();
+        while let Some(item) = ring.next() {
+            if item.len() < mem::size_of::<LapsedRecord>() {
+                continue;
+            }
+            // SAFETY: the length was checked, and every bit pattern is a valid LapsedRecord
+            let lapsed = unsafe { (item.as_ptr() as *const LapsedRecord).read_unaligned() };
+            sender.nack(lapsed.name_hash, &lapsed.record);
+        }
+        guard.clear_ready();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::parse_nack_packet;
+
+    use super::*;
+
+    #[test]
+    fn test_nack_datagram() {
+        let record = PitRecord {
+            face_id: 7,
+            nonce: 0xdead_beef,
+            addr: [10, 0, 0, 2],
+            local_addr: [10, 0, 0, 1],
+            port: 40000u16.to_be_bytes(),
+            ..PitRecord::default()
+        };
+        let nack = NackPacket::new(0x1234, record.nonce, NackReason::Expired).to_bytes();
+        let datagram = ip_datagram(
+            record.local_addr,
+            record.addr,
+            &udp_datagram(record.port, &nack),
+        );
+        // From where the consumer sent its Interest, to the consumer
+        assert_eq!(datagram[0], 0x45);
+        assert_eq!(datagram[9], libc::IPPROTO_UDP as u8);
+        assert_eq!(&datagram[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&datagram[16..20], &[10, 0, 0, 2]);
+        assert_eq!(
+            u16::from_be_bytes([datagram[2], datagram[3]]) as usize,
+            datagram.len()
+        );
+
+        let datagram = &datagram[20..];
+        assert_eq!(&datagram[..2], &6363u16.to_be_bytes());
+        assert_eq!(&datagram[2..4], &40000u16.to_be_bytes());
+        assert_eq!(
+            u16::from_be_bytes([datagram[4], datagram[5]]) as usize,
+            datagram.len()
+        );
+
+        let nack = parse_nack_packet(&datagram[8..]).unwrap();
+        assert_eq!(nack.name_hash, 0x1234);
+        assert_eq!(nack.nonce, 0xdead_beef);
+        assert_eq!(NackReason::from_u8(nack.reason), Some(NackReason::Expired));
+    }
+}
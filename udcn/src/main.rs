@@ -1,22 +1,93 @@
+mod access;
+mod acl;
+mod alarms;
+mod attach;
+mod autosize;
+mod batch;
+mod bench;
+mod benchpair;
+mod capture;
+mod config;
+mod control;
+mod convert;
+mod daemon;
+mod diagnostics;
+mod dtls;
+mod ether;
+mod events;
+mod expr;
+#[cfg(feature = "faults")]
+mod faults;
+mod filters;
+mod flows;
+mod gates;
+mod health;
+mod journal;
+mod lapsed;
+mod metrics;
+mod names;
+mod netns;
+mod nocache;
+mod offload;
+mod otlp;
+mod partitions;
+mod pins;
+mod pit;
+mod prefetch;
+mod preload;
+mod privileges;
+mod repo;
+mod resume;
+mod routes;
+mod selftest;
+mod shaper;
+mod snapshot;
+mod state;
+mod strategies;
+mod tenants;
+mod topo;
+mod trace;
+mod tunnels;
+mod versions;
+
 use anyhow::Context as _;
 use aya::{
-    programs::{Xdp, XdpFlags},
+    programs::XdpFlags,
     maps::Array,
 };
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 #[rustfmt::skip]
 use log::{debug, warn, info};
 use tokio::{signal, time::{sleep, Duration}};
-use std::net::{UdpSocket, SocketAddr};
-use udcn_common::{PacketStats, serialize_interest, serialize_data, hash_name};
-use rand;
+use std::{
+    collections::BTreeMap,
+    io::{Seek as _, SeekFrom, Write as _},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use udcn_client::{
+    control::TableOccupancy,
+    keychain::{Sha256Verifier, Verifier as _},
+    manifest,
+    segments::sha256_signer,
+    FaceTable, FairLimits, InterestFilter, InterestFilters, Liveness, Manifest, MtuPolicy,
+    Codec, Compression, DiskSegmentStore, PoisonGuard, Producer, QueueLimits, Reply, Retransmission,
+    SegmentSize, SegmentStore, StrategyChoice, Tier, Token,
+};
+use udcn_common::{
+    lp, AclAction, CsAdmission, DatapathConfig, DatapathLogLevel, DropReason, Interest, NackReason,
+    Name, PacketStats, PitLayout, SampleConfig, TlvType, DATAPATH_LOG_TARGET,
+    DEFAULT_INTEREST_LIFETIME_MS, MAP_PIN_DIR, ROUTES_CAPACITY,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "udcn")]
 #[command(about = "A minimal µDCN implementation using eBPF/XDP")]
 struct Opt {
-    #[clap(short, long, default_value = "udcn0")]
-    iface: String,
+    /// Interfaces to attach the XDP program to, comma separated.
+    #[clap(short, long, default_value = "udcn0", value_delimiter = ',')]
+    iface: Vec<String>,
     
     #[command(subcommand)]
     command: Commands,
@@ -27,174 +98,2287 @@ enum Commands {
     Run {
         #[clap(long)]
         stats_interval: Option<u64>,
+        #[clap(long, default_value_t = 1000)]
+        negative_cache_ttl_ms: u32,
+        #[clap(long, default_value_t = 500)]
+        duplicate_window_ms: u32,
+        /// Where to pin the datapath maps for other processes.
+        #[clap(long, default_value = MAP_PIN_DIR)]
+        pin_dir: PathBuf,
+        /// Reserve a share of the content store for a single-component prefix, as PREFIX=PERCENT
+        /// (e.g. /video=70). Repeatable; names outside every reservation share the rest.
+        #[clap(long = "cs-partition")]
+        cs_partitions: Vec<partitions::Reservation>,
+        /// Split the shared content store partition into probation and protected segments,
+        /// keeping this share of it for Data that answered an Interest. 0 keeps one LRU.
+        #[clap(long, default_value_t = 0)]
+        cs_protected_percent: u32,
+        /// Never cache Data under this prefix (e.g. /telemetry/live), of up to four components.
+        /// Repeatable.
+        #[clap(long = "no-cache")]
+        no_cache: Vec<nocache::NoCachePrefix>,
+        /// Answer Interests the content store cannot, and that are under no --route prefix, tunnel
+        /// prefix or `udcn route add` one, with a NoRoute Nack from XDP instead of passing them up.
+        #[clap(long)]
+        nack_unroutable: bool,
+        /// A prefix a producer on this host serves, of up to four components, for
+        /// --nack-unroutable. Repeatable.
+        #[clap(long = "route", requires = "nack_unroutable")]
+        routes: Vec<nocache::NoCachePrefix>,
+        /// Drop UDP datagrams whose checksum is wrong before XDP parses them, as bad_checksum.
+        /// Datagrams sent without a checksum are still taken.
+        #[clap(long)]
+        verify_udp_checksums: bool,
+        /// Drop every packet from a source address for --greylist-secs once it sends this many
+        /// malformed packets, bad checksums or Interests over a rate limit within
+        /// --greylist-window-secs. 0 turns greylisting off.
+        #[clap(long, default_value_t = 0)]
+        greylist_after: u32,
+        #[clap(long, default_value_t = 10)]
+        greylist_window_secs: u32,
+        #[clap(long, default_value_t = 60)]
+        greylist_secs: u32,
+        /// Classify packets of these overlay tunnels by the NDN packet inside, comma separated.
+        #[clap(long, value_enum, value_delimiter = ',')]
+        decapsulate: Vec<Encapsulation>,
+        /// Which Data is admitted into the content store.
+        #[clap(long, value_enum, default_value_t = Admission::Always)]
+        cs_admission: Admission,
+        /// Chance that `probabilistic` admission admits a Data packet.
+        #[clap(long, default_value_t = 0.1)]
+        cs_admission_probability: f64,
+        /// Content store misses a name needs within the window before `popularity` admission
+        /// admits its Data.
+        #[clap(long, default_value_t = 2)]
+        cs_admission_threshold: u32,
+        /// Window `popularity` admission counts misses in.
+        #[clap(long, default_value_t = 10_000)]
+        cs_admission_window_ms: u32,
+        /// How often the content store Bloom filter is rebuilt to drop evicted names.
+        #[clap(long, default_value_t = 10)]
+        cs_bloom_rebuild_secs: u64,
+        /// Where the datapath keeps pending Interests.
+        #[clap(long, value_enum, default_value_t = PitKind::HashMap)]
+        pit_layout: PitKind,
+        /// How often the PIT is swept for downstream faces whose Interest lifetime ran out.
+        #[clap(long, default_value_t = 500)]
+        pit_sweep_ms: u64,
+        /// Size the PIT, content store and route maps for the targets below and the memory the
+        /// daemon can lock, printing what was derived, instead of using the built-in entry counts.
+        #[clap(long)]
+        autosize: bool,
+        /// With --autosize, Interests per second the PIT must hold for their lifetime.
+        #[clap(long, requires = "autosize", default_value_t = 1000)]
+        interest_rate: u32,
+        /// With --autosize, the InterestLifetime most Interests carry.
+        #[clap(long, requires = "autosize", default_value_t = DEFAULT_INTEREST_LIFETIME_MS)]
+        interest_lifetime_ms: u32,
+        /// With --autosize, memory the content store and its Data cache may take, e.g. 256M.
+        #[clap(
+            long,
+            requires = "autosize",
+            value_parser = autosize::parse_bytes,
+            default_value = "64M"
+        )]
+        cs_budget: u64,
+        /// With --autosize, prefixes --nack-unroutable must have room for.
+        #[clap(long, requires = "autosize", default_value_t = ROUTES_CAPACITY)]
+        expected_routes: u32,
+        /// Serve Prometheus metrics over HTTP on this address, e.g. 127.0.0.1:9363.
+        #[clap(long)]
+        metrics_listen: Option<SocketAddr>,
+        /// Read further settings, such as OpenTelemetry export and prefetching, from this TOML
+        /// file.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        /// Most verbose records the XDP program logs, printed with the daemon's own log.
+        #[clap(long, value_enum, default_value_t = LogLevel::Off)]
+        datapath_log_level: LogLevel,
+        /// Replace XDP programs already attached to the interfaces.
+        #[clap(long, conflicts_with = "chain")]
+        force: bool,
+        /// Run in front of XDP programs already attached to the interfaces, handing them every
+        /// packet µDCN lets through. They are reattached when the daemon exits.
+        #[clap(long)]
+        chain: bool,
+        /// How the XDP program runs on the interfaces. `offload` also moves packet classification
+        /// and its counters onto SmartNICs that support it, falling back to the driver alone on
+        /// interfaces that do not.
+        #[clap(long, value_enum, default_value_t = XdpMode::Auto)]
+        xdp_mode: XdpMode,
+        /// Run as this user once the program is attached, keeping only CAP_BPF, CAP_NET_ADMIN
+        /// and CAP_PERFMON.
+        #[clap(long)]
+        user: Option<String>,
+        /// Give this group access to the control socket and pinned maps, so its members can use
+        /// the CLI without root.
+        #[clap(long)]
+        group: Option<String>,
+        /// Append each change made over the control socket, such as a registered name or an
+        /// injected fault, to this file for `udcn replay-journal`.
+        #[clap(long)]
+        journal: Option<PathBuf>,
     },
     Send {
         #[clap(short, long)]
-        name: String,
-        #[clap(short, long, default_value = "127.0.0.1:6363")]
-        target: String,
+        name: Name,
+        /// Forwarders or producers to send the Interest to, comma separated, most preferred
+        /// first. Each is tried in turn until one answers.
+        #[clap(short, long, default_value = "127.0.0.1:6363", value_delimiter = ',')]
+        target: Vec<SocketAddr>,
+        /// Delegation name for forwarders without a FIB entry for the name, e.g. the provider
+        /// namespace the producer is reachable under. Repeatable, most preferred first.
+        #[clap(long)]
+        forwarding_hint: Vec<Name>,
+        /// With several targets, send the Interest again on the next one, with a fresh nonce,
+        /// when one leaves it unanswered this long. Without it, the strategy the daemon has in
+        /// effect for the name applies.
+        #[clap(long)]
+        retransmit_ms: Option<u64>,
+        /// Times --retransmit-ms sends an Interest again before giving up.
+        #[clap(long, default_value_t = Retransmission::default().max_retries)]
+        max_retransmissions: u32,
+        /// Tag a target with the tier of the hierarchy it reaches, as <address>=<tier> with a
+        /// tier of local-cache, regional or origin. Interests go to nearer tiers first; untagged
+        /// targets are origins. Repeatable.
+        #[clap(long, value_parser = parse_tier)]
+        tier: Vec<(SocketAddr, Tier)>,
     },
+    #[command(group(
+        ArgGroup::new("source")
+            .args(["content", "file", "segment_store"])
+            .multiple(true)
+    ))]
     Serve {
+        #[clap(short, long, required_unless_present = "filters", requires = "source")]
+        name: Option<Name>,
+        #[clap(short, long, requires = "name")]
+        content: Option<String>,
+        /// Serve the contents of this file instead of --content.
+        #[clap(long, requires = "name", conflicts_with = "content")]
+        file: Option<PathBuf>,
+        #[clap(short, long, default_value = "127.0.0.1:6363")]
+        bind: String,
+        /// Answer every Interest under the name, not only the name itself.
+        #[clap(long)]
+        prefix: bool,
+        /// Also serve the Interest filters listed in this TOML file.
+        #[clap(long)]
+        filters: Option<PathBuf>,
+        /// Publish the content as signed segments of this many bytes, named <name>/seg=<n>, before
+        /// serving. Each Interest is then answered by a lookup. Without a size, or with `auto`,
+        /// each segment is made as large as fits one datagram on --mtu, or on the smallest MTU of
+        /// the host's interfaces.
+        #[clap(
+            long,
+            num_args = 0..=1,
+            default_missing_value = "auto",
+            requires = "name",
+            conflicts_with_all = ["filters", "prefix"]
+        )]
+        segment_size: Option<SegmentSize>,
+        /// FreshnessPeriod of the Data served. Consumers asking for fresh Data, as `udcn get`
+        /// does to find the latest version, only get it for this long after publishing.
+        #[clap(long)]
+        freshness_ms: Option<u64>,
+        /// Keep the signed segments in this file. If it exists, the segments it holds are served
+        /// as they are, without publishing or signing anything; otherwise the content is
+        /// published with --segment-size and saved to it.
+        #[clap(long, requires = "name", conflicts_with_all = ["filters", "prefix", "version"])]
+        segment_store: Option<PathBuf>,
+        /// Serve --segment-store from disk instead of loading it, reading each segment as it is
+        /// asked for, so that content larger than memory can be served. Publishing into it reads
+        /// --file a segment at a time.
+        #[clap(long, requires = "segment_store", conflicts_with_all = ["manifest", "compress"])]
+        on_disk: bool,
+        /// Publish the segments unsigned, with a signed manifest of their digests under
+        /// <name>/manifest: one signature per object, which `udcn get` checks every segment
+        /// against.
+        #[clap(long, requires = "segment_size")]
+        manifest: bool,
+        /// Publish the segments under <name>/v=<version>, numbered this way.
+        #[clap(long, value_enum, requires = "segment_size")]
+        version: Option<VersionScheme>,
+        /// The version for --version explicit.
+        #[clap(long, required_if_eq("version", "explicit"))]
+        version_number: Option<u64>,
+        /// Where --version seq keeps the last version of each name.
+        #[clap(long, default_value = versions::DEFAULT_VERSIONS_PATH)]
+        versions_file: PathBuf,
+        /// With --version and --file, how long a version stays available once a change to the
+        /// file is published under a new one.
+        #[clap(long, default_value_t = 30)]
+        grace_secs: u64,
+
+        /// MTU of every face, instead of learning each from the path. Replies that exceed it
+        /// are split into NDNLPv2 fragments.
+        #[clap(long)]
+        mtu: Option<usize>,
+        /// Drop replies that exceed a face's MTU instead of fragmenting them.
+        #[clap(long)]
+        no_fragment: bool,
+        /// Replies each face may have queued; Interests beyond that get a Congestion Nack.
+        #[clap(long, default_value_t = QueueLimits::default().capacity)]
+        queue_capacity: usize,
+        /// Queued replies from which on replies carry an NDNLPv2 CongestionMark.
+        #[clap(long, default_value_t = QueueLimits::default().mark_threshold)]
+        congestion_mark_at: usize,
+        /// Answer Interests with this many handlers at once, taking them from each client in
+        /// turn, so that one client sending many cannot starve the others.
+        #[clap(long)]
+        fair_workers: Option<usize>,
+        /// With --fair-workers, the Interests per second each client may have answered.
+        #[clap(long, requires = "fair_workers")]
+        client_rate: Option<f64>,
+        /// Interests a client may have answered back to back before --client-rate applies.
+        #[clap(long, default_value_t = FairLimits::default().client_burst)]
+        client_burst: u32,
+        /// Keep the published segments compressed with this codec, lz4 or zstd, decompressing
+        /// each one an Interest is answered with: more content fits in memory, at some CPU time
+        /// per Interest.
+        #[clap(long, requires = "name")]
+        compress: Option<Codec>,
+        /// Only compress segments of at least this many bytes.
+        #[clap(long, requires = "compress", default_value_t = 512)]
+        compress_above: usize,
+        /// Only answer Interests under a prefix that carry a token issued with the secret in a
+        /// file, as <prefix>=<file>; those without one are dropped. Repeatable.
+        #[clap(long, value_parser = parse_gate)]
+        gate: Vec<(Name, PathBuf)>,
+    },
+    /// Fetch segmented content, finding its latest version first unless the name has one.
+    Get {
+        name: Name,
+        /// Forwarders or producers to fetch from, comma separated, most preferred first.
+        #[clap(short, long, default_value = "127.0.0.1:6363", value_delimiter = ',')]
+        target: Vec<SocketAddr>,
+        /// Write the content to this file instead of standard output.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Record the segments received next to --output, and if a fetch into it was cut
+        /// short, only fetch the segments still missing.
+        #[clap(long, requires = "output")]
+        resume: bool,
+        /// With several targets, send each Interest again on the next one, with a fresh nonce,
+        /// when one leaves it unanswered this long. Without it, the strategy the daemon has in
+        /// effect for the name applies.
+        #[clap(long)]
+        retransmit_ms: Option<u64>,
+        /// Times --retransmit-ms sends an Interest again before giving up.
+        #[clap(long, default_value_t = Retransmission::default().max_retries)]
+        max_retransmissions: u32,
+        /// Tag a target with the tier of the hierarchy it reaches, as <address>=<tier> with a
+        /// tier of local-cache, regional or origin. Interests go to nearer tiers first; untagged
+        /// targets are origins. Repeatable.
+        #[clap(long, value_parser = parse_tier)]
+        tier: Vec<(SocketAddr, Tier)>,
+        /// An access token for a gated namespace, as `udcn token` prints it, sent with every
+        /// Interest.
+        #[clap(long)]
+        token: Option<Token>,
+    },
+    /// Issue an access token for a gated namespace, printed in hex.
+    Token {
+        prefix: Name,
+        /// The file holding the prefix's secret.
+        #[clap(long)]
+        secret_file: PathBuf,
+        /// How long the token stays valid.
+        #[clap(long, default_value_t = 3600)]
+        valid_secs: u64,
+    },
+    Stats {
+        /// Print the raw counters as JSON.
+        #[clap(long)]
+        json: bool,
+        /// Also save the counters and table occupancy to this file, for a later --diff.
+        #[clap(long)]
+        save: Option<PathBuf>,
+        /// Show how the counters and tables changed since a snapshot saved with --save.
+        #[clap(long, conflicts_with = "json")]
+        diff: Option<PathBuf>,
+    },
+    Cs {
+        #[command(subcommand)]
+        command: CsCommand,
+    },
+    /// Move what a running daemon has learnt and cached to the daemon of another host.
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    Pit {
+        #[command(subcommand)]
+        command: PitCommand,
+    },
+    Face {
+        #[command(subcommand)]
+        command: FaceCommand,
+    },
+    /// Choose how consumers forward Interests under a prefix.
+    Strategy {
+        #[command(subcommand)]
+        command: StrategyCommand,
+    },
+    /// Route prefixes for `run --nack-unroutable` at runtime.
+    Route {
+        #[command(subcommand)]
+        command: RouteCommand,
+    },
+    /// Inspect and clear source addresses greylisted for misbehaving.
+    Acl {
+        #[command(subcommand)]
+        command: AclCommand,
+    },
+    /// Print datapath decisions as they happen.
+    Events {
+        /// Only show events matching this filter expression, e.g. `type == data, name ~ /video`.
+        #[clap(long)]
+        filter: Option<String>,
+    },
+    /// Sample NDN packets from the datapath and dissect them, or write them to a pcap file.
+    Capture {
+        /// Sample one in this many packets. Defaults to what the daemon last sampled with.
+        #[clap(long)]
+        rate: Option<u32>,
+        /// Keep this many bytes of each packet.
+        #[clap(long)]
+        snaplen: Option<u32>,
+        /// Write the packets to this pcap file instead of printing them.
         #[clap(short, long)]
-        name: String,
+        write: Option<PathBuf>,
+        /// Stop after this many packets.
         #[clap(short, long)]
-        content: String,
+        count: Option<u64>,
+        /// Only capture packets matching this filter expression, as for `events`.
+        #[clap(long)]
+        filter: Option<String>,
+    },
+    /// Change the sampling rate and snap length of running captures; a rate of 0 pauses them.
+    Sample {
+        rate: u32,
+        #[clap(long, default_value_t = capture::DEFAULT_SAMPLING.snaplen)]
+        snaplen: u32,
+        /// Only sample packets of the types and name this filter expression allows.
+        #[clap(long)]
+        filter: Option<String>,
+    },
+    Names {
+        #[command(subcommand)]
+        command: NamesCommand,
+    },
+    /// Fetch a name with a traced Interest and list each forwarder it passed through, as
+    /// reported back by their daemons.
+    Trace {
+        name: Name,
+        #[clap(short, long, default_value = "127.0.0.1:6363")]
+        target: SocketAddr,
+    },
+    /// Measure how long the datapath takes to decide on a packet.
+    Bench {
+        #[command(subcommand)]
+        command: BenchCommand,
+    },
+    /// Multi-node experiments on namespace topologies (needs root).
+    Topo {
+        #[command(subcommand)]
+        command: TopoCommand,
+    },
+    /// Check that the daemon answers, is attached to the interfaces, has its maps pinned and
+    /// handles packets, and that none of its alarms fire. Exits non-zero if any check fails.
+    Health {
+        /// Where the daemon pinned its maps.
+        #[clap(long, default_value = MAP_PIN_DIR)]
+        pin_dir: PathBuf,
+        /// How long to watch the counters for packets.
+        #[clap(long, default_value_t = 2)]
+        wait_secs: u64,
+    },
+    /// Look for the usual reasons the daemon fails to start on the interfaces, such as missing
+    /// capabilities, an old kernel or a driver without XDP.
+    Doctor {
+        /// Also show what the daemon's last load of the datapath reported: the verifier log or
+        /// how much of its instruction limit the program used, instructions per function, map
+        /// memory and BTF.
+        #[clap(long)]
+        verbose: bool,
+    },
+    /// Show the packets each SmartNIC classified, for a daemon run with --xdp-mode offload.
+    Offload,
+    /// Show how long the daemon's table management, such as PIT sweeps, has taken.
+    Timings,
+    /// Show each tenant's PIT entries, content store bytes and Interest rate against its quotas,
+    /// and what the quotas turned away.
+    Tenants,
+    /// Check the datapath end to end on a temporary network of namespaces (needs root).
+    Selftest {
+        /// Show the output of the daemon, producer and consumers.
+        #[clap(long)]
+        verbose: bool,
+    },
+    /// Translate a packet, a `serve --segment-store` file or a pcap capture between
+    /// the native wire format and NDN packet format v0.3.
+    Convert {
+        #[clap(long, value_enum)]
+        from: WireFormat,
+        #[clap(long, value_enum)]
+        to: WireFormat,
+        file: PathBuf,
+        /// Where to write the converted file; standard output if not given.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Send the changes a daemon run with --journal recorded to the running daemon again, in
+    /// order, to restore them after a restart or reproduce a bug report.
+    ReplayJournal {
+        file: PathBuf,
+        /// Only print the requests that would be sent.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Make the running daemon drop, delay or corrupt packets, for testing. Shows the faults in
+    /// effect without a subcommand.
+    #[cfg(feature = "faults")]
+    Fault {
+        #[command(subcommand)]
+        command: Option<FaultCommand>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum WireFormat {
+    Native,
+    Ndn03,
+}
+
+impl From<WireFormat> for udcn_common::Format {
+    fn from(format: WireFormat) -> Self {
+        match format {
+            WireFormat::Native => Self::Native,
+            WireFormat::Ndn03 => Self::Ndn03,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Admission {
+    /// Admit every Data packet.
+    Always,
+    /// Admit each Data packet with --cs-admission-probability.
+    Probabilistic,
+    /// Admit Data once its name missed the content store --cs-admission-threshold times.
+    Popularity,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PitKind {
+    /// A BPF hash map of PIT entries.
+    HashMap,
+    /// An array of twice as many slots with a fingerprint each, where a name may only take one
+    /// of the eight slots from its home slot; for comparing with the hash map.
+    OpenAddressing,
+}
+
+impl From<PitKind> for PitLayout {
+    fn from(kind: PitKind) -> Self {
+        match kind {
+            PitKind::HashMap => Self::HashMap,
+            PitKind::OpenAddressing => Self::OpenAddressing,
+        }
+    }
+}
+
+impl From<Admission> for CsAdmission {
+    fn from(admission: Admission) -> Self {
+        match admission {
+            Admission::Always => Self::Always,
+            Admission::Probabilistic => Self::Probabilistic,
+            Admission::Popularity => Self::Popularity,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum XdpMode {
+    /// Native mode where the driver supports it, generic otherwise.
+    Auto,
+    /// In the driver, before the kernel allocates socket buffers.
+    Native,
+    /// In the kernel's network stack; slower, but works with any driver.
+    Generic,
+    /// Classification and counters on the NIC, the rest native.
+    Offload,
+}
+
+impl From<XdpMode> for XdpFlags {
+    fn from(mode: XdpMode) -> Self {
+        match mode {
+            XdpMode::Auto => Self::default(),
+            XdpMode::Native | XdpMode::Offload => Self::DRV_MODE,
+            XdpMode::Generic => Self::SKB_MODE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Encapsulation {
+    /// Ethernet frames in UDP to port 4789, as datacenter overlays carry them.
+    Vxlan,
+    /// IP packets in GTP-U G-PDUs to UDP port 2152, as mobile core networks carry them.
+    Gtpu,
+}
+
+impl From<Encapsulation> for u32 {
+    fn from(encapsulation: Encapsulation) -> Self {
+        match encapsulation {
+            Encapsulation::Vxlan => udcn_common::DECAPSULATE_VXLAN,
+            Encapsulation::Gtpu => udcn_common::DECAPSULATE_GTPU,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogLevel {
+    Off,
+    /// Failures that should not happen.
+    Error,
+    /// Drops, with their reason.
+    Warn,
+    /// What happened to each packet.
+    Info,
+    /// Table lookups and updates.
+    Debug,
+    /// The parsed fields of each packet.
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> log::LevelFilter {
+        match self {
+            Self::Off => log::LevelFilter::Off,
+            Self::Error => log::LevelFilter::Error,
+            Self::Warn => log::LevelFilter::Warn,
+            Self::Info => log::LevelFilter::Info,
+            Self::Debug => log::LevelFilter::Debug,
+            Self::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for DatapathLogLevel {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::Off,
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Trace => Self::Trace,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum VersionScheme {
+    /// Microseconds since the Unix epoch.
+    Timestamp,
+    /// One more than the last version published under the name.
+    Seq,
+    /// The number given with --version-number.
+    Explicit,
+}
+
+#[derive(Debug, Subcommand)]
+enum CsCommand {
+    List,
+    /// Show the capacity and occupancy of each content store partition.
+    Partitions,
+    /// Remove every entry from the content store.
+    Flush,
+    /// Remove the Data cached under a name, e.g. a copy that failed validation.
+    Purge {
+        name: Name,
+    },
+    /// Fetch and cache the segments under a prefix while the datapath is idle. Needs the
+    /// [prefetch] section in the daemon's configuration.
+    Prefetch {
+        prefix: Name,
+    },
+    /// Warm the content store before a flash crowd: fetch each name in a manifest, or import
+    /// the local file given after it, then show how much fit in each partition.
+    Preload {
+        manifest: PathBuf,
+        /// Where to fetch the names from. Defaults to the [prefetch] upstream.
+        #[clap(long)]
+        upstream: Option<SocketAddr>,
+        /// Split imported files into segments of this many bytes, named <name>/seg=<n>. With
+        /// `auto`, each segment is made as large as fits one datagram on the smallest MTU of the
+        /// daemon's interfaces.
+        #[clap(long, default_value_t = SegmentSize::Auto)]
+        segment_size: SegmentSize,
+    },
+    /// Keep the Data cached under a name from ever being evicted, until unpinned. Journaled, so
+    /// pins survive a restart replaying the journal.
+    Pin {
+        name: Name,
+        /// Pin every name under this prefix instead.
+        #[clap(long)]
+        prefix: bool,
+    },
+    /// Let pinned Data be evicted again.
+    Unpin {
+        name: Name,
+        #[clap(long)]
+        prefix: bool,
+    },
+    /// Show what is pinned and how many cached entries each pin covers.
+    Pins,
+}
+
+#[derive(Debug, Subcommand)]
+enum StateCommand {
+    /// Write the names, strategy overrides, pins and content store entries the daemon holds to
+    /// a file, for `state import` on another host.
+    Export {
+        file: PathBuf,
+        /// Also export what the Data cache holds for each entry.
+        #[clap(long)]
+        payloads: bool,
+    },
+    /// Take on the state `state export` wrote, caching its entries again.
+    Import { file: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+enum PitCommand {
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum FaceCommand {
+    /// Create a face, such as `ether://eth0` for NDN over Ethernet on eth0.
+    Create {
+        uri: String,
+        /// Send Data that several hosts on the segment wait for once, to the NDN multicast
+        /// address.
+        #[clap(long)]
+        multicast: bool,
+    },
+    /// Show the daemon's own faces, such as tunnels to other sites.
+    List {
+        /// Also show send buffer occupancy, EWOULDBLOCK drops and queuing delay.
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum StrategyCommand {
+    /// Override the strategy for a prefix, until unset. Survives daemon restarts.
+    Set {
+        prefix: Name,
+        /// failover, retransmit or load-balance.
+        strategy: String,
+        /// Parameters as key=value: probe_interval_ms, failures_until_down, on_nack (propagate,
+        /// wait or suppress), deadline_aware (true or false), for retransmit retransmit_ms and
+        /// max_retries, and for load-balance weights=<addr>:<weight>,...
+        parameters: Vec<String>,
+    },
+    /// Drop the override for a prefix, going back to the configured strategy.
+    Unset { prefix: Name },
+    /// Show the strategy in effect for each prefix and where it comes from.
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum RouteCommand {
+    /// Route a prefix of up to four components. Survives daemon restarts unless --ephemeral.
+    Add {
+        prefix: nocache::NoCachePrefix,
+        /// Route it only until the daemon stops.
+        #[clap(long)]
+        ephemeral: bool,
+    },
+    /// Drop a route added with `udcn route add`.
+    Remove { prefix: nocache::NoCachePrefix },
+    /// Show every route and where it comes from.
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum AclCommand {
+    /// Show the source addresses that misbehaved, and which are greylisted.
+    ListDynamic,
+    /// Forget a source address's offences, or every address's, lifting its greylisting.
+    ClearDynamic { addr: Option<Ipv4Addr> },
+    /// Count an offence against a source address, such as a Data whose signature failed to
+    /// verify.
+    Report { addr: Ipv4Addr },
+}
+
+#[cfg(feature = "faults")]
+#[derive(Debug, Subcommand)]
+enum FaultCommand {
+    /// Drop this percentage of Interests, picked at random.
+    DropInterests { percent: f64 },
+    /// Hold back Data answering Interests for this many milliseconds.
+    DelayData { ms: u32 },
+    /// Flip bits in the signature of every Data.
+    CorruptSignatures {
+        /// Stop corrupting signatures.
+        #[clap(long)]
+        off: bool,
+    },
+    /// Drop every Interest under a prefix.
+    Blackhole { prefix: nocache::NoCachePrefix },
+    /// Stop dropping Interests under a blackholed prefix.
+    Restore { prefix: nocache::NoCachePrefix },
+    /// Stop injecting faults.
+    Clear,
+}
+
+#[cfg(feature = "faults")]
+impl FaultCommand {
+    /// The `fault` control request.
+    fn request(&self) -> String {
+        match self {
+            Self::DropInterests { percent } => format!("fault drop-interests {percent}"),
+            Self::DelayData { ms } => format!("fault delay-data {ms}"),
+            Self::CorruptSignatures { off } => {
+                format!("fault corrupt-signatures {}", if *off { "off" } else { "on" })
+            }
+            Self::Blackhole { prefix } => format!("fault blackhole {}", prefix.0),
+            Self::Restore { prefix } => format!("fault restore {}", prefix.0),
+            Self::Clear => "fault clear".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum BenchCommand {
+    /// Send Interests for fresh names, for `bench latency` on the forwarder to time.
+    Load {
+        #[clap(short, long)]
+        target: SocketAddr,
+        #[clap(long, default_value_t = 100_000)]
+        count: u64,
+        /// Interests a second.
+        #[clap(long, default_value_t = 10_000)]
+        rate: u64,
+    },
+    /// On the forwarder, time the benchmark Interests from the NIC to the XDP decision and on to
+    /// the socket, and report p50/p99/p99.9.
+    Latency {
+        /// Where the Interests are passed up to; nothing else may be bound there.
+        #[clap(long, default_value = "0.0.0.0:6363")]
+        listen: SocketAddr,
+        /// Switch on hardware receive timestamps for this interface (needs root).
+        #[clap(short, long)]
+        interface: Option<String>,
+        /// Stop after pairing this many Interests with their decisions, or on Ctrl-C.
+        #[clap(long, default_value_t = 100_000)]
+        count: usize,
+    },
+    /// Time throughput and latency between a consumer and a producer on two hosts, through the
+    /// forwarders between them, and print one JSON report with where Data was cached.
+    Pair {
+        #[clap(long, value_enum)]
+        role: PairRole,
+        /// The producer's control channel: where it listens, or where the consumer reaches it.
+        #[clap(short, long)]
+        control: SocketAddr,
+        /// Where the producer receives Interests.
         #[clap(short, long, default_value = "127.0.0.1:6363")]
         bind: String,
+        /// The consumer's first forwarder towards the producer.
+        #[clap(short, long, required_if_eq("role", "consumer"))]
+        target: Option<SocketAddr>,
+        #[clap(long, default_value = "/udcn/bench/pair")]
+        prefix: Name,
+        #[clap(long, default_value_t = 10)]
+        duration_secs: u64,
+        /// Interests a second.
+        #[clap(long, default_value_t = 1_000)]
+        rate: u64,
+        #[clap(long, default_value_t = 1_024)]
+        content_size: usize,
+        /// Share of Interests, in percent, asking again for names already fetched.
+        #[clap(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+        repeat_percent: u8,
+        /// Write the report here rather than to stdout.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PairRole {
+    /// Serve the run and report what reached it.
+    Producer,
+    /// Start the run, send its Interests and merge the report.
+    Consumer,
+}
+
+#[derive(Debug, Subcommand)]
+enum TopoCommand {
+    /// Build the topology described in a TOML file, run its traffic and report per-node hit
+    /// ratios.
+    Run {
+        file: PathBuf,
+        /// Show the output of the daemons, producers and consumers.
+        #[clap(long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum NamesCommand {
+    Register {
+        name: Name,
+    },
+    /// Count the Interests the datapath handles for a while and list the names with the most.
+    Top {
+        /// Names to list.
+        #[clap(short, default_value_t = 10)]
+        k: usize,
+        /// Seconds to count for, up to 60.
+        #[clap(long, default_value_t = 5)]
+        secs: u64,
     },
-    Stats,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
 
-    env_logger::init();
+    let mut logger = env_logger::Builder::from_default_env();
+    if let Commands::Run { datapath_log_level, .. } = &opt.command {
+        // Asking for datapath records is enough to see them, whatever RUST_LOG says
+        if !matches!(datapath_log_level, LogLevel::Off) {
+            logger.filter_module(DATAPATH_LOG_TARGET, datapath_log_level.filter());
+        }
+    }
+    logger.init();
 
     match opt.command {
-        Commands::Run { stats_interval } => {
-            run_daemon(opt.iface, stats_interval).await
+        Commands::Run {
+            stats_interval,
+            negative_cache_ttl_ms,
+            duplicate_window_ms,
+            pin_dir,
+            cs_partitions,
+            cs_protected_percent,
+            no_cache,
+            nack_unroutable,
+            routes,
+            verify_udp_checksums,
+            greylist_after,
+            greylist_window_secs,
+            greylist_secs,
+            decapsulate,
+            cs_admission,
+            cs_admission_probability,
+            cs_admission_threshold,
+            cs_admission_window_ms,
+            cs_bloom_rebuild_secs,
+            pit_layout,
+            pit_sweep_ms,
+            autosize,
+            interest_rate,
+            interest_lifetime_ms,
+            cs_budget,
+            expected_routes,
+            metrics_listen,
+            config: config_file,
+            datapath_log_level,
+            force,
+            chain,
+            xdp_mode,
+            user,
+            group,
+            journal,
+        } => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&cs_admission_probability),
+                "--cs-admission-probability must be between 0 and 1"
+            );
+            let daemon_config = match config_file {
+                Some(path) => config::load(&path)?,
+                None => config::DaemonConfig::default(),
+            };
+            let config = DatapathConfig {
+                negative_cache_ttl_ms,
+                duplicate_window_ms,
+                cs_admission: CsAdmission::from(cs_admission) as u32,
+                cs_admission_probability: (cs_admission_probability * u32::MAX as f64) as u32,
+                cs_admission_threshold,
+                cs_admission_window_ms,
+                log_level: DatapathLogLevel::from(datapath_log_level) as u32,
+                cs_segmented: (cs_protected_percent > 0) as u32,
+                nack_unroutable: nack_unroutable as u32,
+                pit_layout: PitLayout::from(pit_layout) as u32,
+                tenants: daemon_config.tenants.len() as u32,
+                verify_udp_checksums: verify_udp_checksums as u32,
+                acl_rules: daemon_config.acl.rules.len() as u32,
+                acl_default_deny: (daemon_config.acl.default == AclAction::Deny) as u32,
+                greylist_offences: greylist_after,
+                greylist_window_ms: greylist_window_secs.saturating_mul(1000),
+                greylist_ms: greylist_secs.saturating_mul(1000),
+                decapsulate: decapsulate.iter().fold(0, |flags, &encapsulation| {
+                    flags | u32::from(encapsulation)
+                }),
+                fault_injection: cfg!(feature = "faults") as u32,
+            };
+            let sizes = if autosize {
+                let targets = autosize::Targets {
+                    interest_rate,
+                    interest_lifetime_ms,
+                    cs_bytes: cs_budget,
+                    routes: expected_routes,
+                };
+                let available = autosize::available_memory();
+                if available.is_none() {
+                    warn!("failed to find the memory the maps can lock; sizing them regardless");
+                }
+                let sizing =
+                    autosize::Sizing::derive(&targets, PitLayout::from(pit_layout), available)?;
+                print!("{sizing}");
+                sizing.sizes
+            } else {
+                autosize::MapSizes::default()
+            };
+            let layout = partitions::Layout::new(&cs_partitions, cs_protected_percent, sizes.cs)?;
+            let routes = if nack_unroutable {
+                routes::collect(&routes, &daemon_config.tunnels)?
+            } else {
+                Vec::new()
+            };
+            anyhow::ensure!(
+                greylist_after == 0 || (greylist_window_secs > 0 && greylist_secs > 0),
+                "--greylist-window-secs and --greylist-secs must be positive"
+            );
+            anyhow::ensure!(cs_bloom_rebuild_secs > 0, "--cs-bloom-rebuild-secs must be positive");
+            let cs_bloom_rebuild = Duration::from_secs(cs_bloom_rebuild_secs);
+            anyhow::ensure!(pit_sweep_ms > 0, "--pit-sweep-ms must be positive");
+            let pit_sweep = Duration::from_millis(pit_sweep_ms);
+            let attach = match (force, chain) {
+                (true, _) => attach::Policy::Replace,
+                (_, true) => attach::Policy::Chain,
+                _ => attach::Policy::Refuse,
+            };
+            let options = DaemonOptions {
+                attach,
+                xdp_mode,
+                user,
+                group,
+                stats_interval,
+                pin_dir,
+                layout,
+                sizes,
+                no_cache,
+                routes,
+                cs_bloom_rebuild,
+                pit_sweep,
+                metrics_listen,
+                otlp: daemon_config.otlp,
+                prefetch: daemon_config.prefetch,
+                flows: daemon_config.flows,
+                tunnels: daemon_config.tunnels,
+                dtls_listeners: daemon_config.dtls_listeners,
+                strategies: daemon_config.strategies,
+                alarms: daemon_config.alarms,
+                tenants: daemon_config.tenants,
+                acl: daemon_config.acl,
+                gates: daemon_config.gates,
+                control: daemon_config.control,
+                journal,
+            };
+            run_daemon(opt.iface, config, options).await
+        }
+        Commands::Send {
+            name,
+            target,
+            forwarding_hint,
+            retransmit_ms,
+            max_retransmissions,
+            tier,
+        } => {
+            let faces =
+                face_table(&target, &name, retransmit_ms, max_retransmissions, &tier).await;
+            send_interest(name, &faces, forwarding_hint).await
+        }
+        Commands::Serve {
+            name,
+            content,
+            file,
+            bind,
+            prefix,
+            filters,
+            segment_size,
+            freshness_ms,
+            segment_store,
+            on_disk,
+            manifest,
+            version,
+            version_number,
+            versions_file,
+            grace_secs,
+            mtu,
+            no_fragment,
+            queue_capacity,
+            congestion_mark_at,
+            fair_workers,
+            client_rate,
+            client_burst,
+            compress,
+            compress_above,
+            gate,
+        } => {
+            let compression =
+                compress.map(|codec| Compression { codec, min_size: compress_above });
+            let producer = Producer::bind(&bind).await?;
+            for (prefix, secret_file) in gate {
+                let secret = std::fs::read(&secret_file)
+                    .with_context(|| format!("failed to read {}", secret_file.display()))?;
+                anyhow::ensure!(!secret.is_empty(), "{} is empty", secret_file.display());
+                info!("Gating '{prefix}': answering only Interests with a valid token");
+                producer.add_gate(prefix, secret);
+            }
+            producer.set_mtu(mtu);
+            if no_fragment {
+                producer.set_mtu_policy(MtuPolicy::Refuse);
+            }
+            producer.set_queue_limits(QueueLimits {
+                capacity: queue_capacity,
+                mark_threshold: congestion_mark_at,
+            });
+            if let Some(workers) = fair_workers {
+                anyhow::ensure!(workers > 0, "--fair-workers must be positive");
+                anyhow::ensure!(
+                    client_rate.is_none_or(|rate| rate > 0.0),
+                    "--client-rate must be positive"
+                );
+                producer.set_fair_limits(Some(FairLimits { workers, client_rate, client_burst }));
+            }
+            if let (true, Some(path), Some(name)) = (on_disk, &segment_store, &name) {
+                let store = if path.exists() {
+                    let store = DiskSegmentStore::open(path).await?;
+                    info!("Indexed {} signed segment(s) in {}", store.len(), path.display());
+                    store
+                } else {
+                    let (Some(segment_size), Some(source)) =
+                        (segment_size, content_source(file.as_deref(), content.as_deref()).await?)
+                    else {
+                        anyhow::bail!(
+                            "{} does not exist yet; give --segment-size and the content to publish \
+                             into it",
+                            path.display()
+                        );
+                    };
+                    let (len, source) = source;
+                    let last = name.clone().append_segment(len);
+                    let segment_size =
+                        segment_size.resolve(mtu, &last, freshness_ms, sha256_signer)?;
+                    let store = DiskSegmentStore::publish(
+                        path,
+                        name,
+                        source,
+                        segment_size,
+                        freshness_ms,
+                        sha256_signer,
+                    )
+                    .await?;
+                    info!(
+                        "Published {} segment(s) under '{name}' into {}",
+                        store.len(),
+                        path.display()
+                    );
+                    store
+                };
+                return serve_disk_store(&producer, name, Arc::new(store), bind).await;
+            }
+            let content = match &file {
+                Some(path) => Some(
+                    std::fs::read(path)
+                        .with_context(|| format!("failed to read {}", path.display()))?,
+                ),
+                None => content.map(String::into_bytes),
+            };
+            let publish = if manifest { manifest::publish } else { SegmentStore::publish };
+            if let (Some(path), Some(name)) = (&segment_store, &name) {
+                let mut store = if path.exists() {
+                    let store = SegmentStore::load(path)?;
+                    info!("Loaded {} signed segment(s) from {}", store.len(), path.display());
+                    store
+                } else {
+                    let (Some(segment_size), Some(content)) = (segment_size, &content) else {
+                        anyhow::bail!(
+                            "{} does not exist yet; give --segment-size and the content to publish \
+                             into it",
+                            path.display()
+                        );
+                    };
+                    let last = name.clone().append_segment(content.len() as u64);
+                    let segment_size =
+                        segment_size.resolve(mtu, &last, freshness_ms, sha256_signer)?;
+                    let mut store = SegmentStore::new();
+                    let segments =
+                        publish(&mut store, name, content, segment_size, freshness_ms, sha256_signer);
+                    store.save(path)?;
+                    info!("Published {segments} segment(s) under '{name}' into {}", path.display());
+                    store
+                };
+                store.set_compression(compression);
+                return serve_store(&producer, name, &store, bind).await;
+            }
+            if let (Some(segment_size), Some(name), Some(content)) = (segment_size, &name, &content) {
+                // A file served in versions may grow, so size for the longest names there can be
+                let last = match version {
+                    Some(_) => name.clone().append_version(u64::MAX).append_segment(u64::MAX),
+                    None => name.clone().append_segment(content.len() as u64),
+                };
+                let segment_size = segment_size.resolve(mtu, &last, freshness_ms, sha256_signer)?;
+                info!("Publishing '{name}' in segments of {segment_size} bytes");
+                let Some(scheme) = version else {
+                    let mut store = SegmentStore::new();
+                    store.set_compression(compression);
+                    let segments =
+                        publish(&mut store, name, content, segment_size, freshness_ms, sha256_signer);
+                    info!("Published {segments} segment(s) under '{name}'");
+                    return serve_store(&producer, name, &store, bind).await;
+                };
+                if freshness_ms.is_none() {
+                    warn!(
+                        "'{name}' is stale as soon as it is published without --freshness-ms, so \
+                         `udcn get` cannot find it as the latest version"
+                    );
+                }
+                // Explicit versions count up from --version-number as the file changes
+                let mut explicit = version_number.unwrap_or_default();
+                let next_version = || -> anyhow::Result<u64> {
+                    Ok(match scheme {
+                        VersionScheme::Timestamp => versions::timestamp(),
+                        VersionScheme::Seq => versions::next_sequence(&versions_file, name)?,
+                        VersionScheme::Explicit => {
+                            explicit += 1;
+                            explicit - 1
+                        }
+                    })
+                };
+                let grace = Duration::from_secs(grace_secs);
+                let mut publisher =
+                    repo::Publisher::new(name.clone(), segment_size, freshness_ms, grace, next_version);
+                publisher.set_manifest(manifest);
+                let mut store = SegmentStore::new();
+                store.set_compression(compression);
+                let store = std::sync::RwLock::new(store);
+                publisher.publish(&store, content)?;
+                return serve_versions(&producer, name, &store, publisher, file.as_deref(), bind)
+                    .await;
+            }
+            let mut filters = match filters {
+                Some(path) => filters::load(&path)?,
+                None => InterestFilters::new(),
+            };
+            if let (Some(name), Some(content)) = (name, content) {
+                let filter = InterestFilter::new(name);
+                // Nothing may follow the name unless the whole prefix is served
+                let filter = if prefix { filter } else { filter.regex("/")? };
+                filters.register(filter, filters::answer_with(content, freshness_ms));
+            }
+            serve_data(&producer, filters, bind).await
+        }
+        Commands::Get {
+            name,
+            target,
+            output,
+            resume,
+            retransmit_ms,
+            max_retransmissions,
+            tier,
+            token,
+        } => {
+            let faces =
+                face_table(&target, &name, retransmit_ms, max_retransmissions, &tier).await;
+            faces.set_token(token.map(|token| token.0));
+            let result = match output {
+                Some(output) if resume => resume_content(name, &faces, &output).await,
+                output => get_content(name, &faces, output).await,
+            };
+            if !tier.is_empty() {
+                for (tier, stats) in faces.tier_stats() {
+                    info!("Tier {tier}: {} Interests sent, {} hits", stats.sent, stats.hits);
+                }
+            }
+            result
+        }
+        Commands::Token { prefix, secret_file, valid_secs } => {
+            let secret = std::fs::read(&secret_file)
+                .with_context(|| format!("failed to read {}", secret_file.display()))?;
+            anyhow::ensure!(!secret.is_empty(), "{} is empty", secret_file.display());
+            let expires = state::unix_ms() / 1000 + valid_secs;
+            println!("{}", Token::issue(&secret, &prefix, expires));
+            Ok(())
         }
-        Commands::Send { name, target } => {
-            send_interest(name, target).await
+        Commands::Stats { json, save, diff } => {
+            show_stats(json, save, diff).await
         }
-        Commands::Serve { name, content, bind } => {
-            serve_data(name, content, bind).await
+        Commands::Cs { command: CsCommand::List } => {
+            print!("{}", udcn_client::control::request("cs-list").await?);
+            Ok(())
         }
-        Commands::Stats => {
-            show_stats().await
+        Commands::Cs { command: CsCommand::Partitions } => {
+            print!("{}", udcn_client::control::request("cs-partitions").await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Flush } => {
+            print!("{}", udcn_client::control::request("cs-flush").await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Purge { name } } => {
+            let request = format!("cs-purge {name}");
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Pin { name, prefix } } => {
+            let pin = if prefix { pins::Pin::Prefix(name) } else { pins::Pin::Name(name) };
+            print!("{}", udcn_client::control::request(&format!("cs-pin {pin}")).await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Unpin { name, prefix } } => {
+            let pin = if prefix { pins::Pin::Prefix(name) } else { pins::Pin::Name(name) };
+            print!("{}", udcn_client::control::request(&format!("cs-unpin {pin}")).await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Pins } => {
+            print!("{}", udcn_client::control::request("cs-pins").await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Preload { manifest, upstream, segment_size } } => {
+            // Journal replays read the manifest again, from wherever they run
+            let manifest = std::fs::canonicalize(&manifest)
+                .with_context(|| format!("failed to find {}", manifest.display()))?;
+            anyhow::ensure!(
+                !manifest.to_string_lossy().contains(char::is_whitespace),
+                "the manifest's path may not contain whitespace"
+            );
+            let request = preload::Request { manifest, upstream, segment_size };
+            print!("{}", preload::send(&format!("cs-preload {request}"), &request).await?);
+            Ok(())
+        }
+        Commands::State { command: StateCommand::Export { file, payloads } } => {
+            let request = if payloads { "state-export payloads" } else { "state-export" };
+            let state = udcn_client::control::request(request).await?;
+            std::fs::write(&file, state)
+                .with_context(|| format!("failed to write {}", file.display()))?;
+            println!("Exported the daemon's state to {}", file.display());
+            Ok(())
+        }
+        Commands::State { command: StateCommand::Import { file } } => {
+            // Journal replays read the file again, from wherever they run
+            let file = std::fs::canonicalize(&file)
+                .with_context(|| format!("failed to find {}", file.display()))?;
+            let request = format!("state-import {}", file.display());
+            print!("{}", state::send(&request, &file).await?);
+            Ok(())
+        }
+        Commands::Cs { command: CsCommand::Prefetch { prefix } } => {
+            let request = format!("cs-prefetch {prefix}");
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Pit { command: PitCommand::List } => {
+            print!("{}", udcn_client::control::request("pit-list").await?);
+            Ok(())
+        }
+        Commands::Face { command: FaceCommand::Create { uri, multicast } } => {
+            let request = match multicast {
+                true => format!("face-create {uri} multicast"),
+                false => format!("face-create {uri}"),
+            };
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Face { command: FaceCommand::List { verbose } } => {
+            let faces = udcn_client::control::faces().await?;
+            print!("{}", tunnels::format_faces(&faces, verbose));
+            Ok(())
+        }
+        Commands::Strategy { command: StrategyCommand::Set { prefix, strategy, parameters } } => {
+            let request = format!("strategy-set {prefix} {strategy} {}", parameters.join(" "));
+            print!("{}", udcn_client::control::request(request.trim_end()).await?);
+            Ok(())
+        }
+        Commands::Strategy { command: StrategyCommand::Unset { prefix } } => {
+            let request = format!("strategy-unset {prefix}");
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Strategy { command: StrategyCommand::List } => {
+            print!("{}", udcn_client::control::request("strategy-list").await?);
+            Ok(())
+        }
+        Commands::Route { command: RouteCommand::Add { prefix, ephemeral } } => {
+            print!("{}", udcn_client::control::route_add(&prefix.0, ephemeral).await?);
+            Ok(())
+        }
+        Commands::Route { command: RouteCommand::Remove { prefix } } => {
+            print!("{}", udcn_client::control::route_remove(&prefix.0).await?);
+            Ok(())
+        }
+        Commands::Route { command: RouteCommand::List } => {
+            print!("{}", udcn_client::control::request("route-list").await?);
+            Ok(())
+        }
+        Commands::Acl { command: AclCommand::ListDynamic } => {
+            print!("{}", udcn_client::control::request("acl-list-dynamic").await?);
+            Ok(())
+        }
+        Commands::Acl { command: AclCommand::ClearDynamic { addr } } => {
+            let request = match addr {
+                Some(addr) => format!("acl-clear-dynamic {addr}"),
+                None => "acl-clear-dynamic".to_string(),
+            };
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Acl { command: AclCommand::Report { addr } } => {
+            let request = format!("acl-report {addr}");
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Trace { name, target } => {
+            trace_fetch(name, target).await
+        }
+        Commands::Bench { command: BenchCommand::Load { target, count, rate } } => {
+            bench::load(target, count, rate).await
+        }
+        Commands::Bench { command: BenchCommand::Latency { listen, interface, count } } => {
+            bench::latency(listen, interface.as_deref(), count).await
+        }
+        Commands::Bench {
+            command:
+                BenchCommand::Pair {
+                    role,
+                    control,
+                    bind,
+                    target,
+                    prefix,
+                    duration_secs,
+                    rate,
+                    content_size,
+                    repeat_percent,
+                    output,
+                },
+        } => match role {
+            PairRole::Producer => benchpair::producer(control, &bind).await,
+            PairRole::Consumer => {
+                let options = benchpair::ConsumerOptions {
+                    control,
+                    target: target.context("the consumer needs --target")?,
+                    prefix,
+                    duration: Duration::from_secs(duration_secs),
+                    rate,
+                    content_size,
+                    repeat_percent,
+                };
+                let report = benchpair::consumer(&options).await?;
+                let json = serde_json::to_string_pretty(&report)? + "\n";
+                match output {
+                    Some(path) => std::fs::write(&path, json)
+                        .with_context(|| format!("failed to write {}", path.display()))?,
+                    None => print!("{json}"),
+                }
+                Ok(())
+            }
+        },
+        Commands::Events { filter } => {
+            let mut events = udcn_client::control::events(filter.as_deref()).await?;
+            while let Some(line) = events.next_line().await? {
+                println!("{line}");
+            }
+            Ok(())
+        }
+        Commands::Capture { rate, snaplen, write, count, filter } => {
+            let config = (rate.is_some() || snaplen.is_some()).then(|| SampleConfig {
+                rate: rate.unwrap_or(capture::DEFAULT_SAMPLING.rate),
+                snaplen: snaplen.unwrap_or(capture::DEFAULT_SAMPLING.snaplen),
+                ..capture::DEFAULT_SAMPLING
+            });
+            capture::run(config, filter.as_deref(), write.as_deref(), count).await
+        }
+        Commands::Sample { rate, snaplen, filter } => {
+            let response = udcn_client::control::set_sampling(rate, snaplen, filter.as_deref());
+            print!("{}", response.await?);
+            Ok(())
+        }
+        Commands::Names { command: NamesCommand::Register { name } } => {
+            let name_hash = udcn_client::control::register(&name).await?;
+            println!("{name} -> {name_hash:#010x}");
+            Ok(())
+        }
+        Commands::Names { command: NamesCommand::Top { k, secs } } => {
+            print!("{}", udcn_client::control::top_names(k, secs).await?);
+            Ok(())
+        }
+        Commands::Topo { command: TopoCommand::Run { file, verbose } } => {
+            topo::run(&file, verbose).await
+        }
+        Commands::Health { pin_dir, wait_secs } => {
+            let wait = Duration::from_secs(wait_secs);
+            let failures = health::health(&opt.iface, &pin_dir, wait).await;
+            anyhow::ensure!(failures == 0, "{failures} health check(s) failed");
+            Ok(())
+        }
+        Commands::Timings => {
+            print!("{}", batch::format_timings(&udcn_client::control::timings().await?));
+            Ok(())
+        }
+        Commands::Tenants => {
+            print!("{}", tenants::format_tenants(&udcn_client::control::tenants().await?));
+            Ok(())
+        }
+        Commands::Offload => {
+            print!("{}", udcn_client::control::request("offload").await?);
+            Ok(())
+        }
+        Commands::Doctor { verbose } => {
+            let failures = health::doctor(&opt.iface, verbose);
+            anyhow::ensure!(failures == 0, "{failures} problem(s) found");
+            Ok(())
+        }
+        #[cfg(feature = "faults")]
+        Commands::Fault { command } => {
+            let request = command.map_or_else(|| "fault".to_string(), |command| command.request());
+            print!("{}", udcn_client::control::request(&request).await?);
+            Ok(())
+        }
+        Commands::Selftest { verbose } => {
+            let failures = selftest::run_checks(verbose).await?;
+            anyhow::ensure!(failures == 0, "{failures} selftest check(s) failed");
+            Ok(())
+        }
+        Commands::Convert { from, to, file, output } => {
+            let contents =
+                std::fs::read(&file).with_context(|| format!("failed to read {}", file.display()))?;
+            let converted = convert::convert(&contents, from.into(), to.into())
+                .with_context(|| format!("failed to convert {}", file.display()))?;
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, &converted.bytes)
+                        .with_context(|| format!("failed to write {}", output.display()))?;
+                    println!("{converted}");
+                }
+                None => std::io::stdout().write_all(&converted.bytes)?,
+            }
+            Ok(())
+        }
+        Commands::ReplayJournal { file, dry_run } => {
+            let failures = journal::replay(&file, dry_run).await?;
+            anyhow::ensure!(failures == 0, "{failures} journaled request(s) failed");
+            Ok(())
         }
     }
 }
 
-async fn run_daemon(iface: String, stats_interval: Option<u64>) -> anyhow::Result<()> {
+/// How the daemon runs, as opposed to the `DatapathConfig` it hands the XDP program.
+struct DaemonOptions {
+    attach: attach::Policy,
+    xdp_mode: XdpMode,
+    user: Option<String>,
+    group: Option<String>,
+    stats_interval: Option<u64>,
+    pin_dir: PathBuf,
+    layout: partitions::Layout,
+    sizes: autosize::MapSizes,
+    no_cache: Vec<nocache::NoCachePrefix>,
+    /// Prefixes Interests may go up for, with `--nack-unroutable`, besides those added at runtime.
+    routes: Vec<nocache::NoCachePrefix>,
+    cs_bloom_rebuild: Duration,
+    pit_sweep: Duration,
+    metrics_listen: Option<SocketAddr>,
+    otlp: Option<otlp::OtlpConfig>,
+    prefetch: Option<prefetch::PrefetchConfig>,
+    flows: Option<flows::FlowsConfig>,
+    tunnels: Vec<tunnels::TunnelConfig>,
+    dtls_listeners: Vec<dtls::ListenerConfig>,
+    strategies: BTreeMap<Name, StrategyChoice>,
+    alarms: Vec<alarms::AlarmConfig>,
+    tenants: Vec<tenants::TenantConfig>,
+    acl: acl::AclConfig,
+    gates: Vec<gates::GateConfig>,
+    control: access::ControlConfig,
+    journal: Option<PathBuf>,
+}
+
+async fn run_daemon(
+    ifaces: Vec<String>,
+    config: DatapathConfig,
+    options: DaemonOptions,
+) -> anyhow::Result<()> {
+    let DaemonOptions {
+        attach,
+        xdp_mode,
+        user,
+        group,
+        stats_interval,
+        pin_dir,
+        layout,
+        sizes,
+        no_cache,
+        routes,
+        cs_bloom_rebuild,
+        pit_sweep,
+        metrics_listen,
+        otlp,
+        prefetch,
+        flows,
+        tunnels,
+        dtls_listeners,
+        strategies,
+        alarms,
+        tenants,
+        acl,
+        gates,
+        control,
+        journal,
+    } = options;
+    privileges::check()?;
+    let user = user.as_deref().map(privileges::lookup_user).transpose()?;
+    let group = group.as_deref().map(privileges::lookup_group).transpose()?;
     bump_memlock_rlimit()?;
     
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-        env!("OUT_DIR"),
-        "/udcn"
-    )))?;
+    let pit_layout = PitLayout::from_u32(config.pit_layout).unwrap_or_default();
+    let mut loader = aya::EbpfLoader::new();
+    layout.size_maps(&mut loader);
+    pit::size_maps(&mut loader, pit_layout, sizes.pit);
+    sizes.size_maps(&mut loader);
+    let mut ebpf = diagnostics::load(
+        &mut loader,
+        aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/udcn")),
+        Path::new(diagnostics::DEFAULT_LOAD_REPORT_PATH),
+    )
+    .context("failed to load the datapath; `udcn doctor --verbose` shows why")?;
     
     if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
         warn!("failed to initialize eBPF logger: {e}");
     }
-    
-    let program: &mut Xdp = ebpf.program_mut("udcn").unwrap().try_into()?;
-    program.load()?;
-    program.attach(&iface, XdpFlags::default())
-        .context("failed to attach the XDP program with default flags - try changing XdpFlags::default() to XdpFlags::SKB_MODE")?;
 
-    info!("µDCN XDP program loaded and attached to {}", iface);
+    let mut config_map: Array<_, DatapathConfig> = Array::try_from(ebpf.map_mut("CONFIG").unwrap())?;
+    config_map.set(0, config, 0).context("failed to write datapath config")?;
+    layout.install(&mut ebpf).context("failed to write content store partitions")?;
+    for (prefix, capacity) in &layout.reserved {
+        info!("Reserved {capacity} content store entries for '{prefix}'");
+    }
+    if layout.protected > 0 {
+        info!(
+            "Shared content store split into {} entries on probation and {} protected",
+            layout.shared, layout.protected
+        );
+    }
+    // The datapath cannot check tokens, so it must never answer a gated Interest from its cache
+    let mut no_cache = no_cache;
+    for gate in &gates {
+        let prefix = gate.no_cache()?;
+        if !no_cache.contains(&prefix) {
+            no_cache.push(prefix);
+        }
+        info!("Gating '{}': relaying only Interests with a valid token", gate.prefix);
+    }
+    let gates = Arc::new(gates::load(&gates)?);
+    // Read DTLS credentials now, as the gates' secrets, before privileges are dropped
+    let tunnels = tunnels
+        .into_iter()
+        .map(tunnels::Tunnel::load)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let dtls_listeners = dtls_listeners
+        .iter()
+        .map(dtls::Listener::load)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    nocache::install(&mut ebpf, &no_cache).context("failed to write non-cacheable prefixes")?;
+    for prefix in &no_cache {
+        info!("Not caching Data under '{}'", prefix.0);
+    }
+    let routes = routes::Routes::load(&routes, routes::DEFAULT_ROUTES_PATH, sizes.routes)?;
+    routes::install(&mut ebpf, &routes.all(), sizes.routes).context("failed to write routes")?;
+    tenants::install(&mut ebpf, &tenants).context("failed to write tenants")?;
+    for tenant in &tenants {
+        info!("Tenant '{}' holds the names under '{}'", tenant.name(), tenant.prefix);
+    }
+    acl::install(&mut ebpf, &acl).context("failed to write ACL rules")?;
+    if config.acl_rules > 0 {
+        info!(
+            "Checking names against {} ACL rules, {} by default",
+            acl.rules.len(),
+            if config.acl_default_deny != 0 { "dropping" } else { "passing" }
+        );
+    }
+    if pit_layout == PitLayout::OpenAddressing {
+        info!("Keeping the PIT in {} open-addressing slots", udcn_common::pit::PIT_SLOTS);
+    }
+    if config.nack_unroutable != 0 {
+        let all: Vec<String> = routes.all().iter().map(|route| route.0.to_string()).collect();
+        info!("Nacking Interests under none of: {}", all.join(", "));
+    }
+    if config.decapsulate != 0 {
+        let tunnels: Vec<&str> = [
+            (udcn_common::DECAPSULATE_VXLAN, "VXLAN"),
+            (udcn_common::DECAPSULATE_GTPU, "GTP-U"),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| config.decapsulate & flag != 0)
+        .map(|(_, tunnel)| tunnel)
+        .collect();
+        info!("Classifying NDN inside {} packets", tunnels.join(" and "));
+    }
+    if config.greylist_offences > 0 {
+        info!(
+            "Greylisting sources for {} s after {} offences within {} s",
+            config.greylist_ms / 1000,
+            config.greylist_offences,
+            config.greylist_window_ms / 1000
+        );
+    }
+
+    let time_base = events::install_time_base(&mut ebpf).context("failed to write the time base")?;
+    debug!(
+        "Datapath clock {} ns is {} ns since the epoch",
+        time_base.monotonic_ns, time_base.realtime_ns
+    );
+
+    match daemon::pin_maps(&ebpf, &pin_dir) {
+        Ok(()) => info!("Pinned maps under {}", pin_dir.display()),
+        Err(e) => warn!("failed to pin maps under {}: {e:#}", pin_dir.display()),
+    }
+
+    let attached = attach::attach(&mut ebpf, &ifaces, attach, xdp_mode.into(), &pin_dir)?;
+    info!("µDCN XDP program loaded and attached to {}", ifaces.join(", "));
+    // Loading onto a NIC needs the privileges the daemon is about to drop
+    let offloads = (xdp_mode == XdpMode::Offload)
+        .then(|| Arc::new(offload::Offloads::load(&ifaces, &pin_dir)));
+
+    let names = names::NameTable::load(names::DEFAULT_NAMES_PATH)?;
+    info!("Loaded {} known names from {}", names.len(), names::DEFAULT_NAMES_PATH);
+    let daemon = daemon::Daemon::new(&mut ebpf, names, &layout, pit_layout, &sizes)?;
+    let daemon = Arc::new(Mutex::new(daemon));
+    let strategies =
+        strategies::Strategies::load(&strategies, strategies::DEFAULT_STRATEGIES_PATH)?;
+    daemon.lock().unwrap().set_strategies(strategies);
+    daemon.lock().unwrap().set_routes(routes);
+    if let Some(offloads) = &offloads {
+        daemon.lock().unwrap().set_offloads(offloads.clone());
+    }
+    if let Some(path) = &journal {
+        daemon.lock().unwrap().set_journal(journal::Journal::open(path)?);
+        info!("Journaling control changes to {}", path.display());
+    }
+    let own_uid = user.map_or_else(|| unsafe { libc::geteuid() }, |(uid, _)| uid);
+    let access = Arc::new(access::Access::new(&control, own_uid, group)?);
+    if let Some(path) = &control.audit_log {
+        info!("Auditing control changes to {}", path.display());
+    }
+    let hub = events::EventHub::new(&mut ebpf)?;
+    let sampler = capture::Sampler::new(&mut ebpf)?;
+    let control_listener = control::bind()?;
+    // The raw socket has to be opened while the daemon still has CAP_NET_RAW
+    let nacks = match lapsed::NackSender::open() {
+        Ok(sender) => Some(Arc::new(sender)),
+        Err(e) => {
+            warn!(
+                "faces whose PIT records lapse will not be sent Nacks, nor traced packets hop \
+                 reports, without CAP_NET_RAW: {e}"
+            );
+            None
+        }
+    };
+    #[cfg(feature = "faults")]
+    daemon.lock().unwrap().set_faults(faults::Faults::new(&mut ebpf)?);
+
+    if user.is_some() || group.is_some() {
+        let uid = user.map(|(uid, _)| uid);
+        let gid = group.or(user.map(|(_, gid)| gid));
+        let mut paths = vec![pin_dir.clone(), udcn_client::control::socket_path()];
+        // The daemon appends to the name table as names are registered, and keeps strategy
+        // overrides next to it
+        if user.is_some() {
+            if let Some(dir) = std::path::Path::new(names::DEFAULT_NAMES_PATH).parent() {
+                std::fs::create_dir_all(dir)?;
+                paths.push(dir.to_path_buf());
+            }
+        }
+        for path in paths {
+            if let Err(e) = privileges::hand_over(&path, uid, gid) {
+                warn!("failed to hand {} over: {e:#}", path.display());
+            }
+        }
+    }
+    privileges::drop_privileges(user).context("failed to drop privileges")?;
+    if let Some((uid, _)) = user {
+        info!("Dropped privileges to uid {uid}");
+    }
 
     if let Some(interval) = stats_interval {
-        let stats_map: Array<_, PacketStats> = Array::try_from(ebpf.take_map("STATS").unwrap())?;
+        let daemon = daemon.clone();
         
         tokio::spawn(async move {
             loop {
                 sleep(Duration::from_secs(interval)).await;
-                if let Ok(stats) = stats_map.get(&0, 0) {
-                    print_stats(&stats);
+                let daemon = daemon.lock().unwrap();
+                if let (Ok(stats), Ok(tables)) = (daemon.stats(), daemon.tables()) {
+                    print_stats(&stats, &tables);
+                }
+            }
+        });
+    }
+
+    if let Some(sender) = nacks.clone() {
+        trace::start(&mut ebpf, sender)?;
+    }
+    lapsed::start(&mut ebpf, daemon.clone(), nacks, pit_sweep)?;
+
+    let bloom_daemon = daemon.clone();
+    tokio::spawn(async move {
+        loop {
+            sleep(cs_bloom_rebuild).await;
+            if let Err(e) = bloom_daemon.lock().unwrap().rebuild_cs_bloom() {
+                warn!("failed to rebuild the content store Bloom filter: {e:#}");
+            }
+        }
+    });
+
+    if let Some(addr) = metrics_listen {
+        let metrics_daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, metrics_daemon).await {
+                warn!("metrics exporter stopped: {e:#}");
+            }
+        });
+    }
+
+    if let Some(config) = otlp {
+        otlp::start(config, daemon.clone())?;
+    }
+
+    if !alarms.is_empty() {
+        info!("Evaluating {} alarm(s)", alarms.len());
+        let alarm_daemon = daemon.clone();
+        alarm_daemon.lock().unwrap().set_alarms(alarms::Alarms::new(alarms));
+        tokio::spawn(async move {
+            loop {
+                sleep(alarms::EVALUATION_INTERVAL).await;
+                if let Err(e) = alarm_daemon.lock().unwrap().evaluate_alarms() {
+                    warn!("failed to evaluate alarms: {e:#}");
+                }
+            }
+        });
+    }
+
+    if !tenants.is_empty() {
+        let tenant_daemon = daemon.clone();
+        let tenants = tenants::Tenants::new(&mut ebpf, tenants)?;
+        tenant_daemon.lock().unwrap().set_tenants(tenants);
+        tokio::spawn(async move {
+            loop {
+                sleep(tenants::SYNC_INTERVAL).await;
+                if let Err(e) = tenant_daemon.lock().unwrap().sync_tenants() {
+                    warn!("failed to sync tenant usage: {e:#}");
                 }
             }
         });
     }
 
+    daemon.lock().unwrap().set_no_cache(no_cache.clone());
+    daemon.lock().unwrap().set_gates(gates);
+    if let Some(config) = prefetch {
+        prefetch::start(config, daemon.clone(), &hub, no_cache)?;
+    }
+    if let Some(config) = flows {
+        flows::start(config, daemon.clone(), &hub)?;
+    }
+    if !tunnels.is_empty() {
+        tunnels::start(tunnels, daemon.clone())?;
+    }
+    for listener in dtls_listeners {
+        listener.start()?;
+    }
+
+    let control_daemon = daemon.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            control::serve(control_listener, control_daemon, access, hub, sampler).await
+        {
+            warn!("control socket stopped: {e:#}");
+        }
+    });
+
     let ctrl_c = signal::ctrl_c();
     info!("µDCN daemon running. Press Ctrl-C to exit...");
     ctrl_c.await?;
     info!("Shutting down µDCN daemon...");
+    if let Some(offloads) = offloads {
+        offloads.detach();
+    }
+    attached.detach(&mut ebpf);
+    daemon::unpin_maps(&pin_dir);
 
     Ok(())
 }
 
-async fn send_interest(name: String, target: String) -> anyhow::Result<()> {
-    let socket = UdpSocket::bind("0.0.0.0:0")?;
-    let target_addr: SocketAddr = target.parse()?;
-    
-    let nonce = rand::random::<u32>();
-    let interest_packet = serialize_interest(&name, nonce);
+/// Faces for `target`, most preferred first and tagged with `tiers`, retransmitting after
+/// `retransmit_ms` if given and following the daemon's strategy for `name` otherwise.
+async fn face_table(
+    target: &[SocketAddr],
+    name: &Name,
+    retransmit_ms: Option<u64>,
+    max_retries: u32,
+    tiers: &[(SocketAddr, Tier)],
+) -> Arc<FaceTable> {
+    let tiers = tiers.iter().copied().collect();
+    if let Some(ms) = retransmit_ms {
+        let faces = FaceTable::new(target, Liveness::default());
+        faces.set_tiers(&tiers);
+        faces.set_retransmission(Some(Retransmission {
+            timeout: Duration::from_millis(ms),
+            max_retries,
+        }));
+        return faces;
+    }
+    let strategy = match udcn_client::control::strategy(name).await {
+        Ok(strategy) => strategy,
+        Err(e) => {
+            debug!("could not ask the daemon for the strategy for '{name}': {e:#}");
+            StrategyChoice::default()
+        }
+    };
+    let faces = FaceTable::new(target, strategy.liveness());
+    faces.set_tiers(&tiers);
+    faces.set_retransmission(strategy.retransmission());
+    faces.set_nack_policy(strategy.nack_policy());
+    faces.set_deadline_aware(strategy.deadline_aware());
+    faces.set_load_balance(strategy.load_balance());
+    faces
+}
+
+/// A `--gate` argument: `<prefix>=<file>`.
+fn parse_gate(argument: &str) -> anyhow::Result<(Name, PathBuf)> {
+    let (prefix, secret_file) = argument
+        .split_once('=')
+        .context("expected <prefix>=<file>")?;
+    Ok((Name::from(prefix), PathBuf::from(secret_file)))
+}
+
+/// A `--tier` argument: `<address>=<tier>`.
+fn parse_tier(argument: &str) -> anyhow::Result<(SocketAddr, Tier)> {
+    let (addr, tier) = argument
+        .split_once('=')
+        .context("expected <address>=<tier>")?;
+    Ok((addr.parse()?, tier.parse()?))
+}
+
+async fn send_interest(
+    name: Name,
+    faces: &FaceTable,
+    forwarding_hint: Vec<Name>,
+) -> anyhow::Result<()> {
+    register_name(&name).await;
     
-    socket.send_to(&interest_packet, target_addr)?;
-    info!("Sent Interest for '{}' to {}", name, target);
+    let interest = forwarding_hint
+        .into_iter()
+        .fold(Interest::builder().name(name.clone()), |builder, delegation| {
+            builder.forwarding_hint(delegation)
+        })
+        .build();
+    info!("Sending Interest for '{}'", name);
     
-    let mut buf = [0u8; 1024];
-    match socket.recv_from(&mut buf) {
-        Ok((len, addr)) => {
-            info!("Received Data response ({} bytes) from {}", len, addr);
+    match faces.express(&interest).await {
+        Ok((from, Reply::Data(data))) => {
+            info!(
+                "Received Data for '{}' ({} content bytes) from {}",
+                data.name,
+                data.content.len(),
+                from
+            );
         }
+        Ok((target, Reply::Nack(reason))) => match NackReason::from_u8(reason) {
+            Some(reason) => anyhow::bail!("received Nack ({reason:?}) for '{name}' from {target}"),
+            None => anyhow::bail!("received Nack (reason {reason}) for '{name}' from {target}"),
+        },
         Err(e) => {
-            warn!("Failed to receive Data response: {}", e);
+            return Err(e.context("failed to receive Data response"));
         }
     }
     
     Ok(())
 }
 
-async fn serve_data(name: String, content: String, bind: String) -> anyhow::Result<()> {
-    let socket = UdpSocket::bind(&bind)?;
-    info!("Serving content for '{}' on {}", name, bind);
-    
-    let mut buf = [0u8; 1024];
-    
-    loop {
-        match socket.recv_from(&mut buf) {
-            Ok((len, addr)) => {
-                if let Some(interest) = udcn_common::parse_interest_packet(&buf[..len]) {
-                    let expected_hash = hash_name(name.as_bytes());
-                    if interest.name_hash == expected_hash {
-                        let signature = rand::random::<u32>();
-                        let data_packet = serialize_data(&name, content.as_bytes(), signature);
-                        
-                        if let Err(e) = socket.send_to(&data_packet, addr) {
-                            warn!("Failed to send Data response: {}", e);
-                        } else {
-                            info!("Sent Data response for '{}' to {}", name, addr);
-                        }
-                    }
+async fn get_content(
+    name: Name,
+    faces: &FaceTable,
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let name = match name.version() {
+        Some(_) => name,
+        None => {
+            let latest = udcn_client::fetch::discover_latest(faces, &name).await?;
+            info!("Latest version of '{name}' is '{latest}'");
+            latest
+        }
+    };
+    register_name(&name).await;
+
+    let mut content = Vec::new();
+    fetch_checked(faces, &name, 0, |_, segment| {
+        content.extend_from_slice(segment);
+        Ok(())
+    })
+    .await?;
+    info!("Fetched {} bytes of '{name}'", content.len());
+    match output {
+        Some(path) => std::fs::write(&path, &content)
+            .with_context(|| format!("failed to write {}", path.display()))?,
+        None => std::io::Write::write_all(&mut std::io::stdout(), &content)?,
+    }
+    Ok(())
+}
+
+/// Fetch `name` into `output` like `get_content`, carrying on from the segments an earlier fetch
+/// left there.
+async fn resume_content(name: Name, faces: &FaceTable, output: &Path) -> anyhow::Result<()> {
+    let mut resume = resume::Resume::open(output)?;
+    let name = match resume.name() {
+        // Carry on with the version being fetched, even if a newer one is out by now
+        Some(fetching) if name.is_prefix_of(fetching) => fetching.clone(),
+        _ if name.version().is_some() => name,
+        _ => {
+            let latest = udcn_client::fetch::discover_latest(faces, &name).await?;
+            info!("Latest version of '{name}' is '{latest}'");
+            latest
+        }
+    };
+    register_name(&name).await;
+    resume.start(&name)?;
+
+    let (first, bytes) = resume.position();
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(output)
+        .with_context(|| format!("failed to open {}", output.display()))?;
+    // Whatever was written after the last segment recorded is written again
+    file.set_len(bytes)?;
+    file.seek(SeekFrom::End(0))?;
+    if first > 0 {
+        info!("Resuming '{name}' from segment {first} ({bytes} bytes received)");
+    }
+    let segments = fetch_checked(faces, &name, first, |segment, content| {
+        file.write_all(content)
+            .with_context(|| format!("failed to write {}", output.display()))?;
+        resume.record(segment, content.len())
+    })
+    .await?;
+    info!("Fetched {segments} segment(s) of '{name}' into {}", output.display());
+    resume.finish()
+}
+
+/// Fetch the segments of `name` from `first` on, checking each against the manifest if the
+/// producer published one, or else its signature if that needs no key. A segment failing its
+/// check is purged from the local daemon's content store and fetched again. Returns the number
+/// of segments.
+async fn fetch_checked(
+    faces: &FaceTable,
+    name: &Name,
+    first: u64,
+    received: impl FnMut(u64, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    let guard = PoisonGuard::default();
+    let segments = match Manifest::fetch_guarded(faces, &guard, name, check_signature).await? {
+        Some(manifest) => {
+            info!("Checking {} segment(s) of '{name}' against its manifest", manifest.segments());
+            manifest.fetch_guarded_segments_from(faces, &guard, first, received).await?
+        }
+        None => {
+            let check = |data: &udcn_common::Data| match data.signature_info {
+                // Other signatures need keys `get` is not given
+                Some(ref info) if info.signature_type != udcn_common::SIGNATURE_DIGEST_SHA256 => {
+                    Ok(())
                 }
+                _ => check_signature(data),
+            };
+            let fetch = udcn_client::fetch::fetch_guarded_segments_from;
+            fetch(faces, &guard, name, first, check, received).await?
+        }
+    };
+    let stats = guard.stats();
+    if stats.poisoned > 0 {
+        warn!(
+            "Refetched {} poisoned packet(s) of '{name}', {} purged from the content store",
+            stats.poisoned, stats.poisoned_purged
+        );
+    }
+    Ok(segments)
+}
+
+/// Check a signature that needs no key, such as the DigestSha256 `serve` signs with.
+fn check_signature(data: &udcn_common::Data) -> anyhow::Result<()> {
+    let signature_type = data
+        .signature_info
+        .as_ref()
+        .map_or(udcn_common::SIGNATURE_DIGEST_SHA256, |info| info.signature_type);
+    anyhow::ensure!(
+        Sha256Verifier.verify(signature_type, None, &data.signed_portion(), &data.signature_value),
+        "signature type {signature_type} does not check out"
+    );
+    Ok(())
+}
+
+async fn trace_fetch(name: Name, target: SocketAddr) -> anyhow::Result<()> {
+    register_name(&name).await;
+    let interest = Interest::builder().name(name.clone()).build();
+    let trace_id = lp::random_trace_id();
+    println!("Tracing '{name}' via {target} (trace {trace_id:016x})");
+
+    let trace = udcn_client::consumer::trace(target, &interest, trace_id).await?;
+    for (hop, (elapsed, report)) in trace.hops.iter().enumerate() {
+        let packet_type = match report.packet_type {
+            t if t == TlvType::Interest as u8 => "interest",
+            t if t == TlvType::Data as u8 => "data",
+            t if t == TlvType::Nack as u8 => "nack",
+            _ => "unknown",
+        };
+        println!(
+            "{:>3}  {:<24} {:<8} {:>10.3} ms",
+            hop + 1,
+            report.node,
+            packet_type,
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
+    match trace.reply {
+        Some(Reply::Data(data)) => {
+            println!("Data for '{}' ({} content bytes)", data.name, data.content.len());
+        }
+        Some(Reply::Nack(reason)) => match NackReason::from_u8(reason) {
+            Some(reason) => println!("Nack ({reason:?})"),
+            None => println!("Nack (reason {reason})"),
+        },
+        None => anyhow::bail!("no reply for '{name}' within the Interest lifetime"),
+    }
+    Ok(())
+}
+
+async fn serve_data(producer: &Producer, filters: InterestFilters, bind: String) -> anyhow::Result<()> {
+    for filter in filters.iter() {
+        info!("Serving content for '{}' on {}", filter.prefix(), bind);
+        register_name(filter.prefix()).await;
+    }
+    
+    serve_until_interrupted(producer, producer.serve_filters(filters)).await
+}
+
+async fn serve_store(
+    producer: &Producer,
+    name: &Name,
+    store: &SegmentStore,
+    bind: String,
+) -> anyhow::Result<()> {
+    info!("Serving {} segment(s) of '{}' on {}", store.len(), name, bind);
+    register_name(name).await;
+
+    let result = serve_until_interrupted(producer, producer.serve_store(store)).await;
+    report_compression(store);
+    result
+}
+
+async fn serve_disk_store(
+    producer: &Producer,
+    name: &Name,
+    store: Arc<DiskSegmentStore>,
+    bind: String,
+) -> anyhow::Result<()> {
+    info!("Serving {} segment(s) of '{}' from disk on {}", store.len(), name, bind);
+    register_name(name).await;
+
+    serve_until_interrupted(producer, producer.serve_disk_store(store)).await
+}
+
+/// The length of the content to publish and a reader for it, from `file` or else `content`.
+async fn content_source<'a>(
+    file: Option<&std::path::Path>,
+    content: Option<&'a str>,
+) -> anyhow::Result<Option<(u64, Box<dyn tokio::io::AsyncRead + Unpin + 'a>)>> {
+    Ok(match (file, content) {
+        (Some(path), _) => {
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            Some((file.metadata().await?.len(), Box::new(file)))
+        }
+        (None, Some(content)) => Some((content.len() as u64, Box::new(content.as_bytes()))),
+        (None, None) => None,
+    })
+}
+
+async fn serve_versions(
+    producer: &Producer,
+    name: &Name,
+    store: &std::sync::RwLock<SegmentStore>,
+    mut publisher: repo::Publisher<impl FnMut() -> anyhow::Result<u64>>,
+    file: Option<&std::path::Path>,
+    bind: String,
+) -> anyhow::Result<()> {
+    info!("Serving the latest version of '{}' on {}", name, bind);
+    register_name(name).await;
+
+    let serve = async {
+        match file {
+            Some(file) => tokio::select! {
+                result = producer.serve_shared_store(store) => result,
+                result = publisher.watch(file, store) => result,
+            },
+            None => producer.serve_shared_store(store).await,
+        }
+    };
+    let result = serve_until_interrupted(producer, serve).await;
+    report_compression(&store.read().unwrap());
+    result
+}
+
+fn report_compression(store: &SegmentStore) {
+    if let Some(compression) = store.compression() {
+        info!("Compression ({}): {}", compression.codec, store.compression_stats());
+    }
+}
+
+/// Run `serve` until Ctrl-C, then report each face's MTU and the replies that exceeded it.
+async fn serve_until_interrupted(
+    producer: &Producer,
+    serve: impl std::future::Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    tokio::select! {
+        result = serve => result,
+        result = signal::ctrl_c() => {
+            result?;
+            for (addr, counters) in producer.face_counters() {
+                let mtu = counters.mtu.map_or("unknown".to_string(), |mtu| mtu.to_string());
+                info!(
+                    "Face {addr}: MTU {mtu}, {} fragmented_out, {} oversized_dropped",
+                    counters.fragmented_out, counters.oversized_dropped
+                );
+            }
+            for (addr, counters) in producer.queue_counters() {
+                info!(
+                    "Face {addr}: {} congestion_marked, {} dropped, {} send_errors",
+                    counters.congestion_marked, counters.dropped, counters.send_errors
+                );
+            }
+            for (addr, stats) in producer.client_stats() {
+                let mean_wait_ms = stats.waited.as_secs_f64() * 1000.0 / stats.served.max(1) as f64;
+                info!(
+                    "Client {addr}: {} served, {} throttled, {mean_wait_ms:.1} ms mean wait",
+                    stats.served, stats.throttled
+                );
             }
-            Err(e) => {
-                warn!("Failed to receive packet: {}", e);
+            for (prefix, unauthorized) in producer.unauthorized() {
+                info!("Gate '{prefix}': {unauthorized} Interests dropped without a valid token");
             }
+            Ok(())
         }
     }
 }
 
-async fn show_stats() -> anyhow::Result<()> {
-    bump_memlock_rlimit()?;
-    
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-        env!("OUT_DIR"),
-        "/udcn"
-    )))?;
-    
-    let stats_map: Array<_, PacketStats> = Array::try_from(ebpf.take_map("STATS").unwrap())?;
-    
-    if let Ok(stats) = stats_map.get(&0, 0) {
-        print_stats(&stats);
+async fn show_stats(
+    json: bool,
+    save: Option<PathBuf>,
+    diff: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let stats = udcn_client::control::stats().await?;
+    let tables = udcn_client::control::tables().await?;
+    let snapshot = snapshot::Snapshot::now(stats, tables);
+    if let Some(path) = diff {
+        let old = snapshot::load(&path)?;
+        print!("{}", snapshot::format_diff(&old, &serde_json::to_value(&snapshot)?));
+    } else if json {
+        // The time taken is only kept in saved snapshots
+        let report = snapshot::Snapshot {
+            taken_at_unix_ms: None,
+            ..snapshot
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return save_snapshot(report, save);
     } else {
-        println!("No statistics available");
+        print_stats(&snapshot.stats, &snapshot.tables);
+    }
+    save_snapshot(snapshot, save)
+}
+
+fn save_snapshot(snapshot: snapshot::Snapshot, path: Option<PathBuf>) -> anyhow::Result<()> {
+    if let Some(path) = path {
+        snapshot.save(&path)?;
+        info!("Saved the counters to {}", path.display());
     }
-    
     Ok(())
 }
 
-fn print_stats(stats: &PacketStats) {
-    println!("µDCN Statistics:");
-    println!("================");
-    println!("Interest packets received: {}", stats.interest_received);
-    println!("Data packets received:     {}", stats.data_received);
-    println!("Cache hits:                {}", stats.cache_hits);
-    println!("Cache misses:              {}", stats.cache_misses);
-    println!("PIT hits:                  {}", stats.pit_hits);
-    println!("Forwards:                  {}", stats.forwards);
-    println!("Drops:                     {}", stats.drops);
+/// Tell a running daemon about `name` so inspection output can show it instead of its hash.
+async fn register_name(name: &Name) {
+    if let Err(e) = udcn_client::control::register(name).await {
+        debug!("could not register '{name}' with the daemon: {e:#}");
+    }
+}
+
+fn print_stats(stats: &PacketStats, tables: &[TableOccupancy]) {
+    print!("{}", format_stats(stats, tables));
+}
+
+fn format_stats(stats: &PacketStats, tables: &[TableOccupancy]) -> String {
+    let mut out = String::new();
+    out += "µDCN Statistics:\n";
+    out += "================\n";
+    out += &format!("Interest packets received: {}\n", stats.interest_received);
+    out += &format!("Data packets received:     {}\n", stats.data_received);
+    out += &format!("Cache hits:                {}\n", stats.cache_hits);
+    out += &format!("  fresh hits:              {}\n", stats.fresh_hits);
+    out += &format!("  stale hits:              {}\n", stats.stale_hits);
+    out += &format!("  prefetched:              {}\n", stats.prefetch_hits);
+    if stats.cs_probation_hits != 0 || stats.cs_protected_hits != 0 {
+        out += &format!("  on probation:            {}\n", stats.cs_probation_hits);
+        out += &format!("  protected:               {}\n", stats.cs_protected_hits);
+    }
+    out += &format!("Cache misses:              {}\n", stats.cache_misses);
+    out += &format!("PIT hits:                  {}\n", stats.pit_hits);
+    out += &format!("Forwards:                  {}\n", stats.forwards);
+    out += &format!("Drops:                     {}\n", stats.total_drops());
+    for reason in DropReason::ALL {
+        let label = format!("  {}:", reason.as_str());
+        out += &format!("{label:<27}{}\n", stats.drops[reason as usize]);
+    }
+    out += &format!("Negative cache hits:       {}\n", stats.negative_cache_hits);
+    if stats.unroutable_nacks != 0 {
+        out += &format!("Unroutable Nacks:          {}\n", stats.unroutable_nacks);
+    }
+    out += &format!("Duplicate interests:       {}\n", stats.duplicate_interests);
+    out += &format!("CS admission rejects:      {}\n", stats.cs_admission_rejects);
+    out += &format!("Uncacheable Data:          {}\n", stats.uncacheable);
     
     let total_interests = stats.cache_hits + stats.cache_misses;
     if total_interests > 0 {
         let hit_ratio = (stats.cache_hits as f64 / total_interests as f64) * 100.0;
-        println!("Cache hit ratio:           {:.2}%", hit_ratio);
+        out += &format!("Cache hit ratio:           {:.2}%\n", hit_ratio);
+    }
+
+    out += "\nTable occupancy:\n";
+    for table in tables {
+        let used = table.entries as f64 * 100.0 / table.capacity.max(1) as f64;
+        out += &format!(
+            "{:<26} {:>5}/{:<5} ({:.1}%)\n",
+            format!("{}:", table.table),
+            table.entries,
+            table.capacity,
+            used
+        );
     }
+    out
 }
 
 fn bump_memlock_rlimit() -> anyhow::Result<()> {
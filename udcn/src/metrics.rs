@@ -0,0 +1,304 @@
+//! Prometheus exporter: the datapath counters, table occupancy and the daemon's own faces in the
+//! text exposition format, served over plain HTTP on every path.
+
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use log::{debug, info};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use udcn_client::control::{FaceStatus, OperationTiming, TableOccupancy};
+use udcn_common::{Name, PacketStats};
+
+use crate::daemon::Daemon;
+
+/// Answer scrapes on `addr` until the listener fails.
+pub async fn serve(addr: SocketAddr, daemon: Arc<Mutex<Daemon>>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving Prometheus metrics on http://{addr}/metrics");
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_scrape(stream, daemon).await {
+                debug!("metrics scrape from {peer} failed: {e:#}");
+            }
+        });
+    }
+}
+
+async fn answer_scrape(mut stream: TcpStream, daemon: Arc<Mutex<Daemon>>) -> anyhow::Result<()> {
+    // The request itself does not matter; read it so the client sees an orderly response
+    let mut request = [0u8; 1024];
+    let _ = stream.read(&mut request).await?;
+
+    let body = {
+        let daemon = daemon.lock().unwrap();
+        let mut body = render(
+            &daemon.stats()?,
+            &daemon.tables()?,
+            &daemon.timings(),
+            &daemon.faces(),
+        )?;
+        render_gates(&mut body, &daemon.gates().unauthorized())?;
+        body
+    };
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Append the Interests each gated prefix turned away, if any prefix is gated.
+fn render_gates(out: &mut String, unauthorized: &[(Name, u64)]) -> std::fmt::Result {
+    if unauthorized.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "# HELP udcn_gate_unauthorized_total Gated Interests dropped without a valid token."
+    )?;
+    writeln!(out, "# TYPE udcn_gate_unauthorized_total counter")?;
+    for (prefix, dropped) in unauthorized {
+        writeln!(
+            out,
+            "udcn_gate_unauthorized_total{{prefix=\"{prefix}\"}} {dropped}"
+        )?;
+    }
+    Ok(())
+}
+
+/// Name, type and help text of a per-face metric, and how to read it off a face.
+type FaceMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&FaceStatus) -> f64,
+);
+
+/// The metrics page: one `udcn_<counter>_total` per datapath counter (per reason for drops), then
+/// the entries and capacity of each table, then what managing them has cost, then how sending on
+/// each of the daemon's own faces goes.
+pub fn render(
+    stats: &PacketStats,
+    tables: &[TableOccupancy],
+    timings: &[OperationTiming],
+    faces: &[FaceStatus],
+) -> anyhow::Result<String> {
+    let mut out = String::new();
+    let serde_json::Value::Object(counters) = serde_json::to_value(stats)? else {
+        anyhow::bail!("datapath counters are not a struct");
+    };
+    for (counter, value) in counters {
+        writeln!(out, "# TYPE udcn_{counter}_total counter")?;
+        match value {
+            // Counters broken down by reason, like drops
+            serde_json::Value::Object(by_reason) => {
+                for (reason, value) in by_reason {
+                    writeln!(out, "udcn_{counter}_total{{reason=\"{reason}\"}} {value}")?;
+                }
+            }
+            value => writeln!(out, "udcn_{counter}_total {value}")?,
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP udcn_table_entries Entries currently in a datapath table."
+    )?;
+    writeln!(out, "# TYPE udcn_table_entries gauge")?;
+    for table in tables {
+        writeln!(
+            out,
+            "udcn_table_entries{{table=\"{}\"}} {}",
+            table.table, table.entries
+        )?;
+    }
+    writeln!(
+        out,
+        "# HELP udcn_table_capacity Entries a datapath table can hold."
+    )?;
+    writeln!(out, "# TYPE udcn_table_capacity gauge")?;
+    for table in tables {
+        writeln!(
+            out,
+            "udcn_table_capacity{{table=\"{}\"}} {}",
+            table.table, table.capacity
+        )?;
+    }
+    writeln!(
+        out,
+        "# HELP udcn_management_seconds_total Time the daemon spent managing datapath tables."
+    )?;
+    writeln!(out, "# TYPE udcn_management_seconds_total counter")?;
+    for timing in timings {
+        writeln!(
+            out,
+            "udcn_management_seconds_total{{operation=\"{}\"}} {}",
+            timing.operation,
+            timing.total_us as f64 / 1e6
+        )?;
+    }
+    writeln!(
+        out,
+        "# HELP udcn_management_entries_total Table entries the daemon read or wrote."
+    )?;
+    writeln!(out, "# TYPE udcn_management_entries_total counter")?;
+    for timing in timings {
+        writeln!(
+            out,
+            "udcn_management_entries_total{{operation=\"{}\"}} {}",
+            timing.operation, timing.entries
+        )?;
+    }
+
+    let face_metrics: [FaceMetric; 8] = [
+        (
+            "face_send_queue_bytes",
+            "gauge",
+            "Bytes waiting in the send buffer of a face's socket.",
+            |face| face.send_queue_bytes as f64,
+        ),
+        (
+            "face_send_buffer_bytes",
+            "gauge",
+            "Bytes the send buffer of a face's socket holds.",
+            |face| face.send_buffer_bytes as f64,
+        ),
+        (
+            "face_would_block_drops_total",
+            "counter",
+            "Packets dropped as a face's send buffer was full.",
+            |face| face.would_block_drops as f64,
+        ),
+        (
+            "face_send_errors_total",
+            "counter",
+            "Packets a face failed to send for other reasons.",
+            |face| face.send_errors as f64,
+        ),
+        (
+            "face_suppressed_duplicates_total",
+            "counter",
+            "Copies of Data a face's multicast sends saved.",
+            |face| face.suppressed_duplicates as f64,
+        ),
+        (
+            "face_queuing_delay_seconds",
+            "gauge",
+            "Smoothed time from a packet arriving at a face to its socket taking it.",
+            |face| face.queuing_delay_us as f64 / 1e6,
+        ),
+        (
+            "face_shaped_bytes_total",
+            "counter",
+            "Bytes held back to keep within a face's egress rate.",
+            |face| face.shaped_bytes as f64,
+        ),
+        (
+            "face_shaping_dropped_bytes_total",
+            "counter",
+            "Bytes dropped as a face's shaping queue was full.",
+            |face| face.shaping_dropped_bytes as f64,
+        ),
+    ];
+    for (metric, kind, help, value) in face_metrics {
+        writeln!(out, "# HELP udcn_{metric} {help}")?;
+        writeln!(out, "# TYPE udcn_{metric} {kind}")?;
+        for face in faces {
+            writeln!(
+                out,
+                "udcn_{metric}{{face=\"{}\"}} {}",
+                face.face,
+                value(face)
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "# HELP udcn_face_nacks_total Nacks received on a face, by reason."
+    )?;
+    writeln!(out, "# TYPE udcn_face_nacks_total counter")?;
+    for face in faces {
+        for (reason, count) in &face.nacks {
+            writeln!(
+                out,
+                "udcn_face_nacks_total{{face=\"{}\",reason=\"{reason}\"}} {count}",
+                face.face
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_tables() {
+        let stats: PacketStats = serde_json::from_value(serde_json::json!({
+            "interest_received": 7, "data_received": 3, "cache_hits": 1, "cache_misses": 0,
+            "pit_hits": 3, "forwards": 10, "drops": { "pit_full": 12 }, "negative_cache_hits": 0,
+            "duplicate_interests": 0, "cs_admission_rejects": 2, "fresh_hits": 1, "stale_hits": 0,
+            "uncacheable": 0, "prefetch_hits": 0,
+            "cs_probation_hits": 0, "cs_protected_hits": 0, "unroutable_nacks": 0
+        }))
+        .unwrap();
+        let tables = [TableOccupancy {
+            table: "PIT".to_string(),
+            entries: 4,
+            capacity: 1024,
+        }];
+
+        let timings = [OperationTiming {
+            operation: "pit_gc".to_string(),
+            calls: 4,
+            entries: 40,
+            batched: true,
+            last_us: 500_000,
+            total_us: 1_500_000,
+        }];
+
+        let faces = [FaceStatus {
+            face: "tunnel site-b:6363".to_string(),
+            would_block_drops: 3,
+            suppressed_duplicates: 4,
+            queuing_delay_us: 250,
+            nacks: [("NoRoute".to_string(), 2)].into(),
+            shaped_bytes: 1200,
+            ..FaceStatus::default()
+        }];
+
+        let page = render(&stats, &tables, &timings, &faces).unwrap();
+        assert!(page.contains(
+            "# TYPE udcn_interest_received_total counter\nudcn_interest_received_total 7\n"
+        ));
+        assert!(page.contains("udcn_cs_admission_rejects_total 2\n"));
+        assert!(page.contains("udcn_fresh_hits_total 1\n"));
+        assert!(page.contains("udcn_drops_total{reason=\"pit_full\"} 12\n"));
+        assert!(page.contains("udcn_drops_total{reason=\"malformed\"} 0\n"));
+        assert!(page.contains("udcn_table_entries{table=\"PIT\"} 4\n"));
+        assert!(page.contains("udcn_table_capacity{table=\"PIT\"} 1024\n"));
+        assert!(page.contains("udcn_management_seconds_total{operation=\"pit_gc\"} 1.5\n"));
+        assert!(page.contains("udcn_management_entries_total{operation=\"pit_gc\"} 40\n"));
+        assert!(page.contains("udcn_face_would_block_drops_total{face=\"tunnel site-b:6363\"} 3\n"));
+        assert!(
+            page.contains("udcn_face_suppressed_duplicates_total{face=\"tunnel site-b:6363\"} 4\n")
+        );
+        assert!(
+            page.contains("udcn_face_queuing_delay_seconds{face=\"tunnel site-b:6363\"} 0.00025\n")
+        );
+        assert!(page.contains("udcn_face_shaped_bytes_total{face=\"tunnel site-b:6363\"} 1200\n"));
+        assert!(page
+            .contains("udcn_face_nacks_total{face=\"tunnel site-b:6363\",reason=\"NoRoute\"} 2\n"));
+    }
+}
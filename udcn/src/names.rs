@@ -0,0 +1,140 @@
+//! Reverse mapping from the name hashes used by the datapath back to the names they came from.
+//!
+//! Names are learnt from control-socket registrations, the Data the daemon caches itself, the
+//! Interests it sends upstream for tunnels and prefetches, and packets captured whole.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write as _},
+    path::PathBuf,
+};
+
+use anyhow::Context as _;
+use log::warn;
+use udcn_common::Name;
+
+pub const DEFAULT_NAMES_PATH: &str = "/var/lib/udcn/names";
+
+/// Names seen by the daemon, persisted one canonical URI per line so they survive restarts.
+pub struct NameTable {
+    path: PathBuf,
+    names: HashMap<u32, String>,
+}
+
+impl NameTable {
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let mut names = HashMap::new();
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.is_empty()) {
+                    match Name::from_uri(line) {
+                        Ok(name) => {
+                            names.insert(name.name_hash(), name.to_string());
+                        }
+                        Err(e) => {
+                            warn!("ignoring invalid name {line:?} in {}: {e}", path.display())
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", path.display()))
+            }
+        }
+
+        Ok(Self { path, names })
+    }
+
+    /// Remember `name` and return its hash. New names are appended to the table file.
+    pub fn insert(&mut self, name: &Name) -> u32 {
+        let name_hash = name.name_hash();
+        let uri = name.to_string();
+        if self.names.insert(name_hash, uri.clone()).is_none() {
+            if let Err(e) = self.persist(&uri) {
+                warn!("failed to persist name '{}' to {}: {e}", uri, self.path.display());
+            }
+        }
+        name_hash
+    }
+
+    pub fn resolve(&self, name_hash: u32) -> Option<&str> {
+        self.names.get(&name_hash).map(String::as_str)
+    }
+
+    /// The name for `name_hash`, or the bare hash when it was never registered.
+    pub fn display(&self, name_hash: u32) -> String {
+        match self.resolve(name_hash) {
+            Some(name) => name.to_string(),
+            None => format!("<{name_hash:#010x}>"),
+        }
+    }
+
+    /// Every name as a URI, in no particular order.
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.names.values().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn persist(&self, name: &str) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{name}")
+    }
+}
+
+/// The `k` name hashes with the highest counts, highest first, ties broken by hash.
+pub fn top(counts: &HashMap<u32, u64>, k: usize) -> Vec<(u32, u64)> {
+    let mut top: Vec<(u32, u64)> = counts
+        .iter()
+        .map(|(&name_hash, &count)| (name_hash, count))
+        .collect();
+    top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    top.truncate(k);
+    top
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_survive_reload() {
+        let path = std::env::temp_dir().join(format!("udcn-names-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut table = NameTable::load(&path).unwrap();
+        let name_hash = table.insert(&Name::from("/test/data"));
+        table.insert(&Name::from("/test/data"));
+        assert_eq!(table.resolve(name_hash), Some("/test/data"));
+
+        let reloaded = NameTable::load(&path).unwrap();
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded.display(name_hash), "/test/data");
+        assert_eq!(reloaded.display(0x1234), "<0x00001234>");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stores_canonical_uris() {
+        let mut table = NameTable::load("/nonexistent/udcn-names").unwrap();
+        let name_hash = table.insert(&Name::from("ndn:/a/b c\n"));
+        assert_eq!(table.resolve(name_hash), Some("/a/b%20c%0A"));
+    }
+
+    #[test]
+    fn test_top() {
+        let counts = HashMap::from([(1, 5), (2, 9), (3, 5), (4, 1)]);
+        assert_eq!(top(&counts, 3), vec![(2, 9), (1, 5), (3, 5)]);
+        assert_eq!(top(&counts, 10).len(), 4);
+    }
+}
@@ -0,0 +1,149 @@
+//! Network namespace plumbing shared by `selftest` and `topo`, driven through the `ip` command.
+
+use std::{
+    ffi::OsStr,
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use log::warn;
+use tokio::time::sleep;
+use udcn_client::control;
+
+const DAEMON_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Network namespaces created for one run, deleted again on drop.
+pub struct Namespaces {
+    names: Vec<String>,
+    verbose: bool,
+}
+
+impl Namespaces {
+    /// Start with no namespaces, first deleting any starting with `prefix` that an interrupted
+    /// run left behind. Unless `verbose`, processes started in them are silenced.
+    pub fn new(prefix: &str, verbose: bool) -> Self {
+        if let Ok(output) = Command::new("ip").args(["netns", "list"]).output() {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                let namespace = line.split_whitespace().next().unwrap_or_default();
+                if namespace.starts_with(prefix) {
+                    let _ = ip(&format!("netns del {namespace}"));
+                }
+            }
+        }
+        Self {
+            names: Vec::new(),
+            verbose,
+        }
+    }
+
+    pub fn add(&mut self, namespace: &str) -> anyhow::Result<()> {
+        ip(&format!("netns add {namespace}"))?;
+        self.names.push(namespace.to_string());
+        ip(&format!("-n {namespace} link set lo up"))
+    }
+
+    /// A command running `udcn <args>` inside `namespace`.
+    pub fn udcn<I, S>(&self, namespace: &str, args: I) -> anyhow::Result<Command>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new("ip");
+        command
+            .args(["netns", "exec", namespace])
+            .arg(std::env::current_exe()?)
+            .args(args);
+        if !self.verbose {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        Ok(command)
+    }
+
+    /// Start `udcn <args>` inside `namespace`.
+    pub fn spawn<I, S>(&self, namespace: &str, args: I) -> anyhow::Result<Process>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Process::spawn(&mut self.udcn(namespace, args)?)
+    }
+
+    /// Run `udcn <args>` inside `namespace` to completion.
+    pub fn exec<I, S>(&self, namespace: &str, args: I) -> anyhow::Result<ExitStatus>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        Ok(self.udcn(namespace, args)?.status()?)
+    }
+}
+
+impl Drop for Namespaces {
+    fn drop(&mut self) {
+        for namespace in self.names.drain(..).rev() {
+            if let Err(e) = ip(&format!("netns del {namespace}")) {
+                warn!("failed to delete network namespace {namespace}: {e:#}");
+            }
+        }
+    }
+}
+
+/// A child process that is interrupted, like Ctrl-C would, when dropped.
+pub struct Process(Child);
+
+impl Process {
+    pub fn spawn(command: &mut Command) -> anyhow::Result<Self> {
+        let child = command
+            .spawn()
+            .with_context(|| format!("failed to run {command:?}"))?;
+        Ok(Self(child))
+    }
+
+    /// Wait until the daemon this process runs answers on the control socket at `socket`.
+    pub async fn wait_for_daemon(&mut self, socket: &Path) -> anyhow::Result<()> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.0.try_wait()? {
+                anyhow::bail!("daemon exited with {status}; rerun with --verbose");
+            }
+            match control::stats_at(socket).await {
+                Ok(_) => return Ok(()),
+                Err(e) if started.elapsed() > DAEMON_STARTUP_TIMEOUT => {
+                    return Err(e.context("daemon did not come up"))
+                }
+                Err(_) => sleep(Duration::from_millis(100)).await,
+            }
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // `ip netns exec` execs the command, so the child is udcn itself
+        if let Ok(None) = self.0.try_wait() {
+            unsafe { libc::kill(self.0.id() as libc::pid_t, libc::SIGINT) };
+        }
+        if let Err(e) = self.0.wait() {
+            warn!("failed to wait for process {}: {e}", self.0.id());
+        }
+    }
+}
+
+/// Run `ip` with the whitespace-separated `args`.
+pub fn ip(args: &str) -> anyhow::Result<()> {
+    run(Command::new("ip").args(args.split_whitespace()))
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let output = command
+        .output()
+        .with_context(|| format!("failed to run {command:?}"))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{command:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim_end()
+    );
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Namespaces kept out of the content store, for private or rapidly changing content such as
+//! `/telemetry/live`.
+//!
+//! The datapath hashes the component elements of a Data name one after the other and looks up
+//! the running hash after each, so a prefix matches at any length up to
+//! `MAX_NO_CACHE_PREFIX_COMPONENTS`. Producers can also keep single packets out of every
+//! forwarder's store by giving them the `CONTENT_TYPE_NO_CACHE` ContentType.
+
+use std::str::FromStr;
+
+use aya::maps::HashMap;
+use udcn_common::{
+    no_cache_key, tlv, Name, MAX_NO_CACHE_COMPONENT_LEN, MAX_NO_CACHE_PREFIXES,
+    MAX_NO_CACHE_PREFIX_COMPONENTS,
+};
+
+/// A prefix whose Data is never cached.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoCachePrefix(pub Name);
+
+impl FromStr for NoCachePrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::new(Name::from_uri(s)?)
+    }
+}
+
+impl NoCachePrefix {
+    /// `prefix`, if the datapath can match names against it.
+    pub fn new(prefix: Name) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            (1..=MAX_NO_CACHE_PREFIX_COMPONENTS).contains(&prefix.len()),
+            "'{prefix}' must have between 1 and {MAX_NO_CACHE_PREFIX_COMPONENTS} components"
+        );
+        for component in prefix.components() {
+            // The datapath reads component types and lengths as single bytes
+            anyhow::ensure!(
+                component.typ < 253 && component.value.len() <= MAX_NO_CACHE_COMPONENT_LEN,
+                "components of '{prefix}' must be at most {MAX_NO_CACHE_COMPONENT_LEN} bytes"
+            );
+        }
+        Ok(Self(prefix))
+    }
+
+    /// The prefix's key in `NO_CACHE_PREFIXES`.
+    pub fn key(&self) -> u32 {
+        let mut encoded = Vec::new();
+        for component in self.0.components() {
+            tlv::write_element(&mut encoded, component.typ, &component.value);
+        }
+        no_cache_key(&encoded)
+    }
+}
+
+/// Tell the datapath which prefixes to keep out of the content store.
+pub fn install(ebpf: &mut aya::Ebpf, prefixes: &[NoCachePrefix]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        prefixes.len() <= MAX_NO_CACHE_PREFIXES as usize,
+        "at most {MAX_NO_CACHE_PREFIXES} prefixes can be kept out of the content store"
+    );
+    let mut map: HashMap<_, u32, u8> =
+        HashMap::try_from(ebpf.map_mut("NO_CACHE_PREFIXES").unwrap())?;
+    for prefix in prefixes {
+        map.insert(prefix.key(), 1, 0)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::hash_name;
+
+    use super::*;
+
+    #[test]
+    fn test_prefix_keys_hash_encoded_components() {
+        let prefix: NoCachePrefix = "/telemetry/live".parse().unwrap();
+        let mut expected = vec![0x08, 9];
+        expected.extend_from_slice(b"telemetry");
+        expected.extend_from_slice(&[0x08, 4]);
+        expected.extend_from_slice(b"live");
+        assert_eq!(prefix.key(), hash_name(&expected));
+        assert_ne!(
+            prefix.key(),
+            "/telemetrylive".parse::<NoCachePrefix>().unwrap().key()
+        );
+
+        assert!("/".parse::<NoCachePrefix>().is_err());
+        assert!("/a/b/c/d/e".parse::<NoCachePrefix>().is_err());
+        assert!(format!("/{}", "x".repeat(33))
+            .parse::<NoCachePrefix>()
+            .is_err());
+    }
+}
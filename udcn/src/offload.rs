@@ -0,0 +1,346 @@
+//! Hardware-offloaded XDP (`run --xdp-mode offload`) on SmartNICs with BPF offload, such as
+//! Netronome/Corigine NFP cards.
+//!
+//! A NIC only runs a subset of BPF: array and hash maps, no ring buffers, LRU maps or tail calls,
+//! and few helpers. So the datapath is split. The `udcn-classify` program runs on the NIC, where
+//! it tells NDN packets apart by type and counts them, and passes everything to the full program
+//! in the driver. aya loads programs for the host only, so the classifier and its maps are bound
+//! to the NIC through the bpf syscall directly, then attached with `ip link ... xdpoffload`.
+//! Interfaces that cannot take it run the whole datapath in the driver, with the reason reported
+//! at startup and by `udcn offload`.
+
+use std::{
+    ffi::CString,
+    fmt::Write as _,
+    fs, io,
+    os::fd::{AsFd as _, AsRawFd as _, FromRawFd as _, OwnedFd},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use aya_obj::generated::bpf_insn;
+use log::{info, warn};
+use udcn_common::OffloadClass;
+
+use crate::{attach, health, netns::ip};
+
+static CLASSIFIER: &[u8] = aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/udcn-classify"));
+
+/// Drivers that implement BPF offload; netdevsim is the kernel's simulated one, for testing.
+const OFFLOAD_DRIVERS: [&str; 2] = ["nfp", "netdevsim"];
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_long = 1;
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+
+/// Verifier output kept when the NIC refuses the classifier.
+const VERIFIER_LOG_SIZE: usize = 64 * 1024;
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+    inner_map_fd: u32,
+    numa_node: u32,
+    map_name: [u8; 16],
+    map_ifindex: u32,
+}
+
+#[repr(C)]
+struct MapLookupAttr {
+    map_fd: u32,
+    reserved: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+fn bpf<T>(command: libc::c_long, attr: &T) -> io::Result<libc::c_long> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            command,
+            attr as *const T,
+            std::mem::size_of::<T>(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// `name` cut to fit a kernel object name.
+fn object_name(name: &str) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for (out, byte) in out.iter_mut().zip(name.bytes().take(15)) {
+        *out = byte;
+    }
+    out
+}
+
+/// The classifier running on one NIC.
+pub struct Offloaded {
+    iface: String,
+    pin: PathBuf,
+    stats: OwnedFd,
+}
+
+impl Offloaded {
+    /// Packets the NIC has seen, by class.
+    pub fn counters(&self) -> io::Result<[u64; OffloadClass::ALL.len()]> {
+        let mut counters = [0u64; OffloadClass::ALL.len()];
+        for (class, counter) in OffloadClass::ALL.into_iter().zip(&mut counters) {
+            let key = class as u32;
+            let attr = MapLookupAttr {
+                map_fd: self.stats.as_raw_fd() as u32,
+                reserved: 0,
+                key: &key as *const u32 as u64,
+                value: counter as *mut u64 as u64,
+                flags: 0,
+            };
+            bpf(BPF_MAP_LOOKUP_ELEM, &attr)?;
+        }
+        Ok(counters)
+    }
+
+    fn detach(&self) {
+        if let Err(e) = ip(&format!("link set dev {} xdpoffload off", self.iface)) {
+            warn!(
+                "failed to detach the offloaded classifier from {}: {e:#}",
+                self.iface
+            );
+        }
+        let _ = fs::remove_file(&self.pin);
+    }
+}
+
+/// Whether `iface`'s driver can run offloaded programs at all.
+fn check_driver(iface: &str) -> anyhow::Result<()> {
+    let driver = health::driver(iface).context("its driver is unknown")?;
+    anyhow::ensure!(
+        OFFLOAD_DRIVERS.contains(&driver.as_str()),
+        "driver {driver} has no BPF offload; only {} do",
+        OFFLOAD_DRIVERS.join(" and ")
+    );
+    Ok(())
+}
+
+/// Load the classifier onto the NIC behind `iface` and attach it, pinned under `pin_dir`.
+pub fn load(iface: &str, ifindex: u32, pin_dir: &Path) -> anyhow::Result<Offloaded> {
+    check_driver(iface)?;
+    let mut obj = aya_obj::Object::parse(CLASSIFIER).context("invalid classifier object")?;
+
+    let definitions = obj.maps.clone();
+    let mut maps = Vec::new();
+    for (name, map) in &definitions {
+        let attr = MapCreateAttr {
+            map_type: map.map_type(),
+            key_size: map.key_size(),
+            value_size: map.value_size(),
+            max_entries: map.max_entries(),
+            map_flags: map.map_flags(),
+            map_name: object_name(name),
+            map_ifindex: ifindex,
+            ..Default::default()
+        };
+        let fd =
+            bpf(BPF_MAP_CREATE, &attr).with_context(|| format!("the NIC refused map {name}"))?;
+        maps.push((name.as_str(), unsafe { OwnedFd::from_raw_fd(fd as i32) }));
+    }
+    let text_sections = obj
+        .functions
+        .keys()
+        .map(|(section_index, _)| *section_index)
+        .collect();
+    obj.relocate_maps(
+        maps.iter()
+            .map(|(name, fd)| (*name, fd.as_raw_fd(), &definitions[*name])),
+        &text_sections,
+    )?;
+    obj.relocate_calls(&text_sections)?;
+
+    let program = obj
+        .programs
+        .get("udcn_classify")
+        .context("no udcn_classify program in the classifier object")?;
+    let function = &obj.functions[&program.function_key()];
+    let fd = load_program(&function.instructions, &program.license, ifindex)?;
+
+    let dir = pin_dir.join("offload");
+    fs::create_dir_all(&dir)?;
+    let pin = dir.join(iface);
+    let _ = fs::remove_file(&pin);
+    attach::pin_program(fd.as_fd(), &pin)
+        .with_context(|| format!("failed to pin the classifier to {}", pin.display()))?;
+    ip(&format!(
+        "link set dev {iface} xdpoffload pinned {}",
+        pin.display()
+    ))?;
+
+    let stats = maps
+        .into_iter()
+        .find(|(name, _)| *name == "OFFLOAD_STATS")
+        .map(|(_, fd)| fd)
+        .context("no OFFLOAD_STATS map in the classifier object")?;
+    Ok(Offloaded {
+        iface: iface.to_string(),
+        pin,
+        stats,
+    })
+}
+
+/// `BPF_PROG_LOAD` for the NIC behind `ifindex`, with the verifier's complaint if it refuses.
+fn load_program(
+    instructions: &[bpf_insn],
+    license: &CString,
+    ifindex: u32,
+) -> anyhow::Result<OwnedFd> {
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: instructions.len() as u32,
+        insns: instructions.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        prog_name: object_name("udcn_classify"),
+        prog_ifindex: ifindex,
+        ..Default::default()
+    };
+    let e = match bpf(BPF_PROG_LOAD, &attr) {
+        Ok(fd) => return Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) }),
+        Err(e) => e,
+    };
+
+    // Load again to find out why
+    let mut log = vec![0u8; VERIFIER_LOG_SIZE];
+    attr.log_level = 1;
+    attr.log_size = log.len() as u32;
+    attr.log_buf = log.as_mut_ptr() as u64;
+    let _ = bpf(BPF_PROG_LOAD, &attr);
+    let log = String::from_utf8_lossy(&log);
+    let log = log.trim_end_matches('\0').trim();
+    let last = log.lines().last().unwrap_or_default();
+    Err(anyhow::anyhow!(
+        "the NIC refused the classifier: {e}: {last}"
+    ))
+}
+
+/// Interfaces asked to run in offload mode, and how each ended up.
+pub struct Offloads {
+    interfaces: Vec<(String, Result<Offloaded, String>)>,
+}
+
+impl Offloads {
+    /// Offload the classifier to each of `ifaces` where the NIC lets it, reporting where each
+    /// runs its datapath.
+    pub fn load(ifaces: &[String], pin_dir: &Path) -> Self {
+        let interfaces = ifaces
+            .iter()
+            .map(|iface| {
+                let offloaded = attach::existing_program(iface)
+                    .and_then(|(ifindex, _)| load(iface, ifindex, pin_dir))
+                    .map_err(|e| format!("{e:#}"));
+                match &offloaded {
+                    Ok(_) => info!(
+                        "{iface}: classification and counters offloaded to the NIC, the rest in \
+                         the driver"
+                    ),
+                    Err(reason) => warn!(
+                        "{iface}: XDP offload unavailable ({reason}); the whole datapath runs in \
+                         the driver"
+                    ),
+                }
+                (iface.clone(), offloaded)
+            })
+            .collect();
+        Self { interfaces }
+    }
+
+    /// A line per interface with its NIC counters, for `udcn offload`.
+    pub fn format_table(&self) -> String {
+        let mut out = format!("{:<16} {:<10}", "IFACE", "MODE");
+        for class in OffloadClass::ALL {
+            let _ = write!(out, " {:>12}", class.as_str().to_uppercase());
+        }
+        out.push('\n');
+        for (iface, offloaded) in &self.interfaces {
+            let counters = match offloaded {
+                Ok(offloaded) => offloaded.counters().map_err(|e| e.to_string()),
+                Err(reason) => Err(reason.clone()),
+            };
+            match counters {
+                Ok(counters) => {
+                    let _ = write!(out, "{iface:<16} {:<10}", "offload");
+                    for count in counters {
+                        let _ = write!(out, " {count:>12}");
+                    }
+                    out.push('\n');
+                }
+                Err(reason) => {
+                    let _ = writeln!(out, "{iface:<16} {:<10} {reason}", "driver");
+                }
+            }
+        }
+        out
+    }
+
+    /// Take the classifier off every NIC it runs on.
+    pub fn detach(&self) {
+        for offloaded in self
+            .interfaces
+            .iter()
+            .filter_map(|(_, offloaded)| offloaded.as_ref().ok())
+        {
+            offloaded.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_table() {
+        assert_eq!(&object_name("OFFLOAD_STATS")[..14], b"OFFLOAD_STATS\0");
+        assert_eq!(object_name("a_name_longer_than_fifteen")[15], 0);
+
+        let offloads = Offloads {
+            interfaces: vec![(
+                "eth0".to_string(),
+                Err("driver e1000e has no BPF offload; only nfp and netdevsim do".to_string()),
+            )],
+        };
+        let table = offloads.format_table();
+        let mut lines = table.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .ends_with("INTEREST         DATA         NACK    LP_PACKET"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("eth0             driver     driver e1000e"));
+    }
+}
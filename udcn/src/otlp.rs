@@ -0,0 +1,460 @@
+//! OpenTelemetry export over OTLP/HTTP, JSON-encoded.
+//!
+//! Spans time the work the daemon does in userspace: PIT sweeps, the Nacks and hop reports it
+//! sends to faces, packets relayed by tunnels and Ethernet faces, prefetches and control
+//! requests. Every `interval_secs` the spans recorded since the last export go to the collector,
+//! followed by the datapath counters as cumulative sums and the table occupancy as gauges. The
+//! counters are 32 bits and wrap, so each sum adds what its counter moved since the last export.
+//! Both carry the same resource, so a backend can line up slow-path latency with what the
+//! datapath was doing at the time. Spans are only recorded once export is started.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::BuildHasher as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Context as _;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+    time::{sleep, timeout, Duration},
+};
+use udcn_client::control::TableOccupancy;
+use udcn_common::PacketStats;
+
+use crate::{daemon::Daemon, events::Clock};
+
+/// Spans kept between exports; the oldest are dropped when the collector cannot keep up.
+const MAX_PENDING_SPANS: usize = 4096;
+
+/// How long a collector has to take an export.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `[otlp]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://127.0.0.1:4318`.
+    pub endpoint: String,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+fn default_service_name() -> String {
+    "udcn".to_string()
+}
+
+/// Where to send exports: `host:port` and the path `/v1/traces` and `/v1/metrics` go under.
+#[derive(Debug, PartialEq)]
+pub struct Endpoint {
+    authority: String,
+    base_path: String,
+}
+
+impl OtlpConfig {
+    pub fn endpoint(&self) -> anyhow::Result<Endpoint> {
+        let Some(rest) = self.endpoint.strip_prefix("http://") else {
+            anyhow::bail!(
+                "OTLP endpoint '{}' must be a plain http:// URL",
+                self.endpoint
+            );
+        };
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        anyhow::ensure!(
+            !authority.is_empty(),
+            "OTLP endpoint '{}' has no host",
+            self.endpoint
+        );
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:4318")
+        };
+        let path = path.trim_end_matches('/');
+        Ok(Endpoint {
+            authority,
+            base_path: if path.is_empty() {
+                String::new()
+            } else {
+                format!("/{path}")
+            },
+        })
+    }
+}
+
+struct SpanRecord {
+    name: &'static str,
+    start_ns: u64,
+    end_ns: u64,
+    attributes: Vec<(&'static str, String)>,
+}
+
+static PENDING_SPANS: Mutex<Option<VecDeque<SpanRecord>>> = Mutex::new(None);
+
+/// A span in progress, recorded when dropped if export is on.
+pub struct Span {
+    record: Option<SpanRecord>,
+}
+
+/// Start timing `name`.
+pub fn span(name: &'static str) -> Span {
+    let recording = PENDING_SPANS.lock().unwrap().is_some();
+    Span {
+        record: recording.then(|| SpanRecord {
+            name,
+            start_ns: Clock::datapath().now_ns(),
+            end_ns: 0,
+            attributes: Vec::new(),
+        }),
+    }
+}
+
+impl Span {
+    pub fn attr(&mut self, key: &'static str, value: impl ToString) {
+        if let Some(record) = &mut self.record {
+            record.attributes.push((key, value.to_string()));
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(mut record) = self.record.take() else {
+            return;
+        };
+        record.end_ns = Clock::datapath().now_ns();
+        if let Some(pending) = PENDING_SPANS.lock().unwrap().as_mut() {
+            if pending.len() == MAX_PENDING_SPANS {
+                pending.pop_front();
+            }
+            pending.push_back(record);
+        }
+    }
+}
+
+/// Start recording spans, and export them with the datapath metrics every `interval_secs`.
+pub fn start(config: OtlpConfig, daemon: Arc<Mutex<Daemon>>) -> anyhow::Result<()> {
+    let endpoint = config.endpoint()?;
+    *PENDING_SPANS.lock().unwrap() = Some(VecDeque::new());
+    info!(
+        "Exporting OpenTelemetry spans and metrics to {} every {}s",
+        config.endpoint, config.interval_secs
+    );
+    let started_ns = Clock::datapath().now_ns();
+    tokio::spawn(async move {
+        let mut totals = Totals::default();
+        loop {
+            sleep(Duration::from_secs(config.interval_secs)).await;
+            let export = export(
+                &endpoint,
+                &config.service_name,
+                &daemon,
+                &mut totals,
+                started_ns,
+            );
+            if let Err(e) = export.await {
+                warn!("OpenTelemetry export to {} failed: {e:#}", config.endpoint);
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn export(
+    endpoint: &Endpoint,
+    service_name: &str,
+    daemon: &Mutex<Daemon>,
+    totals: &mut Totals,
+    started_ns: u64,
+) -> anyhow::Result<()> {
+    let spans: Vec<SpanRecord> = match PENDING_SPANS.lock().unwrap().as_mut() {
+        Some(pending) => pending.drain(..).collect(),
+        None => Vec::new(),
+    };
+    if !spans.is_empty() {
+        post(endpoint, "/v1/traces", &traces(service_name, &spans)).await?;
+    }
+    let (stats, tables) = {
+        let daemon = daemon.lock().unwrap();
+        (daemon.stats()?, daemon.tables()?)
+    };
+    let now_ns = Clock::datapath().now_ns();
+    let metrics = metrics(service_name, &stats, &tables, totals, started_ns, now_ns)?;
+    post(endpoint, "/v1/metrics", &metrics).await
+}
+
+async fn post(endpoint: &Endpoint, path: &str, body: &Value) -> anyhow::Result<()> {
+    let body = body.to_string();
+    let request = format!(
+        "POST {}{path} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        endpoint.base_path,
+        endpoint.authority,
+        body.len()
+    );
+    let response = timeout(EXPORT_TIMEOUT, async {
+        let mut stream = TcpStream::connect(&endpoint.authority).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        anyhow::Ok(response)
+    })
+    .await
+    .context("the collector did not answer in time")??;
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    anyhow::ensure!(
+        status_line
+            .split(' ')
+            .nth(1)
+            .is_some_and(|status| status.starts_with('2')),
+        "the collector answered '{status_line}' to {path}"
+    );
+    Ok(())
+}
+
+fn resource(service_name: &str) -> Value {
+    json!({ "attributes": [attribute("service.name", service_name)] })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// A random identifier of `words` 64-bit words in hex, as OTLP/JSON encodes trace and span IDs.
+fn random_id(words: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    (0..words)
+        .map(|_| {
+            let seed = COUNTER.fetch_add(1, Ordering::Relaxed);
+            format!("{:016x}", RandomState::new().hash_one(seed))
+        })
+        .collect()
+}
+
+/// Each span is the root of a trace of its own.
+fn traces(service_name: &str, spans: &[SpanRecord]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            json!({
+                "traceId": random_id(2),
+                "spanId": random_id(1),
+                "name": span.name,
+                // SPAN_KIND_INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": span.start_ns.to_string(),
+                "endTimeUnixNano": span.end_ns.to_string(),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource(service_name),
+            "scopeSpans": [{ "scope": { "name": "udcn" }, "spans": spans }],
+        }]
+    })
+}
+
+/// What each datapath counter added up to over every export, by the counter's name.
+#[derive(Default)]
+struct Totals(HashMap<String, (u32, u64)>);
+
+impl Totals {
+    /// The total of `counter` now that it reads `value`, taking it to have wrapped if it went
+    /// down.
+    fn update(&mut self, counter: String, value: u32) -> u64 {
+        let (last, total) = self.0.entry(counter).or_default();
+        *total += u64::from(value.wrapping_sub(*last));
+        *last = value;
+        *total
+    }
+}
+
+/// One cumulative `udcn.<counter>` sum per datapath counter (per reason for drops), then the
+/// entries and capacity of each table as gauges.
+fn metrics(
+    service_name: &str,
+    stats: &PacketStats,
+    tables: &[TableOccupancy],
+    totals: &mut Totals,
+    started_ns: u64,
+    now_ns: u64,
+) -> anyhow::Result<Value> {
+    let Value::Object(counters) = serde_json::to_value(stats)? else {
+        anyhow::bail!("datapath counters are not a struct");
+    };
+    let point = |value: u64, attributes: Vec<Value>| {
+        json!({
+            "asInt": value.to_string(),
+            "startTimeUnixNano": started_ns.to_string(),
+            "timeUnixNano": now_ns.to_string(),
+            "attributes": attributes,
+        })
+    };
+    let mut total = |counter: String, value: &Value| {
+        totals.update(counter, value.as_u64().unwrap_or_default() as u32)
+    };
+    let mut metrics: Vec<Value> = counters
+        .iter()
+        .map(|(counter, value)| {
+            let points: Vec<Value> = match value {
+                // Counters broken down by reason, like drops
+                Value::Object(by_reason) => by_reason
+                    .iter()
+                    .map(|(reason, value)| {
+                        let total = total(format!("{counter}.{reason}"), value);
+                        point(total, vec![attribute("reason", reason)])
+                    })
+                    .collect(),
+                value => vec![point(total(counter.clone(), value), Vec::new())],
+            };
+            json!({
+                "name": format!("udcn.{counter}"),
+                "sum": {
+                    "dataPoints": points,
+                    // AGGREGATION_TEMPORALITY_CUMULATIVE
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                },
+            })
+        })
+        .collect();
+    let gauge = |name: &str, value: fn(&TableOccupancy) -> u32| {
+        let points: Vec<Value> = tables
+            .iter()
+            .map(|table| point(value(table).into(), vec![attribute("table", &table.table)]))
+            .collect();
+        json!({ "name": name, "gauge": { "dataPoints": points } })
+    };
+    metrics.push(gauge("udcn.table.entries", |table| table.entries));
+    metrics.push(gauge("udcn.table.capacity", |table| table.capacity));
+    Ok(json!({
+        "resourceMetrics": [{
+            "resource": resource(service_name),
+            "scopeMetrics": [{ "scope": { "name": "udcn" }, "metrics": metrics }],
+        }]
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        let config = |endpoint: &str| OtlpConfig {
+            endpoint: endpoint.to_string(),
+            interval_secs: 10,
+            service_name: "udcn".to_string(),
+        };
+        assert_eq!(
+            config("http://collector/otel/").endpoint().unwrap(),
+            Endpoint {
+                authority: "collector:4318".to_string(),
+                base_path: "/otel".to_string(),
+            }
+        );
+        assert_eq!(
+            config("http://10.0.0.1:4000").endpoint().unwrap().base_path,
+            ""
+        );
+        assert!(config("collector:4318").endpoint().is_err());
+        assert!(config("http:///v1").endpoint().is_err());
+    }
+
+    #[test]
+    fn test_spans_and_metrics_payloads() {
+        let spans = [SpanRecord {
+            name: "pit_sweep",
+            start_ns: 1_000,
+            end_ns: 3_500,
+            attributes: vec![("lapsed", "2".to_string())],
+        }];
+        let traces = traces("edge1", &spans);
+        let span = &traces["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "pit_sweep");
+        assert_eq!(span["endTimeUnixNano"], "3500");
+        assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(span["attributes"][0], attribute("lapsed", "2"));
+        assert_eq!(
+            traces["resourceSpans"][0]["resource"]["attributes"][0],
+            attribute("service.name", "edge1")
+        );
+
+        let stats: PacketStats = serde_json::from_value(json!({
+            "interest_received": 7, "data_received": 3, "cache_hits": 1, "cache_misses": 0,
+            "pit_hits": 3, "forwards": 10, "drops": { "pit_full": 12 }, "negative_cache_hits": 0,
+            "duplicate_interests": 0, "cs_admission_rejects": 2, "fresh_hits": 1, "stale_hits": 0,
+            "uncacheable": 0, "prefetch_hits": 0,
+            "cs_probation_hits": 0, "cs_protected_hits": 0, "unroutable_nacks": 0
+        }))
+        .unwrap();
+        let tables = [TableOccupancy {
+            table: "PIT".to_string(),
+            entries: 4,
+            capacity: 1024,
+        }];
+        let mut totals = Totals::default();
+        let payload = metrics("udcn", &stats, &tables, &mut totals, 100, 200).unwrap();
+        let metrics = payload["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let metric = |name: &str| metrics.iter().find(|m| m["name"] == name).unwrap();
+
+        let interests = &metric("udcn.interest_received")["sum"];
+        assert_eq!(interests["isMonotonic"], true);
+        assert_eq!(interests["dataPoints"][0]["asInt"], "7");
+        assert_eq!(interests["dataPoints"][0]["startTimeUnixNano"], "100");
+        let drops = metric("udcn.drops")["sum"]["dataPoints"]
+            .as_array()
+            .unwrap();
+        assert!(drops
+            .iter()
+            .any(|p| p["asInt"] == "12" && p["attributes"][0] == attribute("reason", "pit_full")));
+        let entries = &metric("udcn.table.entries")["gauge"]["dataPoints"][0];
+        assert_eq!(entries["asInt"], "4");
+        assert_eq!(entries["attributes"][0], attribute("table", "PIT"));
+        assert_eq!(
+            metric("udcn.table.capacity")["gauge"]["dataPoints"][0]["asInt"],
+            "1024"
+        );
+    }
+
+    #[test]
+    fn test_wrapped_counters_keep_adding_up() {
+        let mut totals = Totals::default();
+        assert_eq!(
+            totals.update("forwards".to_string(), u32::MAX - 1),
+            u64::from(u32::MAX - 1)
+        );
+        assert_eq!(
+            totals.update("forwards".to_string(), 3),
+            u64::from(u32::MAX) + 4
+        );
+        assert_eq!(
+            totals.update("forwards".to_string(), 3),
+            u64::from(u32::MAX) + 4
+        );
+        assert_eq!(totals.update("drops.pit_full".to_string(), 5), 5);
+    }
+}
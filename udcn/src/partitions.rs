@@ -0,0 +1,187 @@
+//! Content store partitions: shares of the CS reserved for a prefix, so a busy namespace can only
+//! evict its own content.
+//!
+//! The datapath picks a partition by the first component of a name, so reserved prefixes have
+//! exactly one component. Each partition is its own LRU map, sized when the program is loaded;
+//! names outside every reserved prefix share what is left in `CONTENT_STORE`.
+//!
+//! The shared partition can itself be split into two segments (`run --cs-protected-percent`):
+//! Data enters `CONTENT_STORE` on probation, and moves to `CS_PROTECTED` when it answers an
+//! Interest. Names requested only once then compete for the probation segment alone, so a scan
+//! cannot flush out content that is requested again and again.
+
+use std::str::FromStr;
+
+use anyhow::Context as _;
+use aya::{maps::HashMap, EbpfLoader};
+use udcn_common::{
+    cs_partition_key, Name, CS_PARTITION_MAPS, MAX_CS_PARTITIONS, MAX_CS_PARTITION_COMPONENT_LEN,
+};
+
+/// `PREFIX=PERCENT`: reserve `PERCENT` of the content store for names under `PREFIX`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reservation {
+    pub prefix: Name,
+    pub percent: u32,
+}
+
+impl FromStr for Reservation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (prefix, percent) = s
+            .rsplit_once('=')
+            .context("expected PREFIX=PERCENT, e.g. /video=70")?;
+        let prefix = Name::from_uri(prefix)?;
+        let percent = percent
+            .trim_end_matches('%')
+            .parse()
+            .with_context(|| format!("invalid percentage '{percent}'"))?;
+        anyhow::ensure!(
+            prefix.len() == 1,
+            "'{prefix}' must have exactly one component to be partitioned"
+        );
+        anyhow::ensure!(
+            prefix.components()[0].value.len() <= MAX_CS_PARTITION_COMPONENT_LEN,
+            "'{prefix}' is longer than {MAX_CS_PARTITION_COMPONENT_LEN} bytes"
+        );
+        anyhow::ensure!(
+            (1..=100).contains(&percent),
+            "percentage must be between 1 and 100"
+        );
+        Ok(Self { prefix, percent })
+    }
+}
+
+/// Content store capacity per partition, in entries.
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    /// Partitions 1.., in the order they were reserved.
+    pub reserved: Vec<(Name, u32)>,
+    /// Partition 0, `CONTENT_STORE`: its probation segment, when it is segmented.
+    pub shared: u32,
+    /// The protected segment of partition 0, `CS_PROTECTED`, or 0 when it is not segmented.
+    pub protected: u32,
+}
+
+impl Layout {
+    /// Split a content store of `capacity` entries between `reservations`, keeping
+    /// `protected_percent` of what is left for the shared partition's protected segment.
+    pub fn new(
+        reservations: &[Reservation],
+        protected_percent: u32,
+        capacity: u32,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            reservations.len() <= MAX_CS_PARTITIONS,
+            "at most {MAX_CS_PARTITIONS} content store partitions can be reserved"
+        );
+        let total: u32 = reservations.iter().map(|r| r.percent).sum();
+        anyhow::ensure!(
+            total <= 100,
+            "content store reservations add up to {total}%"
+        );
+        anyhow::ensure!(
+            protected_percent < 100,
+            "the protected segment must leave room for a probation segment"
+        );
+
+        let mut reserved: Vec<(Name, u32)> = Vec::new();
+        for reservation in reservations {
+            anyhow::ensure!(
+                reserved
+                    .iter()
+                    .all(|(prefix, _)| *prefix != reservation.prefix),
+                "'{}' is reserved twice",
+                reservation.prefix
+            );
+            let reserved_capacity =
+                (u64::from(capacity) * u64::from(reservation.percent) / 100).max(1) as u32;
+            reserved.push((reservation.prefix.clone(), reserved_capacity));
+        }
+        // An LRU map needs at least one entry, even when the reservations leave nothing over
+        let used: u32 = reserved.iter().map(|(_, capacity)| capacity).sum();
+        let shared = capacity.saturating_sub(used).max(1);
+        let protected = match protected_percent {
+            0 => 0,
+            percent => (u64::from(shared) * u64::from(percent) / 100).max(1) as u32,
+        };
+        Ok(Self {
+            reserved,
+            shared: shared.saturating_sub(protected).max(1),
+            protected,
+        })
+    }
+
+    /// Size the content store maps `loader` will create.
+    pub fn size_maps(&self, loader: &mut EbpfLoader) {
+        loader.set_max_entries("CONTENT_STORE", self.shared);
+        loader.set_max_entries("CS_PROTECTED", self.protected.max(1));
+        for (index, (_, capacity)) in self.reserved.iter().enumerate() {
+            loader.set_max_entries(CS_PARTITION_MAPS[index], *capacity);
+        }
+    }
+
+    /// Tell the datapath which prefix each partition is reserved for.
+    pub fn install(&self, ebpf: &mut aya::Ebpf) -> anyhow::Result<()> {
+        let mut prefixes: HashMap<_, u32, u32> =
+            HashMap::try_from(ebpf.map_mut("CS_PARTITION_PREFIXES").unwrap())?;
+        for (index, (prefix, _)) in self.reserved.iter().enumerate() {
+            let key = cs_partition_key(&prefix.components()[0].value);
+            prefixes.insert(key, index as u32 + 1, 0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::CS_CAPACITY;
+
+    use super::*;
+
+    #[test]
+    fn test_layout_splits_capacity() {
+        let reservations: Vec<Reservation> = ["/video=70", "/telemetry=20%"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let layout = Layout::new(&reservations, 0, CS_CAPACITY).unwrap();
+        assert_eq!(
+            layout.reserved,
+            vec![
+                (Name::from("/video"), CS_CAPACITY * 70 / 100),
+                (Name::from("/telemetry"), CS_CAPACITY * 20 / 100),
+            ]
+        );
+        assert_eq!(
+            layout.shared + layout.reserved.iter().map(|(_, c)| c).sum::<u32>(),
+            CS_CAPACITY
+        );
+
+        assert!("/video/hd=10".parse::<Reservation>().is_err());
+        assert!("/video".parse::<Reservation>().is_err());
+        assert!("/video=0".parse::<Reservation>().is_err());
+        let over: Vec<Reservation> = ["/a=60", "/b=50"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert!(Layout::new(&over, 0, CS_CAPACITY).is_err());
+    }
+
+    #[test]
+    fn test_layout_segments_shared_partition() {
+        let reservations: Vec<Reservation> = vec!["/video=50".parse().unwrap()];
+        let layout = Layout::new(&reservations, 80, CS_CAPACITY).unwrap();
+        let shared = CS_CAPACITY - CS_CAPACITY * 50 / 100;
+        assert_eq!(layout.protected, shared * 80 / 100);
+        assert_eq!(layout.shared + layout.protected, shared);
+        assert_eq!(
+            Layout::new(&reservations, 0, CS_CAPACITY)
+                .unwrap()
+                .protected,
+            0
+        );
+        assert!(Layout::new(&reservations, 100, CS_CAPACITY).is_err());
+    }
+}
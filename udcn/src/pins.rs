@@ -0,0 +1,172 @@
+//! Content store pins: names and prefixes whose Data nothing evicts (`udcn cs pin`).
+//!
+//! The datapath caches the Data of a pinned name, or of a name under a pinned prefix, in
+//! `CS_PINNED` instead of its partition. That map is not an LRU one, so the Data stays however
+//! contended the partitions are, until it is unpinned or purged; fresher Data for the name
+//! replaces it in place. Flushing the content store leaves it, and tenant quotas count it without
+//! evicting it. Pinning moves Data already cached into `CS_PINNED`, and unpinning moves what no
+//! pin covers any more back to the partition it would have gone to.
+//!
+//! The daemon can only tell which cached Data falls under a prefix by the names it has been told
+//! about. Pinned Data it cannot name stays pinned while any prefix is.
+
+use std::{fmt::Write as _, str::FromStr};
+
+use aya::maps::{HashMap, MapData};
+use udcn_common::{Name, MAX_CS_PINS};
+
+use crate::nocache::NoCachePrefix;
+
+/// The map holding pinned Data.
+pub const TABLE: &str = "CS_PINNED";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pin {
+    /// Data named exactly this.
+    Name(Name),
+    /// Data named anything under this prefix.
+    Prefix(Name),
+}
+
+impl Pin {
+    /// Whether the pin covers Data named `name_hash`, or `name` if the daemon knows it. A prefix
+    /// covers no Data the daemon cannot name.
+    pub fn matches(&self, name_hash: u32, name: Option<&Name>) -> bool {
+        match self {
+            Self::Name(pinned) => pinned.name_hash() == name_hash,
+            Self::Prefix(prefix) => name.is_some_and(|name| prefix.is_prefix_of(name)),
+        }
+    }
+}
+
+/// `/a/b` pins a name, `prefix /a` a prefix.
+impl FromStr for Pin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.strip_prefix("prefix ") {
+            Some(prefix) => Ok(Self::Prefix(NoCachePrefix::from_str(prefix.trim())?.0)),
+            None => Ok(Self::Name(Name::from_uri(s.trim())?)),
+        }
+    }
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Prefix(prefix) => write!(f, "prefix {prefix}"),
+        }
+    }
+}
+
+pub struct Pins {
+    pins: Vec<Pin>,
+    names: HashMap<MapData, u32, u8>,
+    prefixes: HashMap<MapData, u32, u8>,
+}
+
+impl Pins {
+    pub fn new(ebpf: &mut aya::Ebpf) -> anyhow::Result<Self> {
+        Ok(Self {
+            pins: Vec::new(),
+            names: HashMap::try_from(ebpf.take_map("CS_PIN_NAMES").unwrap())?,
+            prefixes: HashMap::try_from(ebpf.take_map("CS_PIN_PREFIXES").unwrap())?,
+        })
+    }
+
+    pub fn pins(&self) -> &[Pin] {
+        &self.pins
+    }
+
+    /// Tell the datapath about `pin`. Returns false if it was already made.
+    pub fn pin(&mut self, pin: Pin) -> anyhow::Result<bool> {
+        if self.pins.contains(&pin) {
+            return Ok(false);
+        }
+        let prefix = matches!(pin, Pin::Prefix(_));
+        let made = self
+            .pins
+            .iter()
+            .filter(|other| matches!(other, Pin::Prefix(_)) == prefix)
+            .count();
+        anyhow::ensure!(
+            made < MAX_CS_PINS as usize,
+            "at most {MAX_CS_PINS} names and {MAX_CS_PINS} prefixes can be pinned"
+        );
+        match &pin {
+            Pin::Name(name) => self.names.insert(name.name_hash(), 1, 0)?,
+            Pin::Prefix(prefix) => {
+                self.prefixes
+                    .insert(NoCachePrefix(prefix.clone()).key(), 1, 0)?
+            }
+        }
+        self.pins.push(pin);
+        Ok(true)
+    }
+
+    /// Stop pinning new Data for `pin`. Returns false if it was not made.
+    pub fn unpin(&mut self, pin: &Pin) -> anyhow::Result<bool> {
+        let Some(index) = self.pins.iter().position(|other| other == pin) else {
+            return Ok(false);
+        };
+        match pin {
+            Pin::Name(name) => self.names.remove(&name.name_hash())?,
+            Pin::Prefix(prefix) => self.prefixes.remove(&NoCachePrefix(prefix.clone()).key())?,
+        }
+        self.pins.remove(index);
+        Ok(true)
+    }
+
+    /// Whether Data named `name_hash`, or `name` if the daemon knows it, may be pinned.
+    pub fn covers(&self, name_hash: u32, name: Option<&Name>) -> bool {
+        self.pins.iter().any(|pin| match (pin, name) {
+            (Pin::Prefix(_), None) => true,
+            (pin, name) => pin.matches(name_hash, name),
+        })
+    }
+}
+
+/// Pins as `udcn cs pins` shows them, with how many cached entries each covers.
+pub fn format_pins(pins: &[(Pin, usize)]) -> String {
+    let mut out = format!("{:<8} {:<48} {:>8}\n", "KIND", "NAME", "CACHED");
+    for (pin, cached) in pins {
+        let (kind, name) = match pin {
+            Pin::Name(name) => ("name", name),
+            Pin::Prefix(prefix) => ("prefix", prefix),
+        };
+        let _ = writeln!(out, "{kind:<8} {:<48} {cached:>8}", name.to_string());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_parse_and_cover_names() {
+        let name = Name::from_uri("/video/intro/seg=0").unwrap();
+        let pin: Pin = "/video/intro/seg=0".parse().unwrap();
+        assert_eq!(pin, Pin::Name(name.clone()));
+        assert!(pin.matches(name.name_hash(), None));
+        assert!(!pin.matches(name.name_hash() ^ 1, Some(&name)));
+
+        let prefix: Pin = "prefix /video/intro".parse().unwrap();
+        assert_eq!(prefix.to_string(), "prefix /video/intro");
+        assert_eq!(prefix.to_string().parse::<Pin>().unwrap(), prefix);
+        assert!(prefix.matches(0, Some(&name)));
+        assert!(!prefix.matches(0, Some(&Name::from_uri("/video/outro").unwrap())));
+        assert!(!prefix.matches(0, None));
+        assert!("prefix /".parse::<Pin>().is_err());
+        assert!("prefix /a/b/c/d/e".parse::<Pin>().is_err());
+
+        let table = format_pins(&[(prefix, 3)]);
+        assert!(table
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("prefix   /video/intro"));
+        assert!(table.lines().nth(1).unwrap().ends_with(" 3"));
+    }
+}
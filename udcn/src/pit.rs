@@ -0,0 +1,166 @@
+//! The daemon's side of the PIT, in whichever layout `run --pit-layout` chose.
+//!
+//! The hash map layout goes through [`batch`]. The open-addressing layout is read whole, both
+//! arrays in a batch each. Each entry the daemon changes or removes is found again by probing
+//! its slots the way the datapath does, as an entry can move to another slot between the read
+//! and the write. A removed entry leaves a tombstone, just as the datapath's removals do.
+
+use aya::{
+    maps::{Array, HashMap, MapData},
+    EbpfLoader,
+};
+use udcn_common::{
+    pit::{self, PitLayout, PIT_SLOTS},
+    PitEntry,
+};
+
+use crate::batch;
+
+/// `BPF_NOEXIST`: only add an entry for a key that has none.
+const BPF_NOEXIST: u64 = 1;
+
+pub enum Pit {
+    HashMap {
+        map: HashMap<MapData, u32, PitEntry>,
+        capacity: u32,
+    },
+    OpenAddressing {
+        fingerprints: Array<MapData, u32>,
+        entries: Array<MapData, PitEntry>,
+    },
+}
+
+/// Give the hash map `capacity` entries, or the open-addressing arrays their slots, whichever
+/// `layout` uses. The arrays are left at one entry otherwise.
+pub fn size_maps(loader: &mut EbpfLoader, layout: PitLayout, capacity: u32) {
+    if layout == PitLayout::HashMap {
+        loader.set_max_entries("PIT", capacity);
+    } else {
+        loader.set_max_entries("PIT_FINGERPRINTS", PIT_SLOTS);
+        loader.set_max_entries("PIT_ENTRIES", PIT_SLOTS);
+    }
+}
+
+impl Pit {
+    /// The PIT in `layout`, with `capacity` entries if it is the hash map.
+    pub fn new(ebpf: &mut aya::Ebpf, layout: PitLayout, capacity: u32) -> anyhow::Result<Self> {
+        Ok(match layout {
+            PitLayout::HashMap => Self::HashMap {
+                map: HashMap::try_from(ebpf.take_map("PIT").unwrap())?,
+                capacity,
+            },
+            PitLayout::OpenAddressing => Self::OpenAddressing {
+                fingerprints: Array::try_from(ebpf.take_map("PIT_FINGERPRINTS").unwrap())?,
+                entries: Array::try_from(ebpf.take_map("PIT_ENTRIES").unwrap())?,
+            },
+        })
+    }
+
+    /// How many entries the layout has room for.
+    pub fn capacity(&self) -> u32 {
+        match self {
+            Self::HashMap { capacity, .. } => *capacity,
+            Self::OpenAddressing { .. } => PIT_SLOTS,
+        }
+    }
+
+    /// Every pending entry by name hash, and whether they were read in batches.
+    pub fn entries(&self) -> anyhow::Result<(Vec<(u32, PitEntry)>, bool)> {
+        let (fingerprints, entries) = match self {
+            Self::HashMap { map, .. } => return batch::entries(map),
+            Self::OpenAddressing {
+                fingerprints,
+                entries,
+            } => (batch::values(fingerprints)?, batch::values(entries)?),
+        };
+        let pending = fingerprints
+            .0
+            .iter()
+            .zip(entries.0)
+            .filter(|(fingerprint, _)| **fingerprint > pit::TOMBSTONE)
+            .map(|(_, entry)| (entry.name_hash, entry))
+            .collect();
+        Ok((pending, fingerprints.1 && entries.1))
+    }
+
+    /// Overwrite the entries still pending with `changed`.
+    pub fn update_existing(&mut self, changed: &[(u32, PitEntry)]) -> anyhow::Result<()> {
+        match self {
+            Self::HashMap { map, .. } => {
+                batch::update_existing(map, changed)?;
+            }
+            Self::OpenAddressing {
+                fingerprints,
+                entries,
+            } => {
+                for (name_hash, entry) in changed {
+                    if let pit::Probe::Found(slot) = probe(fingerprints, entries, *name_hash) {
+                        entries.set(slot, entry, 0)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the entries for `name_hashes`, ignoring those already gone.
+    pub fn delete(&mut self, name_hashes: &[u32]) -> anyhow::Result<()> {
+        match self {
+            Self::HashMap { map, .. } => {
+                batch::delete(map, name_hashes)?;
+            }
+            Self::OpenAddressing {
+                fingerprints,
+                entries,
+            } => {
+                for name_hash in name_hashes {
+                    if let pit::Probe::Found(slot) = probe(fingerprints, entries, *name_hash) {
+                        fingerprints.set(slot, pit::TOMBSTONE, 0)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `entry` unless its name has one pending already.
+    pub fn insert_new(&mut self, entry: PitEntry) -> anyhow::Result<()> {
+        let name_hash = entry.name_hash;
+        match self {
+            Self::HashMap { map, .. } => match map.insert(name_hash, entry, BPF_NOEXIST) {
+                Ok(()) => Ok(()),
+                Err(_) if map.get(&name_hash, 0).is_ok() => Ok(()),
+                Err(e) => Err(e.into()),
+            },
+            Self::OpenAddressing {
+                fingerprints,
+                entries,
+            } => match probe(fingerprints, entries, name_hash) {
+                pit::Probe::Found(_) => Ok(()),
+                pit::Probe::Vacant(slot) => {
+                    entries.set(slot, entry, 0)?;
+                    fingerprints.set(slot, pit::fingerprint(name_hash), 0)?;
+                    Ok(())
+                }
+                pit::Probe::Full => anyhow::bail!("no PIT slot is free for {name_hash:#010x}"),
+            },
+        }
+    }
+}
+
+/// Probe the open-addressing arrays for `name_hash`, a lookup per slot.
+fn probe(
+    fingerprints: &Array<MapData, u32>,
+    entries: &Array<MapData, PitEntry>,
+    name_hash: u32,
+) -> pit::Probe {
+    pit::probe(
+        name_hash,
+        |slot| fingerprints.get(&slot, 0).unwrap_or(pit::EMPTY),
+        |slot| {
+            entries
+                .get(&slot, 0)
+                .map_or(!name_hash, |entry| entry.name_hash)
+        },
+    )
+}
@@ -0,0 +1,355 @@
+//! Content store prefetching: the daemon fetches Data it expects to be asked for while the
+//! datapath is quiet, so sequential workloads such as media playback hit the content store.
+//!
+//! Segments are queued three ways: each prefix in the `[prefetch]` section is warmed from segment
+//! 0 when the daemon starts, `udcn cs prefetch` queues another while it runs, and with `ahead` set,
+//! an Interest for segment k of a registered name queues segments k+1 to k+`ahead` of the same
+//! content. Following Interests keeps datapath events published for as long as the daemon runs.
+//! A run of segments stops at the first one the upstream does not supply.
+//!
+//! Every `interval_secs` the daemon looks at how many Interests arrived; while fewer than
+//! `idle_below` a second did, queued segments are fetched from `upstream` one at a time until the
+//! next check. Each fetch gets a PIT entry for the daemon's own face, so the datapath takes the
+//! reply as solicited, and the Data is then cached marked as prefetched. Interests it answers are
+//! counted in `prefetch_hits` as well as `cache_hits`.
+
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::time::{sleep, Duration, Instant};
+use udcn_client::{Consumer, Reply};
+use udcn_common::{Interest, Name, TlvType, CONTENT_TYPE_NO_CACHE};
+
+use crate::{daemon::Daemon, events::EventHub, nocache::NoCachePrefix, otlp};
+
+/// Runs of segments queued at once; more are refused until some are fetched.
+const MAX_QUEUED: usize = 256;
+
+/// Lifetime of the Interests the prefetcher sends.
+const INTEREST_LIFETIME_MS: u64 = 1000;
+
+/// The `[prefetch]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefetchConfig {
+    /// Forwarder or producer prefetch Interests are sent to.
+    pub upstream: SocketAddr,
+    /// Prefixes warmed segment by segment once the daemon starts.
+    #[serde(default)]
+    pub prefixes: Vec<Name>,
+    /// Segments fetched ahead of an Interest for a segmented name; 0 turns following off.
+    #[serde(default)]
+    pub ahead: u64,
+    /// Segments warmed per prefix at most.
+    #[serde(default = "default_max_segments")]
+    pub max_segments: u64,
+    /// Interests a second below which the datapath counts as idle.
+    #[serde(default = "default_idle_below")]
+    pub idle_below: u64,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_max_segments() -> u64 {
+    1024
+}
+
+fn default_idle_below() -> u64 {
+    100
+}
+
+fn default_interval_secs() -> u64 {
+    1
+}
+
+impl PrefetchConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.upstream.is_ipv4(),
+            "prefetch.upstream must be an IPv4 address"
+        );
+        anyhow::ensure!(
+            self.max_segments > 0,
+            "prefetch.max_segments must be positive"
+        );
+        anyhow::ensure!(
+            self.interval_secs > 0,
+            "prefetch.interval_secs must be positive"
+        );
+        Ok(())
+    }
+}
+
+/// Segments `first..=last` under `prefix`, fetched in order.
+#[derive(Clone, Debug, PartialEq)]
+struct Run {
+    prefix: Name,
+    first: u64,
+    last: u64,
+}
+
+/// Runs of segments waiting for the datapath to go idle.
+pub struct Queue {
+    runs: Mutex<VecDeque<Run>>,
+    max_segments: u64,
+    /// Where the runs are fetched from.
+    pub upstream: SocketAddr,
+}
+
+impl Queue {
+    fn new(max_segments: u64, upstream: SocketAddr) -> Self {
+        Self {
+            runs: Mutex::new(VecDeque::new()),
+            max_segments,
+            upstream,
+        }
+    }
+
+    /// Queue `prefix` to be warmed from segment 0. Returns false if the queue is full.
+    pub fn warm(&self, prefix: Name) -> bool {
+        let last = self.max_segments - 1;
+        self.push(Run {
+            prefix,
+            first: 0,
+            last,
+        })
+    }
+
+    /// Queue the `count` segments after `segment` of `prefix`, unless a queued run covers them.
+    fn ahead(&self, prefix: Name, segment: u64, count: u64) -> bool {
+        let run = Run {
+            prefix,
+            first: segment.saturating_add(1),
+            last: segment.saturating_add(count),
+        };
+        let covered = self.runs.lock().unwrap().iter().any(|queued| {
+            queued.prefix == run.prefix && queued.first <= run.first && run.last <= queued.last
+        });
+        covered || self.push(run)
+    }
+
+    fn push(&self, run: Run) -> bool {
+        let mut runs = self.runs.lock().unwrap();
+        if runs.len() >= MAX_QUEUED {
+            return false;
+        }
+        runs.push_back(run);
+        true
+    }
+
+    fn pop(&self) -> Option<Run> {
+        self.runs.lock().unwrap().pop_front()
+    }
+
+    /// Put back what is left of a run cut short by the datapath getting busy.
+    fn resume(&self, run: Run) {
+        self.runs.lock().unwrap().push_front(run);
+    }
+}
+
+/// Queue the configured prefixes and start fetching, and following Interests if asked to.
+pub fn start(
+    config: PrefetchConfig,
+    daemon: Arc<Mutex<Daemon>>,
+    hub: &Arc<EventHub>,
+    no_cache: Vec<NoCachePrefix>,
+) -> anyhow::Result<()> {
+    let queue = Arc::new(Queue::new(config.max_segments, config.upstream));
+    for prefix in &config.prefixes {
+        info!("Prefetching '{prefix}' from {}", config.upstream);
+        queue.warm(prefix.clone());
+    }
+    daemon.lock().unwrap().set_prefetch_queue(queue.clone());
+
+    if config.ahead > 0 {
+        info!(
+            "Prefetching {} segments ahead of Interests for segmented names",
+            config.ahead
+        );
+        let mut events = hub.subscribe();
+        let (daemon, queue, ahead) = (daemon.clone(), queue.clone(), config.ahead);
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(missed) => {
+                        debug!("prefetcher missed {missed} datapath events");
+                        continue;
+                    }
+                };
+                if event.packet_type != TlvType::Interest as u8 {
+                    continue;
+                }
+                let Some(name) = daemon.lock().unwrap().name(event.name_hash) else {
+                    continue;
+                };
+                if let Some(segment) = name.segment() {
+                    if !queue.ahead(name.prefix(name.len() - 1), segment, ahead) {
+                        debug!("prefetch queue full, not following '{name}'");
+                    }
+                }
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = run(config, daemon, queue, no_cache).await {
+            warn!("prefetcher stopped: {e:#}");
+        }
+    });
+    Ok(())
+}
+
+async fn run(
+    config: PrefetchConfig,
+    daemon: Arc<Mutex<Daemon>>,
+    queue: Arc<Queue>,
+    no_cache: Vec<NoCachePrefix>,
+) -> anyhow::Result<()> {
+    let consumer = Consumer::connect(config.upstream)
+        .await
+        .with_context(|| format!("failed to connect to {}", config.upstream))?;
+    let face = SocketAddrV4::new(Ipv4Addr::LOCALHOST, consumer.local_addr()?.port());
+    let interval = Duration::from_secs(config.interval_secs);
+    let mut interests = daemon.lock().unwrap().stats()?.interest_received;
+
+    loop {
+        sleep(interval).await;
+        let received = daemon.lock().unwrap().stats()?.interest_received;
+        let rate = u64::from(received.wrapping_sub(interests)) / config.interval_secs;
+        interests = received;
+        if rate >= config.idle_below {
+            debug!("{rate} Interests/s, not prefetching");
+            continue;
+        }
+
+        let deadline = Instant::now() + interval;
+        while Instant::now() < deadline {
+            let Some(mut run) = queue.pop() else {
+                break;
+            };
+            while run.first <= run.last {
+                if Instant::now() >= deadline {
+                    queue.resume(run.clone());
+                    break;
+                }
+                let name = run.prefix.clone().append_segment(run.first);
+                if fetch(&consumer, face, &daemon, &no_cache, name).await == Fetched::Failed {
+                    break;
+                }
+                run.first += 1;
+            }
+        }
+    }
+}
+
+/// What became of a name the prefetcher was asked to fetch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fetched {
+    Cached,
+    AlreadyCached,
+    /// The Data came, but must not be cached.
+    Uncacheable,
+    /// The upstream did not supply it, or it could not be cached; for segments, those after it
+    /// are not tried.
+    Failed,
+}
+
+/// Fetch and cache `name` unless it is cached already. `face` is the consumer's, as the datapath
+/// sees it.
+pub async fn fetch(
+    consumer: &Consumer,
+    face: SocketAddrV4,
+    daemon: &Mutex<Daemon>,
+    no_cache: &[NoCachePrefix],
+    name: Name,
+) -> Fetched {
+    let mut span = otlp::span("prefetch");
+    span.attr("name", &name);
+    let fetched = fetch_uncached(consumer, face, daemon, no_cache, name).await;
+    span.attr("outcome", format!("{fetched:?}"));
+    fetched
+}
+
+async fn fetch_uncached(
+    consumer: &Consumer,
+    face: SocketAddrV4,
+    daemon: &Mutex<Daemon>,
+    no_cache: &[NoCachePrefix],
+    name: Name,
+) -> Fetched {
+    if daemon.lock().unwrap().is_cached(name.name_hash()) {
+        return Fetched::AlreadyCached;
+    }
+    let interest = Interest::builder()
+        .name(name)
+        .lifetime_ms(INTEREST_LIFETIME_MS)
+        .build();
+    if let Err(e) = daemon.lock().unwrap().expect_data(&interest, face) {
+        warn!("failed to record prefetch of '{}': {e:#}", interest.name);
+        return Fetched::Failed;
+    }
+
+    let data = match consumer.express(&interest).await {
+        Ok(Reply::Data(data)) => data,
+        Ok(Reply::Nack(reason)) => {
+            debug!("prefetch of '{}' Nacked ({reason})", interest.name);
+            return Fetched::Failed;
+        }
+        Err(e) => {
+            debug!("prefetch of '{}' failed: {e:#}", interest.name);
+            return Fetched::Failed;
+        }
+    };
+    let uncacheable = data.meta.content_type == Some(CONTENT_TYPE_NO_CACHE)
+        || no_cache
+            .iter()
+            .any(|prefix| prefix.0.is_prefix_of(&data.name));
+    if uncacheable {
+        debug!("prefetched '{}' is non-cacheable", data.name);
+        return Fetched::Uncacheable;
+    }
+    if let Err(e) = daemon.lock().unwrap().cache_prefetched(&data) {
+        warn!("failed to cache prefetched '{}': {e:#}", data.name);
+        return Fetched::Failed;
+    }
+    debug!("prefetched '{}'", data.name);
+    Fetched::Cached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_skips_covered_runs() {
+        let queue = Queue::new(16, "192.0.2.1:6363".parse().unwrap());
+        let video = Name::from("/video/a");
+        assert!(queue.warm(video.clone()));
+        // Segments 4 to 6 are in the warming run already
+        assert!(queue.ahead(video.clone(), 3, 3));
+        assert!(queue.ahead(Name::from("/video/b"), 3, 3));
+
+        assert_eq!(
+            queue.pop(),
+            Some(Run {
+                prefix: video,
+                first: 0,
+                last: 15
+            })
+        );
+        assert_eq!(queue.pop().map(|run| (run.first, run.last)), Some((4, 6)));
+        assert_eq!(queue.pop(), None);
+
+        for _ in 0..MAX_QUEUED {
+            assert!(queue.warm(Name::from("/video/c")));
+        }
+        assert!(!queue.warm(Name::from("/video/d")));
+    }
+}
@@ -0,0 +1,365 @@
+//! Warming the content store from a manifest ahead of a flash crowd (`udcn cs preload`).
+//!
+//! A manifest lists one name per line. A name on its own is fetched from the upstream the way the
+//! prefetcher fetches segments: under a PIT entry for the daemon's own face, so the datapath takes
+//! the reply as solicited, then cached marked as prefetched. A name followed by a path imports the
+//! local file instead, as segments named `<name>/seg=<n>`, each as large as fits one datagram on
+//! the smallest MTU of the daemon's interfaces unless the request gives a `segment_size`; relative
+//! paths are taken from the manifest's directory. Blank lines and lines starting with `#` are
+//! skipped.
+//!
+//! The daemon never opens the manifest or the files it imports: the CLI reads them with the
+//! caller's permissions and sends them after the request line, as a body of `fetch <name>` lines
+//! and `import <name> <length>` lines each followed by that many bytes of the file.
+//!
+//! ```text
+//! # Intro clip, fetched
+//! /video/launch/intro/seg=0
+//! /video/launch/intro/seg=1
+//! # Poster, imported
+//! /video/launch/poster  poster.jpg
+//! ```
+//!
+//! The report counts what became of each name, then where the preloaded Data ended up: how much
+//! of it each content store partition and segment holds, and how much the preload itself evicted
+//! again because a tier was too small for it.
+
+use std::{
+    fmt::Write as _,
+    io::Write as _,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use udcn_client::{Consumer, SegmentSize};
+use udcn_common::{Data, Name};
+
+use crate::{
+    daemon::Daemon,
+    prefetch::{self, Fetched},
+};
+
+/// One line of a manifest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    Fetch(Name),
+    Import(Name, PathBuf),
+}
+
+/// Parse `manifest`, taking relative paths from `dir`.
+pub fn parse_manifest(manifest: &str, dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in (1..).zip(manifest.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let name = fields.next().unwrap_or_default();
+        let name = Name::from_uri(name)
+            .map_err(|e| anyhow::anyhow!("line {number}: invalid name '{name}': {e}"))?;
+        entries.push(match (fields.next(), fields.next()) {
+            (None, _) => Entry::Fetch(name),
+            (Some(path), None) => Entry::Import(name, dir.join(path)),
+            (Some(_), Some(extra)) => anyhow::bail!("line {number}: unexpected '{extra}'"),
+        });
+    }
+    Ok(entries)
+}
+
+/// The largest body the daemon reads for one preload.
+pub const MAX_BODY: u64 = 1 << 30;
+
+/// A manifest entry as sent to the daemon, with the contents of the file it imports.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    Fetch(Name),
+    Import(Name, Vec<u8>),
+}
+
+/// Read the files `entries` import, with the caller's permissions, into a request body.
+pub fn encode(entries: &[Entry]) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for entry in entries {
+        match entry {
+            Entry::Fetch(name) => writeln!(body, "fetch {name}")?,
+            Entry::Import(name, path) => {
+                let content = std::fs::read(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                writeln!(body, "import {name} {}", content.len())?;
+                body.extend_from_slice(&content);
+            }
+        }
+    }
+    anyhow::ensure!(
+        body.len() as u64 <= MAX_BODY,
+        "the preload exceeds {MAX_BODY} bytes"
+    );
+    Ok(body)
+}
+
+/// Parse a request body written by [`encode`].
+pub fn decode(mut body: &[u8]) -> anyhow::Result<Vec<Item>> {
+    let mut items = Vec::new();
+    while !body.is_empty() {
+        let end = body
+            .iter()
+            .position(|&b| b == b'\n')
+            .context("truncated preload entry")?;
+        let line = std::str::from_utf8(&body[..end]).context("invalid preload entry")?;
+        body = &body[end + 1..];
+        let mut fields = line.split(' ');
+        let (kind, name) = (fields.next(), fields.next().unwrap_or_default());
+        let name =
+            Name::from_uri(name).map_err(|e| anyhow::anyhow!("invalid name '{name}': {e}"))?;
+        items.push(match (kind, fields.next(), fields.next()) {
+            (Some("fetch"), None, _) => Item::Fetch(name),
+            (Some("import"), Some(length), None) => {
+                let length: usize = length.parse().context("invalid import length")?;
+                anyhow::ensure!(length <= body.len(), "truncated import of '{name}'");
+                let (content, rest) = body.split_at(length);
+                body = rest;
+                Item::Import(name, content.to_vec())
+            }
+            _ => anyhow::bail!("invalid preload entry '{line}'"),
+        });
+    }
+    Ok(items)
+}
+
+/// Send `line`, the `cs-preload` request for `request`, with the manifest and the files it
+/// imports read here rather than by the daemon.
+pub async fn send(line: &str, request: &Request) -> anyhow::Result<String> {
+    let manifest = std::fs::read_to_string(&request.manifest)
+        .with_context(|| format!("failed to read {}", request.manifest.display()))?;
+    let dir = request.manifest.parent().unwrap_or(Path::new("."));
+    let body = encode(&parse_manifest(&manifest, dir)?)?;
+    udcn_client::control::request_with_body(line, &body).await
+}
+
+/// A `cs-preload <manifest> [upstream=<addr>] [segment_size=<bytes>|auto]` request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    pub manifest: PathBuf,
+    /// Defaults to the prefetcher's.
+    pub upstream: Option<SocketAddr>,
+    pub segment_size: SegmentSize,
+}
+
+impl std::str::FromStr for Request {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut fields = s.split_whitespace();
+        let manifest = fields.next().context("expected a manifest path")?;
+        let mut request = Self {
+            manifest: PathBuf::from(manifest),
+            upstream: None,
+            segment_size: SegmentSize::Auto,
+        };
+        for field in fields {
+            match field.split_once('=') {
+                Some(("upstream", addr)) => request.upstream = Some(addr.parse()?),
+                Some(("segment_size", size)) => request.segment_size = size.parse()?,
+                _ => anyhow::bail!("unknown parameter '{field}'"),
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl std::fmt::Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.manifest.display())?;
+        if let Some(upstream) = self.upstream {
+            write!(f, " upstream={upstream}")?;
+        }
+        write!(f, " segment_size={}", self.segment_size)
+    }
+}
+
+/// How much of the preloaded Data one content store partition or segment holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tier {
+    pub tier: String,
+    pub capacity: u32,
+    pub preloaded: usize,
+}
+
+/// What a preload did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// Names fetched and cached, including imported segments.
+    pub cached: usize,
+    pub already_cached: usize,
+    pub uncacheable: usize,
+    pub failed: usize,
+    pub tiers: Vec<Tier>,
+}
+
+/// Fetch or import everything in `body`, the manifest `request` names as the CLI sent it, then
+/// report where it went.
+pub async fn preload(
+    daemon: Arc<Mutex<Daemon>>,
+    request: &Request,
+    body: &[u8],
+) -> anyhow::Result<Report> {
+    let items = decode(body)?;
+
+    let consumer = match items.iter().any(|item| matches!(item, Item::Fetch(_))) {
+        true => {
+            let prefetch_upstream = daemon.lock().unwrap().prefetch_upstream();
+            let upstream = request.upstream.or(prefetch_upstream).context(
+                "no upstream to fetch from; give one or configure the [prefetch] section",
+            )?;
+            let consumer = Consumer::connect(upstream)
+                .await
+                .with_context(|| format!("failed to connect to {upstream}"))?;
+            let face = SocketAddrV4::new(Ipv4Addr::LOCALHOST, consumer.local_addr()?.port());
+            Some((consumer, face))
+        }
+        false => None,
+    };
+    let no_cache = daemon.lock().unwrap().no_cache().to_vec();
+
+    let mut report = Report::default();
+    let mut preloaded = Vec::new();
+    for item in items {
+        match item {
+            Item::Fetch(name) => {
+                let (consumer, face) = consumer.as_ref().unwrap();
+                preloaded.push(name.name_hash());
+                let fetched = prefetch::fetch(consumer, *face, &daemon, &no_cache, name).await;
+                match fetched {
+                    Fetched::Cached => report.cached += 1,
+                    Fetched::AlreadyCached => report.already_cached += 1,
+                    Fetched::Uncacheable => report.uncacheable += 1,
+                    Fetched::Failed => report.failed += 1,
+                }
+            }
+            Item::Import(name, content) => {
+                if no_cache.iter().any(|prefix| prefix.0.is_prefix_of(&name)) {
+                    report.uncacheable += 1;
+                    continue;
+                }
+                let last = name.clone().append_segment(content.len() as u64);
+                // Imported segments are not signed
+                let segment_size = request
+                    .segment_size
+                    .resolve(None, &last, None, |_| Vec::new())?;
+                for (segment, chunk) in (0..).zip(content.chunks(segment_size)) {
+                    let data = Data::builder()
+                        .name(name.clone().append_segment(segment))
+                        .content(chunk)
+                        .build();
+                    preloaded.push(data.name.name_hash());
+                    match daemon.lock().unwrap().cache_prefetched(&data) {
+                        Ok(()) => report.cached += 1,
+                        Err(e) => {
+                            log::debug!("failed to import '{}': {e:#}", data.name);
+                            report.failed += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    report.tiers = daemon.lock().unwrap().cs_tiers(&preloaded)?;
+    Ok(report)
+}
+
+/// A report as `udcn cs preload` prints it.
+pub fn format_report(report: &Report) -> String {
+    let mut out = format!(
+        "Cached {}, already cached {}, uncacheable {}, failed {}\n",
+        report.cached, report.already_cached, report.uncacheable, report.failed
+    );
+    let _ = writeln!(out, "{:<32} {:>10} {:>10}", "TIER", "CAPACITY", "PRELOADED");
+    for tier in &report.tiers {
+        let _ = writeln!(
+            out,
+            "{:<32} {:>10} {:>10}",
+            tier.tier, tier.capacity, tier.preloaded
+        );
+    }
+    let held: usize = report.tiers.iter().map(|tier| tier.preloaded).sum();
+    let evicted = (report.cached + report.already_cached).saturating_sub(held);
+    if evicted > 0 {
+        let _ = writeln!(out, "{evicted} entries did not fit and were evicted again");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests_parse_and_reports_count_what_did_not_fit() {
+        let manifest = "# warm-up\n/video/intro/seg=0\n\n/video/poster  poster.jpg\n";
+        let entries = parse_manifest(manifest, Path::new("/srv/launch")).unwrap();
+        assert_eq!(
+            entries,
+            [
+                Entry::Fetch(Name::from_uri("/video/intro/seg=0").unwrap()),
+                Entry::Import(
+                    Name::from_uri("/video/poster").unwrap(),
+                    PathBuf::from("/srv/launch/poster.jpg")
+                ),
+            ]
+        );
+        let error = parse_manifest("/a\n/b c d\n", Path::new(".")).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"));
+
+        let dir = std::env::temp_dir().join(format!("udcn-preload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("poster.jpg"), b"two\nlines").unwrap();
+        let body = encode(&parse_manifest(manifest, &dir).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            decode(&body).unwrap(),
+            [
+                Item::Fetch(Name::from_uri("/video/intro/seg=0").unwrap()),
+                Item::Import(
+                    Name::from_uri("/video/poster").unwrap(),
+                    b"two\nlines".to_vec()
+                ),
+            ]
+        );
+        assert!(decode(&body[..body.len() - 1]).is_err());
+        assert!(encode(&[Entry::Import(Name::from_uri("/a").unwrap(), dir)]).is_err());
+
+        let request: Request = "/srv/launch/manifest.txt upstream=192.0.2.1:6363"
+            .parse()
+            .unwrap();
+        assert_eq!(request.segment_size, SegmentSize::Auto);
+        assert_eq!(request.to_string().parse::<Request>().unwrap(), request);
+        assert!("/m segment_size=0".parse::<Request>().is_err());
+        assert!("/m colour=red".parse::<Request>().is_err());
+
+        let report = Report {
+            cached: 90,
+            already_cached: 10,
+            failed: 2,
+            tiers: vec![
+                Tier {
+                    tier: "(shared)".to_string(),
+                    capacity: 64,
+                    preloaded: 64,
+                },
+                Tier {
+                    tier: "(pinned)".to_string(),
+                    capacity: 256,
+                    preloaded: 6,
+                },
+            ],
+            ..Report::default()
+        };
+        let out = format_report(&report);
+        assert!(out.starts_with("Cached 90, already cached 10, uncacheable 0, failed 2\n"));
+        assert!(out.lines().nth(2).unwrap().ends_with(" 64"));
+        assert!(out.ends_with("30 entries did not fit and were evicted again\n"));
+    }
+}
@@ -0,0 +1,353 @@
+//! Running the daemon with the capabilities it needs instead of as full root.
+//!
+//! Loading and attaching the XDP program takes CAP_BPF, CAP_NET_ADMIN and CAP_PERFMON (CAP_SYS_ADMIN
+//! covers the first and last on kernels that predate them). Once the program is attached, the
+//! daemon keeps only those, optionally as an unprivileged `--user`, and hands the control socket
+//! and pinned maps to `--group` so operators in it can use the CLI without sudo. CAP_NET_RAW is
+//! kept as well if the daemon has it, for creating Ethernet faces.
+
+use std::{
+    ffi::CString,
+    fs,
+    os::unix::fs::{chown, PermissionsExt as _},
+    path::Path,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_NET_RAW: u32 = 13;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// prctl reads its arguments as unsigned longs; passing them as such keeps the upper half of each
+/// register defined on 64-bit targets.
+const NO_ARG: libc::c_ulong = 0;
+
+fn bit(cap: u32) -> u64 {
+    1 << cap
+}
+
+/// The effective capability set, from `/proc/self/status`.
+fn effective() -> anyhow::Result<u64> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    parse_cap_eff(&status).context("no CapEff in /proc/self/status")
+}
+
+fn parse_cap_eff(status: &str) -> Option<u64> {
+    let caps = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(caps.trim(), 16).ok()
+}
+
+/// The capabilities the daemon lacks, by name.
+fn missing(caps: u64) -> Vec<&'static str> {
+    let has = |cap| caps & bit(cap) != 0;
+    let mut missing = Vec::new();
+    if !has(CAP_BPF) && !has(CAP_SYS_ADMIN) {
+        missing.push("CAP_BPF");
+    }
+    if !has(CAP_NET_ADMIN) {
+        missing.push("CAP_NET_ADMIN");
+    }
+    if !has(CAP_PERFMON) && !has(CAP_SYS_ADMIN) {
+        missing.push("CAP_PERFMON");
+    }
+    missing
+}
+
+/// How to grant the capabilities the daemon needs without running it as root.
+pub const SETCAP_HINT: &str = "sudo setcap cap_bpf,cap_net_admin,cap_perfmon+ep <path to udcn>";
+
+/// The capabilities this process lacks to load and attach the program, by name.
+pub fn missing_capabilities() -> anyhow::Result<Vec<&'static str>> {
+    Ok(missing(effective()?))
+}
+
+/// Fail with the capabilities to grant if the daemon cannot load and attach the program.
+pub fn check() -> anyhow::Result<()> {
+    let missing = missing_capabilities()?;
+    anyhow::ensure!(
+        missing.is_empty(),
+        "the daemon needs {}; run it as root or grant them with `{SETCAP_HINT}`",
+        missing.join(", ")
+    );
+    Ok(())
+}
+
+/// The uid and primary gid of `user`.
+pub fn lookup_user(user: &str) -> anyhow::Result<(u32, u32)> {
+    let name = CString::new(user)?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    anyhow::ensure!(!passwd.is_null(), "no user '{user}'");
+    Ok(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) })
+}
+
+pub fn lookup_group(group: &str) -> anyhow::Result<u32> {
+    let name = CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    anyhow::ensure!(!entry.is_null(), "no group '{group}'");
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Give `path`, and everything under it, to `uid` and `gid`, with read and write access for the
+/// group.
+pub fn hand_over(path: &Path, uid: Option<u32>, gid: Option<u32>) -> anyhow::Result<()> {
+    chown(path, uid, gid).with_context(|| format!("failed to chown {}", path.display()))?;
+    let metadata = fs::metadata(path)?;
+    if gid.is_some() {
+        let mut permissions = metadata.permissions();
+        // Directories also need to be searchable by the group
+        let group = if metadata.is_dir() { 0o070 } else { 0o060 };
+        permissions.set_mode(permissions.mode() | group);
+        fs::set_permissions(path, permissions)?;
+    }
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            hand_over(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Keep only the capabilities the running daemon still uses, becoming `user` (a uid and gid)
+/// first if given. CAP_NET_RAW is kept for Ethernet faces. CAP_NET_ADMIN stays ambient so `ip`
+/// can reattach chained programs on exit.
+///
+/// Capabilities belong to threads, not processes, and the daemon drops them from inside a
+/// multi-threaded runtime. The calling thread drops them itself and signals every other thread to
+/// do the same, as libc does for setuid; every thread's CapEff is then checked.
+pub fn drop_privileges(user: Option<(u32, u32)>) -> anyhow::Result<()> {
+    let caps = effective()?;
+    let mut keep = caps & (bit(CAP_BPF) | bit(CAP_NET_ADMIN) | bit(CAP_NET_RAW) | bit(CAP_PERFMON));
+    if caps & bit(CAP_BPF) == 0 {
+        keep |= caps & bit(CAP_SYS_ADMIN);
+    }
+    KEEP.store(keep, Ordering::SeqCst);
+
+    if let Some((uid, gid)) = user {
+        // Without this, changing uid clears the permitted set
+        on_every_thread(KEEP_CAPS)?;
+        unsafe {
+            // libc applies these to every thread
+            check_errno(libc::setgroups(0, std::ptr::null()), "setgroups")?;
+            check_errno(libc::setresgid(gid, gid, gid), "setresgid")?;
+            check_errno(libc::setresuid(uid, uid, uid), "setresuid")?;
+        }
+    }
+    on_every_thread(SET_CAPS)?;
+
+    // Threads started meanwhile inherit whatever their creator had; go over them again
+    for _ in 0..3 {
+        let privileged = privileged_threads(keep)?;
+        if privileged.is_empty() {
+            return Ok(());
+        }
+        signal(&privileged, SET_CAPS);
+    }
+    let privileged = privileged_threads(keep)?;
+    anyhow::ensure!(
+        privileged.is_empty(),
+        "threads {privileged:?} kept capabilities beyond {keep:#x}"
+    );
+    Ok(())
+}
+
+/// What the signalled threads do.
+const KEEP_CAPS: u8 = 0;
+const SET_CAPS: u8 = 1;
+
+static KEEP: AtomicU64 = AtomicU64::new(0);
+static TASK: AtomicU8 = AtomicU8::new(SET_CAPS);
+static DONE: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+/// Run `task` on the calling thread and every other thread of the process.
+fn on_every_thread(task: u8) -> anyhow::Result<()> {
+    anyhow::ensure!(run_task(task) == 0, "{}", task_name(task));
+    let me = unsafe { libc::gettid() };
+    let others: Vec<i32> = threads()?.into_iter().filter(|tid| *tid != me).collect();
+    let failed = signal(&others, task);
+    anyhow::ensure!(
+        failed == 0,
+        "{} failed on {failed} thread(s)",
+        task_name(task)
+    );
+    Ok(())
+}
+
+fn task_name(task: u8) -> &'static str {
+    match task {
+        KEEP_CAPS => "PR_SET_KEEPCAPS",
+        _ => "capset",
+    }
+}
+
+/// Have each of `tids` run `task`, waiting up to a second for them. Returns how many failed.
+fn signal(tids: &[i32], task: u8) -> usize {
+    let signal = libc::SIGRTMAX() - 1;
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+    TASK.store(task, Ordering::SeqCst);
+    DONE.store(0, Ordering::SeqCst);
+    FAILED.store(0, Ordering::SeqCst);
+    let pid = std::process::id() as libc::c_long;
+    // Threads that exited meanwhile cannot be signalled, and need not be
+    let sent = tids
+        .iter()
+        .filter(|tid| unsafe {
+            libc::syscall(
+                libc::SYS_tgkill,
+                pid,
+                **tid as libc::c_long,
+                signal as libc::c_long,
+            ) == 0
+        })
+        .count();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while DONE.load(Ordering::SeqCst) < sent && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    FAILED.load(Ordering::SeqCst)
+}
+
+extern "C" fn on_signal(_: libc::c_int) {
+    if run_task(TASK.load(Ordering::SeqCst)) != 0 {
+        FAILED.fetch_add(1, Ordering::SeqCst);
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Run `task` on the calling thread, with nothing but system calls, as signal handlers must.
+/// Returns 0 on success.
+fn run_task(task: u8) -> libc::c_int {
+    if task == KEEP_CAPS {
+        return unsafe {
+            libc::prctl(
+                libc::PR_SET_KEEPCAPS,
+                1 as libc::c_ulong,
+                NO_ARG,
+                NO_ARG,
+                NO_ARG,
+            )
+        };
+    }
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: i32,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    let keep = KEEP.load(Ordering::SeqCst);
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [0, 32].map(|shift| {
+        let set = (keep >> shift) as u32;
+        CapData {
+            effective: set,
+            permitted: set,
+            inheritable: set,
+        }
+    });
+    unsafe {
+        let ret = libc::syscall(libc::SYS_capset, &header, data.as_ptr()) as libc::c_int;
+        if ret != 0 || keep & bit(CAP_NET_ADMIN) == 0 {
+            return ret;
+        }
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+            CAP_NET_ADMIN as libc::c_ulong,
+            NO_ARG,
+            NO_ARG,
+        )
+    }
+}
+
+/// The ids of the process's threads.
+fn threads() -> anyhow::Result<Vec<i32>> {
+    let mut tids = Vec::new();
+    for entry in fs::read_dir("/proc/self/task").context("failed to list threads")? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|tid| tid.parse().ok()) {
+            tids.push(tid);
+        }
+    }
+    Ok(tids)
+}
+
+/// The threads whose CapEff, from `/proc/self/task/<tid>/status`, holds more than `keep`.
+fn privileged_threads(keep: u64) -> anyhow::Result<Vec<i32>> {
+    let mut privileged = Vec::new();
+    for tid in threads()? {
+        // A thread that exited since it was listed holds nothing
+        let Ok(status) = fs::read_to_string(format!("/proc/self/task/{tid}/status")) else {
+            continue;
+        };
+        let caps = parse_cap_eff(&status).context("no CapEff in a thread's status")?;
+        if caps & !keep != 0 {
+            privileged.push(tid);
+        }
+    }
+    Ok(privileged)
+}
+
+fn check_errno(ret: libc::c_int, call: &str) -> anyhow::Result<()> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("{call} failed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_capabilities() {
+        let status = "Name:\tudcn\nCapInh:\t0000000000000000\nCapEff:\t000000c000001000\n";
+        let caps = parse_cap_eff(status).unwrap();
+        assert_eq!(caps, bit(CAP_NET_ADMIN) | bit(CAP_PERFMON) | bit(CAP_BPF));
+        assert!(missing(caps).is_empty());
+
+        // Root on a kernel without CAP_BPF and CAP_PERFMON
+        assert!(missing(bit(CAP_SYS_ADMIN) | bit(CAP_NET_ADMIN)).is_empty());
+        assert_eq!(missing(bit(CAP_BPF)), ["CAP_NET_ADMIN", "CAP_PERFMON"]);
+        assert_eq!(missing(0).len(), 3);
+    }
+
+    #[test]
+    fn test_other_threads_are_signalled() {
+        // Setting the capabilities the process already has changes nothing
+        let caps = effective().unwrap();
+        KEEP.store(caps, Ordering::SeqCst);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            tx.send(unsafe { libc::gettid() }).unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+        });
+        let tid = rx.recv().unwrap();
+        assert_eq!(signal(&[tid], SET_CAPS), 0);
+        assert_eq!(DONE.load(Ordering::SeqCst), 1);
+        assert!(!privileged_threads(caps).unwrap().contains(&tid));
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! Versioned content that changes while it is served (`serve --file --version`).
+//!
+//! The file is polled, and whenever its content changes it is republished under a new version.
+//! The version it replaces is re-signed at once with a FreshnessPeriod of
+//! [`REPLACED_FRESHNESS_MS`], so that caches soon stop handing it out and version discovery finds
+//! the new one, but stays available for a grace period so that consumers part way through
+//! fetching it can finish. Then it is removed.
+//!
+//! Published with a manifest, a replaced version keeps its FreshnessPeriod instead: re-signing
+//! its segments would change the digests its manifest lists, failing the consumers still fetching
+//! it. Version discovery still finds the new one, the newest of the fresh versions.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context as _;
+use log::{info, warn};
+use tokio::time;
+use udcn_client::{
+    manifest,
+    segments::{implicit_digest, sha256_signer},
+    SegmentStore,
+};
+use udcn_common::Name;
+
+/// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// FreshnessPeriod of a version once a newer one replaces it.
+pub const REPLACED_FRESHNESS_MS: u64 = 1000;
+
+/// Publishes new versions of content under one name into a store.
+pub struct Publisher<F> {
+    name: Name,
+    segment_size: usize,
+    freshness_ms: Option<u64>,
+    grace: Duration,
+    next_version: F,
+    /// Whether each version's segments are published unsigned, under a signed manifest.
+    manifest: bool,
+    /// The version served as the latest, and the SHA-256 of its content.
+    current: Option<(Name, [u8; 32])>,
+    /// Replaced versions, and when each is removed.
+    replaced: VecDeque<(Name, Instant)>,
+}
+
+impl<F: FnMut() -> anyhow::Result<u64>> Publisher<F> {
+    pub fn new(
+        name: Name,
+        segment_size: usize,
+        freshness_ms: Option<u64>,
+        grace: Duration,
+        next_version: F,
+    ) -> Self {
+        Self {
+            name,
+            segment_size,
+            freshness_ms,
+            grace,
+            next_version,
+            manifest: false,
+            current: None,
+            replaced: VecDeque::new(),
+        }
+    }
+
+    /// Publish each version with a manifest of its segments' digests.
+    pub fn set_manifest(&mut self, manifest: bool) {
+        self.manifest = manifest;
+    }
+
+    /// Publish `content` under the next version, unless it is what the latest version holds.
+    /// Returns the new version's name.
+    pub fn publish(
+        &mut self,
+        store: &RwLock<SegmentStore>,
+        content: &[u8],
+    ) -> anyhow::Result<Option<Name>> {
+        let digest = implicit_digest(content);
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(_, current)| *current == digest)
+        {
+            return Ok(None);
+        }
+        let versioned = self.name.clone().append_version((self.next_version)()?);
+
+        let mut store = store.write().unwrap();
+        let publish = if self.manifest {
+            manifest::publish
+        } else {
+            SegmentStore::publish
+        };
+        let segments = publish(
+            &mut store,
+            &versioned,
+            content,
+            self.segment_size,
+            self.freshness_ms,
+            sha256_signer,
+        );
+        info!("Published {segments} segment(s) under '{versioned}'");
+        if let Some((previous, _)) = self.current.replace((versioned.clone(), digest)) {
+            if !self.manifest {
+                store.set_freshness(&previous, REPLACED_FRESHNESS_MS, sha256_signer);
+            }
+            info!(
+                "Serving '{previous}' for another {} s",
+                self.grace.as_secs()
+            );
+            self.replaced
+                .push_back((previous, Instant::now() + self.grace));
+        }
+        Ok(Some(versioned))
+    }
+
+    /// Remove the replaced versions whose grace period is over.
+    pub fn retire(&mut self, store: &RwLock<SegmentStore>) {
+        let now = Instant::now();
+        while let Some((name, _)) = self.replaced.front().filter(|(_, until)| *until <= now) {
+            let removed = store.write().unwrap().remove(name);
+            info!("Removed {removed} segment(s) of replaced version '{name}'");
+            self.replaced.pop_front();
+        }
+    }
+
+    /// Republish `file` each time its content changes. Fails only if the file is not there to
+    /// begin with.
+    pub async fn watch(&mut self, file: &Path, store: &RwLock<SegmentStore>) -> anyhow::Result<()> {
+        let mut last = modified(file)?;
+        let mut interval = time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.retire(store);
+            // An editor may replace the file rather than write to it, leaving it briefly missing
+            let Ok(now) = modified(file) else {
+                continue;
+            };
+            if now == last {
+                continue;
+            }
+            last = now;
+            let published = fs::read(file)
+                .with_context(|| format!("failed to read {}", file.display()))
+                .and_then(|content| self.publish(store, &content));
+            if let Err(e) = published {
+                warn!("failed to republish {}: {e:#}", file.display());
+            }
+        }
+    }
+}
+
+fn modified(file: &Path) -> anyhow::Result<(SystemTime, u64)> {
+    let metadata =
+        fs::metadata(file).with_context(|| format!("failed to read {}", file.display()))?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{Data, Interest};
+
+    use super::*;
+
+    #[test]
+    fn test_republish_keeps_replaced_version_for_grace() {
+        let store = RwLock::new(SegmentStore::new());
+        let mut version = 0;
+        let mut publisher =
+            Publisher::new(Name::from("/doc"), 4, Some(60_000), Duration::ZERO, || {
+                version += 1;
+                Ok(version)
+            });
+        let v1 = publisher.publish(&store, b"first").unwrap().unwrap();
+        assert_eq!(publisher.publish(&store, b"first").unwrap(), None);
+        let v2 = publisher.publish(&store, b"second").unwrap().unwrap();
+        assert_eq!(v2, Name::from("/doc/v=2"));
+
+        let discover = Interest::builder()
+            .name("/doc")
+            .can_be_prefix(true)
+            .must_be_fresh(true)
+            .build();
+        let latest = Data::decode(&store.read().unwrap().find(&discover).unwrap()).unwrap();
+        assert_eq!(latest.name, v2.clone().append_segment(0));
+        let replaced = Data::decode(
+            &store
+                .read()
+                .unwrap()
+                .get(&v1.clone().append_segment(1))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            replaced.meta.freshness_period_ms,
+            Some(REPLACED_FRESHNESS_MS)
+        );
+
+        publisher.retire(&store);
+        assert!(store.read().unwrap().get(&v1.append_segment(0)).is_none());
+        assert_eq!(store.read().unwrap().len(), 2);
+    }
+}
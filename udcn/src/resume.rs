@@ -0,0 +1,173 @@
+//! State of a resumable `udcn get --resume`, in a file next to the output.
+//!
+//! Segments are written to the output in order as they arrive, and each one is then recorded in
+//! the state file as a `<segment> <bytes>` line, after a first `name <name>` line naming the
+//! version being fetched. A fetch cut short leaves both behind; run again, it keeps the segments
+//! recorded, drops whatever the output holds beyond them and fetches from the next segment on.
+//! The state file is removed once the last segment is in.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use udcn_common::Name;
+
+/// Segments of one name already in the output.
+#[derive(Debug, Default, PartialEq)]
+struct Received {
+    name: Option<Name>,
+    segments: u64,
+    bytes: u64,
+    /// Bytes of the state file taken up by whole lines.
+    recorded: u64,
+}
+
+impl Received {
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        // The last line may have been cut short, so only whole lines count
+        let mut lines = contents
+            .split_inclusive('\n')
+            .filter_map(|line| line.strip_suffix('\n'));
+        let Some(first) = lines.next() else {
+            return Ok(Self::default());
+        };
+        let name = first
+            .strip_prefix("name ")
+            .context("expected `name <name>` first")?;
+        let mut received = Self {
+            name: Some(Name::from_uri(name)?),
+            recorded: first.len() as u64 + 1,
+            ..Self::default()
+        };
+        for line in lines {
+            let (segment, bytes): (u64, u64) = line
+                .split_once(' ')
+                .and_then(|(segment, bytes)| Some((segment.parse().ok()?, bytes.parse().ok()?)))
+                .with_context(|| format!("expected `<segment> <bytes>`, not '{line}'"))?;
+            anyhow::ensure!(
+                segment == received.segments,
+                "segment {segment} recorded out of order"
+            );
+            received.segments += 1;
+            received.bytes += bytes;
+            received.recorded += line.len() as u64 + 1;
+        }
+        Ok(received)
+    }
+}
+
+pub struct Resume {
+    path: PathBuf,
+    received: Received,
+    file: Option<File>,
+}
+
+impl Resume {
+    /// Load the state of a fetch into `output`, if one was cut short.
+    pub fn open(output: &Path) -> anyhow::Result<Self> {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".resume");
+        let path = PathBuf::from(path);
+        let received = match fs::read_to_string(&path) {
+            Ok(contents) => Received::parse(&contents)
+                .with_context(|| format!("invalid resume state {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Received::default(),
+            Err(e) => return Err(e).context(format!("failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path,
+            received,
+            file: None,
+        })
+    }
+
+    /// The name being fetched, if a fetch was cut short.
+    pub fn name(&self) -> Option<&Name> {
+        self.received.name.as_ref()
+    }
+
+    /// The first segment still to fetch, and how many bytes of the output hold the ones before.
+    pub fn position(&self) -> (u64, u64) {
+        (self.received.segments, self.received.bytes)
+    }
+
+    /// Go on fetching `name`, starting over unless it is what was being fetched.
+    pub fn start(&mut self, name: &Name) -> anyhow::Result<()> {
+        let open = || -> std::io::Result<File> {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            if self.name() == Some(name) {
+                // Drop a line cut short
+                file.set_len(self.received.recorded)?;
+            } else {
+                file.set_len(0)?;
+                writeln!(file, "name {name}")?;
+            }
+            Ok(file)
+        };
+        let file = open().with_context(|| format!("failed to open {}", self.path.display()))?;
+        if self.name() != Some(name) {
+            self.received = Received {
+                name: Some(name.clone()),
+                ..Received::default()
+            };
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Record that `segment`, `bytes` long, is in the output.
+    pub fn record(&mut self, segment: u64, bytes: usize) -> anyhow::Result<()> {
+        let file = self.file.as_mut().context("resume state not started")?;
+        writeln!(file, "{segment} {bytes}")
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.received.segments = segment + 1;
+        self.received.bytes += bytes as u64;
+        Ok(())
+    }
+
+    /// The fetch is complete: there is nothing left to resume.
+    pub fn finish(self) -> anyhow::Result<()> {
+        fs::remove_file(&self.path)
+            .with_context(|| format!("failed to remove {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_keeps_recorded_segments() {
+        let output = std::env::temp_dir().join(format!("udcn-resume-{}", std::process::id()));
+        let name = Name::from("/file/v=3");
+
+        let mut resume = Resume::open(&output).unwrap();
+        assert_eq!((resume.name(), resume.position()), (None, (0, 0)));
+        resume.start(&name).unwrap();
+        resume.record(0, 1024).unwrap();
+        resume.record(1, 1024).unwrap();
+        // Cut short in the middle of recording segment 2
+        write!(resume.file.as_mut().unwrap(), "2 10").unwrap();
+
+        let mut resume = Resume::open(&output).unwrap();
+        assert_eq!(resume.name(), Some(&name));
+        assert_eq!(resume.position(), (2, 2048));
+        resume.start(&name).unwrap();
+        resume.record(2, 7).unwrap();
+        let mut resume = Resume::open(&output).unwrap();
+        assert_eq!(resume.position(), (3, 2055));
+        resume.start(&Name::from("/file/v=4")).unwrap();
+        assert_eq!(resume.position(), (0, 0));
+        resume.finish().unwrap();
+
+        assert!(Received::parse("name /a\n1 10\n").is_err());
+        assert!(Received::parse("0 10\n").is_err());
+        assert_eq!(Received::parse("name /a\n0 10").unwrap().segments, 0);
+    }
+}
@@ -0,0 +1,287 @@
+//! Prefixes served behind the network stack, for `run --nack-unroutable`.
+//!
+//! With the flag set, the datapath answers an Interest it cannot answer from the content store
+//! with a NoRoute Nack straight away unless its name falls under one of these prefixes, so junk
+//! traffic never reaches the slow path. The routes are the `--route` prefixes, for producers on
+//! the host, and the prefixes of every tunnel. They take the same form as `--no-cache` prefixes
+//! and are looked up the same way.
+//!
+//! `udcn route add` routes a prefix at runtime and keeps it one prefix per line, so that the
+//! router comes back from a power cycle with the same forwarding table, until `udcn route remove`
+//! drops it. Routes added with `--ephemeral` last until the daemon stops. A prefix both kept and
+//! configured is routed once, and stays routed while either holds it.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use aya::maps::HashMap;
+
+use crate::{nocache::NoCachePrefix, tunnels::TunnelConfig};
+
+pub const DEFAULT_ROUTES_PATH: &str = "/var/lib/udcn/routes";
+
+#[derive(Default)]
+pub struct Routes {
+    /// The `--route` prefixes and those of the tunnels.
+    configured: Vec<NoCachePrefix>,
+    /// Added at runtime and kept at `path`.
+    saved: Vec<NoCachePrefix>,
+    /// Added at runtime with `--ephemeral`.
+    ephemeral: Vec<NoCachePrefix>,
+    path: Option<PathBuf>,
+    /// Entries in the datapath's `ROUTES` map.
+    capacity: usize,
+}
+
+impl Routes {
+    /// The `configured` routes, with those kept at `path` alongside, for a `ROUTES` map of
+    /// `capacity` entries.
+    pub fn load(
+        configured: &[NoCachePrefix],
+        path: impl Into<PathBuf>,
+        capacity: u32,
+    ) -> anyhow::Result<Self> {
+        let path = path.into();
+        let mut routes = Self {
+            configured: configured.to_vec(),
+            path: Some(path.clone()),
+            capacity: capacity as usize,
+            ..Self::default()
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.is_empty()) {
+                    let route: NoCachePrefix = line
+                        .parse()
+                        .with_context(|| format!("invalid line {line:?} in {}", path.display()))?;
+                    if !routes.saved.contains(&route) {
+                        routes.saved.push(route);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+        Ok(routes)
+    }
+
+    /// Every routed prefix, each once.
+    pub fn all(&self) -> Vec<NoCachePrefix> {
+        let mut all = Vec::new();
+        for route in self
+            .configured
+            .iter()
+            .chain(&self.saved)
+            .chain(&self.ephemeral)
+        {
+            if !all.contains(route) {
+                all.push(route.clone());
+            }
+        }
+        all
+    }
+
+    /// The routes added at runtime and kept, as `route-add` takes them.
+    pub fn saved(&self) -> &[NoCachePrefix] {
+        &self.saved
+    }
+
+    /// Route `prefix`, keeping it unless `ephemeral`. Adding it again switches between the two.
+    /// Nothing changes if the kept routes cannot be written.
+    pub fn add(&mut self, prefix: NoCachePrefix, ephemeral: bool) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.all().contains(&prefix) || self.all().len() < self.capacity,
+            "at most {} prefixes can be routed",
+            self.capacity
+        );
+        let mut saved = self.saved.clone();
+        saved.retain(|route| *route != prefix);
+        if !ephemeral {
+            saved.push(prefix.clone());
+        }
+        self.persist(&saved)?;
+        self.saved = saved;
+        self.ephemeral.retain(|route| *route != prefix);
+        if ephemeral {
+            self.ephemeral.push(prefix);
+        }
+        Ok(())
+    }
+
+    /// Drop the route added for `prefix`, returning whether the configuration still routes it.
+    /// Fails for a prefix no `route add` routed. Nothing changes if the kept routes cannot be
+    /// written.
+    pub fn remove(&mut self, prefix: &NoCachePrefix) -> anyhow::Result<bool> {
+        let configured = self.configured.contains(prefix);
+        if !self.saved.contains(prefix) && !self.ephemeral.contains(prefix) {
+            anyhow::ensure!(
+                !configured,
+                "'{}' is routed by --route or a tunnel, not `udcn route add`",
+                prefix.0
+            );
+            anyhow::bail!("'{}' is not routed", prefix.0);
+        }
+        if self.saved.contains(prefix) {
+            let mut saved = self.saved.clone();
+            saved.retain(|route| route != prefix);
+            self.persist(&saved)?;
+            self.saved = saved;
+        }
+        self.ephemeral.retain(|route| route != prefix);
+        Ok(configured)
+    }
+
+    pub fn format_table(&self) -> String {
+        let mut out = format!("{:<32} {}\n", "PREFIX", "SOURCE");
+        for route in self.all() {
+            let sources: Vec<&str> = [
+                (&self.configured, "config"),
+                (&self.saved, "saved"),
+                (&self.ephemeral, "ephemeral"),
+            ]
+            .into_iter()
+            .filter(|(routes, _)| routes.contains(&route))
+            .map(|(_, source)| source)
+            .collect();
+            let _ = writeln!(out, "{:<32} {}", route.0.to_string(), sources.join(", "));
+        }
+        out
+    }
+
+    fn persist(&self, saved: &[NoCachePrefix]) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write(path, saved).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn write(path: &Path, routes: &[NoCachePrefix]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents: String = routes
+        .iter()
+        .map(|route| format!("{}\n", route.0))
+        .collect();
+    fs::write(path, contents)
+}
+
+/// The `--route` prefixes and those the tunnels relay. Fails if a tunnel relays every Interest,
+/// which leaves no name unroutable.
+pub fn collect(
+    routes: &[NoCachePrefix],
+    tunnels: &[TunnelConfig],
+) -> anyhow::Result<Vec<NoCachePrefix>> {
+    let mut collected = routes.to_vec();
+    for tunnel in tunnels {
+        anyhow::ensure!(
+            !tunnel.prefixes.is_empty(),
+            "the tunnel to {} relays every Interest, so none is unroutable; give it prefixes",
+            tunnel.remote
+        );
+        for prefix in &tunnel.prefixes {
+            let route: NoCachePrefix = prefix.to_string().parse()?;
+            if !collected.contains(&route) {
+                collected.push(route);
+            }
+        }
+    }
+    Ok(collected)
+}
+
+/// Tell the datapath which prefixes are routed, in a `ROUTES` map of `capacity` entries.
+pub fn install(
+    ebpf: &mut aya::Ebpf,
+    routes: &[NoCachePrefix],
+    capacity: u32,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        routes.len() <= capacity as usize,
+        "at most {capacity} prefixes can be routed"
+    );
+    let mut map: HashMap<_, u32, u8> = HashMap::try_from(ebpf.map_mut("ROUTES").unwrap())?;
+    for route in routes {
+        map.insert(route.key(), 1, 0)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{Name, ROUTES_CAPACITY};
+
+    use super::*;
+
+    #[test]
+    fn test_tunnel_prefixes_are_routes() {
+        let tunnel: TunnelConfig =
+            toml::from_str("remote = \"site-b:6363\"\nprefixes = [\"/site-b\", \"/local/app\"]\n")
+                .unwrap();
+        let local: NoCachePrefix = "/local/app".parse().unwrap();
+        let routes = collect(std::slice::from_ref(&local), std::slice::from_ref(&tunnel)).unwrap();
+        assert_eq!(routes, [local, "/site-b".parse().unwrap()]);
+
+        let everything = TunnelConfig {
+            prefixes: Vec::new(),
+            ..tunnel.clone()
+        };
+        assert!(collect(&[], &[everything]).is_err());
+        let long = TunnelConfig {
+            prefixes: vec![Name::from("/a/b/c/d/e")],
+            ..tunnel
+        };
+        assert!(collect(&[], &[long]).is_err());
+    }
+
+    #[test]
+    fn test_added_routes_survive_reload_alongside_config() {
+        let path = std::env::temp_dir().join(format!("udcn-routes-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let prefix = |uri: &str| uri.parse::<NoCachePrefix>().unwrap();
+        let configured = [prefix("/sensors")];
+
+        let mut routes = Routes::load(&configured, &path, ROUTES_CAPACITY).unwrap();
+        routes.add(prefix("/video"), false).unwrap();
+        routes.add(prefix("/sensors"), false).unwrap();
+        routes.add(prefix("/scratch"), true).unwrap();
+        assert_eq!(routes.all().len(), 3);
+        assert!(routes.remove(&prefix("/sensors")).unwrap());
+        assert!(routes.remove(&prefix("/sensors")).is_err());
+        assert!(routes.remove(&prefix("/audio")).is_err());
+
+        let routes = Routes::load(&configured, &path, ROUTES_CAPACITY).unwrap();
+        assert_eq!(routes.all(), [prefix("/sensors"), prefix("/video")]);
+        let table = routes.format_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].ends_with(" config"), "{table}");
+        assert!(lines[2].ends_with(" saved"), "{table}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "/video\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_writes_change_nothing() {
+        // A directory can't be created under a file
+        let file = std::env::temp_dir().join(format!("udcn-routes-file-{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let prefix = |uri: &str| uri.parse::<NoCachePrefix>().unwrap();
+        let mut routes = Routes {
+            saved: vec![prefix("/video")],
+            path: Some(file.join("routes")),
+            capacity: ROUTES_CAPACITY as usize,
+            ..Routes::default()
+        };
+
+        assert!(routes.add(prefix("/audio"), false).is_err());
+        assert!(routes.add(prefix("/video"), true).is_err());
+        assert!(routes.remove(&prefix("/video")).is_err());
+        assert_eq!(routes.saved(), [prefix("/video")]);
+        assert_eq!(routes.all(), [prefix("/video")]);
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+//! End-to-end checks of the datapath on a throwaway network.
+//!
+//! `udcn selftest` builds a routed topology out of network namespaces, runs the daemon on the
+//! router and drives producers and consumers across it by running this same binary inside the
+//! other namespaces:
+//!
+//! ```text
+//! consumer  c0 10.77.1.2 ──┐
+//!                          ├── br0 (lan) ── rt0 10.77.1.1 + 10.77.2.1  router, XDP
+//! producer  p0 10.77.2.2 ──┘
+//! ```
+//!
+//! Both subnets hang off the router's single interface, so Interests and the Data answering them
+//! both arrive on the interface the XDP program is attached to, as they would on a real LAN.
+
+use std::time::Duration;
+
+use anyhow::Context as _;
+use log::{debug, warn};
+use tokio::time::sleep;
+use udcn_client::control;
+use udcn_common::PacketStats;
+
+use crate::netns::{ip, Namespaces};
+
+const LAN: &str = "udcn-st-lan";
+const ROUTER: &str = "udcn-st-router";
+const CONSUMER: &str = "udcn-st-consumer";
+const PRODUCER: &str = "udcn-st-producer";
+
+const PRODUCER_ADDR: &str = "10.77.2.2:6363";
+const CONTENT_NAME: &str = "/udcn/selftest/hello";
+const MISSING_NAME: &str = "/udcn/selftest/missing";
+
+/// Create the namespaces, links and addresses shown in the module documentation.
+fn create_topology(verbose: bool) -> anyhow::Result<Namespaces> {
+    let mut namespaces = Namespaces::new("udcn-st-", verbose);
+    for namespace in [LAN, ROUTER, CONSUMER, PRODUCER] {
+        namespaces.add(namespace)?;
+    }
+
+    ip(&format!("-n {LAN} link add br0 type bridge"))?;
+    ip(&format!("-n {LAN} link set br0 up"))?;
+    for (namespace, dev) in [(ROUTER, "rt0"), (CONSUMER, "c0"), (PRODUCER, "p0")] {
+        let port = format!("{dev}-br");
+        ip(&format!(
+            "link add {dev} netns {namespace} type veth peer name {port} netns {LAN}"
+        ))?;
+        ip(&format!("-n {LAN} link set {port} master br0 up"))?;
+        ip(&format!("-n {namespace} link set {dev} up"))?;
+    }
+
+    ip(&format!("-n {ROUTER} addr add 10.77.1.1/24 dev rt0"))?;
+    ip(&format!("-n {ROUTER} addr add 10.77.2.1/24 dev rt0"))?;
+    ip(&format!("-n {CONSUMER} addr add 10.77.1.2/24 dev c0"))?;
+    ip(&format!("-n {CONSUMER} route add default via 10.77.1.1"))?;
+    ip(&format!("-n {PRODUCER} addr add 10.77.2.2/24 dev p0"))?;
+    ip(&format!("-n {PRODUCER} route add default via 10.77.2.1"))?;
+
+    // Traffic between the subnets leaves the router the way it came in; redirects would
+    // teach the hosts to bypass it
+    for setting in [
+        "net.ipv4.ip_forward=1",
+        "net.ipv4.conf.all.send_redirects=0",
+        "net.ipv4.conf.rt0.send_redirects=0",
+    ] {
+        ip(&format!("netns exec {ROUTER} sysctl -qw {setting}"))?;
+    }
+
+    // Frames bounced with XDP_TX from rt0 are only accepted by its peer when the peer runs
+    // NAPI, which GRO turns on. Without it negative cache replies are dropped
+    if let Err(e) = ip(&format!("netns exec {LAN} ethtool -K rt0-br gro on")) {
+        warn!("failed to enable GRO on rt0-br, Nacks sent by XDP may be lost: {e:#}");
+    }
+
+    Ok(namespaces)
+}
+
+/// Run every check and return how many failed.
+pub async fn run_checks(verbose: bool) -> anyhow::Result<usize> {
+    anyhow::ensure!(
+        unsafe { libc::geteuid() } == 0,
+        "selftest needs root to create namespaces"
+    );
+    anyhow::ensure!(
+        control::stats().await.is_err(),
+        "a udcn daemon is already running; stop it before running the selftest"
+    );
+
+    let namespaces = create_topology(verbose).context("failed to create the test network")?;
+    let mut daemon = namespaces.spawn(
+        ROUTER,
+        ["--iface", "rt0", "run", "--negative-cache-ttl-ms", "5000"],
+    )?;
+    daemon
+        .wait_for_daemon(&control::socket_path())
+        .await
+        .context("failed to start the daemon on the router")?;
+    let _producer = namespaces.spawn(
+        PRODUCER,
+        [
+            "serve",
+            "-n",
+            CONTENT_NAME,
+            "-c",
+            "hello from the selftest",
+            "-b",
+            PRODUCER_ADDR,
+        ],
+    )?;
+    // Give the producer time to bind before the first Interest reaches it
+    sleep(Duration::from_millis(500)).await;
+
+    let checks = Checks {
+        namespaces: &namespaces,
+    };
+    let mut failures = 0;
+    for (name, result) in [
+        (
+            "interest is satisfied through the router",
+            checks.fetch().await,
+        ),
+        (
+            "repeated interest hits the content store",
+            checks.repeat_fetch().await,
+        ),
+        (
+            "nack populates the negative cache",
+            checks.negative_cache().await,
+        ),
+        (
+            "pit drains once interests are answered",
+            checks.pit_drains().await,
+        ),
+        #[cfg(feature = "faults")]
+        (
+            "injected interest drops go unanswered",
+            checks.injected_drops().await,
+        ),
+        #[cfg(feature = "faults")]
+        (
+            "blackholed prefix goes unanswered",
+            checks.blackhole().await,
+        ),
+        #[cfg(feature = "faults")]
+        ("delayed data still arrives", checks.delayed_data().await),
+    ] {
+        match result {
+            Ok(()) => println!("PASS  {name}"),
+            Err(e) => {
+                failures += 1;
+                println!("FAIL  {name}: {e:#}");
+            }
+        }
+    }
+    Ok(failures)
+}
+
+struct Checks<'a> {
+    namespaces: &'a Namespaces,
+}
+
+impl Checks<'_> {
+    async fn fetch(&self) -> anyhow::Result<()> {
+        let (fetched, delta) = self.send(CONTENT_NAME).await?;
+        anyhow::ensure!(fetched, "consumer did not receive Data");
+        expect("interest_received", delta.interest_received, 1)?;
+        expect("data_received", delta.data_received, 1)?;
+        expect("pit_hits", delta.pit_hits, 1)?;
+        expect("cache_hits", delta.cache_hits, 0)
+    }
+
+    async fn repeat_fetch(&self) -> anyhow::Result<()> {
+        let cs = control::request("cs-list").await?;
+        anyhow::ensure!(
+            cs.contains(CONTENT_NAME),
+            "{CONTENT_NAME} missing from cs-list:\n{cs}"
+        );
+
+        let (fetched, delta) = self.send(CONTENT_NAME).await?;
+        anyhow::ensure!(fetched, "consumer did not receive Data");
+        expect("cache_hits", delta.cache_hits, 1)
+    }
+
+    async fn negative_cache(&self) -> anyhow::Result<()> {
+        let (fetched, delta) = self.send(MISSING_NAME).await?;
+        anyhow::ensure!(!fetched, "consumer received Data for {MISSING_NAME}");
+        expect("interest_received", delta.interest_received, 1)?;
+        expect("negative_cache_hits", delta.negative_cache_hits, 0)?;
+
+        let (fetched, delta) = self.send(MISSING_NAME).await?;
+        anyhow::ensure!(!fetched, "consumer received Data for {MISSING_NAME}");
+        expect("negative_cache_hits", delta.negative_cache_hits, 1)
+    }
+
+    async fn pit_drains(&self) -> anyhow::Result<()> {
+        let pit = control::request("pit-list").await?;
+        anyhow::ensure!(pit.lines().count() == 1, "entries left in the PIT:\n{pit}");
+        Ok(())
+    }
+
+    #[cfg(feature = "faults")]
+    async fn injected_drops(&self) -> anyhow::Result<()> {
+        control::request("fault drop-interests 100").await?;
+        let sent = self.send(CONTENT_NAME).await;
+        control::request("fault clear").await?;
+        let (fetched, delta) = sent?;
+        anyhow::ensure!(
+            !fetched,
+            "consumer received Data with every Interest dropped"
+        );
+        expect_injected_drops(&delta)
+    }
+
+    #[cfg(feature = "faults")]
+    async fn blackhole(&self) -> anyhow::Result<()> {
+        control::request("fault blackhole /udcn/selftest").await?;
+        let sent = self.send(CONTENT_NAME).await;
+        control::request("fault restore /udcn/selftest").await?;
+        let (fetched, delta) = sent?;
+        anyhow::ensure!(!fetched, "consumer received Data under a blackholed prefix");
+        expect_injected_drops(&delta)?;
+
+        let (fetched, _) = self.send(CONTENT_NAME).await?;
+        anyhow::ensure!(
+            fetched,
+            "consumer did not receive Data once the prefix was restored"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "faults")]
+    async fn delayed_data(&self) -> anyhow::Result<()> {
+        const DELAY: Duration = Duration::from_millis(500);
+
+        // Cached Data would answer without going through the PIT
+        control::request("cs-flush").await?;
+        control::request(&format!("fault delay-data {}", DELAY.as_millis())).await?;
+        let start = std::time::Instant::now();
+        let sent = self.send(CONTENT_NAME).await;
+        let elapsed = start.elapsed();
+        control::request("fault clear").await?;
+        let (fetched, delta) = sent?;
+        anyhow::ensure!(fetched, "consumer did not receive the delayed Data");
+        expect("pit_hits", delta.pit_hits, 1)?;
+        anyhow::ensure!(
+            elapsed >= DELAY,
+            "Data arrived after {elapsed:?}, before the {DELAY:?} delay"
+        );
+        Ok(())
+    }
+
+    /// Fetch `name` from the consumer namespace, returning whether Data came back and how the
+    /// datapath counters moved meanwhile.
+    async fn send(&self, name: &str) -> anyhow::Result<(bool, PacketStats)> {
+        let before = control::stats().await?;
+        let status = self
+            .namespaces
+            .exec(CONSUMER, ["send", "-n", name, "-t", PRODUCER_ADDR])?;
+        let after = control::stats().await?;
+        debug!("send {name}: {status}");
+        Ok((status.success(), delta(&before, &after)))
+    }
+}
+
+fn expect(counter: &str, actual: u32, expected: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        actual == expected,
+        "{counter} went up by {actual}, expected {expected}"
+    );
+    Ok(())
+}
+
+/// Consumers may retransmit, so any number of injected drops will do.
+#[cfg(feature = "faults")]
+fn expect_injected_drops(delta: &PacketStats) -> anyhow::Result<()> {
+    let drops = delta.drops[udcn_common::DropReason::Injected as usize];
+    anyhow::ensure!(drops > 0, "no Interest counted as an injected drop");
+    Ok(())
+}
+
+fn delta(before: &PacketStats, after: &PacketStats) -> PacketStats {
+    PacketStats {
+        interest_received: after
+            .interest_received
+            .wrapping_sub(before.interest_received),
+        data_received: after.data_received.wrapping_sub(before.data_received),
+        cache_hits: after.cache_hits.wrapping_sub(before.cache_hits),
+        cache_misses: after.cache_misses.wrapping_sub(before.cache_misses),
+        pit_hits: after.pit_hits.wrapping_sub(before.pit_hits),
+        forwards: after.forwards.wrapping_sub(before.forwards),
+        drops: std::array::from_fn(|i| after.drops[i].wrapping_sub(before.drops[i])),
+        negative_cache_hits: after
+            .negative_cache_hits
+            .wrapping_sub(before.negative_cache_hits),
+        duplicate_interests: after
+            .duplicate_interests
+            .wrapping_sub(before.duplicate_interests),
+        cs_admission_rejects: after
+            .cs_admission_rejects
+            .wrapping_sub(before.cs_admission_rejects),
+        fresh_hits: after.fresh_hits.wrapping_sub(before.fresh_hits),
+        stale_hits: after.stale_hits.wrapping_sub(before.stale_hits),
+        uncacheable: after.uncacheable.wrapping_sub(before.uncacheable),
+        prefetch_hits: after.prefetch_hits.wrapping_sub(before.prefetch_hits),
+        cs_probation_hits: after
+            .cs_probation_hits
+            .wrapping_sub(before.cs_probation_hits),
+        cs_protected_hits: after
+            .cs_protected_hits
+            .wrapping_sub(before.cs_protected_hits),
+        unroutable_nacks: after
+            .unroutable_nacks
+            .wrapping_sub(before.unroutable_nacks),
+    }
+}
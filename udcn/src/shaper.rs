@@ -0,0 +1,180 @@
+//! Shaping what a face sends to a byte rate, so an edge node does not saturate a constrained
+//! uplink.
+//!
+//! A token bucket fills at the configured rate up to `burst` bytes. A packet goes out at once if
+//! the bucket is not empty, which may leave it owing bytes, so packets larger than the burst still
+//! get through. Otherwise it waits in a queue of at most `queue` bytes for the bucket to refill,
+//! and is dropped if the queue has no room. The bytes that had to wait and the bytes dropped are
+//! counted for `udcn face list` and the metrics page.
+
+use std::collections::VecDeque;
+
+use tokio::time::{Duration, Instant};
+
+/// A byte rate to send at, and how far a face may burst above it or queue behind it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shaping {
+    pub bytes_per_sec: u64,
+    pub burst_bytes: u64,
+    pub queue_bytes: u64,
+}
+
+impl Shaping {
+    /// A tenth of a second at the rate, but no less than a full-size Ethernet frame.
+    pub fn default_burst(bytes_per_sec: u64) -> u64 {
+        (bytes_per_sec / 10).max(1500)
+    }
+
+    /// A quarter of a second at the rate, so queued packets are not held long, but no less than
+    /// the burst.
+    pub fn default_queue(bytes_per_sec: u64) -> u64 {
+        (bytes_per_sec / 4).max(Self::default_burst(bytes_per_sec))
+    }
+}
+
+/// Bytes a shaper held back or dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShapingStats {
+    /// Bytes that waited in the queue for the bucket to refill.
+    pub shaped_bytes: u64,
+    /// Bytes dropped as the queue was full.
+    pub dropped_bytes: u64,
+}
+
+/// What [`Shaper::offer`] did with a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Send it now.
+    Send,
+    /// Queued, to come out of [`Shaper::release`].
+    Queued,
+    Dropped,
+}
+
+pub struct Shaper {
+    shaping: Shaping,
+    /// Bytes that may be sent now; negative while paying off a packet larger than what was left.
+    tokens: f64,
+    refilled: Instant,
+    /// Packets waiting, each with when it arrived.
+    queue: VecDeque<(Vec<u8>, Instant)>,
+    queued_bytes: u64,
+    stats: ShapingStats,
+}
+
+impl Shaper {
+    pub fn new(shaping: Shaping, now: Instant) -> Self {
+        Self {
+            shaping,
+            tokens: shaping.burst_bytes as f64,
+            refilled: now,
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            stats: ShapingStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ShapingStats {
+        self.stats
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.shaping.bytes_per_sec as f64)
+            .min(self.shaping.burst_bytes as f64);
+        self.refilled = now;
+    }
+
+    /// Take `packet`, which arrived at `arrived`, to send now, queue or drop.
+    pub fn offer(&mut self, packet: &[u8], arrived: Instant, now: Instant) -> Verdict {
+        self.refill(now);
+        let len = packet.len() as u64;
+        if self.queue.is_empty() && self.tokens > 0.0 {
+            self.tokens -= len as f64;
+            return Verdict::Send;
+        }
+        if self.queued_bytes + len > self.shaping.queue_bytes {
+            self.stats.dropped_bytes += len;
+            return Verdict::Dropped;
+        }
+        self.queue.push_back((packet.to_vec(), arrived));
+        self.queued_bytes += len;
+        self.stats.shaped_bytes += len;
+        Verdict::Queued
+    }
+
+    /// When the packet at the head of the queue may go, if any is waiting.
+    pub fn next_release(&self) -> Option<Instant> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        // The bucket has to be above empty again
+        let owed = (-self.tokens).max(0.0) + 1.0;
+        let wait = Duration::from_secs_f64(owed / self.shaping.bytes_per_sec as f64);
+        Some(self.refilled + wait)
+    }
+
+    /// The queued packets that may go now, with when each arrived.
+    pub fn release(&mut self, now: Instant) -> Vec<(Vec<u8>, Instant)> {
+        self.refill(now);
+        let mut released = Vec::new();
+        while self.tokens > 0.0 {
+            let Some((packet, arrived)) = self.queue.pop_front() else {
+                break;
+            };
+            self.tokens -= packet.len() as f64;
+            self.queued_bytes -= packet.len() as u64;
+            released.push((packet, arrived));
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shaper_queues_above_the_rate_and_drops_past_the_queue() {
+        let start = Instant::now();
+        let ms = Duration::from_millis(1);
+        let mut shaper = Shaper::new(
+            Shaping {
+                bytes_per_sec: 100_000,
+                burst_bytes: 1000,
+                queue_bytes: 1500,
+            },
+            start,
+        );
+        let packet = [0u8; 600];
+
+        // The burst lets two through, the second into debt
+        assert_eq!(shaper.offer(&packet, start, start), Verdict::Send);
+        assert_eq!(shaper.offer(&packet, start, start), Verdict::Send);
+        assert_eq!(shaper.offer(&packet, start, start), Verdict::Queued);
+        assert_eq!(shaper.offer(&packet, start, start), Verdict::Queued);
+        assert_eq!(shaper.offer(&packet, start, start), Verdict::Dropped);
+        assert_eq!(
+            shaper.stats(),
+            ShapingStats {
+                shaped_bytes: 1200,
+                dropped_bytes: 600,
+            }
+        );
+
+        // 200 bytes owed take 2 ms to pay off at 100 kB/s
+        let release = shaper.next_release().unwrap();
+        assert!(release > start + 2 * ms && release < start + 3 * ms);
+        assert!(shaper.release(start + ms).is_empty());
+        assert_eq!(shaper.release(release).len(), 1);
+        assert_eq!(shaper.release(release + 5 * ms).len(), 0);
+        let released = shaper.release(release + 7 * ms);
+        assert_eq!(released, [(packet.to_vec(), start)]);
+        assert_eq!(shaper.next_release(), None);
+        // Once the queue drains, packets go straight out again
+        assert_eq!(
+            shaper.offer(&packet, start, release + 20 * ms),
+            Verdict::Send
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Counter snapshots for `udcn stats --save` and `--diff`, to measure what a config change or a
+//! traffic event did.
+//!
+//! A snapshot is the `stats --json` report plus the time it was taken. Snapshots are compared
+//! field by field as JSON, so one saved by an older version, with fewer counters, still diffs:
+//! counters it lacks count as 0.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use serde::Serialize;
+use serde_json::Value;
+use udcn_client::control::TableOccupancy;
+use udcn_common::PacketStats;
+
+/// The counters and the occupancy of each table, as printed by `udcn stats --json`.
+#[derive(Serialize)]
+pub struct Snapshot {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taken_at_unix_ms: Option<u64>,
+    #[serde(flatten)]
+    pub stats: PacketStats,
+    pub tables: Vec<TableOccupancy>,
+}
+
+impl Snapshot {
+    /// A snapshot taken now.
+    pub fn now(stats: PacketStats, tables: Vec<TableOccupancy>) -> Self {
+        Self {
+            taken_at_unix_ms: Some(unix_ms()),
+            stats,
+            tables,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Read a snapshot saved with `--save`, or the output of `stats --json`.
+pub fn load(path: &Path) -> anyhow::Result<Value> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let snapshot: Value = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not a stats snapshot", path.display()))?;
+    anyhow::ensure!(
+        snapshot.is_object(),
+        "{} is not a stats snapshot",
+        path.display()
+    );
+    Ok(snapshot)
+}
+
+/// Every counter, with those broken down by reason flattened to `drops.pit_full` and the like.
+fn counters(snapshot: &Value) -> BTreeMap<String, u64> {
+    let mut counters = BTreeMap::new();
+    let Some(fields) = snapshot.as_object() else {
+        return counters;
+    };
+    for (field, value) in fields {
+        match value {
+            Value::Number(n) if field != "taken_at_unix_ms" => {
+                counters.insert(field.clone(), n.as_u64().unwrap_or(0));
+            }
+            Value::Object(by_reason) => {
+                for (reason, n) in by_reason {
+                    counters.insert(format!("{field}.{reason}"), n.as_u64().unwrap_or(0));
+                }
+            }
+            _ => {}
+        }
+    }
+    counters
+}
+
+fn tables(snapshot: &Value) -> Vec<TableOccupancy> {
+    snapshot
+        .get("tables")
+        .and_then(|tables| serde_json::from_value(tables.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// What changed from `old` to `new`: each counter that moved, and the entries of each table.
+pub fn format_diff(old: &Value, new: &Value) -> String {
+    let mut out = String::new();
+    let taken_at = |snapshot: &Value| snapshot.get("taken_at_unix_ms").and_then(Value::as_u64);
+    match (taken_at(old), taken_at(new)) {
+        (Some(old), Some(new)) => {
+            let _ = writeln!(
+                out,
+                "Changes over {:.1}s:",
+                new.saturating_sub(old) as f64 / 1000.0
+            );
+        }
+        _ => out += "Changes:\n",
+    }
+
+    let (old_counters, new_counters) = (counters(old), counters(new));
+    let mut went_down = false;
+    let mut changed = 0;
+    for (counter, &now) in &new_counters {
+        let before = old_counters.get(counter).copied().unwrap_or(0);
+        if now == before {
+            continue;
+        }
+        changed += 1;
+        went_down |= now < before;
+        let delta = now as i128 - before as i128;
+        let _ = writeln!(out, "  {counter:<32} {delta:>+12}  ({before} -> {now})");
+    }
+    if changed == 0 {
+        out += "  no counter changed\n";
+    }
+    if went_down {
+        out += "  (some counters went down: the daemon was restarted in between)\n";
+    }
+
+    out += "\nTable entries:\n";
+    let old_tables = tables(old);
+    let new_tables = tables(new);
+    for table in &new_tables {
+        let label = format!("{}:", table.table);
+        match old_tables.iter().find(|old| old.table == table.table) {
+            Some(old) => {
+                let delta = table.entries as i64 - old.entries as i64;
+                let _ = writeln!(
+                    out,
+                    "  {label:<32} {delta:>+12}  ({} -> {})",
+                    old.entries, table.entries
+                );
+            }
+            None => {
+                let _ = writeln!(out, "  {label:<32} {:>12}  (new)", table.entries);
+            }
+        }
+    }
+    for old in &old_tables {
+        if !new_tables.iter().any(|table| table.table == old.table) {
+            let _ = writeln!(
+                out,
+                "  {:<32} {:>12}  (gone)",
+                format!("{}:", old.table),
+                ""
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_diff_counters_and_tables() {
+        let old = json!({
+            "taken_at_unix_ms": 1_000, "interest_received": 10, "cache_hits": 4,
+            "drops": { "pit_full": 1, "malformed": 0 },
+            "tables": [
+                { "table": "PIT", "entries": 5, "capacity": 1024 },
+                { "table": "CS_VIDEO", "entries": 2, "capacity": 100 },
+            ],
+        });
+        let new = json!({
+            "taken_at_unix_ms": 3_500, "interest_received": 25, "cache_hits": 4,
+            "drops": { "pit_full": 3, "malformed": 0 }, "uncacheable": 2,
+            "tables": [
+                { "table": "PIT", "entries": 1, "capacity": 1024 },
+                { "table": "CS", "entries": 7, "capacity": 100 },
+            ],
+        });
+        let diff = format_diff(&old, &new);
+        assert!(diff.starts_with("Changes over 2.5s:\n"));
+        assert!(diff.contains("interest_received") && diff.contains("+15  (10 -> 25)"));
+        assert!(diff.contains("drops.pit_full") && diff.contains("+2  (1 -> 3)"));
+        // Counters an older snapshot lacks count from 0
+        assert!(diff.contains("uncacheable") && diff.contains("+2  (0 -> 2)"));
+        assert!(!diff.contains("cache_hits") && !diff.contains("malformed"));
+        assert!(diff.contains("-4  (5 -> 1)"));
+        assert!(diff.contains("CS:") && diff.contains("7  (new)"));
+        assert!(diff.contains("CS_VIDEO:") && diff.contains("(gone)"));
+        assert!(!diff.contains("went down"));
+
+        let restarted = json!({ "interest_received": 3 });
+        let diff = format_diff(&old, &restarted);
+        assert!(diff.starts_with("Changes:\n"));
+        assert!(diff.contains("-7  (10 -> 3)") && diff.contains("went down"));
+    }
+}
@@ -0,0 +1,230 @@
+//! Moving a daemon's warmed state to another host (`udcn state export` and `udcn state import`).
+//!
+//! An export holds what the daemon learnt while running rather than what it was configured with:
+//! the names it was told about, the strategies overridden and routes added at runtime, the content
+//! store pins and an entry for each cached Data, with the payload the Data cache holds for it if
+//! asked for.
+//! Importing it into the daemon of a replacement router, or of the other host in an A/B pair,
+//! caches the entries again, each in the partition that daemon's layout and pins put it in, so it
+//! starts out with a warm cache. Entries it already caches are left as they are.
+//!
+//! Cache timestamps count from the exporting host's boot, so each entry carries its age and the
+//! freshness it has left instead, which the import counts from its own clock. Routes from `--route`
+//! and the tunnels are not exported, as the new host is given the configuration as it is. Neither
+//! are PIT entries, whose consumers retransmit.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout_at, Duration, Instant};
+use udcn_common::{CacheEntry, EventOutcome, TlvType};
+
+use crate::{
+    daemon::Daemon,
+    events::{ktime_ns, EventHub},
+};
+
+/// The version of the export format this daemon writes and reads.
+pub const VERSION: u32 = 1;
+
+/// The largest export the daemon reads for one import.
+pub const MAX_BODY: u64 = 1 << 30;
+
+/// How long an import with payloads waits, once done, for the datapath's announcements of Data
+/// it cached meanwhile to come through the ring buffer.
+const SETTLE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub version: u32,
+    pub exported_at_unix_ms: u64,
+    /// Name URIs, so the importing daemon can name what it caches and pins.
+    pub names: Vec<String>,
+    /// `<prefix> <strategy> [<key>=<value>...]`, as `udcn strategy set` takes them.
+    pub strategies: Vec<String>,
+    /// Prefixes `udcn route add` routed and kept. Exports made before it existed have none.
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// As `udcn cs pins` shows them.
+    pub pins: Vec<String>,
+    pub cached: Vec<Cached>,
+}
+
+/// A content store entry, timed relative to the export.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cached {
+    pub name_hash: u32,
+    pub data_size: u16,
+    pub prefetched: bool,
+    pub age_ms: u64,
+    /// How much longer the Data may answer Interests with MustBeFresh.
+    pub fresh_for_ms: u64,
+    /// What the Data cache held for the name, if payloads were exported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Vec<u8>>,
+}
+
+impl Cached {
+    /// `entry` as exported at `now` (bpf_ktime ns).
+    pub fn new(entry: &CacheEntry, now: u64, payload: Option<Vec<u8>>) -> Self {
+        Self {
+            name_hash: entry.name_hash,
+            data_size: entry.data_size,
+            prefetched: entry.prefetched != 0,
+            age_ms: now.saturating_sub(entry.timestamp) / 1_000_000,
+            fresh_for_ms: entry.fresh_until.saturating_sub(now) / 1_000_000,
+            payload,
+        }
+    }
+
+    /// The entry to cache at `now`, for the tenant and pin the importing daemon finds for it.
+    pub fn entry(&self, now: u64) -> CacheEntry {
+        CacheEntry {
+            name_hash: self.name_hash,
+            data_size: self.data_size,
+            prefetched: self.prefetched as u8,
+            tenant: 0,
+            timestamp: now.saturating_sub(self.age_ms.saturating_mul(1_000_000)),
+            fresh_until: now.saturating_add(self.fresh_for_ms.saturating_mul(1_000_000)),
+            pinned: 0,
+            reserved: [0; 7],
+        }
+    }
+}
+
+/// What an import did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Imported {
+    pub names: usize,
+    pub strategies: usize,
+    pub routes: usize,
+    /// Pins made, leaving out those already made.
+    pub pins: usize,
+    pub cached: usize,
+    pub already_cached: usize,
+    /// Entries that found no room, or went over a tenant's quota.
+    pub failed: usize,
+}
+
+impl std::fmt::Display for Imported {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Imported {} names, {} strategies, {} routes and {} pins; \
+             cached {}, already cached {}, failed {}",
+            self.names,
+            self.strategies,
+            self.routes,
+            self.pins,
+            self.cached,
+            self.already_cached,
+            self.failed
+        )
+    }
+}
+
+/// Import `state` into the daemon. While the import writes payloads, it listens for the `Cached`
+/// events the datapath announces each copy it caches with, and deletes the payloads those copies
+/// replaced under it; if it misses any, it checks every payload instead.
+pub async fn import(
+    daemon: &Mutex<Daemon>,
+    hub: &Arc<EventHub>,
+    state: &State,
+) -> anyhow::Result<Imported> {
+    if !state.cached.iter().any(|cached| cached.payload.is_some()) {
+        return daemon.lock().unwrap().import_state(state);
+    }
+    // The datapath only publishes events while someone listens
+    let mut events = hub.subscribe();
+    let imported = daemon.lock().unwrap().import_state(state)?;
+    let (finished, deadline) = (ktime_ns(), Instant::now() + SETTLE);
+    let mut dropped = 0;
+    while let Ok(event) = timeout_at(deadline, events.recv()).await {
+        let event = match event {
+            Ok(event) => event,
+            Err(missed) => {
+                debug!("missed {missed} datapath events during an import, checking every payload");
+                dropped += daemon.lock().unwrap().drop_stale_payloads()?;
+                continue;
+            }
+        };
+        if event.timestamp_ns > finished {
+            break;
+        }
+        if event.packet_type == TlvType::Data as u8
+            && event.outcome == EventOutcome::Cached as u8
+            && daemon.lock().unwrap().datapath_cached(event.name_hash)
+        {
+            dropped += 1;
+        }
+    }
+    if dropped > 0 {
+        debug!("deleted {dropped} imported payloads of Data the datapath cached meanwhile");
+    }
+    Ok(imported)
+}
+
+/// Send `line`, the `state-import` request for the export at `path`, with the export read here
+/// rather than by the daemon.
+pub async fn send(line: &str, path: &Path) -> anyhow::Result<String> {
+    let state =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    udcn_client::control::request_with_body(line, &state).await
+}
+
+pub fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_entries_keep_their_age_and_freshness() {
+        let ms = 1_000_000;
+        let entry = CacheEntry {
+            name_hash: 0xabcd,
+            data_size: 300,
+            prefetched: 1,
+            tenant: 2,
+            timestamp: 10_000 * ms,
+            fresh_until: 16_000 * ms,
+            pinned: 1,
+            reserved: [0; 7],
+        };
+        let cached = Cached::new(&entry, 12_000 * ms, Some(vec![7; 256]));
+        assert_eq!((cached.age_ms, cached.fresh_for_ms), (2_000, 4_000));
+
+        let state = State {
+            version: VERSION,
+            exported_at_unix_ms: unix_ms(),
+            names: vec!["/video/clip/seg=0".to_string()],
+            strategies: vec!["/video failover probe_interval_ms=500".to_string()],
+            routes: vec!["/sensors".to_string()],
+            pins: vec!["prefix /video".to_string()],
+            cached: vec![cached],
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        let imported: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(imported, state);
+
+        // On a host booted 5 s ago, the entry is as old and as fresh as it was
+        let restored = imported.cached[0].entry(5_000 * ms);
+        assert_eq!(restored.timestamp, 3_000 * ms);
+        assert_eq!(restored.fresh_until, 9_000 * ms);
+        assert_eq!((restored.tenant, restored.pinned), (0, 0));
+        // Stale Data stays stale
+        let stale = Cached::new(&entry, 20_000 * ms, None);
+        assert_eq!(stale.entry(5_000 * ms).fresh_until, 5_000 * ms);
+        assert!(!serde_json::to_string(&stale).unwrap().contains("payload"));
+    }
+}
@@ -0,0 +1,235 @@
+//! The strategy chosen for each prefix, from the `[strategies]` section of the configuration and
+//! from `udcn strategy set` at runtime.
+//!
+//! Choices made at runtime override the configured one for the same prefix and are kept one
+//! `<prefix> <strategy> [<key>=<value>...]` line per prefix, so that they survive restarts until
+//! `udcn strategy unset` drops them. Consumers such as `udcn get` ask the daemon which choice is
+//! in effect for the name they fetch.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use udcn_client::strategy::{StrategyChoice, StrategyTable};
+use udcn_common::Name;
+
+pub const DEFAULT_STRATEGIES_PATH: &str = "/var/lib/udcn/strategies";
+
+#[derive(Default)]
+pub struct Strategies {
+    configured: StrategyTable,
+    overrides: StrategyTable,
+    /// Where the overrides are kept, if anywhere.
+    path: Option<PathBuf>,
+}
+
+impl Strategies {
+    /// The `configured` choices, with the overrides kept at `path` on top.
+    pub fn load(
+        configured: &BTreeMap<Name, StrategyChoice>,
+        path: impl Into<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let path = path.into();
+        let mut strategies = Self {
+            path: Some(path.clone()),
+            ..Self::default()
+        };
+        for (prefix, choice) in configured {
+            strategies.configured.insert(prefix.clone(), choice.clone());
+        }
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.is_empty()) {
+                    let (prefix, choice) = parse_line(line)
+                        .with_context(|| format!("invalid line {line:?} in {}", path.display()))?;
+                    strategies.overrides.insert(prefix, choice);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+        Ok(strategies)
+    }
+
+    /// Override the choice for `prefix`. Nothing changes if the overrides cannot be written.
+    pub fn set(&mut self, prefix: Name, choice: StrategyChoice) -> anyhow::Result<()> {
+        let mut overrides = self.overrides.clone();
+        overrides.insert(prefix, choice);
+        self.persist(&overrides)?;
+        self.overrides = overrides;
+        Ok(())
+    }
+
+    /// Drop the override for `prefix`, returning whether there was one. Nothing changes if the
+    /// overrides cannot be written.
+    pub fn unset(&mut self, prefix: &Name) -> anyhow::Result<bool> {
+        let mut overrides = self.overrides.clone();
+        if overrides.remove(prefix).is_none() {
+            return Ok(false);
+        }
+        self.persist(&overrides)?;
+        self.overrides = overrides;
+        Ok(true)
+    }
+
+    /// The overrides as `strategy-set` takes them.
+    pub fn override_lines(&self) -> Vec<String> {
+        self.overrides
+            .iter()
+            .map(|(prefix, choice)| format!("{prefix} {choice}"))
+            .collect()
+    }
+
+    /// The choices in effect: the configured ones, overridden per prefix.
+    fn table(&self) -> StrategyTable {
+        let mut table = self.configured.clone();
+        for (prefix, choice) in self.overrides.iter() {
+            table.insert(prefix.clone(), choice.clone());
+        }
+        table
+    }
+
+    /// The choice in effect for `name`.
+    pub fn effective(&self, name: &Name) -> StrategyChoice {
+        self.table().effective(name)
+    }
+
+    pub fn format_table(&self) -> String {
+        let table = self.table();
+        let mut out = format!(
+            "{:<32} {:<12} {:<48} {}\n",
+            "PREFIX", "STRATEGY", "PARAMETERS", "SOURCE"
+        );
+        if table.get(&Name::new()).is_none() {
+            let _ = writeln!(
+                out,
+                "{:<32} {:<12} {:<48} default",
+                "/",
+                StrategyChoice::default().strategy,
+                "-"
+            );
+        }
+        for (prefix, choice) in table.iter() {
+            let shown = choice.to_string();
+            let parameters = shown
+                .split_once(' ')
+                .map_or("-", |(_, parameters)| parameters);
+            let source = if self.overrides.get(prefix).is_some() {
+                "override"
+            } else {
+                "config"
+            };
+            let _ = writeln!(
+                out,
+                "{:<32} {:<12} {:<48} {source}",
+                prefix.to_string(),
+                choice.strategy,
+                parameters
+            );
+        }
+        out
+    }
+
+    fn persist(&self, overrides: &StrategyTable) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write(path, overrides).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// A `strategy-set` argument or a line of the overrides file.
+pub fn parse_line(line: &str) -> anyhow::Result<(Name, StrategyChoice)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (prefix, choice) = words
+        .split_first()
+        .context("expected `<prefix> <strategy> [<key>=<value>...]`")?;
+    Ok((Name::from_uri(prefix)?, StrategyChoice::parse(choice)?))
+}
+
+fn write(path: &Path, overrides: &StrategyTable) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents: String = overrides
+        .iter()
+        .map(|(prefix, choice)| format!("{prefix} {choice}\n"))
+        .collect();
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_client::strategy::Strategy;
+
+    use super::*;
+
+    #[test]
+    fn test_overrides_survive_reload_on_top_of_config() {
+        let path = std::env::temp_dir().join(format!("udcn-strategies-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let configured = BTreeMap::from([(
+            Name::from("/video"),
+            StrategyChoice::parse(&["retransmit", "max_retries=2"]).unwrap(),
+        )]);
+
+        let mut strategies = Strategies::load(&configured, &path).unwrap();
+        let (prefix, choice) = parse_line("/video failover probe_interval_ms=500").unwrap();
+        strategies.set(prefix, choice).unwrap();
+        let (prefix, choice) = parse_line("/video/live retransmit").unwrap();
+        strategies.set(prefix, choice).unwrap();
+        assert!(strategies.unset(&Name::from("/video/live")).unwrap());
+        assert!(!strategies.unset(&Name::from("/audio")).unwrap());
+
+        let strategies = Strategies::load(&configured, &path).unwrap();
+        assert_eq!(
+            strategies.effective(&Name::from("/video/live/seg=0")),
+            choice_of("failover probe_interval_ms=500")
+        );
+        assert_eq!(
+            strategies.effective(&Name::from("/audio")).strategy,
+            Strategy::Failover
+        );
+        let table = strategies.format_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].ends_with("default"), "{table}");
+        assert!(lines[2].contains("probe_interval_ms=500"), "{table}");
+        assert!(lines[2].ends_with("override"), "{table}");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "/video failover probe_interval_ms=500\n"
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(parse_line("/video").is_err());
+        assert!(parse_line("/video failover retransmit_ms=10").is_err());
+    }
+
+    #[test]
+    fn test_failed_writes_change_nothing() {
+        // A directory can't be created under a file
+        let file =
+            std::env::temp_dir().join(format!("udcn-strategies-file-{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let mut strategies = Strategies {
+            path: Some(file.join("strategies")),
+            ..Strategies::default()
+        };
+        let (prefix, choice) = parse_line("/video failover").unwrap();
+        strategies.overrides.insert(prefix.clone(), choice);
+
+        let (other, choice) = parse_line("/audio failover").unwrap();
+        assert!(strategies.set(other.clone(), choice).is_err());
+        assert!(strategies.unset(&prefix).is_err());
+        assert_eq!(strategies.override_lines(), ["/video failover"]);
+        fs::remove_file(&file).unwrap();
+    }
+
+    fn choice_of(words: &str) -> StrategyChoice {
+        StrategyChoice::parse(&words.split(' ').collect::<Vec<_>>()).unwrap()
+    }
+}
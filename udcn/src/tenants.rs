@@ -0,0 +1,321 @@
+//! Tenants: namespaces with quotas on how much of the forwarder their names may take.
+//!
+//! Each `[[tenants]]` entry of the configuration file gives a prefix and caps on the PIT entries,
+//! content store bytes and Interests per second of the names under it; a cap left out, or 0, is
+//! no cap. Tenant prefixes may not nest, so a name belongs to at most one tenant.
+//!
+//! The datapath looks up the tenant of every Interest and approximates the quotas with counters
+//! per tenant: it drops Interests over the rate as rate limited and Interests over the PIT quota
+//! as if the PIT were full, and keeps Data over the byte quota out of the content store. Data
+//! counts against the tenant of the PIT entry it satisfies, which the entry records, as does the
+//! content store entry it makes. As the counters drift, every `SYNC_INTERVAL` the daemon recounts
+//! both tables by the tenant their entries record, evicts each tenant's oldest entries over its
+//! quotas, and writes the counts over the datapath's.
+
+use std::{fmt::Write as _, time::Duration};
+
+use aya::maps::{Array, HashMap, MapData};
+use serde::Deserialize;
+use udcn_client::control::TenantStatus;
+use udcn_common::{Name, TenantQuota, TenantUsage, MAX_TENANTS};
+
+use crate::nocache::NoCachePrefix;
+
+/// How often the daemon recounts and enforces usage.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One `[[tenants]]` entry of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Defaults to the prefix.
+    pub name: Option<String>,
+    pub prefix: Name,
+    #[serde(default)]
+    pub pit_entries: u32,
+    /// Content bytes of the tenant's Data in the content store.
+    #[serde(default)]
+    pub cs_bytes: u32,
+    #[serde(default)]
+    pub interests_per_sec: u32,
+}
+
+impl TenantConfig {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.prefix.to_string())
+    }
+
+    fn quota(&self) -> TenantQuota {
+        TenantQuota {
+            pit_entries: self.pit_entries,
+            cs_bytes: self.cs_bytes,
+            interests_per_sec: self.interests_per_sec,
+            reserved: 0,
+        }
+    }
+}
+
+/// Check that the datapath can look `tenants` up and that no name belongs to two of them.
+pub fn validate(tenants: &[TenantConfig]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        tenants.len() <= MAX_TENANTS as usize,
+        "at most {MAX_TENANTS} tenants can be defined"
+    );
+    for (i, tenant) in tenants.iter().enumerate() {
+        NoCachePrefix::new(tenant.prefix.clone())
+            .map_err(|e| anyhow::anyhow!("invalid prefix for tenant '{}': {e}", tenant.name()))?;
+        if let Some(other) = tenants[..i].iter().find(|other| {
+            other.prefix.is_prefix_of(&tenant.prefix) || tenant.prefix.is_prefix_of(&other.prefix)
+        }) {
+            anyhow::bail!("tenants '{}' and '{}' overlap", other.name(), tenant.name());
+        }
+    }
+    Ok(())
+}
+
+/// Tell the datapath the tenants' prefixes and quotas. Tenants are numbered from 1 in the order
+/// they are configured.
+pub fn install(ebpf: &mut aya::Ebpf, tenants: &[TenantConfig]) -> anyhow::Result<()> {
+    validate(tenants)?;
+    let mut prefixes: HashMap<_, u32, u32> =
+        HashMap::try_from(ebpf.map_mut("TENANT_PREFIXES").unwrap())?;
+    for (tenant, config) in (1..).zip(tenants) {
+        prefixes.insert(NoCachePrefix(config.prefix.clone()).key(), tenant, 0)?;
+    }
+    let mut quotas: Array<_, TenantQuota> =
+        Array::try_from(ebpf.map_mut("TENANT_QUOTAS").unwrap())?;
+    for (tenant, config) in (1..).zip(tenants) {
+        quotas.set(tenant, config.quota(), 0)?;
+    }
+    Ok(())
+}
+
+/// One PIT or content store entry counted against a tenant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Held {
+    pub tenant: u32,
+    pub name_hash: u32,
+    /// When the entry was made.
+    pub timestamp: u64,
+    /// What it counts for: 1 for a PIT entry, its content bytes for Data.
+    pub amount: u32,
+    /// Pinned Data, which counts first and is never evicted.
+    pub pinned: bool,
+}
+
+/// Sum `held` by tenant, keeping each tenant's pinned entries and its newest others up to
+/// `quota(tenant)`. Returns the sums by tenant number and the name hashes of the entries over
+/// quota, to evict.
+pub fn enforce(mut held: Vec<Held>, quota: impl Fn(u32) -> u32) -> (Vec<u32>, Vec<u32>) {
+    let mut used = vec![0u32; MAX_TENANTS as usize + 1];
+    let mut evicted = Vec::new();
+    held.sort_by_key(|held| std::cmp::Reverse((held.pinned, held.timestamp)));
+    for held in held {
+        let Some(sum) = used.get_mut(held.tenant as usize) else {
+            continue;
+        };
+        let limit = quota(held.tenant);
+        match sum.checked_add(held.amount) {
+            Some(total) if limit == 0 || total <= limit => *sum = total,
+            _ if held.pinned => *sum = sum.saturating_add(held.amount),
+            _ => evicted.push(held.name_hash),
+        }
+    }
+    (used, evicted)
+}
+
+pub struct Tenants {
+    tenants: Vec<TenantConfig>,
+    usage: Array<MapData, TenantUsage>,
+}
+
+impl Tenants {
+    pub fn new(ebpf: &mut aya::Ebpf, tenants: Vec<TenantConfig>) -> anyhow::Result<Self> {
+        Ok(Self {
+            tenants,
+            usage: Array::try_from(ebpf.take_map("TENANT_USAGE").unwrap())?,
+        })
+    }
+
+    /// The number of the tenant `name` belongs to, 0 for none.
+    pub fn tenant_of(&self, name: &Name) -> u32 {
+        (1..)
+            .zip(&self.tenants)
+            .find(|(_, tenant)| tenant.prefix.is_prefix_of(name))
+            .map_or(0, |(tenant, _)| tenant)
+    }
+
+    fn config(&self, tenant: u32) -> Option<&TenantConfig> {
+        self.tenants.get((tenant as usize).checked_sub(1)?)
+    }
+
+    /// A tenant's quota on PIT entries (`pit`) or content store bytes, 0 for none.
+    pub fn quota(&self, tenant: u32, pit: bool) -> u32 {
+        match (self.config(tenant), pit) {
+            (Some(config), true) => config.pit_entries,
+            (Some(config), false) => config.cs_bytes,
+            (None, _) => 0,
+        }
+    }
+
+    /// Count `amount` more PIT entries (`pit`) or content store bytes against `tenant`, as the
+    /// daemon adds an entry of its own, unless that takes it over quota.
+    pub fn admit(&mut self, tenant: u32, pit: bool, amount: u32) -> anyhow::Result<()> {
+        let Some(config) = self.config(tenant) else {
+            return Ok(());
+        };
+        let name = config.name();
+        let limit = self.quota(tenant, pit);
+        let mut usage = self.usage.get(&tenant, 0)?;
+        let (used, rejects) = match pit {
+            true => (&mut usage.pit_entries, &mut usage.pit_rejects),
+            false => (&mut usage.cs_bytes, &mut usage.cs_rejects),
+        };
+        let admitted = limit == 0 || used.saturating_add(amount) <= limit;
+        match admitted {
+            true => *used = used.saturating_add(amount),
+            false => *rejects = rejects.wrapping_add(1),
+        }
+        self.usage.set(tenant, usage, 0)?;
+        anyhow::ensure!(admitted, "tenant '{name}' is at its quota");
+        Ok(())
+    }
+
+    /// Write recounted PIT entries and content store bytes by tenant number over the datapath's
+    /// counts. What the datapath counts between the read and the write is lost.
+    pub fn set_usage(&mut self, pit_entries: &[u32], cs_bytes: &[u32]) -> anyhow::Result<()> {
+        for tenant in 1..=self.tenants.len() as u32 {
+            let mut usage = self.usage.get(&tenant, 0)?;
+            usage.pit_entries = pit_entries[tenant as usize];
+            usage.cs_bytes = cs_bytes[tenant as usize];
+            self.usage.set(tenant, usage, 0)?;
+        }
+        Ok(())
+    }
+
+    pub fn statuses(&self) -> anyhow::Result<Vec<TenantStatus>> {
+        (1..)
+            .zip(&self.tenants)
+            .map(|(tenant, config)| {
+                let usage = self.usage.get(&tenant, 0)?;
+                Ok(status(config, &usage))
+            })
+            .collect()
+    }
+}
+
+fn status(config: &TenantConfig, usage: &TenantUsage) -> TenantStatus {
+    TenantStatus {
+        tenant: config.name(),
+        prefix: config.prefix.to_string(),
+        pit_entries: usage.pit_entries,
+        pit_quota: config.pit_entries,
+        cs_bytes: usage.cs_bytes,
+        cs_quota: config.cs_bytes,
+        interest_rate: usage.window_interests,
+        interest_rate_quota: config.interests_per_sec,
+        interests: usage.interests,
+        rate_limited: usage.rate_limited,
+        pit_rejects: usage.pit_rejects,
+        cs_rejects: usage.cs_rejects,
+    }
+}
+
+/// Tenants as `udcn tenants` shows them, each use against its quota.
+pub fn format_tenants(tenants: &[TenantStatus]) -> String {
+    let of = |used: u32, quota: u32| match quota {
+        0 => used.to_string(),
+        quota => format!("{used}/{quota}"),
+    };
+    let mut out = format!(
+        "{:<20} {:<24} {:>13} {:>21} {:>13} {:>10} {:>10} {:>10}\n",
+        "TENANT", "PREFIX", "PIT", "CS BYTES", "INTERESTS/S", "LIMITED", "PIT FULL", "CS FULL"
+    );
+    for tenant in tenants {
+        let _ = writeln!(
+            out,
+            "{:<20} {:<24} {:>13} {:>21} {:>13} {:>10} {:>10} {:>10}",
+            tenant.tenant,
+            tenant.prefix,
+            of(tenant.pit_entries, tenant.pit_quota),
+            of(tenant.cs_bytes, tenant.cs_quota),
+            of(tenant.interest_rate, tenant.interest_rate_quota),
+            tenant.rate_limited,
+            tenant.pit_rejects,
+            tenant.cs_rejects
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(prefix: &str, pit_entries: u32) -> TenantConfig {
+        TenantConfig {
+            name: None,
+            prefix: Name::from(prefix),
+            pit_entries,
+            cs_bytes: 0,
+            interests_per_sec: 0,
+        }
+    }
+
+    #[test]
+    fn test_enforce_keeps_the_newest_entries_within_quota() {
+        let held = |tenant, name_hash, timestamp, amount| Held {
+            tenant,
+            name_hash,
+            timestamp,
+            amount,
+            pinned: false,
+        };
+        let (used, evicted) = enforce(
+            vec![
+                held(1, 0xa1, 10, 600),
+                held(1, 0xa2, 30, 300),
+                held(1, 0xa3, 20, 300),
+                held(2, 0xb1, 5, 5000),
+                held(0, 0xc1, 1, 9000),
+            ],
+            |tenant| if tenant == 1 { 1000 } else { 0 },
+        );
+        assert_eq!(&used[..3], [9000, 600, 5000]);
+        assert_eq!(evicted, [0xa1]);
+        // Pinned Data stays, even over quota, and pushes out the rest
+        let (used, evicted) = enforce(
+            vec![
+                held(1, 0xa1, 10, 600),
+                held(1, 0xa2, 30, 300),
+                Held {
+                    pinned: true,
+                    ..held(1, 0xa3, 5, 1200)
+                },
+            ],
+            |_| 1000,
+        );
+        assert_eq!(used[1], 1200);
+        assert_eq!(evicted, [0xa2, 0xa1]);
+
+        assert!(validate(&[tenant("/a", 10), tenant("/b/c", 0)]).is_ok());
+        assert!(validate(&[tenant("/a", 10), tenant("/a/b", 0)]).is_err());
+        assert!(validate(&[tenant("/", 10)]).is_err());
+        let many: Vec<_> = (0..=MAX_TENANTS)
+            .map(|i| tenant(&format!("/t{i}"), 0))
+            .collect();
+        assert!(validate(&many).is_err());
+
+        let status = status(
+            &tenant("/a", 10),
+            &TenantUsage {
+                pit_entries: 4,
+                cs_bytes: 512,
+                ..TenantUsage::default()
+            },
+        );
+        let table = format_tenants(&[status]);
+        assert!(table.lines().nth(1).unwrap().contains("4/10"));
+        assert!(table.lines().nth(1).unwrap().contains(" 512 "));
+    }
+}
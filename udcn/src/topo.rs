@@ -0,0 +1,652 @@
+//! Multi-node experiments: `udcn topo run topology.toml`.
+//!
+//! Every node of the topology file becomes a network namespace and every link a veth pair, with
+//! optional netem delay and loss. Forwarders run the daemon on all of their links, producers run
+//! `udcn serve --prefix`, and the traffic section is replayed with `udcn send` from the consumer
+//! nodes. Each node's FIB maps name prefixes to the producer nodes Interests are addressed to;
+//! in between, packets follow IP routes along shortest paths, chosen so that Data retraces the
+//! path of its Interest through the same forwarders. Traffic for names no FIB entry matches can
+//! carry a `forwarding_hint`, whose delegations are looked up instead, in order. A FIB entry can
+//! list `alternates`, producers `udcn send` fails over to when the preferred one does not answer.
+//!
+//! ```toml
+//! [[node]]
+//! name = "c1"
+//! fib = [{ prefix = "/video", producer = "p1" }]
+//!
+//! [[node]]
+//! name = "r1"
+//! forwarder = true
+//!
+//! [[node]]
+//! name = "p1"
+//! serve = { prefix = "/video", content = "frame" }
+//!
+//! [[link]]
+//! a = "c1"
+//! b = "r1"
+//!
+//! [[link]]
+//! a = "r1"
+//! b = "p1"
+//! delay_ms = 20
+//!
+//! [[traffic]]
+//! from = "c1"
+//! names = ["/video/1", "/video/2"]
+//! repeat = 3
+//! ```
+
+use std::{
+    fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use tokio::time::sleep;
+use udcn_client::control::{self, SOCKET_PATH_ENV};
+use udcn_common::{Name, PacketStats};
+
+use crate::netns::{ip, Namespaces, Process};
+
+const NAMESPACE_PREFIX: &str = "udcn-topo-";
+/// Each link gets a /24 out of 10.78.0.0/16 and a bit of the route tie-breaker in [`next_links`].
+const MAX_LINKS: usize = 63;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Topology {
+    #[serde(rename = "node")]
+    nodes: Vec<Node>,
+    #[serde(rename = "link", default)]
+    links: Vec<Link>,
+    #[serde(rename = "traffic", default)]
+    traffic: Vec<Traffic>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Node {
+    name: String,
+    /// Run the daemon with the XDP program attached to every link of the node.
+    #[serde(default)]
+    forwarder: bool,
+    #[serde(default)]
+    serve: Option<Serve>,
+    #[serde(default)]
+    fib: Vec<FibEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Serve {
+    prefix: Name,
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FibEntry {
+    prefix: Name,
+    producer: String,
+    #[serde(default)]
+    alternates: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Link {
+    a: String,
+    b: String,
+    #[serde(default)]
+    delay_ms: u32,
+    #[serde(default)]
+    loss_percent: f32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Traffic {
+    from: String,
+    names: Vec<Name>,
+    #[serde(default = "default_repeat")]
+    repeat: u32,
+    #[serde(default)]
+    interval_ms: u64,
+    #[serde(default)]
+    forwarding_hint: Vec<Name>,
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+impl Topology {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid topology {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let topology: Self = toml::from_str(contents)?;
+        topology.validate()?;
+        Ok(topology)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.links.len() <= MAX_LINKS,
+            "at most {MAX_LINKS} links are supported"
+        );
+        for (i, node) in self.nodes.iter().enumerate() {
+            anyhow::ensure!(
+                !node.name.is_empty()
+                    && node.name.len() <= 15
+                    && node
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                "node name '{}' must be 1-15 letters, digits or dashes",
+                node.name
+            );
+            anyhow::ensure!(
+                self.nodes[..i].iter().all(|other| other.name != node.name),
+                "node '{}' is defined twice",
+                node.name
+            );
+            anyhow::ensure!(
+                !node.forwarder || self.links.iter().any(|link| link.connects(&node.name)),
+                "forwarder '{}' has no links",
+                node.name
+            );
+            for entry in &node.fib {
+                for nexthop in std::iter::once(&entry.producer).chain(&entry.alternates) {
+                    anyhow::ensure!(
+                        self.node(nexthop)?.serve.is_some(),
+                        "FIB of '{}' points at '{}', which serves nothing",
+                        node.name,
+                        nexthop
+                    );
+                }
+            }
+        }
+        for link in &self.links {
+            self.node(&link.a)?;
+            self.node(&link.b)?;
+            anyhow::ensure!(link.a != link.b, "link from '{}' to itself", link.a);
+        }
+        for traffic in &self.traffic {
+            let node = self.node(&traffic.from)?;
+            for name in &traffic.names {
+                anyhow::ensure!(
+                    node.route(name, &traffic.forwarding_hint).is_some(),
+                    "no FIB entry on '{}' for {name} or its forwarding hint",
+                    node.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&self, name: &str) -> anyhow::Result<&Node> {
+        self.nodes
+            .iter()
+            .find(|node| node.name == name)
+            .with_context(|| format!("unknown node '{name}'"))
+    }
+
+    fn index(&self, name: &str) -> usize {
+        self.nodes
+            .iter()
+            .position(|node| node.name == name)
+            .unwrap()
+    }
+
+    /// The address of `node` on `link`: the first host of the link's subnet for `a`, the second
+    /// for `b`.
+    fn address(&self, link: usize, node: &str) -> Ipv4Addr {
+        let host = if self.links[link].a == node { 1 } else { 2 };
+        Ipv4Addr::new(10, 78, link as u8, host)
+    }
+
+    /// The address Interests for `node` are sent to: the one on its first link.
+    fn primary_address(&self, node: &str) -> Option<Ipv4Addr> {
+        let link = self.links.iter().position(|link| link.connects(node))?;
+        Some(self.address(link, node))
+    }
+
+    /// The interface `node` has on `link`; interfaces are numbered in link order.
+    fn interface(&self, link: usize, node: &str) -> String {
+        let n = self.links[..link]
+            .iter()
+            .filter(|other| other.connects(node))
+            .count();
+        format!("eth{n}")
+    }
+}
+
+impl Node {
+    /// The producer the longest FIB prefix matching `name` points at, or failing that, the one
+    /// the first delegation of `forwarding_hint` with a match leads to.
+    fn route(&self, name: &Name, forwarding_hint: &[Name]) -> Option<&str> {
+        self.fib_entry(name, forwarding_hint)
+            .map(|entry| entry.producer.as_str())
+    }
+
+    /// The producer [`Node::route`] picks, then the alternates of its FIB entry.
+    fn nexthops(&self, name: &Name, forwarding_hint: &[Name]) -> Vec<&str> {
+        self.fib_entry(name, forwarding_hint)
+            .map(|entry| {
+                std::iter::once(&entry.producer)
+                    .chain(&entry.alternates)
+                    .map(String::as_str)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn fib_entry(&self, name: &Name, forwarding_hint: &[Name]) -> Option<&FibEntry> {
+        std::iter::once(name)
+            .chain(forwarding_hint)
+            .find_map(|name| self.longest_match(name))
+    }
+
+    fn longest_match(&self, name: &Name) -> Option<&FibEntry> {
+        self.fib
+            .iter()
+            .filter(|entry| entry.prefix.is_prefix_of(name))
+            .max_by_key(|entry| entry.prefix.len())
+    }
+}
+
+impl Link {
+    fn connects(&self, node: &str) -> bool {
+        self.a == node || self.b == node
+    }
+}
+
+/// For every pair of nodes, the link to take from the first towards the second.
+///
+/// Paths are shortest in hops, with ties broken by the set of links used. That makes every
+/// shortest path unique, so the route from `a` to `b` is the route from `b` to `a` reversed and
+/// the destination-based routes of all nodes agree with each other.
+fn next_links(nodes: usize, links: &[(usize, usize)]) -> Vec<Vec<Option<usize>>> {
+    debug_assert!(links.len() < 64);
+    let weight = |link: usize| (1u128 << 64) + (1u128 << link);
+
+    let mut next = vec![vec![None; nodes]; nodes];
+    for destination in 0..nodes {
+        // Dijkstra from the destination; `via[n]` is the link n takes towards it
+        let mut distance = vec![u128::MAX; nodes];
+        let mut via = vec![None; nodes];
+        let mut done = vec![false; nodes];
+        distance[destination] = 0;
+        while let Some(node) = (0..nodes)
+            .filter(|&n| !done[n] && distance[n] != u128::MAX)
+            .min_by_key(|&n| distance[n])
+        {
+            done[node] = true;
+            for (link, &(a, b)) in links.iter().enumerate() {
+                let neighbor = match node {
+                    _ if a == node => b,
+                    _ if b == node => a,
+                    _ => continue,
+                };
+                let candidate = distance[node] + weight(link);
+                if candidate < distance[neighbor] {
+                    distance[neighbor] = candidate;
+                    via[neighbor] = Some(link);
+                }
+            }
+        }
+        for node in 0..nodes {
+            next[node][destination] = via[node];
+        }
+    }
+    next
+}
+
+/// Build `topology`, replay its traffic and print per-node statistics.
+pub async fn run(path: &Path, verbose: bool) -> anyhow::Result<()> {
+    let topology = Topology::load(path)?;
+    anyhow::ensure!(
+        unsafe { libc::geteuid() } == 0,
+        "topo needs root to create namespaces"
+    );
+
+    let namespaces = build(&topology, verbose).context("failed to create the topology")?;
+
+    let mut processes = Vec::new();
+    let mut forwarders = Vec::new();
+    for node in topology.nodes.iter().filter(|node| node.forwarder) {
+        let ifaces: Vec<_> = (0..topology.links.len())
+            .filter(|&link| topology.links[link].connects(&node.name))
+            .map(|link| topology.interface(link, &node.name))
+            .collect();
+        let socket = control_socket(&node.name);
+        let mut command = namespaces.udcn(
+            &namespace(&node.name),
+            [
+                "--iface",
+                &ifaces.join(","),
+                "run",
+                "--pin-dir",
+                &format!("/sys/fs/bpf/{}", namespace(&node.name)),
+            ],
+        )?;
+        let mut daemon = Process::spawn(command.env(SOCKET_PATH_ENV, &socket))?;
+        daemon
+            .wait_for_daemon(&socket)
+            .await
+            .with_context(|| format!("failed to start forwarder '{}'", node.name))?;
+        processes.push(daemon);
+        forwarders.push((node.name.as_str(), socket));
+    }
+
+    for node in &topology.nodes {
+        let Some(serve) = &node.serve else {
+            continue;
+        };
+        let address = topology
+            .primary_address(&node.name)
+            .with_context(|| format!("producer '{}' has no links", node.name))?;
+        processes.push(namespaces.spawn(
+            &namespace(&node.name),
+            [
+                "serve",
+                "--prefix",
+                "-n",
+                &serve.prefix.to_string(),
+                "-c",
+                &serve.content,
+                "-b",
+                &format!("{address}:6363"),
+            ],
+        )?);
+    }
+    // Give the producers time to bind before the first Interest reaches them
+    sleep(Duration::from_millis(500)).await;
+
+    for traffic in &topology.traffic {
+        let node = topology.node(&traffic.from)?;
+        let (mut sent, mut satisfied) = (0, 0);
+        for round in 0..traffic.repeat {
+            if round > 0 {
+                sleep(Duration::from_millis(traffic.interval_ms)).await;
+            }
+            for name in &traffic.names {
+                let targets = node
+                    .nexthops(name, &traffic.forwarding_hint)
+                    .into_iter()
+                    .map(|producer| {
+                        let address = topology
+                            .primary_address(producer)
+                            .with_context(|| format!("producer '{producer}' has no links"))?;
+                        Ok(format!("{address}:6363"))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let mut args = vec![
+                    "send".to_string(),
+                    "-n".to_string(),
+                    name.to_string(),
+                    "-t".to_string(),
+                    targets.join(","),
+                ];
+                for delegation in &traffic.forwarding_hint {
+                    args.extend(["--forwarding-hint".to_string(), delegation.to_string()]);
+                }
+                let status = namespaces.exec(&namespace(&node.name), &args)?;
+                sent += 1;
+                satisfied += status.success() as u32;
+            }
+        }
+        println!("{}: {satisfied}/{sent} Interests satisfied", traffic.from);
+    }
+
+    println!();
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>10}",
+        "NODE", "INTERESTS", "DATA", "CACHE HITS", "HIT RATIO"
+    );
+    for (name, socket) in &forwarders {
+        let stats = control::stats_at(socket).await?;
+        println!(
+            "{name:<16} {:>10} {:>10} {:>10} {:>10}",
+            stats.interest_received,
+            stats.data_received,
+            stats.cache_hits,
+            hit_ratio(&stats)
+        );
+    }
+
+    drop(processes);
+    for (_, socket) in &forwarders {
+        let _ = fs::remove_file(socket);
+    }
+    Ok(())
+}
+
+/// Create the namespaces, links, addresses and routes of `topology`.
+fn build(topology: &Topology, verbose: bool) -> anyhow::Result<Namespaces> {
+    let mut namespaces = Namespaces::new(NAMESPACE_PREFIX, verbose);
+    for node in &topology.nodes {
+        let ns = namespace(&node.name);
+        namespaces.add(&ns)?;
+        ip(&format!("netns exec {ns} sysctl -qw net.ipv4.ip_forward=1"))?;
+    }
+
+    for (i, link) in topology.links.iter().enumerate() {
+        let (ns_a, ns_b) = (namespace(&link.a), namespace(&link.b));
+        let (dev_a, dev_b) = (
+            topology.interface(i, &link.a),
+            topology.interface(i, &link.b),
+        );
+        ip(&format!(
+            "link add {dev_a} netns {ns_a} type veth peer name {dev_b} netns {ns_b}"
+        ))?;
+        for (node, ns, dev) in [(&link.a, &ns_a, &dev_a), (&link.b, &ns_b, &dev_b)] {
+            let address = topology.address(i, node);
+            ip(&format!("-n {ns} addr add {address}/24 dev {dev}"))?;
+            ip(&format!("-n {ns} link set {dev} up"))?;
+            if link.delay_ms > 0 || link.loss_percent > 0.0 {
+                ip(&format!(
+                    "netns exec {ns} tc qdisc add dev {dev} root netem delay {}ms loss {}%",
+                    link.delay_ms, link.loss_percent
+                ))?;
+            }
+        }
+    }
+
+    let ends: Vec<_> = topology
+        .links
+        .iter()
+        .map(|link| (topology.index(&link.a), topology.index(&link.b)))
+        .collect();
+    let next = next_links(topology.nodes.len(), &ends);
+    for (from, node) in topology.nodes.iter().enumerate() {
+        let ns = namespace(&node.name);
+        for (link, &(a, b)) in ends.iter().enumerate() {
+            if a == from || b == from {
+                continue;
+            }
+            // Host routes to both ends of every link the node is not on
+            for (end, end_name) in [(a, &topology.links[link].a), (b, &topology.links[link].b)] {
+                let Some(hop) = next[from][end] else {
+                    continue;
+                };
+                let hop_link = &topology.links[hop];
+                let neighbor = if hop_link.a == node.name {
+                    &hop_link.b
+                } else {
+                    &hop_link.a
+                };
+                ip(&format!(
+                    "-n {ns} route replace {}/32 via {}",
+                    topology.address(link, end_name),
+                    topology.address(hop, neighbor)
+                ))?;
+            }
+        }
+    }
+
+    Ok(namespaces)
+}
+
+fn namespace(node: &str) -> String {
+    format!("{NAMESPACE_PREFIX}{node}")
+}
+
+fn control_socket(node: &str) -> PathBuf {
+    Path::new(control::SOCKET_PATH).with_file_name(format!("topo-{node}.sock"))
+}
+
+/// Cache hits over the Interests that reached the node.
+fn hit_ratio(stats: &PacketStats) -> String {
+    if stats.interest_received == 0 {
+        return "-".to_string();
+    }
+    format!(
+        "{:.2}%",
+        stats.cache_hits as f64 / stats.interest_received as f64 * 100.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_topology_is_valid() {
+        let topology =
+            Topology::parse(include_str!("../../examples/topologies/shared-edge.toml")).unwrap();
+        assert_eq!(topology.interface(1, "r1"), "eth1");
+        let consumer = topology.node("c1").unwrap();
+        assert_eq!(consumer.route(&Name::from("/video/seg/1"), &[]), Some("p1"));
+        assert_eq!(consumer.route(&Name::from("/audio/1"), &[]), None);
+    }
+
+    #[test]
+    fn test_routes_by_forwarding_hint_without_fib_match() {
+        let topology = Topology::parse(
+            r#"
+            [[node]]
+            name = "c1"
+            fib = [{ prefix = "/isp-a", producer = "p1" }, { prefix = "/alice/news", producer = "p2" }]
+
+            [[node]]
+            name = "p1"
+            serve = { prefix = "/alice", content = "x" }
+
+            [[node]]
+            name = "p2"
+            serve = { prefix = "/alice/news", content = "y" }
+
+            [[traffic]]
+            from = "c1"
+            names = ["/alice/video/1"]
+            forwarding_hint = ["/isp-b", "/isp-a/pop1"]
+            "#,
+        )
+        .unwrap();
+        let consumer = topology.node("c1").unwrap();
+        let hint = &topology.traffic[0].forwarding_hint;
+        assert_eq!(
+            consumer.route(&Name::from("/alice/video/1"), hint),
+            Some("p1")
+        );
+        // A FIB match for the name itself wins over the hint
+        assert_eq!(
+            consumer.route(&Name::from("/alice/news/1"), hint),
+            Some("p2")
+        );
+        assert_eq!(consumer.route(&Name::from("/alice/video/1"), &[]), None);
+
+        let error = Topology::parse(
+            "[[node]]\nname = \"c1\"\n[[traffic]]\nfrom = \"c1\"\nnames = [\"/a\"]\n\
+             forwarding_hint = [\"/isp-b\"]\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no FIB entry on 'c1' for /a or its forwarding hint"
+        );
+    }
+
+    #[test]
+    fn test_fib_alternates_follow_the_preferred_producer() {
+        let topology = Topology::parse(
+            r#"
+            [[node]]
+            name = "c1"
+            fib = [{ prefix = "/video", producer = "p1", alternates = ["p2"] }]
+
+            [[node]]
+            name = "p1"
+            serve = { prefix = "/video", content = "a" }
+
+            [[node]]
+            name = "p2"
+            serve = { prefix = "/video", content = "b" }
+            "#,
+        )
+        .unwrap();
+        let consumer = topology.node("c1").unwrap();
+        let name = Name::from("/video/1");
+        assert_eq!(consumer.route(&name, &[]), Some("p1"));
+        assert_eq!(consumer.nexthops(&name, &[]), ["p1", "p2"]);
+        assert!(consumer.nexthops(&Name::from("/audio"), &[]).is_empty());
+
+        let error = Topology::parse(
+            r#"
+            [[node]]
+            name = "c1"
+            fib = [{ prefix = "/video", producer = "p1", alternates = ["c1"] }]
+
+            [[node]]
+            name = "p1"
+            serve = { prefix = "/video", content = "a" }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "FIB of 'c1' points at 'c1', which serves nothing"
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_nodes() {
+        let error = Topology::parse("[[node]]\nname = \"a\"\n[[link]]\na = \"a\"\nb = \"b\"\n")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "unknown node 'b'");
+    }
+
+    #[test]
+    fn test_routes_are_symmetric() {
+        // A square with a diagonal: 0-1, 1-2, 2-3, 3-0, 0-2
+        let links = [(0, 1), (1, 2), (2, 3), (3, 0), (0, 2)];
+        let next = next_links(4, &links);
+        let path = |mut from: usize, to: usize| {
+            let mut path = vec![from];
+            while from != to {
+                let (a, b) = links[next[from][to].unwrap()];
+                from = if a == from { b } else { a };
+                path.push(from);
+            }
+            path
+        };
+
+        for a in 0..4 {
+            for b in 0..4 {
+                let mut back = path(b, a);
+                back.reverse();
+                assert_eq!(path(a, b), back);
+            }
+        }
+        assert_eq!(path(1, 3).len(), 3);
+    }
+}
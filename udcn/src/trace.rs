@@ -0,0 +1,81 @@
+//! Hop reports for traced packets.
+//!
+//! The datapath publishes every packet carrying a TraceId on `TRACE_HOPS`, then handles the
+//! packet inside as if it came unwrapped. The daemon answers each with a `HopReport` naming this
+//! node, sent from the NDN port to the consumer, so `udcn trace` can follow a fetch across every
+//! forwarder it goes through.
+
+use std::{ffi::CStr, mem, sync::Arc};
+
+use aya::maps::{MapData, RingBuf};
+use log::{debug, warn};
+use tokio::io::unix::AsyncFd;
+use udcn_common::{lp::HopReport, TraceHop};
+
+use crate::{events::Clock, lapsed::NackSender, otlp};
+
+/// Send a hop report for every traced packet the datapath sees.
+pub fn start(ebpf: &mut aya::Ebpf, sender: Arc<NackSender>) -> anyhow::Result<()> {
+    let ring = RingBuf::try_from(ebpf.take_map("TRACE_HOPS").unwrap())?;
+    let ring = AsyncFd::new(ring)?;
+    tokio::spawn(report_hops(ring, sender, node_name()));
+    Ok(())
+}
+
+async fn report_hops(mut ring: AsyncFd<RingBuf<MapData>>, sender: Arc<NackSender>, node: String) {
+    let clock = Clock::datapath();
+    loop {
+        let mut guard = match ring.readable_mut().await {
+            Ok(guard) => guard,
+            Err(e) => {
+                warn!("traced packet channel stopped: {e}");
+                return;
+            }
+        };
+        let ring = guard.get_inner_mut();
+        while let Some(item) = ring.next() {
+            if item.len() < mem::size_of::<TraceHop>() {
+                continue;
+            }
+            // SAFETY: the length was checked, and every bit pattern is a valid TraceHop
+            let hop = unsafe { (item.as_ptr() as *const TraceHop).read_unaligned() };
+            report(&sender, &hop, &node, &clock);
+        }
+        guard.clear_ready();
+    }
+}
+
+fn report(sender: &NackSender, hop: &TraceHop, node: &str, clock: &Clock) {
+    let report = HopReport {
+        trace_id: hop.trace_id,
+        node: node.to_string(),
+        packet_type: hop.packet_type,
+        name_hash: hop.name_hash,
+        timestamp_ns: clock.realtime_ns(hop.timestamp_ns),
+    };
+    let [a, b, c, d] = hop.addr;
+    let port = u16::from_be_bytes(hop.port);
+    let mut span = otlp::span("hop_report");
+    span.attr("face", format!("{a}.{b}.{c}.{d}:{port}"));
+    span.attr("trace_id", format!("{:016x}", hop.trace_id));
+    match sender.send(hop.local_addr, hop.addr, hop.port, &report.encode()) {
+        Ok(()) => debug!(
+            "Reported trace {:016x} of {:#010x} to {a}.{b}.{c}.{d}:{port}",
+            hop.trace_id, hop.name_hash
+        ),
+        Err(e) => warn!(
+            "failed to report trace {:016x} to {a}.{b}.{c}.{d}:{port}: {e}",
+            hop.trace_id
+        ),
+    }
+}
+
+/// The host name, which identifies this forwarder in hop reports.
+pub(crate) fn node_name() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    match CStr::from_bytes_until_nul(&buf) {
+        Ok(name) if ret == 0 => name.to_string_lossy().into_owned(),
+        _ => "unknown".to_string(),
+    }
+}
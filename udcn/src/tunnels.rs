@@ -0,0 +1,796 @@
+//! Permanent UDP tunnels to other µDCN sites, from `[[tunnels]]` sections of the configuration.
+//!
+//! A tunnel listens where the datapath passes up the Interests it cannot answer, the NDN port by
+//! default, and relays those under its prefixes to the forwarder at `remote`. Data and Nacks come
+//! back on the same socket and are relayed to each consumer still waiting for them. Each relayed
+//! Interest gets a PIT entry for the tunnel's own face, as prefetching does, so the datapath takes
+//! the reply as solicited and caches it for the site.
+//!
+//! A tunnel outlives the site at the other end. `remote` is a host name or address with a port,
+//! looked up again every `resolve_secs`; the socket towards it is recreated whenever the address
+//! changes or the socket fails, so a peer that restarts, or comes back under the same name at
+//! another address, is reached again without restarting the daemon. Until `remote` resolves,
+//! Interests for the tunnel are dropped.
+//!
+//! Each tunnel is one of the daemon's faces in `udcn face list` and on the metrics page. Packets
+//! are handed to its sockets without waiting for room, so a link that cannot keep up shows as a
+//! full send buffer and EWOULDBLOCK drops there, rather than as Interests lost somewhere along
+//! the forwarding path. The queuing delay is the smoothed time from a packet arriving at the
+//! tunnel to a socket taking it.
+//!
+//! Nacks from the other site are counted by reason. A tunnel with `on_nack = "suppress"` does
+//! not relay them, so its consumers time out and try their other faces as they would if the
+//! tunnel were silent; with a single upstream there is nothing else for it to wait for.
+//!
+//! Tunnels carry NDN in the clear unless given a `dtls` table, which makes the socket towards the
+//! remote a DTLS session with a listener at the other site, see [`dtls`](crate::dtls). The
+//! session is set up whenever the socket is, and set up again if it fails. The handshake runs in
+//! a task of its own, so the tunnel goes on relaying over the session it had, if any, meanwhile.
+//! As the datapath never sees their Data in the clear, it is not cached for the site.
+//!
+//! A tunnel over a constrained uplink can be held to `egress_bytes_per_sec` towards its remote,
+//! see [`shaper`](crate::shaper). Interests over the rate wait their turn in a short queue, and
+//! are dropped once it is full. Replies to consumers are not shaped, as they go out on the local
+//! network.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    io, mem,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket as StdUdpSocket},
+    os::fd::{AsRawFd as _, RawFd},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use log::{debug, info, warn};
+use openssl::ssl::SslContext;
+use serde::Deserialize;
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    task::JoinHandle,
+    time::{interval, sleep_until, Duration, Instant},
+};
+use udcn_client::{control::FaceStatus, NackPolicy};
+use udcn_common::{
+    lp, parse_nack_packet, Data, Interest, NackReason, Name, DEFAULT_INTEREST_LIFETIME_MS,
+};
+
+use crate::{
+    daemon::Daemon,
+    dtls::{self, DtlsConfig},
+    otlp,
+    shaper::{Shaper, Shaping, ShapingStats, Verdict},
+};
+
+/// Largest packet relayed.
+const MAX_PACKET_SIZE: usize = 65_536;
+
+/// Interests waiting for a reply on one tunnel; more are dropped until some are answered.
+const MAX_PENDING: usize = 65_536;
+
+/// A `[[tunnels]]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TunnelConfig {
+    /// The other site's forwarder, as `host:port`.
+    pub remote: String,
+    /// Where the Interests to relay arrive.
+    #[serde(default = "default_listen")]
+    pub listen: SocketAddr,
+    /// Prefixes whose Interests are relayed; every Interest when empty.
+    #[serde(default)]
+    pub prefixes: Vec<Name>,
+    /// How often `remote` is looked up again.
+    #[serde(default = "default_resolve_secs")]
+    pub resolve_secs: u64,
+    /// Whether Nacks from the other site are relayed to consumers (`propagate`) or not
+    /// (`suppress`).
+    #[serde(default)]
+    pub on_nack: NackPolicy,
+    /// The most bytes a second sent towards `remote`; unlimited when left out.
+    #[serde(default)]
+    pub egress_bytes_per_sec: Option<u64>,
+    /// How far sending may run ahead of the rate.
+    #[serde(default)]
+    pub egress_burst_bytes: Option<u64>,
+    /// Bytes waiting for the rate to allow them, at most, beyond which packets are dropped.
+    #[serde(default)]
+    pub egress_queue_bytes: Option<u64>,
+    /// Encrypt the tunnel as a DTLS session with the remote; in the clear when left out.
+    #[serde(default)]
+    pub dtls: Option<DtlsConfig>,
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 6363))
+}
+
+fn default_resolve_secs() -> u64 {
+    60
+}
+
+impl TunnelConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let port = self
+            .remote
+            .rsplit_once(':')
+            .and_then(|(host, port)| (!host.is_empty()).then_some(port)?.parse::<u16>().ok());
+        anyhow::ensure!(
+            port.is_some(),
+            "tunnels.remote must be host:port, not '{}'",
+            self.remote
+        );
+        anyhow::ensure!(
+            self.listen.is_ipv4(),
+            "tunnels.listen must be an IPv4 address"
+        );
+        anyhow::ensure!(
+            self.resolve_secs > 0,
+            "tunnels.resolve_secs must be positive"
+        );
+        anyhow::ensure!(
+            self.on_nack != NackPolicy::Wait,
+            "tunnels.on_nack must be propagate or suppress, as a tunnel has one upstream"
+        );
+        anyhow::ensure!(
+            self.egress_bytes_per_sec != Some(0) && self.egress_burst_bytes != Some(0),
+            "tunnels.egress_bytes_per_sec and egress_burst_bytes must be positive"
+        );
+        anyhow::ensure!(
+            self.egress_bytes_per_sec.is_some()
+                || (self.egress_burst_bytes.is_none() && self.egress_queue_bytes.is_none()),
+            "tunnels.egress_burst_bytes and egress_queue_bytes only apply with egress_bytes_per_sec"
+        );
+        if let Some(dtls) = &self.dtls {
+            dtls.validate()?;
+        }
+        Ok(())
+    }
+
+    /// How what the tunnel sends towards `remote` is shaped, if it is.
+    fn shaping(&self) -> Option<Shaping> {
+        let bytes_per_sec = self.egress_bytes_per_sec?;
+        Some(Shaping {
+            bytes_per_sec,
+            burst_bytes: self
+                .egress_burst_bytes
+                .unwrap_or_else(|| Shaping::default_burst(bytes_per_sec)),
+            queue_bytes: self
+                .egress_queue_bytes
+                .unwrap_or_else(|| Shaping::default_queue(bytes_per_sec)),
+        })
+    }
+
+    fn relays(&self, name: &Name) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| prefix.is_prefix_of(name))
+    }
+}
+
+/// A consumer waiting for the reply to an Interest relayed through the tunnel.
+#[derive(Clone, Debug, PartialEq)]
+struct Waiting<C> {
+    name: Name,
+    nonce: u32,
+    consumer: C,
+    expires: Instant,
+}
+
+/// Consumers waiting on one face, by the name hash of their Interest. A tunnel's consumers are
+/// UDP addresses, an Ethernet face's the MAC addresses its Interests came from.
+#[derive(Debug)]
+pub struct Pending<C> {
+    waiting: HashMap<u32, Vec<Waiting<C>>>,
+    len: usize,
+}
+
+impl<C> Default for Pending<C> {
+    fn default() -> Self {
+        Self {
+            waiting: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<C: Copy + Ord> Pending<C> {
+    /// Wait for a reply to `interest` from `consumer`. Returns false if too many already wait.
+    pub fn insert(&mut self, interest: &Interest, consumer: C, now: Instant) -> bool {
+        self.expire(now);
+        if self.len >= MAX_PENDING {
+            return false;
+        }
+        let lifetime_ms = interest
+            .lifetime_ms
+            .unwrap_or(u64::from(DEFAULT_INTEREST_LIFETIME_MS));
+        self.waiting
+            .entry(interest.name.name_hash())
+            .or_default()
+            .push(Waiting {
+                name: interest.name.clone(),
+                nonce: interest.nonce,
+                consumer,
+                expires: now + Duration::from_millis(lifetime_ms),
+            });
+        self.len += 1;
+        true
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        let mut expired = 0;
+        self.waiting.retain(|_, waiting| {
+            let before = waiting.len();
+            waiting.retain(|waiting| waiting.expires > now);
+            expired += before - waiting.len();
+            !waiting.is_empty()
+        });
+        self.len -= expired;
+    }
+
+    /// The consumers `packet` answers, who then no longer wait for it.
+    pub fn answer(&mut self, packet: &[u8]) -> Vec<C> {
+        let mut answered = Vec::new();
+        if let Some(nack) = parse_nack_packet(packet) {
+            if let Some(waiting) = self.waiting.get_mut(&nack.name_hash) {
+                waiting.retain(|waiting| {
+                    let matches = waiting.nonce == nack.nonce;
+                    if matches {
+                        answered.push(waiting.consumer);
+                    }
+                    !matches
+                });
+                if waiting.is_empty() {
+                    self.waiting.remove(&nack.name_hash);
+                }
+            }
+        } else if let Some(data) = Data::decode(packet) {
+            // Interests for the name or any prefix of it, as with CanBePrefix
+            for len in 0..=data.name.len() {
+                let name_hash = data.name.prefix(len).name_hash();
+                let Some(waiting) = self.waiting.get_mut(&name_hash) else {
+                    continue;
+                };
+                waiting.retain(|waiting| {
+                    let matches = waiting.name.is_prefix_of(&data.name);
+                    if matches {
+                        answered.push(waiting.consumer);
+                    }
+                    !matches
+                });
+                if waiting.is_empty() {
+                    self.waiting.remove(&name_hash);
+                }
+            }
+        }
+        self.len -= answered.len();
+        answered.sort_unstable();
+        answered.dedup();
+        answered
+    }
+}
+
+/// A tunnel as one of the daemon's faces, and what sending on it has run into.
+pub struct Face {
+    remote: String,
+    listen: SocketAddr,
+    state: Mutex<FaceState>,
+}
+
+#[derive(Default)]
+struct FaceState {
+    /// The socket towards the other site and the address it is connected to, while it is up.
+    upstream: Option<(Arc<UdpSocket>, SocketAddrV4)>,
+    sent: u64,
+    would_block_drops: u64,
+    send_errors: u64,
+    /// Smoothed time from a packet arriving to a socket taking it.
+    queuing_delay: Option<Duration>,
+    nacks: BTreeMap<String, u64>,
+    shaping: ShapingStats,
+}
+
+impl Face {
+    fn new(config: &TunnelConfig) -> Self {
+        Self {
+            remote: config.remote.clone(),
+            listen: config.listen,
+            state: Mutex::new(FaceState::default()),
+        }
+    }
+
+    fn set_upstream(&self, upstream: Option<&Upstream>) {
+        self.state.lock().unwrap().upstream =
+            upstream.map(|upstream| (upstream.socket.clone(), upstream.remote));
+    }
+
+    /// Count what became of a packet that arrived at `received` and was handed to a socket with
+    /// `sent` as the result.
+    fn sent(&self, sent: io::Result<usize>, received: Instant) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match sent {
+            Ok(_) => {
+                state.sent += 1;
+                let delay = received.elapsed();
+                // Smoothed as TCP smooths its round-trip time
+                state.queuing_delay = Some(
+                    state
+                        .queuing_delay
+                        .map_or(delay, |smoothed| (smoothed * 7 + delay) / 8),
+                );
+                Ok(())
+            }
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    state.would_block_drops += 1;
+                } else {
+                    state.send_errors += 1;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn shaped(&self, stats: ShapingStats) {
+        self.state.lock().unwrap().shaping = stats;
+    }
+
+    /// Count a Nack from the other site.
+    fn nacked(&self, reason: u8) {
+        let reason = match NackReason::from_u8(reason) {
+            Some(reason) => format!("{reason:?}"),
+            None => reason.to_string(),
+        };
+        *self.state.lock().unwrap().nacks.entry(reason).or_default() += 1;
+    }
+
+    pub fn status(&self) -> FaceStatus {
+        let state = self.state.lock().unwrap();
+        let (send_queue_bytes, send_buffer_bytes) = state
+            .upstream
+            .as_ref()
+            .map_or((0, 0), |(socket, _)| send_buffer(socket.as_raw_fd()));
+        FaceStatus {
+            face: format!("tunnel {}", self.remote),
+            local: self.listen.to_string(),
+            remote: state
+                .upstream
+                .as_ref()
+                .map(|(_, remote)| remote.to_string()),
+            send_queue_bytes,
+            send_buffer_bytes,
+            sent: state.sent,
+            would_block_drops: state.would_block_drops,
+            send_errors: state.send_errors,
+            queuing_delay_us: state.queuing_delay.unwrap_or_default().as_micros() as u64,
+            nacks: state.nacks.clone(),
+            shaped_bytes: state.shaping.shaped_bytes,
+            shaping_dropped_bytes: state.shaping.dropped_bytes,
+            ..FaceStatus::default()
+        }
+    }
+}
+
+/// Bytes waiting in the send buffer of the socket `fd`, and how many the buffer holds.
+pub fn send_buffer(fd: RawFd) -> (u64, u64) {
+    let (mut queued, mut size): (libc::c_int, libc::c_int) = (0, 0);
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SIOCOUTQ, which has the same number as TIOCOUTQ
+    if unsafe { libc::ioctl(fd, libc::TIOCOUTQ, &mut queued) } != 0 {
+        queued = 0;
+    }
+    let size_ptr = (&mut size as *mut libc::c_int).cast();
+    if unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size_ptr, &mut len) } != 0 {
+        size = 0;
+    }
+    (queued.max(0) as u64, size.max(0) as u64)
+}
+
+/// `udcn face list`: each face's state, and with `verbose` how sending on it goes.
+pub fn format_faces(faces: &[FaceStatus], verbose: bool) -> String {
+    let mut out = format!("{:<40} {:<22} {:<22}", "FACE", "LOCAL", "REMOTE");
+    if verbose {
+        let _ = write!(
+            out,
+            " {:>14} {:>10} {:>10} {:>8} {:>10} {:>10} {:>16}  NACKS",
+            "SEND QUEUE", "SENT", "WOULDBLOCK", "ERRORS", "SUPPRESSED", "DELAY", "SHAPED/DROPPED"
+        );
+    }
+    out.push('\n');
+    for face in faces {
+        let _ = write!(
+            out,
+            "{:<40} {:<22} {:<22}",
+            face.face,
+            face.local,
+            face.remote.as_deref().unwrap_or("down")
+        );
+        if verbose {
+            let nacks: Vec<String> = face
+                .nacks
+                .iter()
+                .map(|(reason, count)| format!("{reason}={count}"))
+                .collect();
+            let _ = write!(
+                out,
+                " {:>14} {:>10} {:>10} {:>8} {:>10} {:>10} {:>16}  {}",
+                format!("{}/{}", face.send_queue_bytes, face.send_buffer_bytes),
+                face.sent,
+                face.would_block_drops,
+                face.send_errors,
+                face.suppressed_duplicates,
+                format!("{} us", face.queuing_delay_us),
+                format!("{}/{}", face.shaped_bytes, face.shaping_dropped_bytes),
+                if nacks.is_empty() {
+                    "-".to_string()
+                } else {
+                    nacks.join(",")
+                }
+            );
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The socket towards the other site, connected to the address `remote` resolved to, and the
+/// DTLS session over it if the tunnel has one.
+struct Upstream {
+    socket: Arc<UdpSocket>,
+    session: Option<dtls::Session>,
+    remote: SocketAddrV4,
+    /// The tunnel's own face, that PIT entries for relayed Interests are made for.
+    face: SocketAddrV4,
+}
+
+impl Upstream {
+    async fn connect(remote: SocketAddrV4, dtls: Option<&SslContext>) -> anyhow::Result<Self> {
+        let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.connect(remote)?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+        let face = SocketAddrV4::new(Ipv4Addr::LOCALHOST, socket.local_addr()?.port());
+        let session = match dtls {
+            Some(context) => Some(dtls::Session::connect(socket.clone(), context).await?),
+            None => None,
+        };
+        Ok(Self {
+            socket,
+            session,
+            remote,
+            face,
+        })
+    }
+
+    fn try_send(&self, packet: &[u8]) -> io::Result<usize> {
+        match &self.session {
+            Some(session) => session.try_send(packet),
+            None => self.socket.try_send(packet),
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.session {
+            Some(session) => session.recv(buf).await,
+            None => self.socket.recv(buf).await,
+        }
+    }
+}
+
+/// A tunnel, with its DTLS credentials read.
+pub struct Tunnel {
+    config: TunnelConfig,
+    dtls: Option<SslContext>,
+}
+
+impl Tunnel {
+    pub fn load(config: TunnelConfig) -> anyhow::Result<Self> {
+        let dtls = match &config.dtls {
+            Some(dtls) => Some(dtls.context(false).with_context(|| {
+                format!(
+                    "invalid DTLS credentials for the tunnel to {}",
+                    config.remote
+                )
+            })?),
+            None => None,
+        };
+        Ok(Self { config, dtls })
+    }
+}
+
+/// Bind each tunnel's listening socket and start relaying, listing the tunnels as faces of the
+/// daemon.
+pub fn start(tunnels: Vec<Tunnel>, daemon: Arc<Mutex<Daemon>>) -> anyhow::Result<()> {
+    let mut faces = Vec::new();
+    for Tunnel { config, dtls } in tunnels {
+        let socket = StdUdpSocket::bind(config.listen).with_context(|| {
+            format!("failed to listen for tunnel Interests on {}", config.listen)
+        })?;
+        socket.set_nonblocking(true)?;
+        let listen = UdpSocket::from_std(socket)?;
+        info!(
+            "Tunnelling Interests from {} to {}",
+            config.listen, config.remote
+        );
+        let face = Arc::new(Face::new(&config));
+        faces.push(face.clone());
+        let daemon = daemon.clone();
+        tokio::spawn(async move { run(config, dtls, listen, face, daemon).await });
+    }
+    daemon.lock().unwrap().set_faces(faces);
+    Ok(())
+}
+
+/// The IPv4 address `remote` resolves to now.
+async fn resolve(remote: &str) -> anyhow::Result<SocketAddrV4> {
+    lookup_host(remote)
+        .await?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(addr),
+            SocketAddr::V6(_) => None,
+        })
+        .context("no IPv4 address")
+}
+
+async fn run(
+    config: TunnelConfig,
+    dtls: Option<SslContext>,
+    listen: UdpSocket,
+    face: Arc<Face>,
+    daemon: Arc<Mutex<Daemon>>,
+) {
+    let mut upstream: Option<Upstream> = None;
+    let mut connecting: Option<(SocketAddrV4, JoinHandle<anyhow::Result<Upstream>>)> = None;
+    let mut pending = Pending::<SocketAddr>::default();
+    let mut reassembler = lp::Reassembler::new();
+    let mut resolve_every = interval(Duration::from_secs(config.resolve_secs));
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut reply_buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut shaper = config
+        .shaping()
+        .map(|shaping| Shaper::new(shaping, Instant::now()));
+    let gates = daemon.lock().unwrap().gates();
+
+    loop {
+        let release = shaper.as_ref().and_then(Shaper::next_release);
+        let received = async {
+            match &upstream {
+                Some(upstream) => upstream.recv(&mut reply_buf).await,
+                None => std::future::pending().await,
+            }
+        };
+        let connected = async {
+            match &mut connecting {
+                Some((_, task)) => task.await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = resolve_every.tick() => match resolve(&config.remote).await {
+                Ok(remote) if upstream.as_ref().is_some_and(|up| up.remote == remote) => {}
+                Ok(remote) if connecting.as_ref().is_some_and(|(to, _)| *to == remote) => {}
+                Ok(remote) => {
+                    if let Some((_, task)) = connecting.take() {
+                        task.abort();
+                    }
+                    // A DTLS handshake takes round trips, which relaying does not wait for
+                    let dtls = dtls.clone();
+                    let task =
+                        tokio::spawn(async move { Upstream::connect(remote, dtls.as_ref()).await });
+                    connecting = Some((remote, task));
+                }
+                // Keep the address it had, which may well still be right
+                Err(e) => warn!("failed to resolve tunnel remote {}: {e:#}", config.remote),
+            },
+            connected = connected => {
+                let Some((remote, _)) = connecting.take() else {
+                    continue;
+                };
+                match connected {
+                    Ok(Ok(connected)) => {
+                        info!("Tunnel to {} reaches {remote}", config.remote);
+                        face.set_upstream(Some(&connected));
+                        upstream = Some(connected);
+                    }
+                    Ok(Err(e)) => warn!("failed to open tunnel to {remote}: {e:#}"),
+                    Err(e) => warn!("failed to open tunnel to {remote}: {e}"),
+                }
+            },
+            received = listen.recv_from(&mut buf) => {
+                let arrived = Instant::now();
+                let (len, consumer) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        debug!("tunnel listener on {}: {e}", config.listen);
+                        continue;
+                    }
+                };
+                let Some(interest) = Interest::decode(&buf[..len]) else {
+                    continue;
+                };
+                if !config.relays(&interest.name) {
+                    continue;
+                }
+                let mut span = otlp::span("tunnel_interest");
+                span.attr("name", &interest.name);
+                if !gates.admits(&interest) {
+                    debug!("dropping '{}' from {consumer} without a valid token", interest.name);
+                    continue;
+                }
+                let Some(upstream) = &upstream else {
+                    debug!("tunnel to {} not up, dropping '{}'", config.remote, interest.name);
+                    continue;
+                };
+                if !pending.insert(&interest, consumer, arrived) {
+                    debug!("too many Interests pending on the tunnel, dropping '{}'", interest.name);
+                    continue;
+                }
+                // XDP never sees the Data of a DTLS session in the clear, so has nothing to cache
+                if upstream.session.is_none() {
+                    if let Err(e) = daemon.lock().unwrap().expect_data(&interest, upstream.face) {
+                        warn!("failed to record tunnelled '{}': {e:#}", interest.name);
+                    }
+                }
+                let verdict = match &mut shaper {
+                    Some(shaper) => {
+                        let verdict = shaper.offer(&buf[..len], arrived, Instant::now());
+                        face.shaped(shaper.stats());
+                        verdict
+                    }
+                    None => Verdict::Send,
+                };
+                match verdict {
+                    Verdict::Send => {
+                        let sent = upstream.try_send(&buf[..len]);
+                        if let Err(e) = face.sent(sent, arrived) {
+                            debug!(
+                                "failed to relay '{}' to {}: {e}",
+                                interest.name, upstream.remote
+                            );
+                        }
+                    }
+                    Verdict::Queued => {}
+                    Verdict::Dropped => debug!(
+                        "shaping queue to {} full, dropping '{}'",
+                        config.remote, interest.name
+                    ),
+                }
+            },
+            () = sleep_until(release.unwrap_or_else(Instant::now)), if release.is_some() => {
+                let Some(shaper) = &mut shaper else {
+                    continue;
+                };
+                for (packet, arrived) in shaper.release(Instant::now()) {
+                    let Some(upstream) = &upstream else {
+                        debug!("tunnel to {} is down, dropping a shaped packet", config.remote);
+                        continue;
+                    };
+                    if let Err(e) = face.sent(upstream.try_send(&packet), arrived) {
+                        debug!("failed to relay a shaped packet to {}: {e}", upstream.remote);
+                    }
+                }
+            },
+            received = received => {
+                let arrived = Instant::now();
+                let len = match received {
+                    Ok(len) => len,
+                    // An ICMP port unreachable: the other site is down, perhaps restarting
+                    Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                        debug!("tunnel peer {} refused: {e}", config.remote);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("tunnel to {} failed, recreating it: {e}", config.remote);
+                        upstream = None;
+                        face.set_upstream(None);
+                        resolve_every.reset_immediately();
+                        continue;
+                    }
+                };
+                let reassembled;
+                let packet = if lp::is_lp_packet(&reply_buf[..len]) {
+                    match reassembler.receive(&reply_buf[..len]) {
+                        Some(packet) => {
+                            reassembled = packet;
+                            &reassembled[..]
+                        }
+                        None => continue,
+                    }
+                } else {
+                    &reply_buf[..len]
+                };
+                let answered = pending.answer(packet);
+                let mut span = otlp::span("tunnel_reply");
+                span.attr("consumers", answered.len());
+                if let Some(nack) = parse_nack_packet(packet) {
+                    face.nacked(nack.reason);
+                    if config.on_nack == NackPolicy::Suppress {
+                        debug!("not relaying a Nack with reason {} to {answered:?}", nack.reason);
+                        continue;
+                    }
+                }
+                for consumer in answered {
+                    if let Err(e) = face.sent(listen.try_send_to(packet, consumer), arrived) {
+                        debug!("failed to relay a reply to {consumer}: {e}");
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use udcn_common::{NackPacket, NackReason};
+
+    use super::*;
+
+    #[test]
+    fn test_replies_go_to_the_consumers_waiting() {
+        let (a, b) = (
+            SocketAddr::from(([10, 0, 0, 1], 5000)),
+            SocketAddr::from(([10, 0, 0, 2], 5000)),
+        );
+        let now = Instant::now();
+        let mut pending = Pending::default();
+        let video = Interest::builder().name("/site-b/video").nonce(1).build();
+        let segment = Interest::builder()
+            .name("/site-b/video/seg=0")
+            .nonce(2)
+            .lifetime_ms(100)
+            .build();
+        assert!(pending.insert(&video, a, now));
+        assert!(pending.insert(&segment, b, now));
+
+        let nack = NackPacket::new(segment.name.name_hash(), 2, NackReason::NoRoute);
+        assert_eq!(pending.answer(&nack.to_bytes()), [b]);
+        assert!(pending.insert(&segment, b, now));
+        let data = Data::builder().name("/site-b/video/seg=0").build().encode();
+        assert_eq!(pending.answer(&data), [a, b]);
+        assert_eq!((pending.len, pending.answer(&data)), (0, vec![]));
+
+        assert!(pending.insert(&segment, b, now));
+        pending.expire(now + Duration::from_millis(100));
+        assert_eq!(pending.len, 0);
+    }
+
+    #[test]
+    fn test_face_counts_what_sending_runs_into() {
+        let config = TunnelConfig {
+            remote: "site-b:6363".to_string(),
+            listen: default_listen(),
+            prefixes: Vec::new(),
+            resolve_secs: 60,
+            on_nack: NackPolicy::Propagate,
+            egress_bytes_per_sec: None,
+            egress_burst_bytes: None,
+            egress_queue_bytes: None,
+            dtls: None,
+        };
+        let face = Face::new(&config);
+        let arrived = Instant::now() - Duration::from_micros(800);
+        face.sent(Ok(100), arrived).unwrap();
+        face.sent(Ok(100), Instant::now()).unwrap();
+        assert!(face
+            .sent(Err(io::ErrorKind::WouldBlock.into()), arrived)
+            .is_err());
+        assert!(face
+            .sent(Err(io::ErrorKind::PermissionDenied.into()), arrived)
+            .is_err());
+        face.nacked(NackReason::NoRoute as u8);
+        face.nacked(NackReason::NoRoute as u8);
+        face.nacked(7);
+
+        let status = face.status();
+        assert_eq!(status.remote, None);
+        assert_eq!(
+            (status.sent, status.would_block_drops, status.send_errors),
+            (2, 1, 1)
+        );
+        // 7/8 of the first delay, and a little for the time the test takes
+        assert!((700..800).contains(&status.queuing_delay_us));
+        let listing = format_faces(&[status], true);
+        assert!(listing.contains("tunnel site-b:6363"), "{listing}");
+        assert!(listing.contains("down"), "{listing}");
+        assert!(listing.contains("7=1,NoRoute=2"), "{listing}");
+    }
+}
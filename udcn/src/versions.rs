@@ -0,0 +1,81 @@
+//! Version numbers for content published with `serve --version`.
+//!
+//! `timestamp` versions are the microseconds since the Unix epoch at publishing, as NDN tools
+//! use by default. `seq` versions count up from 1 per name, with the last one handed out kept one
+//! `<name> <version>` line per name so that restarting the producer moves on to the next.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use udcn_common::Name;
+
+pub const DEFAULT_VERSIONS_PATH: &str = "/var/lib/udcn/versions";
+
+/// The current time as a timestamp version.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// The version after the last one handed out for `name`, recorded in the file at `path`.
+pub fn next_sequence(path: &Path, name: &Name) -> anyhow::Result<u64> {
+    let mut versions = BTreeMap::new();
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                let (uri, version) = line
+                    .rsplit_once(' ')
+                    .and_then(|(uri, version)| Some((uri, version.parse::<u64>().ok()?)))
+                    .with_context(|| format!("invalid line {line:?} in {}", path.display()))?;
+                versions.insert(uri.to_string(), version);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+
+    let version = versions.entry(name.to_string()).or_default();
+    *version += 1;
+    let version = *version;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents: String = versions
+        .iter()
+        .map(|(uri, version)| format!("{uri} {version}\n"))
+        .collect();
+    fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_counts_per_name() {
+        let path = std::env::temp_dir().join(format!("udcn-versions-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let file = Name::from("/file");
+        assert_eq!(next_sequence(&path, &file).unwrap(), 1);
+        assert_eq!(next_sequence(&path, &file).unwrap(), 2);
+        assert_eq!(next_sequence(&path, &Name::from("/a b")).unwrap(), 1);
+        assert_eq!(next_sequence(&path, &file).unwrap(), 3);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "/a%20b 1\n/file 3\n"
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(timestamp() > 1_600_000_000_000_000);
+    }
+}
@@ -0,0 +1,39 @@
+//! End-to-end tests against a live datapath.
+//!
+//! These build network namespaces and attach the XDP program, so they need root and are ignored
+//! by default: `sudo -E cargo test -p udcn --test integration -- --ignored`.
+
+use std::process::{Command, Output};
+
+fn udcn(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_udcn")).args(args).output().expect("failed to run udcn")
+}
+
+/// Every selftest check from one run: runs at once would clobber each other's `udcn-st-*`
+/// namespaces.
+#[test]
+#[ignore = "needs root to create network namespaces and attach XDP"]
+fn test_selftest() {
+    let output = udcn(&["selftest"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "selftest failed\n{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!stdout.contains("FAIL"), "{stdout}");
+    assert!(stdout.lines().any(|line| line.starts_with("PASS")), "{stdout}");
+
+    let namespaces = Command::new("ip").args(["netns", "list"]).output().unwrap();
+    let namespaces = String::from_utf8_lossy(&namespaces.stdout);
+    assert!(!namespaces.contains("udcn-st-"), "namespaces left behind:\n{namespaces}");
+
+    #[cfg(feature = "faults")]
+    for check in [
+        "injected interest drops go unanswered",
+        "blackholed prefix goes unanswered",
+        "delayed data still arrives",
+    ] {
+        assert!(stdout.contains(&format!("PASS  {check}")), "{stdout}");
+    }
+}